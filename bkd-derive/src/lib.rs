@@ -0,0 +1,136 @@
+//! Derive macros for the `bkd` crate's `Point` and `SpatialPoint` traits.
+//!
+//! Enable the `derive` feature of `bkd` and derive from it rather than depending on this
+//! crate directly. Each named field is one dimension, numbered in declaration order; mark
+//! fields that are not coordinates with `#[point(skip)]`. Coordinate fields may be of any
+//! type with a lossless `f64::from` conversion (`f64`, `f32`, `i32`, `u32` and narrower).
+//!
+//! ```rust
+//! use bkd::{Point, SpatialPoint};
+//!
+//! #[derive(Point, SpatialPoint)]
+//! struct Reading {
+//!     lon: f64,
+//!     lat: f64,
+//!     altitude: f32,
+//!     #[point(skip)]
+//!     sensor: String,
+//! }
+//!
+//! let reading = Reading { lon: 2.35, lat: 48.86, altitude: 35.0, sensor: "a".into() };
+//! assert_eq!(reading.dimensions(), 3);
+//! assert_eq!(reading.get_dimension(2), 35.0);
+//! ```
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{Data, DeriveInput, Error, Fields, Ident, parse_macro_input};
+
+/// Implement `bkd::Point` with one dimension per named field, in declaration order.
+#[proc_macro_derive(Point, attributes(point))]
+pub fn derive_point(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_point(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Implement `bkd::SpatialPoint` for a derived point: points have no extent, so a point is
+/// within or overlaps only a point with the same coordinates.
+#[proc_macro_derive(SpatialPoint, attributes(point))]
+pub fn derive_spatial_point(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_spatial_point(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand_point(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = coordinate_fields(input)?;
+    let name = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    let dims = fields.len();
+    let arms = fields.iter().enumerate().map(|(dim, field)| {
+        quote! { #dim => ::core::convert::From::from(self.#field), }
+    });
+    let message = format!("Invalid dimension for {name}: {{}}");
+
+    Ok(quote! {
+        impl #impl_generics ::bkd::Point for #name #type_generics #where_clause {
+            fn get_dimension(&self, dim: usize) -> f64 {
+                match dim {
+                    #(#arms)*
+                    _ => panic!(#message, dim),
+                }
+            }
+
+            fn dimensions(&self) -> usize {
+                #dims
+            }
+        }
+    })
+}
+
+fn expand_spatial_point(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = coordinate_fields(input)?;
+    let name = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::bkd::SpatialPoint for #name #type_generics #where_clause {
+            fn is_within(&self, query: &Self) -> bool {
+                self.overlaps(query)
+            }
+
+            fn overlaps(&self, query: &Self) -> bool {
+                true #(&& self.#fields == query.#fields)*
+            }
+        }
+    })
+}
+
+/// Named fields of a struct that are not marked `#[point(skip)]`.
+fn coordinate_fields(input: &DeriveInput) -> syn::Result<Vec<Ident>> {
+    let Data::Struct(data) = &input.data else {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "Point can only be derived for structs with named fields",
+        ));
+    };
+    let Fields::Named(named) = &data.fields else {
+        return Err(Error::new_spanned(
+            &data.fields,
+            "Point can only be derived for structs with named fields",
+        ));
+    };
+
+    let mut fields = Vec::new();
+    for field in &named.named {
+        let mut skip = false;
+        for attr in field
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("point"))
+        {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("skip") {
+                    skip = true;
+                    Ok(())
+                } else {
+                    Err(meta.error("expected `skip`"))
+                }
+            })?;
+        }
+        if !skip {
+            fields.extend(field.ident.clone());
+        }
+    }
+    if fields.is_empty() {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "Point needs at least one coordinate field",
+        ));
+    }
+    Ok(fields)
+}
@@ -0,0 +1,104 @@
+use bkd::{
+    BuildOptions, InMemoryLinker, NodeArena, NodeLinker, Point, RangeQuery, SpatialPoint,
+    bulk_build, spatial_search,
+};
+
+#[derive(Debug, Clone, Point, SpatialPoint)]
+struct Sample {
+    x: f64,
+    #[point(skip)]
+    label: &'static str,
+    y: f32,
+    z: i32,
+}
+
+#[derive(Point)]
+struct Generic<T> {
+    a: f64,
+    #[point(skip)]
+    #[allow(dead_code)]
+    extra: T,
+}
+
+#[test]
+fn test_dimensions_follow_field_order() {
+    let sample = Sample {
+        x: 1.5,
+        label: "a",
+        y: 2.5,
+        z: -3,
+    };
+    assert_eq!(sample.label, "a");
+    assert_eq!(sample.dimensions(), 3);
+    assert_eq!(sample.get_dimension(0), 1.5);
+    assert_eq!(sample.get_dimension(1), 2.5);
+    assert_eq!(sample.get_dimension(2), -3.0);
+
+    let generic = Generic {
+        a: 4.0,
+        extra: vec![1u8],
+    };
+    assert_eq!(generic.dimensions(), 1);
+    assert_eq!(generic.get_dimension(0), 4.0);
+}
+
+#[test]
+#[should_panic(expected = "Invalid dimension for Sample: 3")]
+fn test_out_of_range_dimension_panics() {
+    let sample = Sample {
+        x: 0.0,
+        label: "",
+        y: 0.0,
+        z: 0,
+    };
+    sample.get_dimension(3);
+}
+
+#[test]
+fn test_spatial_point_compares_coordinates() {
+    let sample = Sample {
+        x: 1.0,
+        label: "a",
+        y: 2.0,
+        z: 3,
+    };
+    let relabeled = Sample {
+        label: "b",
+        ..sample.clone()
+    };
+    let moved = Sample {
+        z: 4,
+        ..sample.clone()
+    };
+    assert!(sample.is_within(&relabeled));
+    assert!(sample.overlaps(&relabeled));
+    assert!(!sample.overlaps(&moved));
+}
+
+#[test]
+fn test_index_derived_points() {
+    let mut arena = NodeArena::new();
+    let mut nodes: Vec<usize> = (0..50)
+        .map(|i| {
+            let sample = Sample {
+                x: i as f64,
+                label: "",
+                y: (i % 5) as f32,
+                z: i / 10,
+            };
+            arena.allocate(sample, i)
+        })
+        .collect();
+    let mut linker = InMemoryLinker::new(&mut arena);
+    let root = bulk_build(&mut linker, &mut nodes, 0, &BuildOptions::default()).unwrap();
+
+    let query = RangeQuery::new()
+        .with_range(1, 0.0, 0.0)
+        .with_range(2, 2.0, 2.0);
+    let mut found: Vec<i32> = spatial_search(&linker, root, &query, 0)
+        .into_iter()
+        .map(|node| *linker.get_data(node))
+        .collect();
+    found.sort_unstable();
+    assert_eq!(found, [20, 25]);
+}
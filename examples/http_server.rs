@@ -0,0 +1,293 @@
+//! Small HTTP query server over a persisted index that keeps accepting entries.
+//!
+//! ```text
+//! cargo run --example http_server -- <index.bkd> [address]
+//! curl 'http://127.0.0.1:3000/search?bbox=0,0,10,10&limit=100'
+//! curl -X POST -H 'content-type: application/json' \
+//!     -d '[{"id":7,"bbox":[1,1,2,2]}]' http://127.0.0.1:3000/entries
+//! ```
+//!
+//! The index is loaded once into a `SharedTree`. Every search clones the latest published
+//! `TreeSnapshot` and runs on it without locks, so queries run concurrently on Tokio's
+//! worker threads while a commit inserts a batch of entries. Snapshots are published after
+//! the whole batch is in, so a search sees every entry of a commit or none of them; the
+//! `version` of a response is the number of entries it saw.
+
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use bkd::node_file::NodeFileReader;
+use bkd::{BoundingBox, NodeLinker, SharedTree, TreeSnapshot, spatial_search};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+
+/// Index shared by all requests: the writer's tree and the snapshot searches pin.
+struct Index {
+    tree: Mutex<SharedTree<BoundingBox, u64>>,
+    published: RwLock<TreeSnapshot<BoundingBox, u64>>,
+}
+
+impl Index {
+    fn new(tree: SharedTree<BoundingBox, u64>) -> Self {
+        let published = RwLock::new(tree.snapshot());
+        Index {
+            tree: Mutex::new(tree),
+            published,
+        }
+    }
+
+    /// The latest published snapshot. The lock is only held to clone it.
+    fn snapshot(&self) -> TreeSnapshot<BoundingBox, u64> {
+        self.published.read().unwrap().clone()
+    }
+
+    /// Insert `entries` and publish them together, returning the new version.
+    fn commit(&self, entries: &[Entry]) -> usize {
+        let mut tree = self.tree.lock().unwrap();
+        for entry in entries {
+            let [xmin, ymin, xmax, ymax] = entry.bbox;
+            tree.insert(BoundingBox::new(xmin, ymin, xmax, ymax), entry.id);
+        }
+        *self.published.write().unwrap() = tree.snapshot();
+        tree.len()
+    }
+}
+
+#[derive(Deserialize)]
+struct SearchParams {
+    /// `xmin,ymin,xmax,ymax`
+    bbox: String,
+    limit: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    id: u64,
+    bbox: [f64; 4],
+}
+
+#[derive(Serialize, Deserialize)]
+struct SearchResponse {
+    version: usize,
+    count: usize,
+    truncated: bool,
+    matches: Vec<Entry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CommitResponse {
+    version: usize,
+}
+
+fn parse_bbox(value: &str) -> Result<BoundingBox, String> {
+    let numbers: Vec<f64> = value
+        .split(',')
+        .map(|field| field.trim().parse::<f64>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("invalid bbox '{value}'"))?;
+    match numbers[..] {
+        [xmin, ymin, xmax, ymax] if xmin <= xmax && ymin <= ymax => {
+            Ok(BoundingBox::new(xmin, ymin, xmax, ymax))
+        }
+        _ => Err("bbox expects xmin,ymin,xmax,ymax with min <= max".to_string()),
+    }
+}
+
+async fn search(
+    State(index): State<Arc<Index>>,
+    Query(params): Query<SearchParams>,
+) -> Result<Json<SearchResponse>, (StatusCode, String)> {
+    let query = parse_bbox(&params.bbox).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let limit = params.limit.unwrap_or(1000);
+
+    let snapshot = index.snapshot();
+    let results = spatial_search(&snapshot, snapshot.root(), &query, 0);
+    let matches = results
+        .iter()
+        .take(limit)
+        .map(|&node| {
+            let bbox = snapshot.get_point(node);
+            Entry {
+                id: *snapshot.get_data(node),
+                bbox: [bbox.xmin, bbox.ymin, bbox.xmax, bbox.ymax],
+            }
+        })
+        .collect();
+    Ok(Json(SearchResponse {
+        version: snapshot.len(),
+        count: results.len(),
+        truncated: results.len() > limit,
+        matches,
+    }))
+}
+
+async fn commit(
+    State(index): State<Arc<Index>>,
+    Json(entries): Json<Vec<Entry>>,
+) -> Result<Json<CommitResponse>, (StatusCode, String)> {
+    if let Some(entry) = entries.iter().find(|entry| {
+        let [xmin, ymin, xmax, ymax] = entry.bbox;
+        !(xmin <= xmax && ymin <= ymax)
+    }) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("entry {} has min > max", entry.id),
+        ));
+    }
+    // Inserting is quick but blocking; keep it off the threads serving searches
+    let version = tokio::task::spawn_blocking(move || index.commit(&entries))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(CommitResponse { version }))
+}
+
+fn router(index: Arc<Index>) -> Router {
+    Router::new()
+        .route("/search", get(search))
+        .route("/entries", post(commit))
+        .route("/health", get(|| async { "ok" }))
+        .with_state(index)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args().skip(1);
+    let path = PathBuf::from(
+        args.next()
+            .ok_or("usage: http_server <index.bkd> [address]")?,
+    );
+    let address = args.next().unwrap_or_else(|| "127.0.0.1:3000".to_string());
+
+    // Records are in pre-order, so inserting them in file order rebuilds the same tree
+    let (arena, _) = NodeFileReader::<BoundingBox, u64>::open(&path)?.load_arena()?;
+    let mut tree = SharedTree::new();
+    for node in 0..arena.len() {
+        let node = arena.get(node);
+        tree.insert(node.get_point().clone(), *node.get_data());
+    }
+    println!("loaded {} nodes from {}", tree.len(), path.display());
+    let index = Arc::new(Index::new(tree));
+
+    let listener = tokio::net::TcpListener::bind(&address).await?;
+    println!("listening on http://{address}");
+    axum::serve(listener, router(index)).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{Body, to_bytes};
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    const BATCH: usize = 40;
+    const COMMITS: usize = 25;
+
+    /// Entry `i` of the test load: every third one inside the query box.
+    fn entry(i: usize) -> Entry {
+        let x = ((i * 37) % 101) as f64;
+        let y = ((i * 53) % 97) as f64;
+        let (x, y) = if i % 3 == 0 {
+            (x / 10.0, y / 10.0)
+        } else {
+            (x + 20.0, y)
+        };
+        Entry {
+            id: i as u64,
+            bbox: [x, y, x + 0.5, y + 0.5],
+        }
+    }
+
+    async fn call(router: &Router, request: Request<Body>) -> Vec<u8> {
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap()
+            .to_vec()
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_searches_see_whole_commits_under_load() {
+        let mut tree = SharedTree::new();
+        for i in 0..BATCH {
+            let [xmin, ymin, xmax, ymax] = entry(i).bbox;
+            tree.insert(BoundingBox::new(xmin, ymin, xmax, ymax), i as u64);
+        }
+        let router = router(Arc::new(Index::new(tree)));
+
+        let writer = {
+            let router = router.clone();
+            tokio::spawn(async move {
+                for commit in 1..=COMMITS {
+                    let batch: Vec<Entry> =
+                        (commit * BATCH..(commit + 1) * BATCH).map(entry).collect();
+                    let request = Request::post("/entries")
+                        .header("content-type", "application/json")
+                        .body(Body::from(serde_json::to_vec(&batch).unwrap()))
+                        .unwrap();
+                    let response: CommitResponse =
+                        serde_json::from_slice(&call(&router, request).await).unwrap();
+                    assert_eq!(response.version, (commit + 1) * BATCH);
+                    tokio::task::yield_now().await;
+                }
+            })
+        };
+
+        let readers: Vec<_> = (0..8)
+            .map(|_| {
+                let router = router.clone();
+                tokio::spawn(async move {
+                    let mut last_version = 0;
+                    while last_version < (COMMITS + 1) * BATCH {
+                        let request = Request::get("/search?bbox=0,0,11,11&limit=100000")
+                            .body(Body::empty())
+                            .unwrap();
+                        let response: SearchResponse =
+                            serde_json::from_slice(&call(&router, request).await).unwrap();
+
+                        // A whole number of commits, never older than the last one seen
+                        assert_eq!(response.version % BATCH, 0);
+                        assert!(response.version >= last_version);
+                        last_version = response.version;
+
+                        // Exactly the matching entries of those commits
+                        let mut ids: Vec<u64> =
+                            response.matches.iter().map(|entry| entry.id).collect();
+                        ids.sort_unstable();
+                        let expected: Vec<u64> = (0..response.version as u64)
+                            .filter(|i| i % 3 == 0)
+                            .collect();
+                        assert_eq!(ids, expected);
+                        assert_eq!(response.count, expected.len());
+                        assert!(!response.truncated);
+                        // Searches never wait, so let the writer's task run between them
+                        tokio::task::yield_now().await;
+                    }
+                })
+            })
+            .collect();
+
+        writer.await.unwrap();
+        for reader in readers {
+            reader.await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rejects_malformed_requests() {
+        let router = router(Arc::new(Index::new(SharedTree::new())));
+        for request in [
+            Request::get("/search?bbox=1,1,0,0").body(Body::empty()),
+            Request::post("/entries")
+                .header("content-type", "application/json")
+                .body(Body::from(r#"[{"id":1,"bbox":[2,2,1,1]}]"#)),
+        ] {
+            let response = router.clone().oneshot(request.unwrap()).await.unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+}
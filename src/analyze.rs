@@ -0,0 +1,467 @@
+//! Tuning reports for built trees: shape statistics, a pruning simulation over sample
+//! queries, and the changes they call for.
+
+use crate::build::{BuildOptions, ShadowLinker, SplitPolicy, bulk_build};
+use crate::query::SpatialQuery;
+use crate::search::spatial_search;
+use crate::spatial::Point;
+use crate::storage::NodeLinker;
+use std::cell::Cell;
+use std::fmt::Write as _;
+
+/// Height over the balanced height beyond which a rebuild is recommended.
+const REBUILD_HEIGHT_RATIO: f64 = 1.5;
+
+/// Reads of the tree over the reads of a rebuilt one beyond which a rebuild is recommended.
+const REBUILD_READ_RATIO: f64 = 1.25;
+
+/// Smallest and largest block sizes recommended.
+const MIN_BLOCK_SIZE: usize = 32;
+const MAX_BLOCK_SIZE: usize = 4096;
+
+/// A change `analyze` recommends.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Recommendation {
+    /// Rebuild the tree, with `rebuild_compact` or a bulk build: it is `height` levels
+    /// deep where a balanced tree has `optimal_height`, or the sample queries read
+    /// `read_ratio` times the nodes they read in a rebuilt tree.
+    Rebuild {
+        height: usize,
+        optimal_height: usize,
+        read_ratio: f64,
+    },
+    /// Bulk-build with `policy`, under which the sample queries read `read_ratio` times the
+    /// nodes they read under the other policy, 1.0 or less.
+    SplitPolicy {
+        policy: SplitPolicy,
+        read_ratio: f64,
+    },
+    /// Pack the tree into a block tree with `max_points_in_leaf` entries per leaf, about
+    /// what a typical sample query matches.
+    BlockSize { max_points_in_leaf: usize },
+}
+
+impl Recommendation {
+    /// Stable name of the kind of recommendation, as in `TuningReport::to_json`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Recommendation::Rebuild { .. } => "rebuild",
+            Recommendation::SplitPolicy { .. } => "split_policy",
+            Recommendation::BlockSize { .. } => "block_size",
+        }
+    }
+}
+
+/// Outcome of `analyze`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TuningReport {
+    /// Entries reachable from the root.
+    pub entries: usize,
+    /// Levels from the root to the deepest node.
+    pub height: usize,
+    /// Levels of a balanced tree of as many entries.
+    pub optimal_height: usize,
+    /// Mean and variance of the depth of nodes without children, the root at depth 1.
+    pub mean_leaf_depth: f64,
+    pub leaf_depth_variance: f64,
+    /// Share of the child slots of inner nodes in use: 1.0 when every inner node has two
+    /// children, near 0.5 for a tree degenerated into a list.
+    pub leaf_occupancy: f64,
+    /// Sample queries run.
+    pub queries: usize,
+    /// Mean entries matched and nodes read per sample query.
+    pub mean_matches: f64,
+    pub mean_reads: f64,
+    /// Changes worth making, at most one of each kind.
+    pub recommendations: Vec<Recommendation>,
+}
+
+impl TuningReport {
+    /// The report as a JSON object, for scripts deciding when to rebuild. Recommendations
+    /// are objects tagged with their `kind`; split policies are `"median"` or
+    /// `"sliding_midpoint"`.
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("{");
+        write!(
+            json,
+            "\"entries\":{},\"height\":{},\"optimal_height\":{},",
+            self.entries, self.height, self.optimal_height
+        )
+        .expect("writing to a String cannot fail");
+        for (name, value) in [
+            ("mean_leaf_depth", self.mean_leaf_depth),
+            ("leaf_depth_variance", self.leaf_depth_variance),
+            ("leaf_occupancy", self.leaf_occupancy),
+        ] {
+            write_field(&mut json, name, value);
+        }
+        write!(json, "\"queries\":{},", self.queries).expect("writing to a String cannot fail");
+        write_field(&mut json, "mean_matches", self.mean_matches);
+        write_field(&mut json, "mean_reads", self.mean_reads);
+
+        json.push_str("\"recommendations\":[");
+        for (i, recommendation) in self.recommendations.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            write!(json, "{{\"kind\":\"{}\",", recommendation.kind())
+                .expect("writing to a String cannot fail");
+            match recommendation {
+                Recommendation::Rebuild {
+                    height,
+                    optimal_height,
+                    read_ratio,
+                } => {
+                    write!(
+                        json,
+                        "\"height\":{height},\"optimal_height\":{optimal_height},"
+                    )
+                    .expect("writing to a String cannot fail");
+                    write_field(&mut json, "read_ratio", *read_ratio);
+                }
+                Recommendation::SplitPolicy { policy, read_ratio } => {
+                    let policy = match policy {
+                        SplitPolicy::Median => "median",
+                        SplitPolicy::SlidingMidpoint => "sliding_midpoint",
+                    };
+                    write!(json, "\"policy\":\"{policy}\",")
+                        .expect("writing to a String cannot fail");
+                    write_field(&mut json, "read_ratio", *read_ratio);
+                }
+                Recommendation::BlockSize { max_points_in_leaf } => {
+                    write!(json, "\"max_points_in_leaf\":{max_points_in_leaf},")
+                        .expect("writing to a String cannot fail");
+                }
+            }
+            json.pop();
+            json.push('}');
+        }
+        json.push_str("]}");
+        json
+    }
+}
+
+/// Write `"name":value,`, with non-finite values as `null`.
+fn write_field(json: &mut String, name: &str, value: f64) {
+    if value.is_finite() {
+        write!(json, "\"{name}\":{value},").expect("writing to a String cannot fail");
+    } else {
+        write!(json, "\"{name}\":null,").expect("writing to a String cannot fail");
+    }
+}
+
+/// Inspect the tree at `root`, whose root splits at depth 0, and recommend how to tune it
+/// for workloads like `queries`, a sample of real queries such as from a request log.
+///
+/// # Architecture Decision: simulate rather than guess
+/// Shape statistics say when a tree has drifted from balance, but not whether that, or
+/// the split policy, matters to the queries it serves. So the sample queries run three
+/// times with every node read counted: on the tree as it is, and on a bulk build of its
+/// entries under each `SplitPolicy`. The builds only lay out links beside the tree, nothing
+/// is copied or changed, and each costs about 50 bytes per entry. From the counts:
+/// - `Rebuild` when the tree is `REBUILD_HEIGHT_RATIO` times deeper than balanced, or reads
+///   `REBUILD_READ_RATIO` times the nodes of the better build
+/// - `SplitPolicy` names the policy whose build read fewer nodes
+/// - `BlockSize` is the median number of matches per query, rounded to a power of two:
+///   block tree queries read every leaf their results touch, so leaves much smaller than a
+///   result mean many reads, and much larger ones decode entries that do not match
+///
+/// Without queries, only shape statistics and a height-based `Rebuild` are reported.
+///
+/// # Usage pattern:
+/// ```rust
+/// use bkd::{BoundingBox, Recommendation, SpatialIndex, analyze};
+///
+/// // Inserted in sorted order, so each entry hangs below the last
+/// let mut index = SpatialIndex::new();
+/// for i in 0..200 {
+///     let x = f64::from(i);
+///     index.insert(BoundingBox::new(x, x, x + 1.0, x + 1.0), i);
+/// }
+///
+/// let queries = [BoundingBox::new(10.0, 10.0, 30.0, 30.0)];
+/// let report = analyze(&index, index.root(), &queries);
+/// assert!(report.height > report.optimal_height);
+/// assert_eq!(report.recommendations[0].kind(), "rebuild");
+/// assert!(report.to_json().contains("\"kind\":\"rebuild\""));
+/// ```
+pub fn analyze<P: Point, T, L: NodeLinker<P, T>, Q: SpatialQuery<P>>(
+    linker: &L,
+    root: Option<L::NodeRef>,
+    queries: &[Q],
+) -> TuningReport {
+    let mut nodes = Vec::new();
+    let mut leaf_depths: Vec<usize> = Vec::new();
+    let mut child_links = 0;
+    let mut height = 0;
+    let mut stack: Vec<(L::NodeRef, usize)> = root.map(|root| (root, 1)).into_iter().collect();
+    while let Some((node, depth)) = stack.pop() {
+        nodes.push(node);
+        height = height.max(depth);
+        let children = [linker.get_left(node), linker.get_right(node)];
+        let count = children.iter().flatten().count();
+        if count == 0 {
+            leaf_depths.push(depth);
+        }
+        child_links += count;
+        stack.extend(
+            children
+                .into_iter()
+                .flatten()
+                .map(|child| (child, depth + 1)),
+        );
+    }
+
+    let entries = nodes.len();
+    let optimal_height = (usize::BITS - entries.leading_zeros()) as usize;
+    let mean = |values: &[f64]| {
+        if values.is_empty() {
+            0.0
+        } else {
+            values.iter().sum::<f64>() / values.len() as f64
+        }
+    };
+    let depths: Vec<f64> = leaf_depths.iter().map(|&depth| depth as f64).collect();
+    let mean_leaf_depth = mean(&depths);
+    let squares: Vec<f64> = depths
+        .iter()
+        .map(|depth| (depth - mean_leaf_depth).powi(2))
+        .collect();
+    let inner = entries - leaf_depths.len();
+    let leaf_occupancy = if inner == 0 {
+        1.0
+    } else {
+        child_links as f64 / (2 * inner) as f64
+    };
+
+    // Reads and matches of every query on the tree as it is
+    let counter = ReadCounter::new(linker);
+    let mut matches = Vec::with_capacity(queries.len());
+    for query in queries {
+        matches.push(spatial_search(&counter, root, query, 0).len());
+    }
+    let reads = counter.reads.get();
+
+    let mut recommendations = Vec::new();
+    let mut rebuild_ratio: f64 = 1.0;
+    if !queries.is_empty() && entries > 0 {
+        let [median, sliding] = [SplitPolicy::Median, SplitPolicy::SlidingMidpoint]
+            .map(|policy| simulated_reads(linker, &nodes, policy, queries));
+        let (policy, best, other) = if sliding < median {
+            (SplitPolicy::SlidingMidpoint, sliding, median)
+        } else {
+            (SplitPolicy::Median, median, sliding)
+        };
+        rebuild_ratio = reads as f64 / best.max(1) as f64;
+        recommendations.push(Recommendation::SplitPolicy {
+            policy,
+            read_ratio: best as f64 / other.max(1) as f64,
+        });
+
+        let mut sorted = matches.clone();
+        sorted.sort_unstable();
+        let typical = sorted[sorted.len() / 2];
+        recommendations.push(Recommendation::BlockSize {
+            max_points_in_leaf: typical
+                .next_power_of_two()
+                .clamp(MIN_BLOCK_SIZE, MAX_BLOCK_SIZE),
+        });
+    }
+    if height as f64 > REBUILD_HEIGHT_RATIO * optimal_height as f64
+        || rebuild_ratio > REBUILD_READ_RATIO
+    {
+        recommendations.insert(
+            0,
+            Recommendation::Rebuild {
+                height,
+                optimal_height,
+                read_ratio: rebuild_ratio,
+            },
+        );
+    }
+
+    let matches: Vec<f64> = matches.iter().map(|&count| count as f64).collect();
+    TuningReport {
+        entries,
+        height,
+        optimal_height,
+        mean_leaf_depth,
+        leaf_depth_variance: mean(&squares),
+        leaf_occupancy,
+        queries: queries.len(),
+        mean_matches: mean(&matches),
+        mean_reads: if queries.is_empty() {
+            0.0
+        } else {
+            reads as f64 / queries.len() as f64
+        },
+        recommendations,
+    }
+}
+
+/// Nodes `queries` read in a bulk build of `nodes` under `policy`.
+fn simulated_reads<P: Point, T, L: NodeLinker<P, T>, Q: SpatialQuery<P>>(
+    linker: &L,
+    nodes: &[L::NodeRef],
+    policy: SplitPolicy,
+    queries: &[Q],
+) -> usize {
+    let mut shadow = ShadowLinker::new(linker, nodes.to_vec());
+    let mut positions: Vec<usize> = (0..nodes.len()).collect();
+    let options = BuildOptions::with_split_policy(policy);
+    let root = bulk_build(&mut shadow, &mut positions, 0, &options)
+        .expect("builds without a cancellation token always finish");
+    let counter = ReadCounter::new(&shadow);
+    for query in queries {
+        spatial_search(&counter, root, query, 0);
+    }
+    counter.reads.get()
+}
+
+/// Read-only linker counting the points read through it, one per node a search visits.
+struct ReadCounter<'l, L> {
+    linker: &'l L,
+    reads: Cell<usize>,
+}
+
+impl<'l, L> ReadCounter<'l, L> {
+    fn new(linker: &'l L) -> Self {
+        ReadCounter {
+            linker,
+            reads: Cell::new(0),
+        }
+    }
+}
+
+impl<P: Point, T, L: NodeLinker<P, T>> NodeLinker<P, T> for ReadCounter<'_, L> {
+    type NodeRef = L::NodeRef;
+
+    fn link_left(&mut self, _parent: L::NodeRef, _child: L::NodeRef) {
+        panic!("cannot link nodes through a read counter");
+    }
+
+    fn link_right(&mut self, _parent: L::NodeRef, _child: L::NodeRef) {
+        panic!("cannot link nodes through a read counter");
+    }
+
+    fn get_left(&self, node: L::NodeRef) -> Option<L::NodeRef> {
+        self.linker.get_left(node)
+    }
+
+    fn get_right(&self, node: L::NodeRef) -> Option<L::NodeRef> {
+        self.linker.get_right(node)
+    }
+
+    fn get_point(&self, node: L::NodeRef) -> &P {
+        self.reads.set(self.reads.get() + 1);
+        self.linker.get_point(node)
+    }
+
+    fn get_data(&self, node: L::NodeRef) -> &T {
+        self.linker.get_data(node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::SpatialIndex;
+    use crate::spatial::BoundingBox;
+    use crate::storage::{InMemoryLinker, NodeArena};
+
+    #[test]
+    fn test_analyze_recommends_from_shape_and_simulation() {
+        // Tight clusters far apart, median bulk-built, with a simulation to run
+        let mut arena = NodeArena::new();
+        let mut nodes: Vec<usize> = (0..3000u32)
+            .map(|i| {
+                let (cx, cy) = [(0.0, 0.0), (900.0, 50.0), (400.0, 800.0)][i as usize % 3];
+                let x = cx + ((i * 37) % 101) as f64 * 0.05;
+                let y = cy + ((i * 53) % 97) as f64 * 0.05;
+                arena.allocate(BoundingBox::new(x, y, x + 0.01, y + 0.01), i)
+            })
+            .collect();
+        let mut linker = InMemoryLinker::new(&mut arena);
+        let root = bulk_build(&mut linker, &mut nodes, 0, &BuildOptions::default()).unwrap();
+        let queries: Vec<BoundingBox> = (0..40)
+            .map(|i| {
+                let x = (i * 23 % 1000) as f64;
+                let y = (i * 41 % 900) as f64;
+                BoundingBox::new(x, y, x + 5.0, y + 5.0)
+            })
+            .collect();
+
+        let report = analyze(&linker, root, &queries);
+        assert_eq!(report.entries, 3000);
+        assert_eq!(report.optimal_height, 12);
+        assert_eq!(report.height, 12);
+        assert!(report.leaf_occupancy > 0.7);
+        assert!(report.leaf_depth_variance < 1.0);
+        assert_eq!(report.queries, 40);
+        let expected: usize = queries
+            .iter()
+            .map(|query| spatial_search(&linker, root, query, 0).len())
+            .sum();
+        assert_eq!(report.mean_matches, expected as f64 / 40.0);
+        assert!(report.mean_reads >= report.mean_matches);
+
+        // Balanced, but the median splits cut the clusters into slabs every query crosses,
+        // so the sliding-midpoint build reads far fewer nodes and calls for a rebuild
+        let kinds: Vec<&str> = report.recommendations.iter().map(|r| r.kind()).collect();
+        assert_eq!(kinds, ["rebuild", "split_policy", "block_size"]);
+        let Recommendation::Rebuild {
+            height, read_ratio, ..
+        } = report.recommendations[0]
+        else {
+            unreachable!();
+        };
+        assert_eq!(height, 12);
+        assert!(read_ratio > REBUILD_READ_RATIO);
+        let Recommendation::SplitPolicy { policy, read_ratio } = report.recommendations[1] else {
+            unreachable!();
+        };
+        assert_eq!(policy, SplitPolicy::SlidingMidpoint);
+        assert!(read_ratio < 1.0);
+        let Recommendation::BlockSize { max_points_in_leaf } = report.recommendations[2] else {
+            unreachable!();
+        };
+        assert!(max_points_in_leaf.is_power_of_two());
+        assert!((MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&max_points_in_leaf));
+
+        // Sorted inserts make a list: rebuild, and reads far above a rebuilt tree's
+        let mut list = SpatialIndex::new();
+        for i in 0..300u32 {
+            let x = f64::from(i);
+            list.insert(BoundingBox::new(x, x, x + 1.0, x + 1.0), i);
+        }
+        let queries = [BoundingBox::new(250.0, 250.0, 260.0, 260.0)];
+        let report = analyze(&list, list.root(), &queries);
+        assert_eq!(report.height, 300);
+        assert!((report.leaf_occupancy - 0.5).abs() < 0.01);
+        let Recommendation::Rebuild {
+            height,
+            optimal_height,
+            read_ratio,
+        } = report.recommendations[0]
+        else {
+            panic!("expected a rebuild first, got {:?}", report.recommendations);
+        };
+        assert_eq!((height, optimal_height), (300, 9));
+        assert!(read_ratio > REBUILD_READ_RATIO);
+        let json = report.to_json();
+        assert!(json.starts_with("{\"entries\":300,\"height\":300,\"optimal_height\":9,"));
+        assert!(json.contains("{\"kind\":\"rebuild\",\"height\":300,\"optimal_height\":9,"));
+        assert!(json.contains("\"kind\":\"block_size\",\"max_points_in_leaf\":32}"));
+        assert!(json.ends_with("}]}"));
+
+        let empty: TuningReport = analyze(&list, None, &queries);
+        assert_eq!((empty.entries, empty.height), (0, 0));
+        assert!(empty.recommendations.is_empty());
+        assert_eq!(
+            empty.to_json(),
+            "{\"entries\":0,\"height\":0,\"optimal_height\":0,\"mean_leaf_depth\":0,\
+             \"leaf_depth_variance\":0,\"leaf_occupancy\":1,\"queries\":1,\"mean_matches\":0,\
+             \"mean_reads\":0,\"recommendations\":[]}"
+        );
+    }
+}
@@ -0,0 +1,214 @@
+//! Async search over disk-backed indexes (feature `async`).
+//!
+//! Tokio-based services must not block executor threads on cold-cache reads. This module
+//! mirrors `spatial_search` but awaits every node read, and provides `AsyncNodeFile`, which
+//! performs node-file reads on Tokio's blocking thread pool.
+
+use crate::buffer_pool::read_exact_at;
+use crate::codec::FixedCodec;
+use crate::metrics::Metrics;
+use crate::node_file::{HEADER_SIZE, NodeFileHeader, NodeRecord};
+use crate::query::SpatialQuery;
+use crate::spatial::Point;
+use std::fs::File;
+use std::future::Future;
+use std::io;
+use std::marker::PhantomData;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Async counterpart of `NodeLinker` for read-only, disk-backed trees.
+///
+/// Nodes are addressed by record index and returned by value: a node read may complete
+/// long after the call was made, so there is no stable storage to borrow from.
+pub trait AsyncNodeSource<P, T> {
+    /// Index of the root node, if the tree is not empty.
+    fn root(&self) -> Option<u64>;
+
+    /// Read a single node.
+    fn read_node(&self, index: u64) -> impl Future<Output = io::Result<NodeRecord<P, T>>> + Send;
+
+    /// Counters to record served queries in, if the source collects metrics.
+    fn metrics(&self) -> Option<&Metrics> {
+        None
+    }
+}
+
+/// Node file opened for async reads.
+///
+/// Reads use positional I/O on a shared file handle inside `spawn_blocking`, so concurrent
+/// searches never contend on a file cursor and never block the async executor.
+pub struct AsyncNodeFile<P, T> {
+    file: Arc<File>,
+    header: NodeFileHeader,
+    metrics: Option<Arc<Metrics>>,
+    _marker: PhantomData<fn() -> (P, T)>,
+}
+
+impl<P: FixedCodec, T: FixedCodec> AsyncNodeFile<P, T> {
+    /// Open a node file and validate its header against `P` and `T`.
+    pub async fn open(path: &Path) -> io::Result<Self> {
+        let path = path.to_path_buf();
+        let (file, header) = spawn_blocking(move || {
+            let file = File::open(path)?;
+            let mut buf = [0u8; HEADER_SIZE];
+            read_exact_at(&file, &mut buf, 0)?;
+            Ok((file, NodeFileHeader::decode(&buf)?))
+        })
+        .await?;
+        header.check_layout::<P, T>()?;
+
+        Ok(AsyncNodeFile {
+            file: Arc::new(file),
+            header,
+            metrics: None,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Count this file's disk reads and served queries in `metrics`.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// The decoded file header.
+    pub fn header(&self) -> &NodeFileHeader {
+        &self.header
+    }
+}
+
+impl<P: FixedCodec, T: FixedCodec> AsyncNodeSource<P, T> for AsyncNodeFile<P, T> {
+    fn root(&self) -> Option<u64> {
+        self.header.root
+    }
+
+    async fn read_node(&self, index: u64) -> io::Result<NodeRecord<P, T>> {
+        let offset = self.header.record_offset(index)?;
+        let record_size = self.header.record_size();
+        let file = self.file.clone();
+        let buf = spawn_blocking(move || {
+            let mut buf = vec![0u8; record_size];
+            read_exact_at(&file, &mut buf, offset)?;
+            Ok(buf)
+        })
+        .await?;
+        if let Some(metrics) = &self.metrics {
+            metrics.record_read(record_size as u64);
+        }
+        Ok(NodeRecord::decode(&buf))
+    }
+
+    fn metrics(&self) -> Option<&Metrics> {
+        self.metrics.as_deref()
+    }
+}
+
+async fn spawn_blocking<R: Send + 'static>(
+    task: impl FnOnce() -> io::Result<R> + Send + 'static,
+) -> io::Result<R> {
+    tokio::task::spawn_blocking(task)
+        .await
+        .map_err(io::Error::other)?
+}
+
+/// Async variant of `spatial_search` over any `AsyncNodeSource`.
+/// Returns matching record indices in the same pre-order as `spatial_search`.
+///
+/// # Architecture
+/// The traversal is the iterative stack walk used by paginated search; the only difference
+/// is that each node is fetched with an awaited read, yielding the executor thread while
+/// the I/O is in flight.
+pub async fn spatial_search_async<P, T, S, Q>(
+    source: &S,
+    query: &Q,
+    depth: usize,
+) -> io::Result<Vec<u64>>
+where
+    P: Point,
+    S: AsyncNodeSource<P, T>,
+    Q: SpatialQuery<P>,
+{
+    if let Some(metrics) = source.metrics() {
+        metrics.record_query();
+    }
+    let mut results = Vec::new();
+    let mut stack: Vec<(u64, usize)> = source
+        .root()
+        .map(|root| (root, depth))
+        .into_iter()
+        .collect();
+
+    while let Some((index, depth)) = stack.pop() {
+        let node = source.read_node(index).await?;
+        if query.matches(&node.point) {
+            results.push(index);
+        }
+
+        let dimension = depth % node.point.dimensions();
+        let split_value = node.point.get_dimension(dimension);
+        let (query_min, query_max) = query.dimension_range(dimension);
+        if let Some(right) = node.right {
+            if query_max >= split_value {
+                stack.push((right, depth + 1));
+            }
+        }
+        if let Some(left) = node.left {
+            if query_min <= split_value {
+                stack.push((left, depth + 1));
+            }
+        }
+    }
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::external::{ExternalBuildOptions, external_bulk_build};
+    use crate::node_file::NodeFileReader;
+    use crate::search::spatial_search;
+    use crate::spatial::BoundingBox;
+    use crate::storage::InMemoryLinker;
+
+    #[test]
+    fn test_async_search_matches_sync_search() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.bkd");
+        let entries = (0..100u32).map(|i| {
+            let x = ((i * 31) % 50) as f64;
+            (BoundingBox::new(x, x, x + 1.0, x + 1.0), i)
+        });
+        external_bulk_build(entries, &path, &ExternalBuildOptions::default()).unwrap();
+
+        let query = BoundingBox::new(10.0, 10.0, 20.0, 20.0);
+        let (mut arena, root) = NodeFileReader::<BoundingBox, u32>::open(&path)
+            .unwrap()
+            .load_arena()
+            .unwrap();
+        let linker = InMemoryLinker::new(&mut arena);
+        let expected: Vec<u64> = spatial_search(&linker, root, &query, 0)
+            .into_iter()
+            .map(|node| node as u64)
+            .collect();
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let metrics = Arc::new(Metrics::new());
+        let results = runtime.block_on(async {
+            let file = AsyncNodeFile::<BoundingBox, u32>::open(&path)
+                .await
+                .unwrap()
+                .with_metrics(metrics.clone());
+            spatial_search_async(&file, &query, 0).await.unwrap()
+        });
+
+        assert!(!results.is_empty());
+        assert_eq!(results, expected);
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.queries, 1);
+        assert!(snapshot.disk_reads >= results.len() as u64);
+    }
+}
@@ -7,21 +7,20 @@ showing how to build spatial indexes for rectangular regions:
 - BoundingBox spatial data with 4D coordinates (xmin, ymin, xmax, ymax)
 - Storage-agnostic KD-tree algorithms using NodeLinker abstraction
 - Spatial search with dimensional pruning for geographic/geometric queries
-- SVG visualization of tree structure and search queries
+
+To render a persisted index instead of this hard-coded one, use `kd-tree viz`.
 
 The core library modules used:
 - bkd::spatial - BoundingBox type implementing Point and SpatialPoint traits
 - bkd::storage - NodeArena and InMemoryLinker for memory-based storage
-- bkd::search - spatial_search, insert_node, and SVG visualization functions
+- bkd::search - spatial_search and insert_node
 
 This example demonstrates the fundamental bounding box use case for spatial
 indexing, which forms the foundation for more complex geometric data types.
 */
 
 // Import library modules
-use bkd::search::{add_query_to_svg, tree_to_svg};
-use bkd::spatial::{Point, SpatialPoint};
-use bkd::storage::{Node, NodeLinker};
+use bkd::storage::NodeLinker;
 use bkd::{BoundingBox, InMemoryLinker, NodeArena, insert_node, spatial_search};
 
 fn main() {
@@ -75,27 +74,13 @@ fn main() {
         );
     }
 
-    // Generate SVG visualization
-    println!("\n=== SVG Visualization Demo ===");
-    let mut svg = tree_to_svg(&linker, Some(root), 800, 600);
-
-    // Add query overlay for downtown search
-    let svg_bounds = BoundingBox::new(0.0, 0.0, 12.0, 10.0); // Estimated bounds
-    add_query_to_svg(&mut svg, &downtown_query, &svg_bounds, 800, 600);
-
-    // Write SVG to file
-    use std::fs::write;
-    match write("kdtree_demo.svg", &svg) {
-        Ok(_) => println!("SVG visualization saved to 'kdtree_demo.svg'"),
-        Err(e) => println!("Failed to write SVG file: {}", e),
-    }
-
-    println!("You can open the SVG file in a web browser to view the tree visualization");
+    println!("\nRender a persisted index with `kd-tree viz <index> <output.svg>`");
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use bkd::search::{add_query_to_svg, tree_to_svg};
 
     /// Test the bbox program integration - verify it can run without errors
     #[test]
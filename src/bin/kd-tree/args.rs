@@ -0,0 +1,70 @@
+//! Minimal argument parsing shared by the subcommands.
+//!
+//! Options are `--name value` pairs or bare `--flag`s, and may appear anywhere; whatever is
+//! left over is positional. Each subcommand takes what it understands and calls `finish`
+//! so that typos are reported instead of silently ignored.
+
+use std::str::FromStr;
+
+pub struct Args {
+    args: Vec<String>,
+}
+
+impl Args {
+    pub fn new(args: impl IntoIterator<Item = String>) -> Self {
+        Args {
+            args: args.into_iter().collect(),
+        }
+    }
+
+    /// Remove and return the value of `--name value`.
+    pub fn option(&mut self, name: &str) -> Result<Option<String>, String> {
+        let Some(position) = self.args.iter().position(|arg| arg == name) else {
+            return Ok(None);
+        };
+        if position + 1 >= self.args.len() {
+            return Err(format!("{name} needs a value"));
+        }
+        let value = self.args.remove(position + 1);
+        self.args.remove(position);
+        Ok(Some(value))
+    }
+
+    /// Remove and parse the value of `--name value`.
+    pub fn parsed_option<V: FromStr>(&mut self, name: &str) -> Result<Option<V>, String> {
+        self.option(name)?
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|_| format!("invalid value '{value}' for {name}"))
+            })
+            .transpose()
+    }
+
+    /// Remove `--name` and report whether it was present.
+    pub fn flag(&mut self, name: &str) -> bool {
+        match self.args.iter().position(|arg| arg == name) {
+            Some(position) => {
+                self.args.remove(position);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remove and return the next positional argument.
+    pub fn positional(&mut self, what: &str) -> Result<String, String> {
+        match self.args.iter().position(|arg| !arg.starts_with("--")) {
+            Some(position) => Ok(self.args.remove(position)),
+            None => Err(format!("missing {what}")),
+        }
+    }
+
+    /// Fail if any arguments were not consumed.
+    pub fn finish(self) -> Result<(), String> {
+        match self.args.first() {
+            Some(arg) => Err(format!("unexpected argument '{arg}'")),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,86 @@
+//! `kd-tree build`: bulk-build a node file from a data file.
+
+use crate::CliResult;
+use crate::args::Args;
+use crate::input::{CsvEntries, Format, read_geojson};
+use bkd::{BuildProgress, ExternalBuildOptions, ProgressCallback, external_bulk_build};
+use std::path::{Path, PathBuf};
+
+pub fn run(mut args: Args) -> CliResult<()> {
+    let format = args
+        .option("--format")?
+        .map(|f| Format::parse(&f))
+        .transpose()?;
+    let max_in_memory = args.parsed_option::<usize>("--max-in-memory")?;
+    let temp_dir = args.option("--temp-dir")?.map(PathBuf::from);
+    let quiet = args.flag("--quiet");
+    let input = PathBuf::from(args.positional("input file")?);
+    let output = PathBuf::from(args.positional("output file")?);
+    args.finish()?;
+
+    let format = match format.or_else(|| Format::from_path(&input)) {
+        Some(format) => format,
+        None => return Err("cannot infer the input format; pass --format csv|geojson".into()),
+    };
+
+    let mut options = ExternalBuildOptions {
+        temp_dir,
+        ..ExternalBuildOptions::default()
+    };
+    if let Some(max_in_memory) = max_in_memory {
+        options.max_entries_in_memory = max_in_memory.max(1);
+    }
+    if !quiet {
+        options.progress = Some(ProgressCallback::new(
+            100_000,
+            |progress: &BuildProgress| {
+                eprint!("\rwrote {} / {} nodes", progress.processed, progress.total);
+            },
+        ));
+    }
+
+    // Built beside the output and renamed into place once complete, so a bad input line
+    // or an interrupted build never leaves a valid-looking partial index behind
+    let mut partial = output.clone().into_os_string();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+    let built = build_into(format, &input, &partial, &options).and_then(|count| {
+        std::fs::rename(&partial, &output)?;
+        Ok(count)
+    });
+    let count = match built {
+        Ok(count) => count,
+        Err(error) => {
+            let _ = std::fs::remove_file(&partial);
+            return Err(error);
+        }
+    };
+    if !quiet {
+        eprintln!();
+    }
+    println!("indexed {count} entries into {}", output.display());
+    Ok(())
+}
+
+/// Read `input` and build its index at `path`, failing on the first bad entry.
+fn build_into(
+    format: Format,
+    input: &Path,
+    path: &Path,
+    options: &ExternalBuildOptions,
+) -> CliResult<u64> {
+    match format {
+        Format::Csv => {
+            let mut entries = CsvEntries::open(input)?;
+            let count = external_bulk_build(&mut entries, path, options)?;
+            if let Some(error) = entries.error {
+                return Err(format!("{}: {error}", input.display()).into());
+            }
+            Ok(count)
+        }
+        Format::GeoJson => {
+            let entries = read_geojson(input).map_err(|e| format!("{}: {e}", input.display()))?;
+            Ok(external_bulk_build(entries, path, options)?)
+        }
+    }
+}
@@ -0,0 +1,215 @@
+//! Readers for the data files accepted by `kd-tree build`.
+//!
+//! Every input row becomes one `(BoundingBox, u64)` entry: the box is the row's extent and
+//! the payload is its id, which query results report back.
+
+use bkd::BoundingBox;
+use serde_json::Value;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+/// Supported input formats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Comma-separated rows of `x,y` or `xmin,ymin,xmax,ymax`, each optionally followed
+    /// by an integer id. A non-numeric first line is treated as a header.
+    Csv,
+    /// A GeoJSON `FeatureCollection`; each feature is indexed by the bounding box of its
+    /// geometry.
+    GeoJson,
+}
+
+impl Format {
+    /// Parse a `--format` value.
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name.to_ascii_lowercase().as_str() {
+            "csv" => Ok(Format::Csv),
+            "geojson" | "json" => Ok(Format::GeoJson),
+            other => Err(format!(
+                "unknown input format '{other}' (expected csv or geojson)"
+            )),
+        }
+    }
+
+    /// Guess the format from a file extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?;
+        Format::parse(extension).ok()
+    }
+}
+
+/// Streaming CSV reader yielding entries; the first malformed row stops iteration and is
+/// kept in `error` so a build consuming the iterator can report it afterwards.
+pub struct CsvEntries<R> {
+    lines: io::Lines<R>,
+    line_number: usize,
+    next_id: u64,
+    pub error: Option<String>,
+}
+
+impl CsvEntries<BufReader<File>> {
+    /// Open a CSV file for streaming.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Ok(CsvEntries::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+impl<R: BufRead> CsvEntries<R> {
+    pub fn new(reader: R) -> Self {
+        CsvEntries {
+            lines: reader.lines(),
+            line_number: 0,
+            next_id: 0,
+            error: None,
+        }
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<Option<(BoundingBox, u64)>, String> {
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        if line.trim().is_empty() || line.starts_with('#') {
+            return Ok(None);
+        }
+        let numbers: Result<Vec<f64>, _> = fields.iter().map(|f| f.parse::<f64>()).collect();
+        let numbers = match numbers {
+            Ok(numbers) => numbers,
+            // Header row
+            Err(_) if self.line_number == 1 => return Ok(None),
+            Err(_) => return Err(format!("line {}: non-numeric field", self.line_number)),
+        };
+
+        let (bbox, id) = match numbers.as_slice() {
+            [x, y] => (BoundingBox::new(*x, *y, *x, *y), None),
+            [x, y, id] => (BoundingBox::new(*x, *y, *x, *y), Some(*id)),
+            [xmin, ymin, xmax, ymax] => (BoundingBox::new(*xmin, *ymin, *xmax, *ymax), None),
+            [xmin, ymin, xmax, ymax, id] => {
+                (BoundingBox::new(*xmin, *ymin, *xmax, *ymax), Some(*id))
+            }
+            _ => {
+                return Err(format!(
+                    "line {}: expected 2 to 5 columns, found {}",
+                    self.line_number,
+                    numbers.len()
+                ));
+            }
+        };
+        if bbox.xmin > bbox.xmax || bbox.ymin > bbox.ymax {
+            return Err(format!("line {}: min exceeds max", self.line_number));
+        }
+        let id = match id {
+            Some(id) if id >= 0.0 && id.fract() == 0.0 => id as u64,
+            Some(_) => {
+                return Err(format!(
+                    "line {}: id must be a non-negative integer",
+                    self.line_number
+                ));
+            }
+            None => self.next_id,
+        };
+        self.next_id += 1;
+        Ok(Some((bbox, id)))
+    }
+}
+
+impl<R: BufRead> Iterator for CsvEntries<R> {
+    type Item = (BoundingBox, u64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.error.is_some() {
+            return None;
+        }
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) => {
+                    self.error = Some(e.to_string());
+                    return None;
+                }
+            };
+            self.line_number += 1;
+            match self.parse_line(&line) {
+                Ok(Some(entry)) => return Some(entry),
+                Ok(None) => continue,
+                Err(e) => {
+                    self.error = Some(e);
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+/// Read every feature of a GeoJSON file.
+/// Features with a numeric `id` keep it; others are numbered by position.
+pub fn read_geojson(path: &Path) -> Result<Vec<(BoundingBox, u64)>, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let document: Value =
+        serde_json::from_reader(BufReader::new(file)).map_err(|e| e.to_string())?;
+    parse_geojson(&document)
+}
+
+pub fn parse_geojson(document: &Value) -> Result<Vec<(BoundingBox, u64)>, String> {
+    let features = match document["type"].as_str() {
+        Some("FeatureCollection") => document["features"]
+            .as_array()
+            .ok_or("FeatureCollection without a features array")?
+            .iter()
+            .collect(),
+        Some("Feature") => vec![document],
+        _ => return Err("expected a GeoJSON FeatureCollection or Feature".to_string()),
+    };
+
+    let mut entries = Vec::with_capacity(features.len());
+    for (position, feature) in features.into_iter().enumerate() {
+        let geometry = &feature["geometry"];
+        if geometry.is_null() {
+            continue;
+        }
+        let mut bbox: Option<BoundingBox> = None;
+        extend_with_geometry(&mut bbox, geometry)
+            .map_err(|e| format!("feature {position}: {e}"))?;
+        let Some(bbox) = bbox else { continue };
+        let id = feature["id"].as_u64().unwrap_or(position as u64);
+        entries.push((bbox, id));
+    }
+    Ok(entries)
+}
+
+fn extend_with_geometry(bbox: &mut Option<BoundingBox>, geometry: &Value) -> Result<(), String> {
+    if geometry["type"] == "GeometryCollection" {
+        for member in geometry["geometries"]
+            .as_array()
+            .ok_or("missing geometries")?
+        {
+            extend_with_geometry(bbox, member)?;
+        }
+        return Ok(());
+    }
+    extend_with_coordinates(bbox, &geometry["coordinates"])
+}
+
+/// Walk arbitrarily nested coordinate arrays, growing `bbox` by every position.
+fn extend_with_coordinates(
+    bbox: &mut Option<BoundingBox>,
+    coordinates: &Value,
+) -> Result<(), String> {
+    let array = coordinates.as_array().ok_or("missing coordinates")?;
+    match array.first() {
+        Some(Value::Number(_)) => {
+            let (Some(x), Some(y)) = (array[0].as_f64(), array.get(1).and_then(Value::as_f64))
+            else {
+                return Err("position needs two numbers".to_string());
+            };
+            *bbox = Some(match bbox.take() {
+                Some(b) => {
+                    BoundingBox::new(b.xmin.min(x), b.ymin.min(y), b.xmax.max(x), b.ymax.max(y))
+                }
+                None => BoundingBox::new(x, y, x, y),
+            });
+            Ok(())
+        }
+        _ => array
+            .iter()
+            .try_for_each(|nested| extend_with_coordinates(bbox, nested)),
+    }
+}
@@ -0,0 +1,195 @@
+//! `kd-tree inspect`: report the shape and storage details of a built index.
+
+use crate::CliResult;
+use crate::args::Args;
+use bkd::BoundingBox;
+use bkd::node_file::{NodeFileHeader, NodeFileReader};
+use serde_json::json;
+use std::io;
+use std::path::PathBuf;
+
+/// Shape of the tree reachable from the root.
+#[derive(Debug, Default, PartialEq)]
+struct TreeStats {
+    reachable: u64,
+    leaves: u64,
+    one_child: u64,
+    two_children: u64,
+    height: usize,
+    total_leaf_depth: u64,
+}
+
+impl TreeStats {
+    fn mean_leaf_depth(&self) -> f64 {
+        if self.leaves == 0 {
+            0.0
+        } else {
+            self.total_leaf_depth as f64 / self.leaves as f64
+        }
+    }
+}
+
+/// Height of a perfectly balanced tree with `nodes` nodes.
+fn optimal_height(nodes: u64) -> usize {
+    (u64::BITS - nodes.leading_zeros()) as usize
+}
+
+fn tree_stats(reader: &mut NodeFileReader<BoundingBox, u64>) -> io::Result<TreeStats> {
+    let mut stats = TreeStats::default();
+    let mut stack: Vec<(u64, usize)> = reader.root().map(|root| (root, 1)).into_iter().collect();
+    while let Some((index, depth)) = stack.pop() {
+        if stats.reachable == reader.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "tree links form a cycle or share nodes",
+            ));
+        }
+        let node = reader.read_node(index)?;
+        stats.reachable += 1;
+        stats.height = stats.height.max(depth);
+        match (node.left, node.right) {
+            (None, None) => {
+                stats.leaves += 1;
+                stats.total_leaf_depth += depth as u64;
+            }
+            (Some(_), None) | (None, Some(_)) => stats.one_child += 1,
+            (Some(_), Some(_)) => stats.two_children += 1,
+        }
+        stack.extend(
+            node.left
+                .into_iter()
+                .chain(node.right)
+                .map(|child| (child, depth + 1)),
+        );
+    }
+    Ok(stats)
+}
+
+pub fn run(mut args: Args) -> CliResult<()> {
+    let as_json = args.flag("--json");
+    let index = PathBuf::from(args.positional("index file")?);
+    args.finish()?;
+
+    let file_size = std::fs::metadata(&index)?.len();
+    let mut reader = NodeFileReader::<BoundingBox, u64>::open(&index)?;
+    let header = reader.header().clone();
+    let stats = tree_stats(&mut reader)?;
+    if as_json {
+        println!("{}", report_json(&header, &stats, file_size));
+    } else {
+        print!("{}", report_text(&header, &stats, file_size));
+    }
+    Ok(())
+}
+
+fn report_json(header: &NodeFileHeader, stats: &TreeStats, file_size: u64) -> serde_json::Value {
+    json!({
+        "version": header.version,
+        "dimensions": header.dimensions,
+        "point_size": header.point_size,
+        "data_size": header.data_size,
+        "record_size": header.record_size(),
+        "file_size": file_size,
+        "nodes": header.node_count,
+        "root": header.root,
+        "features": header.features.to_string(),
+        "reachable": stats.reachable,
+        "leaves": stats.leaves,
+        "one_child": stats.one_child,
+        "two_children": stats.two_children,
+        "height": stats.height,
+        "optimal_height": optimal_height(header.node_count),
+        "mean_leaf_depth": stats.mean_leaf_depth(),
+    })
+}
+
+fn report_text(header: &NodeFileHeader, stats: &TreeStats, file_size: u64) -> String {
+    let percent = |count: u64| {
+        if stats.reachable == 0 {
+            0.0
+        } else {
+            100.0 * count as f64 / stats.reachable as f64
+        }
+    };
+    let root = header
+        .root
+        .map_or_else(|| "none".to_string(), |root| root.to_string());
+    let mut report = format!(
+        "format version   {}\n\
+         dimensions       {}\n\
+         record size      {} bytes (point {}, payload {})\n\
+         file size        {} bytes\n\
+         nodes            {}\n\
+         root             {root}\n\
+         features         {}\n\
+         height           {} (optimal {})\n\
+         mean leaf depth  {:.2}\n\
+         leaves           {} ({:.1}%)\n\
+         one child        {} ({:.1}%)\n\
+         two children     {} ({:.1}%)\n",
+        header.version,
+        header.dimensions,
+        header.record_size(),
+        header.point_size,
+        header.data_size,
+        file_size,
+        header.node_count,
+        header.features,
+        stats.height,
+        optimal_height(header.node_count),
+        stats.mean_leaf_depth(),
+        stats.leaves,
+        percent(stats.leaves),
+        stats.one_child,
+        percent(stats.one_child),
+        stats.two_children,
+        percent(stats.two_children),
+    );
+    if stats.reachable != header.node_count {
+        report.push_str(&format!(
+            "warning          {} nodes are unreachable from the root\n",
+            header.node_count - stats.reachable
+        ));
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bkd::node_file::NodeFileWriter;
+
+    #[test]
+    fn test_tree_stats() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.bkd");
+        let point = BoundingBox::new(0.0, 0.0, 1.0, 1.0);
+
+        // 0 -> (1, 2), 1 -> (3, -); node 4 is unreachable
+        let mut writer = NodeFileWriter::<BoundingBox, u64>::create(&path).unwrap();
+        writer.push(&point, &0, Some(1), Some(2)).unwrap();
+        writer.push(&point, &1, Some(3), None).unwrap();
+        writer.push(&point, &2, None, None).unwrap();
+        writer.push(&point, &3, None, None).unwrap();
+        writer.push(&point, &4, None, None).unwrap();
+        writer.finish(Some(0)).unwrap();
+
+        let mut reader = NodeFileReader::open(&path).unwrap();
+        let stats = tree_stats(&mut reader).unwrap();
+        assert_eq!(
+            stats,
+            TreeStats {
+                reachable: 4,
+                leaves: 2,
+                one_child: 1,
+                two_children: 1,
+                height: 3,
+                total_leaf_depth: 5,
+            }
+        );
+        assert_eq!(optimal_height(5), 3);
+
+        let report = report_text(reader.header(), &stats, 0);
+        assert!(report.contains("1 nodes are unreachable"));
+    }
+}
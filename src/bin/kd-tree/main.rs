@@ -0,0 +1,168 @@
+/*!
+Command-line interface for building and querying persisted BKD indexes.
+
+```text
+kd-tree build <input> <output> [--format csv|geojson] [--max-in-memory N] [--temp-dir DIR] [--quiet]
+kd-tree query <index> (--bbox xmin,ymin,xmax,ymax | --radius x,y,r) [--output csv|json]
+kd-tree inspect <index> [--json]
+kd-tree verify <index>
+kd-tree repair <index> <output> [--max-in-memory N] [--temp-dir DIR]
+kd-tree viz <index> <output.svg|output.html> [--bbox xmin,ymin,xmax,ymax] [--width W] [--height H]
+            [--max-depth D] [--max-nodes N]
+```
+
+Indexes are node files of `(BoundingBox, u64)` records, the id being the input row or
+feature id. Building uses `external_bulk_build`, so inputs larger than memory are fine.
+*/
+
+mod args;
+mod build;
+mod input;
+mod inspect;
+mod query;
+mod repair;
+mod verify;
+mod viz;
+
+use args::Args;
+use std::process::ExitCode;
+
+type CliResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+const USAGE: &str = "\
+usage: kd-tree <command> [options]
+
+commands:
+  build <input> <output>   bulk-build an index from a CSV or GeoJSON file
+      --format csv|geojson   input format (default: from the file extension)
+      --max-in-memory N      entries held in memory before spilling to disk
+      --temp-dir DIR         directory for spill files
+      --quiet                do not report progress
+  query <index>            print the entries matching a query
+      --bbox xmin,ymin,xmax,ymax   entries overlapping or inside the box
+      --radius x,y,r               entries within distance r of (x, y)
+      --output csv|json            output format (default: csv)
+  inspect <index>          print tree shape, sizes and format details
+      --json                 machine-readable output
+  verify <index>           check checksums, links and split order; fails on damage
+  repair <index> <output>  rebuild a damaged index from its readable entries
+      --max-in-memory N      entries held in memory before spilling to disk
+      --temp-dir DIR         directory for spill files
+  viz <index> <output>     render the tree to SVG, or HTML for a .html output
+      --bbox xmin,ymin,xmax,ymax   overlay a query box
+      --width W, --height H        image size in pixels (default: 800x600)
+      --max-depth D                deepest level drawn (default: 64)
+      --max-nodes N                most nodes drawn (default: 100000)
+";
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let command = args.next();
+    let args = Args::new(args);
+
+    let result = match command.as_deref() {
+        Some("build") => build::run(args),
+        Some("query") => query::run(args),
+        Some("inspect") => inspect::run(args),
+        Some("verify") => verify::run(args),
+        Some("repair") => repair::run(args),
+        Some("viz") => viz::run(args),
+        Some("help" | "--help" | "-h") | None => {
+            print!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Some(other) => Err(format!("unknown command '{other}'\n\n{USAGE}").into()),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("kd-tree: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::{CsvEntries, parse_geojson};
+    use bkd::BoundingBox;
+    use bkd::node_file::NodeFileReader;
+
+    #[test]
+    fn test_csv_entries() {
+        let csv = "xmin,ymin,xmax,ymax\n0,0,1,1\n\n2,3,4,5,42\n";
+        let entries: Vec<_> = CsvEntries::new(csv.as_bytes()).collect();
+        assert_eq!(
+            entries,
+            vec![
+                (BoundingBox::new(0.0, 0.0, 1.0, 1.0), 0),
+                (BoundingBox::new(2.0, 3.0, 4.0, 5.0), 42)
+            ]
+        );
+
+        let mut bad = CsvEntries::new("1,2\n3,x\n".as_bytes());
+        assert_eq!(bad.by_ref().count(), 1);
+        assert!(bad.error.unwrap().contains("line 2"));
+    }
+
+    #[test]
+    fn test_geojson_bounds() {
+        let document = serde_json::json!({
+            "type": "FeatureCollection",
+            "features": [
+                {"type": "Feature", "id": 9, "geometry": {"type": "Point", "coordinates": [1.0, 2.0]}},
+                {"type": "Feature", "geometry": {"type": "Polygon",
+                    "coordinates": [[[0.0, 0.0], [4.0, -1.0], [2.0, 3.0], [0.0, 0.0]]]}},
+                {"type": "Feature", "geometry": null}
+            ]
+        });
+        assert_eq!(
+            parse_geojson(&document).unwrap(),
+            vec![
+                (BoundingBox::new(1.0, 2.0, 1.0, 2.0), 9),
+                (BoundingBox::new(0.0, -1.0, 4.0, 3.0), 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_build_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("points.csv");
+        let output = dir.path().join("points.bkd");
+        let rows: String = (0..50).map(|i| format!("{i},{}\n", i * 2)).collect();
+        std::fs::write(&input, rows).unwrap();
+
+        let args = Args::new(
+            [input.to_str().unwrap(), output.to_str().unwrap(), "--quiet"].map(String::from),
+        );
+        build::run(args).unwrap();
+
+        let reader = NodeFileReader::<BoundingBox, u64>::open(&output).unwrap();
+        assert_eq!(reader.len(), 50);
+
+        // A bad line fails the build and keeps the previous index, with nothing left over
+        let mut rows: String = (0..50).map(|i| format!("{i},{i}\n")).collect();
+        rows.insert_str(4, "1,x\n");
+        std::fs::write(&input, rows).unwrap();
+        let args = Args::new(
+            [input.to_str().unwrap(), output.to_str().unwrap(), "--quiet"].map(String::from),
+        );
+        let error = build::run(args).unwrap_err();
+        assert!(error.to_string().contains("line 2"), "{error}");
+        let reader = NodeFileReader::<BoundingBox, u64>::open(&output).unwrap();
+        assert_eq!(reader.len(), 50);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+
+        // Without a previous index, none is written
+        let fresh = dir.path().join("fresh.bkd");
+        let args = Args::new(
+            [input.to_str().unwrap(), fresh.to_str().unwrap(), "--quiet"].map(String::from),
+        );
+        assert!(build::run(args).is_err());
+        assert!(!fresh.exists());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+}
@@ -0,0 +1,137 @@
+//! `kd-tree query`: run a box or radius query against a built index.
+
+use crate::CliResult;
+use crate::args::Args;
+use bkd::node_file::NodeFileReader;
+use bkd::{BoundingBox, Circle, InMemoryLinker, NodeLinker, SpatialQuery, spatial_search};
+use serde_json::json;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+/// Query given on the command line.
+enum Query {
+    Box(BoundingBox),
+    Radius(Circle),
+}
+
+impl SpatialQuery<BoundingBox> for Query {
+    fn dimension_range(&self, dim: usize) -> (f64, f64) {
+        match self {
+            Query::Box(bbox) => bbox.dimension_range(dim),
+            Query::Radius(circle) => circle.dimension_range(dim),
+        }
+    }
+
+    fn matches(&self, point: &BoundingBox) -> bool {
+        match self {
+            Query::Box(bbox) => bbox.matches(point),
+            Query::Radius(circle) => circle.matches(point),
+        }
+    }
+}
+
+/// How matches are printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Csv,
+    Json,
+}
+
+pub fn run(mut args: Args) -> CliResult<()> {
+    let bbox = args.option("--bbox")?;
+    let radius = args.option("--radius")?;
+    let output = match args.option("--output")?.as_deref() {
+        None | Some("csv") => OutputFormat::Csv,
+        Some("json") => OutputFormat::Json,
+        Some(other) => return Err(format!("unknown output format '{other}'").into()),
+    };
+    let index = PathBuf::from(args.positional("index file")?);
+    args.finish()?;
+
+    let query = match (bbox, radius) {
+        (Some(bbox), None) => match parse_numbers(&bbox, "--bbox")?[..] {
+            [xmin, ymin, xmax, ymax] if xmin <= xmax && ymin <= ymax => {
+                Query::Box(BoundingBox::new(xmin, ymin, xmax, ymax))
+            }
+            _ => return Err("--bbox expects xmin,ymin,xmax,ymax with min <= max".into()),
+        },
+        (None, Some(radius)) => match parse_numbers(&radius, "--radius")?[..] {
+            [x, y, r] if r >= 0.0 => Query::Radius(Circle::new(x, y, r)),
+            _ => return Err("--radius expects x,y,r with r >= 0".into()),
+        },
+        _ => return Err("pass exactly one of --bbox or --radius".into()),
+    };
+
+    let mut reader = NodeFileReader::<BoundingBox, u64>::open(&index)?;
+    let (mut arena, root) = reader.load_arena()?;
+    let linker = InMemoryLinker::new(&mut arena);
+    let matches: Vec<(&BoundingBox, u64)> = spatial_search(&linker, root, &query, 0)
+        .into_iter()
+        .map(|node| (linker.get_point(node), *linker.get_data(node)))
+        .collect();
+
+    let mut stdout = io::stdout().lock();
+    write_matches(&mut stdout, &matches, output)?;
+    Ok(())
+}
+
+pub fn parse_numbers(value: &str, name: &str) -> Result<Vec<f64>, String> {
+    value
+        .split(',')
+        .map(|field| {
+            field
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid number '{field}' in {name}"))
+        })
+        .collect()
+}
+
+fn write_matches(
+    out: &mut impl Write,
+    matches: &[(&BoundingBox, u64)],
+    format: OutputFormat,
+) -> io::Result<()> {
+    match format {
+        OutputFormat::Csv => {
+            writeln!(out, "id,xmin,ymin,xmax,ymax")?;
+            for (bbox, id) in matches {
+                writeln!(
+                    out,
+                    "{id},{},{},{},{}",
+                    bbox.xmin, bbox.ymin, bbox.xmax, bbox.ymax
+                )?;
+            }
+        }
+        OutputFormat::Json => {
+            let matches: Vec<_> = matches
+                .iter()
+                .map(|(bbox, id)| json!({"id": id, "bbox": [bbox.xmin, bbox.ymin, bbox.xmax, bbox.ymax]}))
+                .collect();
+            serde_json::to_writer(&mut *out, &matches)?;
+            writeln!(out)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_matches() {
+        let bbox = BoundingBox::new(1.0, 2.0, 3.0, 4.5);
+        let mut csv = Vec::new();
+        write_matches(&mut csv, &[(&bbox, 7)], OutputFormat::Csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "id,xmin,ymin,xmax,ymax\n7,1,2,3,4.5\n"
+        );
+
+        let mut json = Vec::new();
+        write_matches(&mut json, &[(&bbox, 7)], OutputFormat::Json).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(value, json!([{"id": 7, "bbox": [1.0, 2.0, 3.0, 4.5]}]));
+    }
+}
@@ -0,0 +1,36 @@
+//! `kd-tree repair`: rebuild a damaged index from the records it still holds.
+
+use crate::CliResult;
+use crate::args::Args;
+use bkd::{BoundingBox, ExternalBuildOptions, repair};
+use std::path::PathBuf;
+
+pub fn run(mut args: Args) -> CliResult<()> {
+    let max_in_memory = args.parsed_option::<usize>("--max-in-memory")?;
+    let temp_dir = args.option("--temp-dir")?.map(PathBuf::from);
+    let index = PathBuf::from(args.positional("index file")?);
+    let output = PathBuf::from(args.positional("output file")?);
+    args.finish()?;
+    if index == output {
+        return Err("the output must differ from the damaged index".into());
+    }
+
+    let mut options = ExternalBuildOptions {
+        temp_dir,
+        ..ExternalBuildOptions::default()
+    };
+    if let Some(max_in_memory) = max_in_memory {
+        options.max_entries_in_memory = max_in_memory.max(1);
+    }
+    let report = repair::<BoundingBox, u64>(&index, &output, &options)?;
+    if report.header_damaged {
+        eprintln!("header unreadable; record count taken from the file size");
+    }
+    println!(
+        "salvaged {} entries into {}, discarded {}",
+        report.salvaged,
+        output.display(),
+        report.discarded
+    );
+    Ok(())
+}
@@ -0,0 +1,30 @@
+//! `kd-tree verify`: check an index for damage before serving it.
+
+use crate::CliResult;
+use crate::args::Args;
+use bkd::{BoundingBox, verify};
+use std::path::PathBuf;
+
+pub fn run(mut args: Args) -> CliResult<()> {
+    let index = PathBuf::from(args.positional("index file")?);
+    args.finish()?;
+
+    let report = verify::<BoundingBox, u64>(&index)?;
+    let checksum = if report.checksummed {
+        "checked"
+    } else {
+        "absent"
+    };
+    println!(
+        "{} of {} nodes reachable, checksum {checksum}",
+        report.reachable, report.records
+    );
+    for problem in &report.problems {
+        println!("problem: {problem}");
+    }
+    if report.is_ok() {
+        Ok(())
+    } else {
+        Err(format!("{} problems found", report.problems.len()).into())
+    }
+}
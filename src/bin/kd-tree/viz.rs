@@ -0,0 +1,97 @@
+//! `kd-tree viz`: render a built index to SVG or HTML.
+
+use crate::CliResult;
+use crate::args::Args;
+use crate::query::parse_numbers;
+use bkd::node_file::NodeFileReader;
+use bkd::search::{SvgOptions, add_query_to_svg, tree_svg_bounds, tree_to_svg_with_options};
+use bkd::{BoundingBox, InMemoryLinker};
+use std::path::{Path, PathBuf};
+
+/// Trees larger than this render slowly and are unreadable; warn before drawing them.
+const LARGE_TREE: u64 = 20_000;
+
+pub fn run(mut args: Args) -> CliResult<()> {
+    let query = args.option("--bbox")?;
+    let width = args.parsed_option::<u32>("--width")?.unwrap_or(800);
+    let height = args.parsed_option::<u32>("--height")?.unwrap_or(600);
+    let mut options = SvgOptions::default();
+    if let Some(max_depth) = args.parsed_option::<usize>("--max-depth")? {
+        options.max_depth = max_depth;
+    }
+    if let Some(max_nodes) = args.parsed_option::<usize>("--max-nodes")? {
+        options.max_nodes = max_nodes;
+    }
+    let index = PathBuf::from(args.positional("index file")?);
+    let output = PathBuf::from(args.positional("output file")?);
+    args.finish()?;
+
+    let query = match query {
+        Some(query) => match parse_numbers(&query, "--bbox")?[..] {
+            [xmin, ymin, xmax, ymax] if xmin <= xmax && ymin <= ymax => {
+                Some(BoundingBox::new(xmin, ymin, xmax, ymax))
+            }
+            _ => return Err("--bbox expects xmin,ymin,xmax,ymax with min <= max".into()),
+        },
+        None => None,
+    };
+
+    let mut reader = NodeFileReader::<BoundingBox, u64>::open(&index)?;
+    if reader.len() > LARGE_TREE && options.max_nodes as u64 > LARGE_TREE {
+        eprintln!(
+            "warning: rendering {} nodes; the output will be large",
+            reader.len()
+        );
+    }
+    let (mut arena, root) = reader.load_arena()?;
+    let linker = InMemoryLinker::new(&mut arena);
+
+    let mut svg = tree_to_svg_with_options(&linker, root, width, height, &options);
+    if let (Some(query), Some(bounds)) = (&query, tree_svg_bounds(&linker, root)) {
+        add_query_to_svg(&mut svg, query, &bounds, width, height);
+    }
+
+    let document = if is_html(&output) {
+        wrap_html(&svg, &index)
+    } else {
+        svg
+    };
+    std::fs::write(&output, document)?;
+    println!("wrote {}", output.display());
+    Ok(())
+}
+
+fn is_html(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("html"))
+}
+
+/// Standalone page embedding the SVG, for opening straight in a browser.
+fn wrap_html(svg: &str, index: &Path) -> String {
+    let title = index
+        .display()
+        .to_string()
+        .replace('&', "&amp;")
+        .replace('<', "&lt;");
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n</head>\n\
+         <body>\n<h1>{title}</h1>\n<p>Rectangles are coloured by tree depth.</p>\n{svg}\n</body>\n</html>\n"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_html_output() {
+        assert!(is_html(Path::new("tree.HTML")));
+        assert!(!is_html(Path::new("tree.svg")));
+
+        let page = wrap_html("<svg></svg>", Path::new("a<b.bkd"));
+        assert!(page.starts_with("<!DOCTYPE html>"));
+        assert!(page.contains("<title>a&lt;b.bkd</title>"));
+        assert!(page.contains("<svg></svg>"));
+    }
+}
@@ -0,0 +1,2320 @@
+//! Block KD-tree (BKD) in the style of Lucene's `BKDWriter` / `BKDReader`.
+//!
+//! Unlike the node-per-entry trees elsewhere in this crate, a block KD-tree stores entries
+//! only in leaf blocks of up to `max_points_in_leaf` entries; inner nodes hold nothing but
+//! a split dimension and split value.
+//!
+//! # File layout
+//! ```text
+//! ┌──────────────────────────────┐  offset 0
+//! │ Header (64 bytes)            │  magic, version, sizes, counts, index offset
+//! ├──────────────────────────────┤  offset 64
+//! │ Leaf block 0                 │  count u32 | count × (point | data), or columnar
+//! │ Leaf block 1                 │  or, compressed: count u32 | size u32 | size bytes
+//! │ ...                          │  leaves in left-to-right order
+//! ├──────────────────────────────┤  index offset
+//! │ Index                        │  `PackedIndex`: split dims and values, min/max, leaf offsets
+//! ├──────────────────────────────┤
+//! │ Payload dictionary           │  only with dictionary payloads: count u32 | count × data
+//! └──────────────────────────────┘
+//! ```
+//! The inner tree is implicit: with `num_leaves` a power of two, node 1 is the root, node
+//! `n` has children `2n` and `2n + 1`, and nodes `num_leaves..2 * num_leaves` are leaves.
+//! The reader keeps the whole index in memory and reads only the leaf blocks a query needs.
+//!
+//! Leaf blocks may be compressed with the codec `BkdWriterOptions::compression` names,
+//! recorded in the header. A compressed block is the compressed form of the records an
+//! uncompressed block would hold after its count.
+//!
+//! With `BkdWriterOptions::dictionary_payloads`, each distinct payload is stored once in a
+//! dictionary after the index, and leaf records hold `point | varint id` in place of
+//! `point | data`. Such blocks always carry their size, and are compressed like any other
+//! when a codec is set.
+//!
+//! With `BkdWriterOptions::columnar`, a block holds its entries column by column:
+//! ```text
+//! dimensions × (min f64 | max f64) | dimensions × count × f64 | count × point | payloads
+//! ```
+//! where each column holds one dimension's values of every entry, and the payloads are
+//! `count × data`, or varint ids with dictionary payloads. Readers skip a crossing leaf
+//! whose column bounds fall outside the query, and filter the rest a column at a time,
+//! passing over columns the query does not constrain, before decoding a single point.
+
+use crate::cancel::{self, CancellationToken};
+use crate::codec::FixedCodec;
+use crate::compression::Compression;
+use crate::external::{RunMerge, TempFile, read_entries, read_entry, sort_runs, write_entry};
+use crate::metrics::Metrics;
+use crate::nearest::{EntryShape, Metric, Neighbor};
+use crate::query::{Relation, SpatialQuery};
+use crate::spatial::Point;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Magic bytes identifying a block tree file.
+pub const MAGIC: [u8; 8] = *b"BKDBLOCK";
+
+/// Current format version. Version 2 added leaf compression, version 3 dictionary
+/// payloads and version 4 columnar leaves; trees are written with the oldest version that
+/// can hold them, so older readers open every tree that uses none of these.
+pub const VERSION: u32 = 4;
+
+/// Size of the file header in bytes.
+pub const HEADER_SIZE: usize = 64;
+
+/// Header flag: leaves refer to payloads in a dictionary.
+const FLAG_DICTIONARY: u32 = 1;
+
+/// Header flag: leaves are laid out column by column.
+const FLAG_COLUMNAR: u32 = 2;
+
+/// Width ratio between the sides of a split beyond which the wider is sparse.
+const SPARSE_WIDTH_RATIO: f64 = 4.0;
+
+/// Longest varint of a `u32` dictionary id.
+const MAX_VARINT_LEN: usize = 5;
+
+/// How `BkdWriter` spreads entries over leaf blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LeafSizing {
+    /// Split every cell at its median, so all leaves hold about the same number of entries.
+    #[default]
+    Uniform,
+    /// Size leaves by density: sparse cells are packed into full leaves, and the leaves
+    /// they leave unused go to dense cells, which keep splitting at their median into
+    /// smaller ones. The tree gets `2^extra_levels` times the leaves `Uniform` would;
+    /// unused leaves hold no entries and cost a few bytes each.
+    Adaptive { extra_levels: u32 },
+}
+
+/// Options for `BkdWriter`.
+#[derive(Debug, Clone)]
+pub struct BkdWriterOptions {
+    /// Maximum number of entries per leaf block (Lucene's default is 512).
+    pub max_points_in_leaf: usize,
+    /// Maximum number of entries buffered in memory before spilling to disk.
+    pub max_entries_in_memory: usize,
+    /// Directory for spill files; defaults to the system temporary directory.
+    pub temp_dir: Option<PathBuf>,
+    /// Optional token checked between cells while writing.
+    pub cancel: Option<CancellationToken>,
+    /// Codec for the leaf blocks.
+    pub compression: Compression,
+    /// Store each distinct payload once and refer to it from leaves by a varint id. Pays
+    /// off when few payloads repeat across many entries, such as categories or layer ids;
+    /// writers and readers hold the whole dictionary in memory.
+    pub dictionary_payloads: bool,
+    /// Lay leaves out column by column, with the bounds of each column, so searches skip
+    /// and filter entries by dimension before decoding points. Costs one `f64` per
+    /// dimension per entry on top of the records.
+    pub columnar: bool,
+    /// How entries are spread over leaves.
+    pub leaf_sizing: LeafSizing,
+}
+
+impl Default for BkdWriterOptions {
+    fn default() -> Self {
+        BkdWriterOptions {
+            max_points_in_leaf: 512,
+            max_entries_in_memory: 1 << 20,
+            temp_dir: None,
+            cancel: None,
+            compression: Compression::None,
+            dictionary_payloads: false,
+            columnar: false,
+            leaf_sizing: LeafSizing::Uniform,
+        }
+    }
+}
+
+/// Decoded block tree header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BkdHeader {
+    pub version: u32,
+    pub dimensions: u32,
+    pub point_size: u32,
+    pub data_size: u32,
+    pub max_points_in_leaf: u32,
+    pub num_leaves: u64,
+    pub point_count: u64,
+    pub index_offset: u64,
+    pub compression: Compression,
+    pub dictionary_payloads: bool,
+    pub columnar: bool,
+}
+
+impl BkdHeader {
+    fn encode(&self) -> [u8; HEADER_SIZE] {
+        let mut buf = [0u8; HEADER_SIZE];
+        buf[0..8].copy_from_slice(&MAGIC);
+        self.version.encode(&mut buf[8..12]);
+        self.dimensions.encode(&mut buf[12..16]);
+        self.point_size.encode(&mut buf[16..20]);
+        self.data_size.encode(&mut buf[20..24]);
+        self.max_points_in_leaf.encode(&mut buf[24..28]);
+        let mut flags = 0;
+        if self.dictionary_payloads {
+            flags |= FLAG_DICTIONARY;
+        }
+        if self.columnar {
+            flags |= FLAG_COLUMNAR;
+        }
+        flags.encode(&mut buf[28..32]);
+        self.num_leaves.encode(&mut buf[32..40]);
+        self.point_count.encode(&mut buf[40..48]);
+        self.index_offset.encode(&mut buf[48..56]);
+        let (codec, level) = self.compression.to_parts();
+        codec.encode(&mut buf[56..60]);
+        (level as u32).encode(&mut buf[60..64]);
+        buf
+    }
+
+    fn decode(buf: &[u8; HEADER_SIZE]) -> io::Result<Self> {
+        if buf[0..8] != MAGIC {
+            return Err(invalid_data("not a block KD-tree file (bad magic)"));
+        }
+        let flags = u32::decode(&buf[28..32]);
+        if flags & !(FLAG_DICTIONARY | FLAG_COLUMNAR) != 0 {
+            return Err(invalid_data(&format!(
+                "unknown block tree flags {flags:#x}"
+            )));
+        }
+        let header = BkdHeader {
+            version: u32::decode(&buf[8..12]),
+            dimensions: u32::decode(&buf[12..16]),
+            point_size: u32::decode(&buf[16..20]),
+            data_size: u32::decode(&buf[20..24]),
+            max_points_in_leaf: u32::decode(&buf[24..28]),
+            num_leaves: u64::decode(&buf[32..40]),
+            point_count: u64::decode(&buf[40..48]),
+            index_offset: u64::decode(&buf[48..56]),
+            compression: Compression::from_parts(
+                u32::decode(&buf[56..60]),
+                u32::decode(&buf[60..64]) as i32,
+            )?,
+            dictionary_payloads: flags & FLAG_DICTIONARY != 0,
+            columnar: flags & FLAG_COLUMNAR != 0,
+        };
+        if !(1..=VERSION).contains(&header.version) {
+            return Err(invalid_data(&format!(
+                "unsupported block tree version {}",
+                header.version
+            )));
+        }
+        if !header.num_leaves.is_power_of_two() {
+            return Err(invalid_data("leaf count is not a power of two"));
+        }
+        Ok(header)
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Number of leaves for `count` entries: enough to hold them all, rounded up to a power of
+/// two so the inner tree is complete.
+fn leaf_count(count: u64, max_points_in_leaf: usize) -> u64 {
+    count
+        .div_ceil(max_points_in_leaf as u64)
+        .max(1)
+        .next_power_of_two()
+}
+
+/// Entries of one pending cell: still in memory or spilled to a file.
+enum Cell<P, T> {
+    Memory(Vec<(P, T)>),
+    Spilled { file: TempFile, len: usize },
+}
+
+/// Buffers unsorted entries and writes them as a block KD-tree.
+///
+/// # Usage pattern:
+/// ```rust
+/// # use bkd::BoundingBox;
+/// # use bkd::block_tree::{BkdReader, BkdWriter, BkdWriterOptions};
+/// # let dir = tempfile::tempdir().unwrap();
+/// # let path = dir.path().join("points.bkd");
+/// let mut writer = BkdWriter::new(BkdWriterOptions::default());
+/// for i in 0..1000u32 {
+///     let x = f64::from(i % 100);
+///     writer.add(BoundingBox::new(x, x, x + 1.0, x + 1.0), i).unwrap();
+/// }
+/// writer.finish(&path).unwrap();
+///
+/// let mut reader = BkdReader::<BoundingBox, u32>::open(&path).unwrap();
+/// let matches = reader.search(&BoundingBox::new(10.0, 10.0, 12.0, 12.0)).unwrap();
+/// assert_eq!(matches.len(), 40);
+/// ```
+///
+/// # Architecture
+/// Entries are buffered in memory and spilled to a temporary file once the buffer exceeds
+/// `max_entries_in_memory`. `finish` partitions them recursively: every cell is split at
+/// its median on the dimension with the largest spread, down to a fixed depth of
+/// `log2(num_leaves)`, so every leaf holds at most `max_points_in_leaf` entries. Cells
+/// larger than the memory limit are partitioned with the external sort used by
+/// `external_bulk_build`; smaller ones in memory. Leaves are written left to right, so the
+/// output is written sequentially.
+///
+/// With `LeafSizing::Adaptive`, each split also marks its sides sparse or dense by how far
+/// apart their entries lie, and cells inherit their parent's mark. Sparse cells held in
+/// memory are split where their left side fills whole leaves, and a sparse cell that fits
+/// one leaf sends every entry left with an infinite split value, so the cells to its right
+/// are empty and never relate to a query. Dense cells split at their median down to the
+/// leaf level, where the leaves the sparse cells did not use make their blocks small. The
+/// implicit tree keeps its fixed depth, so readers need nothing new.
+pub struct BkdWriter<P, T> {
+    options: BkdWriterOptions,
+    buffer: Vec<(P, T)>,
+    spill: Option<(TempFile, BufWriter<File>)>,
+    count: usize,
+}
+
+impl<P: Point + FixedCodec, T: FixedCodec> BkdWriter<P, T> {
+    /// Create a writer with the given options.
+    pub fn new(options: BkdWriterOptions) -> Self {
+        BkdWriter {
+            options,
+            buffer: Vec::new(),
+            spill: None,
+            count: 0,
+        }
+    }
+
+    /// Add an entry. Entries may arrive in any order.
+    pub fn add(&mut self, point: P, data: T) -> io::Result<()> {
+        self.count += 1;
+        if let Some((_, writer)) = &mut self.spill {
+            return write_entry(writer, &(point, data));
+        }
+        self.buffer.push((point, data));
+        if self.buffer.len() > self.options.max_entries_in_memory.max(1) {
+            let file = TempFile::new(&self.temp_dir());
+            let mut writer = BufWriter::new(File::create(&file.path)?);
+            for entry in self.buffer.drain(..) {
+                write_entry(&mut writer, &entry)?;
+            }
+            self.buffer = Vec::new();
+            self.spill = Some((file, writer));
+        }
+        Ok(())
+    }
+
+    /// Number of entries added so far.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    /// Check if no entries have been added.
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    fn temp_dir(&self) -> PathBuf {
+        self.options
+            .temp_dir
+            .clone()
+            .unwrap_or_else(std::env::temp_dir)
+    }
+
+    /// Write the tree to `path` and return its header.
+    ///
+    /// Fails with `Unsupported` if the codec's feature is not enabled.
+    pub fn finish(self, path: &Path) -> io::Result<BkdHeader> {
+        self.options.compression.check_available()?;
+        let temp_dir = self.temp_dir();
+        let BkdWriter {
+            options,
+            buffer,
+            spill,
+            count,
+        } = self;
+
+        let (cell, dimensions) = match spill {
+            Some((file, mut writer)) => {
+                writer.flush()?;
+                drop(writer);
+                let mut reader = BufReader::new(File::open(&file.path)?);
+                let (first, _): (P, T) = read_entry(&mut reader)?;
+                (Cell::Spilled { file, len: count }, first.dimensions())
+            }
+            None => {
+                let dimensions = buffer.first().map_or(1, |(point, _)| point.dimensions());
+                (Cell::Memory(buffer), dimensions)
+            }
+        };
+
+        let max_points_in_leaf = options.max_points_in_leaf.max(1);
+        let num_leaves = match options.leaf_sizing {
+            LeafSizing::Uniform => leaf_count(count as u64, max_points_in_leaf),
+            LeafSizing::Adaptive { extra_levels } => {
+                leaf_count(count as u64, max_points_in_leaf) << extra_levels
+            }
+        };
+        let mut output = BufWriter::new(File::create(path)?);
+        output.write_all(&[0u8; HEADER_SIZE])?;
+
+        let mut builder = BlockBuilder {
+            output,
+            offset: HEADER_SIZE as u64,
+            dimensions,
+            num_leaves: num_leaves as usize,
+            limit: options.max_entries_in_memory.max(1),
+            temp_dir,
+            cancel: options.cancel.as_ref(),
+            compression: options.compression,
+            dictionary: options.dictionary_payloads.then(PayloadDictionary::default),
+            columnar: options.columnar,
+            leaf_sizing: options.leaf_sizing,
+            max_points_in_leaf,
+            block: Vec::new(),
+            index: PackedIndex::empty(dimensions, num_leaves as usize),
+            next_leaf: 0,
+            _marker: PhantomData,
+        };
+        builder.build(cell, 1, false)?;
+
+        let BlockBuilder {
+            mut output,
+            offset: index_offset,
+            index,
+            dictionary,
+            ..
+        } = builder;
+        output.write_all(&index.encode())?;
+        if let Some(dictionary) = &dictionary {
+            output.write_all(&(dictionary.len() as u32).to_le_bytes())?;
+            output.write_all(&dictionary.values)?;
+        }
+
+        let header = BkdHeader {
+            version: if options.columnar {
+                4
+            } else if options.dictionary_payloads {
+                3
+            } else if options.compression != Compression::None {
+                2
+            } else {
+                1
+            },
+            dimensions: dimensions as u32,
+            point_size: P::SIZE as u32,
+            data_size: T::SIZE as u32,
+            max_points_in_leaf: max_points_in_leaf as u32,
+            num_leaves,
+            point_count: count as u64,
+            index_offset,
+            compression: options.compression,
+            dictionary_payloads: options.dictionary_payloads,
+            columnar: options.columnar,
+        };
+        output.seek(SeekFrom::Start(0))?;
+        output.write_all(&header.encode())?;
+        let file = output.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+        Ok(header)
+    }
+}
+
+struct BlockBuilder<'a, P, T> {
+    output: BufWriter<File>,
+    offset: u64,
+    dimensions: usize,
+    num_leaves: usize,
+    limit: usize,
+    temp_dir: PathBuf,
+    cancel: Option<&'a CancellationToken>,
+    compression: Compression,
+    dictionary: Option<PayloadDictionary>,
+    columnar: bool,
+    leaf_sizing: LeafSizing,
+    max_points_in_leaf: usize,
+    /// Encoded block of the leaf being written.
+    block: Vec<u8>,
+    index: PackedIndex,
+    next_leaf: usize,
+    _marker: PhantomData<(P, T)>,
+}
+
+impl<'a, P: Point + FixedCodec, T: FixedCodec> BlockBuilder<'a, P, T> {
+    /// Write the cell rooted at implicit node `node`; `sparse` as `sparse_sides` says.
+    fn build(&mut self, cell: Cell<P, T>, node: usize, sparse: bool) -> io::Result<()> {
+        cancel::check(self.cancel)?;
+        let (file, len) = match cell {
+            Cell::Memory(mut entries) => return self.build_in_memory(&mut entries, node, sparse),
+            Cell::Spilled { file, len } if len <= self.limit => {
+                let mut entries = read_entries(&file.path, len)?;
+                drop(file);
+                return self.build_in_memory(&mut entries, node, sparse);
+            }
+            Cell::Spilled { file, len } => (file, len),
+        };
+        if node >= self.num_leaves {
+            // A leaf over the memory limit still holds at most `max_points_in_leaf` entries
+            let entries = read_entries(&file.path, len)?;
+            drop(file);
+            return self.write_leaf(&entries);
+        }
+
+        // One pass for the bounds, then an external sort on the widest dimension
+        let mut reader = BufReader::new(File::open(&file.path)?);
+        let mut min = vec![f64::INFINITY; self.dimensions];
+        let mut max = vec![f64::NEG_INFINITY; self.dimensions];
+        for _ in 0..len {
+            let (point, _): (P, T) = read_entry(&mut reader)?;
+            extend_bounds(&mut min, &mut max, &point);
+        }
+        drop(reader);
+        let dimension = widest_dimension(&min, &max);
+        let runs = sort_runs::<P, T>(&file.path, len, dimension, self.limit, &self.temp_dir)?;
+        drop(file);
+
+        let median = len / 2;
+        let left = TempFile::new(&self.temp_dir);
+        let right = TempFile::new(&self.temp_dir);
+        let mut left_writer = BufWriter::new(File::create(&left.path)?);
+        let mut right_writer = BufWriter::new(File::create(&right.path)?);
+        let mut merge = RunMerge::<P, T>::new(&runs)?;
+        let mut position = 0;
+        let mut value = 0.0;
+        while let Some(entry) = merge.next_entry()? {
+            if position == median {
+                value = entry.0.get_dimension(dimension);
+                self.index.set_split(node, dimension, value);
+            }
+            if position < median {
+                write_entry(&mut left_writer, &entry)?;
+            } else {
+                write_entry(&mut right_writer, &entry)?;
+            }
+            position += 1;
+        }
+        left_writer.flush()?;
+        right_writer.flush()?;
+        drop(merge);
+        drop(runs);
+
+        let (left_sparse, right_sparse) = sparse_sides(
+            (min[dimension], value, max[dimension]),
+            (median, len - median),
+            sparse,
+        );
+        self.build(
+            Cell::Spilled {
+                file: left,
+                len: median,
+            },
+            2 * node,
+            left_sparse,
+        )?;
+        self.build(
+            Cell::Spilled {
+                file: right,
+                len: len - median,
+            },
+            2 * node + 1,
+            right_sparse,
+        )
+    }
+
+    fn build_in_memory(
+        &mut self,
+        entries: &mut [(P, T)],
+        node: usize,
+        sparse: bool,
+    ) -> io::Result<()> {
+        if node >= self.num_leaves {
+            return self.write_leaf(entries);
+        }
+        cancel::check(self.cancel)?;
+
+        let mut min = vec![f64::INFINITY; self.dimensions];
+        let mut max = vec![f64::NEG_INFINITY; self.dimensions];
+        for (point, _) in entries.iter() {
+            extend_bounds(&mut min, &mut max, point);
+        }
+        let dimension = widest_dimension(&min, &max);
+        let len = entries.len();
+        let split = match self.leaf_sizing {
+            // Pack sparse cells into full leaves: the left side takes half the leaves they
+            // need, filled, and the whole cell once it fits one
+            LeafSizing::Adaptive { .. } if sparse => {
+                let max_points_in_leaf = self.max_points_in_leaf;
+                len.min(len.div_ceil(max_points_in_leaf).div_ceil(2) * max_points_in_leaf)
+            }
+            _ => len / 2,
+        };
+        let sides = if split < len {
+            entries.select_nth_unstable_by(split, |a, b| {
+                a.0.get_dimension(dimension)
+                    .total_cmp(&b.0.get_dimension(dimension))
+            });
+            let value = entries[split].0.get_dimension(dimension);
+            self.index.set_split(node, dimension, value);
+            sparse_sides(
+                (min[dimension], value, max[dimension]),
+                (split, len - split),
+                sparse,
+            )
+        } else if len == 0 {
+            // Empty cell: any split works, its leaves are empty
+            self.index.set_split(node, dimension, 0.0);
+            (sparse, sparse)
+        } else {
+            // All of it goes left; the right cell starts past any value, so no query
+            // reaches its empty leaves
+            self.index.set_split(node, dimension, f64::INFINITY);
+            (sparse, sparse)
+        };
+
+        let (left, right) = entries.split_at_mut(split);
+        self.build_in_memory(left, 2 * node, sides.0)?;
+        self.build_in_memory(right, 2 * node + 1, sides.1)
+    }
+
+    fn write_leaf(&mut self, entries: &[(P, T)]) -> io::Result<()> {
+        self.index.leaf_offsets[self.next_leaf] = self.offset;
+        self.next_leaf += 1;
+        self.output
+            .write_all(&(entries.len() as u32).to_le_bytes())?;
+        for entry in entries {
+            extend_bounds(&mut self.index.min, &mut self.index.max, &entry.0);
+        }
+        self.encode_leaf(entries);
+        if self.compression == Compression::None && self.dictionary.is_none() {
+            self.output.write_all(&self.block)?;
+            self.offset += 4 + self.block.len() as u64;
+            return Ok(());
+        }
+
+        let compressed = self.compression.compress(&self.block)?;
+        self.output
+            .write_all(&(compressed.len() as u32).to_le_bytes())?;
+        self.output.write_all(&compressed)?;
+        self.offset += 8 + compressed.len() as u64;
+        Ok(())
+    }
+
+    /// Encode the block of a leaf after its count into `self.block`, uncompressed.
+    fn encode_leaf(&mut self, entries: &[(P, T)]) {
+        self.block.clear();
+        let mut buf = vec![0u8; P::SIZE.max(T::SIZE)];
+        if self.columnar {
+            for dim in 0..self.dimensions {
+                let (mut min, mut max) = (f64::INFINITY, f64::NEG_INFINITY);
+                for (point, _) in entries {
+                    min = min.min(point.get_dimension(dim));
+                    max = max.max(point.get_dimension(dim));
+                }
+                self.block.extend_from_slice(&min.to_le_bytes());
+                self.block.extend_from_slice(&max.to_le_bytes());
+            }
+            for dim in 0..self.dimensions {
+                for (point, _) in entries {
+                    self.block
+                        .extend_from_slice(&point.get_dimension(dim).to_le_bytes());
+                }
+            }
+            for (point, _) in entries {
+                point.encode(&mut buf[..P::SIZE]);
+                self.block.extend_from_slice(&buf[..P::SIZE]);
+            }
+        }
+        for (point, data) in entries {
+            if !self.columnar {
+                point.encode(&mut buf[..P::SIZE]);
+                self.block.extend_from_slice(&buf[..P::SIZE]);
+            }
+            data.encode(&mut buf[..T::SIZE]);
+            match &mut self.dictionary {
+                Some(dictionary) => {
+                    write_varint(&mut self.block, dictionary.intern(&buf[..T::SIZE]))
+                }
+                None => self.block.extend_from_slice(&buf[..T::SIZE]),
+            }
+        }
+    }
+}
+
+/// Distinct payloads of a tree in the order first written, each `data_size` bytes.
+#[derive(Default)]
+struct PayloadDictionary {
+    /// Id of each payload; only filled while writing.
+    ids: HashMap<Vec<u8>, u32>,
+    values: Vec<u8>,
+    count: usize,
+}
+
+impl PayloadDictionary {
+    fn len(&self) -> usize {
+        self.count
+    }
+
+    /// Id of `payload`, adding it if new.
+    fn intern(&mut self, payload: &[u8]) -> u32 {
+        if let Some(&id) = self.ids.get(payload) {
+            return id;
+        }
+        let id = u32::try_from(self.count).expect("more than u32::MAX distinct payloads");
+        self.ids.insert(payload.to_vec(), id);
+        self.values.extend_from_slice(payload);
+        self.count += 1;
+        id
+    }
+
+    /// Expand a block whose payloads are varint ids into `output`: `prefix` bytes copied
+    /// as they are, then `count` records of `point | varint id` becoming `point | data`.
+    /// Columnar blocks keep their points in the prefix, with a `point_size` of zero.
+    fn expand(
+        &self,
+        mut encoded: &[u8],
+        prefix: usize,
+        count: usize,
+        point_size: usize,
+        data_size: usize,
+        output: &mut Vec<u8>,
+    ) -> io::Result<()> {
+        output.clear();
+        let head = encoded
+            .get(..prefix)
+            .ok_or_else(|| invalid_data("dictionary leaf block truncated"))?;
+        output.extend_from_slice(head);
+        encoded = &encoded[prefix..];
+        for _ in 0..count {
+            let point = encoded
+                .get(..point_size)
+                .ok_or_else(|| invalid_data("dictionary leaf block truncated"))?;
+            output.extend_from_slice(point);
+            encoded = &encoded[point_size..];
+            let id = read_varint(&mut encoded)? as usize;
+            if id >= self.count {
+                return Err(invalid_data("payload id outside the dictionary"));
+            }
+            output.extend_from_slice(&self.values[id * data_size..(id + 1) * data_size]);
+        }
+        if !encoded.is_empty() {
+            return Err(invalid_data("trailing bytes in dictionary leaf block"));
+        }
+        Ok(())
+    }
+}
+
+fn write_varint(output: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        output.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    output.push(value as u8);
+}
+
+fn read_varint(input: &mut &[u8]) -> io::Result<u32> {
+    let mut value = 0u32;
+    for (i, &byte) in input.iter().take(MAX_VARINT_LEN).enumerate() {
+        value |= u32::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            *input = &input[i + 1..];
+            return Ok(value);
+        }
+    }
+    Err(invalid_data("malformed varint in dictionary leaf block"))
+}
+
+fn extend_bounds<P: Point>(min: &mut [f64], max: &mut [f64], point: &P) {
+    for dim in 0..min.len() {
+        let value = point.get_dimension(dim);
+        min[dim] = min[dim].min(value);
+        max[dim] = max[dim].max(value);
+    }
+}
+
+/// Whether the cells on each side of a split at `value`, within `min..=max`, are sparse,
+/// for `LeafSizing::Adaptive`. A side whose entries lie `SPARSE_WIDTH_RATIO` times farther
+/// apart along the split dimension than the other's is sparse, and the other dense; when
+/// neither stands out, both keep the `parent` cell's flag.
+fn sparse_sides(
+    (min, value, max): (f64, f64, f64),
+    (left_len, right_len): (usize, usize),
+    parent: bool,
+) -> (bool, bool) {
+    let left = (value - min) / left_len.max(1) as f64;
+    let right = (max - value) / right_len.max(1) as f64;
+    if left > SPARSE_WIDTH_RATIO * right {
+        (true, false)
+    } else if right > SPARSE_WIDTH_RATIO * left {
+        (false, true)
+    } else {
+        (parent, parent)
+    }
+}
+
+/// Dimension with the largest spread, ties to the lowest dimension.
+fn widest_dimension(min: &[f64], max: &[f64]) -> usize {
+    (0..min.len())
+        .max_by(|&a, &b| {
+            (max[a] - min[a])
+                .total_cmp(&(max[b] - min[b]))
+                .then(b.cmp(&a))
+        })
+        .unwrap_or(0)
+}
+
+/// Inner tree of a block tree, packed for read-only traversal.
+///
+/// The tree is implicit: node `n` has children `2n` and `2n + 1`, so no child pointers are
+/// stored, only one split value and one split dimension per inner node, in heap order.
+/// Nodes near the root sit next to each other at the front of the arrays, which keeps the
+/// top levels of every traversal in the same few cache lines. At 9 bytes per inner node, a
+/// tree of a million entries in 512-entry leaves needs 18 KiB for its splits; leaf offsets
+/// add 8 bytes per leaf.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PackedIndex {
+    /// Split values of inner nodes `1..num_leaves`, stored at `node - 1`.
+    values: Box<[f64]>,
+    /// Split dimensions, parallel to `values`; empty for one-dimensional trees.
+    dims: Box<[u8]>,
+    leaf_offsets: Box<[u64]>,
+    min: Vec<f64>,
+    max: Vec<f64>,
+}
+
+impl PackedIndex {
+    fn empty(dimensions: usize, num_leaves: usize) -> Self {
+        let inner = num_leaves - 1;
+        PackedIndex {
+            values: vec![0.0; inner].into_boxed_slice(),
+            dims: vec![0; if dimensions > 1 { inner } else { 0 }].into_boxed_slice(),
+            leaf_offsets: vec![0; num_leaves].into_boxed_slice(),
+            min: vec![f64::INFINITY; dimensions],
+            max: vec![f64::NEG_INFINITY; dimensions],
+        }
+    }
+
+    fn set_split(&mut self, node: usize, dimension: usize, value: f64) {
+        self.values[node - 1] = value;
+        if !self.dims.is_empty() {
+            self.dims[node - 1] = dimension as u8;
+        }
+    }
+
+    /// Number of leaf blocks.
+    pub fn num_leaves(&self) -> usize {
+        self.leaf_offsets.len()
+    }
+
+    /// Split dimension and value of inner node `node`; the root is node 1.
+    ///
+    /// # Panics
+    /// Panics if `node` is 0 or a leaf.
+    pub fn split(&self, node: usize) -> (usize, f64) {
+        let dimension = self.dims.get(node - 1).map_or(0, |&dim| dim as usize);
+        (dimension, self.values[node - 1])
+    }
+
+    /// Leaf number of `node`, or `None` for inner nodes.
+    pub fn leaf(&self, node: usize) -> Option<usize> {
+        node.checked_sub(self.num_leaves())
+    }
+
+    /// File offset of the block of leaf `leaf`.
+    pub fn leaf_offset(&self, leaf: usize) -> u64 {
+        self.leaf_offsets[leaf]
+    }
+
+    /// Heap bytes held by the index.
+    pub fn memory_usage(&self) -> usize {
+        self.values.len() * 8
+            + self.dims.len()
+            + self.leaf_offsets.len() * 8
+            + (self.min.len() + self.max.len()) * 8
+    }
+
+    /// Encoded size of the index of a tree with the given shape.
+    fn encoded_len(dimensions: usize, num_leaves: usize) -> usize {
+        let inner = num_leaves - 1;
+        let dims = if dimensions > 1 { inner } else { 0 };
+        dims + 8 * inner + 16 * dimensions + 8 * num_leaves
+    }
+
+    /// Encode as split dims, split values, per-dimension min and max, then leaf offsets.
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Self::encoded_len(self.min.len(), self.num_leaves()));
+        bytes.extend_from_slice(&self.dims);
+        for value in self.values.iter().chain(&self.min).chain(&self.max) {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        for offset in self.leaf_offsets.iter() {
+            bytes.extend_from_slice(&offset.to_le_bytes());
+        }
+        bytes
+    }
+
+    fn decode(bytes: &[u8], dimensions: usize, num_leaves: usize) -> io::Result<Self> {
+        let mut index = PackedIndex::empty(dimensions, num_leaves);
+        let (dims, rest) = bytes.split_at(index.dims.len());
+        if dims.iter().any(|&dim| dim as usize >= dimensions) {
+            return Err(invalid_data("split dimension out of range"));
+        }
+        index.dims.copy_from_slice(dims);
+
+        let mut words = rest.chunks_exact(8);
+        let targets = index
+            .values
+            .iter_mut()
+            .chain(index.min.iter_mut())
+            .chain(index.max.iter_mut());
+        for (target, word) in targets.zip(words.by_ref()) {
+            *target = f64::decode(word);
+        }
+        for (target, word) in index.leaf_offsets.iter_mut().zip(words) {
+            *target = u64::decode(word);
+        }
+        Ok(index)
+    }
+}
+
+/// Callback interface for `BkdReader::intersect`, mirroring Lucene's `IntersectVisitor`.
+pub trait IntersectVisitor<P, T> {
+    /// Called for every entry of a cell that `compare` reported as fully inside.
+    fn visit(&mut self, data: &T);
+
+    /// Called for every entry of a cell that crosses the query; the visitor decides.
+    fn visit_point(&mut self, point: &P, data: &T);
+
+    /// Relate a cell, given by inclusive per-dimension bounds, to the query.
+    fn compare(&self, min: &[f64], max: &[f64]) -> Relation;
+
+    /// Inclusive range of values an entry may hold in `dim` and still be accepted.
+    /// Columnar leaves drop entries outside it before `visit_point`; a wider range is
+    /// always safe, and the default accepts everything.
+    fn dimension_range(&self, _dim: usize) -> (f64, f64) {
+        (f64::NEG_INFINITY, f64::INFINITY)
+    }
+}
+
+/// Visitor collecting the payloads of entries matching a `SpatialQuery`.
+pub struct QueryVisitor<'q, Q, T> {
+    query: &'q Q,
+    pub results: Vec<T>,
+}
+
+impl<'q, Q, T> QueryVisitor<'q, Q, T> {
+    /// Create a visitor for `query` with no results yet.
+    pub fn new(query: &'q Q) -> Self {
+        QueryVisitor {
+            query,
+            results: Vec::new(),
+        }
+    }
+}
+
+impl<'q, P: Point, T: Clone, Q: SpatialQuery<P>> IntersectVisitor<P, T> for QueryVisitor<'q, Q, T> {
+    fn visit(&mut self, data: &T) {
+        self.results.push(data.clone());
+    }
+
+    fn visit_point(&mut self, point: &P, data: &T) {
+        if self.query.matches(point) {
+            self.results.push(data.clone());
+        }
+    }
+
+    fn compare(&self, min: &[f64], max: &[f64]) -> Relation {
+        self.query.relate(min, max)
+    }
+
+    fn dimension_range(&self, dim: usize) -> (f64, f64) {
+        self.query.dimension_range(dim)
+    }
+}
+
+/// Reader for block tree files: the index lives in memory, leaf blocks are read on demand.
+pub struct BkdReader<P, T> {
+    file: File,
+    header: BkdHeader,
+    index: PackedIndex,
+    block: Vec<u8>,
+    /// Compressed bytes of the last leaf read.
+    compressed: Vec<u8>,
+    /// Dictionary-encoded records of the last leaf read.
+    encoded: Vec<u8>,
+    dictionary: Option<PayloadDictionary>,
+    /// Entries of a columnar leaf that passed the column filters.
+    selected: Vec<bool>,
+    metrics: Option<Arc<Metrics>>,
+    // Decodes owned values and never holds one, so it is Send and Sync whatever P and T
+    _marker: PhantomData<fn() -> (P, T)>,
+}
+
+impl<P: Point + FixedCodec, T: FixedCodec> BkdReader<P, T> {
+    /// Open a block tree file, validate it against `P` and `T`, and load its index.
+    ///
+    /// Fails with `Unsupported` if the leaves are compressed with a codec whose feature is
+    /// not enabled.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let mut buf = [0u8; HEADER_SIZE];
+        file.read_exact(&mut buf)?;
+        let header = BkdHeader::decode(&buf)?;
+        header.compression.check_available()?;
+        if header.point_size as usize != P::SIZE || header.data_size as usize != T::SIZE {
+            return Err(invalid_data(
+                "block tree record layout does not match the requested types",
+            ));
+        }
+
+        let dimensions = header.dimensions as usize;
+        let num_leaves = header.num_leaves as usize;
+        let mut bytes = vec![0u8; PackedIndex::encoded_len(dimensions, num_leaves)];
+        file.seek(SeekFrom::Start(header.index_offset))?;
+        file.read_exact(&mut bytes)?;
+        let index = PackedIndex::decode(&bytes, dimensions, num_leaves)?;
+
+        let dictionary = if header.dictionary_payloads {
+            let mut count = [0u8; 4];
+            file.read_exact(&mut count)?;
+            let count = u32::from_le_bytes(count) as usize;
+            let mut values = vec![0u8; count * T::SIZE];
+            file.read_exact(&mut values)?;
+            Some(PayloadDictionary {
+                ids: HashMap::new(),
+                values,
+                count,
+            })
+        } else {
+            None
+        };
+
+        Ok(BkdReader {
+            file,
+            header,
+            index,
+            block: Vec::new(),
+            compressed: Vec::new(),
+            encoded: Vec::new(),
+            dictionary,
+            selected: Vec::new(),
+            metrics: None,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Count the bytes decompressed from compressed leaves in `metrics`.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// The decoded file header.
+    pub fn header(&self) -> &BkdHeader {
+        &self.header
+    }
+
+    /// Number of distinct payloads, if the tree stores them in a dictionary.
+    pub fn distinct_payloads(&self) -> Option<usize> {
+        self.dictionary.as_ref().map(PayloadDictionary::len)
+    }
+
+    /// Number of indexed entries.
+    pub fn len(&self) -> u64 {
+        self.header.point_count
+    }
+
+    /// Check if the tree holds no entries.
+    pub fn is_empty(&self) -> bool {
+        self.header.point_count == 0
+    }
+
+    /// Per-dimension bounds of all entries, as `(min, max)`.
+    pub fn bounds(&self) -> (&[f64], &[f64]) {
+        (&self.index.min, &self.index.max)
+    }
+
+    /// The in-memory inner tree.
+    pub fn index(&self) -> &PackedIndex {
+        &self.index
+    }
+
+    /// Walk the tree, asking `visitor` to relate every cell and reading only the leaf
+    /// blocks of cells that are not outside the query.
+    pub fn intersect<V: IntersectVisitor<P, T>>(&mut self, visitor: &mut V) -> io::Result<()> {
+        self.intersect_leaves(visitor, |_| true)
+    }
+
+    /// `intersect`, skipping every leaf for which `leaf_filter` returns `false` without
+    /// reading it; pair with `LeafSummaries` to rule out leaves by their payloads.
+    pub fn intersect_leaves<V: IntersectVisitor<P, T>>(
+        &mut self,
+        visitor: &mut V,
+        mut leaf_filter: impl FnMut(usize) -> bool,
+    ) -> io::Result<()> {
+        for candidate in self.collect_candidates(|min, max| visitor.compare(min, max)) {
+            if leaf_filter(candidate.leaf) {
+                self.refine(&candidate, visitor)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Coarse phase of a search: the leaves whose cells `query` does not rule out, left to
+    /// right, with how each cell relates to it. Uses the in-memory index only.
+    ///
+    /// # Usage pattern:
+    /// - `candidates` for a cheap query, such as the bounding box of a polygon
+    /// - drop candidates by anything the caller knows: the exact shape against the cell,
+    ///   `LeafSummaries`, a budget of leaves to read
+    /// - `refine` the rest with a visitor running the exact per-entry test, or
+    ///   `read_block` them to test the entries directly
+    ///
+    /// `intersect` is both phases back to back.
+    pub fn candidates<Q: SpatialQuery<P>>(&self, query: &Q) -> Vec<LeafCandidate> {
+        self.collect_candidates(|min, max| query.relate(min, max))
+    }
+
+    fn collect_candidates(
+        &self,
+        compare: impl Fn(&[f64], &[f64]) -> Relation,
+    ) -> Vec<LeafCandidate> {
+        let mut candidates = Vec::new();
+        if self.is_empty() {
+            return candidates;
+        }
+        let mut stack = vec![(1usize, self.index.min.clone(), self.index.max.clone())];
+        while let Some((node, min, max)) = stack.pop() {
+            let relation = compare(&min, &max);
+            if relation == Relation::CellOutsideQuery {
+                continue;
+            }
+            if let Some(leaf) = self.index.leaf(node) {
+                candidates.push(LeafCandidate {
+                    leaf,
+                    relation,
+                    min,
+                    max,
+                });
+                continue;
+            }
+
+            let (dimension, split) = self.index.split(node);
+            let mut left_max = max.clone();
+            left_max[dimension] = left_max[dimension].min(split);
+            let mut right_min = min.clone();
+            right_min[dimension] = right_min[dimension].max(split);
+            stack.push((2 * node + 1, right_min, max));
+            stack.push((2 * node, min, left_max));
+        }
+        candidates
+    }
+
+    /// Refinement phase of a search: read the leaf of `candidate` and hand its entries to
+    /// `visitor`, as `intersect` would. Entries of a candidate inside the query go to
+    /// `visit` untested; the rest to `visit_point`.
+    pub fn refine<V: IntersectVisitor<P, T>>(
+        &mut self,
+        candidate: &LeafCandidate,
+        visitor: &mut V,
+    ) -> io::Result<()> {
+        self.visit_leaf(candidate.leaf, candidate.relation, visitor)
+    }
+
+    /// Read every entry of the leaf of `candidate`, for callers refining entries
+    /// themselves.
+    pub fn read_block(&mut self, candidate: &LeafCandidate) -> io::Result<LeafBlock<P, T>> {
+        self.leaf_block(candidate.leaf, candidate.min.clone(), candidate.max.clone())
+    }
+
+    fn leaf_block(
+        &mut self,
+        leaf: usize,
+        min: Vec<f64>,
+        max: Vec<f64>,
+    ) -> io::Result<LeafBlock<P, T>> {
+        let records = self.read_leaf(leaf)?;
+        let block = &self.block;
+        let points = (0..records.count)
+            .map(|i| P::decode(records.point(block, i)))
+            .collect();
+        let data = (0..records.count)
+            .map(|i| T::decode(records.data(block, i)))
+            .collect();
+        Ok(LeafBlock {
+            leaf,
+            min,
+            max,
+            points,
+            data,
+        })
+    }
+
+    /// Collect the payloads of all entries matching `query`.
+    pub fn search<Q: SpatialQuery<P>>(&mut self, query: &Q) -> io::Result<Vec<T>>
+    where
+        T: Clone,
+    {
+        let mut visitor = QueryVisitor::new(query);
+        self.intersect(&mut visitor)?;
+        Ok(visitor.results)
+    }
+
+    /// Collect the payloads of entries matching `query` that satisfy `predicate`, reading
+    /// only the leaves `leaf_filter` accepts.
+    ///
+    /// `leaf_filter` may only reject leaves holding no payload that satisfies `predicate`,
+    /// as `LeafSummaries` does; it is a shortcut, not a substitute for the predicate.
+    pub fn search_filtered<Q: SpatialQuery<P>>(
+        &mut self,
+        query: &Q,
+        leaf_filter: impl FnMut(usize) -> bool,
+        mut predicate: impl FnMut(&T) -> bool,
+    ) -> io::Result<Vec<T>>
+    where
+        T: Clone,
+    {
+        let mut visitor = QueryVisitor::new(query);
+        self.intersect_leaves(&mut visitor, leaf_filter)?;
+        let mut results = visitor.results;
+        results.retain(|data| predicate(data));
+        Ok(results)
+    }
+
+    /// Find the `k` entries closest to `target` under `metric`, nearest first.
+    ///
+    /// Branch and bound over cells: cells are expanded closest first, and the search stops
+    /// once the closest unexpanded cell is farther than the `k`-th best entry found so far,
+    /// since nothing inside it can improve the result. Both children of a split stay
+    /// candidates, so an entry just across a split plane from the target is still found.
+    /// Only the leaves of expanded cells are read.
+    ///
+    /// Ties are broken deterministically, in favor of the entry found first.
+    pub fn nearest_neighbors(
+        &mut self,
+        target: &[f64],
+        k: usize,
+        metric: &Metric,
+    ) -> io::Result<Vec<Neighbor<(P, T)>>> {
+        self.nearest_entries(target, k, metric, EntryShape::Point)
+    }
+
+    /// Find the `k` entries whose boxes are closest to `target` under `metric`, nearest
+    /// first, measuring entries as `EntryShape::Box` does. `target` holds one coordinate
+    /// per axis of the boxes.
+    pub fn nearest_boxes(
+        &mut self,
+        target: &[f64],
+        k: usize,
+        metric: &Metric,
+    ) -> io::Result<Vec<Neighbor<(P, T)>>> {
+        self.nearest_entries(target, k, metric, EntryShape::Box)
+    }
+
+    fn nearest_entries(
+        &mut self,
+        target: &[f64],
+        k: usize,
+        metric: &Metric,
+        shape: EntryShape,
+    ) -> io::Result<Vec<Neighbor<(P, T)>>> {
+        if k == 0 || self.is_empty() {
+            return Ok(Vec::new());
+        }
+        // Sorted by distance; equal distances keep the order they were found in
+        let mut best: Vec<(f64, P, T)> = Vec::with_capacity(k);
+        let mut seq = 0u64;
+        let mut cells = BinaryHeap::new();
+        let (min, max) = (self.index.min.clone(), self.index.max.clone());
+        let distance = shape.distance_to_cell(metric, target, &min, &max);
+        cells.push(Reverse(CellKey {
+            distance,
+            seq,
+            node: 1,
+            min,
+            max,
+        }));
+
+        while let Some(Reverse(cell)) = cells.pop() {
+            if best.len() == k && cell.distance > best[k - 1].0 {
+                break;
+            }
+            if let Some(leaf) = self.index.leaf(cell.node) {
+                let records = self.read_leaf(leaf)?;
+                for i in 0..records.count {
+                    let point = P::decode(records.point(&self.block, i));
+                    let distance = shape.distance(metric, &point, target);
+                    if best.len() == k && distance >= best[k - 1].0 {
+                        continue;
+                    }
+                    let at = best.partition_point(|entry| entry.0 <= distance);
+                    let data = T::decode(records.data(&self.block, i));
+                    best.insert(at, (distance, point, data));
+                    best.truncate(k);
+                }
+                continue;
+            }
+
+            let (dimension, split) = self.index.split(cell.node);
+            let mut left_max = cell.max.clone();
+            left_max[dimension] = left_max[dimension].min(split);
+            let mut right_min = cell.min.clone();
+            right_min[dimension] = right_min[dimension].max(split);
+            for (node, min, max) in [
+                (2 * cell.node, cell.min, left_max),
+                (2 * cell.node + 1, right_min, cell.max),
+            ] {
+                let distance = shape.distance_to_cell(metric, target, &min, &max);
+                if best.len() < k || distance <= best[k - 1].0 {
+                    seq += 1;
+                    cells.push(Reverse(CellKey {
+                        distance,
+                        seq,
+                        node,
+                        min,
+                        max,
+                    }));
+                }
+            }
+        }
+
+        Ok(best
+            .into_iter()
+            .map(|(distance, point, data)| Neighbor {
+                node: (point, data),
+                distance,
+            })
+            .collect())
+    }
+
+    /// Iterate over the leaf blocks, left to right, decoding each one's entries.
+    ///
+    /// Leaves are yielded in file order, so a full scan reads the file sequentially. Use it
+    /// for exports, statistics or scans that `intersect` cannot express; an empty tree has
+    /// no leaves.
+    pub fn leaves(&mut self) -> LeafIter<'_, P, T> {
+        let stack = if self.is_empty() {
+            Vec::new()
+        } else {
+            vec![(1, self.index.min.clone(), self.index.max.clone())]
+        };
+        LeafIter {
+            reader: self,
+            stack,
+        }
+    }
+
+    /// Read the block of leaf `leaf` into `self.block`, returning where its records lie.
+    fn read_leaf(&mut self, leaf: usize) -> io::Result<LeafRecords> {
+        self.file
+            .seek(SeekFrom::Start(self.index.leaf_offsets[leaf]))?;
+        let mut count = [0u8; 4];
+        self.file.read_exact(&mut count)?;
+        let count = u32::from_le_bytes(count) as usize;
+        if count > self.header.max_points_in_leaf as usize {
+            return Err(invalid_data("leaf block larger than the leaf size"));
+        }
+
+        let records = if self.header.columnar {
+            LeafRecords::columnar(count, self.header.dimensions as usize, P::SIZE, T::SIZE)
+        } else {
+            LeafRecords::rows(count, P::SIZE, T::SIZE)
+        };
+        let len = records.block_len();
+        let compression = self.header.compression;
+        if compression == Compression::None && self.dictionary.is_none() {
+            self.block.resize(len, 0);
+            self.file.read_exact(&mut self.block)?;
+            return Ok(records);
+        }
+
+        let mut size = [0u8; 4];
+        self.file.read_exact(&mut size)?;
+        self.compressed.resize(u32::from_le_bytes(size) as usize, 0);
+        self.file.read_exact(&mut self.compressed)?;
+        let Some(dictionary) = &self.dictionary else {
+            compression.decompress(&self.compressed, &mut self.block, len)?;
+            if let Some(metrics) = &self.metrics {
+                metrics.record_decompressed(len as u64);
+            }
+            return Ok(records);
+        };
+        // Columnar blocks end in their ids; row blocks interleave them with the points
+        let (prefix, point_size) = if records.is_columnar() {
+            (records.data, 0)
+        } else {
+            (0, P::SIZE)
+        };
+        let encoded = if compression == Compression::None {
+            &self.compressed
+        } else {
+            let max_len = prefix + count * (point_size + MAX_VARINT_LEN);
+            compression.decompress_within(&self.compressed, &mut self.encoded, max_len)?;
+            if let Some(metrics) = &self.metrics {
+                metrics.record_decompressed(self.encoded.len() as u64);
+            }
+            &self.encoded
+        };
+        dictionary.expand(encoded, prefix, count, point_size, T::SIZE, &mut self.block)?;
+        Ok(records)
+    }
+
+    fn visit_leaf<V: IntersectVisitor<P, T>>(
+        &mut self,
+        leaf: usize,
+        mut relation: Relation,
+        visitor: &mut V,
+    ) -> io::Result<()> {
+        let records = self.read_leaf(leaf)?;
+        let mut filtered = false;
+        if relation == Relation::CellCrossesQuery && records.is_columnar() && records.count > 0 {
+            // The bounds of the entries are tighter than those of the cell
+            let (min, max): (Vec<f64>, Vec<f64>) = (0..records.dimensions)
+                .map(|dim| records.bounds(&self.block, dim))
+                .unzip();
+            relation = visitor.compare(&min, &max);
+            match relation {
+                Relation::CellOutsideQuery => return Ok(()),
+                Relation::CellInsideQuery => {}
+                Relation::CellCrossesQuery => {
+                    self.filter_columns(&records, visitor);
+                    filtered = true;
+                }
+            }
+        }
+
+        for i in 0..records.count {
+            if filtered && !self.selected[i] {
+                continue;
+            }
+            let data = T::decode(records.data(&self.block, i));
+            if relation == Relation::CellInsideQuery {
+                // Inside cells never need the coordinates
+                visitor.visit(&data);
+            } else {
+                visitor.visit_point(&P::decode(records.point(&self.block, i)), &data);
+            }
+        }
+        Ok(())
+    }
+
+    /// Mark in `self.selected` the entries of a columnar leaf within every dimension range
+    /// of `visitor`, reading only the columns whose bounds the range cuts.
+    fn filter_columns<V: IntersectVisitor<P, T>>(&mut self, records: &LeafRecords, visitor: &V) {
+        self.selected.clear();
+        self.selected.resize(records.count, true);
+        for dim in 0..records.dimensions {
+            let (low, high) = visitor.dimension_range(dim);
+            let (column_min, column_max) = records.bounds(&self.block, dim);
+            if low <= column_min && column_max <= high {
+                continue;
+            }
+            // Branch-free over a contiguous column, so the compiler can vectorize it
+            let column = records.column(&self.block, dim);
+            for (keep, value) in self.selected.iter_mut().zip(column.chunks_exact(8)) {
+                let value = f64::decode(value);
+                *keep &= low <= value && value <= high;
+            }
+        }
+    }
+}
+
+/// Where the records of a decoded leaf block lie in `BkdReader::block`.
+#[derive(Debug, Clone, Copy)]
+struct LeafRecords {
+    count: usize,
+    /// Dimensions of the columns leading a columnar block; zero for a row block.
+    dimensions: usize,
+    point_size: usize,
+    data_size: usize,
+    /// Offset and stride of the points.
+    points: usize,
+    point_stride: usize,
+    /// Offset and stride of the payloads.
+    data: usize,
+    data_stride: usize,
+}
+
+impl LeafRecords {
+    /// Records of `point | data`.
+    fn rows(count: usize, point_size: usize, data_size: usize) -> Self {
+        let stride = point_size + data_size;
+        LeafRecords {
+            count,
+            dimensions: 0,
+            point_size,
+            data_size,
+            points: 0,
+            point_stride: stride,
+            data: point_size,
+            data_stride: stride,
+        }
+    }
+
+    /// Column bounds, columns, points, then payloads.
+    fn columnar(count: usize, dimensions: usize, point_size: usize, data_size: usize) -> Self {
+        let points = dimensions * 16 + dimensions * count * 8;
+        LeafRecords {
+            count,
+            dimensions,
+            point_size,
+            data_size,
+            points,
+            point_stride: point_size,
+            data: points + count * point_size,
+            data_stride: data_size,
+        }
+    }
+
+    fn is_columnar(&self) -> bool {
+        self.dimensions > 0
+    }
+
+    /// Length of the decoded block.
+    fn block_len(&self) -> usize {
+        if self.is_columnar() {
+            self.data + self.count * self.data_size
+        } else {
+            self.count * self.point_stride
+        }
+    }
+
+    fn point<'b>(&self, block: &'b [u8], i: usize) -> &'b [u8] {
+        let at = self.points + i * self.point_stride;
+        &block[at..at + self.point_size]
+    }
+
+    fn data<'b>(&self, block: &'b [u8], i: usize) -> &'b [u8] {
+        let at = self.data + i * self.data_stride;
+        &block[at..at + self.data_size]
+    }
+
+    /// Smallest and largest value of column `dim`.
+    fn bounds(&self, block: &[u8], dim: usize) -> (f64, f64) {
+        let at = dim * 16;
+        (
+            f64::decode(&block[at..at + 8]),
+            f64::decode(&block[at + 8..at + 16]),
+        )
+    }
+
+    /// Values of every entry in `dim`, as little-endian `f64`s.
+    fn column<'b>(&self, block: &'b [u8], dim: usize) -> &'b [u8] {
+        let at = self.dimensions * 16 + dim * self.count * 8;
+        &block[at..at + self.count * 8]
+    }
+}
+
+/// Cell awaiting expansion in `BkdReader::nearest_neighbors`, ordered by distance to the
+/// target, then by discovery so the search is deterministic.
+struct CellKey {
+    distance: f64,
+    seq: u64,
+    node: usize,
+    min: Vec<f64>,
+    max: Vec<f64>,
+}
+
+impl PartialEq for CellKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for CellKey {}
+
+impl PartialOrd for CellKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for CellKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance
+            .total_cmp(&other.distance)
+            .then(self.seq.cmp(&other.seq))
+    }
+}
+
+/// A leaf that may hold entries matching a query, from `BkdReader::candidates`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LeafCandidate {
+    /// Leaf number, from 0 at the left.
+    pub leaf: usize,
+    /// How the leaf's cell relates to the query: inside or crossing.
+    pub relation: Relation,
+    /// Per-dimension lower bounds of the leaf's cell.
+    pub min: Vec<f64>,
+    /// Per-dimension upper bounds of the leaf's cell.
+    pub max: Vec<f64>,
+}
+
+/// One leaf block of a block tree, as yielded by `BkdReader::leaves`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LeafBlock<P, T> {
+    /// Leaf number, from 0 at the left.
+    pub leaf: usize,
+    /// Per-dimension lower bounds of the leaf's cell: the splits above it, clamped to the
+    /// tree's bounds. Entries lie within the cell but need not touch its edges.
+    pub min: Vec<f64>,
+    /// Per-dimension upper bounds of the leaf's cell.
+    pub max: Vec<f64>,
+    /// Points of the leaf's entries, in block order.
+    pub points: Vec<P>,
+    /// Payloads, parallel to `points`.
+    pub data: Vec<T>,
+}
+
+impl<P, T> LeafBlock<P, T> {
+    /// Number of entries in the block.
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    /// Check if the block holds no entries.
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+}
+
+/// Iterator over the leaf blocks of a `BkdReader`, from `BkdReader::leaves`.
+///
+/// Stops after the first error.
+pub struct LeafIter<'r, P, T> {
+    reader: &'r mut BkdReader<P, T>,
+    /// Nodes still to visit with their cell bounds; the top is the next in left-to-right
+    /// order.
+    stack: Vec<(usize, Vec<f64>, Vec<f64>)>,
+}
+
+impl<P: Point + FixedCodec, T: FixedCodec> Iterator for LeafIter<'_, P, T> {
+    type Item = io::Result<LeafBlock<P, T>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((node, min, max)) = self.stack.pop() {
+            let index = &self.reader.index;
+            if let Some(leaf) = index.leaf(node) {
+                let block = self.reader.leaf_block(leaf, min, max);
+                if block.is_err() {
+                    self.stack.clear();
+                }
+                return Some(block);
+            }
+
+            let (dimension, split) = index.split(node);
+            let mut left_max = max.clone();
+            left_max[dimension] = left_max[dimension].min(split);
+            let mut right_min = min.clone();
+            right_min[dimension] = right_min[dimension].max(split);
+            self.stack.push((2 * node + 1, right_min, max));
+            self.stack.push((2 * node, min, left_max));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::{Circle, RangeQuery};
+    use crate::spatial::BoundingBox;
+
+    fn entries(count: u32) -> Vec<(BoundingBox, u32)> {
+        (0..count)
+            .map(|i| {
+                let x = ((i * 37) % 211) as f64;
+                let y = ((i * 53) % 197) as f64;
+                (BoundingBox::new(x, y, x + 3.0, y + 2.0), i)
+            })
+            .collect()
+    }
+
+    fn write(path: &Path, entries: &[(BoundingBox, u32)], options: BkdWriterOptions) {
+        let mut writer = BkdWriter::new(options);
+        for (point, data) in entries {
+            writer.add(point.clone(), *data).unwrap();
+        }
+        writer.finish(path).unwrap();
+    }
+
+    #[test]
+    fn test_block_tree_matches_brute_force() {
+        let dir = tempfile::tempdir().unwrap();
+        let entries = entries(3000);
+        let queries = [
+            BoundingBox::new(0.0, 0.0, 50.0, 50.0),
+            BoundingBox::new(100.0, 20.0, 101.0, 180.0),
+            BoundingBox::new(-10.0, -10.0, 500.0, 500.0),
+            BoundingBox::new(300.0, 300.0, 400.0, 400.0),
+        ];
+
+        // The last limit is below the leaf size, so spilled cells reach leaf level
+        for (name, limit) in [
+            ("memory.bkd", 1 << 20),
+            ("spilled.bkd", 700),
+            ("tiny.bkd", 10),
+        ] {
+            let path = dir.path().join(name);
+            let options = BkdWriterOptions {
+                max_points_in_leaf: 64,
+                max_entries_in_memory: limit,
+                temp_dir: Some(dir.path().to_path_buf()),
+                ..BkdWriterOptions::default()
+            };
+            write(&path, &entries, options);
+
+            let mut reader = BkdReader::<BoundingBox, u32>::open(&path).unwrap();
+            assert_eq!(reader.len(), 3000);
+            assert_eq!(reader.header().num_leaves, 64);
+            for query in &queries {
+                let mut results = reader.search(query).unwrap();
+                results.sort();
+                let expected: Vec<u32> = entries
+                    .iter()
+                    .filter(|(point, _)| query.matches(point))
+                    .map(|(_, data)| *data)
+                    .collect();
+                assert_eq!(results, expected);
+            }
+        }
+        // Only the tree files remain; spill files are cleaned up
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn test_block_tree_inside_cells_skip_point_checks() {
+        struct Counting {
+            inside: usize,
+            checked: usize,
+        }
+        impl IntersectVisitor<BoundingBox, u32> for Counting {
+            fn visit(&mut self, _data: &u32) {
+                self.inside += 1;
+            }
+            fn visit_point(&mut self, _point: &BoundingBox, _data: &u32) {
+                self.checked += 1;
+            }
+            fn compare(&self, min: &[f64], max: &[f64]) -> Relation {
+                BoundingBox::new(-1e9, -1e9, 1e9, 1e9).relate(min, max)
+            }
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.bkd");
+        write(&path, &entries(1000), BkdWriterOptions::default());
+        let mut reader = BkdReader::<BoundingBox, u32>::open(&path).unwrap();
+        let mut visitor = Counting {
+            inside: 0,
+            checked: 0,
+        };
+        reader.intersect(&mut visitor).unwrap();
+        assert_eq!((visitor.inside, visitor.checked), (1000, 0));
+    }
+
+    #[test]
+    fn test_compressed_leaves_read_like_plain_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let entries = entries(3000);
+        let query = BoundingBox::new(20.0, 20.0, 90.0, 70.0);
+        let plain = dir.path().join("plain.bkd");
+        write(&plain, &entries, BkdWriterOptions::default());
+        let mut reader = BkdReader::<BoundingBox, u32>::open(&plain).unwrap();
+        assert_eq!(reader.header().version, 1);
+        let mut expected = reader.search(&query).unwrap();
+        expected.sort_unstable();
+        let plain_size = std::fs::metadata(&plain).unwrap().len();
+
+        for compression in [Compression::Lz4, Compression::Zstd { level: 3 }] {
+            let path = dir.path().join("compressed.bkd");
+            let options = BkdWriterOptions {
+                max_points_in_leaf: 64,
+                compression,
+                ..BkdWriterOptions::default()
+            };
+            let mut writer = BkdWriter::new(options);
+            for (point, data) in &entries {
+                writer.add(point.clone(), *data).unwrap();
+            }
+            if !compression.is_available() {
+                let error = writer.finish(&path).unwrap_err();
+                assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+                continue;
+            }
+            let header = writer.finish(&path).unwrap();
+            assert_eq!((header.version, header.compression), (2, compression));
+            assert!(std::fs::metadata(&path).unwrap().len() < plain_size);
+
+            let mut reader = BkdReader::<BoundingBox, u32>::open(&path).unwrap();
+            assert_eq!(reader.header().compression, compression);
+            let mut found = reader.search(&query).unwrap();
+            found.sort_unstable();
+            assert_eq!(found, expected);
+            let scanned: usize = reader.leaves().map(|leaf| leaf.unwrap().len()).sum();
+            assert_eq!(scanned, 3000);
+
+            // Every leaf read is decompressed and counted, at least the records of the matches
+            let metrics = Arc::new(Metrics::new());
+            let mut reader = BkdReader::<BoundingBox, u32>::open(&path)
+                .unwrap()
+                .with_metrics(metrics.clone());
+            assert_eq!(reader.search(&query).unwrap().len(), expected.len());
+            let decompressed = metrics.snapshot().bytes_decompressed;
+            assert!(decompressed >= (expected.len() * (BoundingBox::SIZE + 4)) as u64);
+            reader.search(&query).unwrap();
+            assert_eq!(metrics.snapshot().bytes_decompressed, 2 * decompressed);
+        }
+
+        // A header naming a codec this build lacks, or none at all
+        let mut bytes = std::fs::read(&plain).unwrap();
+        bytes[56] = 9;
+        std::fs::write(&plain, &bytes).unwrap();
+        let error = BkdReader::<BoundingBox, u32>::open(&plain).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_dictionary_payloads_read_like_plain_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        // Seven categories, far fewer than entries
+        let entries: Vec<(BoundingBox, u64)> = entries(3000)
+            .into_iter()
+            .map(|(point, data)| (point, u64::from(data % 7) << 40))
+            .collect();
+        let query = BoundingBox::new(20.0, 20.0, 90.0, 70.0);
+        let write_with = |path: &Path, options: BkdWriterOptions| {
+            let mut writer = BkdWriter::new(options);
+            for (point, data) in &entries {
+                writer.add(point.clone(), *data).unwrap();
+            }
+            writer.finish(path)
+        };
+        let plain = dir.path().join("plain.bkd");
+        write_with(&plain, BkdWriterOptions::default()).unwrap();
+        let mut reader = BkdReader::<BoundingBox, u64>::open(&plain).unwrap();
+        assert_eq!(reader.distinct_payloads(), None);
+        let mut expected = reader.search(&query).unwrap();
+        expected.sort_unstable();
+        let plain_size = std::fs::metadata(&plain).unwrap().len();
+
+        for compression in [Compression::None, Compression::Lz4] {
+            if !compression.is_available() {
+                continue;
+            }
+            let path = dir.path().join("dictionary.bkd");
+            let options = BkdWriterOptions {
+                max_points_in_leaf: 64,
+                compression,
+                dictionary_payloads: true,
+                ..BkdWriterOptions::default()
+            };
+            let header = write_with(&path, options).unwrap();
+            assert_eq!(header.version, 3);
+            assert!(header.dictionary_payloads);
+            assert!(std::fs::metadata(&path).unwrap().len() < plain_size);
+
+            let mut reader = BkdReader::<BoundingBox, u64>::open(&path).unwrap();
+            assert_eq!(reader.distinct_payloads(), Some(7));
+            let mut found = reader.search(&query).unwrap();
+            found.sort_unstable();
+            assert_eq!(found, expected);
+            let scanned: usize = reader.leaves().map(|leaf| leaf.unwrap().len()).sum();
+            assert_eq!(scanned, 3000);
+        }
+
+        // An id past the end of the dictionary
+        let path = dir.path().join("dictionary.bkd");
+        let options = BkdWriterOptions {
+            dictionary_payloads: true,
+            ..BkdWriterOptions::default()
+        };
+        write_with(&path, options).unwrap();
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[HEADER_SIZE + 8 + BoundingBox::SIZE] = 0x7f;
+        std::fs::write(&path, &bytes).unwrap();
+        let mut reader = BkdReader::<BoundingBox, u64>::open(&path).unwrap();
+        let error = reader.leaves().next().unwrap().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let mut buf = Vec::new();
+        for value in [0, 127, 128, 300, u32::MAX] {
+            write_varint(&mut buf, value);
+        }
+        let mut input = &buf[..];
+        for value in [0, 127, 128, 300, u32::MAX] {
+            assert_eq!(read_varint(&mut input).unwrap(), value);
+        }
+        assert!(input.is_empty() && read_varint(&mut input).is_err());
+    }
+
+    #[test]
+    fn test_columnar_leaves_filter_before_decoding_points() {
+        /// Collects like `QueryVisitor`, counting the entries handed to `visit_point`.
+        struct Checking {
+            query: RangeQuery,
+            results: Vec<u32>,
+            checked: usize,
+        }
+        impl IntersectVisitor<BoundingBox, u32> for Checking {
+            fn visit(&mut self, data: &u32) {
+                self.results.push(*data);
+            }
+            fn visit_point(&mut self, point: &BoundingBox, data: &u32) {
+                self.checked += 1;
+                if self.query.matches(point) {
+                    self.results.push(*data);
+                }
+            }
+            fn compare(&self, min: &[f64], max: &[f64]) -> Relation {
+                SpatialQuery::<BoundingBox>::relate(&self.query, min, max)
+            }
+            fn dimension_range(&self, dim: usize) -> (f64, f64) {
+                SpatialQuery::<BoundingBox>::dimension_range(&self.query, dim)
+            }
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let entries = entries(3000);
+        let plain = dir.path().join("plain.bkd");
+        write(&plain, &entries, BkdWriterOptions::default());
+        let mut plain = BkdReader::<BoundingBox, u32>::open(&plain).unwrap();
+        let scan = |reader: &mut BkdReader<BoundingBox, u32>, query: &RangeQuery| {
+            let mut visitor = Checking {
+                query: query.clone(),
+                results: Vec::new(),
+                checked: 0,
+            };
+            reader.intersect(&mut visitor).unwrap();
+            visitor.results.sort_unstable();
+            (visitor.results, visitor.checked)
+        };
+        // Only ymin is constrained: the other three columns are never read
+        let partial = RangeQuery::new().with_range(1, 40.0, 60.0);
+        let full = RangeQuery::new()
+            .with_range(0, 20.0, 90.0)
+            .with_range(1, 20.0, 70.0)
+            .with_range(2, 20.0, 93.0)
+            .with_range(3, 20.0, 72.0);
+
+        for (compression, dictionary_payloads) in [
+            (Compression::None, false),
+            (Compression::None, true),
+            (Compression::Lz4, true),
+        ] {
+            if !compression.is_available() {
+                continue;
+            }
+            let path = dir.path().join("columnar.bkd");
+            let options = BkdWriterOptions {
+                max_points_in_leaf: 64,
+                compression,
+                dictionary_payloads,
+                columnar: true,
+                ..BkdWriterOptions::default()
+            };
+            write(&path, &entries, options);
+            let mut reader = BkdReader::<BoundingBox, u32>::open(&path).unwrap();
+            assert_eq!(reader.header().version, VERSION);
+            assert!(reader.header().columnar);
+
+            for query in [&partial, &full] {
+                let (expected, plain_checked) = scan(&mut plain, query);
+                let (found, checked) = scan(&mut reader, query);
+                assert_eq!(found, expected);
+                // The ranges describe these queries exactly, so only matches are decoded
+                assert!(checked <= found.len() && checked < plain_checked);
+            }
+            let bbox = BoundingBox::new(20.0, 20.0, 90.0, 70.0);
+            let mut found = reader.search(&bbox).unwrap();
+            found.sort_unstable();
+            let mut expected = plain.search(&bbox).unwrap();
+            expected.sort_unstable();
+            assert_eq!(found, expected);
+            let scanned: Vec<(BoundingBox, u32)> = reader
+                .leaves()
+                .flat_map(|leaf| {
+                    let leaf = leaf.unwrap();
+                    leaf.points.into_iter().zip(leaf.data)
+                })
+                .collect();
+            assert_eq!(scanned.len(), 3000);
+            let nearest = |reader: &mut BkdReader<BoundingBox, u32>| {
+                reader
+                    .nearest_neighbors(&[100.0; 4], 5, &Metric::euclidean())
+                    .unwrap()
+                    .into_iter()
+                    .map(|neighbor| neighbor.node.1)
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(nearest(&mut reader), nearest(&mut plain));
+        }
+    }
+
+    #[test]
+    fn test_candidates_then_refinement_match_a_search() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.bkd");
+        let options = BkdWriterOptions {
+            max_points_in_leaf: 32,
+            ..BkdWriterOptions::default()
+        };
+        // Points, as boxes of no size: all four dimensions bound the same two coordinates
+        let points: Vec<(BoundingBox, u32)> = entries(3000)
+            .into_iter()
+            .map(|(point, data)| {
+                (
+                    BoundingBox::new(point.xmin, point.ymin, point.xmin, point.ymin),
+                    data,
+                )
+            })
+            .collect();
+        write(&path, &points, options);
+        let mut reader = BkdReader::<BoundingBox, u32>::open(&path).unwrap();
+        let circle = Circle::new(100.0, 100.0, 40.0);
+        let mut expected = reader.search(&circle).unwrap();
+        expected.sort_unstable();
+
+        // Coarse phase on the bounding box, then the disc against the area each cell's
+        // points can lie in, which the query's per-dimension ranges cannot express
+        let candidates = reader.candidates(&circle.bounding_box());
+        assert!(
+            candidates
+                .windows(2)
+                .all(|pair| pair[0].leaf < pair[1].leaf)
+        );
+        let reaches_disc = |candidate: &&LeafCandidate| {
+            let (min, max) = (&candidate.min, &candidate.max);
+            let area = BoundingBox::new(
+                min[0].max(min[2]),
+                min[1].max(min[3]),
+                max[0].min(max[2]),
+                max[1].min(max[3]),
+            );
+            circle.distance_to_box(&area) <= 40.0
+        };
+        let kept: Vec<&LeafCandidate> = candidates.iter().filter(reaches_disc).collect();
+        assert!(kept.len() < candidates.len());
+
+        let mut visitor = QueryVisitor::new(&circle);
+        for candidate in &kept {
+            reader.refine(candidate, &mut visitor).unwrap();
+        }
+        let mut refined = visitor.results;
+        refined.sort_unstable();
+        assert_eq!(refined, expected);
+
+        // Or test the entries of each block directly
+        let mut tested = Vec::new();
+        for candidate in &kept {
+            let block = reader.read_block(candidate).unwrap();
+            assert_eq!((block.leaf, &block.min), (candidate.leaf, &candidate.min));
+            for (point, &data) in block.points.iter().zip(&block.data) {
+                if circle.matches(point) {
+                    tested.push(data);
+                }
+            }
+        }
+        tested.sort_unstable();
+        assert_eq!(tested, expected);
+
+        let everything = reader.candidates(&BoundingBox::new(-1.0, -1.0, 300.0, 300.0));
+        assert_eq!(everything.len(), reader.index().num_leaves());
+        assert!(
+            everything
+                .iter()
+                .all(|candidate| candidate.relation == Relation::CellInsideQuery)
+        );
+    }
+
+    #[test]
+    fn test_packed_index_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.bkd");
+        let options = BkdWriterOptions {
+            max_points_in_leaf: 16,
+            ..BkdWriterOptions::default()
+        };
+        let entries = entries(1000);
+        write(&path, &entries, options);
+        let reader = BkdReader::<BoundingBox, u32>::open(&path).unwrap();
+        let index = reader.index();
+
+        assert_eq!(index.num_leaves(), 64);
+        assert_eq!(index.leaf(1), None);
+        assert_eq!(index.leaf(63), None);
+        assert_eq!(index.leaf(64), Some(0));
+        assert_eq!(index.leaf_offset(0), HEADER_SIZE as u64);
+        // 63 splits at 9 bytes, 64 leaf offsets, 4 dimensions of bounds
+        assert_eq!(index.memory_usage(), 63 * 9 + 64 * 8 + 8 * 8);
+
+        // The root split halves the entries on its dimension
+        let (dimension, value) = index.split(1);
+        let below = entries
+            .iter()
+            .filter(|(point, _)| point.get_dimension(dimension) < value)
+            .count();
+        assert!(below <= 500);
+        assert!(entries.len() - below >= 500);
+    }
+
+    #[test]
+    fn test_block_tree_empty_and_tiny() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.bkd");
+        let query = BoundingBox::new(0.0, 0.0, 1000.0, 1000.0);
+
+        write(&path, &[], BkdWriterOptions::default());
+        let mut reader = BkdReader::<BoundingBox, u32>::open(&path).unwrap();
+        assert!(reader.is_empty());
+        assert!(reader.search(&query).unwrap().is_empty());
+
+        let options = BkdWriterOptions {
+            max_points_in_leaf: 1,
+            ..BkdWriterOptions::default()
+        };
+        write(&path, &entries(3), options);
+        let mut reader = BkdReader::<BoundingBox, u32>::open(&path).unwrap();
+        assert_eq!(reader.header().num_leaves, 4);
+        let mut results = reader.search(&query).unwrap();
+        results.sort();
+        assert_eq!(results, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_leaf_scan_covers_every_entry_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("points.bkd");
+        let entries = entries(1000);
+        let options = BkdWriterOptions {
+            max_points_in_leaf: 50,
+            ..BkdWriterOptions::default()
+        };
+        write(&path, &entries, options);
+
+        let mut reader = BkdReader::<BoundingBox, u32>::open(&path).unwrap();
+        let num_leaves = reader.index().num_leaves();
+        let leaves: Vec<LeafBlock<BoundingBox, u32>> =
+            reader.leaves().collect::<io::Result<_>>().unwrap();
+        assert_eq!(leaves.len(), num_leaves);
+
+        let mut seen = Vec::new();
+        for (position, block) in leaves.iter().enumerate() {
+            assert_eq!(block.leaf, position);
+            assert!(block.len() <= 50);
+            for (point, &data) in block.points.iter().zip(&block.data) {
+                for dim in 0..4 {
+                    let value = point.get_dimension(dim);
+                    assert!(block.min[dim] <= value && value <= block.max[dim]);
+                }
+                assert_eq!(point, &entries[data as usize].0);
+                seen.push(data);
+            }
+        }
+        seen.sort_unstable();
+        assert_eq!(seen, (0..1000).collect::<Vec<u32>>());
+
+        // Scanning does not disturb later queries
+        let query = BoundingBox::new(0.0, 0.0, 30.0, 30.0);
+        let expected = entries
+            .iter()
+            .filter(|(point, _)| query.matches(point))
+            .count();
+        assert_eq!(reader.search(&query).unwrap().len(), expected);
+    }
+
+    #[test]
+    fn test_leaf_scan_of_empty_tree() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("empty.bkd");
+        write(&path, &[], BkdWriterOptions::default());
+        let mut reader = BkdReader::<BoundingBox, u32>::open(&path).unwrap();
+        assert_eq!(reader.leaves().count(), 0);
+    }
+
+    #[test]
+    fn test_nearest_neighbors_match_brute_force() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("points.bkd");
+        let entries = entries(2000);
+        let options = BkdWriterOptions {
+            max_points_in_leaf: 32,
+            ..BkdWriterOptions::default()
+        };
+        write(&path, &entries, options);
+        let mut reader = BkdReader::<BoundingBox, u32>::open(&path).unwrap();
+
+        let targets = [
+            [50.0, 50.0, 53.0, 52.0],
+            [0.0, 0.0, 0.0, 0.0],
+            [-100.0, 300.0, -90.0, 310.0],
+            // On a split plane, where a one-sided descent misses neighbors across it
+            [105.5, 98.0, 108.5, 100.0],
+        ];
+        let metrics = [
+            Metric::euclidean(),
+            Metric::manhattan(),
+            Metric::chebyshev().with_weights(vec![1.0, 2.0, 0.0, 0.5]),
+        ];
+        for target in &targets {
+            for metric in &metrics {
+                for k in [1, 7, 100] {
+                    let mut expected: Vec<f64> = entries
+                        .iter()
+                        .map(|(point, _)| metric.distance(point, target))
+                        .collect();
+                    expected.sort_by(f64::total_cmp);
+                    expected.truncate(k);
+
+                    let neighbors = reader.nearest_neighbors(target, k, metric).unwrap();
+                    let distances: Vec<f64> = neighbors.iter().map(|n| n.distance).collect();
+                    assert_eq!(distances, expected);
+                    for neighbor in &neighbors {
+                        let (point, data) = &neighbor.node;
+                        assert_eq!(point, &entries[*data as usize].0);
+                    }
+                }
+            }
+        }
+
+        // Measured to the boxes, from targets with one coordinate per axis
+        for target in [[50.0, 50.0], [106.0, 99.0], [-10.0, 250.0]] {
+            let metric = Metric::euclidean();
+            let mut expected: Vec<f64> = entries
+                .iter()
+                .map(|(point, _)| metric.distance_to_box(point, &target))
+                .collect();
+            expected.sort_by(f64::total_cmp);
+            expected.truncate(12);
+            let neighbors = reader.nearest_boxes(&target, 12, &metric).unwrap();
+            let distances: Vec<f64> = neighbors.iter().map(|n| n.distance).collect();
+            assert_eq!(distances, expected);
+        }
+
+        assert!(
+            reader
+                .nearest_neighbors(&targets[0], 0, &Metric::euclidean())
+                .unwrap()
+                .is_empty()
+        );
+        let all = reader
+            .nearest_neighbors(&targets[0], 5000, &Metric::euclidean())
+            .unwrap();
+        assert_eq!(all.len(), 2000);
+    }
+
+    #[test]
+    fn test_nearest_neighbors_of_empty_tree() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("empty.bkd");
+        write(&path, &[], BkdWriterOptions::default());
+        let mut reader = BkdReader::<BoundingBox, u32>::open(&path).unwrap();
+        let neighbors = reader
+            .nearest_neighbors(&[0.0; 4], 3, &Metric::euclidean())
+            .unwrap();
+        assert!(neighbors.is_empty());
+    }
+
+    #[test]
+    fn test_adaptive_leaves_shrink_in_clusters_and_grow_in_sparse_regions() {
+        let dir = tempfile::tempdir().unwrap();
+        // A dense city in the corner of a sparsely covered map
+        let mut entries: Vec<(BoundingBox, u32)> = (0..2000u32)
+            .map(|i| {
+                let x = ((i * 37) % 101) as f64 * 0.1;
+                let y = ((i * 53) % 97) as f64 * 0.1;
+                (BoundingBox::new(x, y, x + 0.05, y + 0.05), i)
+            })
+            .collect();
+        entries.extend((0..500u32).map(|i| {
+            let x = 20.0 + ((i * 71) % 983) as f64;
+            let y = ((i * 43) % 991) as f64;
+            (BoundingBox::new(x, y, x + 1.0, y + 1.0), 2000 + i)
+        }));
+        let in_city = |point: &BoundingBox| point.xmax <= 20.0;
+
+        let mut readers = Vec::new();
+        for (name, limit, leaf_sizing) in [
+            ("uniform.bkd", 1 << 20, LeafSizing::Uniform),
+            (
+                "adaptive.bkd",
+                1 << 20,
+                LeafSizing::Adaptive { extra_levels: 2 },
+            ),
+            ("spilled.bkd", 700, LeafSizing::Adaptive { extra_levels: 2 }),
+        ] {
+            let path = dir.path().join(name);
+            let options = BkdWriterOptions {
+                max_points_in_leaf: 64,
+                max_entries_in_memory: limit,
+                temp_dir: Some(dir.path().to_path_buf()),
+                leaf_sizing,
+                ..BkdWriterOptions::default()
+            };
+            write(&path, &entries, options);
+            readers.push(BkdReader::<BoundingBox, u32>::open(&path).unwrap());
+        }
+        assert_eq!(readers[0].header().num_leaves, 64);
+        assert_eq!(readers[1].header().num_leaves, 256);
+
+        let queries = [
+            BoundingBox::new(2.0, 2.0, 2.6, 2.6),
+            BoundingBox::new(5.0, -1.0, 500.0, 300.0),
+            BoundingBox::new(-10.0, -10.0, 2000.0, 2000.0),
+        ];
+        for reader in &mut readers {
+            for query in &queries {
+                let mut results = reader.search(query).unwrap();
+                results.sort();
+                let expected: Vec<u32> = entries
+                    .iter()
+                    .filter(|(point, _)| query.matches(point))
+                    .map(|(_, data)| *data)
+                    .collect();
+                assert_eq!(results, expected);
+            }
+            let target = [500.0, 500.0, 500.0, 500.0];
+            let found: Vec<f64> = reader
+                .nearest_neighbors(&target, 5, &Metric::euclidean())
+                .unwrap()
+                .into_iter()
+                .map(|neighbor| neighbor.distance)
+                .collect();
+            let mut expected: Vec<f64> = entries
+                .iter()
+                .map(|(point, _)| Metric::euclidean().distance(point, &target))
+                .collect();
+            expected.sort_by(f64::total_cmp);
+            assert_eq!(found, expected[..5]);
+        }
+
+        // Mean size of the non-empty leaves holding the city, and of those holding the rest
+        let leaf_sizes = |reader: &mut BkdReader<BoundingBox, u32>| {
+            let (mut city, mut rest) = (Vec::new(), Vec::new());
+            for block in reader.leaves() {
+                let block = block.unwrap();
+                match block.points.first() {
+                    Some(point) if in_city(point) => city.push(block.len()),
+                    Some(_) => rest.push(block.len()),
+                    None => {}
+                }
+            }
+            let mean = |sizes: &[usize]| sizes.iter().sum::<usize>() as f64 / sizes.len() as f64;
+            (mean(&city), mean(&rest))
+        };
+        let (uniform_city, uniform_rest) = leaf_sizes(&mut readers[0]);
+        let (adaptive_city, adaptive_rest) = leaf_sizes(&mut readers[1]);
+        assert!(adaptive_city < uniform_city);
+        assert!(adaptive_rest > uniform_rest);
+        assert!(adaptive_rest > 2.0 * adaptive_city);
+
+        // A small query in the city reads fewer entries
+        let read = |reader: &mut BkdReader<BoundingBox, u32>| -> usize {
+            reader
+                .candidates(&queries[0])
+                .iter()
+                .map(|candidate| reader.read_block(candidate).unwrap().len())
+                .sum()
+        };
+        assert!(read(&mut readers[1]) < read(&mut readers[0]));
+    }
+}
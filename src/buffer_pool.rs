@@ -0,0 +1,568 @@
+//! Shared page cache for node files, with pinning, dirty tracking and clock eviction.
+
+use crate::codec::FixedCodec;
+use crate::metrics::Metrics;
+use crate::node_file::{HEADER_SIZE, NO_NODE, NodeFileHeader, NodeRecord};
+use crate::sync::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// Default page size, matching the usual OS page.
+pub const DEFAULT_PAGE_SIZE: usize = 4096;
+
+/// Storage a `BufferPool` reads pages from and writes them back to.
+///
+/// Implemented for `File`. Wrappers transform pages on their way to disk, as
+/// `EncryptedFile` (feature `encryption`) does.
+pub trait PageStorage: Send + Sync {
+    /// Length of the stored bytes.
+    fn len(&self) -> io::Result<u64>;
+
+    /// Fill `buf` with the bytes at `offset`, all of which must be stored.
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()>;
+
+    /// Write all of `buf` at `offset`.
+    fn write_all_at(&self, buf: &[u8], offset: u64) -> io::Result<()>;
+
+    /// Make every completed write durable.
+    fn sync_data(&self) -> io::Result<()>;
+}
+
+impl PageStorage for File {
+    fn len(&self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        read_exact_at(self, buf, offset)
+    }
+
+    fn write_all_at(&self, buf: &[u8], offset: u64) -> io::Result<()> {
+        write_all_at(self, buf, offset)
+    }
+
+    fn sync_data(&self) -> io::Result<()> {
+        File::sync_data(self)
+    }
+}
+
+/// Fixed number of file pages cached in memory and shared by every reader and writer.
+///
+/// # Architecture
+/// - The file is divided into pages of `page_size` bytes; the pool holds up to `capacity`
+///   of them in frames
+/// - `pin` returns a `PinnedPage` guard; a pinned frame is never evicted, and dropping the
+///   guard unpins it
+/// - Writing through a guard marks its frame dirty; dirty frames are written back when
+///   evicted or on `flush`
+/// - Eviction uses the clock algorithm: a hand sweeps the frames, clearing the reference
+///   bit of recently used ones and evicting the first unpinned frame whose bit is clear
+///
+/// Page misses are read while holding the pool's lock, so concurrent misses are serialized
+/// while hits only wait for bookkeeping. Pages past the end of the file read as zeros and
+/// are never written back past it; the pool does not grow files.
+pub struct BufferPool {
+    file: Box<dyn PageStorage>,
+    file_len: u64,
+    page_size: usize,
+    state: Mutex<PoolState>,
+    metrics: Option<Arc<Metrics>>,
+}
+
+struct PoolState {
+    frames: Vec<Frame>,
+    pages: HashMap<u64, usize>,
+    hand: usize,
+}
+
+struct Frame {
+    page: Option<u64>,
+    data: Arc<RwLock<Box<[u8]>>>,
+    pins: usize,
+    dirty: bool,
+    referenced: bool,
+}
+
+impl BufferPool {
+    /// Create a pool of `capacity` pages over an open file. Open the file for writing too
+    /// if pages will be modified.
+    ///
+    /// # Panics
+    /// Panics if `capacity` or `page_size` is zero.
+    pub fn new(file: File, capacity: usize, page_size: usize) -> io::Result<Self> {
+        Self::from_storage(file, capacity, page_size)
+    }
+
+    /// Create a pool of `capacity` pages over any `PageStorage`.
+    ///
+    /// # Panics
+    /// Panics if `capacity` or `page_size` is zero.
+    pub fn from_storage<S: PageStorage + 'static>(
+        storage: S,
+        capacity: usize,
+        page_size: usize,
+    ) -> io::Result<Self> {
+        assert!(capacity > 0, "buffer pool needs at least one frame");
+        assert!(page_size > 0, "page size must be positive");
+        let file_len = storage.len()?;
+        let frames = (0..capacity)
+            .map(|_| Frame {
+                page: None,
+                data: Arc::new(RwLock::new(vec![0u8; page_size].into_boxed_slice())),
+                pins: 0,
+                dirty: false,
+                referenced: false,
+            })
+            .collect();
+        Ok(BufferPool {
+            file: Box::new(storage),
+            file_len,
+            page_size,
+            state: Mutex::new(PoolState {
+                frames,
+                pages: HashMap::new(),
+                hand: 0,
+            }),
+            metrics: None,
+        })
+    }
+
+    /// Count page reads, hits and misses in `metrics`.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Size of one page in bytes.
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /// Number of frames.
+    pub fn capacity(&self) -> usize {
+        self.state().frames.len()
+    }
+
+    /// Number of frames currently pinned.
+    pub fn pinned(&self) -> usize {
+        self.state()
+            .frames
+            .iter()
+            .filter(|frame| frame.pins > 0)
+            .count()
+    }
+
+    /// Pin page `page`, reading it from the file unless it is already cached.
+    ///
+    /// Fails if the read fails or if every frame is pinned.
+    pub fn pin(self: &Arc<Self>, page: u64) -> io::Result<PinnedPage> {
+        let mut state = self.state();
+        let frame = match state.pages.get(&page) {
+            Some(&frame) => {
+                if let Some(metrics) = &self.metrics {
+                    metrics.record_cache_hit();
+                }
+                frame
+            }
+            None => {
+                if let Some(metrics) = &self.metrics {
+                    metrics.record_cache_miss();
+                }
+                let frame = self.evict(&mut state)?;
+                self.read_page(page, &mut state.frames[frame].data.write().unwrap())?;
+                state.frames[frame].page = Some(page);
+                state.pages.insert(page, frame);
+                frame
+            }
+        };
+
+        let slot = &mut state.frames[frame];
+        slot.pins += 1;
+        slot.referenced = true;
+        Ok(PinnedPage {
+            pool: self.clone(),
+            frame,
+            page,
+            data: slot.data.clone(),
+        })
+    }
+
+    /// Write every dirty page back to the file.
+    pub fn flush(&self) -> io::Result<()> {
+        let mut state = self.state();
+        for frame in &mut state.frames {
+            if let (true, Some(page)) = (frame.dirty, frame.page) {
+                self.write_page(page, &frame.data.read().unwrap())?;
+                frame.dirty = false;
+            }
+        }
+        self.file.sync_data()
+    }
+
+    fn state(&self) -> MutexGuard<'_, PoolState> {
+        self.state.lock().expect("buffer pool lock poisoned")
+    }
+
+    /// Free a frame with the clock algorithm, writing its page back if it is dirty.
+    fn evict(&self, state: &mut PoolState) -> io::Result<usize> {
+        let count = state.frames.len();
+        // Two sweeps: the first may only clear reference bits
+        for _ in 0..2 * count {
+            let index = state.hand;
+            state.hand = (state.hand + 1) % count;
+            let frame = &mut state.frames[index];
+            if frame.pins > 0 {
+                continue;
+            }
+            if frame.referenced {
+                frame.referenced = false;
+                continue;
+            }
+
+            if let Some(page) = frame.page.take() {
+                if frame.dirty {
+                    self.write_page(page, &frame.data.read().unwrap())?;
+                    frame.dirty = false;
+                }
+                state.pages.remove(&page);
+            }
+            return Ok(index);
+        }
+        Err(io::Error::other(
+            "buffer pool exhausted: every frame is pinned",
+        ))
+    }
+
+    fn read_page(&self, page: u64, buf: &mut [u8]) -> io::Result<()> {
+        let offset = page * self.page_size as u64;
+        let len = self.file_len.saturating_sub(offset).min(buf.len() as u64) as usize;
+        self.file.read_exact_at(&mut buf[..len], offset)?;
+        buf[len..].fill(0);
+        if let Some(metrics) = &self.metrics {
+            metrics.record_read(len as u64);
+        }
+        Ok(())
+    }
+
+    fn write_page(&self, page: u64, buf: &[u8]) -> io::Result<()> {
+        let offset = page * self.page_size as u64;
+        let len = self.file_len.saturating_sub(offset).min(buf.len() as u64) as usize;
+        self.file.write_all_at(&buf[..len], offset)
+    }
+}
+
+/// A page held in memory until the guard is dropped.
+pub struct PinnedPage {
+    pool: Arc<BufferPool>,
+    frame: usize,
+    page: u64,
+    data: Arc<RwLock<Box<[u8]>>>,
+}
+
+impl PinnedPage {
+    /// Page number within the file.
+    pub fn page(&self) -> u64 {
+        self.page
+    }
+
+    /// Borrow the page's bytes.
+    pub fn read(&self) -> RwLockReadGuard<'_, Box<[u8]>> {
+        self.data.read().expect("page lock poisoned")
+    }
+
+    /// Borrow the page's bytes mutably and mark the page dirty.
+    ///
+    /// Release the guard before pinning another page: `flush` takes the pool lock before
+    /// page locks, so pinning while holding a write guard can deadlock with it.
+    pub fn write(&self) -> RwLockWriteGuard<'_, Box<[u8]>> {
+        self.pool.state().frames[self.frame].dirty = true;
+        self.data.write().expect("page lock poisoned")
+    }
+}
+
+impl Drop for PinnedPage {
+    fn drop(&mut self) {
+        self.pool.state().frames[self.frame].pins -= 1;
+    }
+}
+
+/// Node file accessed through a shared `BufferPool`.
+///
+/// Takes `&self` everywhere, so one instance (behind an `Arc`) serves any number of
+/// concurrent readers and a writer relinking nodes; hot pages such as the ones near the root
+/// are read from disk once instead of once per query. Records that straddle a page
+/// boundary are assembled from both pages.
+pub struct PooledNodeFile<P, T> {
+    pool: Arc<BufferPool>,
+    header: NodeFileHeader,
+    _marker: PhantomData<fn() -> (P, T)>,
+}
+
+impl<P: FixedCodec, T: FixedCodec> PooledNodeFile<P, T> {
+    /// Read the header through `pool` and validate it against `P` and `T`.
+    pub fn new(pool: Arc<BufferPool>) -> io::Result<Self> {
+        let mut buf = [0u8; HEADER_SIZE];
+        read_through(&pool, 0, &mut buf)?;
+        let header = NodeFileHeader::decode(&buf)?;
+        header.check_layout::<P, T>()?;
+        Ok(PooledNodeFile {
+            pool,
+            header,
+            _marker: PhantomData,
+        })
+    }
+
+    /// The decoded file header.
+    pub fn header(&self) -> &NodeFileHeader {
+        &self.header
+    }
+
+    /// Index of the root record, if the tree is not empty.
+    pub fn root(&self) -> Option<u64> {
+        self.header.root
+    }
+
+    /// The shared pool.
+    pub fn pool(&self) -> &Arc<BufferPool> {
+        &self.pool
+    }
+
+    /// Read and decode the record at `index`.
+    pub fn read_node(&self, index: u64) -> io::Result<NodeRecord<P, T>> {
+        let mut buf = vec![0u8; self.header.record_size()];
+        read_through(&self.pool, self.header.record_offset(index)?, &mut buf)?;
+        Ok(NodeRecord::decode(&buf))
+    }
+
+    /// Overwrite the child links of the record at `index`. The change reaches the file when
+    /// the page is evicted or the pool is flushed.
+    pub fn set_links(&self, index: u64, left: Option<u64>, right: Option<u64>) -> io::Result<()> {
+        let mut links = [0u8; 16];
+        left.unwrap_or(NO_NODE).encode(&mut links[0..8]);
+        right.unwrap_or(NO_NODE).encode(&mut links[8..16]);
+        let offset = self.header.record_offset(index)?;
+        let page_size = self.pool.page_size() as u64;
+        let mut written = 0;
+        while written < links.len() {
+            let position = offset + written as u64;
+            let page = self.pool.pin(position / page_size)?;
+            let start = (position % page_size) as usize;
+            let mut bytes = page.write();
+            let len = (bytes.len() - start).min(links.len() - written);
+            bytes[start..start + len].copy_from_slice(&links[written..written + len]);
+            written += len;
+        }
+        Ok(())
+    }
+}
+
+/// Copy `buf.len()` bytes starting at file `offset` out of the pool.
+fn read_through(pool: &Arc<BufferPool>, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+    let page_size = pool.page_size() as u64;
+    let mut read = 0;
+    while read < buf.len() {
+        let position = offset + read as u64;
+        let page = pool.pin(position / page_size)?;
+        let start = (position % page_size) as usize;
+        let bytes = page.read();
+        let len = (bytes.len() - start).min(buf.len() - read);
+        buf[read..read + len].copy_from_slice(&bytes[start..start + len]);
+        read += len;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+pub(crate) fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+pub(crate) fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    while !buf.is_empty() {
+        match std::os::windows::fs::FileExt::seek_read(file, buf, offset) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(read) => {
+                buf = &mut buf[read..];
+                offset += read as u64;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+pub(crate) fn write_all_at(file: &File, buf: &[u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::write_all_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+pub(crate) fn write_all_at(file: &File, mut buf: &[u8], mut offset: u64) -> io::Result<()> {
+    while !buf.is_empty() {
+        match std::os::windows::fs::FileExt::seek_write(file, buf, offset) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(written) => {
+                buf = &buf[written..];
+                offset += written as u64;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(all(test, not(bkd_loom)))]
+mod tests {
+    use super::*;
+    use crate::node_file::{NodeFileReader, write_arena};
+    use crate::spatial::BoundingBox;
+    use crate::storage::NodeArena;
+    use std::fs::OpenOptions;
+
+    fn write_tree(path: &std::path::Path, count: u32) {
+        let mut arena = NodeArena::new();
+        for i in 0..count {
+            let node = arena.allocate(BoundingBox::new(i as f64, 0.0, i as f64, 1.0), i);
+            if i + 1 < count {
+                arena.get_mut(node).right = Some(node + 1);
+            }
+        }
+        write_arena(path, &arena, Some(0)).unwrap();
+    }
+
+    #[test]
+    fn test_pooled_reads_share_pages_and_evict() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.bkd");
+        write_tree(&path, 200);
+
+        let metrics = Arc::new(Metrics::new());
+        let pool = BufferPool::new(File::open(&path).unwrap(), 2, 256)
+            .unwrap()
+            .with_metrics(metrics.clone());
+        let file = Arc::new(PooledNodeFile::<BoundingBox, u32>::new(Arc::new(pool)).unwrap());
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let file = file.clone();
+                std::thread::spawn(move || {
+                    for index in 0..200 {
+                        let record = file.read_node(index).unwrap();
+                        assert_eq!(record.data, index as u32);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(file.pool().pinned(), 0);
+
+        // 48-byte records in 256-byte pages: consecutive reads mostly hit the same page
+        let snapshot = metrics.snapshot();
+        assert!(snapshot.cache_hits > snapshot.cache_misses);
+        assert!(snapshot.disk_reads < 4 * 200);
+    }
+
+    #[test]
+    fn test_pinned_pages_are_not_evicted() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.bkd");
+        write_tree(&path, 50);
+        let pool = Arc::new(BufferPool::new(File::open(&path).unwrap(), 2, 64).unwrap());
+
+        let first = pool.pin(0).unwrap();
+        let second = pool.pin(1).unwrap();
+        assert!(pool.pin(2).is_err());
+        drop(second);
+        let third = pool.pin(2).unwrap();
+        assert_eq!(first.read()[..8], *b"BKDNODES");
+        assert_eq!(third.page(), 2);
+    }
+
+    #[test]
+    fn test_dirty_pages_written_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.bkd");
+        write_tree(&path, 50);
+        let handle = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        let pool = Arc::new(BufferPool::new(handle, 1, 128).unwrap());
+        let file = PooledNodeFile::<BoundingBox, u32>::new(pool.clone()).unwrap();
+
+        // Records straddle page boundaries; a one-frame pool evicts on every page change
+        file.set_links(10, Some(3), None).unwrap();
+        file.set_links(40, None, Some(2)).unwrap();
+        assert_eq!(file.read_node(10).unwrap().left, Some(3));
+        pool.flush().unwrap();
+
+        let mut reader = NodeFileReader::<BoundingBox, u32>::open(&path).unwrap();
+        let record = reader.read_node(10).unwrap();
+        assert_eq!((record.left, record.right), (Some(3), None));
+        let record = reader.read_node(40).unwrap();
+        assert_eq!((record.left, record.right), (None, Some(2)));
+        assert_eq!(reader.read_node(11).unwrap().right, Some(12));
+    }
+}
+
+#[cfg(all(test, bkd_loom))]
+mod loom_tests {
+    use super::*;
+    use crate::node_file::write_arena;
+    use crate::spatial::BoundingBox;
+    use crate::storage::NodeArena;
+    use loom::thread;
+    use std::fs::OpenOptions;
+
+    #[test]
+    fn test_concurrent_pins_and_link_updates() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.bkd");
+        let mut arena = NodeArena::new();
+        for i in 0..8u32 {
+            arena.allocate(BoundingBox::new(i as f64, 0.0, i as f64, 1.0), i);
+        }
+        write_arena(&path, &arena, Some(0)).unwrap();
+
+        loom::model(move || {
+            let handle = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&path)
+                .unwrap();
+            // Two frames for three pages: every run evicts
+            let pool = Arc::new(BufferPool::new(handle, 2, 128).unwrap());
+            let file = Arc::new(PooledNodeFile::<BoundingBox, u32>::new(pool.clone()).unwrap());
+
+            let writer = {
+                let file = file.clone();
+                thread::spawn(move || file.set_links(1, Some(2), Some(3)).unwrap())
+            };
+            let reader = {
+                let file = file.clone();
+                thread::spawn(move || {
+                    let record = file.read_node(1).unwrap();
+                    // Both links change under one page lock: old or new, never mixed
+                    let links = (record.left, record.right);
+                    assert!(links == (None, None) || links == (Some(2), Some(3)));
+                    assert_eq!(file.read_node(5).unwrap().data, 5);
+                })
+            };
+            writer.join().unwrap();
+            reader.join().unwrap();
+
+            assert_eq!(pool.pinned(), 0);
+            let record = file.read_node(1).unwrap();
+            assert_eq!((record.left, record.right), (Some(2), Some(3)));
+        });
+    }
+}
@@ -0,0 +1,890 @@
+//! Bulk construction of balanced KD-trees from pre-allocated nodes.
+
+use crate::block_tree::{BkdHeader, BkdReader, BkdWriter, BkdWriterOptions};
+use crate::cancel::{self, CancellationToken, Cancelled};
+use crate::codec::FixedCodec;
+use crate::deletes::DeletionBitmap;
+use crate::query::SpatialQuery;
+use crate::search::spatial_search;
+use crate::spatial::Point;
+use crate::spill::SpillRef;
+use crate::storage::{InMemoryLinker, NodeArena, NodeLinker};
+use std::fmt;
+use std::io;
+use std::marker::PhantomData;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Rule for choosing the split node of each subtree during a bulk build.
+///
+/// The split dimension always cycles with depth (`depth % dimensions`), exactly like
+/// `insert_node`, so bulk-built trees are searched by the same algorithms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SplitPolicy {
+    /// Split at the median value: perfectly balanced, but on clustered data the cells around
+    /// a dense cluster become thin slabs that queries must cross many times.
+    #[default]
+    Median,
+    /// Cut the cell at the midpoint of its extent, sliding the cut to the nearest entry only
+    /// when every entry lies on one side of it (the rule used by ANN libraries). Cells start
+    /// as the bounds of all entries and shrink with each cut, so they stay fat on clustered
+    /// data at the cost of a deeper tree.
+    SlidingMidpoint,
+}
+
+/// Configuration for `bulk_build`.
+#[derive(Debug, Clone, Default)]
+pub struct BuildOptions {
+    pub split_policy: SplitPolicy,
+    /// Optional hook called periodically while nodes are placed.
+    pub progress: Option<ProgressCallback>,
+    /// Optional token checked before each subtree is split.
+    pub cancel: Option<CancellationToken>,
+}
+
+impl BuildOptions {
+    /// Options using the given split policy.
+    pub fn with_split_policy(split_policy: SplitPolicy) -> Self {
+        BuildOptions {
+            split_policy,
+            ..Default::default()
+        }
+    }
+}
+
+/// Snapshot of a running build, passed to progress callbacks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuildProgress {
+    /// Nodes placed into the tree so far.
+    pub processed: u64,
+    /// Total number of nodes the build will place.
+    pub total: u64,
+    /// Depth of the node placed most recently.
+    pub depth: usize,
+    /// Time since the build started.
+    pub elapsed: Duration,
+}
+
+impl BuildProgress {
+    /// Completed fraction in `[0, 1]`.
+    pub fn fraction(&self) -> f64 {
+        if self.total == 0 {
+            1.0
+        } else {
+            self.processed as f64 / self.total as f64
+        }
+    }
+
+    /// Estimated time remaining, extrapolated linearly from the rate so far.
+    /// `None` until at least one node has been placed.
+    pub fn eta(&self) -> Option<Duration> {
+        if self.processed == 0 {
+            return None;
+        }
+        let remaining = self.total.saturating_sub(self.processed) as f64;
+        Some(self.elapsed.mul_f64(remaining / self.processed as f64))
+    }
+}
+
+/// Progress hook for long-running builds.
+///
+/// The callback runs on the building thread every `interval` placed nodes and once more
+/// when the build completes, so it should be cheap (log a line, update a gauge, ...).
+#[derive(Clone)]
+pub struct ProgressCallback {
+    interval: u64,
+    callback: Arc<dyn Fn(&BuildProgress) + Send + Sync>,
+}
+
+impl ProgressCallback {
+    /// Call `callback` every `interval` nodes (an interval of 0 is treated as 1).
+    pub fn new(interval: u64, callback: impl Fn(&BuildProgress) + Send + Sync + 'static) -> Self {
+        ProgressCallback {
+            interval: interval.max(1),
+            callback: Arc::new(callback),
+        }
+    }
+}
+
+impl fmt::Debug for ProgressCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProgressCallback")
+            .field("interval", &self.interval)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Counts placed nodes and fires the progress callback on schedule.
+pub(crate) struct ProgressTracker<'a> {
+    callback: Option<&'a ProgressCallback>,
+    started: Instant,
+    processed: u64,
+    total: u64,
+}
+
+impl<'a> ProgressTracker<'a> {
+    pub(crate) fn new(callback: Option<&'a ProgressCallback>, total: u64) -> Self {
+        ProgressTracker {
+            callback,
+            started: Instant::now(),
+            processed: 0,
+            total,
+        }
+    }
+
+    /// Record one placed node at `depth`.
+    pub(crate) fn advance(&mut self, depth: usize) {
+        self.processed += 1;
+        if let Some(callback) = self.callback {
+            if self.processed % callback.interval == 0 || self.processed == self.total {
+                (callback.callback)(&BuildProgress {
+                    processed: self.processed,
+                    total: self.total,
+                    depth,
+                    elapsed: self.started.elapsed(),
+                });
+            }
+        }
+    }
+}
+
+/// Pending subtree of a bulk build: the slice `start..end` still has to become a subtree
+/// linked under `parent` (or the root when `parent` is `None`).
+struct BuildTask<R> {
+    start: usize,
+    end: usize,
+    depth: usize,
+    parent: Option<(R, bool)>, // (parent node, is_left)
+    /// Per-dimension `(min, max)` of the subtree's cell, for sliding-midpoint splits; empty
+    /// under other policies.
+    cell: Vec<(f64, f64)>,
+}
+
+/// Build a KD-tree over a set of already allocated, unlinked nodes in one pass.
+/// Returns the root, or `None` if `nodes` is empty.
+///
+/// Returns `Err(Cancelled)` if `options.cancel` is triggered; the nodes are then left
+/// partially linked and should be discarded.
+///
+/// # Architecture
+/// Unlike repeated `insert_node` calls, which produce a tree shaped by insertion order,
+/// the bulk build chooses each split from the full set of entries below it:
+/// - The split dimension is `depth % dimensions`, matching the search algorithms
+/// - The split node is picked by `options.split_policy`; entries in its left subtree have
+///   values at most its own and entries in its right subtree at least its own, so equal
+///   values may end up on either side, as the search algorithms allow
+/// - Work is driven by an explicit task stack, so degenerate inputs that produce very deep
+///   trees cannot overflow the call stack
+///
+/// `nodes` is reordered in place. Nodes must not already have children linked.
+pub fn bulk_build<P: Point, T, L: NodeLinker<P, T>>(
+    linker: &mut L,
+    nodes: &mut [L::NodeRef],
+    depth: usize,
+    options: &BuildOptions,
+) -> Result<Option<L::NodeRef>, Cancelled> {
+    let mut root = None;
+    let mut progress = ProgressTracker::new(options.progress.as_ref(), nodes.len() as u64);
+    let cell = match (options.split_policy, nodes.first()) {
+        (SplitPolicy::SlidingMidpoint, Some(&first)) => {
+            let dimensions = linker.get_point(first).dimensions();
+            let mut cell = vec![(f64::INFINITY, f64::NEG_INFINITY); dimensions];
+            for &node in nodes.iter() {
+                let point = linker.get_point(node);
+                for (dimension, (min, max)) in cell.iter_mut().enumerate() {
+                    *min = min.min(point.get_dimension(dimension));
+                    *max = max.max(point.get_dimension(dimension));
+                }
+            }
+            cell
+        }
+        _ => Vec::new(),
+    };
+    let mut tasks = vec![BuildTask {
+        start: 0,
+        end: nodes.len(),
+        depth,
+        parent: None,
+        cell,
+    }];
+
+    while let Some(task) = tasks.pop() {
+        if task.start == task.end {
+            continue;
+        }
+        cancel::check(options.cancel.as_ref())?;
+
+        let slice = &mut nodes[task.start..task.end];
+        let dimension = task.depth % linker.get_point(slice[0]).dimensions();
+        let (split, left_cell, right_cell) = match options.split_policy {
+            SplitPolicy::Median => (
+                split_median(linker, slice, dimension),
+                Vec::new(),
+                Vec::new(),
+            ),
+            SplitPolicy::SlidingMidpoint => {
+                let (split, left_max, right_min) =
+                    split_sliding_midpoint(linker, slice, dimension, task.cell[dimension]);
+                let (mut left_cell, mut right_cell) = (task.cell.clone(), task.cell);
+                left_cell[dimension].1 = left_max;
+                right_cell[dimension].0 = right_min;
+                (split, left_cell, right_cell)
+            }
+        };
+
+        let node = slice[split];
+        match task.parent {
+            None => root = Some(node),
+            Some((parent, true)) => linker.link_left(parent, node),
+            Some((parent, false)) => linker.link_right(parent, node),
+        }
+        progress.advance(task.depth);
+
+        let split = task.start + split;
+        tasks.push(BuildTask {
+            start: split + 1,
+            end: task.end,
+            depth: task.depth + 1,
+            parent: Some((node, false)),
+            cell: right_cell,
+        });
+        tasks.push(BuildTask {
+            start: task.start,
+            end: split,
+            depth: task.depth + 1,
+            parent: Some((node, true)),
+            cell: left_cell,
+        });
+    }
+
+    Ok(root)
+}
+
+/// Copy the entries matching `region` into a new, balanced, standalone index.
+///
+/// Returns the new arena and its root, or `None` for the root if nothing matched. The
+/// source tree is only read, so tiles, shards and exports can be cut from a live index;
+/// entries keep their points and payloads but not their place in the source tree, and the
+/// copy is bulk-built with default options.
+pub fn extract_region<P: Point + Clone, T: Clone, L: NodeLinker<P, T>, Q: SpatialQuery<P>>(
+    linker: &L,
+    root: Option<L::NodeRef>,
+    region: &Q,
+) -> (NodeArena<P, T>, Option<usize>) {
+    let mut arena = NodeArena::new();
+    let mut nodes: Vec<usize> = spatial_search(linker, root, region, 0)
+        .into_iter()
+        .map(|node| {
+            arena.allocate(
+                linker.get_point(node).clone(),
+                linker.get_data(node).clone(),
+            )
+        })
+        .collect();
+    let root = bulk_build(
+        &mut InMemoryLinker::new(&mut arena),
+        &mut nodes,
+        0,
+        &BuildOptions::default(),
+    )
+    .expect("builds without a cancellation token are never cancelled");
+    (arena, root)
+}
+
+/// Rebuild any tree into a balanced, compact copy in a fresh arena.
+///
+/// Only nodes reachable from `root` are copied, so holes left by deleted or abandoned
+/// nodes disappear, and the copy is median-balanced however lopsided the source was.
+/// Nodes are laid out in pre-order: the root is node 0 and every left child directly
+/// follows its parent, so a search descending left walks sequential memory. Meant to run
+/// after heavy update churn; see `rebuild_packed` for a read-only block tree instead.
+pub fn rebuild_compact<P: Point + Clone, T: Clone, L: NodeLinker<P, T>>(
+    linker: &L,
+    root: Option<L::NodeRef>,
+) -> (NodeArena<P, T>, Option<usize>) {
+    rebuild_from(linker, reachable(linker, root))
+}
+
+/// Rebuild a tree like `rebuild_compact`, leaving out the nodes marked in `deleted`.
+///
+/// This is how soft deletes are purged: the copy has none of the deleted entries, so it
+/// needs a fresh, empty `DeletionBitmap`. Handles change, as with `rebuild_compact`.
+pub fn rebuild_purged<P, T, L>(
+    linker: &L,
+    root: Option<L::NodeRef>,
+    deleted: &DeletionBitmap<L::NodeRef>,
+) -> (NodeArena<P, T>, Option<usize>)
+where
+    P: Point + Clone,
+    T: Clone,
+    L: NodeLinker<P, T>,
+    L::NodeRef: SpillRef,
+{
+    let mut live = reachable(linker, root);
+    live.retain(|&node| !deleted.is_deleted(node));
+    rebuild_from(linker, live)
+}
+
+/// Copy `sources` into a fresh arena as a balanced tree laid out in pre-order.
+fn rebuild_from<P: Point + Clone, T: Clone, L: NodeLinker<P, T>>(
+    linker: &L,
+    sources: Vec<L::NodeRef>,
+) -> (NodeArena<P, T>, Option<usize>) {
+    let mut shadow = ShadowLinker::new(linker, sources);
+    let mut order: Vec<usize> = (0..shadow.sources.len()).collect();
+    let Some(shadow_root) = bulk_build(&mut shadow, &mut order, 0, &BuildOptions::default())
+        .expect("builds without a cancellation token are never cancelled")
+    else {
+        return (NodeArena::new(), None);
+    };
+
+    // Copy in pre-order, linking each node to the parent copied before it
+    let mut arena = NodeArena::new();
+    let mut stack = vec![(shadow_root, None)];
+    while let Some((node, parent)) = stack.pop() {
+        let source = shadow.sources[node];
+        let copy = arena.allocate(
+            linker.get_point(source).clone(),
+            linker.get_data(source).clone(),
+        );
+        let mut copies = InMemoryLinker::new(&mut arena);
+        match parent {
+            Some((parent, true)) => copies.link_left(parent, copy),
+            Some((parent, false)) => copies.link_right(parent, copy),
+            None => {}
+        }
+        stack.extend(shadow.right[node].map(|child| (child, Some((copy, false)))));
+        stack.extend(shadow.left[node].map(|child| (child, Some((copy, true)))));
+    }
+    (arena, Some(0))
+}
+
+/// Rebuild any tree into a packed, read-only block tree file at `path`.
+///
+/// The same conversion as `to_block_tree`, under the name of the other post-churn rebuild.
+pub fn rebuild_packed<P, T, L>(
+    linker: &L,
+    root: Option<L::NodeRef>,
+    path: &Path,
+    options: BkdWriterOptions,
+) -> io::Result<BkdHeader>
+where
+    P: Point + FixedCodec + Clone,
+    T: FixedCodec + Clone,
+    L: NodeLinker<P, T>,
+{
+    to_block_tree(linker, root, path, options)
+}
+
+/// Convert a node-per-entry tree into a block tree file at `path`, for serving.
+///
+/// Ingest incrementally with `insert_node` or a `SharedTree`, then pack the result. The
+/// reachable entries of the source are streamed into a `BkdWriter`, which spills to disk as
+/// its options allow, so trees larger than memory can be converted.
+pub fn to_block_tree<P, T, L>(
+    linker: &L,
+    root: Option<L::NodeRef>,
+    path: &Path,
+    options: BkdWriterOptions,
+) -> io::Result<BkdHeader>
+where
+    P: Point + FixedCodec + Clone,
+    T: FixedCodec + Clone,
+    L: NodeLinker<P, T>,
+{
+    let mut writer = BkdWriter::new(options);
+    for node in reachable(linker, root) {
+        writer.add(
+            linker.get_point(node).clone(),
+            linker.get_data(node).clone(),
+        )?;
+    }
+    writer.finish(path)
+}
+
+/// Load a block tree into a balanced node-per-entry tree, to resume incremental updates.
+///
+/// Every leaf block is read, so the whole tree must fit in memory.
+pub fn to_node_tree<P, T>(
+    reader: &mut BkdReader<P, T>,
+) -> io::Result<(NodeArena<P, T>, Option<usize>)>
+where
+    P: Point + FixedCodec,
+    T: FixedCodec,
+{
+    let mut arena = NodeArena::with_capacity(reader.len() as usize);
+    for block in reader.leaves() {
+        let block = block?;
+        for (point, data) in block.points.into_iter().zip(block.data) {
+            arena.allocate(point, data);
+        }
+    }
+    let mut nodes: Vec<usize> = (0..arena.len()).collect();
+    let mut linker = InMemoryLinker::new(&mut arena);
+    let root = bulk_build(&mut linker, &mut nodes, 0, &BuildOptions::default())
+        .expect("builds without a cancellation token are never cancelled");
+    Ok((arena, root))
+}
+
+/// Every node reachable from `root`, in pre-order.
+fn reachable<P: Point, T, L: NodeLinker<P, T>>(
+    linker: &L,
+    root: Option<L::NodeRef>,
+) -> Vec<L::NodeRef> {
+    let mut nodes = Vec::new();
+    let mut stack: Vec<L::NodeRef> = root.into_iter().collect();
+    while let Some(node) = stack.pop() {
+        nodes.push(node);
+        stack.extend(linker.get_right(node));
+        stack.extend(linker.get_left(node));
+    }
+    nodes
+}
+
+/// Linker over positions in a list of source nodes, with links kept on the side, so a
+/// tree's shape can be built before anything is copied.
+pub(crate) struct ShadowLinker<'l, P: Point, T, L: NodeLinker<P, T>> {
+    linker: &'l L,
+    sources: Vec<L::NodeRef>,
+    left: Vec<Option<usize>>,
+    right: Vec<Option<usize>>,
+    _marker: PhantomData<fn() -> (P, T)>,
+}
+
+impl<'l, P: Point, T, L: NodeLinker<P, T>> ShadowLinker<'l, P, T, L> {
+    pub(crate) fn new(linker: &'l L, sources: Vec<L::NodeRef>) -> Self {
+        let len = sources.len();
+        ShadowLinker {
+            linker,
+            sources,
+            left: vec![None; len],
+            right: vec![None; len],
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<P: Point, T, L: NodeLinker<P, T>> NodeLinker<P, T> for ShadowLinker<'_, P, T, L> {
+    type NodeRef = usize;
+
+    fn link_left(&mut self, parent: usize, child: usize) {
+        self.left[parent] = Some(child);
+    }
+
+    fn link_right(&mut self, parent: usize, child: usize) {
+        self.right[parent] = Some(child);
+    }
+
+    fn get_left(&self, node: usize) -> Option<usize> {
+        self.left[node]
+    }
+
+    fn get_right(&self, node: usize) -> Option<usize> {
+        self.right[node]
+    }
+
+    fn get_point(&self, node: usize) -> &P {
+        self.linker.get_point(self.sources[node])
+    }
+
+    fn get_data(&self, node: usize) -> &T {
+        self.linker.get_data(self.sources[node])
+    }
+}
+
+/// Partition around the median and return its index.
+/// Afterwards every entry before the index is <= the median and every entry after is >=.
+fn split_median<P: Point, T, L: NodeLinker<P, T>>(
+    linker: &L,
+    slice: &mut [L::NodeRef],
+    dimension: usize,
+) -> usize {
+    let mid = slice.len() / 2;
+    slice.select_nth_unstable_by(mid, |&a, &b| {
+        let a_value = linker.get_point(a).get_dimension(dimension);
+        let b_value = linker.get_point(b).get_dimension(dimension);
+        a_value.total_cmp(&b_value)
+    });
+    mid
+}
+
+/// Cut the cell `(min, max)` at its midpoint and return the index of the split entry with
+/// the upper bound of the left cell and the lower bound of the right one.
+///
+/// Entries below the midpoint go before the index and the rest after it; the split entry
+/// is the smallest of the rest, so it bounds both sides. When every entry lies on one side,
+/// the cut slides to the nearest entry, which becomes the split with the others beside it.
+fn split_sliding_midpoint<P: Point, T, L: NodeLinker<P, T>>(
+    linker: &L,
+    slice: &mut [L::NodeRef],
+    dimension: usize,
+    (min, max): (f64, f64),
+) -> (usize, f64, f64) {
+    let value = |node: L::NodeRef| linker.get_point(node).get_dimension(dimension);
+    let midpoint = min + (max - min) / 2.0;
+
+    let mut boundary = 0;
+    for index in 0..slice.len() {
+        if value(slice[index]) < midpoint {
+            slice.swap(boundary, index);
+            boundary += 1;
+        }
+    }
+
+    let nearest = |range: std::ops::Range<usize>, closer: fn(f64, f64) -> bool| {
+        range
+            .reduce(|best, index| {
+                if closer(value(slice[index]), value(slice[best])) {
+                    index
+                } else {
+                    best
+                }
+            })
+            .expect("cells are never empty")
+    };
+    if boundary == slice.len() {
+        // Every entry below the midpoint: slide down to the largest
+        let largest = nearest(0..slice.len(), |a, b| a > b);
+        let last = slice.len() - 1;
+        slice.swap(largest, last);
+        (last, value(slice[last]), max)
+    } else {
+        // The smallest entry at or above the midpoint, the lowest of all when sliding up
+        let smallest = nearest(boundary..slice.len(), |a, b| a < b);
+        slice.swap(boundary, smallest);
+        let cut = if boundary == 0 {
+            value(slice[0])
+        } else {
+            midpoint
+        };
+        (boundary, cut, cut)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyze::analyze;
+    use crate::search::insert_node;
+    use crate::spatial::{BoundingBox, SpatialPoint};
+
+    fn height<P: Point, T, L: NodeLinker<P, T>>(linker: &L, node: Option<L::NodeRef>) -> usize {
+        node.map_or(0, |node| {
+            1 + height(linker, linker.get_left(node)).max(height(linker, linker.get_right(node)))
+        })
+    }
+
+    /// Two dense clusters far apart plus a few scattered boxes.
+    fn clustered_arena() -> NodeArena<BoundingBox, usize> {
+        let mut arena = NodeArena::new();
+        for i in 0..200 {
+            let offset = (i % 20) as f64 * 0.01;
+            let base = if i % 2 == 0 { 0.0 } else { 1000.0 };
+            let x = base + offset;
+            let y = base + (i / 20) as f64 * 0.01;
+            arena.allocate(BoundingBox::new(x, y, x + 0.005, y + 0.005), i);
+        }
+        for i in 0..10 {
+            let x = i as f64 * 100.0;
+            arena.allocate(BoundingBox::new(x, 500.0, x + 1.0, 501.0), 200 + i);
+        }
+        arena
+    }
+
+    #[test]
+    fn test_bulk_build_matches_brute_force() {
+        let queries = [
+            BoundingBox::new(-1.0, -1.0, 0.1, 0.1),
+            BoundingBox::new(999.0, 999.0, 1000.05, 1000.05),
+            BoundingBox::new(150.0, 400.0, 650.0, 600.0),
+            BoundingBox::new(-10.0, -10.0, 2000.0, 2000.0),
+        ];
+
+        for policy in [SplitPolicy::Median, SplitPolicy::SlidingMidpoint] {
+            let mut arena = clustered_arena();
+            let len = arena.len();
+            let expected: Vec<Vec<usize>> = queries
+                .iter()
+                .map(|query| {
+                    (0..len)
+                        .filter(|&i| arena.get(i).point.overlaps(query))
+                        .collect()
+                })
+                .collect();
+
+            let mut linker = InMemoryLinker::new(&mut arena);
+            let mut nodes: Vec<usize> = (0..len).collect();
+            let options = BuildOptions::with_split_policy(policy);
+            let root = bulk_build(&mut linker, &mut nodes, 0, &options).unwrap();
+
+            for (query, expected) in queries.iter().zip(&expected) {
+                let mut results = spatial_search(&linker, root, query, 0);
+                results.sort();
+                assert_eq!(&results, expected, "{:?} {:?}", policy, query);
+            }
+        }
+    }
+
+    #[test]
+    fn test_median_build_is_balanced() {
+        let mut arena = clustered_arena();
+        let len = arena.len();
+        let mut linker = InMemoryLinker::new(&mut arena);
+        let mut nodes: Vec<usize> = (0..len).collect();
+        let root = bulk_build(&mut linker, &mut nodes, 0, &BuildOptions::default()).unwrap();
+
+        // 210 entries fit in a perfectly balanced tree of height 8
+        assert_eq!(height(&linker, root), 8);
+    }
+
+    #[test]
+    fn test_sliding_midpoint_avoids_slabs_on_clustered_data() {
+        // Small queries in and around the clusters, and in the space between them
+        let queries: Vec<BoundingBox> = (0..60)
+            .map(|i| {
+                let base = [0.0, 1000.0, 480.0][i % 3];
+                let x = base + (i / 3) as f64 * 0.012 - 0.02;
+                let y = base + (i / 3 % 7) as f64 * 0.015;
+                BoundingBox::new(x, y, x + 0.01, y + 0.01)
+            })
+            .collect();
+        let [median, sliding] = [SplitPolicy::Median, SplitPolicy::SlidingMidpoint].map(|policy| {
+            let mut arena = clustered_arena();
+            let len = arena.len();
+            let mut linker = InMemoryLinker::new(&mut arena);
+            let mut nodes: Vec<usize> = (0..len).collect();
+            let options = BuildOptions::with_split_policy(policy);
+            let root = bulk_build(&mut linker, &mut nodes, 0, &options).unwrap();
+            let report = analyze(&linker, root, &queries);
+            (
+                height(&linker, root),
+                report.mean_reads,
+                report.mean_matches,
+            )
+        });
+
+        // Deeper than balanced, but the fat cells around the clusters are crossed by far
+        // fewer queries, so the same matches cost fewer node reads
+        assert_eq!(median.0, 8);
+        assert!(sliding.0 > median.0);
+        assert_eq!(sliding.2, median.2);
+        assert!(sliding.1 < 0.75 * median.1, "{sliding:?} vs {median:?}");
+    }
+
+    #[test]
+    fn test_bulk_build_reports_progress() {
+        use std::sync::Mutex;
+
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let sink = reports.clone();
+        let options = BuildOptions {
+            progress: Some(ProgressCallback::new(
+                50,
+                move |progress: &BuildProgress| {
+                    sink.lock().unwrap().push(progress.clone());
+                },
+            )),
+            ..Default::default()
+        };
+
+        let mut arena = clustered_arena();
+        let len = arena.len();
+        let mut linker = InMemoryLinker::new(&mut arena);
+        let mut nodes: Vec<usize> = (0..len).collect();
+        bulk_build(&mut linker, &mut nodes, 0, &options).unwrap();
+
+        let reports = reports.lock().unwrap();
+        let processed: Vec<u64> = reports.iter().map(|report| report.processed).collect();
+        assert_eq!(processed, vec![50, 100, 150, 200, 210]);
+        assert!(reports.iter().all(|report| report.total == 210));
+        assert_eq!(reports.last().unwrap().fraction(), 1.0);
+        assert_eq!(reports.last().unwrap().eta(), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_bulk_build_empty() {
+        let mut arena: NodeArena<BoundingBox, u32> = NodeArena::new();
+        let mut linker = InMemoryLinker::new(&mut arena);
+        let root = bulk_build(&mut linker, &mut [], 0, &BuildOptions::default());
+        assert_eq!(root, Ok(None));
+    }
+
+    #[test]
+    fn test_bulk_build_cancelled() {
+        let token = CancellationToken::new();
+        let options = BuildOptions {
+            cancel: Some(token.clone()),
+            ..Default::default()
+        };
+
+        let mut arena = clustered_arena();
+        let len = arena.len();
+        let mut linker = InMemoryLinker::new(&mut arena);
+        let mut nodes: Vec<usize> = (0..len).collect();
+
+        token.cancel();
+        assert_eq!(
+            bulk_build(&mut linker, &mut nodes, 0, &options),
+            Err(Cancelled)
+        );
+    }
+
+    #[test]
+    fn test_extract_region() {
+        let mut arena = clustered_arena();
+        let len = arena.len();
+        let mut linker = InMemoryLinker::new(&mut arena);
+        let mut nodes: Vec<usize> = (0..len).collect();
+        let root = bulk_build(&mut linker, &mut nodes, 0, &BuildOptions::default()).unwrap();
+
+        let region = BoundingBox::new(0.0, 0.0, 20.0, 20.0);
+        let mut expected: Vec<usize> = spatial_search(&linker, root, &region, 0)
+            .into_iter()
+            .map(|node| *linker.get_data(node))
+            .collect();
+        assert!(!expected.is_empty() && expected.len() < len);
+
+        let (mut tile, tile_root) = extract_region(&linker, root, &region);
+        assert_eq!(tile.len(), expected.len());
+        let tile_linker = InMemoryLinker::new(&mut tile);
+        let everything = BoundingBox::new(f64::MIN, f64::MIN, f64::MAX, f64::MAX);
+        let mut found: Vec<usize> = spatial_search(&tile_linker, tile_root, &everything, 0)
+            .into_iter()
+            .map(|node| *tile_linker.get_data(node))
+            .collect();
+        expected.sort_unstable();
+        found.sort_unstable();
+        assert_eq!(found, expected);
+
+        let nowhere = BoundingBox::new(1e9, 1e9, 1e9 + 1.0, 1e9 + 1.0);
+        let (empty, empty_root) = extract_region(&linker, root, &nowhere);
+        assert!(empty.is_empty() && empty_root.is_none());
+    }
+
+    #[test]
+    fn test_rebuild_compact_lopsided_tree_with_holes() {
+        // Sorted inserts make a chain; every third node is allocated but never linked
+        let mut arena = NodeArena::new();
+        let mut linked = Vec::new();
+        for i in 0..300u32 {
+            let x = i as f64;
+            let node = arena.allocate(BoundingBox::new(x, x, x + 1.0, x + 1.0), i);
+            if i % 3 != 0 {
+                linked.push(node);
+            }
+        }
+        let mut linker = InMemoryLinker::new(&mut arena);
+        let root = linked[0];
+        for &node in &linked[1..] {
+            insert_node(&mut linker, Some(root), node, 0);
+        }
+        assert_eq!(height(&linker, Some(root)), 200);
+
+        let (mut compact, compact_root) = rebuild_compact(&linker, Some(root));
+        assert_eq!(compact.len(), 200);
+        assert_eq!(compact_root, Some(0));
+        let compact_linker = InMemoryLinker::new(&mut compact);
+        assert_eq!(height(&compact_linker, compact_root), 8);
+        // Pre-order layout: a left child directly follows its parent
+        for node in 0..200 {
+            if let Some(left) = compact_linker.get_left(node) {
+                assert_eq!(left, node + 1);
+            }
+        }
+
+        let query = BoundingBox::new(50.0, 50.0, 120.0, 120.0);
+        let mut expected: Vec<u32> = spatial_search(&linker, Some(root), &query, 0)
+            .into_iter()
+            .map(|node| *linker.get_data(node))
+            .collect();
+        let mut found: Vec<u32> = spatial_search(&compact_linker, compact_root, &query, 0)
+            .into_iter()
+            .map(|node| *compact_linker.get_data(node))
+            .collect();
+        expected.sort_unstable();
+        found.sort_unstable();
+        assert_eq!(found, expected);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("packed.bkd");
+        let options = BkdWriterOptions {
+            max_points_in_leaf: 16,
+            ..Default::default()
+        };
+        let header = rebuild_packed(&linker, Some(root), &path, options).unwrap();
+        assert_eq!(header.point_count, 200);
+        let mut reader = crate::block_tree::BkdReader::<BoundingBox, u32>::open(&path).unwrap();
+        let mut packed = reader.search(&query).unwrap();
+        packed.sort_unstable();
+        assert_eq!(packed, expected);
+    }
+
+    #[test]
+    fn test_convert_between_node_and_block_trees() {
+        let mut arena = NodeArena::new();
+        let mut nodes: Vec<usize> = (0..700u32)
+            .map(|i| {
+                let x = (i * 37 % 211) as f64;
+                let y = (i * 53 % 197) as f64;
+                arena.allocate(BoundingBox::new(x, y, x + 2.0, y + 2.0), i)
+            })
+            .collect();
+        let mut linker = InMemoryLinker::new(&mut arena);
+        let root = bulk_build(&mut linker, &mut nodes, 0, &BuildOptions::default()).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("served.bkd");
+        let options = BkdWriterOptions {
+            max_points_in_leaf: 32,
+            ..Default::default()
+        };
+        let header = to_block_tree(&linker, root, &path, options).unwrap();
+        assert_eq!(header.point_count, 700);
+
+        let mut reader = BkdReader::<BoundingBox, u32>::open(&path).unwrap();
+        let (mut loaded, loaded_root) = to_node_tree(&mut reader).unwrap();
+        assert_eq!(loaded.len(), 700);
+        let loaded_linker = InMemoryLinker::new(&mut loaded);
+        assert!(height(&loaded_linker, loaded_root) <= 10);
+
+        let query = BoundingBox::new(40.0, 40.0, 90.0, 120.0);
+        let mut expected: Vec<u32> = spatial_search(&linker, root, &query, 0)
+            .into_iter()
+            .map(|node| *linker.get_data(node))
+            .collect();
+        let mut found: Vec<u32> = spatial_search(&loaded_linker, loaded_root, &query, 0)
+            .into_iter()
+            .map(|node| *loaded_linker.get_data(node))
+            .collect();
+        let mut packed = reader.search(&query).unwrap();
+        expected.sort_unstable();
+        found.sort_unstable();
+        packed.sort_unstable();
+        assert_eq!(found, expected);
+        assert_eq!(packed, expected);
+
+        // Empty trees convert both ways
+        let empty_path = dir.path().join("empty.bkd");
+        let mut empty = NodeArena::<BoundingBox, u32>::new();
+        let empty_linker = InMemoryLinker::new(&mut empty);
+        to_block_tree(
+            &empty_linker,
+            None,
+            &empty_path,
+            BkdWriterOptions::default(),
+        )
+        .unwrap();
+        let mut reader = BkdReader::<BoundingBox, u32>::open(&empty_path).unwrap();
+        let (loaded, loaded_root) = to_node_tree(&mut reader).unwrap();
+        assert!(loaded.is_empty() && loaded_root.is_none());
+    }
+
+    #[test]
+    fn test_rebuild_compact_empty() {
+        let mut arena = NodeArena::<BoundingBox, u32>::new();
+        let linker = InMemoryLinker::new(&mut arena);
+        let (compact, root) = rebuild_compact(&linker, None);
+        assert!(compact.is_empty() && root.is_none());
+    }
+}
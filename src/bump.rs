@@ -0,0 +1,155 @@
+//! Bump-allocated node arena (feature `bumpalo`).
+//!
+//! Short-lived trees — e.g. one built per query over a candidate set — pay mostly for
+//! allocation and teardown. Allocating their nodes from a `bumpalo::Bump` makes both
+//! nearly free: allocation is a pointer bump, and resetting or dropping the `Bump` releases
+//! every node at once instead of freeing chunk by chunk.
+
+use crate::spatial::Point;
+use crate::storage::{CHUNK_SIZE, Node, NodeStore};
+use bumpalo::Bump;
+use bumpalo::collections::Vec as BumpVec;
+
+/// Node arena whose chunks are allocated from a caller-provided bump allocator.
+///
+/// # Usage pattern:
+/// ```rust
+/// # use bkd::{BoundingBox, InMemoryLinker, insert_node, spatial_search};
+/// # use bkd::bump::BumpNodeArena;
+/// let bump = bumpalo::Bump::new();
+/// let mut arena = BumpNodeArena::new(&bump);
+/// let node = arena.allocate(BoundingBox::new(0.0, 0.0, 1.0, 1.0), 7u32);
+/// let mut linker = InMemoryLinker::new(&mut arena);
+/// let root = insert_node(&mut linker, None, node, 0);
+/// let query = BoundingBox::new(0.0, 0.0, 2.0, 2.0);
+/// assert_eq!(spatial_search(&linker, Some(root), &query, 0), vec![node]);
+/// ```
+///
+/// Like `NodeArena`, nodes are stored in fixed-size chunks that never move. The chunk
+/// memory belongs to the `Bump`, so it is reclaimed when the bump allocator is reset or
+/// dropped; node destructors still run when the arena itself is dropped.
+pub struct BumpNodeArena<'bump, P: Point, T> {
+    bump: &'bump Bump,
+    chunks: Vec<BumpVec<'bump, Node<P, T>>>,
+    len: usize,
+}
+
+impl<'bump, P: Point, T> BumpNodeArena<'bump, P, T> {
+    /// Create a new empty arena allocating from `bump`.
+    pub fn new(bump: &'bump Bump) -> Self {
+        BumpNodeArena {
+            bump,
+            chunks: Vec::new(),
+            len: 0,
+        }
+    }
+
+    /// Allocate a new node and return its index.
+    pub fn allocate(&mut self, point: P, data: T) -> usize {
+        let index = self.len;
+        let chunk = index / CHUNK_SIZE;
+        if chunk == self.chunks.len() {
+            self.chunks
+                .push(BumpVec::with_capacity_in(CHUNK_SIZE, self.bump));
+        }
+        self.chunks[chunk].push(Node {
+            point,
+            data,
+            left: None,
+            right: None,
+        });
+        self.len += 1;
+        index
+    }
+
+    /// Get a reference to a node by index.
+    pub fn get(&self, index: usize) -> &Node<P, T> {
+        assert!(index < self.len, "node index out of range");
+        &self.chunks[index / CHUNK_SIZE][index % CHUNK_SIZE]
+    }
+
+    /// Get a mutable reference to a node by index.
+    pub fn get_mut(&mut self, index: usize) -> &mut Node<P, T> {
+        assert!(index < self.len, "node index out of range");
+        &mut self.chunks[index / CHUNK_SIZE][index % CHUNK_SIZE]
+    }
+
+    /// Get the number of allocated nodes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Check if the arena is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<'bump, P: Point, T> NodeStore<P, T> for BumpNodeArena<'bump, P, T> {
+    fn node(&self, index: usize) -> &Node<P, T> {
+        self.get(index)
+    }
+
+    fn node_mut(&mut self, index: usize) -> &mut Node<P, T> {
+        self.get_mut(index)
+    }
+
+    fn allocate(&mut self, point: P, data: T) -> usize {
+        BumpNodeArena::allocate(self, point, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::{insert_node, spatial_search};
+    use crate::spatial::BoundingBox;
+    use crate::storage::{InMemoryLinker, NodeArena};
+
+    #[test]
+    fn test_bump_arena_matches_node_arena() {
+        let points: Vec<BoundingBox> = (0..2 * CHUNK_SIZE + 3)
+            .map(|i| {
+                let x = (i * 37 % 500) as f64;
+                let y = (i * 91 % 500) as f64;
+                BoundingBox::new(x, y, x + 4.0, y + 4.0)
+            })
+            .collect();
+        let query = BoundingBox::new(100.0, 100.0, 180.0, 260.0);
+
+        let mut arena = NodeArena::new();
+        let nodes: Vec<usize> = points
+            .iter()
+            .enumerate()
+            .map(|(i, point)| arena.allocate(point.clone(), i))
+            .collect();
+        let mut linker = InMemoryLinker::new(&mut arena);
+        let root = insert_node(&mut linker, None, nodes[0], 0);
+        for &node in &nodes[1..] {
+            insert_node(&mut linker, Some(root), node, 0);
+        }
+        let mut expected = spatial_search(&linker, Some(root), &query, 0);
+
+        let mut bump = Bump::new();
+        {
+            let mut arena = BumpNodeArena::new(&bump);
+            let nodes: Vec<usize> = points
+                .iter()
+                .enumerate()
+                .map(|(i, point)| arena.allocate(point.clone(), i))
+                .collect();
+            let mut linker = InMemoryLinker::new(&mut arena);
+            let root = insert_node(&mut linker, None, nodes[0], 0);
+            for &node in &nodes[1..] {
+                insert_node(&mut linker, Some(root), node, 0);
+            }
+            let mut results = spatial_search(&linker, Some(root), &query, 0);
+            results.sort();
+            expected.sort();
+            assert!(!results.is_empty());
+            assert_eq!(results, expected);
+        }
+        assert!(bump.allocated_bytes() > 0);
+        bump.reset();
+    }
+}
@@ -0,0 +1,69 @@
+//! Cooperative cancellation for long-running operations.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Shared flag that asks a running operation to stop.
+///
+/// # Usage pattern:
+/// Clone the token into the operation's options and keep one clone on the controlling side
+/// (e.g. a request handler with a deadline). Operations poll the flag periodically and
+/// return `Cancelled` at the next check after `cancel()` is called. Checks are a single
+/// relaxed atomic load, so they are cheap enough to run inside hot loops.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    /// Create a token that is not cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request cancellation of every operation holding a clone of this token.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Check if cancellation has been requested.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Return `Err(Cancelled)` if cancellation has been requested.
+    pub fn check(&self) -> Result<(), Cancelled> {
+        if self.is_cancelled() {
+            Err(Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Check an optional token; operations without a token are never cancelled.
+pub(crate) fn check(token: Option<&CancellationToken>) -> Result<(), Cancelled> {
+    token.map_or(Ok(()), CancellationToken::check)
+}
+
+/// Error returned by an operation that stopped because its token was cancelled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("operation cancelled")
+    }
+}
+
+impl Error for Cancelled {}
+
+/// I/O-based operations surface cancellation as `ErrorKind::Interrupted`.
+impl From<Cancelled> for io::Error {
+    fn from(cancelled: Cancelled) -> Self {
+        io::Error::new(io::ErrorKind::Interrupted, cancelled)
+    }
+}
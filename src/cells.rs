@@ -0,0 +1,320 @@
+//! Queries by cells of global grid systems (S2 with feature `s2`, H3 with feature `h3`).
+//!
+//! Indexed entries are longitude/latitude boxes, as in `geo`. A cell or covering is turned
+//! into planar bounding boxes for pruning, and matches are then confirmed against the cells
+//! themselves, so users of Google's S2 or Uber's H3 cell schemes can query the index with
+//! the cell ids they already store.
+
+use crate::query::SpatialQuery;
+use crate::spatial::BoundingBox;
+
+/// A cell of a hierarchical grid on the sphere.
+pub trait GridCell {
+    /// Corners of the cell as `(lon, lat)` degrees, in order around the boundary. Edges
+    /// between consecutive corners are great-circle arcs.
+    fn vertices(&self) -> Vec<(f64, f64)>;
+
+    /// Check if the cell contains the point, by the grid's own assignment rule.
+    fn contains_point(&self, lon: f64, lat: f64) -> bool;
+}
+
+/// Query matching the entries that intersect any of a set of grid cells (a covering).
+///
+/// # Architecture
+/// Each cell is bounded by one planar box, or two when it crosses the antimeridian; the
+/// boxes prune the traversal and reject entries far from every cell. Point entries
+/// (`xmin == xmax` and `ymin == ymax`) are then tested with `GridCell::contains_point`, so
+/// they match exactly the cells the grid assigns them to. Larger boxes match when they
+/// overlap a cell's bounding box, which may include a box that only touches the slack
+/// between a cell and its bounds.
+#[derive(Debug, Clone)]
+pub struct CellQuery<C> {
+    cells: Vec<C>,
+    /// Planar bounds of the cells, with the index of the cell each bounds.
+    parts: Vec<(BoundingBox, usize)>,
+    /// Union of the parts; empty (inverted) when there are no cells.
+    bounds: BoundingBox,
+}
+
+impl<C: GridCell> CellQuery<C> {
+    /// Query for the union of `cells`.
+    pub fn new(cells: impl IntoIterator<Item = C>) -> Self {
+        let cells: Vec<C> = cells.into_iter().collect();
+        let parts: Vec<(BoundingBox, usize)> = cells
+            .iter()
+            .enumerate()
+            .flat_map(|(index, cell)| cell_bounds(cell).into_iter().map(move |part| (part, index)))
+            .collect();
+        let bounds = parts
+            .iter()
+            .map(|(part, _)| part.clone())
+            .reduce(|bounds, part| bounds.union(&part))
+            .unwrap_or(BoundingBox::new(
+                f64::INFINITY,
+                f64::INFINITY,
+                f64::NEG_INFINITY,
+                f64::NEG_INFINITY,
+            ));
+        CellQuery {
+            cells,
+            parts,
+            bounds,
+        }
+    }
+
+    /// The cells of the query.
+    pub fn cells(&self) -> &[C] {
+        &self.cells
+    }
+
+    /// Planar boxes bounding the cells, one or two per cell.
+    pub fn bounding_boxes(&self) -> impl Iterator<Item = &BoundingBox> {
+        self.parts.iter().map(|(part, _)| part)
+    }
+}
+
+impl<C: GridCell> SpatialQuery<BoundingBox> for CellQuery<C> {
+    fn dimension_range(&self, dim: usize) -> (f64, f64) {
+        self.bounds.dimension_range(dim)
+    }
+
+    fn matches(&self, point: &BoundingBox) -> bool {
+        let is_point = point.xmin == point.xmax && point.ymin == point.ymax;
+        self.parts.iter().any(|(part, cell)| {
+            part.matches(point)
+                && (!is_point || self.cells[*cell].contains_point(point.xmin, point.ymin))
+        })
+    }
+}
+
+/// Samples per edge when bounding a cell.
+const EDGE_SAMPLES: usize = 16;
+
+/// Planar bounds of a cell: one box, or two split at the antimeridian.
+///
+/// Edges are sampled along their great circles. The arc between two samples strays from
+/// the straight line between them by far less than their separation, so widening the
+/// sampled extent by the largest separation yields bounds that always contain the cell.
+fn cell_bounds<C: GridCell>(cell: &C) -> Vec<BoundingBox> {
+    let vertices: Vec<[f64; 3]> = cell
+        .vertices()
+        .into_iter()
+        .map(|(lon, lat)| to_xyz(lon, lat))
+        .collect();
+
+    let mut samples = Vec::with_capacity(vertices.len() * EDGE_SAMPLES);
+    let mut margin: f64 = 0.0;
+    for (index, a) in vertices.iter().enumerate() {
+        let b = &vertices[(index + 1) % vertices.len()];
+        let mut previous = *a;
+        for step in 0..EDGE_SAMPLES {
+            let t = step as f64 / EDGE_SAMPLES as f64;
+            let sample = normalize([
+                a[0] + (b[0] - a[0]) * t,
+                a[1] + (b[1] - a[1]) * t,
+                a[2] + (b[2] - a[2]) * t,
+            ]);
+            samples.push(to_lon_lat(sample));
+            margin = margin.max(angle(previous, sample));
+            previous = sample;
+        }
+        margin = margin.max(angle(previous, *b));
+    }
+
+    let mut south = samples.iter().map(|&(_, lat)| lat).fold(90.0, f64::min) - margin;
+    let mut north = samples.iter().map(|&(_, lat)| lat).fold(-90.0, f64::max) + margin;
+    let north_pole = cell.contains_point(0.0, 90.0);
+    let south_pole = cell.contains_point(0.0, -90.0);
+    if north_pole {
+        north = 90.0;
+    }
+    if south_pole {
+        south = -90.0;
+    }
+    let (south, north) = (south.max(-90.0), north.min(90.0));
+
+    // A longitude margin of `margin` degrees of arc widens toward the poles
+    let widest = south.abs().max(north.abs());
+    let lon_margin = margin / widest.to_radians().cos().max(1e-9);
+    if north_pole || south_pole || lon_margin >= 180.0 {
+        return vec![BoundingBox::new(-180.0, south, 180.0, north)];
+    }
+
+    // The longitudes span the circle minus the widest gap between sampled longitudes
+    let mut lons: Vec<f64> = samples.iter().map(|&(lon, _)| lon).collect();
+    lons.sort_by(f64::total_cmp);
+    let (mut gap, mut west, mut east) = (
+        lons[0] + 360.0 - lons[lons.len() - 1],
+        lons[0],
+        lons[lons.len() - 1],
+    );
+    for pair in lons.windows(2) {
+        if pair[1] - pair[0] > gap {
+            gap = pair[1] - pair[0];
+            west = pair[1];
+            east = pair[0];
+        }
+    }
+    if gap <= 2.0 * lon_margin {
+        return vec![BoundingBox::new(-180.0, south, 180.0, north)];
+    }
+    let (west, east) = (west - lon_margin, east + lon_margin);
+    if west <= east && west >= -180.0 && east <= 180.0 {
+        return vec![BoundingBox::new(west, south, east, north)];
+    }
+    // Wraps across ±180°: normalize both edges and split
+    let west = if west < -180.0 { west + 360.0 } else { west };
+    let east = if east > 180.0 { east - 360.0 } else { east };
+    vec![
+        BoundingBox::new(west, south, 180.0, north),
+        BoundingBox::new(-180.0, south, east, north),
+    ]
+}
+
+pub(crate) fn to_xyz(lon: f64, lat: f64) -> [f64; 3] {
+    let (lon, lat) = (lon.to_radians(), lat.to_radians());
+    [lat.cos() * lon.cos(), lat.cos() * lon.sin(), lat.sin()]
+}
+
+pub(crate) fn to_lon_lat(p: [f64; 3]) -> (f64, f64) {
+    let lon = p[1].atan2(p[0]).to_degrees();
+    let lat = p[2].atan2(p[0].hypot(p[1])).to_degrees();
+    (lon, lat)
+}
+
+fn normalize(p: [f64; 3]) -> [f64; 3] {
+    let norm = (p[0] * p[0] + p[1] * p[1] + p[2] * p[2]).sqrt();
+    [p[0] / norm, p[1] / norm, p[2] / norm]
+}
+
+/// Angle between two unit vectors, in degrees.
+fn angle(a: [f64; 3], b: [f64; 3]) -> f64 {
+    let cross = [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ];
+    let sin = (cross[0] * cross[0] + cross[1] * cross[1] + cross[2] * cross[2]).sqrt();
+    let cos = a[0] * b[0] + a[1] * b[1] + a[2] * b[2];
+    sin.atan2(cos).to_degrees()
+}
+
+#[cfg(feature = "h3")]
+impl GridCell for h3o::CellIndex {
+    fn vertices(&self) -> Vec<(f64, f64)> {
+        self.boundary()
+            .iter()
+            .map(|vertex| (vertex.lng(), vertex.lat()))
+            .collect()
+    }
+
+    fn contains_point(&self, lon: f64, lat: f64) -> bool {
+        h3o::LatLng::new(lat, lon).is_ok_and(|point| point.to_cell(self.resolution()) == *self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Square cell on the lon/lat grid, for testing the bounds without a grid crate.
+    struct Square {
+        west: f64,
+        south: f64,
+        size: f64,
+    }
+
+    impl GridCell for Square {
+        fn vertices(&self) -> Vec<(f64, f64)> {
+            let (w, s, size) = (self.west, self.south, self.size);
+            vec![(w, s), (w + size, s), (w + size, s + size), (w, s + size)]
+        }
+
+        fn contains_point(&self, lon: f64, lat: f64) -> bool {
+            let lon = if lon < self.west { lon + 360.0 } else { lon };
+            (self.west..self.west + self.size).contains(&lon)
+                && (self.south..self.south + self.size).contains(&lat)
+        }
+    }
+
+    #[test]
+    fn test_cell_bounds_cover_cells() {
+        let bounds = cell_bounds(&Square {
+            west: 10.0,
+            south: 40.0,
+            size: 2.0,
+        });
+        assert_eq!(bounds.len(), 1);
+        let bounds = &bounds[0];
+        // Great-circle edges between corners bulge poleward of the northern corners
+        assert!(bounds.xmin <= 10.0 && bounds.xmax >= 12.0);
+        assert!(bounds.ymin <= 40.0 && bounds.ymax > 42.0);
+        assert!(bounds.xmax - bounds.xmin < 3.0 && bounds.ymax - bounds.ymin < 3.0);
+
+        let wrapped = cell_bounds(&Square {
+            west: 179.0,
+            south: 0.0,
+            size: 2.0,
+        });
+        assert_eq!(wrapped.len(), 2);
+        assert!(wrapped[0].xmin <= 179.0 && wrapped[0].xmax == 180.0);
+        assert!(wrapped[1].xmin == -180.0 && wrapped[1].xmax >= -179.0);
+    }
+
+    #[test]
+    fn test_cell_query_filters_points_exactly() {
+        let query = CellQuery::new([Square {
+            west: 10.0,
+            south: 40.0,
+            size: 2.0,
+        }]);
+        assert!(query.matches(&BoundingBox::new(11.0, 41.0, 11.0, 41.0)));
+        // Inside the bounds' slack but outside the cell
+        assert!(!query.matches(&BoundingBox::new(11.0, 42.0001, 11.0, 42.0001)));
+        assert!(query.matches(&BoundingBox::new(11.0, 41.9, 13.0, 45.0)));
+        assert!(!query.matches(&BoundingBox::new(20.0, 41.0, 21.0, 42.0)));
+
+        let empty = CellQuery::<Square>::new([]);
+        assert!(!empty.matches(&BoundingBox::new(0.0, 0.0, 0.0, 0.0)));
+        assert_eq!(empty.dimension_range(0).1, f64::NEG_INFINITY);
+    }
+
+    #[cfg(feature = "h3")]
+    #[test]
+    fn test_h3_cell_query() {
+        use crate::search::spatial_search;
+        use crate::storage::{InMemoryLinker, NodeArena, NodeLinker};
+        use h3o::{LatLng, Resolution};
+
+        let cell = LatLng::new(48.8566, 2.3522)
+            .unwrap()
+            .to_cell(Resolution::Seven);
+        let mut arena = NodeArena::new();
+        let mut nodes = Vec::new();
+        for i in 0..400 {
+            let lon = 2.25 + (i % 20) as f64 * 0.01;
+            let lat = 48.80 + (i / 20) as f64 * 0.006;
+            nodes.push(arena.allocate(BoundingBox::new(lon, lat, lon, lat), i));
+        }
+        let mut linker = InMemoryLinker::new(&mut arena);
+        let root = crate::build::bulk_build(
+            &mut linker,
+            &mut nodes,
+            0,
+            &crate::build::BuildOptions::default(),
+        )
+        .unwrap();
+
+        let query = CellQuery::new([cell]);
+        let found = spatial_search(&linker, root, &query, 0);
+        assert!(!found.is_empty());
+        let expected = (0..400)
+            .filter(|&node| {
+                let point = linker.get_point(node);
+                let latlng = LatLng::new(point.ymin, point.xmin).unwrap();
+                latlng.to_cell(Resolution::Seven) == cell
+            })
+            .count();
+        assert_eq!(found.len(), expected);
+    }
+}
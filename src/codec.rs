@@ -0,0 +1,235 @@
+//! Fixed-width binary encoding for points and payloads written to disk.
+
+use crate::spatial::BoundingBox;
+
+/// Type with a fixed-size binary encoding, used for every on-disk record.
+///
+/// # Design Principle: Explicit byte layout
+/// Encodings are little-endian, fixed-width and independent of the platform (no `usize`,
+/// no native-endian floats), so a file written on one machine reads identically on any
+/// other. Fixed width keeps records addressable by index: record `i` lives at
+/// `header + i * record_size`, with no offset table.
+pub trait FixedCodec: Sized {
+    /// Number of bytes in the encoding.
+    const SIZE: usize;
+
+    /// Write the encoding into `buf`, which is exactly `SIZE` bytes long.
+    fn encode(&self, buf: &mut [u8]);
+
+    /// Read a value back from `buf`, which is exactly `SIZE` bytes long.
+    fn decode(buf: &[u8]) -> Self;
+}
+
+macro_rules! impl_fixed_codec_for_numbers {
+    ($($ty:ty),*) => {
+        $(
+            impl FixedCodec for $ty {
+                const SIZE: usize = std::mem::size_of::<$ty>();
+
+                fn encode(&self, buf: &mut [u8]) {
+                    buf.copy_from_slice(&self.to_le_bytes());
+                }
+
+                fn decode(buf: &[u8]) -> Self {
+                    <$ty>::from_le_bytes(buf.try_into().expect("buffer size matches SIZE"))
+                }
+            }
+        )*
+    };
+}
+
+impl_fixed_codec_for_numbers!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+
+/// Type whose in-memory representation is identical to its `FixedCodec` encoding on
+/// little-endian targets, so encoded bytes can be borrowed in place as `&Self`.
+///
+/// # Safety
+/// Implementors must guarantee that the type has no padding, an alignment of at most 8,
+/// that every bit pattern is a valid value, and that on little-endian targets its memory
+/// layout is byte-for-byte its `FixedCodec` encoding.
+pub unsafe trait ZeroCopy: FixedCodec {}
+
+macro_rules! impl_zero_copy {
+    ($($ty:ty),*) => {
+        $(
+            // SAFETY: primitive numbers are stored in native byte order, which is the
+            // little-endian encoding on the targets zero-copy access is enabled for.
+            unsafe impl ZeroCopy for $ty {}
+        )*
+    };
+}
+
+impl_zero_copy!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+
+// SAFETY: `BoundingBox` is `repr(C)` with four `f64` fields in encoding order.
+unsafe impl ZeroCopy for BoundingBox {}
+
+/// Encoded as `xmin, ymin, xmax, ymax`, 8 bytes each.
+impl FixedCodec for BoundingBox {
+    const SIZE: usize = 32;
+
+    fn encode(&self, buf: &mut [u8]) {
+        for (dim, value) in [self.xmin, self.ymin, self.xmax, self.ymax]
+            .iter()
+            .enumerate()
+        {
+            value.encode(&mut buf[dim * 8..dim * 8 + 8]);
+        }
+    }
+
+    fn decode(buf: &[u8]) -> Self {
+        BoundingBox::new(
+            f64::decode(&buf[0..8]),
+            f64::decode(&buf[8..16]),
+            f64::decode(&buf[16..24]),
+            f64::decode(&buf[24..32]),
+        )
+    }
+}
+
+/// Pairs are encoded as the first value followed by the second.
+impl<A: FixedCodec, B: FixedCodec> FixedCodec for (A, B) {
+    const SIZE: usize = A::SIZE + B::SIZE;
+
+    fn encode(&self, buf: &mut [u8]) {
+        self.0.encode(&mut buf[..A::SIZE]);
+        self.1.encode(&mut buf[A::SIZE..]);
+    }
+
+    fn decode(buf: &[u8]) -> Self {
+        (A::decode(&buf[..A::SIZE]), B::decode(&buf[A::SIZE..]))
+    }
+}
+
+/// Arrays are encoded element by element, so `[f64; N]` points persist as `N` floats.
+impl<A: FixedCodec, const N: usize> FixedCodec for [A; N] {
+    const SIZE: usize = A::SIZE * N;
+
+    fn encode(&self, buf: &mut [u8]) {
+        for (value, chunk) in self.iter().zip(buf.chunks_exact_mut(A::SIZE)) {
+            value.encode(chunk);
+        }
+    }
+
+    fn decode(buf: &[u8]) -> Self {
+        std::array::from_fn(|i| A::decode(&buf[i * A::SIZE..(i + 1) * A::SIZE]))
+    }
+}
+
+// SAFETY: array elements are laid out back to back with no padding, in encoding order.
+unsafe impl<A: ZeroCopy, const N: usize> ZeroCopy for [A; N] {}
+
+/// Encode a float as 8 big-endian bytes whose byte order is the value order.
+///
+/// Like Lucene's `NumericUtils`: positive values get their sign bit set and negative
+/// values have every bit flipped, so comparing encodings with `memcmp` (or `Ord` on the
+/// arrays) agrees with `f64::total_cmp`, including `-0.0 < 0.0` and the placement of NaN.
+pub fn f64_to_sortable_bytes(value: f64) -> [u8; 8] {
+    let bits = value.to_bits();
+    let sortable = if bits >> 63 == 0 {
+        bits | 1 << 63
+    } else {
+        !bits
+    };
+    sortable.to_be_bytes()
+}
+
+/// Decode a float from `f64_to_sortable_bytes`; `bytes` must be 8 bytes long.
+pub fn sortable_bytes_to_f64(bytes: &[u8]) -> f64 {
+    let sortable = u64::from_be_bytes(bytes.try_into().expect("sortable floats are 8 bytes"));
+    let bits = if sortable >> 63 == 1 {
+        sortable & !(1 << 63)
+    } else {
+        !sortable
+    };
+    f64::from_bits(bits)
+}
+
+/// Encode an integer as 8 big-endian bytes, sign bit flipped, whose byte order is the
+/// value order.
+pub fn i64_to_sortable_bytes(value: i64) -> [u8; 8] {
+    (value as u64 ^ 1 << 63).to_be_bytes()
+}
+
+/// Decode an integer from `i64_to_sortable_bytes`; `bytes` must be 8 bytes long.
+pub fn sortable_bytes_to_i64(bytes: &[u8]) -> i64 {
+    let sortable = u64::from_be_bytes(bytes.try_into().expect("sortable integers are 8 bytes"));
+    (sortable ^ 1 << 63) as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixed_codec_roundtrip() {
+        let record = (BoundingBox::new(-1.5, 2.0, 3.25, f64::MAX), 42u32);
+        let mut buf = vec![0u8; <(BoundingBox, u32)>::SIZE];
+        record.encode(&mut buf);
+
+        assert_eq!(buf.len(), 36);
+        assert_eq!(&buf[32..], &[42, 0, 0, 0]); // little-endian payload
+        assert_eq!(<(BoundingBox, u32)>::decode(&buf), record);
+    }
+
+    #[test]
+    fn test_encodings_are_little_endian_on_every_target() {
+        fn encoded<V: FixedCodec>(value: V) -> Vec<u8> {
+            let mut buf = vec![0u8; V::SIZE];
+            value.encode(&mut buf);
+            buf
+        }
+        // Spelled out byte by byte, so a host-order encoding fails on big-endian targets
+        assert_eq!(encoded(0x0102_0304u32), [4, 3, 2, 1]);
+        assert_eq!(encoded(-2i16), [0xfe, 0xff]);
+        assert_eq!(encoded(1.0f32), [0, 0, 0x80, 0x3f]);
+        assert_eq!(encoded(-2.0f64), [0, 0, 0, 0, 0, 0, 0, 0xc0]);
+        assert_eq!(encoded([1u16, 0x0302]), [1, 0, 2, 3]);
+        assert_eq!(encoded((7u8, 0x0a0bu16)), [7, 0x0b, 0x0a]);
+        assert_eq!(
+            encoded(BoundingBox::new(1.0, 0.0, 0.0, 0.0))[..8],
+            [0, 0, 0, 0, 0, 0, 0xf0, 0x3f]
+        );
+        assert_eq!(
+            <[f64; 3]>::decode(&encoded([1.5, -0.0, 4.0])),
+            [1.5, -0.0, 4.0]
+        );
+    }
+
+    #[test]
+    fn test_sortable_bytes_order_like_values() {
+        let floats = [
+            f64::NEG_INFINITY,
+            f64::MIN,
+            -1.5,
+            -f64::MIN_POSITIVE,
+            -0.0,
+            0.0,
+            f64::MIN_POSITIVE,
+            1.0,
+            1.5,
+            f64::MAX,
+            f64::INFINITY,
+            f64::NAN,
+        ];
+        for pair in floats.windows(2) {
+            let (a, b) = (
+                f64_to_sortable_bytes(pair[0]),
+                f64_to_sortable_bytes(pair[1]),
+            );
+            assert!(a < b, "{} should sort before {}", pair[0], pair[1]);
+        }
+        for value in floats {
+            let decoded = sortable_bytes_to_f64(&f64_to_sortable_bytes(value));
+            assert_eq!(decoded.to_bits(), value.to_bits());
+        }
+
+        let integers = [i64::MIN, -300, -1, 0, 1, 255, 256, i64::MAX];
+        for pair in integers.windows(2) {
+            assert!(i64_to_sortable_bytes(pair[0]) < i64_to_sortable_bytes(pair[1]));
+        }
+        for value in integers {
+            assert_eq!(sortable_bytes_to_i64(&i64_to_sortable_bytes(value)), value);
+        }
+    }
+}
@@ -0,0 +1,198 @@
+//! Codecs for the leaf blocks of block trees.
+
+use std::fmt;
+use std::io;
+
+/// How the leaf blocks of a block tree are compressed, chosen in `BkdWriterOptions` and
+/// recorded in the file header so readers need not be told.
+///
+/// LZ4 decompresses fastest and suits latency-bound serving; Zstd shrinks files further at
+/// some read cost, more so at higher levels. Codecs are compiled in by the `lz4` and
+/// `zstd` features: every variant is always known, so a header naming a codec this build
+/// lacks is reported as `Unsupported` rather than as a damaged file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    /// Leaf blocks are stored as plain records.
+    #[default]
+    None,
+    /// LZ4 block format (feature `lz4`).
+    Lz4,
+    /// Zstandard at the given level, 1 to 22, or negative for faster modes (feature `zstd`).
+    Zstd { level: i32 },
+}
+
+impl Compression {
+    /// Check if this build can compress and decompress with the codec.
+    pub fn is_available(&self) -> bool {
+        match self {
+            Compression::None => true,
+            Compression::Lz4 => cfg!(feature = "lz4"),
+            Compression::Zstd { .. } => cfg!(feature = "zstd"),
+        }
+    }
+
+    /// Codec id and level, as stored in a header.
+    pub(crate) fn to_parts(self) -> (u32, i32) {
+        match self {
+            Compression::None => (0, 0),
+            Compression::Lz4 => (1, 0),
+            Compression::Zstd { level } => (2, level),
+        }
+    }
+
+    pub(crate) fn from_parts(codec: u32, level: i32) -> io::Result<Self> {
+        match codec {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Lz4),
+            2 => Ok(Compression::Zstd { level }),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown compression codec {codec}"),
+            )),
+        }
+    }
+
+    fn unavailable(&self) -> io::Error {
+        let feature = match self {
+            Compression::Zstd { .. } => "zstd",
+            _ => "lz4",
+        };
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{self} compression requires the `{feature}` feature"),
+        )
+    }
+
+    /// Fail with `Unsupported` if this build lacks the codec.
+    pub(crate) fn check_available(&self) -> io::Result<()> {
+        if self.is_available() {
+            Ok(())
+        } else {
+            Err(self.unavailable())
+        }
+    }
+
+    /// Compress `bytes` into a new buffer.
+    pub(crate) fn compress(&self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(bytes.to_vec()),
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => Ok(lz4_flex::block::compress(bytes)),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd { level } => zstd::bulk::compress(bytes, *level),
+            #[allow(unreachable_patterns)]
+            _ => Err(self.unavailable()),
+        }
+    }
+
+    /// Decompress `bytes` into `output`, which must come out exactly `len` bytes long.
+    pub(crate) fn decompress(
+        &self,
+        bytes: &[u8],
+        output: &mut Vec<u8>,
+        len: usize,
+    ) -> io::Result<()> {
+        self.decompress_within(bytes, output, len)?;
+        if output.len() != len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "compressed block holds {} bytes, expected {len}",
+                    output.len()
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Decompress `bytes` into `output`, for blocks whose length is only known to be at
+    /// most `max_len`.
+    pub(crate) fn decompress_within(
+        &self,
+        bytes: &[u8],
+        output: &mut Vec<u8>,
+        max_len: usize,
+    ) -> io::Result<()> {
+        output.resize(max_len, 0);
+        let written = match self {
+            Compression::None => {
+                if bytes.len() > max_len {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "block holds {} bytes, at most {max_len} expected",
+                            bytes.len()
+                        ),
+                    ));
+                }
+                output[..bytes.len()].copy_from_slice(bytes);
+                bytes.len()
+            }
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => lz4_flex::block::decompress_into(bytes, output)
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?,
+            #[cfg(feature = "zstd")]
+            Compression::Zstd { .. } => zstd::bulk::decompress_to_buffer(bytes, &mut output[..])
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?,
+            #[allow(unreachable_patterns)]
+            _ => return Err(self.unavailable()),
+        };
+        output.truncate(written);
+        Ok(())
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Compression::None => write!(f, "none"),
+            Compression::Lz4 => write!(f, "lz4"),
+            Compression::Zstd { level } => write!(f, "zstd (level {level})"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codecs_round_trip_when_available() {
+        let bytes: Vec<u8> = (0..4000u32).flat_map(|i| (i % 50).to_le_bytes()).collect();
+        for compression in [
+            Compression::None,
+            Compression::Lz4,
+            Compression::Zstd { level: 3 },
+        ] {
+            let (codec, level) = compression.to_parts();
+            assert_eq!(Compression::from_parts(codec, level).unwrap(), compression);
+            if !compression.is_available() {
+                let error = compression.compress(&bytes).unwrap_err();
+                assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+                continue;
+            }
+            let compressed = compression.compress(&bytes).unwrap();
+            if compression != Compression::None {
+                assert!(compressed.len() < bytes.len() / 4);
+            }
+            let mut output = Vec::new();
+            compression
+                .decompress(&compressed, &mut output, bytes.len())
+                .unwrap();
+            assert_eq!(output, bytes);
+            let short = compression.decompress(&compressed, &mut output, bytes.len() + 1);
+            assert_eq!(short.unwrap_err().kind(), io::ErrorKind::InvalidData);
+            compression
+                .decompress_within(&compressed, &mut output, bytes.len() + 100)
+                .unwrap();
+            assert_eq!(output, bytes);
+            let cut = compression.decompress_within(&compressed, &mut output, bytes.len() - 1);
+            assert_eq!(cut.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        }
+        assert!(Compression::from_parts(9, 0).is_err());
+        assert_eq!(
+            Compression::Zstd { level: 19 }.to_string(),
+            "zstd (level 19)"
+        );
+    }
+}
@@ -0,0 +1,148 @@
+//! Copying a tree from one storage backend into another.
+
+use crate::build::{BuildOptions, bulk_build};
+use crate::search::export_all;
+use crate::spatial::Point;
+use crate::storage::{AllocatingLinker, NodeLinker};
+
+/// Shape of the tree `copy_tree` writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CopyMode {
+    /// Recreate the source tree node for node, with the same links.
+    #[default]
+    Preserve,
+    /// Rebuild a balanced tree over the entries with `bulk_build`, rooted at depth 0.
+    Rebalance,
+}
+
+/// Copy the tree at `src_root` into `dst` and return the new root.
+///
+/// Moves a tree between backends, e.g. from a `NodeArena` into a memory-mapped file, or
+/// rebalances it on the way with `CopyMode::Rebalance`, which suits trees grown by many
+/// `insert_node` calls. Both modes walk the source with an explicit stack, so deep trees
+/// cannot overflow the call stack. `Preserve` keeps the split dimensions of the source,
+/// so search the copy from the same depth.
+///
+/// The copy is not recorded as the root of `dst`: call `RootedLinker::set_root` with the
+/// result where the backend stores its root.
+pub fn copy_tree<P, T, S, D>(
+    src: &S,
+    src_root: Option<S::NodeRef>,
+    dst: &mut D,
+    mode: CopyMode,
+) -> Option<D::NodeRef>
+where
+    P: Point + Clone,
+    T: Clone,
+    S: NodeLinker<P, T>,
+    D: AllocatingLinker<P, T>,
+{
+    match mode {
+        CopyMode::Preserve => {
+            let root = src_root?;
+            let copy_root = copy_node(src, root, dst);
+            let mut stack = vec![(root, copy_root)];
+            while let Some((node, copy)) = stack.pop() {
+                if let Some(left) = src.get_left(node) {
+                    let left_copy = copy_node(src, left, dst);
+                    dst.link_left(copy, left_copy);
+                    stack.push((left, left_copy));
+                }
+                if let Some(right) = src.get_right(node) {
+                    let right_copy = copy_node(src, right, dst);
+                    dst.link_right(copy, right_copy);
+                    stack.push((right, right_copy));
+                }
+            }
+            Some(copy_root)
+        }
+        CopyMode::Rebalance => {
+            let mut nodes: Vec<D::NodeRef> = export_all(src, src_root)
+                .map(|(point, data)| dst.allocate(point, data))
+                .collect();
+            bulk_build(dst, &mut nodes, 0, &BuildOptions::default())
+                .expect("builds without a cancellation token are never cancelled")
+        }
+    }
+}
+
+fn copy_node<P: Point + Clone, T: Clone, S: NodeLinker<P, T>, D: AllocatingLinker<P, T>>(
+    src: &S,
+    node: S::NodeRef,
+    dst: &mut D,
+) -> D::NodeRef {
+    dst.allocate(src.get_point(node).clone(), src.get_data(node).clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::trees_equal;
+    use crate::index::SpatialIndex;
+    use crate::search::{insert_node, spatial_search};
+    use crate::spatial::BoundingBox;
+    use crate::storage::{InMemoryLinker, NodeArena, RootedLinker};
+
+    fn depth<P: Point, T, L: NodeLinker<P, T>>(linker: &L, root: Option<L::NodeRef>) -> usize {
+        let mut deepest = 0;
+        let mut stack: Vec<(L::NodeRef, usize)> = root.into_iter().map(|node| (node, 1)).collect();
+        while let Some((node, level)) = stack.pop() {
+            deepest = deepest.max(level);
+            stack.extend(linker.get_left(node).map(|child| (child, level + 1)));
+            stack.extend(linker.get_right(node).map(|child| (child, level + 1)));
+        }
+        deepest
+    }
+
+    #[test]
+    fn test_copy_preserves_or_rebalances() {
+        let mut arena = NodeArena::new();
+        let refs: Vec<usize> = (0..100)
+            .map(|i| {
+                let x = i as f64;
+                arena.allocate(BoundingBox::new(x, 100.0 - x, x + 1.0, 101.0 - x), i)
+            })
+            .collect();
+        let mut linker = InMemoryLinker::new(&mut arena);
+        let root = insert_node(&mut linker, None, refs[0], 0);
+        for &node in &refs[1..] {
+            insert_node(&mut linker, Some(root), node, 0);
+        }
+
+        let mut preserved = SpatialIndex::new();
+        let copy = copy_tree(&linker, Some(root), &mut preserved, CopyMode::Preserve);
+        preserved.set_root(copy);
+        assert!(trees_equal(
+            &linker,
+            Some(root),
+            &preserved,
+            preserved.get_root()
+        ));
+
+        let mut rebalanced = SpatialIndex::new();
+        let copy = copy_tree(&linker, Some(root), &mut rebalanced, CopyMode::Rebalance);
+        rebalanced.set_root(copy);
+        assert_eq!(rebalanced.len(), 100);
+        assert!(depth(&rebalanced, copy) < depth(&linker, Some(root)));
+
+        let query = BoundingBox::new(10.5, 0.0, 20.5, 100.0);
+        let mut expected: Vec<i32> = spatial_search(&linker, Some(root), &query, 0)
+            .into_iter()
+            .map(|node| *linker.get_data(node))
+            .collect();
+        let mut found: Vec<i32> = rebalanced
+            .search(&query)
+            .into_iter()
+            .map(|node| *rebalanced.get_data(node))
+            .collect();
+        expected.sort_unstable();
+        found.sort_unstable();
+        assert_eq!(found, expected);
+
+        let mut empty = SpatialIndex::new();
+        for mode in [CopyMode::Preserve, CopyMode::Rebalance] {
+            assert_eq!(copy_tree(&linker, None, &mut empty, mode), None);
+        }
+        assert!(empty.is_empty());
+    }
+}
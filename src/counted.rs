@@ -0,0 +1,453 @@
+//! Subtree cardinalities kept up to date as a tree changes.
+
+use crate::codec::{f64_to_sortable_bytes, sortable_bytes_to_f64};
+use crate::spatial::Point;
+use crate::storage::NodeLinker;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::marker::PhantomData;
+
+/// A linker that knows how many live entries each subtree holds.
+pub trait CountingLinker<P: Point, T>: NodeLinker<P, T> {
+    /// Number of live entries in the subtree rooted at `node`, `node` included.
+    fn subtree_count(&self, node: Self::NodeRef) -> usize;
+}
+
+/// What `CountedLinker` records per node.
+#[derive(Debug, Clone, Copy)]
+struct NodeCount<R> {
+    count: usize,
+    parent: Option<R>,
+    deleted: bool,
+}
+
+/// Wraps a linker and maintains the live-entry count of every subtree.
+///
+/// # Architecture
+/// Counts live beside the tree in a map, so any backend can be counted:
+/// - Every `link_left`/`link_right` records the child's parent and adds the change in the
+///   child subtree's count to each ancestor, so `insert_node`, `bulk_build` and anything
+///   else that links through the wrapper keeps the counts exact in O(depth) per link
+/// - `delete` is a tombstone, as in `VersionedIndex`: the node stays linked and searchable,
+///   but stops counting. Filter results with `is_deleted`
+/// - Nodes the wrapper has not seen count as live leaves
+///
+/// With counts, `select` finds the k-th live entry in pre-order and `rank` the position of
+/// an entry, both in O(depth): pre-order is the order of `spatial_search` and `export_all`,
+/// so this pages through a tree by offset, samples it uniformly, or splits it into parts of
+/// equal size.
+///
+/// Along one dimension, `count_below`, `select_value` and `quantile` answer questions like
+/// "how many boxes have `ymin < v`" or "what is the median `xmin`" without a full scan, for
+/// statistics and adaptive tiling.
+pub struct CountedLinker<P: Point, T, L: NodeLinker<P, T>> {
+    inner: L,
+    counts: HashMap<L::NodeRef, NodeCount<L::NodeRef>>,
+    _marker: PhantomData<fn() -> (P, T)>,
+}
+
+impl<P: Point, T, L: NodeLinker<P, T>> CountedLinker<P, T, L>
+where
+    L::NodeRef: Eq + Hash,
+{
+    /// Wrap `inner`, counting the tree already linked at `root`, if any.
+    pub fn new(inner: L, root: Option<L::NodeRef>) -> Self {
+        let mut linker = CountedLinker {
+            inner,
+            counts: HashMap::new(),
+            _marker: PhantomData,
+        };
+
+        // Post-order: a node is counted once both children are
+        let mut stack: Vec<(L::NodeRef, bool)> =
+            root.map(|node| (node, false)).into_iter().collect();
+        while let Some((node, children_done)) = stack.pop() {
+            let children = [linker.inner.get_left(node), linker.inner.get_right(node)];
+            if !children_done {
+                stack.push((node, true));
+                stack.extend(children.into_iter().flatten().map(|child| (child, false)));
+                continue;
+            }
+            let mut count = 1;
+            for child in children.into_iter().flatten() {
+                count += linker.subtree_count(child);
+                linker.entry(child).parent = Some(node);
+            }
+            linker.entry(node).count = count;
+        }
+        linker
+    }
+
+    /// Mark `node` deleted, removing it from the counts of its subtree and ancestors.
+    /// Returns false if it was already deleted.
+    pub fn delete(&mut self, node: L::NodeRef) -> bool {
+        let entry = self.entry(node);
+        if entry.deleted {
+            return false;
+        }
+        entry.deleted = true;
+        self.add_to_path(Some(node), -1);
+        true
+    }
+
+    /// Check if `node` was deleted.
+    pub fn is_deleted(&self, node: L::NodeRef) -> bool {
+        self.counts.get(&node).is_some_and(|entry| entry.deleted)
+    }
+
+    /// The `index`-th live entry of the tree at `root` in pre-order, counting from 0.
+    pub fn select(&self, root: Option<L::NodeRef>, mut index: usize) -> Option<L::NodeRef> {
+        let mut current = root;
+        while let Some(node) = current {
+            if !self.is_deleted(node) {
+                if index == 0 {
+                    return Some(node);
+                }
+                index -= 1;
+            }
+            let left = self.inner.get_left(node);
+            let left_count = left.map_or(0, |child| self.subtree_count(child));
+            if index < left_count {
+                current = left;
+            } else {
+                index -= left_count;
+                current = self.inner.get_right(node);
+            }
+        }
+        None
+    }
+
+    /// Number of live entries before `node` in pre-order, within the tree it belongs to.
+    pub fn rank(&self, node: L::NodeRef) -> usize {
+        let mut rank = 0;
+        let mut child = node;
+        while let Some(parent) = self.counts.get(&child).and_then(|entry| entry.parent) {
+            // The parent comes first, then its left subtree, then its right subtree
+            rank += usize::from(!self.is_deleted(parent));
+            if self.inner.get_right(parent) == Some(child) {
+                rank += self
+                    .inner
+                    .get_left(parent)
+                    .map_or(0, |left| self.subtree_count(left));
+            }
+            child = parent;
+        }
+        rank
+    }
+
+    /// Number of live entries of the tree at `root` whose value in `dim` is below `value`.
+    ///
+    /// Trees built by `insert_node` or `bulk_build` keep smaller values left of a split
+    /// and the rest right, so at a node splitting on `dim` one side is decided without
+    /// visiting it: a left subtree below `value` adds its count, a right subtree at or
+    /// above it adds nothing. Only the other side is searched, which makes the count
+    /// sublinear in the size of the tree.
+    pub fn count_below(
+        &self,
+        root: Option<L::NodeRef>,
+        depth: usize,
+        dim: usize,
+        value: f64,
+    ) -> usize {
+        let mut count = 0;
+        let mut stack: Vec<(L::NodeRef, usize)> =
+            root.map(|node| (node, depth)).into_iter().collect();
+        while let Some((node, depth)) = stack.pop() {
+            let point = self.inner.get_point(node);
+            let own = point.get_dimension(dim);
+            if own < value && !self.is_deleted(node) {
+                count += 1;
+            }
+            let left = self.inner.get_left(node);
+            let right = self.inner.get_right(node);
+            if depth % point.dimensions() != dim {
+                stack.extend(left.map(|child| (child, depth + 1)));
+                stack.extend(right.map(|child| (child, depth + 1)));
+            } else if own < value {
+                count += left.map_or(0, |child| self.subtree_count(child));
+                stack.extend(right.map(|child| (child, depth + 1)));
+            } else {
+                stack.extend(left.map(|child| (child, depth + 1)));
+            }
+        }
+        count
+    }
+
+    /// Value in `dim` of the live entry at position `rank` (from 0) when the entries of
+    /// the tree at `root` are sorted by that value, or `None` if there are not that many.
+    ///
+    /// Bisects the ordered bit patterns of `f64` with `count_below`, so it takes at most 64
+    /// counts and returns a value held by an entry. Entries with a NaN value are counted but
+    /// never selected.
+    pub fn select_value(
+        &self,
+        root: Option<L::NodeRef>,
+        depth: usize,
+        dim: usize,
+        rank: usize,
+    ) -> Option<f64> {
+        let total = root.map_or(0, |node| self.subtree_count(node));
+        if rank >= total {
+            return None;
+        }
+        // The largest value with at most `rank` entries below it
+        let mut low = sortable(f64::NEG_INFINITY);
+        let mut high = sortable(f64::INFINITY);
+        while low < high {
+            let middle = low + (high - low).div_ceil(2);
+            if self.count_below(root, depth, dim, from_sortable(middle)) <= rank {
+                low = middle;
+            } else {
+                high = middle - 1;
+            }
+        }
+        Some(from_sortable(low))
+    }
+
+    /// The `q`-quantile of the values in `dim`: the value at rank `floor(q * (n - 1))` of
+    /// the `n` live entries, so `0.5` is the (lower) median. `None` for an empty tree.
+    ///
+    /// # Panics
+    /// Panics if `q` is not within `[0, 1]`.
+    pub fn quantile(
+        &self,
+        root: Option<L::NodeRef>,
+        depth: usize,
+        dim: usize,
+        q: f64,
+    ) -> Option<f64> {
+        assert!((0.0..=1.0).contains(&q), "quantile must be within [0, 1]");
+        let total = root.map_or(0, |node| self.subtree_count(node));
+        let rank = (total.checked_sub(1)? as f64 * q).floor() as usize;
+        self.select_value(root, depth, dim, rank)
+    }
+
+    /// The wrapped linker.
+    pub fn inner(&self) -> &L {
+        &self.inner
+    }
+
+    /// Unwrap the linker, dropping the counts.
+    pub fn into_inner(self) -> L {
+        self.inner
+    }
+
+    fn entry(&mut self, node: L::NodeRef) -> &mut NodeCount<L::NodeRef> {
+        self.counts.entry(node).or_insert(NodeCount {
+            count: 1,
+            parent: None,
+            deleted: false,
+        })
+    }
+
+    /// Add `delta` to the counts of `node` and all its ancestors.
+    fn add_to_path(&mut self, mut node: Option<L::NodeRef>, delta: isize) {
+        while let Some(current) = node {
+            let entry = self.entry(current);
+            entry.count = entry
+                .count
+                .checked_add_signed(delta)
+                .expect("subtree counts stay non-negative");
+            node = entry.parent;
+        }
+    }
+
+    /// Replace a child link of `parent`, moving the counts with it.
+    fn relink(&mut self, parent: L::NodeRef, old: Option<L::NodeRef>, child: L::NodeRef) {
+        let mut delta = self.subtree_count(child) as isize;
+        if let Some(old) = old {
+            delta -= self.subtree_count(old) as isize;
+            self.entry(old).parent = None;
+        }
+        self.entry(child).parent = Some(parent);
+        self.add_to_path(Some(parent), delta);
+    }
+}
+
+/// `f64` as an integer in the same order, as in `f64_to_sortable_bytes`.
+fn sortable(value: f64) -> u64 {
+    u64::from_be_bytes(f64_to_sortable_bytes(value))
+}
+
+fn from_sortable(sortable: u64) -> f64 {
+    sortable_bytes_to_f64(&sortable.to_be_bytes())
+}
+
+impl<P: Point, T, L: NodeLinker<P, T>> NodeLinker<P, T> for CountedLinker<P, T, L>
+where
+    L::NodeRef: Eq + Hash,
+{
+    type NodeRef = L::NodeRef;
+
+    fn link_left(&mut self, parent: Self::NodeRef, child: Self::NodeRef) {
+        let old = self.inner.get_left(parent);
+        self.inner.link_left(parent, child);
+        self.relink(parent, old, child);
+    }
+
+    fn link_right(&mut self, parent: Self::NodeRef, child: Self::NodeRef) {
+        let old = self.inner.get_right(parent);
+        self.inner.link_right(parent, child);
+        self.relink(parent, old, child);
+    }
+
+    fn get_left(&self, node: Self::NodeRef) -> Option<Self::NodeRef> {
+        self.inner.get_left(node)
+    }
+
+    fn get_right(&self, node: Self::NodeRef) -> Option<Self::NodeRef> {
+        self.inner.get_right(node)
+    }
+
+    fn get_point(&self, node: Self::NodeRef) -> &P {
+        self.inner.get_point(node)
+    }
+
+    fn get_data(&self, node: Self::NodeRef) -> &T {
+        self.inner.get_data(node)
+    }
+}
+
+impl<P: Point, T, L: NodeLinker<P, T>> CountingLinker<P, T> for CountedLinker<P, T, L>
+where
+    L::NodeRef: Eq + Hash,
+{
+    fn subtree_count(&self, node: Self::NodeRef) -> usize {
+        self.counts.get(&node).map_or(1, |entry| entry.count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::{BuildOptions, bulk_build};
+    use crate::search::{export_all, insert_node, spatial_search};
+    use crate::spatial::BoundingBox;
+    use crate::storage::{InMemoryLinker, NodeArena};
+
+    /// Live entries of the subtree at `node`, counted the slow way.
+    fn count_live<L: NodeLinker<BoundingBox, u32>>(
+        linker: &CountedLinker<BoundingBox, u32, L>,
+        node: L::NodeRef,
+    ) -> usize
+    where
+        L::NodeRef: Eq + Hash,
+    {
+        let mut stack = vec![node];
+        let mut live = 0;
+        while let Some(node) = stack.pop() {
+            live += usize::from(!linker.is_deleted(node));
+            stack.extend(linker.get_left(node));
+            stack.extend(linker.get_right(node));
+        }
+        live
+    }
+
+    #[test]
+    fn test_counts_follow_inserts_and_deletes() {
+        let mut arena = NodeArena::new();
+        let refs: Vec<usize> = (0..300u32)
+            .map(|i| {
+                let x = ((i * 37) % 101) as f64;
+                let y = ((i * 53) % 97) as f64;
+                arena.allocate(BoundingBox::new(x, y, x + 1.0, y + 1.0), i)
+            })
+            .collect();
+        let mut linker = CountedLinker::new(InMemoryLinker::new(&mut arena), None);
+        let root = insert_node(&mut linker, None, refs[0], 0);
+        for &node in &refs[1..] {
+            insert_node(&mut linker, Some(root), node, 0);
+        }
+        assert_eq!(linker.subtree_count(root), 300);
+
+        for &node in refs.iter().step_by(7) {
+            assert!(linker.delete(node));
+        }
+        assert!(!linker.delete(refs[0]));
+        assert_eq!(linker.subtree_count(root), 300 - 43);
+        for &node in &refs {
+            assert_eq!(linker.subtree_count(node), count_live(&linker, node));
+        }
+
+        // select and rank agree with the pre-order of a full search, tombstones skipped
+        let everything = BoundingBox::new(-1.0, -1.0, 200.0, 200.0);
+        let live: Vec<usize> = spatial_search(&linker, Some(root), &everything, 0)
+            .into_iter()
+            .filter(|&node| !linker.is_deleted(node))
+            .collect();
+        for (index, &node) in live.iter().enumerate() {
+            assert_eq!(linker.select(Some(root), index), Some(node));
+            assert_eq!(linker.rank(node), index);
+        }
+        assert_eq!(linker.select(Some(root), live.len()), None);
+    }
+
+    #[test]
+    fn test_counts_existing_and_bulk_built_trees() {
+        let mut arena = NodeArena::new();
+        let mut nodes: Vec<usize> = (0..100u32)
+            .map(|i| arena.allocate(BoundingBox::new(i as f64, 0.0, i as f64 + 1.0, 1.0), i))
+            .collect();
+        let root = {
+            let mut linker = CountedLinker::new(InMemoryLinker::new(&mut arena), None);
+            let root = bulk_build(&mut linker, &mut nodes, 0, &BuildOptions::default())
+                .unwrap()
+                .unwrap();
+            assert_eq!(linker.subtree_count(root), 100);
+            let left = linker.get_left(root).unwrap();
+            assert_eq!(linker.subtree_count(left), count_live(&linker, left));
+            root
+        };
+
+        // Counting an already linked tree
+        let linker = CountedLinker::new(InMemoryLinker::new(&mut arena), Some(root));
+        assert_eq!(linker.subtree_count(root), 100);
+        let order: Vec<u32> = export_all(linker.inner(), Some(root))
+            .map(|(_, data)| data)
+            .collect();
+        let middle = linker.select(Some(root), 50).unwrap();
+        assert_eq!(*linker.get_data(middle), order[50]);
+        assert_eq!(linker.rank(middle), 50);
+    }
+
+    #[test]
+    fn test_counts_and_quantiles_along_a_dimension() {
+        let mut arena = NodeArena::new();
+        let mut nodes: Vec<usize> = (0..1000u32)
+            .map(|i| {
+                let x = ((i * 7919) % 1009) as f64 / 4.0;
+                let y = ((i * 104_729) % 997) as f64 - 500.0;
+                arena.allocate(BoundingBox::new(x, y, x + 1.0, y + 3.0), i)
+            })
+            .collect();
+        let mut linker = CountedLinker::new(InMemoryLinker::new(&mut arena), None);
+        let root = bulk_build(&mut linker, &mut nodes, 0, &BuildOptions::default()).unwrap();
+        for &node in nodes.iter().step_by(3) {
+            linker.delete(node);
+        }
+        let live: Vec<BoundingBox> = nodes
+            .iter()
+            .filter(|&&node| !linker.is_deleted(node))
+            .map(|&node| linker.get_point(node).clone())
+            .collect();
+
+        for dim in 0..4 {
+            let mut values: Vec<f64> = live.iter().map(|point| point.get_dimension(dim)).collect();
+            values.sort_by(f64::total_cmp);
+            for probe in [-1000.0, -3.5, 0.0, 17.25, 120.0, 1000.0] {
+                let expected = values.iter().filter(|&&value| value < probe).count();
+                assert_eq!(linker.count_below(root, 0, dim, probe), expected);
+            }
+            for rank in [0, 1, 100, values.len() / 2, values.len() - 1] {
+                assert_eq!(linker.select_value(root, 0, dim, rank), Some(values[rank]));
+            }
+            assert_eq!(linker.select_value(root, 0, dim, values.len()), None);
+            assert_eq!(
+                linker.quantile(root, 0, dim, 0.5),
+                Some(values[(values.len() - 1) / 2])
+            );
+            assert_eq!(linker.quantile(root, 0, dim, 1.0), values.last().copied());
+        }
+        assert_eq!(linker.quantile(None, 0, 0, 0.5), None);
+    }
+}
@@ -0,0 +1,206 @@
+//! Soft deletes: a bitmap of deleted nodes that searches skip and rebuilds drop.
+
+use crate::query::SpatialQuery;
+use crate::search::spatial_search;
+use crate::spatial::Point;
+use crate::spill::SpillRef;
+use crate::storage::NodeLinker;
+use std::marker::PhantomData;
+
+/// Set of deleted nodes, one bit per node reference.
+///
+/// # Architecture Decision: mark, don't unlink
+/// Removing a node from a k-d tree means rebalancing the subtree below it, and frozen
+/// trees, segments and node files cannot be changed at all. A deletion bitmap sits beside
+/// the tree instead:
+/// - `delete` sets a bit in O(1) and leaves the tree untouched, so deleted nodes still
+///   route searches to their subtrees
+/// - Searches such as `spatial_search_live` drop marked nodes from their results
+/// - A rebuild, `rebuild_purged` or `FrozenIndex::purge`, or a segment merge leaves the
+///   marked nodes behind, and the bitmap of the result starts out empty
+///
+/// References are mapped to bit positions with `SpillRef`, so the bitmap takes memory in
+/// proportion to the largest deleted reference. That suits the dense handles of arenas,
+/// frozen indexes and node files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeletionBitmap<R = usize> {
+    words: Vec<u64>,
+    len: usize,
+    _marker: PhantomData<fn(R) -> R>,
+}
+
+impl<R: SpillRef> DeletionBitmap<R> {
+    /// Create a bitmap with no node deleted.
+    pub fn new() -> Self {
+        DeletionBitmap {
+            words: Vec::new(),
+            len: 0,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Mark `node` deleted. Returns `false` if it already was.
+    pub fn delete(&mut self, node: R) -> bool {
+        let (word, bit) = position(node);
+        if self.words.len() <= word {
+            self.words.resize(word + 1, 0);
+        }
+        let newly = self.words[word] & bit == 0;
+        self.words[word] |= bit;
+        self.len += newly as usize;
+        newly
+    }
+
+    /// Clear the mark on `node`. Returns `false` if it was not deleted.
+    pub fn restore(&mut self, node: R) -> bool {
+        let (word, bit) = position(node);
+        let Some(slot) = self.words.get_mut(word) else {
+            return false;
+        };
+        let was = *slot & bit != 0;
+        *slot &= !bit;
+        self.len -= was as usize;
+        was
+    }
+
+    /// Check if `node` is marked deleted.
+    pub fn is_deleted(&self, node: R) -> bool {
+        let (word, bit) = position(node);
+        self.words.get(word).is_some_and(|slot| slot & bit != 0)
+    }
+
+    /// Number of deleted nodes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Check if no node is deleted.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Clear every mark, as after a purge.
+    pub fn clear(&mut self) {
+        self.words.clear();
+        self.len = 0;
+    }
+
+    /// Deleted nodes in increasing order of their `SpillRef` value.
+    pub fn iter(&self) -> impl Iterator<Item = R> + '_ {
+        self.words.iter().enumerate().flat_map(|(word, &bits)| {
+            (0..64)
+                .filter(move |bit| bits & (1 << bit) != 0)
+                .map(move |bit| R::from_u64(word as u64 * 64 + bit))
+        })
+    }
+}
+
+impl<R: SpillRef> Default for DeletionBitmap<R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<R: SpillRef> FromIterator<R> for DeletionBitmap<R> {
+    fn from_iter<I: IntoIterator<Item = R>>(nodes: I) -> Self {
+        let mut bitmap = DeletionBitmap::new();
+        for node in nodes {
+            bitmap.delete(node);
+        }
+        bitmap
+    }
+}
+
+/// Word index and bit mask of a node.
+fn position<R: SpillRef>(node: R) -> (usize, u64) {
+    let value = node.to_u64();
+    let word = usize::try_from(value / 64).expect("node reference too large for a bitmap");
+    (word, 1 << (value % 64))
+}
+
+/// Find all nodes matching `query` that are not marked in `deleted`, in the order of
+/// `spatial_search`.
+pub fn spatial_search_live<P, T, L, Q>(
+    linker: &L,
+    root: Option<L::NodeRef>,
+    query: &Q,
+    depth: usize,
+    deleted: &DeletionBitmap<L::NodeRef>,
+) -> Vec<L::NodeRef>
+where
+    P: Point,
+    L: NodeLinker<P, T>,
+    L::NodeRef: SpillRef,
+    Q: SpatialQuery<P>,
+{
+    let mut results = spatial_search(linker, root, query, depth);
+    if !deleted.is_empty() {
+        results.retain(|&node| !deleted.is_deleted(node));
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::rebuild_purged;
+    use crate::index::SpatialIndex;
+    use crate::spatial::BoundingBox;
+    use crate::storage::ArenaView;
+
+    #[test]
+    fn test_bitmap_marks_and_restores() {
+        let mut deleted = DeletionBitmap::<u32>::new();
+        assert!(deleted.is_empty());
+        assert!(deleted.delete(3));
+        assert!(deleted.delete(200));
+        assert!(!deleted.delete(3));
+        assert_eq!(deleted.len(), 2);
+        assert!(deleted.is_deleted(200));
+        assert!(!deleted.is_deleted(199));
+        assert!(!deleted.is_deleted(10_000));
+        assert_eq!(deleted.iter().collect::<Vec<_>>(), [3, 200]);
+
+        assert!(deleted.restore(3));
+        assert!(!deleted.restore(3));
+        assert!(!deleted.restore(10_000));
+        assert_eq!(deleted.iter().collect::<Vec<_>>(), [200]);
+        assert_eq!(deleted, [200].into_iter().collect());
+        deleted.clear();
+        assert!(deleted.is_empty() && !deleted.is_deleted(200));
+    }
+
+    #[test]
+    fn test_searches_skip_deleted_nodes_until_purged() {
+        let mut index = SpatialIndex::new();
+        let handles: Vec<usize> = (0..100)
+            .map(|i| {
+                let x = (i * 37 % 100) as f64;
+                index.insert(BoundingBox::new(x, x, x + 1.0, x + 1.0), i)
+            })
+            .collect();
+        let (arena, root) = index.into_parts();
+        let linker = ArenaView::new(&arena);
+        let query = BoundingBox::new(0.0, 0.0, 50.0, 50.0);
+
+        // Deleting the root still leaves its subtrees reachable
+        let mut deleted: DeletionBitmap = handles.iter().copied().step_by(2).collect();
+        deleted.delete(root.unwrap());
+        let live = spatial_search_live(&linker, root, &query, 0, &deleted);
+        let mut expected = spatial_search(&linker, root, &query, 0);
+        expected.retain(|&node| node % 2 == 1 && Some(node) != root);
+        assert_eq!(live, expected);
+
+        let (purged, purged_root) = rebuild_purged(&linker, root, &deleted);
+        let purged_linker = ArenaView::new(&purged);
+        assert_eq!(purged.len(), 100 - deleted.len());
+        let mut found: Vec<u32> = spatial_search(&purged_linker, purged_root, &query, 0)
+            .into_iter()
+            .map(|node| *purged_linker.get_data(node))
+            .collect();
+        found.sort_unstable();
+        let mut kept: Vec<u32> = live.iter().map(|&node| *linker.get_data(node)).collect();
+        kept.sort_unstable();
+        assert_eq!(found, kept);
+    }
+}
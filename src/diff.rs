@@ -0,0 +1,454 @@
+//! Differences between two versions of a node tree, and renderings of them.
+
+use crate::search::{pad_svg_bounds, svg_rect};
+use crate::spatial::{BoundingBox, Point};
+use crate::storage::NodeLinker;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{Display, Write};
+use std::hash::Hash;
+
+/// Node-level changes from an old tree to a new one, from `diff_trees`.
+///
+/// Nodes are matched by payload, so payloads should identify entries (ids, page numbers);
+/// duplicate payloads are paired in pre-order. A matched node has moved when it hangs from
+/// a parent with a different payload, on the other side of it, or its point changed:
+/// exactly the nodes a merge or rebalance relinked or rewrote.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreeDiff<A, B> {
+    /// Nodes of the new tree whose payload the old tree lacks, in pre-order.
+    pub added: Vec<B>,
+    /// Nodes of the old tree whose payload the new tree lacks, in pre-order.
+    pub removed: Vec<A>,
+    /// Nodes present in both trees that moved, as `(old, new)` in the new tree's pre-order.
+    pub moved: Vec<(A, B)>,
+    /// Number of nodes present in both trees that did not move.
+    pub unchanged: usize,
+}
+
+impl<A, B> TreeDiff<A, B> {
+    /// Check if the trees hold the same entries in the same shape.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.moved.is_empty()
+    }
+}
+
+/// Where a node hangs in its tree: its parent and whether it is the right child.
+type Position<R> = Option<(R, bool)>;
+
+/// Compare two versions of a tree, which may live in different backends.
+pub fn diff_trees<P: Point, T: Eq + Hash, LA: NodeLinker<P, T>, LB: NodeLinker<P, T>>(
+    a: &LA,
+    root_a: Option<LA::NodeRef>,
+    b: &LB,
+    root_b: Option<LB::NodeRef>,
+) -> TreeDiff<LA::NodeRef, LB::NodeRef> {
+    let old = positions(a, root_a);
+    let mut unmatched: HashMap<&T, VecDeque<usize>> = HashMap::new();
+    for (index, &(node, _)) in old.iter().enumerate() {
+        unmatched
+            .entry(a.get_data(node))
+            .or_default()
+            .push_back(index);
+    }
+
+    let mut diff = TreeDiff {
+        added: Vec::new(),
+        removed: Vec::new(),
+        moved: Vec::new(),
+        unchanged: 0,
+    };
+    let mut matched = vec![false; old.len()];
+    for (node, position) in positions(b, root_b) {
+        let data = b.get_data(node);
+        let Some(index) = unmatched.get_mut(data).and_then(VecDeque::pop_front) else {
+            diff.added.push(node);
+            continue;
+        };
+        matched[index] = true;
+
+        let (old_node, old_position) = old[index];
+        let same_position = match (old_position, position) {
+            (None, None) => true,
+            (Some((old_parent, old_side)), Some((parent, side))) => {
+                old_side == side && a.get_data(old_parent) == b.get_data(parent)
+            }
+            _ => false,
+        };
+        if same_position && same_point(a.get_point(old_node), b.get_point(node)) {
+            diff.unchanged += 1;
+        } else {
+            diff.moved.push((old_node, node));
+        }
+    }
+    diff.removed = old
+        .iter()
+        .zip(matched)
+        .filter(|&(_, matched)| !matched)
+        .map(|(&(node, _), _)| node)
+        .collect();
+    diff
+}
+
+/// Check two trees, possibly in different backends, node by node.
+///
+/// The trees are equal when they have the same shape and corresponding nodes have equal
+/// points and payloads; node references are never compared, so an in-memory arena and the
+/// node file it was written to compare equal. Stops at the first difference, and unlike
+/// `diff_trees` needs nothing beyond `PartialEq` on payloads.
+pub fn trees_equal<P: Point, T: PartialEq, LA: NodeLinker<P, T>, LB: NodeLinker<P, T>>(
+    linker_a: &LA,
+    root_a: Option<LA::NodeRef>,
+    linker_b: &LB,
+    root_b: Option<LB::NodeRef>,
+) -> bool {
+    let mut stack = Vec::new();
+    match (root_a, root_b) {
+        (Some(a), Some(b)) => stack.push((a, b)),
+        (None, None) => return true,
+        _ => return false,
+    }
+    while let Some((a, b)) = stack.pop() {
+        if !same_point(linker_a.get_point(a), linker_b.get_point(b))
+            || linker_a.get_data(a) != linker_b.get_data(b)
+        {
+            return false;
+        }
+        let children = [
+            (linker_a.get_left(a), linker_b.get_left(b)),
+            (linker_a.get_right(a), linker_b.get_right(b)),
+        ];
+        for children in children {
+            match children {
+                (Some(a), Some(b)) => stack.push((a, b)),
+                (None, None) => {}
+                _ => return false,
+            }
+        }
+    }
+    true
+}
+
+/// Every node reachable from `root` with its position, in pre-order.
+fn positions<P: Point, T, L: NodeLinker<P, T>>(
+    linker: &L,
+    root: Option<L::NodeRef>,
+) -> Vec<(L::NodeRef, Position<L::NodeRef>)> {
+    let mut nodes = Vec::new();
+    let mut stack: Vec<_> = root.map(|node| (node, None)).into_iter().collect();
+    while let Some((node, position)) = stack.pop() {
+        nodes.push((node, position));
+        stack.extend(
+            linker
+                .get_right(node)
+                .map(|child| (child, Some((node, true)))),
+        );
+        stack.extend(
+            linker
+                .get_left(node)
+                .map(|child| (child, Some((node, false)))),
+        );
+    }
+    nodes
+}
+
+fn same_point<P: Point>(a: &P, b: &P) -> bool {
+    a.dimensions() == b.dimensions()
+        && (0..a.dimensions()).all(|dim| a.get_dimension(dim) == b.get_dimension(dim))
+}
+
+/// How a node of the new tree is drawn.
+#[derive(Clone, Copy)]
+enum Change {
+    Unchanged,
+    Added,
+    Moved,
+}
+
+impl Change {
+    fn color(self) -> &'static str {
+        match self {
+            Change::Unchanged => "gray",
+            Change::Added => "green",
+            Change::Moved => "orange",
+        }
+    }
+}
+
+/// Color removed nodes are drawn in.
+const REMOVED_COLOR: &str = "red";
+
+fn changes<A, B: Copy + Eq + Hash>(diff: &TreeDiff<A, B>) -> HashMap<B, Change> {
+    let added = diff.added.iter().map(|&node| (node, Change::Added));
+    let moved = diff.moved.iter().map(|&(_, node)| (node, Change::Moved));
+    added.chain(moved).collect()
+}
+
+/// Render a diff as a Graphviz DOT graph of the new tree.
+///
+/// Nodes are labelled with their payload: unchanged nodes are gray, added nodes green and
+/// moved nodes orange. Removed nodes are drawn dashed red beside the tree, with no edges.
+pub fn diff_to_dot<P: Point, T: Display, LA: NodeLinker<P, T>, LB: NodeLinker<P, T>>(
+    diff: &TreeDiff<LA::NodeRef, LB::NodeRef>,
+    a: &LA,
+    b: &LB,
+    root_b: Option<LB::NodeRef>,
+) -> String
+where
+    LB::NodeRef: Eq + Hash,
+{
+    let changes = changes(diff);
+    let mut dot = String::from("digraph diff {\n    node [shape=box];\n");
+
+    let mut ids = 0usize;
+    let mut stack: Vec<_> = root_b.map(|node| (node, None)).into_iter().collect();
+    while let Some((node, parent)) = stack.pop() {
+        let id = ids;
+        ids += 1;
+        let change = changes.get(&node).copied().unwrap_or(Change::Unchanged);
+        let _ = writeln!(
+            dot,
+            "    n{id} [label=\"{}\", color={}];",
+            dot_escape(b.get_data(node)),
+            change.color()
+        );
+        if let Some((parent, side)) = parent {
+            let _ = writeln!(dot, "    n{parent} -> n{id} [label=\"{side}\"];");
+        }
+        stack.extend(b.get_right(node).map(|child| (child, Some((id, "R")))));
+        stack.extend(b.get_left(node).map(|child| (child, Some((id, "L")))));
+    }
+
+    for (index, &node) in diff.removed.iter().enumerate() {
+        let _ = writeln!(
+            dot,
+            "    removed{index} [label=\"{}\", color={REMOVED_COLOR}, style=dashed];",
+            dot_escape(a.get_data(node))
+        );
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+fn dot_escape(value: impl Display) -> String {
+    value.to_string().replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Render a diff of two bounding-box trees as SVG.
+///
+/// Draws every node of the new tree, colored as in `diff_to_dot`, and the removed nodes of
+/// the old tree as dashed red rectangles, all scaled to fit the image together.
+pub fn diff_to_svg<T: Display, LA: NodeLinker<BoundingBox, T>, LB: NodeLinker<BoundingBox, T>>(
+    diff: &TreeDiff<LA::NodeRef, LB::NodeRef>,
+    a: &LA,
+    b: &LB,
+    root_b: Option<LB::NodeRef>,
+    width: u32,
+    height: u32,
+) -> String
+where
+    LB::NodeRef: Eq + Hash,
+{
+    let changes = changes(diff);
+    let mut drawn: Vec<(&BoundingBox, &T, Option<Change>)> = Vec::new();
+    let mut stack: Vec<_> = root_b.into_iter().collect();
+    while let Some(node) = stack.pop() {
+        let change = changes.get(&node).copied().unwrap_or(Change::Unchanged);
+        drawn.push((b.get_point(node), b.get_data(node), Some(change)));
+        stack.extend(b.get_right(node));
+        stack.extend(b.get_left(node));
+    }
+    for &node in &diff.removed {
+        drawn.push((a.get_point(node), a.get_data(node), None));
+    }
+
+    let mut svg = format!(
+        r#"<svg width="{width}" height="{height}" xmlns="http://www.w3.org/2000/svg">
+<style>
+    .bbox {{ fill: none; stroke-width: 2; }}
+    .removed {{ stroke-dasharray: 5,5; }}
+    .data-text {{ font-family: Arial; font-size: 12px; fill: black; }}
+    .background {{ fill: white; }}
+</style>
+<rect x="0" y="0" width="{width}" height="{height}" class="background" />
+"#
+    );
+    let Some(bounds) = drawn
+        .iter()
+        .map(|&(bbox, _, _)| bbox.clone())
+        .reduce(|bounds, bbox| bounds.union(&bbox))
+    else {
+        svg.push_str("</svg>");
+        return svg;
+    };
+    let bounds = pad_svg_bounds(&bounds);
+
+    for (bbox, data, change) in drawn {
+        let (x, y, w, h) = svg_rect(bbox, &bounds, width, height);
+        let (color, class) = match change {
+            Some(change) => (change.color(), "bbox"),
+            None => (REMOVED_COLOR, "bbox removed"),
+        };
+        let _ = writeln!(
+            svg,
+            r#"<rect x="{x:.1}" y="{y:.1}" width="{w:.1}" height="{h:.1}" class="{class}" stroke="{color}" />"#
+        );
+        let _ = writeln!(
+            svg,
+            r#"<text x="{:.1}" y="{:.1}" text-anchor="middle" dominant-baseline="middle" class="data-text">{data}</text>"#,
+            x + w / 2.0,
+            y + h / 2.0
+        );
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::insert_node;
+    use crate::storage::{InMemoryLinker, NodeArena};
+
+    fn build(arena: &mut NodeArena<BoundingBox, u32>, entries: &[u32]) -> Option<usize> {
+        let refs: Vec<usize> = entries
+            .iter()
+            .map(|&id| {
+                let x = id as f64;
+                arena.allocate(BoundingBox::new(x, x, x + 1.0, x + 1.0), id)
+            })
+            .collect();
+        let mut linker = InMemoryLinker::new(arena);
+        let root = *refs.first()?;
+        for &node in &refs[1..] {
+            insert_node(&mut linker, Some(root), node, 0);
+        }
+        Some(root)
+    }
+
+    #[test]
+    fn test_diff_identical_trees() {
+        let mut old = NodeArena::new();
+        let mut new = NodeArena::new();
+        let root_a = build(&mut old, &[5, 2, 8, 1, 9]);
+        let root_b = build(&mut new, &[5, 2, 8, 1, 9]);
+        let a = InMemoryLinker::new(&mut old);
+        let b = InMemoryLinker::new(&mut new);
+
+        let diff = diff_trees(&a, root_a, &b, root_b);
+        assert!(diff.is_empty());
+        assert_eq!(diff.unchanged, 5);
+    }
+
+    #[test]
+    fn test_diff_added_removed_moved() {
+        let mut old = NodeArena::new();
+        let mut new = NodeArena::new();
+        // 5 is removed and 7 added, so the root and both of 5's children move
+        let root_a = build(&mut old, &[5, 2, 8, 1, 9]);
+        let root_b = build(&mut new, &[2, 8, 1, 9, 7]);
+        let a = InMemoryLinker::new(&mut old);
+        let b = InMemoryLinker::new(&mut new);
+
+        let diff = diff_trees(&a, root_a, &b, root_b);
+        let data_a = |node| *a.get_data(node);
+        let data_b = |node| *b.get_data(node);
+        assert_eq!(
+            diff.removed.iter().map(|&n| data_a(n)).collect::<Vec<_>>(),
+            [5]
+        );
+        assert_eq!(
+            diff.added.iter().map(|&n| data_b(n)).collect::<Vec<_>>(),
+            [7]
+        );
+        let moved: Vec<u32> = diff.moved.iter().map(|&(_, n)| data_b(n)).collect();
+        assert_eq!(moved, [2, 8]);
+        assert_eq!(diff.unchanged, 2);
+
+        let dot = diff_to_dot(&diff, &a, &b, root_b);
+        assert!(dot.starts_with("digraph diff {"));
+        assert!(dot.contains("n0 [label=\"2\", color=orange];"));
+        assert!(dot.contains("n0 -> n1 [label=\"L\"];"));
+        assert!(dot.contains("[label=\"7\", color=green];"));
+        assert!(dot.contains("removed0 [label=\"5\", color=red, style=dashed];"));
+
+        let svg = diff_to_svg(&diff, &a, &b, root_b, 400, 300);
+        assert_eq!(svg.matches("<rect x=").count(), 7);
+        assert_eq!(svg.matches("stroke=\"red\"").count(), 1);
+        assert_eq!(svg.matches("stroke=\"green\"").count(), 1);
+        assert!(svg.ends_with("</svg>"));
+    }
+
+    #[test]
+    fn test_trees_equal() {
+        let mut old = NodeArena::new();
+        let mut new = NodeArena::new();
+        let root_a = build(&mut old, &[5, 2, 8, 1, 9]);
+        let root_b = build(&mut new, &[5, 2, 8, 1, 9]);
+        let mut a = InMemoryLinker::new(&mut old);
+        let b = InMemoryLinker::new(&mut new);
+        assert!(trees_equal(&a, root_a, &b, root_b));
+        assert!(trees_equal(&a, None, &b, None));
+        assert!(!trees_equal(&a, root_a, &b, None));
+
+        // Same nodes plus one extra leaf
+        let mut longer = NodeArena::new();
+        let root_c = build(&mut longer, &[5, 2, 8, 1, 9, 7]);
+        assert!(!trees_equal(
+            &a,
+            root_a,
+            &InMemoryLinker::new(&mut longer),
+            root_c
+        ));
+
+        // Children swapped
+        let (l, r) = (a.get_left(root_a.unwrap()), a.get_right(root_a.unwrap()));
+        a.link_left(root_a.unwrap(), r.unwrap());
+        a.link_right(root_a.unwrap(), l.unwrap());
+        assert!(!trees_equal(&a, root_a, &b, root_b));
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_trees_equal_in_memory_and_mmap() {
+        use crate::build::{BuildOptions, bulk_build};
+        use crate::mmap::{MmapArena, MmapLinker};
+        use crate::node_file::write_arena;
+
+        let mut arena = NodeArena::new();
+        let mut nodes: Vec<usize> = (0..300u32)
+            .map(|i| {
+                let x = ((i * 37) % 101) as f64;
+                arena.allocate(BoundingBox::new(x, x, x + 1.0, x + 2.0), i)
+            })
+            .collect();
+        let mut linker = InMemoryLinker::new(&mut arena);
+        let root = bulk_build(&mut linker, &mut nodes, 0, &BuildOptions::default()).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.bkd");
+        write_arena(&path, &arena, root).unwrap();
+        let mut mapped = MmapArena::<BoundingBox, u32>::open_read_only(&path).unwrap();
+        let mapped_root = root.map(|root| root as u64);
+        assert!(trees_equal(
+            &InMemoryLinker::new(&mut arena),
+            root,
+            &MmapLinker::new(&mut mapped),
+            mapped_root
+        ));
+    }
+
+    #[test]
+    fn test_diff_empty_trees() {
+        let mut old = NodeArena::new();
+        let mut new = NodeArena::new();
+        let root_b = build(&mut new, &[1, 2]);
+        let a = InMemoryLinker::new(&mut old);
+        let b = InMemoryLinker::new(&mut new);
+
+        let diff = diff_trees(&a, None, &b, root_b);
+        assert_eq!(diff.added.len(), 2);
+        let diff = diff_trees(&b, root_b, &a, None);
+        assert_eq!(diff.removed.len(), 2);
+        assert!(diff_to_svg(&diff, &b, &a, None, 10, 10).ends_with("</svg>"));
+    }
+}
@@ -0,0 +1,166 @@
+//! Deterministic fingerprints of node trees.
+
+use crate::codec::FixedCodec;
+use crate::spatial::Point;
+use crate::storage::NodeLinker;
+
+/// 64-bit FNV-1a, chosen because it is fixed by its specification rather than by the
+/// standard library, whose hashers may change between releases.
+pub(crate) struct Fnv1a(pub(crate) u64);
+
+impl Fnv1a {
+    pub(crate) const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(Self::PRIME);
+        }
+    }
+}
+
+/// Fingerprint of a tree's structure, coordinates and payloads.
+///
+/// Two trees have the same digest when they have the same shape and each node has the same
+/// point, bit for bit, and the same `FixedCodec` encoding of its payload, whatever backend
+/// holds them. Every input is hashed in its little-endian encoding, so the digest is the
+/// same on any platform and across releases, and replicas or backups can be compared by
+/// exchanging eight bytes. It is not cryptographic: use it to detect divergence, not
+/// tampering.
+pub fn tree_digest<P: Point, T: FixedCodec, L: NodeLinker<P, T>>(
+    linker: &L,
+    root: Option<L::NodeRef>,
+) -> u64 {
+    let mut hasher = Fnv1a(Fnv1a::OFFSET_BASIS);
+    let mut payload = vec![0u8; T::SIZE];
+    let mut stack: Vec<L::NodeRef> = root.into_iter().collect();
+    while let Some(node) = stack.pop() {
+        let left = linker.get_left(node);
+        let right = linker.get_right(node);
+        // Pre-order with child flags determines the shape
+        hasher.write(&[left.is_some() as u8 | (right.is_some() as u8) << 1]);
+
+        let point = linker.get_point(node);
+        hasher.write(&(point.dimensions() as u32).to_le_bytes());
+        for dim in 0..point.dimensions() {
+            hasher.write(&point.get_dimension(dim).to_bits().to_le_bytes());
+        }
+        linker.get_data(node).encode(&mut payload);
+        hasher.write(&payload);
+
+        stack.extend(right);
+        stack.extend(left);
+    }
+    hasher.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::{BuildOptions, bulk_build};
+    use crate::node_file::{NodeFileReader, write_arena};
+    use crate::spatial::BoundingBox;
+    use crate::storage::{InMemoryLinker, NodeArena};
+
+    fn build(entries: &[(f64, u64)]) -> (NodeArena<BoundingBox, u64>, Option<usize>) {
+        let mut arena = NodeArena::new();
+        let mut nodes: Vec<usize> = entries
+            .iter()
+            .map(|&(x, id)| arena.allocate(BoundingBox::new(x, x, x + 1.0, x + 1.0), id))
+            .collect();
+        let mut linker = InMemoryLinker::new(&mut arena);
+        let root = bulk_build(&mut linker, &mut nodes, 0, &BuildOptions::default()).unwrap();
+        (arena, root)
+    }
+
+    #[test]
+    fn test_digest_detects_changes() {
+        let entries: Vec<(f64, u64)> = (0..100).map(|i| (((i * 37) % 101) as f64, i)).collect();
+        let (mut arena, root) = build(&entries);
+        let digest = tree_digest(&InMemoryLinker::new(&mut arena), root);
+
+        let (mut same, same_root) = build(&entries);
+        assert_eq!(
+            tree_digest(&InMemoryLinker::new(&mut same), same_root),
+            digest
+        );
+
+        let mut payload = entries.clone();
+        payload[10].1 = 1000;
+        let (mut changed, changed_root) = build(&payload);
+        assert_ne!(
+            tree_digest(&InMemoryLinker::new(&mut changed), changed_root),
+            digest
+        );
+
+        let mut point = entries.clone();
+        point[10].0 += 0.5;
+        let (mut changed, changed_root) = build(&point);
+        assert_ne!(
+            tree_digest(&InMemoryLinker::new(&mut changed), changed_root),
+            digest
+        );
+
+        let (mut shorter, shorter_root) = build(&entries[1..]);
+        assert_ne!(
+            tree_digest(&InMemoryLinker::new(&mut shorter), shorter_root),
+            digest
+        );
+    }
+
+    #[test]
+    fn test_digest_survives_node_file_round_trip() {
+        let entries: Vec<(f64, u64)> = (0..50).map(|i| (i as f64, i)).collect();
+        let (mut arena, root) = build(&entries);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.bkd");
+        write_arena(&path, &arena, root).unwrap();
+
+        let (mut loaded, loaded_root) = NodeFileReader::<BoundingBox, u64>::open(&path)
+            .unwrap()
+            .load_arena()
+            .unwrap();
+        assert_eq!(
+            tree_digest(&InMemoryLinker::new(&mut loaded), loaded_root),
+            tree_digest(&InMemoryLinker::new(&mut arena), root)
+        );
+    }
+
+    #[test]
+    fn test_digest_distinguishes_shape() {
+        // Same nodes in the same pre-order, hanging left in one tree and right in the other
+        let mut left = NodeArena::new();
+        let mut right = NodeArena::new();
+        for arena in [&mut left, &mut right] {
+            arena.allocate(BoundingBox::new(0.0, 0.0, 1.0, 1.0), 1u64);
+            arena.allocate(BoundingBox::new(2.0, 2.0, 3.0, 3.0), 2u64);
+        }
+        let (a, b) = (0, 1);
+        InMemoryLinker::new(&mut left).link_left(a, b);
+        InMemoryLinker::new(&mut right).link_right(a, b);
+        assert_ne!(
+            tree_digest(&InMemoryLinker::new(&mut left), Some(a)),
+            tree_digest(&InMemoryLinker::new(&mut right), Some(a))
+        );
+    }
+
+    #[test]
+    fn test_digest_is_stable() {
+        // Reference value from the FNV specification
+        let mut hasher = Fnv1a(Fnv1a::OFFSET_BASIS);
+        hasher.write(b"a");
+        assert_eq!(hasher.0, 0xaf63_dc4c_8601_ec8c);
+
+        let mut arena = NodeArena::new();
+        assert_eq!(
+            tree_digest(&InMemoryLinker::new(&mut arena), None),
+            Fnv1a::OFFSET_BASIS
+        );
+        let root = arena.allocate(BoundingBox::new(0.0, 0.0, 1.0, 1.0), 7u64);
+        // Pinned so a change to the hashed layout cannot go unnoticed
+        assert_eq!(
+            tree_digest(&InMemoryLinker::new(&mut arena), Some(root)),
+            0x9503_9b2b_f958_d38c
+        );
+    }
+}
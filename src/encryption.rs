@@ -0,0 +1,483 @@
+//! Encryption at rest: a file whose pages are sealed with AES-256-GCM.
+//!
+//! # Layout
+//! ```text
+//! ┌──────────────────────────────┐  offset 0
+//! │ Header (128 bytes)           │  magic, version, page size, length, file id, seal
+//! ├──────────────────────────────┤  offset 128
+//! │ Page 0                       │  nonce (12) | ciphertext (page size) | tag (16)
+//! │ Page 1                       │
+//! │ ...                          │  page i at 128 + i * (page size + 28)
+//! └──────────────────────────────┘
+//! ```
+//! The header stays in the clear but is sealed: an empty message authenticated over its
+//! first 40 bytes, so a wrong key or an edited length fails on `open`. Each page is sealed
+//! with a fresh random nonce and the file id and page number as associated data, so pages
+//! cannot be reordered or moved between files unnoticed. Only the length and page size
+//! leak; the last page is zero-padded to full size.
+
+use crate::buffer_pool::{self, PageStorage};
+use crate::codec::FixedCodec;
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Magic bytes identifying an encrypted file.
+pub const MAGIC: [u8; 8] = *b"BKDCRYPT";
+
+/// Current format version.
+pub const VERSION: u32 = 1;
+
+/// Size of the file header in bytes.
+pub const HEADER_SIZE: usize = 128;
+
+/// Bytes each stored page adds to its plaintext: the nonce and the tag.
+pub const PAGE_OVERHEAD: usize = NONCE_SIZE + TAG_SIZE;
+
+const NONCE_SIZE: usize = 12;
+const TAG_SIZE: usize = 16;
+/// Header bytes covered by the header seal, which follows them.
+const SEALED_HEADER: usize = 40;
+
+/// A 256-bit AES key, supplied by the caller.
+#[derive(Clone)]
+pub struct EncryptionKey([u8; 32]);
+
+impl EncryptionKey {
+    /// Use `bytes` as the key, e.g. one fetched from a key management service.
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        EncryptionKey(bytes)
+    }
+
+    /// Draw a key from the operating system's random number generator.
+    pub fn generate() -> Self {
+        let mut bytes = [0u8; 32];
+        OsRng.fill_bytes(&mut bytes);
+        EncryptionKey(bytes)
+    }
+
+    /// The raw key bytes, to store wherever keys are kept.
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+/// Never prints the key.
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+/// A file of bytes stored as encrypted pages, so nothing reaches the disk in the clear.
+///
+/// # Usage pattern:
+/// - Write a node file into it with `NodeFileWriter::new`, then `sync`
+/// - Serve it through `BufferPool::from_storage` and `PooledNodeFile`, which read, and
+///   write back, whole pages; give the pool this file's page size so each pool page
+///   decrypts exactly one stored page
+///
+/// Positional reads and writes (`PageStorage`) take `&self`. A write re-seals every page
+/// it touches, reading partly covered pages first, and writes to one page are not ordered
+/// against each other: a `BufferPool` serializes them, other callers must. `Read`, `Write`
+/// and `Seek` work on a cursor for sequential use.
+///
+/// The length in the header is rewritten by `flush` and `sync`; pages written after the
+/// last of them are lost if the process dies. Random nonces keep a key safe for about 2^32
+/// page writes, so rotate keys on heavily rewritten files.
+pub struct EncryptedFile {
+    file: File,
+    cipher: Aes256Gcm,
+    page_size: usize,
+    file_id: [u8; 16],
+    len: AtomicU64,
+    position: u64,
+}
+
+impl EncryptedFile {
+    /// Create (or truncate) an encrypted file at `path`, storing `page_size` bytes per page.
+    ///
+    /// # Panics
+    /// Panics if `page_size` is zero.
+    pub fn create(path: &Path, key: &EncryptionKey, page_size: usize) -> io::Result<Self> {
+        assert!(page_size > 0, "page size must be positive");
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        let mut file_id = [0u8; 16];
+        OsRng.fill_bytes(&mut file_id);
+        let encrypted = EncryptedFile {
+            file,
+            cipher: Aes256Gcm::new(key.as_bytes().into()),
+            page_size,
+            file_id,
+            len: AtomicU64::new(0),
+            position: 0,
+        };
+        encrypted.write_header()?;
+        Ok(encrypted)
+    }
+
+    /// Open an encrypted file for reading and writing.
+    ///
+    /// Fails with `InvalidData` if `key` is not the one the file was created with, or the
+    /// header was tampered with.
+    pub fn open(path: &Path, key: &EncryptionKey) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let mut header = [0u8; HEADER_SIZE];
+        buffer_pool::read_exact_at(&file, &mut header, 0)?;
+        if header[0..8] != MAGIC {
+            return Err(invalid_data("not an encrypted BKD file (bad magic)"));
+        }
+        let version = u32::decode(&header[8..12]);
+        if version != VERSION {
+            return Err(invalid_data(&format!(
+                "unsupported encrypted file version {version}"
+            )));
+        }
+        let cipher = Aes256Gcm::new(key.as_bytes().into());
+        let seal = &header[SEALED_HEADER..SEALED_HEADER + PAGE_OVERHEAD];
+        cipher
+            .decrypt(
+                Nonce::from_slice(&seal[..NONCE_SIZE]),
+                Payload {
+                    msg: &seal[NONCE_SIZE..],
+                    aad: &header[..SEALED_HEADER],
+                },
+            )
+            .map_err(|_| invalid_data("wrong key, or the header was tampered with"))?;
+
+        let page_size = u32::decode(&header[12..16]) as usize;
+        if page_size == 0 {
+            return Err(invalid_data("encrypted file has a zero page size"));
+        }
+        Ok(EncryptedFile {
+            file,
+            cipher,
+            page_size,
+            file_id: header[24..40].try_into().expect("16 bytes"),
+            len: AtomicU64::new(u64::decode(&header[16..24])),
+            position: 0,
+        })
+    }
+
+    /// Plaintext bytes per page.
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /// Length of the plaintext.
+    pub fn len(&self) -> u64 {
+        self.len.load(Ordering::Acquire)
+    }
+
+    /// Check if nothing has been written.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Read `buf.len()` plaintext bytes at `offset`, failing with `UnexpectedEof` past the
+    /// end and with `InvalidData` if a page fails authentication.
+    pub fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        if offset.saturating_add(buf.len() as u64) > self.len() {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let mut done = 0;
+        while done < buf.len() {
+            let at = offset + done as u64;
+            let (page, start) = (
+                at / self.page_size as u64,
+                (at % self.page_size as u64) as usize,
+            );
+            let take = (self.page_size - start).min(buf.len() - done);
+            let plaintext = self.read_page(page)?;
+            buf[done..done + take].copy_from_slice(&plaintext[start..start + take]);
+            done += take;
+        }
+        Ok(())
+    }
+
+    /// Write `buf` at `offset`, growing the file as needed; a gap before `offset` reads as
+    /// zeros.
+    pub fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<()> {
+        let page_size = self.page_size as u64;
+        // Seal the pages of a gap, so every page below the length is stored
+        let stored_pages = self.len().div_ceil(page_size);
+        let first_page = offset / page_size;
+        for page in stored_pages..first_page {
+            self.write_page(page, &vec![0u8; self.page_size])?;
+        }
+
+        let mut done = 0;
+        while done < buf.len() {
+            let at = offset + done as u64;
+            let (page, start) = (at / page_size, (at % page_size) as usize);
+            let take = (self.page_size - start).min(buf.len() - done);
+            let mut plaintext = if take == self.page_size || page >= stored_pages {
+                vec![0u8; self.page_size]
+            } else {
+                self.read_page(page)?
+            };
+            plaintext[start..start + take].copy_from_slice(&buf[done..done + take]);
+            self.write_page(page, &plaintext)?;
+            done += take;
+        }
+        self.len
+            .fetch_max(offset + buf.len() as u64, Ordering::AcqRel);
+        Ok(())
+    }
+
+    /// Rewrite the header and make every write durable.
+    pub fn sync(&self) -> io::Result<()> {
+        self.write_header()?;
+        self.file.sync_data()
+    }
+
+    fn slot_offset(&self, page: u64) -> u64 {
+        HEADER_SIZE as u64 + page * (self.page_size + PAGE_OVERHEAD) as u64
+    }
+
+    fn page_aad(&self, page: u64) -> [u8; 24] {
+        let mut aad = [0u8; 24];
+        aad[..16].copy_from_slice(&self.file_id);
+        page.encode(&mut aad[16..]);
+        aad
+    }
+
+    fn read_page(&self, page: u64) -> io::Result<Vec<u8>> {
+        let mut slot = vec![0u8; self.page_size + PAGE_OVERHEAD];
+        buffer_pool::read_exact_at(&self.file, &mut slot, self.slot_offset(page))?;
+        self.cipher
+            .decrypt(
+                Nonce::from_slice(&slot[..NONCE_SIZE]),
+                Payload {
+                    msg: &slot[NONCE_SIZE..],
+                    aad: &self.page_aad(page),
+                },
+            )
+            .map_err(|_| invalid_data(&format!("page {page} failed authentication")))
+    }
+
+    fn write_page(&self, page: u64, plaintext: &[u8]) -> io::Result<()> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let sealed = self
+            .cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: plaintext,
+                    aad: &self.page_aad(page),
+                },
+            )
+            .map_err(|_| io::Error::other("page encryption failed"))?;
+        let mut slot = Vec::with_capacity(self.page_size + PAGE_OVERHEAD);
+        slot.extend_from_slice(&nonce);
+        slot.extend_from_slice(&sealed);
+        buffer_pool::write_all_at(&self.file, &slot, self.slot_offset(page))
+    }
+
+    fn write_header(&self) -> io::Result<()> {
+        let mut header = [0u8; HEADER_SIZE];
+        header[0..8].copy_from_slice(&MAGIC);
+        VERSION.encode(&mut header[8..12]);
+        (self.page_size as u32).encode(&mut header[12..16]);
+        self.len().encode(&mut header[16..24]);
+        header[24..40].copy_from_slice(&self.file_id);
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let tag = self
+            .cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: &[],
+                    aad: &header[..SEALED_HEADER],
+                },
+            )
+            .map_err(|_| io::Error::other("header encryption failed"))?;
+        header[SEALED_HEADER..SEALED_HEADER + NONCE_SIZE].copy_from_slice(&nonce);
+        header[SEALED_HEADER + NONCE_SIZE..SEALED_HEADER + PAGE_OVERHEAD].copy_from_slice(&tag);
+        buffer_pool::write_all_at(&self.file, &header, 0)
+    }
+}
+
+impl PageStorage for EncryptedFile {
+    fn len(&self) -> io::Result<u64> {
+        Ok(EncryptedFile::len(self))
+    }
+
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        self.read_at(buf, offset)
+    }
+
+    fn write_all_at(&self, buf: &[u8], offset: u64) -> io::Result<()> {
+        self.write_at(buf, offset)
+    }
+
+    fn sync_data(&self) -> io::Result<()> {
+        self.sync()
+    }
+}
+
+impl Read for EncryptedFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.len().saturating_sub(self.position);
+        let count = (buf.len() as u64).min(available) as usize;
+        self.read_at(&mut buf[..count], self.position)?;
+        self.position += count as u64;
+        Ok(count)
+    }
+}
+
+impl Write for EncryptedFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_at(buf, self.position)?;
+        self.position += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    /// Rewrites the header, so the length covers every write so far.
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_header()
+    }
+}
+
+impl Seek for EncryptedFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => self.len().checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        };
+        self.position = position.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek before the start of the file",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer_pool::{BufferPool, PooledNodeFile};
+    use crate::node_file::NodeFileWriter;
+    use crate::spatial::BoundingBox;
+    use std::sync::Arc;
+
+    const PAGE_SIZE: usize = 256;
+
+    fn write_index(path: &Path, key: &EncryptionKey) {
+        let file = EncryptedFile::create(path, key, PAGE_SIZE).unwrap();
+        let mut writer = NodeFileWriter::<BoundingBox, u64, _>::new(file).unwrap();
+        for i in 0..40u64 {
+            let x = 1000.0 + i as f64;
+            let left = (i + 1 < 40).then_some(i + 1);
+            writer
+                .push(&BoundingBox::new(x, x, x, x), &i, left, None)
+                .unwrap();
+        }
+        writer.finish_into(Some(0)).unwrap().sync().unwrap();
+    }
+
+    #[test]
+    fn test_node_file_round_trips_through_encrypted_pages() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.bkd.enc");
+        let key = EncryptionKey::generate();
+        write_index(&path, &key);
+
+        // No coordinate or node file magic reaches the disk
+        let raw = std::fs::read(&path).unwrap();
+        let needle = 1007.0f64.to_le_bytes();
+        assert!(!raw.windows(8).any(|window| window == needle));
+        assert!(!raw.windows(8).any(|window| window == b"BKDNODES"));
+
+        let file = EncryptedFile::open(&path, &key).unwrap();
+        assert_eq!(file.len(), 64 + 40 * 56);
+        let pool = Arc::new(BufferPool::from_storage(file, 4, PAGE_SIZE).unwrap());
+        let nodes = PooledNodeFile::<BoundingBox, u64>::new(pool.clone()).unwrap();
+        assert_eq!(nodes.root(), Some(0));
+        assert_eq!(nodes.read_node(7).unwrap().point.xmin, 1007.0);
+
+        // Links written back through the pool are sealed again
+        nodes.set_links(39, Some(0), None).unwrap();
+        pool.flush().unwrap();
+        drop(nodes);
+        drop(pool);
+        let file = EncryptedFile::open(&path, &key).unwrap();
+        let pool = Arc::new(BufferPool::from_storage(file, 4, PAGE_SIZE).unwrap());
+        let nodes = PooledNodeFile::<BoundingBox, u64>::new(pool).unwrap();
+        assert_eq!(nodes.read_node(39).unwrap().left, Some(0));
+        assert_eq!(nodes.read_node(38).unwrap().data, 38);
+    }
+
+    #[test]
+    fn test_wrong_keys_and_tampering_are_detected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.bkd.enc");
+        let key = EncryptionKey::generate();
+        write_index(&path, &key);
+
+        let wrong = EncryptedFile::open(&path, &EncryptionKey::from_bytes([7; 32]));
+        assert_eq!(wrong.err().unwrap().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(format!("{key:?}"), "EncryptionKey(..)");
+
+        // Swap pages 1 and 2: each decrypts, but not at the other's position
+        let mut raw = std::fs::read(&path).unwrap();
+        let slot = PAGE_SIZE + PAGE_OVERHEAD;
+        let (first, second) = (HEADER_SIZE + slot, HEADER_SIZE + 2 * slot);
+        let page: Vec<u8> = raw[first..second].to_vec();
+        raw.copy_within(second..second + slot, first);
+        raw[second..second + slot].copy_from_slice(&page);
+        std::fs::write(&path, &raw).unwrap();
+        let file = EncryptedFile::open(&path, &key).unwrap();
+        let mut buf = [0u8; 8];
+        file.read_at(&mut buf, 0).unwrap();
+        let error = file.read_at(&mut buf, PAGE_SIZE as u64).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        // A flipped ciphertext bit, and an edited length
+        raw[first + 20] ^= 1;
+        raw[16] ^= 1;
+        std::fs::write(&path, &raw).unwrap();
+        assert!(EncryptedFile::open(&path, &key).is_err());
+    }
+
+    #[test]
+    fn test_cursor_reads_and_writes_span_pages() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bytes.enc");
+        let key = EncryptionKey::generate();
+        let bytes: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+
+        let mut file = EncryptedFile::create(&path, &key, 64).unwrap();
+        file.write_all(&bytes[..500]).unwrap();
+        file.seek(SeekFrom::Start(900)).unwrap();
+        file.write_all(&bytes[900..]).unwrap();
+        file.seek(SeekFrom::Start(500)).unwrap();
+        file.write_all(&bytes[500..900]).unwrap();
+        file.sync().unwrap();
+        drop(file);
+
+        let mut file = EncryptedFile::open(&path, &key).unwrap();
+        let mut read = Vec::new();
+        file.read_to_end(&mut read).unwrap();
+        assert_eq!(read, bytes);
+        assert_eq!(file.seek(SeekFrom::End(-10)).unwrap(), 990);
+        assert!(file.seek(SeekFrom::Current(-1000)).is_err());
+    }
+}
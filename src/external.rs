@@ -0,0 +1,509 @@
+//! External-memory bulk build for datasets larger than RAM.
+
+use crate::build::{ProgressCallback, ProgressTracker};
+use crate::cancel::{self, CancellationToken};
+use crate::codec::{FixedCodec, f64_to_sortable_bytes};
+use crate::node_file::{FormatFeatures, NodeFileWriter};
+use crate::spatial::Point;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+
+/// Configuration for `external_bulk_build`.
+#[derive(Debug, Clone)]
+pub struct ExternalBuildOptions {
+    /// Maximum number of entries held in memory at once. Subtrees at most this large are
+    /// built in memory; larger ones are sorted in runs of this size and merged from disk.
+    pub max_entries_in_memory: usize,
+    /// Directory for spill files; defaults to the system temporary directory.
+    pub temp_dir: Option<PathBuf>,
+    /// Optional hook called periodically as nodes are written.
+    pub progress: Option<ProgressCallback>,
+    /// Optional token checked between subtrees and while merging runs. Cancellation is
+    /// reported as an `io::ErrorKind::Interrupted` error and leaves a partial output file.
+    pub cancel: Option<CancellationToken>,
+    /// Format features to record in the output header, e.g. `QUANTIZED`.
+    pub features: FormatFeatures,
+}
+
+impl Default for ExternalBuildOptions {
+    fn default() -> Self {
+        ExternalBuildOptions {
+            max_entries_in_memory: 1 << 20,
+            temp_dir: None,
+            progress: None,
+            cancel: None,
+            features: FormatFeatures::NONE,
+        }
+    }
+}
+
+/// Number of merged entries between cancellation checks.
+const CANCEL_CHECK_INTERVAL: usize = 4096;
+
+/// Spill file removed from disk when dropped.
+pub(crate) struct TempFile {
+    pub(crate) path: PathBuf,
+}
+
+static TEMP_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+impl TempFile {
+    pub(crate) fn new(dir: &Path) -> Self {
+        let id = TEMP_FILE_COUNTER.fetch_add(1, AtomicOrdering::Relaxed);
+        let name = format!("bkd-spill-{}-{}.tmp", std::process::id(), id);
+        TempFile {
+            path: dir.join(name),
+        }
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Entries of one pending subtree: either still in memory or spilled to a file.
+enum Entries<P, T> {
+    Memory(Vec<(P, T)>),
+    Spilled { file: TempFile, len: usize },
+}
+
+impl<P, T> Entries<P, T> {
+    fn len(&self) -> usize {
+        match self {
+            Entries::Memory(entries) => entries.len(),
+            Entries::Spilled { len, .. } => *len,
+        }
+    }
+}
+
+/// Build a KD-tree from an arbitrarily large stream of entries and write it to a node file.
+/// Returns the number of nodes written.
+///
+/// # Architecture
+/// The result is the same median-split tree `bulk_build` produces, laid out in pre-order:
+/// - Subtrees with at most `max_entries_in_memory` entries are built entirely in memory
+/// - Larger subtrees are sorted on their split dimension (`depth % dimensions`) by writing
+///   sorted runs to spill files and k-way merging them; the merged stream is cut at the
+///   median into a left spill file, the node itself, and a right spill file. Run records
+///   carry their sort key as sortable bytes, so the merge compares keys with `memcmp`
+/// - In pre-order a node's left child is the next record and its right child follows the
+///   whole left subtree, so child indices are known from subtree sizes before the children
+///   are written and the output file is written strictly sequentially
+///
+/// Peak memory is bounded by `max_entries_in_memory` entries plus one read buffer per run.
+pub fn external_bulk_build<P, T, I>(
+    entries: I,
+    output: &Path,
+    options: &ExternalBuildOptions,
+) -> io::Result<u64>
+where
+    P: Point + FixedCodec,
+    T: FixedCodec,
+    I: IntoIterator<Item = (P, T)>,
+{
+    let limit = options.max_entries_in_memory.max(1);
+    let temp_dir = options.temp_dir.clone().unwrap_or_else(std::env::temp_dir);
+
+    // Keep small inputs in memory; spill as soon as the input outgrows the limit
+    let mut iter = entries.into_iter();
+    let mut buffer: Vec<(P, T)> = iter.by_ref().take(limit + 1).collect();
+    let dimensions = buffer.first().map_or(1, |(point, _)| point.dimensions());
+    let input = if buffer.len() <= limit {
+        Entries::Memory(buffer)
+    } else {
+        let file = TempFile::new(&temp_dir);
+        let mut writer = BufWriter::new(File::create(&file.path)?);
+        let mut len = 0;
+        for entry in buffer.drain(..).chain(iter) {
+            write_entry(&mut writer, &entry)?;
+            len += 1;
+        }
+        writer.flush()?;
+        Entries::Spilled { file, len }
+    };
+
+    let mut writer = NodeFileWriter::<P, T>::create(output)?.with_features(options.features);
+    let root = if input.len() == 0 { None } else { Some(0) };
+    let mut builder = ExternalBuilder {
+        writer: &mut writer,
+        limit,
+        dimensions,
+        temp_dir,
+        progress: ProgressTracker::new(options.progress.as_ref(), input.len() as u64),
+        cancel: options.cancel.as_ref(),
+    };
+    builder.build(input, 0)?;
+
+    let node_count = writer.len();
+    writer.finish(root)?;
+    Ok(node_count)
+}
+
+struct ExternalBuilder<'a, P, T> {
+    writer: &'a mut NodeFileWriter<P, T>,
+    limit: usize,
+    dimensions: usize,
+    temp_dir: PathBuf,
+    progress: ProgressTracker<'a>,
+    cancel: Option<&'a CancellationToken>,
+}
+
+impl<'a, P: Point + FixedCodec, T: FixedCodec> ExternalBuilder<'a, P, T> {
+    /// Write the subtree holding `entries` starting at the writer's current position.
+    fn build(&mut self, entries: Entries<P, T>, depth: usize) -> io::Result<()> {
+        let len = entries.len();
+        if len == 0 {
+            return Ok(());
+        }
+        cancel::check(self.cancel)?;
+
+        let file = match entries {
+            Entries::Memory(entries) => return self.build_in_memory(entries, depth),
+            Entries::Spilled { file, .. } if len <= self.limit => {
+                let entries = read_entries(&file.path, len)?;
+                drop(file);
+                return self.build_in_memory(entries, depth);
+            }
+            Entries::Spilled { file, .. } => file,
+        };
+
+        let dimension = depth % self.dimensions;
+        let runs = sort_runs::<P, T>(&file.path, len, dimension, self.limit, &self.temp_dir)?;
+        drop(file);
+
+        let median = len / 2;
+        let index = self.writer.len();
+        let (left_link, right_link) = child_links(index, len);
+
+        // Cut the merged (sorted) stream at the median
+        let left = TempFile::new(&self.temp_dir);
+        let right = TempFile::new(&self.temp_dir);
+        let mut left_writer = BufWriter::new(File::create(&left.path)?);
+        let mut right_writer = BufWriter::new(File::create(&right.path)?);
+        let mut merge = RunMerge::new(&runs)?;
+        let mut position = 0;
+        while let Some(entry) = merge.next_entry()? {
+            if position % CANCEL_CHECK_INTERVAL == 0 {
+                cancel::check(self.cancel)?;
+            }
+            match position.cmp(&median) {
+                Ordering::Less => write_entry(&mut left_writer, &entry)?,
+                Ordering::Equal => {
+                    self.writer
+                        .push(&entry.0, &entry.1, left_link, right_link)?;
+                    self.progress.advance(depth);
+                }
+                Ordering::Greater => write_entry(&mut right_writer, &entry)?,
+            }
+            position += 1;
+        }
+        left_writer.flush()?;
+        right_writer.flush()?;
+        drop(merge);
+        drop(runs);
+
+        self.build(
+            Entries::Spilled {
+                file: left,
+                len: median,
+            },
+            depth + 1,
+        )?;
+        self.build(
+            Entries::Spilled {
+                file: right,
+                len: len - median - 1,
+            },
+            depth + 1,
+        )
+    }
+
+    /// Median-split pre-order build of a subtree that fits in memory.
+    fn build_in_memory(&mut self, mut entries: Vec<(P, T)>, depth: usize) -> io::Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        cancel::check(self.cancel)?;
+
+        let dimension = depth % self.dimensions;
+        let median = entries.len() / 2;
+        entries.select_nth_unstable_by(median, |a, b| {
+            a.0.get_dimension(dimension)
+                .total_cmp(&b.0.get_dimension(dimension))
+        });
+
+        let index = self.writer.len();
+        let (left_link, right_link) = child_links(index, entries.len());
+        let right = entries.split_off(median + 1);
+        let (point, data) = entries.pop().expect("median entry exists");
+        self.writer.push(&point, &data, left_link, right_link)?;
+        self.progress.advance(depth);
+
+        self.build_in_memory(entries, depth + 1)?;
+        self.build_in_memory(right, depth + 1)
+    }
+}
+
+/// Split a spill file into sorted runs of at most `limit` entries, sorted on `dimension`.
+pub(crate) fn sort_runs<P: Point + FixedCodec, T: FixedCodec>(
+    path: &Path,
+    len: usize,
+    dimension: usize,
+    limit: usize,
+    temp_dir: &Path,
+) -> io::Result<Vec<Run>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut runs = Vec::new();
+    let mut remaining = len;
+    while remaining > 0 {
+        let chunk_len = remaining.min(limit);
+        let mut chunk: Vec<(P, T)> = (0..chunk_len)
+            .map(|_| read_entry(&mut reader))
+            .collect::<io::Result<_>>()?;
+        chunk.sort_by_cached_key(|entry| f64_to_sortable_bytes(entry.0.get_dimension(dimension)));
+
+        let file = TempFile::new(temp_dir);
+        let mut writer = BufWriter::new(File::create(&file.path)?);
+        for entry in &chunk {
+            writer.write_all(&f64_to_sortable_bytes(entry.0.get_dimension(dimension)))?;
+            write_entry(&mut writer, entry)?;
+        }
+        writer.flush()?;
+        runs.push(Run {
+            file,
+            len: chunk_len,
+        });
+        remaining -= chunk_len;
+    }
+    Ok(runs)
+}
+
+/// Record indices of a node's children in the pre-order layout.
+fn child_links(index: u64, len: usize) -> (Option<u64>, Option<u64>) {
+    let median = len / 2;
+    let left = (median > 0).then_some(index + 1);
+    let right = (len - median - 1 > 0).then_some(index + 1 + median as u64);
+    (left, right)
+}
+
+/// One sorted run on disk. Each record is the sortable bytes of its sort key followed by
+/// the entry, so the merge orders records by comparing bytes.
+pub(crate) struct Run {
+    file: TempFile,
+    len: usize,
+}
+
+/// Heap key of the k-way merge: smallest key first, ties by run index for stability.
+struct MergeKey {
+    key: [u8; 8],
+    run: usize,
+}
+
+impl PartialEq for MergeKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for MergeKey {}
+
+impl PartialOrd for MergeKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for MergeKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .key
+            .cmp(&self.key)
+            .then_with(|| other.run.cmp(&self.run))
+    }
+}
+
+/// K-way merge over sorted runs, holding one entry per run in memory.
+pub(crate) struct RunMerge<P, T> {
+    readers: Vec<(BufReader<File>, usize)>, // (reader, entries left to read)
+    heads: Vec<Option<(P, T)>>,
+    heap: BinaryHeap<MergeKey>,
+}
+
+impl<P: Point + FixedCodec, T: FixedCodec> RunMerge<P, T> {
+    /// Merge runs from `sort_runs`, in the order of the dimension they were sorted on.
+    pub(crate) fn new(runs: &[Run]) -> io::Result<Self> {
+        let mut merge = RunMerge {
+            readers: Vec::with_capacity(runs.len()),
+            heads: Vec::with_capacity(runs.len()),
+            heap: BinaryHeap::with_capacity(runs.len()),
+        };
+        for (run, info) in runs.iter().enumerate() {
+            merge
+                .readers
+                .push((BufReader::new(File::open(&info.file.path)?), info.len));
+            merge.heads.push(None);
+            merge.advance(run)?;
+        }
+        Ok(merge)
+    }
+
+    /// Load the next entry of `run` into its head slot.
+    fn advance(&mut self, run: usize) -> io::Result<()> {
+        let (reader, remaining) = &mut self.readers[run];
+        if *remaining == 0 {
+            return Ok(());
+        }
+        *remaining -= 1;
+        let mut key = [0u8; 8];
+        reader.read_exact(&mut key)?;
+        self.heads[run] = Some(read_entry(reader)?);
+        self.heap.push(MergeKey { key, run });
+        Ok(())
+    }
+
+    pub(crate) fn next_entry(&mut self) -> io::Result<Option<(P, T)>> {
+        let Some(key) = self.heap.pop() else {
+            return Ok(None);
+        };
+        let entry = self.heads[key.run].take();
+        self.advance(key.run)?;
+        Ok(entry)
+    }
+}
+
+pub(crate) fn write_entry<P: FixedCodec, T: FixedCodec, W: Write>(
+    writer: &mut W,
+    entry: &(P, T),
+) -> io::Result<()> {
+    let mut buf = vec![0u8; <(P, T)>::SIZE];
+    entry.0.encode(&mut buf[..P::SIZE]);
+    entry.1.encode(&mut buf[P::SIZE..]);
+    writer.write_all(&buf)
+}
+
+pub(crate) fn read_entry<P: FixedCodec, T: FixedCodec, R: Read>(
+    reader: &mut R,
+) -> io::Result<(P, T)> {
+    let mut buf = vec![0u8; <(P, T)>::SIZE];
+    reader.read_exact(&mut buf)?;
+    Ok(<(P, T)>::decode(&buf))
+}
+
+pub(crate) fn read_entries<P: FixedCodec, T: FixedCodec>(
+    path: &Path,
+    len: usize,
+) -> io::Result<Vec<(P, T)>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    (0..len).map(|_| read_entry(&mut reader)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node_file::NodeFileReader;
+    use crate::search::spatial_search;
+    use crate::spatial::{BoundingBox, SpatialPoint};
+    use crate::storage::{InMemoryLinker, NodeLinker};
+    use std::sync::Arc;
+
+    fn entries() -> Vec<(BoundingBox, u64)> {
+        (0..300u64)
+            .map(|i| {
+                let x = ((i * 37) % 101) as f64;
+                let y = ((i * 53) % 97) as f64;
+                (BoundingBox::new(x, y, x + 2.0, y + 3.0), i)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_external_build_matches_brute_force() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("tree.bkd");
+        let written = Arc::new(AtomicU64::new(0));
+        let counter = written.clone();
+        let options = ExternalBuildOptions {
+            max_entries_in_memory: 16,
+            temp_dir: Some(dir.path().to_path_buf()),
+            progress: Some(ProgressCallback::new(100, move |progress| {
+                counter.store(progress.processed, AtomicOrdering::Relaxed);
+            })),
+            ..Default::default()
+        };
+
+        let count = external_bulk_build(entries(), &output, &options).unwrap();
+        assert_eq!(count, 300);
+        assert_eq!(written.load(AtomicOrdering::Relaxed), 300);
+
+        // Only the output file remains; every spill file was cleaned up
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        let mut reader = NodeFileReader::<BoundingBox, u64>::open(&output).unwrap();
+        let (mut arena, root) = reader.load_arena().unwrap();
+        let linker = InMemoryLinker::new(&mut arena);
+
+        for query in [
+            BoundingBox::new(10.0, 10.0, 30.0, 20.0),
+            BoundingBox::new(90.0, 0.0, 200.0, 5.0),
+            BoundingBox::new(-10.0, -10.0, 200.0, 200.0),
+        ] {
+            let mut results: Vec<u64> = spatial_search(&linker, root, &query, 0)
+                .into_iter()
+                .map(|node| *linker.get_data(node))
+                .collect();
+            results.sort();
+            let expected: Vec<u64> = entries()
+                .into_iter()
+                .filter(|(bbox, _)| bbox.overlaps(&query))
+                .map(|(_, id)| id)
+                .collect();
+            assert_eq!(results, expected);
+        }
+    }
+
+    #[test]
+    fn test_external_build_in_memory_and_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("small.bkd");
+        let count =
+            external_bulk_build(entries(), &output, &ExternalBuildOptions::default()).unwrap();
+        assert_eq!(count, 300);
+
+        let empty = dir.path().join("empty.bkd");
+        let count = external_bulk_build(
+            Vec::<(BoundingBox, u64)>::new(),
+            &empty,
+            &ExternalBuildOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(count, 0);
+        let reader = NodeFileReader::<BoundingBox, u64>::open(&empty).unwrap();
+        assert_eq!(reader.root(), None);
+    }
+
+    #[test]
+    fn test_external_build_cancelled() {
+        let dir = tempfile::tempdir().unwrap();
+        let token = CancellationToken::new();
+        token.cancel();
+        let options = ExternalBuildOptions {
+            max_entries_in_memory: 16,
+            temp_dir: Some(dir.path().to_path_buf()),
+            cancel: Some(token),
+            ..Default::default()
+        };
+
+        let error =
+            external_bulk_build(entries(), &dir.path().join("tree.bkd"), &options).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::Interrupted);
+
+        // Spill files are cleaned up even when the build stops early
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
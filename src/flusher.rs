@@ -0,0 +1,263 @@
+//! Periodic flushing of disk backends on a background thread.
+
+use crate::buffer_pool::BufferPool;
+use std::io;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// A disk backend holding writes in memory until flushed.
+pub trait FlushTarget: Send + Sync {
+    /// Write buffered changes to disk and make them durable.
+    fn flush(&self) -> io::Result<()>;
+}
+
+impl<F: FlushTarget + ?Sized> FlushTarget for Arc<F> {
+    fn flush(&self) -> io::Result<()> {
+        (**self).flush()
+    }
+}
+
+/// Writes back dirty pages.
+impl FlushTarget for BufferPool {
+    fn flush(&self) -> io::Result<()> {
+        BufferPool::flush(self)
+    }
+}
+
+/// Locks the arena for the length of the flush.
+#[cfg(feature = "mmap")]
+impl<P, T> FlushTarget for Mutex<crate::mmap::MmapArena<P, T>>
+where
+    P: crate::spatial::Point + crate::codec::ZeroCopy + Send,
+    T: crate::codec::ZeroCopy + Send,
+{
+    fn flush(&self) -> io::Result<()> {
+        self.lock().expect("arena lock poisoned").flush()
+    }
+}
+
+/// Rewrites the header with the current length and syncs.
+#[cfg(feature = "encryption")]
+impl FlushTarget for crate::encryption::EncryptedFile {
+    fn flush(&self) -> io::Result<()> {
+        self.sync()
+    }
+}
+
+/// Flushes a `FlushTarget` every `interval` on a thread of its own, bounding how much a
+/// crash can lose without the application running timers.
+///
+/// # Usage pattern:
+/// - `spawn` with the backend behind an `Arc`, keeping a clone to write through
+/// - `flush_now` after a write that must not wait for the next tick
+/// - `take_error` now and then: nobody else sees a failed background flush
+/// - `stop` (or drop) flushes one last time and joins the thread
+///
+/// Flushes never overlap, and a flush that takes longer than `interval` delays the next
+/// one rather than queueing more.
+pub struct BackgroundFlusher {
+    shared: Arc<Shared>,
+    handle: Option<JoinHandle<()>>,
+}
+
+struct Shared {
+    state: Mutex<FlusherState>,
+    wake: Condvar,
+}
+
+#[derive(Default)]
+struct FlusherState {
+    stopping: bool,
+    requested: bool,
+    flushes: u64,
+    error: Option<io::Error>,
+}
+
+impl BackgroundFlusher {
+    /// Start flushing `target` every `interval`.
+    ///
+    /// # Panics
+    /// Panics if `interval` is zero.
+    pub fn spawn<F: FlushTarget + 'static>(target: Arc<F>, interval: Duration) -> Self {
+        assert!(!interval.is_zero(), "flush interval must be positive");
+        let shared = Arc::new(Shared {
+            state: Mutex::new(FlusherState::default()),
+            wake: Condvar::new(),
+        });
+        let handle = std::thread::Builder::new()
+            .name("bkd-flusher".to_string())
+            .spawn({
+                let shared = shared.clone();
+                move || shared.run(&*target, interval)
+            })
+            .expect("failed to spawn the flusher thread");
+        BackgroundFlusher {
+            shared,
+            handle: Some(handle),
+        }
+    }
+
+    /// Ask for a flush without waiting for the next tick. Returns at once.
+    pub fn flush_now(&self) {
+        self.shared.state().requested = true;
+        self.shared.wake.notify_one();
+    }
+
+    /// Number of flushes completed so far, failed ones included.
+    pub fn flushes(&self) -> u64 {
+        self.shared.state().flushes
+    }
+
+    /// The first error of a background flush since the last call, if any.
+    pub fn take_error(&self) -> Option<io::Error> {
+        self.shared.state().error.take()
+    }
+
+    /// Flush one last time and stop the thread. Returns the first error not yet taken,
+    /// from the final flush or an earlier one.
+    pub fn stop(mut self) -> io::Result<()> {
+        self.shutdown();
+        match self.take_error() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    fn shutdown(&mut self) {
+        let Some(handle) = self.handle.take() else {
+            return;
+        };
+        self.shared.state().stopping = true;
+        self.shared.wake.notify_one();
+        if let Err(panic) = handle.join() {
+            std::panic::resume_unwind(panic);
+        }
+    }
+}
+
+/// Stops the thread after a final flush; its error, if any, is lost.
+impl Drop for BackgroundFlusher {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl Shared {
+    fn state(&self) -> MutexGuard<'_, FlusherState> {
+        self.state.lock().expect("flusher lock poisoned")
+    }
+
+    fn run(&self, target: &dyn FlushTarget, interval: Duration) {
+        loop {
+            let deadline = Instant::now() + interval;
+            let mut state = self.state();
+            while !state.stopping && !state.requested {
+                let now = Instant::now();
+                if now >= deadline {
+                    break;
+                }
+                state = self
+                    .wake
+                    .wait_timeout(state, deadline - now)
+                    .expect("flusher lock poisoned")
+                    .0;
+            }
+            let stopping = state.stopping;
+            state.requested = false;
+            drop(state);
+
+            let result = target.flush();
+            let mut state = self.state();
+            state.flushes += 1;
+            if let Err(error) = result {
+                state.error.get_or_insert(error);
+            }
+            if stopping {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(all(test, not(bkd_loom)))]
+mod tests {
+    use super::*;
+    use crate::buffer_pool::PooledNodeFile;
+    use crate::node_file::{NodeFileReader, write_arena};
+    use crate::spatial::BoundingBox;
+    use crate::storage::NodeArena;
+    use std::fs::OpenOptions;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    /// Counts flushes and fails every one after the first `healthy`.
+    struct Counter {
+        flushes: AtomicU64,
+        healthy: u64,
+    }
+
+    impl FlushTarget for Counter {
+        fn flush(&self) -> io::Result<()> {
+            if self.flushes.fetch_add(1, Ordering::SeqCst) >= self.healthy {
+                return Err(io::Error::other("disk full"));
+            }
+            Ok(())
+        }
+    }
+
+    fn wait_for(condition: impl Fn() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !condition() {
+            assert!(Instant::now() < deadline, "timed out");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_dirty_pages_reach_disk_without_explicit_flush() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.bkd");
+        let mut arena = NodeArena::new();
+        for i in 0..3u64 {
+            arena.allocate(BoundingBox::new(0.0, 0.0, 1.0, 1.0), i);
+        }
+        write_arena(&path, &arena, Some(0)).unwrap();
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        let pool = Arc::new(BufferPool::new(file, 4, 64).unwrap());
+        let nodes = PooledNodeFile::<BoundingBox, u64>::new(pool.clone()).unwrap();
+        let flusher = BackgroundFlusher::spawn(pool, Duration::from_millis(5));
+        nodes.set_links(0, Some(1), Some(2)).unwrap();
+
+        let on_disk = || {
+            let mut reader = NodeFileReader::<BoundingBox, u64>::open(&path).unwrap();
+            reader.read_links(0).unwrap()
+        };
+        wait_for(|| on_disk() == (Some(1), Some(2)));
+        flusher.stop().unwrap();
+    }
+
+    #[test]
+    fn test_requests_errors_and_final_flush() {
+        let target = Arc::new(Counter {
+            flushes: AtomicU64::new(0),
+            healthy: 2,
+        });
+        let flusher = BackgroundFlusher::spawn(target.clone(), Duration::from_secs(3600));
+        assert_eq!(flusher.flushes(), 0);
+        flusher.flush_now();
+        wait_for(|| flusher.flushes() == 1);
+        assert!(flusher.take_error().is_none());
+
+        // The second flush is the last healthy one; stopping runs a third that fails
+        flusher.flush_now();
+        wait_for(|| flusher.flushes() == 2);
+        let error = flusher.stop().unwrap_err();
+        assert_eq!(error.to_string(), "disk full");
+        assert_eq!(target.flushes.load(Ordering::SeqCst), 3);
+    }
+}
@@ -0,0 +1,726 @@
+//! Immutable, packed trees for read-heavy serving.
+
+use crate::build::{BuildOptions, bulk_build};
+use crate::deletes::DeletionBitmap;
+use crate::digest::Fnv1a;
+use crate::query::{Relation, SpatialQuery};
+use crate::search::{SearchCursor, SearchPage, children_to_visit};
+use crate::spatial::Point;
+use crate::storage::{ArenaView, InMemoryLinker, NodeArena, NodeLinker};
+use std::collections::VecDeque;
+use std::io;
+
+/// Marks a node without a right subtree in `FrozenIndex::right`.
+const NO_RIGHT: u32 = u32::MAX;
+
+/// Subtrees the planner relates to the query before estimating the rest.
+const PLAN_BUDGET: usize = 64;
+
+/// Estimated share of matching entries above which scanning beats traversal.
+const SCAN_SELECTIVITY: f64 = 0.5;
+
+/// How a search visits the entries of a `FrozenIndex`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecutionStrategy {
+    /// Walk the tree, pruning subtrees with their bounds and split values.
+    Traverse,
+    /// Test every entry in storage order, with no pruning and no tree walk.
+    Scan,
+}
+
+/// A strategy chosen by `FrozenIndex::plan`, and the estimate it was chosen from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QueryPlan {
+    /// The cheaper strategy for the query.
+    pub strategy: ExecutionStrategy,
+    /// Estimated number of matching entries.
+    pub estimated_matches: f64,
+}
+
+/// A read-only tree in a packed layout, from `SpatialIndex::freeze` or `from_linker`.
+///
+/// # Architecture Decision: no mutation at all
+/// A `FrozenIndex` has no `&mut self` methods and does not implement `NodeLinker`, whose
+/// `link_*` methods would let a caller reshape it. Since the tree can never change, it is
+/// laid out once for searching:
+/// - Nodes are stored in pre-order in parallel arrays of points, payloads and links, so a
+///   node's left child is the next node and every subtree is a contiguous range
+/// - Subtree bounds are computed once, as `SubtreeBounds` does, and a subtree inside the
+///   query is collected as a range without visiting its nodes
+/// - Links are `u32` offsets, half the size of the `Option<usize>` links of `NodeArena`
+///
+/// It is `Send` and `Sync` when `P` and `T` are: share it behind an `Arc` and search it from
+/// any number of threads without locks.
+///
+/// Handles are positions in pre-order, from `0` to `len() - 1`, and differ from the handles
+/// of the tree it was frozen from.
+#[derive(Debug, Clone)]
+pub struct FrozenIndex<P, T> {
+    points: Box<[P]>,
+    data: Box<[T]>,
+    /// Start of each node's right subtree, or `NO_RIGHT`.
+    right: Box<[u32]>,
+    /// End of each node's subtree, exclusive.
+    end: Box<[u32]>,
+    /// `dimensions` minimums followed by `dimensions` maximums per node.
+    bounds: Box<[f64]>,
+    dimensions: usize,
+    depth: usize,
+    /// Digest of the shape and points, identifying the index in encoded cursors.
+    fingerprint: u64,
+}
+
+/// Nodes of a tree in pre-order, with whether each has a left child and the position of
+/// its right child.
+struct Layout<R> {
+    order: Vec<R>,
+    has_left: Vec<bool>,
+    right: Vec<u32>,
+}
+
+impl<P: Point, T> FrozenIndex<P, T> {
+    /// Freeze a copy of the tree at `root`, whose root splits at `depth`.
+    ///
+    /// # Panics
+    /// Panics if the tree has `u32::MAX` nodes or more.
+    pub fn from_linker<L: NodeLinker<P, T>>(
+        linker: &L,
+        root: Option<L::NodeRef>,
+        depth: usize,
+    ) -> Self
+    where
+        P: Clone,
+        T: Clone,
+    {
+        let layout = pre_order(linker, root);
+        let points = layout
+            .order
+            .iter()
+            .map(|&node| linker.get_point(node).clone())
+            .collect();
+        let data = layout
+            .order
+            .iter()
+            .map(|&node| linker.get_data(node).clone())
+            .collect();
+        FrozenIndex::pack(points, data, layout, depth)
+    }
+
+    /// Freeze the tree at `root` of `arena`, moving the entries instead of copying them.
+    /// Nodes not reachable from `root` are dropped.
+    pub(crate) fn from_arena(arena: NodeArena<P, T>, root: Option<usize>, depth: usize) -> Self {
+        let layout = pre_order(&ArenaView::new(&arena), root);
+        let mut nodes: Vec<Option<(P, T)>> = arena
+            .into_parts()
+            .into_iter()
+            .map(|node| Some((node.point, node.data)))
+            .collect();
+        let (points, data) = layout
+            .order
+            .iter()
+            .map(|&node| nodes[node].take().expect("a tree reaches each node once"))
+            .unzip();
+        FrozenIndex::pack(points, data, layout, depth)
+    }
+
+    /// Compute the subtree ranges and bounds of nodes laid out in pre-order.
+    fn pack(points: Vec<P>, data: Vec<T>, layout: Layout<impl Copy>, depth: usize) -> Self {
+        let Layout {
+            has_left, right, ..
+        } = layout;
+        let len = points.len();
+        let dimensions = points.first().map_or(0, Point::dimensions);
+        let width = 2 * dimensions;
+        let mut end = vec![0u32; len];
+        let mut bounds = vec![0.0; len * width];
+
+        // Children follow their parent, so walking backwards finishes them first
+        for node in (0..len).rev() {
+            let left = has_left[node].then_some(node + 1);
+            let right_child = (right[node] != NO_RIGHT).then_some(right[node] as usize);
+            end[node] = match (left, right_child) {
+                (_, Some(child)) | (Some(child), None) => end[child],
+                (None, None) => (node + 1) as u32,
+            };
+
+            let point = &points[node];
+            for dim in 0..dimensions {
+                bounds[node * width + dim] = point.get_dimension(dim);
+                bounds[node * width + dimensions + dim] = point.get_dimension(dim);
+            }
+            for child in left.into_iter().chain(right_child) {
+                for slot in 0..width {
+                    let value = bounds[child * width + slot];
+                    let bound = &mut bounds[node * width + slot];
+                    *bound = if slot < dimensions {
+                        bound.min(value)
+                    } else {
+                        bound.max(value)
+                    };
+                }
+            }
+        }
+
+        let fingerprint = fingerprint(&points, &has_left, &right);
+        FrozenIndex {
+            points: points.into(),
+            data: data.into(),
+            right: right.into(),
+            end: end.into(),
+            bounds: bounds.into(),
+            dimensions,
+            depth,
+            fingerprint,
+        }
+    }
+
+    /// Number of entries.
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    /// Check if the index holds no entries.
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// Get the point of an entry.
+    pub fn get_point(&self, node: usize) -> &P {
+        &self.points[node]
+    }
+
+    /// Get the payload of an entry.
+    pub fn get_data(&self, node: usize) -> &T {
+        &self.data[node]
+    }
+
+    /// Bounds of the subtree rooted at `node` as `(min, max)`.
+    pub fn subtree_bounds(&self, node: usize) -> (&[f64], &[f64]) {
+        let width = 2 * self.dimensions;
+        self.bounds[node * width..(node + 1) * width].split_at(self.dimensions)
+    }
+
+    /// All entries, in pre-order.
+    pub fn iter(&self) -> impl Iterator<Item = (&P, &T)> {
+        self.points.iter().zip(self.data.iter())
+    }
+
+    /// Handles of all entries matching `query`, in pre-order like `spatial_search`, with
+    /// the strategy `plan` picks.
+    pub fn search<Q: SpatialQuery<P>>(&self, query: &Q) -> Vec<usize> {
+        self.search_with(query, self.plan(query).strategy)
+    }
+
+    /// Handles of the entries matching `query` that are not marked in `deleted`, in the
+    /// order of `search`.
+    pub fn search_live<Q: SpatialQuery<P>>(
+        &self,
+        query: &Q,
+        deleted: &DeletionBitmap<usize>,
+    ) -> Vec<usize> {
+        let mut results = self.search(query);
+        if !deleted.is_empty() {
+            results.retain(|&node| !deleted.is_deleted(node));
+        }
+        results
+    }
+
+    /// Rebuild the index without the entries marked in `deleted`, moving the others into a
+    /// balanced tree. Handles change, so the bitmap does not apply to the result.
+    pub fn purge(self, deleted: &DeletionBitmap<usize>) -> Self {
+        let depth = self.depth;
+        let mut arena = NodeArena::new();
+        let mut nodes: Vec<usize> = self
+            .points
+            .into_vec()
+            .into_iter()
+            .zip(self.data.into_vec())
+            .enumerate()
+            .filter(|&(node, _)| !deleted.is_deleted(node))
+            .map(|(_, (point, data))| arena.allocate(point, data))
+            .collect();
+        let root = bulk_build(
+            &mut InMemoryLinker::new(&mut arena),
+            &mut nodes,
+            depth,
+            &BuildOptions::default(),
+        )
+        .expect("builds without a cancellation token are never cancelled");
+        FrozenIndex::from_arena(arena, root, depth)
+    }
+
+    /// Estimate how many entries match `query` and pick the cheaper way to find them.
+    ///
+    /// # Architecture
+    /// Traversal pays off by skipping subtrees; when most entries match there is little to
+    /// skip, and testing entries in storage order is faster than walking the tree. The
+    /// planner relates the largest subtrees to the query, breadth-first, until `PLAN_BUDGET`
+    /// of them have been looked at:
+    /// - A subtree outside the query adds nothing, one inside it adds its size
+    /// - A crossing subtree adds its root if that matches, and queues its children
+    /// - Crossing subtrees still queued at the end add half their size
+    ///
+    /// Queries expected to match more than `SCAN_SELECTIVITY` of the entries are scanned.
+    /// The estimate costs at most `PLAN_BUDGET` relations, whatever the size of the index.
+    pub fn plan<Q: SpatialQuery<P>>(&self, query: &Q) -> QueryPlan {
+        let mut estimated_matches = 0.0;
+        let mut queue = VecDeque::new();
+        if !self.is_empty() {
+            queue.push_back(0);
+        }
+        let mut related = 0;
+        while related < PLAN_BUDGET {
+            let Some(node) = queue.pop_front() else {
+                break;
+            };
+            related += 1;
+            let (min, max) = self.subtree_bounds(node);
+            match query.relate(min, max) {
+                Relation::CellOutsideQuery => {}
+                Relation::CellInsideQuery => estimated_matches += self.subtree_len(node) as f64,
+                Relation::CellCrossesQuery => {
+                    if query.matches(&self.points[node]) {
+                        estimated_matches += 1.0;
+                    }
+                    if self.has_left(node) {
+                        queue.push_back(node + 1);
+                    }
+                    if self.right[node] != NO_RIGHT {
+                        queue.push_back(self.right[node] as usize);
+                    }
+                }
+            }
+        }
+        estimated_matches += queue
+            .into_iter()
+            .map(|node| self.subtree_len(node) as f64 / 2.0)
+            .sum::<f64>();
+
+        let strategy = if estimated_matches > SCAN_SELECTIVITY * self.len() as f64 {
+            ExecutionStrategy::Scan
+        } else {
+            ExecutionStrategy::Traverse
+        };
+        QueryPlan {
+            strategy,
+            estimated_matches,
+        }
+    }
+
+    /// Handles of all entries matching `query` with the given strategy. Both strategies
+    /// find the same entries in the same order.
+    pub fn search_with<Q: SpatialQuery<P>>(
+        &self,
+        query: &Q,
+        strategy: ExecutionStrategy,
+    ) -> Vec<usize> {
+        match strategy {
+            ExecutionStrategy::Traverse => self.traverse(query),
+            // Storage order is pre-order, the order traversal reports
+            ExecutionStrategy::Scan => (0..self.len())
+                .filter(|&node| query.matches(&self.points[node]))
+                .collect(),
+        }
+    }
+
+    fn traverse<Q: SpatialQuery<P>>(&self, query: &Q) -> Vec<usize> {
+        let mut results = Vec::new();
+        let mut stack: Vec<(usize, usize)> = Vec::new();
+        if !self.is_empty() {
+            stack.push((0, self.depth));
+        }
+        while let Some((node, depth)) = stack.pop() {
+            let (min, max) = self.subtree_bounds(node);
+            match query.relate(min, max) {
+                Relation::CellOutsideQuery => continue,
+                Relation::CellInsideQuery => {
+                    results.extend(node..self.end[node] as usize);
+                    continue;
+                }
+                Relation::CellCrossesQuery => {}
+            }
+
+            let point = &self.points[node];
+            if query.matches(point) {
+                results.push(node);
+            }
+            // Push right first so the left subtree is reported first, as in pre-order
+            let (visit_left, visit_right) = children_to_visit(point, query, depth);
+            if visit_right && self.right[node] != NO_RIGHT {
+                stack.push((self.right[node] as usize, depth + 1));
+            }
+            if visit_left && self.has_left(node) {
+                stack.push((node + 1, depth + 1));
+            }
+        }
+        results
+    }
+
+    /// Identifier of this index for encoded cursors: a digest of its shape and of every
+    /// point, as `tree_digest` computes but without the payloads.
+    pub fn fingerprint(&self) -> u64 {
+        self.fingerprint
+    }
+
+    /// Cursor positioned before the first result of `search_page`.
+    pub fn cursor(&self) -> SearchCursor<usize> {
+        SearchCursor::new((!self.is_empty()).then_some(0), self.depth)
+    }
+
+    /// Collect up to `limit` matches of `query` from `cursor` on, in the order of `search`,
+    /// with a cursor for the next page.
+    ///
+    /// Subtrees inside the query are collected as ranges, as in `search`, when they fit in
+    /// what is left of the page, and walked node by node otherwise.
+    ///
+    /// # Panics
+    /// Panics if `limit` is 0, as `spatial_search_page` does.
+    pub fn search_page<Q: SpatialQuery<P>>(
+        &self,
+        query: &Q,
+        cursor: SearchCursor<usize>,
+        limit: usize,
+    ) -> SearchPage<usize> {
+        assert!(limit > 0, "search pages must hold at least one result");
+        let mut stack = cursor.stack;
+        let mut results = Vec::new();
+        while results.len() < limit {
+            let Some((node, depth)) = stack.pop() else {
+                break;
+            };
+            let (min, max) = self.subtree_bounds(node);
+            match query.relate(min, max) {
+                Relation::CellOutsideQuery => continue,
+                Relation::CellInsideQuery if self.subtree_len(node) <= limit - results.len() => {
+                    results.extend(node..self.end[node] as usize);
+                    continue;
+                }
+                _ => {}
+            }
+
+            let point = &self.points[node];
+            if query.matches(point) {
+                results.push(node);
+            }
+            let (visit_left, visit_right) = children_to_visit(point, query, depth);
+            if visit_right && self.right[node] != NO_RIGHT {
+                stack.push((self.right[node] as usize, depth + 1));
+            }
+            if visit_left && self.has_left(node) {
+                stack.push((node + 1, depth + 1));
+            }
+        }
+        let next = (!stack.is_empty()).then_some(SearchCursor { stack });
+        SearchPage { results, next }
+    }
+
+    /// Encode a cursor of this index as a page token, bound to its `fingerprint`.
+    pub fn encode_cursor(&self, cursor: &SearchCursor<usize>) -> Vec<u8> {
+        let stack = cursor
+            .stack
+            .iter()
+            .map(|&(node, depth)| (node as u64, depth))
+            .collect();
+        SearchCursor { stack }.to_bytes(self.fingerprint)
+    }
+
+    /// Decode a page token from `encode_cursor`, possibly produced by another process
+    /// holding the same index.
+    ///
+    /// Fails with `InvalidData` if the token is malformed, was encoded for a different
+    /// index, or names a node this index does not have, so untrusted tokens are safe to
+    /// resume.
+    pub fn decode_cursor(&self, bytes: &[u8]) -> io::Result<SearchCursor<usize>> {
+        let cursor = SearchCursor::<u64>::from_bytes(bytes, self.fingerprint)?;
+        let stack = cursor
+            .stack
+            .into_iter()
+            .map(|(node, depth)| match usize::try_from(node) {
+                Ok(node) if node < self.len() => Ok((node, depth)),
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "search cursor names a node outside the index",
+                )),
+            })
+            .collect::<io::Result<_>>()?;
+        Ok(SearchCursor { stack })
+    }
+
+    /// Number of entries in the subtree rooted at `node`.
+    fn subtree_len(&self, node: usize) -> usize {
+        self.end[node] as usize - node
+    }
+
+    /// A node has a left child if its subtree holds more than itself and the next node is
+    /// not its right child.
+    fn has_left(&self, node: usize) -> bool {
+        self.end[node] as usize > node + 1 && self.right[node] as usize != node + 1
+    }
+}
+
+/// Digest of nodes in pre-order: child flags and coordinates, as in `tree_digest`.
+fn fingerprint<P: Point>(points: &[P], has_left: &[bool], right: &[u32]) -> u64 {
+    let mut hasher = Fnv1a(Fnv1a::OFFSET_BASIS);
+    for ((point, &left), &right) in points.iter().zip(has_left).zip(right) {
+        hasher.write(&[left as u8 | ((right != NO_RIGHT) as u8) << 1]);
+        hasher.write(&(point.dimensions() as u32).to_le_bytes());
+        for dim in 0..point.dimensions() {
+            hasher.write(&point.get_dimension(dim).to_bits().to_le_bytes());
+        }
+    }
+    hasher.0
+}
+
+/// Lay out the tree at `root` in pre-order.
+fn pre_order<P: Point, T, L: NodeLinker<P, T>>(
+    linker: &L,
+    root: Option<L::NodeRef>,
+) -> Layout<L::NodeRef> {
+    let mut layout = Layout {
+        order: Vec::new(),
+        has_left: Vec::new(),
+        right: Vec::new(),
+    };
+    // Each entry carries the position of the parent whose right child it is
+    let mut stack: Vec<(L::NodeRef, Option<usize>)> =
+        root.map(|node| (node, None)).into_iter().collect();
+    while let Some((node, right_of)) = stack.pop() {
+        let position = layout.order.len();
+        assert!(
+            position < NO_RIGHT as usize,
+            "a frozen index holds fewer than u32::MAX nodes"
+        );
+        if let Some(parent) = right_of {
+            layout.right[parent] = position as u32;
+        }
+        layout.order.push(node);
+        layout.right.push(NO_RIGHT);
+        let left = linker.get_left(node);
+        layout.has_left.push(left.is_some());
+
+        stack.extend(linker.get_right(node).map(|child| (child, Some(position))));
+        stack.extend(left.map(|child| (child, None)));
+    }
+    layout
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::SpatialIndex;
+    use crate::query::Circle;
+    use crate::search::spatial_search;
+    use crate::spatial::BoundingBox;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_frozen_search_matches_source_tree() {
+        let mut index = SpatialIndex::new();
+        for i in 0..500u32 {
+            let x = ((i * 37) % 101) as f64;
+            let y = ((i * 53) % 97) as f64;
+            index.insert(BoundingBox::new(x, y, x + 2.0, y + 1.0), i);
+        }
+        let copy = FrozenIndex::from_linker(&index, index.root(), 0);
+        let queries = [
+            BoundingBox::new(10.0, 10.0, 40.0, 30.0),
+            BoundingBox::new(-5.0, -5.0, 200.0, 200.0),
+            BoundingBox::new(150.0, 150.0, 160.0, 160.0),
+            BoundingBox::new(50.0, 0.0, 50.5, 100.0),
+        ];
+        let expected: Vec<Vec<u32>> = queries
+            .iter()
+            .map(|query| {
+                spatial_search(&index, index.root(), query, 0)
+                    .into_iter()
+                    .map(|node| *index.get_data(node))
+                    .collect()
+            })
+            .collect();
+
+        let frozen = Arc::new(index.freeze());
+        assert_eq!(frozen.len(), 500);
+        assert_eq!(frozen.subtree_bounds(0), copy.subtree_bounds(0));
+        let (min, max) = frozen.subtree_bounds(0);
+        assert_eq!(min, &[0.0, 0.0, 2.0, 1.0]);
+        assert_eq!(max, &[100.0, 96.0, 102.0, 97.0]);
+
+        let threads: Vec<_> = queries
+            .into_iter()
+            .zip(expected)
+            .map(|(query, expected)| {
+                let frozen = Arc::clone(&frozen);
+                std::thread::spawn(move || {
+                    let found: Vec<u32> = frozen
+                        .search(&query)
+                        .into_iter()
+                        .map(|node| *frozen.get_data(node))
+                        .collect();
+                    assert_eq!(found, expected);
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let circle = Circle::new(50.0, 50.0, 10.0);
+        let found = frozen.search(&circle);
+        assert!(!found.is_empty());
+        assert_eq!(found, copy.search(&circle));
+        assert!(
+            found
+                .iter()
+                .all(|&node| circle.matches(frozen.get_point(node)))
+        );
+    }
+
+    #[test]
+    fn test_freeze_degenerate_and_empty_trees() {
+        let empty: FrozenIndex<BoundingBox, u32> = SpatialIndex::new().freeze();
+        assert!(empty.is_empty());
+        assert!(
+            empty
+                .search(&BoundingBox::new(0.0, 0.0, 1.0, 1.0))
+                .is_empty()
+        );
+
+        // Sorted insertion grows a right-leaning chain; reverse order a left-leaning one
+        for values in [(0..200).collect::<Vec<u32>>(), (0..200).rev().collect()] {
+            let mut index = SpatialIndex::new();
+            for &i in &values {
+                let x = f64::from(i);
+                index.insert(BoundingBox::new(x, x, x + 1.0, x + 1.0), i);
+            }
+            let frozen = index.freeze();
+            let mut found: Vec<u32> = frozen
+                .search(&BoundingBox::new(10.5, 10.5, 20.0, 20.0))
+                .into_iter()
+                .map(|node| *frozen.get_data(node))
+                .collect();
+            found.sort_unstable();
+            assert_eq!(found, (10..=20).collect::<Vec<_>>());
+            assert_eq!(frozen.iter().count(), 200);
+        }
+    }
+
+    #[test]
+    fn test_deleted_entries_are_skipped_then_purged() {
+        let mut index = SpatialIndex::new();
+        for i in 0..300u32 {
+            let x = ((i * 37) % 101) as f64;
+            index.insert(BoundingBox::new(x, x, x + 1.0, x + 1.0), i);
+        }
+        let frozen = index.freeze();
+        let query = BoundingBox::new(10.0, 10.0, 60.0, 60.0);
+        let deleted: DeletionBitmap = (0..frozen.len())
+            .filter(|&node| frozen.get_data(node) % 3 == 0)
+            .collect();
+
+        let live = frozen.search_live(&query, &deleted);
+        let mut expected = frozen.search(&query);
+        expected.retain(|&node| frozen.get_data(node) % 3 != 0);
+        assert_eq!(live, expected);
+        let mut kept: Vec<u32> = live.iter().map(|&node| *frozen.get_data(node)).collect();
+
+        let purged = frozen.purge(&deleted);
+        assert_eq!(purged.len(), 200);
+        let mut found: Vec<u32> = purged
+            .search(&query)
+            .into_iter()
+            .map(|node| *purged.get_data(node))
+            .collect();
+        found.sort_unstable();
+        kept.sort_unstable();
+        assert_eq!(found, kept);
+    }
+
+    #[test]
+    fn test_planner_scans_unselective_queries() {
+        let mut index = SpatialIndex::new();
+        for i in 0..2000u32 {
+            let x = ((i * 7919) % 1009) as f64 / 10.0;
+            let y = ((i * 104_729) % 997) as f64 / 10.0;
+            index.insert(BoundingBox::new(x, y, x + 0.5, y + 0.5), i);
+        }
+        let frozen = index.freeze();
+
+        let everything = BoundingBox::new(-1.0, -1.0, 101.0, 101.0);
+        let most = BoundingBox::new(5.0, 5.0, 101.0, 101.0);
+        let small = BoundingBox::new(40.0, 40.0, 45.0, 45.0);
+        let outside = BoundingBox::new(200.0, 200.0, 300.0, 300.0);
+        assert_eq!(frozen.plan(&everything).estimated_matches, 2000.0);
+        assert_eq!(frozen.plan(&everything).strategy, ExecutionStrategy::Scan);
+        assert_eq!(frozen.plan(&most).strategy, ExecutionStrategy::Scan);
+        assert_eq!(frozen.plan(&small).strategy, ExecutionStrategy::Traverse);
+        assert_eq!(frozen.plan(&outside).estimated_matches, 0.0);
+
+        for query in [everything, most, small, outside] {
+            let scanned = frozen.search_with(&query, ExecutionStrategy::Scan);
+            assert_eq!(
+                frozen.search_with(&query, ExecutionStrategy::Traverse),
+                scanned
+            );
+            assert_eq!(frozen.search(&query), scanned);
+            let estimate = frozen.plan(&query).estimated_matches;
+            assert!((estimate - scanned.len() as f64).abs() <= 0.1 * 2000.0);
+        }
+    }
+
+    #[test]
+    fn test_page_tokens_resume_in_another_index_copy() {
+        let build = || {
+            let mut index = SpatialIndex::new();
+            for i in 0..300u32 {
+                let x = ((i * 37) % 101) as f64;
+                let y = ((i * 53) % 97) as f64;
+                index.insert(BoundingBox::new(x, y, x + 2.0, y + 1.0), i);
+            }
+            index.freeze()
+        };
+        // Two processes holding the same index, one serving each page
+        let servers = [build(), build()];
+        assert_eq!(servers[0].fingerprint(), servers[1].fingerprint());
+
+        let query = BoundingBox::new(10.0, 10.0, 60.0, 50.0);
+        let mut pages = 0;
+        let mut found = Vec::new();
+        let mut token = Some(servers[0].encode_cursor(&servers[0].cursor()));
+        while let Some(bytes) = token {
+            let server = &servers[pages % 2];
+            let cursor = server.decode_cursor(&bytes).unwrap();
+            let page = server.search_page(&query, cursor, 7);
+            assert!(page.results.len() <= 7);
+            found.extend(page.results);
+            token = page.next.map(|next| server.encode_cursor(&next));
+            pages += 1;
+        }
+        assert!(pages > 2);
+        assert_eq!(
+            found,
+            servers[0].search_with(&query, ExecutionStrategy::Traverse)
+        );
+
+        // Tokens are rejected by other indexes and when malformed
+        let mut other = SpatialIndex::new();
+        other.insert(BoundingBox::new(0.0, 0.0, 1.0, 1.0), 0);
+        let other = other.freeze();
+        let bytes = servers[0].encode_cursor(&servers[0].cursor());
+        assert!(other.decode_cursor(&bytes).is_err());
+        assert!(servers[0].decode_cursor(&bytes[..bytes.len() - 1]).is_err());
+        let forged = SearchCursor {
+            stack: vec![(5000u64, 0)],
+        }
+        .to_bytes(servers[0].fingerprint());
+        assert!(servers[0].decode_cursor(&forged).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "search pages must hold at least one result")]
+    fn test_search_page_rejects_empty_pages() {
+        let mut index = SpatialIndex::new();
+        index.insert(BoundingBox::new(0.0, 0.0, 1.0, 1.0), 0);
+        let frozen = index.freeze();
+        frozen.search_page(&BoundingBox::new(0.0, 0.0, 1.0, 1.0), frozen.cursor(), 0);
+    }
+}
@@ -0,0 +1,180 @@
+//! Geographic (longitude/latitude) helpers on top of the planar spatial index.
+//!
+//! Indexed `BoundingBox` entries are interpreted as `xmin=west, ymin=south, xmax=east,
+//! ymax=north` in degrees, with longitudes in `[-180, 180]`.
+
+use crate::search::spatial_search;
+use crate::spatial::BoundingBox;
+use crate::storage::NodeLinker;
+use std::collections::HashSet;
+use std::hash::Hash;
+
+/// Wrap a longitude into the `[-180, 180]` range.
+/// Values exactly on the antimeridian keep their sign so `180.0` stays `180.0`.
+pub fn normalize_longitude(lon: f64) -> f64 {
+    if (-180.0..=180.0).contains(&lon) {
+        return lon;
+    }
+    let wrapped = (lon + 180.0).rem_euclid(360.0) - 180.0;
+    if wrapped == -180.0 && lon > 0.0 {
+        180.0
+    } else {
+        wrapped
+    }
+}
+
+/// Geographic query box that may cross the antimeridian.
+///
+/// A box whose `west` edge is east of its `east` edge (e.g. west=170, east=-170) wraps
+/// across ±180° — the Pacific-region case that a planar `BoundingBox` cannot express.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeoBox {
+    pub west: f64,
+    pub south: f64,
+    pub east: f64,
+    pub north: f64,
+}
+
+impl GeoBox {
+    /// Create a new geographic box; longitudes are normalized into `[-180, 180]`.
+    ///
+    /// A box spanning 360° or more of longitude covers every meridian, so it becomes
+    /// `[-180, 180]` rather than collapsing when its edges normalize to the same value.
+    pub fn new(west: f64, south: f64, east: f64, north: f64) -> Self {
+        let (west, east) = if east - west >= 360.0 {
+            (-180.0, 180.0)
+        } else {
+            (west, east)
+        };
+        GeoBox {
+            west: normalize_longitude(west),
+            south,
+            east: normalize_longitude(east),
+            north,
+        }
+    }
+
+    /// Check if the box wraps across the ±180° meridian.
+    pub fn crosses_antimeridian(&self) -> bool {
+        self.west > self.east
+    }
+
+    /// Planar boxes covering this geographic box: one box normally, two when the box
+    /// crosses the antimeridian (`[west, 180]` and `[-180, east]`).
+    pub fn to_bounding_boxes(&self) -> Vec<BoundingBox> {
+        if self.crosses_antimeridian() {
+            vec![
+                BoundingBox::new(self.west, self.south, 180.0, self.north),
+                BoundingBox::new(-180.0, self.south, self.east, self.north),
+            ]
+        } else {
+            vec![BoundingBox::new(
+                self.west, self.south, self.east, self.north,
+            )]
+        }
+    }
+}
+
+/// Antimeridian-aware search over a longitude/latitude index.
+///
+/// # Architecture
+/// The query is split into its planar parts (`GeoBox::to_bounding_boxes`), each part is run
+/// through the regular `spatial_search`, and the results are merged. Entries matching both
+/// halves (e.g. a box spanning the whole globe) are reported once, at their first occurrence.
+pub fn geo_search<T, L: NodeLinker<BoundingBox, T>>(
+    linker: &L,
+    root: Option<L::NodeRef>,
+    query: &GeoBox,
+    depth: usize,
+) -> Vec<L::NodeRef>
+where
+    L::NodeRef: Eq + Hash,
+{
+    let parts = query.to_bounding_boxes();
+    if parts.len() == 1 {
+        return spatial_search(linker, root, &parts[0], depth);
+    }
+
+    let mut seen = HashSet::new();
+    let mut results = Vec::new();
+    for part in &parts {
+        for node in spatial_search(linker, root, part, depth) {
+            if seen.insert(node) {
+                results.push(node);
+            }
+        }
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::insert_node;
+    use crate::storage::{InMemoryLinker, NodeArena};
+
+    #[test]
+    fn test_normalize_longitude() {
+        assert_eq!(normalize_longitude(10.0), 10.0);
+        assert_eq!(normalize_longitude(190.0), -170.0);
+        assert_eq!(normalize_longitude(-190.0), 170.0);
+        assert_eq!(normalize_longitude(540.0), 180.0);
+        assert_eq!(normalize_longitude(-180.0), -180.0);
+    }
+
+    #[test]
+    fn test_geo_search_across_antimeridian() {
+        let mut arena = NodeArena::new();
+        let fiji = arena.allocate(BoundingBox::new(177.0, -19.0, 179.0, -16.0), "fiji");
+        let samoa = arena.allocate(BoundingBox::new(-173.0, -14.5, -171.0, -13.0), "samoa");
+        let lima = arena.allocate(BoundingBox::new(-77.2, -12.2, -76.8, -11.9), "lima");
+        let world = arena.allocate(BoundingBox::new(-180.0, -90.0, 180.0, 90.0), "world");
+
+        let mut linker = InMemoryLinker::new(&mut arena);
+        let root = insert_node(&mut linker, None, lima, 0);
+        for node in [fiji, samoa, world] {
+            insert_node(&mut linker, Some(root), node, 0);
+        }
+
+        let pacific = GeoBox::new(170.0, -25.0, -165.0, -10.0);
+        assert!(pacific.crosses_antimeridian());
+
+        let mut results = geo_search(&linker, Some(root), &pacific, 0);
+        results.sort();
+        assert_eq!(results, vec![fiji, samoa, world]);
+
+        let not_crossing = GeoBox::new(-80.0, -15.0, -70.0, -10.0);
+        let mut results = geo_search(&linker, Some(root), &not_crossing, 0);
+        results.sort();
+        assert_eq!(results, vec![lima, world]);
+    }
+
+    #[test]
+    fn test_full_turn_boxes_cover_the_globe() {
+        for (west, east) in [
+            (0.0, 360.0),
+            (-200.0, 160.0),
+            (-180.0, 180.0),
+            (-500.0, 500.0),
+        ] {
+            let query = GeoBox::new(west, -10.0, east, 10.0);
+            assert_eq!((query.west, query.east), (-180.0, 180.0));
+            assert!(!query.crosses_antimeridian());
+        }
+        // Just short of a full turn still wraps as given
+        let query = GeoBox::new(-200.0, -10.0, 159.0, 10.0);
+        assert_eq!((query.west, query.east), (160.0, 159.0));
+
+        let mut arena = NodeArena::new();
+        let mut root = None;
+        for i in 0..36 {
+            let lon = f64::from(i) * 10.0 - 175.0;
+            let node = arena.allocate(BoundingBox::new(lon, 0.0, lon + 1.0, 1.0), i);
+            let mut linker = InMemoryLinker::new(&mut arena);
+            root = Some(insert_node(&mut linker, root, node, 0));
+        }
+        let linker = InMemoryLinker::new(&mut arena);
+        let found = geo_search(&linker, root, &GeoBox::new(0.0, -10.0, 360.0, 10.0), 0);
+        assert_eq!(found.len(), 36);
+    }
+}
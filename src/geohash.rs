@@ -0,0 +1,200 @@
+//! Geohash encoding, decoding and prefix queries.
+//!
+//! A geohash names a cell of the longitude/latitude grid: each base-32 character adds five
+//! bits that alternately halve the cell's longitude and latitude range, longitude first.
+//! Every prefix of a geohash names the cell containing it, so a prefix query is a bounding
+//! box search. Coordinates follow the rest of the crate: longitude (x) before latitude (y).
+
+use crate::search::spatial_search;
+use crate::spatial::BoundingBox;
+use crate::storage::NodeLinker;
+use std::error::Error;
+use std::fmt;
+
+/// The geohash alphabet: digits and lowercase letters without `a`, `i`, `l` and `o`.
+const ALPHABET: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+/// Longest geohash accepted; 12 characters already resolve to a few centimeters.
+pub const MAX_PRECISION: usize = 12;
+
+/// Error for a geohash that is too long or contains a character outside the alphabet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidGeohash {
+    geohash: String,
+}
+
+impl fmt::Display for InvalidGeohash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid geohash {:?}", self.geohash)
+    }
+}
+
+impl Error for InvalidGeohash {}
+
+/// Encode a position as a geohash of `precision` characters.
+///
+/// Longitudes outside `[-180, 180]` and latitudes outside `[-90, 90]` are clamped.
+///
+/// # Panics
+/// Panics if `precision` is zero or greater than `MAX_PRECISION`.
+pub fn encode(lon: f64, lat: f64, precision: usize) -> String {
+    assert!(
+        (1..=MAX_PRECISION).contains(&precision),
+        "geohash precision must be between 1 and {MAX_PRECISION}"
+    );
+    let mut ranges = [(-180.0, 180.0), (-90.0, 90.0)];
+    let values = [lon.clamp(-180.0, 180.0), lat.clamp(-90.0, 90.0)];
+
+    let mut geohash = String::with_capacity(precision);
+    let mut bit = 0;
+    for _ in 0..precision {
+        let mut index = 0;
+        for _ in 0..5 {
+            let axis = bit % 2;
+            let (min, max) = ranges[axis];
+            let mid = (min + max) / 2.0;
+            index <<= 1;
+            if values[axis] >= mid {
+                index |= 1;
+                ranges[axis].0 = mid;
+            } else {
+                ranges[axis].1 = mid;
+            }
+            bit += 1;
+        }
+        geohash.push(ALPHABET[index] as char);
+    }
+    geohash
+}
+
+/// Cell named by a geohash, as a box of `west, south, east, north` degrees.
+///
+/// The empty geohash names the whole globe. Decoding is case-insensitive.
+pub fn decode_bbox(geohash: &str) -> Result<BoundingBox, InvalidGeohash> {
+    let invalid = || InvalidGeohash {
+        geohash: geohash.to_string(),
+    };
+    if geohash.len() > MAX_PRECISION {
+        return Err(invalid());
+    }
+
+    let mut ranges = [(-180.0, 180.0), (-90.0, 90.0)];
+    let mut bit = 0;
+    for character in geohash.bytes() {
+        let index = ALPHABET
+            .iter()
+            .position(|&letter| letter == character.to_ascii_lowercase())
+            .ok_or_else(invalid)?;
+        for shift in (0..5).rev() {
+            let axis = bit % 2;
+            let mid = (ranges[axis].0 + ranges[axis].1) / 2.0;
+            if index >> shift & 1 == 1 {
+                ranges[axis].0 = mid;
+            } else {
+                ranges[axis].1 = mid;
+            }
+            bit += 1;
+        }
+    }
+    let [(west, east), (south, north)] = ranges;
+    Ok(BoundingBox::new(west, south, east, north))
+}
+
+/// Center of the cell named by a geohash, as `(lon, lat)`.
+pub fn decode(geohash: &str) -> Result<(f64, f64), InvalidGeohash> {
+    let cell = decode_bbox(geohash)?;
+    Ok(((cell.xmin + cell.xmax) / 2.0, (cell.ymin + cell.ymax) / 2.0))
+}
+
+/// Find the entries overlapping the cell a geohash prefix names.
+///
+/// This is a `spatial_search` with the cell as the query box, so an entry lying exactly on
+/// a cell edge matches both neighboring cells; filter with `encode` when each point must
+/// belong to exactly one cell.
+pub fn geohash_search<T, L: NodeLinker<BoundingBox, T>>(
+    linker: &L,
+    root: Option<L::NodeRef>,
+    prefix: &str,
+    depth: usize,
+) -> Result<Vec<L::NodeRef>, InvalidGeohash> {
+    let cell = decode_bbox(prefix)?;
+    Ok(spatial_search(linker, root, &cell, depth))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::insert_node;
+    use crate::spatial::SpatialPoint;
+    use crate::storage::{InMemoryLinker, NodeArena};
+
+    #[test]
+    fn test_encode_decode_known_values() {
+        // Reference values from geohash.org
+        assert_eq!(encode(-5.6, 42.6, 5), "ezs42");
+        assert_eq!(encode(10.40744, 57.64911, 11), "u4pruydqqvj");
+
+        let cell = decode_bbox("ezs42").unwrap();
+        assert!(cell.xmin <= -5.6 && -5.6 <= cell.xmax);
+        assert!(cell.ymin <= 42.6 && 42.6 <= cell.ymax);
+        let (lon, lat) = decode("u4pruydqqvj").unwrap();
+        assert!((lon - 10.40744).abs() < 1e-5 && (lat - 57.64911).abs() < 1e-5);
+
+        assert_eq!(
+            decode_bbox("").unwrap(),
+            BoundingBox::new(-180.0, -90.0, 180.0, 90.0)
+        );
+        assert_eq!(decode_bbox("EZS42"), decode_bbox("ezs42"));
+    }
+
+    #[test]
+    fn test_prefixes_nest() {
+        let geohash = encode(151.2093, -33.8688, MAX_PRECISION);
+        for length in 1..MAX_PRECISION {
+            let outer = decode_bbox(&geohash[..length]).unwrap();
+            let inner = decode_bbox(&geohash[..length + 1]).unwrap();
+            assert!(inner.is_within(&outer));
+        }
+    }
+
+    #[test]
+    fn test_invalid_geohash() {
+        assert!(decode_bbox("ezs4a").is_err());
+        assert!(decode_bbox("0123456789bcd").is_err());
+        let error = decode("héllo").unwrap_err();
+        assert_eq!(error.to_string(), "invalid geohash \"héllo\"");
+    }
+
+    #[test]
+    fn test_geohash_search() {
+        let mut arena = NodeArena::new();
+        let places = [
+            ((-0.1276, 51.5072), "london"),
+            ((2.3522, 48.8566), "paris"),
+            ((-0.0754, 51.5055), "tower bridge"),
+            ((139.6503, 35.6762), "tokyo"),
+        ];
+        let refs: Vec<usize> = places
+            .iter()
+            .map(|&((lon, lat), name)| arena.allocate(BoundingBox::new(lon, lat, lon, lat), name))
+            .collect();
+        let mut linker = InMemoryLinker::new(&mut arena);
+        let root = insert_node(&mut linker, None, refs[0], 0);
+        for &node in &refs[1..] {
+            insert_node(&mut linker, Some(root), node, 0);
+        }
+
+        let prefix = &encode(-0.1, 51.5, 3);
+        assert_eq!(prefix, "gcp");
+        let mut found: Vec<&str> = geohash_search(&linker, Some(root), prefix, 0)
+            .unwrap()
+            .into_iter()
+            .map(|node| *linker.get_data(node))
+            .collect();
+        found.sort_unstable();
+        assert_eq!(found, ["london", "tower bridge"]);
+
+        assert_eq!(geohash_search(&linker, Some(root), "", 0).unwrap().len(), 4);
+        assert!(geohash_search(&linker, Some(root), "!", 0).is_err());
+    }
+}
@@ -0,0 +1,387 @@
+//! Indexes partitioned by a grid or quadtree at the top, with a KD-tree in each cell.
+
+use crate::build::{BuildOptions, bulk_build};
+use crate::cancel::Cancelled;
+use crate::nearest::{Metric, Neighbor, nearest_iter};
+use crate::query::{Relation, SpatialQuery};
+use crate::search::spatial_search;
+use crate::spatial::Point;
+use crate::storage::{ArenaView, InMemoryLinker, NodeArena};
+
+/// How a `HybridIndex` divides its entries before building a KD-tree per cell.
+///
+/// Both divide on the first two dimensions, the lower-left corner of a `BoundingBox`, over
+/// the extent the entries span.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TopLevel {
+    /// A fixed grid of `columns × rows` equal cells.
+    Grid { columns: usize, rows: usize },
+    /// Quadrants split in four until they hold at most `max_entries` entries or reach
+    /// `max_depth`.
+    Quadtree {
+        max_entries: usize,
+        max_depth: usize,
+    },
+}
+
+/// A cell of the top level and the KD-tree over its entries.
+struct Cell {
+    root: usize,
+    len: usize,
+    /// Per-dimension bounds of the entries, not of the cell's region.
+    min: Vec<f64>,
+    max: Vec<f64>,
+}
+
+/// A bulk-built index whose top levels are a grid or quadtree instead of KD splits.
+///
+/// # Architecture Decision: fixed regions first, medians below
+/// A single KD root places its splits at medians, so on global data with dense cities and
+/// empty oceans the top splits run through the cities, and the cells around them become
+/// thin slabs spanning the ocean that every nearby query crosses. Here the top levels cut
+/// space regardless of density: a grid into equal cells, or a quadtree into quadrants that
+/// only keep splitting where entries crowd. Each non-empty cell gets its own KD-tree,
+/// bulk-built with the given `BuildOptions`, and remembers the bounds of its entries.
+/// Queries relate those bounds first, so empty regions cost nothing and only the cells a
+/// query reaches are searched.
+///
+/// All subtrees share one arena; handles are arena positions, as in `SpatialIndex`, and
+/// each subtree's root splits at depth 0.
+///
+/// # Usage pattern:
+/// ```rust
+/// use bkd::{BoundingBox, BuildOptions, HybridIndex, TopLevel};
+///
+/// let entries = vec![
+///     (BoundingBox::new(2.2, 48.8, 2.5, 48.9), "paris"),
+///     (BoundingBox::new(-0.2, 51.4, 0.1, 51.6), "london"),
+///     (BoundingBox::new(139.6, 35.5, 139.9, 35.8), "tokyo"),
+/// ];
+/// let top = TopLevel::Quadtree { max_entries: 1, max_depth: 8 };
+/// let index = HybridIndex::build(entries, top, &BuildOptions::default()).unwrap();
+/// assert_eq!(index.cells(), 3);
+///
+/// let found = index.search(&BoundingBox::new(2.0, 48.0, 3.0, 49.0));
+/// assert_eq!(found.len(), 1);
+/// assert_eq!(*index.arena().get(found[0]).get_data(), "paris");
+/// ```
+pub struct HybridIndex<P: Point, T> {
+    arena: NodeArena<P, T>,
+    cells: Vec<Cell>,
+}
+
+impl<P: Point, T> HybridIndex<P, T> {
+    /// Divide `entries` by `top` and bulk-build a KD-tree in every non-empty cell.
+    ///
+    /// Returns `Err(Cancelled)` if `options.cancel` is triggered. Progress callbacks fire
+    /// per cell, each counting its own entries.
+    ///
+    /// # Panics
+    /// Panics if a grid has no columns or rows, or a quadtree allows no entries per cell.
+    pub fn build(
+        entries: impl IntoIterator<Item = (P, T)>,
+        top: TopLevel,
+        options: &BuildOptions,
+    ) -> Result<Self, Cancelled> {
+        let mut arena = NodeArena::new();
+        let nodes: Vec<usize> = entries
+            .into_iter()
+            .map(|(point, data)| arena.allocate(point, data))
+            .collect();
+        let corner = |node: usize| {
+            let point = arena.get(node).get_point();
+            (point.get_dimension(0), point.get_dimension(1))
+        };
+        let groups = match top {
+            TopLevel::Grid { columns, rows } => {
+                assert!(columns > 0 && rows > 0, "a grid needs columns and rows");
+                grid(&nodes, corner, columns, rows)
+            }
+            TopLevel::Quadtree {
+                max_entries,
+                max_depth,
+            } => {
+                assert!(max_entries > 0, "quadtree cells must hold entries");
+                quadtree(nodes, corner, max_entries, max_depth)
+            }
+        };
+
+        let mut cells = Vec::with_capacity(groups.len());
+        for mut group in groups {
+            let (min, max) = entry_bounds(&arena, &group);
+            let mut linker = InMemoryLinker::new(&mut arena);
+            let root =
+                bulk_build(&mut linker, &mut group, 0, options)?.expect("cells are never empty");
+            cells.push(Cell {
+                root,
+                len: group.len(),
+                min,
+                max,
+            });
+        }
+        Ok(HybridIndex { arena, cells })
+    }
+
+    /// Handles of all entries matching `query`, cell by cell in `spatial_search` order.
+    pub fn search<Q: SpatialQuery<P>>(&self, query: &Q) -> Vec<usize> {
+        let linker = ArenaView::new(&self.arena);
+        let mut found = Vec::new();
+        for cell in &self.cells {
+            if query.relate(&cell.min, &cell.max) != Relation::CellOutsideQuery {
+                found.extend(spatial_search(&linker, Some(cell.root), query, 0));
+            }
+        }
+        found
+    }
+
+    /// Find the `k` entries closest to `target`, nearest first.
+    ///
+    /// Cells are visited by the distance to their entries' bounds, and the search stops at
+    /// the first cell farther than the `k`-th neighbor found so far.
+    pub fn nearest_neighbors(
+        &self,
+        target: &[f64],
+        k: usize,
+        metric: &Metric,
+    ) -> Vec<Neighbor<usize>> {
+        let linker = ArenaView::new(&self.arena);
+        let mut order: Vec<(f64, &Cell)> = self
+            .cells
+            .iter()
+            .map(|cell| (metric.distance_to_cell(target, &cell.min, &cell.max), cell))
+            .collect();
+        order.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut neighbors: Vec<Neighbor<usize>> = Vec::new();
+        for (distance, cell) in order {
+            if neighbors.len() == k && neighbors.last().is_some_and(|far| far.distance < distance) {
+                break;
+            }
+            neighbors.extend(nearest_iter(&linker, Some(cell.root), target, metric, 0).take(k));
+            // Stable, so equidistant entries keep cell order
+            neighbors.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+            neighbors.truncate(k);
+        }
+        neighbors
+    }
+
+    /// Roots of the cells' KD-trees, each splitting at depth 0, for running other
+    /// algorithms over `arena()` cell by cell.
+    pub fn roots(&self) -> impl Iterator<Item = usize> + '_ {
+        self.cells.iter().map(|cell| cell.root)
+    }
+
+    /// Number of non-empty cells.
+    pub fn cells(&self) -> usize {
+        self.cells.len()
+    }
+
+    /// Number of entries in the fullest cell.
+    pub fn largest_cell(&self) -> usize {
+        self.cells.iter().map(|cell| cell.len).max().unwrap_or(0)
+    }
+
+    /// Number of entries.
+    pub fn len(&self) -> usize {
+        self.arena.len()
+    }
+
+    /// Check if the index holds no entries.
+    pub fn is_empty(&self) -> bool {
+        self.arena.is_empty()
+    }
+
+    /// The arena holding the entries of every cell.
+    pub fn arena(&self) -> &NodeArena<P, T> {
+        &self.arena
+    }
+}
+
+/// Extent of the corners of `nodes`: `(xmin, ymin, xmax, ymax)`.
+fn extent(nodes: &[usize], corner: impl Fn(usize) -> (f64, f64)) -> (f64, f64, f64, f64) {
+    nodes.iter().fold(
+        (
+            f64::INFINITY,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            f64::NEG_INFINITY,
+        ),
+        |(xmin, ymin, xmax, ymax), &node| {
+            let (x, y) = corner(node);
+            (xmin.min(x), ymin.min(y), xmax.max(x), ymax.max(y))
+        },
+    )
+}
+
+/// Position of `value` among `count` equal slices of `min..=max`, the last slice closed.
+fn slot(value: f64, min: f64, max: f64, count: usize) -> usize {
+    if max <= min {
+        return 0;
+    }
+    (((value - min) / (max - min) * count as f64) as usize).min(count - 1)
+}
+
+/// Group `nodes` by grid cell, dropping empty cells.
+fn grid(
+    nodes: &[usize],
+    corner: impl Fn(usize) -> (f64, f64),
+    columns: usize,
+    rows: usize,
+) -> Vec<Vec<usize>> {
+    let (xmin, ymin, xmax, ymax) = extent(nodes, &corner);
+    let mut groups = vec![Vec::new(); columns * rows];
+    for &node in nodes {
+        let (x, y) = corner(node);
+        let column = slot(x, xmin, xmax, columns);
+        let row = slot(y, ymin, ymax, rows);
+        groups[row * columns + column].push(node);
+    }
+    groups.retain(|group| !group.is_empty());
+    groups
+}
+
+/// Group `nodes` by quadtree leaf, splitting quadrants over `max_entries` until
+/// `max_depth`. Quadrants are split at the midpoint of their region, not of their entries,
+/// so the cells of a region do not depend on what else was indexed.
+fn quadtree(
+    nodes: Vec<usize>,
+    corner: impl Fn(usize) -> (f64, f64),
+    max_entries: usize,
+    max_depth: usize,
+) -> Vec<Vec<usize>> {
+    let region = extent(&nodes, &corner);
+    let mut groups = Vec::new();
+    let mut tasks = vec![(nodes, region, 0)];
+    while let Some((nodes, (xmin, ymin, xmax, ymax), depth)) = tasks.pop() {
+        if nodes.is_empty() {
+            continue;
+        }
+        if nodes.len() <= max_entries || depth >= max_depth {
+            groups.push(nodes);
+            continue;
+        }
+
+        let (xmid, ymid) = ((xmin + xmax) / 2.0, (ymin + ymax) / 2.0);
+        let mut quadrants: [Vec<usize>; 4] = Default::default();
+        for node in nodes {
+            let (x, y) = corner(node);
+            quadrants[(x > xmid) as usize + 2 * (y > ymid) as usize].push(node);
+        }
+        let [south_west, south_east, north_west, north_east] = quadrants;
+        tasks.push((north_east, (xmid, ymid, xmax, ymax), depth + 1));
+        tasks.push((north_west, (xmin, ymid, xmid, ymax), depth + 1));
+        tasks.push((south_east, (xmid, ymin, xmax, ymid), depth + 1));
+        tasks.push((south_west, (xmin, ymin, xmid, ymid), depth + 1));
+    }
+    groups
+}
+
+/// Per-dimension minimums and maximums of the points of `nodes`.
+fn entry_bounds<P: Point, T>(arena: &NodeArena<P, T>, nodes: &[usize]) -> (Vec<f64>, Vec<f64>) {
+    let dimensions = arena.get(nodes[0]).get_point().dimensions();
+    let mut min = vec![f64::INFINITY; dimensions];
+    let mut max = vec![f64::NEG_INFINITY; dimensions];
+    for &node in nodes {
+        let point = arena.get(node).get_point();
+        for dim in 0..dimensions {
+            min[dim] = min[dim].min(point.get_dimension(dim));
+            max[dim] = max[dim].max(point.get_dimension(dim));
+        }
+    }
+    (min, max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::SpatialIndex;
+    use crate::spatial::BoundingBox;
+
+    #[test]
+    fn test_hybrid_index_matches_a_single_tree_on_skewed_data() {
+        // Three dense cities and a sprinkle of entries across an otherwise empty world
+        let mut entries = Vec::new();
+        for (i, (lon, lat)) in [(2.35, 48.85), (-74.0, 40.7), (139.7, 35.7)]
+            .into_iter()
+            .enumerate()
+        {
+            for j in 0..400u32 {
+                let x = lon + ((j * 37) % 101) as f64 * 0.001;
+                let y = lat + ((j * 53) % 97) as f64 * 0.001;
+                entries.push((
+                    BoundingBox::new(x, y, x + 0.0005, y + 0.0005),
+                    i as u32 * 1000 + j,
+                ));
+            }
+        }
+        for j in 0..60u32 {
+            let x = ((j * 71) % 360) as f64 - 180.0;
+            let y = ((j * 43) % 170) as f64 - 85.0;
+            entries.push((BoundingBox::new(x, y, x + 1.0, y + 1.0), 5000 + j));
+        }
+
+        let mut whole = SpatialIndex::new();
+        for (bbox, id) in &entries {
+            whole.insert(bbox.clone(), *id);
+        }
+        let queries = [
+            BoundingBox::new(2.36, 48.86, 2.38, 48.88),
+            BoundingBox::new(-180.0, -90.0, 0.0, 90.0),
+            BoundingBox::new(100.0, 0.0, 180.0, 50.0),
+            BoundingBox::new(50.0, -80.0, 60.0, -70.0),
+        ];
+        let target = [-73.95, 40.75, -73.95, 40.75];
+        let metric = Metric::euclidean();
+
+        let tops = [
+            TopLevel::Grid {
+                columns: 8,
+                rows: 4,
+            },
+            TopLevel::Quadtree {
+                max_entries: 64,
+                max_depth: 20,
+            },
+        ];
+        for top in tops {
+            let index = HybridIndex::build(entries.clone(), top, &BuildOptions::default()).unwrap();
+            assert_eq!(index.len(), entries.len());
+            assert_eq!(index.roots().count(), index.cells());
+            let data = |node: usize| *index.arena().get(node).get_data();
+
+            for query in &queries {
+                let mut found: Vec<u32> = index.search(query).into_iter().map(data).collect();
+                let mut expected: Vec<u32> = whole
+                    .search(query)
+                    .into_iter()
+                    .map(|node| *whole.arena().get(node).get_data())
+                    .collect();
+                found.sort_unstable();
+                expected.sort_unstable();
+                assert_eq!(found, expected);
+            }
+
+            let distances = |neighbors: Vec<Neighbor<usize>>| -> Vec<f64> {
+                neighbors.into_iter().map(|n| n.distance).collect()
+            };
+            let expected =
+                crate::nearest::nearest_neighbors(&whole, whole.root(), &target, 15, &metric, 0);
+            assert_eq!(
+                distances(index.nearest_neighbors(&target, 15, &metric)),
+                distances(expected)
+            );
+        }
+
+        // The quadtree keeps splitting inside the cities, where the grid cannot
+        let quadtree = HybridIndex::build(entries.clone(), tops[1], &BuildOptions::default());
+        let grid = HybridIndex::build(entries, tops[0], &BuildOptions::default());
+        assert!(quadtree.unwrap().largest_cell() <= 64);
+        assert!(grid.unwrap().largest_cell() >= 400);
+
+        let empty: HybridIndex<BoundingBox, u32> =
+            HybridIndex::build(Vec::new(), tops[1], &BuildOptions::default()).unwrap();
+        assert!(empty.is_empty());
+        assert!(empty.search(&queries[1]).is_empty());
+        assert!(empty.nearest_neighbors(&target, 3, &metric).is_empty());
+    }
+}
@@ -0,0 +1,268 @@
+//! An owned tree that allocates, links and tracks its root in one call.
+
+use crate::frozen::FrozenIndex;
+use crate::query::SpatialQuery;
+use crate::search::{insert_node, spatial_search};
+use crate::spatial::Point;
+use crate::storage::{AllocatingLinker, InMemoryLinker, NodeArena, NodeLinker, RootedLinker};
+
+/// A KD-tree owning its arena and root.
+///
+/// The lower-level API takes three steps per entry: allocate in a `NodeArena`, wrap it in
+/// an `InMemoryLinker`, and pass the current root to `insert_node`. `SpatialIndex` does all
+/// three in `insert`. It is also a `NodeLinker`, so every algorithm in the crate runs on it
+/// with `root()` as the starting node.
+///
+/// # Usage pattern:
+/// ```rust
+/// use bkd::{BoundingBox, NodeLinker, SpatialIndex};
+///
+/// let mut index = SpatialIndex::new();
+/// let paris = index.insert(BoundingBox::new(2.2, 48.8, 2.5, 48.9), "paris");
+/// index.insert(BoundingBox::new(-0.2, 51.4, 0.1, 51.6), "london");
+///
+/// let found = index.search(&BoundingBox::new(2.0, 48.0, 3.0, 49.0));
+/// assert_eq!(found, [paris]);
+/// assert_eq!(*index.get_data(paris), "paris");
+/// ```
+pub struct SpatialIndex<P: Point, T> {
+    arena: NodeArena<P, T>,
+    root: Option<usize>,
+}
+
+impl<P: Point, T> SpatialIndex<P, T> {
+    /// Create an empty index.
+    pub fn new() -> Self {
+        SpatialIndex {
+            arena: NodeArena::new(),
+            root: None,
+        }
+    }
+
+    /// Create an empty index with room for `capacity` entries.
+    pub fn with_capacity(capacity: usize) -> Self {
+        SpatialIndex {
+            arena: NodeArena::with_capacity(capacity),
+            root: None,
+        }
+    }
+
+    /// Insert an entry and return its handle.
+    pub fn insert(&mut self, point: P, data: T) -> usize {
+        let node = self.arena.allocate(point, data);
+        let mut linker = InMemoryLinker::new(&mut self.arena);
+        self.root = Some(insert_node(&mut linker, self.root, node, 0));
+        node
+    }
+
+    /// Handles of all entries matching `query`, in `spatial_search` order.
+    pub fn search<Q: SpatialQuery<P>>(&self, query: &Q) -> Vec<usize> {
+        spatial_search(self, self.root, query, 0)
+    }
+
+    /// Number of entries.
+    pub fn len(&self) -> usize {
+        self.arena.len()
+    }
+
+    /// Check if the index holds no entries.
+    pub fn is_empty(&self) -> bool {
+        self.arena.is_empty()
+    }
+
+    /// Root node handle, if the index is not empty.
+    pub fn root(&self) -> Option<usize> {
+        self.root
+    }
+
+    /// The arena holding the entries.
+    pub fn arena(&self) -> &NodeArena<P, T> {
+        &self.arena
+    }
+
+    /// The arena, for changes that keep the tree valid, such as moving a point within its
+    /// cell.
+    pub(crate) fn arena_mut(&mut self) -> &mut NodeArena<P, T> {
+        &mut self.arena
+    }
+
+    /// Turn the index into a read-only `FrozenIndex`, moving the entries into its packed
+    /// layout. Handles change: search the frozen index for new ones.
+    pub fn freeze(self) -> FrozenIndex<P, T> {
+        FrozenIndex::from_arena(self.arena, self.root, 0)
+    }
+
+    /// Take the arena and root apart, to persist or rebuild them.
+    pub fn into_parts(self) -> (NodeArena<P, T>, Option<usize>) {
+        (self.arena, self.root)
+    }
+
+    /// Reassemble an index from an arena and the root of the tree linked in it, as returned
+    /// by `into_parts`.
+    ///
+    /// # Panics
+    /// Panics if `root` is outside the arena, or missing while the arena holds entries.
+    pub fn from_parts(arena: NodeArena<P, T>, root: Option<usize>) -> Self {
+        match root {
+            Some(root) => assert!(root < arena.len(), "root is outside the arena"),
+            None => assert!(arena.is_empty(), "entries without a root"),
+        }
+        SpatialIndex { arena, root }
+    }
+}
+
+/// Cloning copies the arena, so the copy can be rebuilt or changed apart from the original.
+impl<P: Point + Clone, T: Clone> Clone for SpatialIndex<P, T> {
+    fn clone(&self) -> Self {
+        SpatialIndex {
+            arena: self.arena.clone(),
+            root: self.root,
+        }
+    }
+}
+
+impl<P: Point, T> Default for SpatialIndex<P, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<P: Point, T> NodeLinker<P, T> for SpatialIndex<P, T> {
+    type NodeRef = usize;
+
+    fn link_left(&mut self, parent: Self::NodeRef, child: Self::NodeRef) {
+        self.arena.get_mut(parent).left = Some(child);
+    }
+
+    fn link_right(&mut self, parent: Self::NodeRef, child: Self::NodeRef) {
+        self.arena.get_mut(parent).right = Some(child);
+    }
+
+    fn get_left(&self, node: Self::NodeRef) -> Option<Self::NodeRef> {
+        self.arena.get(node).left
+    }
+
+    fn get_right(&self, node: Self::NodeRef) -> Option<Self::NodeRef> {
+        self.arena.get(node).right
+    }
+
+    fn get_point(&self, node: Self::NodeRef) -> &P {
+        self.arena.get(node).get_point()
+    }
+
+    fn get_data(&self, node: Self::NodeRef) -> &T {
+        self.arena.get(node).get_data()
+    }
+}
+
+/// Allocates without inserting: link the node and `set_root` by hand, as `copy_tree` does.
+impl<P: Point, T> AllocatingLinker<P, T> for SpatialIndex<P, T> {
+    fn allocate(&mut self, point: P, data: T) -> usize {
+        self.arena.allocate(point, data)
+    }
+}
+
+impl<P: Point, T> RootedLinker<P, T> for SpatialIndex<P, T> {
+    fn get_root(&self) -> Option<usize> {
+        self.root
+    }
+
+    /// Replaces the root `insert` tracks, e.g. after relinking the nodes by hand.
+    fn set_root(&mut self, root: Option<usize>) {
+        self.root = root;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nearest::{Metric, nearest_neighbors};
+    use crate::spatial::BoundingBox;
+
+    #[test]
+    fn test_insert_matches_manual_linking() {
+        let mut index = SpatialIndex::new();
+        let mut arena = NodeArena::new();
+        let mut root = None;
+        for i in 0..200u32 {
+            let x = ((i * 37) % 101) as f64;
+            let y = ((i * 53) % 97) as f64;
+            let point = BoundingBox::new(x, y, x + 1.0, y + 1.0);
+            assert_eq!(index.insert(point.clone(), i), i as usize);
+
+            let node = arena.allocate(point, i);
+            let mut linker = InMemoryLinker::new(&mut arena);
+            root = Some(insert_node(&mut linker, root, node, 0));
+        }
+        assert_eq!(index.len(), 200);
+        assert_eq!(index.root(), root);
+        assert_eq!(index.get_root(), root);
+
+        let query = BoundingBox::new(10.0, 10.0, 30.0, 40.0);
+        let linker = InMemoryLinker::new(&mut arena);
+        assert_eq!(
+            index.search(&query),
+            spatial_search(&linker, root, &query, 0)
+        );
+
+        let nearest = nearest_neighbors(
+            &index,
+            index.root(),
+            &[5.0, 5.0, 6.0, 6.0],
+            3,
+            &Metric::euclidean(),
+            0,
+        );
+        assert_eq!(nearest.len(), 3);
+
+        let (arena, root) = index.into_parts();
+        assert_eq!(arena.len(), 200);
+        assert_eq!(root, Some(0));
+    }
+
+    #[test]
+    fn test_clone_and_parts_round_trip_search() {
+        let mut index = SpatialIndex::new();
+        for i in 0..100u32 {
+            let x = ((i * 37) % 101) as f64;
+            let y = ((i * 53) % 97) as f64;
+            index.insert(BoundingBox::new(x, y, x + 1.0, y + 1.0), i);
+        }
+        let query = BoundingBox::new(10.0, 10.0, 50.0, 60.0);
+        let expected = index.search(&query);
+        assert!(!expected.is_empty());
+
+        // The copy answers alike and changes apart from the original
+        let mut copy = index.clone();
+        assert_eq!(copy.search(&query), expected);
+        let extra = copy.insert(BoundingBox::new(20.0, 20.0, 21.0, 21.0), 100);
+        assert!(copy.search(&query).contains(&extra));
+        assert_eq!(index.search(&query), expected);
+
+        let (arena, root) = index.into_parts();
+        let rebuilt = SpatialIndex::from_parts(arena, root);
+        assert_eq!(rebuilt.len(), 100);
+        assert_eq!(rebuilt.search(&query), expected);
+
+        let (arena, root) = SpatialIndex::<BoundingBox, u32>::new().into_parts();
+        assert!(SpatialIndex::from_parts(arena, root).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "root is outside the arena")]
+    fn test_from_parts_rejects_dangling_root() {
+        SpatialIndex::<BoundingBox, u32>::from_parts(NodeArena::new(), Some(0));
+    }
+
+    #[test]
+    fn test_empty_index() {
+        let index: SpatialIndex<BoundingBox, u32> = SpatialIndex::default();
+        assert!(index.is_empty());
+        assert_eq!(index.root(), None);
+        assert!(
+            index
+                .search(&BoundingBox::new(0.0, 0.0, 1.0, 1.0))
+                .is_empty()
+        );
+    }
+}
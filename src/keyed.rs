@@ -0,0 +1,194 @@
+//! An owned tree whose entries are addressed by a caller's key, such as a document id.
+
+use crate::build::{BuildOptions, bulk_build};
+use crate::deletes::DeletionBitmap;
+use crate::index::SpatialIndex;
+use crate::query::SpatialQuery;
+use crate::spatial::Point;
+use crate::storage::{AllocatingLinker, RootedLinker};
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// A `SpatialIndex` with a map from keys to handles, so an entry can be moved or removed
+/// by the key it was inserted under.
+///
+/// # Architecture Decision: replace by marking
+/// A k-d tree cannot move a node: its position follows from its point. `upsert` inserts
+/// the new entry, points the key at it and marks the old one in a `DeletionBitmap`, all
+/// within one `&mut self` call, so no search ever sees both locations or neither.
+/// Replaced and removed entries stay in the tree, routing searches but never reported,
+/// until `compact` rebuilds the tree from the live entries.
+///
+/// # Usage pattern:
+/// ```rust
+/// use bkd::{BoundingBox, KeyedIndex, NodeLinker};
+///
+/// let mut index = KeyedIndex::new();
+/// index.upsert("truck-7", BoundingBox::new(0.0, 0.0, 1.0, 1.0), "parked");
+/// let moved = index.upsert("truck-7", BoundingBox::new(5.0, 5.0, 6.0, 6.0), "driving");
+///
+/// assert!(index.search(&BoundingBox::new(0.0, 0.0, 2.0, 2.0)).is_empty());
+/// assert_eq!(index.search(&BoundingBox::new(4.0, 4.0, 7.0, 7.0)), [moved]);
+/// assert_eq!(*index.index().get_data(moved), "driving");
+/// ```
+pub struct KeyedIndex<K, P: Point, T> {
+    index: SpatialIndex<P, T>,
+    keys: HashMap<K, usize>,
+    deleted: DeletionBitmap,
+}
+
+impl<K: Eq + Hash, P: Point, T> KeyedIndex<K, P, T> {
+    /// Create an empty index.
+    pub fn new() -> Self {
+        KeyedIndex {
+            index: SpatialIndex::new(),
+            keys: HashMap::new(),
+            deleted: DeletionBitmap::new(),
+        }
+    }
+
+    /// Insert an entry under `key`, replacing the entry the key held, if any. Returns the
+    /// handle of the new entry.
+    pub fn upsert(&mut self, key: K, point: P, data: T) -> usize {
+        let node = self.index.insert(point, data);
+        if let Some(previous) = self.keys.insert(key, node) {
+            self.deleted.delete(previous);
+        }
+        node
+    }
+
+    /// Remove the entry held by `key`. Returns its handle, or `None` if the key holds none.
+    pub fn remove(&mut self, key: &K) -> Option<usize> {
+        let node = self.keys.remove(key)?;
+        self.deleted.delete(node);
+        Some(node)
+    }
+
+    /// Handle of the entry held by `key`.
+    pub fn get(&self, key: &K) -> Option<usize> {
+        self.keys.get(key).copied()
+    }
+
+    /// Handles of the live entries matching `query`, in `spatial_search` order.
+    pub fn search<Q: SpatialQuery<P>>(&self, query: &Q) -> Vec<usize> {
+        let mut results = self.index.search(query);
+        if !self.deleted.is_empty() {
+            results.retain(|&node| !self.deleted.is_deleted(node));
+        }
+        results
+    }
+
+    /// Number of keys, one live entry each.
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Check if no key holds an entry.
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Replaced and removed entries still in the tree, until the next `compact`.
+    pub fn stale(&self) -> usize {
+        self.deleted.len()
+    }
+
+    /// The tree, stale entries included; resolve handles with its `NodeLinker` methods.
+    pub fn index(&self) -> &SpatialIndex<P, T> {
+        &self.index
+    }
+
+    pub(crate) fn index_mut(&mut self) -> &mut SpatialIndex<P, T> {
+        &mut self.index
+    }
+
+    /// Rebuild the tree from the live entries, balanced, and drop the stale ones. Handles
+    /// change; look them up again with `get`.
+    pub fn compact(&mut self) {
+        let (arena, _) = std::mem::take(&mut self.index).into_parts();
+        let mut entries: Vec<Option<(P, T)>> = arena
+            .into_parts()
+            .into_iter()
+            .map(|node| Some((node.point, node.data)))
+            .collect();
+        let mut index = SpatialIndex::with_capacity(self.keys.len());
+        let mut nodes: Vec<usize> = self
+            .keys
+            .values_mut()
+            .map(|node| {
+                let (point, data) = entries[*node].take().expect("keys hold distinct entries");
+                *node = index.allocate(point, data);
+                *node
+            })
+            .collect();
+        let root = bulk_build(&mut index, &mut nodes, 0, &BuildOptions::default())
+            .expect("builds without a cancellation token are never cancelled");
+        index.set_root(root);
+        self.index = index;
+        self.deleted.clear();
+    }
+}
+
+impl<K: Eq + Hash, P: Point, T> Default for KeyedIndex<K, P, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spatial::BoundingBox;
+    use crate::storage::NodeLinker;
+
+    fn at(x: f64) -> BoundingBox {
+        BoundingBox::new(x, x, x + 1.0, x + 1.0)
+    }
+
+    #[test]
+    fn test_upsert_moves_entries_and_compact_drops_stale_ones() {
+        let mut index = KeyedIndex::new();
+        for key in 0..100u32 {
+            index.upsert(key, at(key as f64), key);
+        }
+        // Move the even keys far away, then drop every tenth key
+        for key in (0..100u32).step_by(2) {
+            index.upsert(key, at(1000.0 + key as f64), key + 1000);
+        }
+        for key in (0..100u32).step_by(10) {
+            assert!(index.remove(&key).is_some());
+        }
+        assert_eq!(index.remove(&0), None);
+        assert_eq!((index.len(), index.stale()), (90, 60));
+
+        let data = |index: &KeyedIndex<u32, BoundingBox, u32>, query: &BoundingBox| {
+            let mut data: Vec<u32> = index
+                .search(query)
+                .into_iter()
+                .map(|node| *index.index().get_data(node))
+                .collect();
+            data.sort_unstable();
+            data
+        };
+        let near = BoundingBox::new(0.0, 0.0, 200.0, 200.0);
+        let far = BoundingBox::new(900.0, 900.0, 2000.0, 2000.0);
+        let odd: Vec<u32> = (0..100).filter(|key| key % 2 == 1).collect();
+        let moved: Vec<u32> = (0..100)
+            .filter(|key| key % 2 == 0 && key % 10 != 0)
+            .map(|key| key + 1000)
+            .collect();
+        assert_eq!(data(&index, &near), odd);
+        assert_eq!(data(&index, &far), moved);
+
+        index.compact();
+        assert_eq!(
+            (index.len(), index.index().len(), index.stale()),
+            (90, 90, 0)
+        );
+        assert_eq!(data(&index, &near), odd);
+        assert_eq!(data(&index, &far), moved);
+        let node = index.get(&4).unwrap();
+        assert_eq!(*index.index().get_data(node), 1004);
+        assert_eq!(index.search(&at(1004.0)), [node]);
+    }
+}
@@ -0,0 +1,379 @@
+//! Per-leaf payload summaries for block trees, kept in a sidecar file.
+
+use crate::block_tree::{BkdHeader, BkdReader};
+use crate::codec::FixedCodec;
+use crate::spatial::Point;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/// Magic bytes identifying a leaf summary file.
+pub const MAGIC: [u8; 8] = *b"BKDLEAFS";
+
+/// Current summary file version.
+pub const VERSION: u32 = 1;
+
+const HEADER_SIZE: usize = 40;
+const FLAG_RANGE: u32 = 1;
+const FLAG_BLOOM: u32 = 2;
+
+/// Summaries of the payloads of each leaf of a block tree, so searches that combine a
+/// spatial query with a payload predicate can skip leaves the predicate rules out.
+///
+/// Two summaries are available, either or both:
+/// - a numeric attribute's min and max, for range predicates such as a price band
+/// - a bloom filter of a key, for equality predicates such as a category or document id
+///
+/// # Architecture Decision: sidecar, not format
+/// The summaries are built from a finished tree and stored in a file of their own, so
+/// trees without them pay nothing, summaries for new attributes need no rewrite of the
+/// tree, and one tree may carry several. The file records the leaf and entry counts of
+/// its tree, and `open` refuses a tree they do not match.
+///
+/// # Usage pattern:
+/// ```rust
+/// # use bkd::BoundingBox;
+/// # use bkd::block_tree::{BkdReader, BkdWriter, BkdWriterOptions};
+/// # use bkd::leaf_summary::LeafSummaries;
+/// # let dir = tempfile::tempdir().unwrap();
+/// # let path = dir.path().join("points.bkd");
+/// let mut writer = BkdWriter::new(BkdWriterOptions::default());
+/// for doc in 0..2000u32 {
+///     let x = f64::from(doc % 100);
+///     writer.add(BoundingBox::new(x, x, x + 1.0, x + 1.0), doc).unwrap();
+/// }
+/// writer.finish(&path).unwrap();
+///
+/// let mut reader = BkdReader::<BoundingBox, u32>::open(&path).unwrap();
+/// let summaries = LeafSummaries::builder()
+///     .with_bloom(|&doc: &u32| u64::from(doc), 10)
+///     .build(&mut reader)
+///     .unwrap();
+///
+/// let everywhere = BoundingBox::new(0.0, 0.0, 101.0, 101.0);
+/// let found = reader
+///     .search_filtered(&everywhere, |leaf| summaries.may_hold_key(leaf, 1234), |&doc| doc == 1234)
+///     .unwrap();
+/// assert_eq!(found, [1234]);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct LeafSummaries {
+    num_leaves: usize,
+    point_count: u64,
+    /// Min and max of the attribute per leaf; empty without a range summary.
+    ranges: Vec<(f64, f64)>,
+    /// Bloom filter words per leaf; zero without a bloom summary.
+    bloom_words: usize,
+    hashes: u32,
+    blooms: Vec<u64>,
+}
+
+/// Builder for `LeafSummaries`, from `LeafSummaries::builder`.
+pub struct LeafSummaryBuilder<'a, T> {
+    range: Option<Box<dyn FnMut(&T) -> f64 + 'a>>,
+    bloom: Option<(Box<dyn FnMut(&T) -> u64 + 'a>, usize)>,
+}
+
+impl<'a, T> LeafSummaryBuilder<'a, T> {
+    /// Summarize the attribute `value` extracts by its min and max per leaf. NaNs are
+    /// left out of the bounds.
+    pub fn with_range(mut self, value: impl FnMut(&T) -> f64 + 'a) -> Self {
+        self.range = Some(Box::new(value));
+        self
+    }
+
+    /// Record the key `key` extracts in a bloom filter per leaf, of `bits_per_entry`
+    /// bits for every entry a leaf may hold. Ten bits give about one false positive in a
+    /// hundred.
+    pub fn with_bloom(mut self, key: impl FnMut(&T) -> u64 + 'a, bits_per_entry: usize) -> Self {
+        self.bloom = Some((Box::new(key), bits_per_entry.max(1)));
+        self
+    }
+
+    /// Scan every leaf of `reader` and summarize its payloads.
+    pub fn build<P: Point + FixedCodec>(
+        mut self,
+        reader: &mut BkdReader<P, T>,
+    ) -> io::Result<LeafSummaries>
+    where
+        T: FixedCodec,
+    {
+        let header = reader.header().clone();
+        let num_leaves = header.num_leaves as usize;
+        let (bloom_words, hashes) = match &self.bloom {
+            Some((_, bits_per_entry)) => {
+                let bits = bits_per_entry * header.max_points_in_leaf as usize;
+                let hashes = (*bits_per_entry as f64 * std::f64::consts::LN_2).round();
+                (bits.div_ceil(64), hashes.clamp(1.0, 16.0) as u32)
+            }
+            None => (0, 0),
+        };
+        let mut summaries = LeafSummaries {
+            num_leaves,
+            point_count: header.point_count,
+            ranges: Vec::new(),
+            bloom_words,
+            hashes,
+            blooms: vec![0; num_leaves * bloom_words],
+        };
+        if self.range.is_some() {
+            summaries.ranges = vec![(f64::INFINITY, f64::NEG_INFINITY); num_leaves];
+        }
+
+        for block in reader.leaves() {
+            let block = block?;
+            for data in &block.data {
+                if let Some(value) = &mut self.range {
+                    let value = value(data);
+                    let (min, max) = &mut summaries.ranges[block.leaf];
+                    *min = min.min(value);
+                    *max = max.max(value);
+                }
+                if let Some((key, _)) = &mut self.bloom {
+                    let key = key(data);
+                    summaries.bloom_insert(block.leaf, key);
+                }
+            }
+        }
+        Ok(summaries)
+    }
+}
+
+impl LeafSummaries {
+    /// Start describing which summaries to build.
+    pub fn builder<'a, T>() -> LeafSummaryBuilder<'a, T> {
+        LeafSummaryBuilder {
+            range: None,
+            bloom: None,
+        }
+    }
+
+    /// Number of leaves summarized.
+    pub fn num_leaves(&self) -> usize {
+        self.num_leaves
+    }
+
+    /// Min and max of the attribute in `leaf`, or `None` without a range summary. An empty
+    /// leaf has a min above its max.
+    pub fn range(&self, leaf: usize) -> Option<(f64, f64)> {
+        self.ranges.get(leaf).copied()
+    }
+
+    /// Check if `leaf` may hold an attribute in `min..=max`. Always `true` without a range
+    /// summary.
+    pub fn may_hold_range(&self, leaf: usize, min: f64, max: f64) -> bool {
+        match self.range(leaf) {
+            Some((leaf_min, leaf_max)) => leaf_min <= max && min <= leaf_max,
+            None => true,
+        }
+    }
+
+    /// Check if `leaf` may hold `key`. Always `true` without a bloom summary; otherwise
+    /// `false` means it certainly does not.
+    pub fn may_hold_key(&self, leaf: usize, key: u64) -> bool {
+        if self.bloom_words == 0 {
+            return true;
+        }
+        let bloom = &self.blooms[leaf * self.bloom_words..(leaf + 1) * self.bloom_words];
+        self.bloom_bits(key)
+            .all(|bit| bloom[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    fn bloom_insert(&mut self, leaf: usize, key: u64) {
+        let words = self.bloom_words;
+        for bit in self.bloom_bits(key) {
+            self.blooms[leaf * words + bit / 64] |= 1 << (bit % 64);
+        }
+    }
+
+    /// Bit positions of `key`, by double hashing.
+    fn bloom_bits(&self, key: u64) -> impl Iterator<Item = usize> + use<> {
+        let bits = (self.bloom_words * 64) as u64;
+        let first = mix(key);
+        let step = mix(first ^ 0x9e37_79b9_7f4a_7c15) | 1;
+        (0..u64::from(self.hashes))
+            .map(move |i| (first.wrapping_add(i.wrapping_mul(step)) % bits) as usize)
+    }
+
+    /// Write the summaries to `path`.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let mut header = [0u8; HEADER_SIZE];
+        header[0..8].copy_from_slice(&MAGIC);
+        VERSION.encode(&mut header[8..12]);
+        let mut flags = 0;
+        if !self.ranges.is_empty() {
+            flags |= FLAG_RANGE;
+        }
+        if self.bloom_words > 0 {
+            flags |= FLAG_BLOOM;
+        }
+        flags.encode(&mut header[12..16]);
+        (self.num_leaves as u64).encode(&mut header[16..24]);
+        self.point_count.encode(&mut header[24..32]);
+        (self.bloom_words as u32).encode(&mut header[32..36]);
+        self.hashes.encode(&mut header[36..40]);
+
+        let mut output = BufWriter::new(File::create(path)?);
+        output.write_all(&header)?;
+        for (min, max) in &self.ranges {
+            output.write_all(&min.to_le_bytes())?;
+            output.write_all(&max.to_le_bytes())?;
+        }
+        for word in &self.blooms {
+            output.write_all(&word.to_le_bytes())?;
+        }
+        let file = output.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()
+    }
+
+    /// Read summaries from `path`, checking that they describe the tree with `header`.
+    pub fn open(path: &Path, header: &BkdHeader) -> io::Result<Self> {
+        let mut input = BufReader::new(File::open(path)?);
+        let mut buf = [0u8; HEADER_SIZE];
+        input.read_exact(&mut buf)?;
+        if buf[0..8] != MAGIC {
+            return Err(invalid_data("not a leaf summary file (bad magic)"));
+        }
+        let version = u32::decode(&buf[8..12]);
+        if version != VERSION {
+            return Err(invalid_data(&format!(
+                "unsupported leaf summary version {version}"
+            )));
+        }
+        let flags = u32::decode(&buf[12..16]);
+        let num_leaves = u64::decode(&buf[16..24]);
+        let point_count = u64::decode(&buf[24..32]);
+        if (num_leaves, point_count) != (header.num_leaves, header.point_count) {
+            return Err(invalid_data("leaf summaries describe a different tree"));
+        }
+        let num_leaves = num_leaves as usize;
+        let bloom_words = if flags & FLAG_BLOOM != 0 {
+            u32::decode(&buf[32..36]) as usize
+        } else {
+            0
+        };
+
+        let mut word = [0u8; 8];
+        let mut next = |input: &mut BufReader<File>| -> io::Result<[u8; 8]> {
+            input.read_exact(&mut word)?;
+            Ok(word)
+        };
+        let mut ranges = Vec::new();
+        if flags & FLAG_RANGE != 0 {
+            for _ in 0..num_leaves {
+                let min = f64::from_le_bytes(next(&mut input)?);
+                let max = f64::from_le_bytes(next(&mut input)?);
+                ranges.push((min, max));
+            }
+        }
+        let blooms = (0..num_leaves * bloom_words)
+            .map(|_| next(&mut input).map(u64::from_le_bytes))
+            .collect::<io::Result<_>>()?;
+        Ok(LeafSummaries {
+            num_leaves,
+            point_count,
+            ranges,
+            bloom_words,
+            hashes: u32::decode(&buf[36..40]),
+            blooms,
+        })
+    }
+}
+
+/// SplitMix64 finalizer: spreads keys such as sequential ids over all 64 bits.
+fn mix(mut x: u64) -> u64 {
+    x ^= x >> 30;
+    x = x.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x ^= x >> 27;
+    x = x.wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_tree::{BkdWriter, BkdWriterOptions};
+    use crate::spatial::BoundingBox;
+
+    #[test]
+    fn test_summaries_skip_leaves_and_survive_a_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.bkd");
+        let options = BkdWriterOptions {
+            max_points_in_leaf: 64,
+            ..BkdWriterOptions::default()
+        };
+        // Payload: doc id below 10_000, price in the thousands, rising with x
+        let mut writer = BkdWriter::new(options);
+        for doc in 0..3000u32 {
+            let x = f64::from((doc * 37) % 211);
+            let y = f64::from((doc * 53) % 197);
+            let price = (x as u32) * 10_000;
+            writer
+                .add(BoundingBox::new(x, y, x + 1.0, y + 1.0), price + doc)
+                .unwrap();
+        }
+        writer.finish(&path).unwrap();
+        let mut reader = BkdReader::<BoundingBox, u32>::open(&path).unwrap();
+        let summaries = LeafSummaries::builder()
+            .with_range(|&data: &u32| f64::from(data / 10_000))
+            .with_bloom(|&data: &u32| u64::from(data % 10_000), 10)
+            .build(&mut reader)
+            .unwrap();
+        assert_eq!(summaries.num_leaves(), 64);
+
+        let everywhere = BoundingBox::new(-1.0, -1.0, 300.0, 300.0);
+        let mut read = 0;
+        let cheap = reader
+            .search_filtered(
+                &everywhere,
+                |leaf| {
+                    let keep = summaries.may_hold_range(leaf, 50.0, 60.0);
+                    read += keep as usize;
+                    keep
+                },
+                |&data| (50..=60).contains(&(data / 10_000)),
+            )
+            .unwrap();
+        let mut expected = reader.search(&everywhere).unwrap();
+        expected.retain(|&data| (50..=60).contains(&(data / 10_000)));
+        assert_eq!(cheap, expected);
+        assert!(read <= 64 / 4);
+
+        // Look documents up by id: the bloom filters leave a leaf or two to read
+        let written = dir.path().join("tree.leafs");
+        summaries.write(&written).unwrap();
+        let summaries = LeafSummaries::open(&written, reader.header()).unwrap();
+        let mut read = 0;
+        for doc in [0, 1234, 2999] {
+            let found = reader
+                .search_filtered(
+                    &everywhere,
+                    |leaf| {
+                        let keep = summaries.may_hold_key(leaf, u64::from(doc));
+                        read += keep as usize;
+                        keep
+                    },
+                    |&data| data % 10_000 == doc,
+                )
+                .unwrap();
+            assert_eq!(found.len(), 1);
+        }
+        assert!(read <= 6);
+        assert!((10_000..10_100).all(|key| {
+            (0..64)
+                .filter(|&leaf| summaries.may_hold_key(leaf, key))
+                .count()
+                < 8
+        }));
+
+        let mut other = reader.header().clone();
+        other.point_count += 1;
+        let error = LeafSummaries::open(&written, &other).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -11,6 +11,7 @@
 //! - **Generic spatial indexing**: Works with any type implementing the `Point` trait
 //! - **Storage abstraction**: `NodeLinker` trait enables multiple storage backends
 //! - **Dimensional pruning**: Optimized spatial search with geometric pruning
+//! - **Query shapes**: Any `SpatialQuery` (bounding box, circle, ...) drives the same search
 //! - **Tantivy integration**: Designed to work with Tantivy's storage primitives
 //!
 //! # Architecture
@@ -45,16 +46,228 @@
 //! let query = BoundingBox::new(0.5, 0.5, 1.5, 1.5);
 //! let results = spatial_search(&linker, Some(root), &query, 0);
 //! ```
+//!
+//! `SpatialIndex` does the allocation, linking and root tracking in one call:
+//!
+//! ```rust
+//! use bkd::{BoundingBox, SpatialIndex};
+//!
+//! let mut index = SpatialIndex::new();
+//! let location1 = index.insert(BoundingBox::new(1.0, 1.0, 2.0, 2.0), "location1");
+//! index.insert(BoundingBox::new(3.0, 3.0, 4.0, 4.0), "location2");
+//!
+//! let results = index.search(&BoundingBox::new(0.5, 0.5, 1.5, 1.5));
+//! assert_eq!(results, [location1]);
+//! ```
+//!
+//! # Thread Safety
+//!
+//! Which handles may cross threads is part of the API, checked at compile time in the
+//! tests below:
+//!
+//! - **In-memory trees**: `NodeArena`, `SharedTree`, `TreeSnapshot`, `VersionedIndex` and
+//!   `IndexReader` are `Send` and `Sync` when `P` and `T` are. So is `ArenaView`, which is
+//!   `Copy`, so one view can serve any number of concurrent searches. `InMemoryLinker`
+//!   holds the arena mutably: move it, but share an `ArenaView` instead.
+//! - **Frozen trees**: `FrozenIndex` has no mutating methods and is `Send` and `Sync` when
+//!   `P` and `T` are; share it behind an `Arc`.
+//! - **Persisted readers**: `NodeFileReader`, `BkdReader`, `PooledNodeFile`, `BufferPool`
+//!   and `AsyncNodeFile` (feature `async`) are always `Send` and `Sync`: they decode owned
+//!   points and payloads on demand and hold none. Their searches take `&mut self` or lock
+//!   internally, so one reader per thread, or a shared pool, is the intended pattern.
+//! - **Mapped files**: `MmapArena` (feature `mmap`) hands out references into the mapping,
+//!   so it is `Send` and `Sync` only when `P` and `T` are.
+//! - **Segments**: `SegmentedIndex` is `Send` but not `Sync`, since its merge policy and
+//!   scheduler are only required to be `Send`; segments themselves are shared via `Arc`.
+//! - **Not thread-safe**: `ShardedIndex` borrows trees behind arbitrary linkers and is
+//!   neither `Send` nor `Sync`; build one per thread over shared views.
 
+pub mod analyze;
+pub mod block_tree;
+pub mod buffer_pool;
+pub mod build;
+#[cfg(feature = "bumpalo")]
+pub mod bump;
+pub mod cancel;
+pub mod codec;
+pub mod compression;
+pub mod copy;
+pub mod counted;
+pub mod deletes;
+pub mod diff;
+pub mod digest;
+pub mod external;
+pub mod frozen;
+pub mod geo;
+pub mod geohash;
+pub mod hybrid;
+pub mod index;
+pub mod keyed;
+pub mod leaf_summary;
+pub mod metrics;
+pub mod moving;
+pub mod nearest;
+pub mod node_file;
+pub mod payloads;
+pub mod projection;
+pub mod quantize;
+pub mod query;
+pub mod repair;
+pub mod reverse;
 pub mod search;
+pub mod segment;
+pub mod shard;
+pub mod skyline;
+pub mod snapshot;
 pub mod spatial;
+pub mod spill;
 pub mod storage;
+pub mod summary;
+mod sync;
+pub mod tiles;
+pub mod verify;
+pub mod versioned;
+
+// Async search over disk-backed indexes (optional)
+#[cfg(feature = "async")]
+pub mod async_search;
+
+// Encryption at rest (optional)
+#[cfg(feature = "encryption")]
+pub mod encryption;
+
+// Background flushing of disk backends (optional)
+#[cfg(feature = "threads")]
+pub mod flusher;
+
+// Memory-mapped storage backend (optional)
+#[cfg(feature = "mmap")]
+pub mod mmap;
+
+// Mapbox Vector Tile encoding for tile exports (optional)
+#[cfg(feature = "mvt")]
+mod mvt;
+
+// Queries by S2 or H3 cells (optional)
+#[cfg(any(feature = "s2", feature = "h3"))]
+pub mod cells;
+#[cfg(feature = "s2")]
+pub mod s2;
 
 // Tantivy integration module (optional)
 #[cfg(feature = "tantivy")]
 pub mod tantivy_linker;
+#[cfg(feature = "tantivy")]
+pub mod tantivy_points;
+#[cfg(feature = "tantivy")]
+pub mod tantivy_query;
 
 // Re-export key types for convenience
-pub use search::{insert_node, spatial_search};
+pub use analyze::{Recommendation, TuningReport, analyze};
+pub use block_tree::{
+    BkdReader, BkdWriter, BkdWriterOptions, IntersectVisitor, LeafBlock, LeafCandidate, LeafSizing,
+};
+pub use buffer_pool::{BufferPool, PageStorage, PinnedPage, PooledNodeFile};
+pub use build::{
+    BuildOptions, BuildProgress, ProgressCallback, SplitPolicy, bulk_build, extract_region,
+    rebuild_compact, rebuild_packed, rebuild_purged, to_block_tree, to_node_tree,
+};
+pub use cancel::{CancellationToken, Cancelled};
+pub use codec::FixedCodec;
+pub use compression::Compression;
+pub use copy::{CopyMode, copy_tree};
+pub use counted::{CountedLinker, CountingLinker};
+pub use deletes::{DeletionBitmap, spatial_search_live};
+pub use diff::{TreeDiff, diff_to_dot, diff_to_svg, diff_trees, trees_equal};
+pub use digest::tree_digest;
+pub use external::{ExternalBuildOptions, external_bulk_build};
+pub use frozen::{ExecutionStrategy, FrozenIndex, QueryPlan};
+pub use geo::{GeoBox, geo_search};
+pub use geohash::{InvalidGeohash, geohash_search};
+pub use hybrid::{HybridIndex, TopLevel};
+pub use index::SpatialIndex;
+pub use keyed::KeyedIndex;
+pub use leaf_summary::{LeafSummaries, LeafSummaryBuilder};
+pub use metrics::{Metrics, MetricsSnapshot};
+pub use moving::{MoveReport, MovingIndex, MovingOptions};
+pub use nearest::{
+    EntryShape, Metric, NearestIter, Neighbor, WithinDistance, approximate_nearest_iter,
+    approximate_nearest_neighbors, nearest_box_iter, nearest_boxes, nearest_iter,
+    nearest_neighbor_matches, nearest_neighbors,
+};
+pub use payloads::{Payloads, insert_or_append};
+pub use quantize::{QuantizedPoint, Quantizer};
+pub use query::{
+    Circle, LineSegment, PartialBox, RangeQuery, Ray, Relation, SpatialQuery, TolerantBox,
+};
+pub use repair::{RepairReport, repair};
+pub use reverse::{ReverseIndex, find_by_data};
+pub use search::{
+    DimensionScan, ExportAll, Match, ResultOrder, SearchCursor, SearchPage, SvgOptions,
+    dimension_scan, export_all, insert_node, spatial_search, spatial_search_cancellable,
+    spatial_search_matches, spatial_search_ordered, spatial_search_page,
+};
+pub use segment::{
+    LeveledMergePolicy, MergePolicy, MergeScheduler, MergeTask, Segment, SegmentInfo,
+    SegmentedIndex, SerialMergeScheduler, ThreadMergeScheduler, TieredMergePolicy,
+};
+pub use shard::{ShardHit, ShardedIndex, partition};
+pub use skyline::{SkylineCriterion, skyline};
+pub use snapshot::{NEVER_EXPIRES, SharedTree, TreeSnapshot};
 pub use spatial::{BoundingBox, Point, SpatialPoint};
-pub use storage::{InMemoryLinker, NodeArena, NodeLinker};
+pub use spill::{SpillOptions, SpillRef, SpilledResults, spatial_search_spilled};
+pub use storage::{
+    AllocatingLinker, ArenaView, InMemoryLinker, NodeArena, NodeLinker, NodeStore, RootedLinker,
+};
+pub use summary::{SubtreeBounds, spatial_search_summarized};
+pub use tiles::{PropertyValue, TileExport, TileId, TileScheme};
+pub use verify::{Problem, VerifyReport, verify};
+pub use versioned::{IndexReader, Transaction, Version, VersionedIndex};
+
+// Derive macros, named like the traits they implement (optional)
+#[cfg(feature = "derive")]
+pub use bkd_derive::{Point, SpatialPoint};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    fn assert_send<X: Send>() {}
+    fn assert_send_sync<X: Send + Sync>() {}
+
+    /// Compile-time checks of the guarantees listed under "Thread Safety".
+    #[test]
+    fn test_thread_safety_guarantees() {
+        type P = BoundingBox;
+
+        assert_send_sync::<NodeArena<P, u64>>();
+        assert_send_sync::<ArenaView<'static, P, u64>>();
+        assert_send_sync::<InMemoryLinker<'static, P, u64>>();
+        assert_send_sync::<SharedTree<P, u64>>();
+        assert_send_sync::<TreeSnapshot<P, u64>>();
+        assert_send_sync::<VersionedIndex<P, u64>>();
+        assert_send_sync::<IndexReader<P, u64>>();
+        assert_send_sync::<FrozenIndex<P, u64>>();
+        assert_send_sync::<segment::Segment<P, u64>>();
+        assert_send::<SegmentedIndex<P, u64>>();
+
+        // Readers hold no points or payloads, so even a non-thread-safe payload is fine
+        assert_send_sync::<node_file::NodeFileReader<P, Rc<u64>>>();
+        assert_send_sync::<node_file::NodeFileWriter<P, Rc<u64>>>();
+        assert_send_sync::<BkdReader<P, Rc<u64>>>();
+        assert_send_sync::<PooledNodeFile<P, Rc<u64>>>();
+        assert_send_sync::<BufferPool>();
+        #[cfg(feature = "async")]
+        assert_send_sync::<async_search::AsyncNodeFile<P, Rc<u64>>>();
+        #[cfg(feature = "mmap")]
+        assert_send_sync::<mmap::MmapArena<P, u64>>();
+        #[cfg(feature = "encryption")]
+        assert_send_sync::<encryption::EncryptedFile>();
+        #[cfg(feature = "threads")]
+        assert_send_sync::<flusher::BackgroundFlusher>();
+
+        assert_send_sync::<CancellationToken>();
+        assert_send_sync::<Metrics>();
+    }
+}
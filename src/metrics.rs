@@ -0,0 +1,105 @@
+//! Operational counters for disk-backed indexes.
+//!
+//! A `Metrics` instance is shared (via `Arc`) between any number of readers, which update
+//! it with relaxed atomic increments — cheap enough to leave enabled in production. Take a
+//! `snapshot` to export the values to a monitoring system.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Shared counters updated by disk backends.
+///
+/// # Counters
+/// - `disk_reads` / `bytes_read`: node reads that went to the file
+/// - `cache_hits` / `cache_misses`: lookups in a backend's node cache, if it has one
+/// - `bytes_decompressed`: output of block decompression, for compressed backends
+/// - `queries`: searches served from the backend
+///
+/// Backends only update the counters that apply to them, so e.g. an uncached reader
+/// reports zero cache hits and misses.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    disk_reads: AtomicU64,
+    bytes_read: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    bytes_decompressed: AtomicU64,
+    queries: AtomicU64,
+}
+
+impl Metrics {
+    /// Create a set of zeroed counters.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record one read of `bytes` bytes from disk.
+    pub fn record_read(&self, bytes: u64) {
+        self.disk_reads.fetch_add(1, Ordering::Relaxed);
+        self.bytes_read.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Record a node cache hit.
+    pub fn record_cache_hit(&self) {
+        self.cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a node cache miss.
+    pub fn record_cache_miss(&self) {
+        self.cache_misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record `bytes` bytes produced by decompression.
+    pub fn record_decompressed(&self, bytes: u64) {
+        self.bytes_decompressed.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Record one served query.
+    pub fn record_query(&self) {
+        self.queries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Read all counters.
+    /// Counters are read individually, so a snapshot taken under load may mix values from
+    /// slightly different instants.
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            disk_reads: self.disk_reads.load(Ordering::Relaxed),
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            cache_hits: self.cache_hits.load(Ordering::Relaxed),
+            cache_misses: self.cache_misses.load(Ordering::Relaxed),
+            bytes_decompressed: self.bytes_decompressed.load(Ordering::Relaxed),
+            queries: self.queries.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Reset all counters to zero and return their previous values.
+    pub fn reset(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            disk_reads: self.disk_reads.swap(0, Ordering::Relaxed),
+            bytes_read: self.bytes_read.swap(0, Ordering::Relaxed),
+            cache_hits: self.cache_hits.swap(0, Ordering::Relaxed),
+            cache_misses: self.cache_misses.swap(0, Ordering::Relaxed),
+            bytes_decompressed: self.bytes_decompressed.swap(0, Ordering::Relaxed),
+            queries: self.queries.swap(0, Ordering::Relaxed),
+        }
+    }
+}
+
+/// Point-in-time copy of `Metrics`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MetricsSnapshot {
+    pub disk_reads: u64,
+    pub bytes_read: u64,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    pub bytes_decompressed: u64,
+    pub queries: u64,
+}
+
+impl MetricsSnapshot {
+    /// Fraction of cache lookups that hit, or `None` if there were no lookups.
+    pub fn cache_hit_rate(&self) -> Option<f64> {
+        let lookups = self.cache_hits + self.cache_misses;
+        (lookups > 0).then(|| self.cache_hits as f64 / lookups as f64)
+    }
+}
@@ -0,0 +1,679 @@
+//! Memory-mapped node file backend (feature `mmap`).
+//!
+//! The node array lives directly in a memory-mapped node file: opening is instant
+//! regardless of size, the OS page cache decides what stays resident, and any number of
+//! processes can map the same file read-only. Points and payloads are borrowed straight
+//! from the mapping (`ZeroCopy`), so reads never decode or copy.
+
+use crate::codec::{FixedCodec, ZeroCopy};
+use crate::node_file::{
+    FormatFeatures, HEADER_SIZE, NO_NODE, NodeFileHeader, VERSION, record_size,
+};
+use crate::spatial::Point;
+use crate::storage::{AllocatingLinker, NodeLinker, RootedLinker};
+use memmap2::{Mmap, MmapMut};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::marker::PhantomData;
+use std::path::Path;
+
+/// Mapping of the node file, read-only or writable.
+enum Mapping {
+    ReadOnly(Mmap),
+    ReadWrite(MmapMut),
+}
+
+impl Mapping {
+    fn bytes(&self) -> &[u8] {
+        match self {
+            Mapping::ReadOnly(map) => map,
+            Mapping::ReadWrite(map) => map,
+        }
+    }
+
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        match self {
+            Mapping::ReadOnly(_) => panic!("mmap arena was opened read-only"),
+            Mapping::ReadWrite(map) => map,
+        }
+    }
+
+    #[cfg(unix)]
+    fn advise(&self, advice: memmap2::Advice) -> io::Result<()> {
+        match self {
+            Mapping::ReadOnly(map) => map.advise(advice),
+            Mapping::ReadWrite(map) => map.advise(advice),
+        }
+    }
+
+    #[cfg(unix)]
+    fn advise_range(&self, advice: memmap2::Advice, offset: usize, len: usize) -> io::Result<()> {
+        match self {
+            Mapping::ReadOnly(map) => map.advise_range(advice, offset, len),
+            Mapping::ReadWrite(map) => map.advise_range(advice, offset, len),
+        }
+    }
+}
+
+/// Expected access pattern of a mapping, passed to the OS as `madvise` hints.
+///
+/// KD-tree traversal jumps between records far apart in the file, so the kernel's default
+/// sequential read-ahead mostly pulls in pages that are never touched. `Random` disables
+/// it, which lowers query latency on cold caches; `Sequential` suits full scans such as
+/// `load_arena`-style copies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AccessPattern {
+    /// Leave the kernel defaults untouched.
+    #[default]
+    Normal,
+    /// Random access: disable read-ahead (`MADV_RANDOM`).
+    Random,
+    /// Sequential access: aggressive read-ahead (`MADV_SEQUENTIAL`).
+    Sequential,
+}
+
+/// Tuning applied when opening or creating a memory-mapped arena.
+///
+/// Advice is a hint: it is ignored on platforms without `madvise`, and failures to apply
+/// it are reported as errors only by `MmapArena::advise` and `MmapArena::prefetch`.
+#[derive(Debug, Clone, Default)]
+pub struct MmapOpenOptions {
+    /// Access pattern hint for the whole mapping.
+    pub access: AccessPattern,
+    /// Number of bytes from the start of the file to ask the OS to read ahead of time
+    /// (`MADV_WILLNEED`), e.g. to warm the records near the root. `usize::MAX` warms the
+    /// whole file.
+    pub prefetch_bytes: usize,
+}
+
+impl MmapOpenOptions {
+    /// Set the access pattern hint.
+    pub fn with_access(mut self, access: AccessPattern) -> Self {
+        self.access = access;
+        self
+    }
+
+    /// Set how many leading bytes to prefetch.
+    pub fn with_prefetch_bytes(mut self, prefetch_bytes: usize) -> Self {
+        self.prefetch_bytes = prefetch_bytes;
+        self
+    }
+}
+
+/// Arena whose nodes are stored in a memory-mapped node file.
+///
+/// # Architecture
+/// The file uses the regular node-file layout, so trees written by `external_bulk_build`
+/// or `write_arena` open directly, and files written here are readable by every other
+/// node-file reader. Writable arenas reserve spare records past `node_count` and double
+/// the file when they run out, so allocation is amortized O(1) and never rewrites
+/// existing records.
+///
+/// Like all memory-mapped files, the mapping must not be truncated or modified by another
+/// process while it is open.
+pub struct MmapArena<P, T> {
+    file: File,
+    map: Mapping,
+    header: NodeFileHeader,
+    capacity: u64,
+    access: AccessPattern,
+    // Hands out references into the mapping, so sharing it shares P and T values
+    _marker: PhantomData<(P, T)>,
+}
+
+impl<P: Point + ZeroCopy, T: ZeroCopy> MmapArena<P, T> {
+    /// Create (or truncate) a writable arena at `path` with room for `capacity` nodes.
+    pub fn create(path: &Path, capacity: u64) -> io::Result<Self> {
+        Self::create_with(path, capacity, &MmapOpenOptions::default())
+    }
+
+    /// Create a writable arena, applying the given access hints.
+    pub fn create_with(path: &Path, capacity: u64, options: &MmapOpenOptions) -> io::Result<Self> {
+        check_platform::<P, T>()?;
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        let header = NodeFileHeader {
+            version: VERSION,
+            dimensions: 0,
+            point_size: P::SIZE as u32,
+            data_size: T::SIZE as u32,
+            node_count: 0,
+            root: None,
+            checksum: None,
+            features: FormatFeatures::NONE,
+        };
+        let capacity = capacity.max(1);
+        file.set_len(HEADER_SIZE as u64 + capacity * header.record_size() as u64)?;
+
+        // SAFETY: the file was just created by us; see the type-level note on mappings
+        let mut map = unsafe { MmapMut::map_mut(&file)? };
+        map[..HEADER_SIZE].copy_from_slice(&header.encode());
+        let arena = MmapArena {
+            file,
+            map: Mapping::ReadWrite(map),
+            header,
+            capacity,
+            access: options.access,
+            _marker: PhantomData,
+        };
+        arena.apply_hints(options);
+        Ok(arena)
+    }
+
+    /// Open an existing node file for reading and writing.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Self::open_with(path, &MmapOpenOptions::default())
+    }
+
+    /// Open an existing node file for reading and writing, applying the given access hints.
+    pub fn open_with(path: &Path, options: &MmapOpenOptions) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        // SAFETY: see the type-level note on mappings
+        let map = unsafe { MmapMut::map_mut(&file)? };
+        let mut arena = Self::from_mapping(file, Mapping::ReadWrite(map), options)?;
+        // Records change in place from now on, so a checksum would go stale
+        if arena.header.checksum.take().is_some() {
+            let header = arena.header.encode();
+            arena.map.bytes_mut()[..HEADER_SIZE].copy_from_slice(&header);
+        }
+        Ok(arena)
+    }
+
+    /// Open an existing node file read-only; the mapping may be shared with other processes.
+    /// Linking or allocating on a read-only arena panics.
+    pub fn open_read_only(path: &Path) -> io::Result<Self> {
+        Self::open_read_only_with(path, &MmapOpenOptions::default())
+    }
+
+    /// Open an existing node file read-only, applying the given access hints.
+    pub fn open_read_only_with(path: &Path, options: &MmapOpenOptions) -> io::Result<Self> {
+        let file = File::open(path)?;
+        // SAFETY: see the type-level note on mappings
+        let map = unsafe { Mmap::map(&file)? };
+        Self::from_mapping(file, Mapping::ReadOnly(map), options)
+    }
+
+    fn from_mapping(file: File, map: Mapping, options: &MmapOpenOptions) -> io::Result<Self> {
+        check_platform::<P, T>()?;
+        let bytes = map.bytes();
+        let header_bytes: &[u8; HEADER_SIZE] = bytes
+            .get(..HEADER_SIZE)
+            .and_then(|header| header.try_into().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "node file too short"))?;
+        let header = NodeFileHeader::decode(header_bytes)?;
+        header.check_layout::<P, T>()?;
+
+        let capacity = (bytes.len() - HEADER_SIZE) as u64 / header.record_size() as u64;
+        if capacity < header.node_count {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "node file is shorter than its node count",
+            ));
+        }
+        let arena = MmapArena {
+            file,
+            map,
+            header,
+            capacity,
+            access: options.access,
+            _marker: PhantomData,
+        };
+        arena.apply_hints(options);
+        Ok(arena)
+    }
+
+    /// Best-effort application of the open-time hints; advice never affects correctness.
+    fn apply_hints(&self, options: &MmapOpenOptions) {
+        let _ = self.advise(options.access);
+        if options.prefetch_bytes > 0 {
+            let _ = self.prefetch(0, options.prefetch_bytes);
+        }
+    }
+
+    /// Change the access pattern hint for the whole mapping.
+    /// The hint is re-applied whenever a writable arena grows and remaps its file.
+    pub fn advise(&self, access: AccessPattern) -> io::Result<()> {
+        #[cfg(unix)]
+        {
+            let advice = match access {
+                AccessPattern::Normal => memmap2::Advice::Normal,
+                AccessPattern::Random => memmap2::Advice::Random,
+                AccessPattern::Sequential => memmap2::Advice::Sequential,
+            };
+            self.map.advise(advice)
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = access;
+            Ok(())
+        }
+    }
+
+    /// Ask the OS to read `len` bytes starting at byte `offset` ahead of time.
+    /// The range is clamped to the mapping, so `prefetch(0, usize::MAX)` warms the whole file.
+    pub fn prefetch(&self, offset: usize, len: usize) -> io::Result<()> {
+        let mapped = self.map.bytes().len();
+        let offset = offset.min(mapped);
+        let len = len.min(mapped - offset);
+        if len == 0 {
+            return Ok(());
+        }
+        #[cfg(unix)]
+        {
+            self.map
+                .advise_range(memmap2::Advice::WillNeed, offset, len)
+        }
+        #[cfg(not(unix))]
+        {
+            Ok(())
+        }
+    }
+
+    /// Touch the records of the top `levels` levels below `root`, breadth first, faulting
+    /// their pages in before the first query arrives. Returns the number of records touched.
+    pub fn warm_up(&self, root: u64, levels: usize) -> io::Result<u64> {
+        let mut level = vec![root];
+        let mut touched = 0;
+        for _ in 0..levels {
+            let mut next = Vec::with_capacity(level.len() * 2);
+            for index in level {
+                let record = self.record(index);
+                let offset = record.as_ptr() as usize - self.map.bytes().as_ptr() as usize;
+                self.prefetch(offset, record.len())?;
+                // Reading a byte faults the page in even where advice is a no-op
+                std::hint::black_box(record[record.len() - 1]);
+                next.extend(self.link(index, 0));
+                next.extend(self.link(index, 8));
+                touched += 1;
+            }
+            if next.is_empty() {
+                break;
+            }
+            level = next;
+        }
+        Ok(touched)
+    }
+
+    /// Allocate a new node and return its index.
+    pub fn allocate(&mut self, point: P, data: T) -> u64 {
+        if self.header.node_count == self.capacity {
+            self.grow(self.capacity * 2)
+                .expect("failed to grow memory-mapped arena");
+        }
+        let index = self.header.node_count;
+        if self.header.dimensions == 0 {
+            self.header.dimensions = point.dimensions() as u32;
+        }
+        self.header.node_count += 1;
+
+        let record = self.record_mut(index);
+        NO_NODE.encode(&mut record[0..8]);
+        NO_NODE.encode(&mut record[8..16]);
+        point.encode(&mut record[16..16 + P::SIZE]);
+        data.encode(&mut record[16 + P::SIZE..16 + P::SIZE + T::SIZE]);
+        index
+    }
+
+    /// Number of allocated nodes.
+    pub fn len(&self) -> u64 {
+        self.header.node_count
+    }
+
+    /// Check if the arena is empty.
+    pub fn is_empty(&self) -> bool {
+        self.header.node_count == 0
+    }
+
+    /// Index of the root record stored in the header, if any.
+    pub fn root(&self) -> Option<u64> {
+        self.header.root
+    }
+
+    /// Store the root record index in the header; `flush` persists it with the nodes.
+    ///
+    /// # Panics
+    /// Panics if the arena was opened read-only.
+    pub fn set_root(&mut self, root: Option<u64>) {
+        if let Some(root) = root {
+            assert!(root < self.header.node_count, "node index out of range");
+        }
+        self.header.root = root;
+        let header = self.header.encode();
+        self.map.bytes_mut()[..HEADER_SIZE].copy_from_slice(&header);
+    }
+
+    /// Write the header and flush all changes to disk.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let header = self.header.encode();
+        self.map.bytes_mut()[..HEADER_SIZE].copy_from_slice(&header);
+        match &self.map {
+            Mapping::ReadOnly(_) => Ok(()),
+            Mapping::ReadWrite(map) => map.flush(),
+        }
+    }
+
+    /// Remap the file with room for `capacity` nodes.
+    fn grow(&mut self, capacity: u64) -> io::Result<()> {
+        self.flush()?;
+        let length = HEADER_SIZE as u64 + capacity * self.header.record_size() as u64;
+        self.file.set_len(length)?;
+        // SAFETY: see the type-level note on mappings
+        self.map = Mapping::ReadWrite(unsafe { MmapMut::map_mut(&self.file)? });
+        self.capacity = capacity;
+        // A fresh mapping starts with default advice
+        let _ = self.advise(self.access);
+        Ok(())
+    }
+
+    /// Prefetch the records at `indices`, merging records less than a page apart into one
+    /// request. Advice is only a hint, so failures are ignored.
+    fn prefetch_records(&self, indices: &[u64]) {
+        const GAP: usize = 4096;
+        let size = self.header.record_size();
+        let mut offsets: Vec<usize> = indices
+            .iter()
+            .map(|&index| {
+                assert!(index < self.header.node_count, "node index out of range");
+                HEADER_SIZE + index as usize * size
+            })
+            .collect();
+        offsets.sort_unstable();
+        let mut run: Option<(usize, usize)> = None;
+        for offset in offsets {
+            run = match run {
+                Some((start, end)) if offset <= end + GAP => Some((start, end.max(offset + size))),
+                Some((start, end)) => {
+                    let _ = self.prefetch(start, end - start);
+                    Some((offset, offset + size))
+                }
+                None => Some((offset, offset + size)),
+            };
+        }
+        if let Some((start, end)) = run {
+            let _ = self.prefetch(start, end - start);
+        }
+    }
+
+    fn record(&self, index: u64) -> &[u8] {
+        assert!(index < self.header.node_count, "node index out of range");
+        let offset = HEADER_SIZE + index as usize * self.header.record_size();
+        &self.map.bytes()[offset..offset + self.header.record_size()]
+    }
+
+    fn record_mut(&mut self, index: u64) -> &mut [u8] {
+        assert!(index < self.header.node_count, "node index out of range");
+        let size = self.header.record_size();
+        let offset = HEADER_SIZE + index as usize * size;
+        &mut self.map.bytes_mut()[offset..offset + size]
+    }
+
+    fn link(&self, index: u64, slot: usize) -> Option<u64> {
+        match u64::decode(&self.record(index)[slot..slot + 8]) {
+            NO_NODE => None,
+            child => Some(child),
+        }
+    }
+
+    fn set_link(&mut self, index: u64, slot: usize, child: u64) {
+        child.encode(&mut self.record_mut(index)[slot..slot + 8]);
+    }
+
+    fn point(&self, index: u64) -> &P {
+        let bytes = &self.record(index)[16..16 + P::SIZE];
+        // SAFETY: `P: ZeroCopy` and `check_platform` guarantee the bytes are a valid,
+        // suitably aligned `P` (records and the mapping base are 8-byte aligned)
+        unsafe { &*(bytes.as_ptr() as *const P) }
+    }
+
+    fn data(&self, index: u64) -> &T {
+        let bytes = &self.record(index)[16 + P::SIZE..16 + P::SIZE + T::SIZE];
+        // SAFETY: as for `point`; `check_platform` verified the payload offset alignment
+        unsafe { &*(bytes.as_ptr() as *const T) }
+    }
+}
+
+/// Zero-copy access requires the on-disk little-endian layout to be the in-memory layout.
+fn check_platform<P: ZeroCopy, T: ZeroCopy>() -> io::Result<()> {
+    if cfg!(target_endian = "big") {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "memory-mapped node files require a little-endian target",
+        ));
+    }
+    let aligned = std::mem::align_of::<P>() <= 8
+        && std::mem::align_of::<T>() <= 8
+        && (16 + P::SIZE) % std::mem::align_of::<T>() == 0
+        && record_size(P::SIZE, T::SIZE) % 8 == 0;
+    if !aligned {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "point/payload alignment is not compatible with zero-copy access",
+        ));
+    }
+    Ok(())
+}
+
+/// NodeLinker over a memory-mapped arena, mirroring `InMemoryLinker`.
+pub struct MmapLinker<'a, P, T> {
+    arena: &'a mut MmapArena<P, T>,
+}
+
+impl<'a, P: Point + ZeroCopy, T: ZeroCopy> MmapLinker<'a, P, T> {
+    /// Create a new linker that operates on the given arena.
+    pub fn new(arena: &'a mut MmapArena<P, T>) -> Self {
+        MmapLinker { arena }
+    }
+}
+
+impl<'a, P: Point + ZeroCopy, T: ZeroCopy> NodeLinker<P, T> for MmapLinker<'a, P, T> {
+    type NodeRef = u64;
+
+    fn link_left(&mut self, parent: Self::NodeRef, child: Self::NodeRef) {
+        self.arena.set_link(parent, 0, child);
+    }
+
+    fn link_right(&mut self, parent: Self::NodeRef, child: Self::NodeRef) {
+        self.arena.set_link(parent, 8, child);
+    }
+
+    fn get_left(&self, node: Self::NodeRef) -> Option<Self::NodeRef> {
+        self.arena.link(node, 0)
+    }
+
+    fn get_right(&self, node: Self::NodeRef) -> Option<Self::NodeRef> {
+        self.arena.link(node, 8)
+    }
+
+    fn get_point(&self, node: Self::NodeRef) -> &P {
+        self.arena.point(node)
+    }
+
+    fn get_data(&self, node: Self::NodeRef) -> &T {
+        self.arena.data(node)
+    }
+
+    /// Prefetches every record before resolving the first, so the OS reads them in one
+    /// pass over the file instead of faulting on each in turn.
+    fn get_many<'b>(&'b self, refs: &[Self::NodeRef]) -> impl Iterator<Item = (&'b P, &'b T)>
+    where
+        P: 'b,
+        T: 'b,
+    {
+        self.arena.prefetch_records(refs);
+        refs.iter()
+            .map(move |&node| (self.arena.point(node), self.arena.data(node)))
+    }
+}
+
+impl<'a, P: Point + ZeroCopy, T: ZeroCopy> AllocatingLinker<P, T> for MmapLinker<'a, P, T> {
+    fn allocate(&mut self, point: P, data: T) -> u64 {
+        self.arena.allocate(point, data)
+    }
+}
+
+/// The root lives in the node-file header, so every node-file reader sees it.
+impl<'a, P: Point + ZeroCopy, T: ZeroCopy> RootedLinker<P, T> for MmapLinker<'a, P, T> {
+    fn get_root(&self) -> Option<u64> {
+        self.arena.root()
+    }
+
+    fn set_root(&mut self, root: Option<u64>) {
+        self.arena.set_root(root);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::copy::{CopyMode, copy_tree};
+    use crate::diff::trees_equal;
+    use crate::index::SpatialIndex;
+    use crate::node_file::{NodeFileReader, NodeFileWriter};
+    use crate::search::{insert_node, spatial_search};
+    use crate::spatial::BoundingBox;
+    use crate::verify::verify;
+
+    #[test]
+    fn test_mmap_arena_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.bkd");
+
+        let query = BoundingBox::new(0.0, 0.0, 10.0, 10.0);
+        let expected = {
+            // Start tiny so allocation has to grow and remap the file
+            let mut arena = MmapArena::<BoundingBox, u32>::create(&path, 1).unwrap();
+            let refs: Vec<u64> = (0..20)
+                .map(|i| {
+                    let x = (i * 7 % 20) as f64;
+                    arena.allocate(BoundingBox::new(x, x, x + 1.0, x + 1.0), i)
+                })
+                .collect();
+
+            let mut linker = MmapLinker::new(&mut arena);
+            assert_eq!(linker.get_root(), None);
+            for &node in &refs {
+                let root = linker.get_root();
+                let root = insert_node(&mut linker, root, node, 0);
+                linker.set_root(Some(root));
+            }
+            let results = spatial_search(&linker, linker.get_root(), &query, 0);
+            arena.flush().unwrap();
+            results
+        };
+
+        // The reopened file knows its own root
+        let mut reopened = MmapArena::<BoundingBox, u32>::open_read_only(&path).unwrap();
+        assert_eq!(reopened.len(), 20);
+        assert_eq!(reopened.root(), Some(0));
+        let linker = MmapLinker::new(&mut reopened);
+        assert_eq!(
+            spatial_search(&linker, linker.get_root(), &query, 0),
+            expected
+        );
+        assert_eq!(*linker.get_data(0), 0);
+
+        // The file is a regular node file
+        let reader = NodeFileReader::<BoundingBox, u32>::open(&path).unwrap();
+        assert_eq!(reader.len(), 20);
+        assert_eq!(reader.root(), Some(0));
+    }
+
+    #[test]
+    fn test_mmap_access_hints() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.bkd");
+
+        let options = MmapOpenOptions::default()
+            .with_access(AccessPattern::Random)
+            .with_prefetch_bytes(usize::MAX);
+        let mut arena = MmapArena::<BoundingBox, u32>::create_with(&path, 1, &options).unwrap();
+        for i in 0..4 {
+            arena.allocate(BoundingBox::new(0.0, 0.0, 1.0, 1.0), i);
+        }
+        arena.flush().unwrap();
+        drop(arena);
+
+        let arena = MmapArena::<BoundingBox, u32>::open_read_only_with(&path, &options).unwrap();
+        assert_eq!(arena.len(), 4);
+        arena.advise(AccessPattern::Sequential).unwrap();
+        arena.prefetch(HEADER_SIZE, 1 << 20).unwrap();
+        arena.prefetch(usize::MAX, 1).unwrap();
+        assert_eq!(arena.warm_up(0, 10).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_mmap_get_many() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.bkd");
+        let mut arena = MmapArena::<BoundingBox, u32>::create(&path, 16).unwrap();
+        for i in 0..500 {
+            let x = i as f64;
+            arena.allocate(BoundingBox::new(x, x, x + 1.0, x + 1.0), i);
+        }
+        let linker = MmapLinker::new(&mut arena);
+
+        // Unordered, repeated and far-apart references resolve in the order given
+        let refs = [499, 3, 4, 250, 3, 0];
+        let resolved: Vec<(f64, u32)> = linker
+            .get_many(&refs)
+            .map(|(point, data)| (point.xmin, *data))
+            .collect();
+        let expected: Vec<(f64, u32)> = refs.iter().map(|&i| (i as f64, i as u32)).collect();
+        assert_eq!(resolved, expected);
+        assert_eq!(linker.get_many(&[]).count(), 0);
+    }
+
+    #[test]
+    fn test_copy_in_memory_tree_to_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("copy.bkd");
+        let mut index = SpatialIndex::new();
+        for i in 0..50u32 {
+            let x = (i * 13 % 50) as f64;
+            index.insert(BoundingBox::new(x, x, x + 1.0, x + 1.0), i);
+        }
+
+        {
+            let mut arena = MmapArena::<BoundingBox, u32>::create(&path, 1).unwrap();
+            let mut linker = MmapLinker::new(&mut arena);
+            let root = copy_tree(&index, index.root(), &mut linker, CopyMode::Preserve);
+            linker.set_root(root);
+            arena.flush().unwrap();
+        }
+
+        let mut reopened = MmapArena::<BoundingBox, u32>::open_read_only(&path).unwrap();
+        let linker = MmapLinker::new(&mut reopened);
+        assert!(trees_equal(
+            &index,
+            index.root(),
+            &linker,
+            linker.get_root()
+        ));
+    }
+
+    #[test]
+    fn test_writable_open_drops_checksum() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.bkd");
+        let mut writer = NodeFileWriter::<BoundingBox, u32>::create(&path).unwrap();
+        writer
+            .push(&BoundingBox::new(0.0, 0.0, 1.0, 1.0), &7, None, None)
+            .unwrap();
+        writer.finish(Some(0)).unwrap();
+        assert!(verify::<BoundingBox, u32>(&path).unwrap().checksummed);
+
+        let mut arena = MmapArena::<BoundingBox, u32>::open(&path).unwrap();
+        let node = arena.allocate(BoundingBox::new(2.0, 2.0, 3.0, 3.0), 8);
+        MmapLinker::new(&mut arena).link_right(0, node);
+        arena.flush().unwrap();
+        drop(arena);
+        let report = verify::<BoundingBox, u32>(&path).unwrap();
+        assert!(report.is_ok(), "{:?}", report.problems);
+        assert!(!report.checksummed);
+        assert_eq!(report.reachable, 2);
+    }
+}
@@ -0,0 +1,348 @@
+//! Trees of moving objects, such as vehicles, updated in batches of position changes.
+
+use crate::index::SpatialIndex;
+use crate::keyed::KeyedIndex;
+use crate::query::SpatialQuery;
+use crate::spatial::Point;
+use crate::storage::NodeLinker;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+/// When `MovingIndex::update` applies the buffered updates on its own.
+#[derive(Debug, Clone)]
+pub struct MovingOptions {
+    /// Buffered updates that trigger a batch.
+    pub max_pending: usize,
+    /// Age of the oldest buffered update that triggers a batch.
+    pub max_staleness: Duration,
+}
+
+impl Default for MovingOptions {
+    fn default() -> Self {
+        MovingOptions {
+            max_pending: 1024,
+            max_staleness: Duration::from_secs(1),
+        }
+    }
+}
+
+/// What a batch of updates did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MoveReport {
+    /// Entries that stayed in their cell, moved where they were.
+    pub in_place: usize,
+    /// Entries marked stale and inserted again at their new point.
+    pub reinserted: usize,
+    /// Updates for keys holding no object, dropped.
+    pub dropped: usize,
+}
+
+/// A `KeyedIndex` taking a stream of position updates, as from vehicle trackers, and
+/// applying them in batches.
+///
+/// # Architecture Decision: buffer, then move locally
+/// A delete and insert per tick rebuilds a path of the tree for every report, most of
+/// them a few meters from the last. `update` only records the newest point of a key;
+/// `apply` then visits each key once. An entry whose new point stays inside the cell its
+/// ancestors' splits give it, and keeps its own split between its children, is moved
+/// where it is, leaving the tree as it was. Only the others go through `upsert`, and once
+/// their stale copies outnumber the live entries the tree is compacted.
+///
+/// # Usage pattern:
+/// - `insert` each object once, with its payload
+/// - `update` on every report; it applies a batch once `max_pending` updates wait or the
+///   oldest has waited `max_staleness`
+/// - `search` sees the positions of the last batch; `staleness` bounds how far behind
+///   they are, and `apply` catches up before a search that must not lag
+///
+/// ```rust
+/// use bkd::{MovingIndex, RangeQuery};
+///
+/// let mut trucks = MovingIndex::new();
+/// trucks.insert("truck-7", [0.0, 0.0], "parked");
+/// trucks.update("truck-7", [5.0, 5.0]);
+/// assert!(trucks.staleness().is_some());
+///
+/// trucks.apply();
+/// let near = RangeQuery::new().with_range(0, 4.0, 6.0).with_range(1, 4.0, 6.0);
+/// assert_eq!(trucks.search(&near), [trucks.get(&"truck-7").unwrap()]);
+/// ```
+pub struct MovingIndex<K, P: Point, T> {
+    keyed: KeyedIndex<K, P, T>,
+    pending: HashMap<K, P>,
+    oldest: Option<Instant>,
+    options: MovingOptions,
+}
+
+impl<K: Eq + Hash, P: Point, T: Clone> MovingIndex<K, P, T> {
+    /// Create an empty index with default options.
+    pub fn new() -> Self {
+        Self::with_options(MovingOptions::default())
+    }
+
+    /// Create an empty index applying batches as `options` say.
+    pub fn with_options(options: MovingOptions) -> Self {
+        MovingIndex {
+            keyed: KeyedIndex::new(),
+            pending: HashMap::new(),
+            oldest: None,
+            options,
+        }
+    }
+
+    /// Insert an object under `key` right away, replacing the entry and any buffered update
+    /// the key held. Returns the handle of the new entry.
+    pub fn insert(&mut self, key: K, point: P, data: T) -> usize {
+        self.pending.remove(&key);
+        self.keyed.upsert(key, point, data)
+    }
+
+    /// Buffer a new position for `key`, replacing one buffered earlier. Returns the report
+    /// of the batch this update triggered, if any.
+    pub fn update(&mut self, key: K, point: P) -> Option<MoveReport> {
+        self.pending.insert(key, point);
+        let oldest = *self.oldest.get_or_insert_with(Instant::now);
+        if self.pending.len() >= self.options.max_pending
+            || oldest.elapsed() >= self.options.max_staleness
+        {
+            Some(self.apply())
+        } else {
+            None
+        }
+    }
+
+    /// Remove the object held by `key`, with its buffered update. Returns the handle of its
+    /// entry, or `None` if the key holds none.
+    pub fn remove(&mut self, key: &K) -> Option<usize> {
+        self.pending.remove(key);
+        self.keyed.remove(key)
+    }
+
+    /// Apply every buffered update.
+    pub fn apply(&mut self) -> MoveReport {
+        let mut report = MoveReport::default();
+        self.oldest = None;
+        for (key, point) in std::mem::take(&mut self.pending) {
+            let Some(node) = self.keyed.get(&key) else {
+                report.dropped += 1;
+                continue;
+            };
+            let index = self.keyed.index_mut();
+            if stays_in_cell(index, node, &point) {
+                index.arena_mut().get_mut(node).point = point;
+                report.in_place += 1;
+            } else {
+                let data = index.get_data(node).clone();
+                self.keyed.upsert(key, point, data);
+                report.reinserted += 1;
+            }
+        }
+        if self.keyed.stale() > self.keyed.len() {
+            self.keyed.compact();
+        }
+        report
+    }
+
+    /// Handles of the entries matching `query` at their positions as of the last batch.
+    pub fn search<Q: SpatialQuery<P>>(&self, query: &Q) -> Vec<usize> {
+        self.keyed.search(query)
+    }
+
+    /// Handle of the entry held by `key`. Handles change when a batch reinserts the entry.
+    pub fn get(&self, key: &K) -> Option<usize> {
+        self.keyed.get(key)
+    }
+
+    /// Newest position of `key`, buffered or applied.
+    pub fn position(&self, key: &K) -> Option<&P> {
+        self.pending.get(key).or_else(|| {
+            let node = self.keyed.get(key)?;
+            Some(self.keyed.index().get_point(node))
+        })
+    }
+
+    /// Number of buffered updates.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Time the oldest buffered update has waited, or `None` if searches are up to date.
+    pub fn staleness(&self) -> Option<Duration> {
+        self.oldest.map(|oldest| oldest.elapsed())
+    }
+
+    /// Number of objects.
+    pub fn len(&self) -> usize {
+        self.keyed.len()
+    }
+
+    /// Check if no object is held.
+    pub fn is_empty(&self) -> bool {
+        self.keyed.is_empty()
+    }
+
+    /// The tree and its key map, as of the last batch.
+    pub fn keyed(&self) -> &KeyedIndex<K, P, T> {
+        &self.keyed
+    }
+}
+
+impl<K: Eq + Hash, P: Point, T: Clone> Default for MovingIndex<K, P, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Check if `node` can take `point` without moving in the tree: `point` lies on the same
+/// side of every ancestor's split, and its own split still falls between its subtrees.
+fn stays_in_cell<P: Point, T>(index: &SpatialIndex<P, T>, node: usize, point: &P) -> bool {
+    let old = index.get_point(node);
+    let Some((depth, true)) = index
+        .root()
+        .and_then(|root| path_admits(index, root, 0, node, old, point))
+    else {
+        return false;
+    };
+    let dimension = depth % old.dimensions();
+    let value = point.get_dimension(dimension);
+    if value == old.get_dimension(dimension) {
+        return true;
+    }
+    let left = index.get_left(node);
+    let right = index.get_right(node);
+    left.is_none_or(|left| extreme(index, left, depth + 1, dimension, true) <= value)
+        && right.is_none_or(|right| extreme(index, right, depth + 1, dimension, false) >= value)
+}
+
+/// Find the path from `current` to `node` by its `old` point, and check if `new` follows
+/// it too. Returns the depth of `node` and the outcome, or `None` if it is not below
+/// `current`. Points equal to a split may sit on either side, so both are tried.
+fn path_admits<P: Point, T>(
+    index: &SpatialIndex<P, T>,
+    current: usize,
+    depth: usize,
+    node: usize,
+    old: &P,
+    new: &P,
+) -> Option<(usize, bool)> {
+    if current == node {
+        return Some((depth, true));
+    }
+    let dimension = depth % old.dimensions();
+    let split = index.get_point(current).get_dimension(dimension);
+    let (old_coord, new_coord) = (old.get_dimension(dimension), new.get_dimension(dimension));
+    if old_coord <= split
+        && let Some(left) = index.get_left(current)
+        && let Some((found, admits)) = path_admits(index, left, depth + 1, node, old, new)
+    {
+        return Some((found, admits && new_coord <= split));
+    }
+    if old_coord >= split
+        && let Some(right) = index.get_right(current)
+        && let Some((found, admits)) = path_admits(index, right, depth + 1, node, old, new)
+    {
+        return Some((found, admits && new_coord >= split));
+    }
+    None
+}
+
+/// Largest, or smallest, value of `dimension` in the subtree of `node`, skipping the sides
+/// of its splits on `dimension` that cannot hold it.
+fn extreme<P: Point, T>(
+    index: &SpatialIndex<P, T>,
+    node: usize,
+    depth: usize,
+    dimension: usize,
+    largest: bool,
+) -> f64 {
+    let point = index.get_point(node);
+    let pick = |a: f64, b: f64| if largest { a.max(b) } else { a.min(b) };
+    let splits_here = depth % point.dimensions() == dimension;
+    let mut value = point.get_dimension(dimension);
+    for (child, far_side) in [
+        (index.get_left(node), !largest),
+        (index.get_right(node), largest),
+    ] {
+        if let Some(child) = child
+            && (far_side || !splits_here)
+        {
+            value = pick(value, extreme(index, child, depth + 1, dimension, largest));
+        }
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::RangeQuery;
+
+    #[test]
+    fn test_batched_moves_match_current_positions() {
+        let options = MovingOptions {
+            max_pending: 150,
+            max_staleness: Duration::from_secs(3600),
+        };
+        let mut index = MovingIndex::with_options(options);
+        let mut positions: Vec<[f64; 2]> = (0..200u32)
+            .map(|i| [((i * 37) % 101) as f64, ((i * 53) % 97) as f64])
+            .collect();
+        for (key, &point) in positions.iter().enumerate() {
+            index.insert(key, point, key as u32);
+        }
+
+        let mut totals = MoveReport::default();
+        for tick in 0..10 {
+            for (key, point) in positions.iter_mut().enumerate() {
+                // Most objects drift a little; every seventh jumps across the map
+                let step = if (key + tick) % 7 == 0 { 50.0 } else { 0.01 };
+                point[0] = (point[0] + step) % 101.0;
+                if let Some(report) = index.update(key, *point) {
+                    assert_eq!(report.in_place + report.reinserted, 150);
+                    totals.in_place += report.in_place;
+                    totals.reinserted += report.reinserted;
+                }
+                assert_eq!(index.position(&key), Some(&*point));
+            }
+            assert!(index.pending() < 150);
+            assert!(index.staleness().is_some());
+            let report = index.apply();
+            totals.in_place += report.in_place;
+            totals.reinserted += report.reinserted;
+            assert_eq!(index.staleness(), None);
+
+            let area = RangeQuery::new()
+                .with_range(0, 20.0, 60.0)
+                .with_range(1, 10.0, 70.0);
+            let mut found: Vec<u32> = index
+                .search(&area)
+                .into_iter()
+                .map(|node| *index.keyed().index().get_data(node))
+                .collect();
+            found.sort_unstable();
+            let expected: Vec<u32> = (0..200u32)
+                .filter(|&key| {
+                    let [x, y] = positions[key as usize];
+                    (20.0..=60.0).contains(&x) && (10.0..=70.0).contains(&y)
+                })
+                .collect();
+            assert_eq!(found, expected);
+        }
+        assert_eq!(totals.in_place + totals.reinserted, 2000);
+        assert!(totals.in_place > 2 * totals.reinserted);
+        assert!(index.keyed().stale() <= index.len());
+
+        // A removed key drops its buffered update; one never inserted is dropped in a batch
+        index.update(3, [1.0, 1.0]);
+        assert!(index.remove(&3).is_some());
+        assert_eq!(index.position(&3), None);
+        index.update(500, [1.0, 1.0]);
+        let report = index.apply();
+        assert_eq!(
+            (report.in_place, report.reinserted, report.dropped),
+            (0, 0, 1)
+        );
+        assert_eq!((index.len(), index.get(&500)), (199, None));
+    }
+}
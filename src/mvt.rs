@@ -0,0 +1,335 @@
+//! Mapbox Vector Tile (MVT 2.1) encoding of a single layer, for `TileExport`.
+//!
+//! The format is a small protobuf schema, written here by hand: a `Tile` holds `Layer`s,
+//! and each layer its `Feature`s with geometry in tile-local integer coordinates plus
+//! tags indexing the layer's shared key and value tables.
+
+use crate::spatial::BoundingBox;
+use crate::tiles::PropertyValue;
+use std::collections::HashMap;
+
+/// Tile-local coordinates span `0..EXTENT` on each axis.
+pub(crate) const EXTENT: u32 = 4096;
+
+/// Coordinates kept beyond the tile edge, so renderers draw strokes across seams but
+/// huge boxes stay within `i32`.
+const BUFFER: f64 = 64.0;
+
+const MOVE_TO: u32 = 1;
+const LINE_TO: u32 = 2;
+const CLOSE_PATH: u32 = 7;
+
+const POINT: u64 = 1;
+const POLYGON: u64 = 3;
+
+/// Builds one layer, interning property keys and values as features are added.
+pub(crate) struct LayerEncoder {
+    name: String,
+    features: Vec<u8>,
+    keys: HashMap<String, u32>,
+    key_table: Vec<u8>,
+    values: HashMap<Vec<u8>, u32>,
+    value_table: Vec<u8>,
+}
+
+impl LayerEncoder {
+    pub(crate) fn new(name: &str) -> Self {
+        LayerEncoder {
+            name: name.to_string(),
+            features: Vec::new(),
+            keys: HashMap::new(),
+            key_table: Vec::new(),
+            values: HashMap::new(),
+            value_table: Vec::new(),
+        }
+    }
+
+    /// Add a feature for `bbox`, already in tile-local coordinates with y pointing down:
+    /// a point if it rounds to one, else a rectangle clipped to the buffered tile.
+    pub(crate) fn add(&mut self, bbox: &BoundingBox, properties: &[(String, PropertyValue)]) {
+        let clip = |value: f64| value.clamp(-BUFFER, EXTENT as f64 + BUFFER).round() as i32;
+        let (x0, y0, x1, y1) = (
+            clip(bbox.xmin),
+            clip(bbox.ymin),
+            clip(bbox.xmax),
+            clip(bbox.ymax),
+        );
+
+        let mut geometry = Vec::new();
+        let kind = if x0 == x1 || y0 == y1 {
+            geometry.push(command(MOVE_TO, 1));
+            geometry.extend([zigzag((x0 + x1) / 2), zigzag((y0 + y1) / 2)]);
+            POINT
+        } else {
+            // Clockwise with y down, as exterior rings must be
+            geometry.push(command(MOVE_TO, 1));
+            geometry.extend([zigzag(x0), zigzag(y0)]);
+            geometry.push(command(LINE_TO, 3));
+            geometry.extend([zigzag(x1 - x0), 0, 0, zigzag(y1 - y0)]);
+            geometry.extend([zigzag(x0 - x1), 0]);
+            geometry.push(command(CLOSE_PATH, 1));
+            POLYGON
+        };
+
+        let mut tags = Vec::with_capacity(properties.len() * 2);
+        for (key, value) in properties {
+            tags.push(self.intern_key(key));
+            tags.push(self.intern_value(value));
+        }
+
+        let mut feature = Vec::new();
+        write_packed(&mut feature, 2, &tags);
+        write_varint_field(&mut feature, 3, kind);
+        write_packed(&mut feature, 4, &geometry);
+        write_bytes_field(&mut self.features, 2, &feature);
+    }
+
+    /// Encode a `Tile` holding this layer alone. Tiles of several layers are the
+    /// concatenation of single-layer tiles.
+    pub(crate) fn finish(self) -> Vec<u8> {
+        let mut layer = Vec::new();
+        write_varint_field(&mut layer, 15, 2);
+        write_bytes_field(&mut layer, 1, self.name.as_bytes());
+        layer.extend(self.features);
+        layer.extend(self.key_table);
+        layer.extend(self.value_table);
+        write_varint_field(&mut layer, 5, EXTENT as u64);
+        let mut tile = Vec::new();
+        write_bytes_field(&mut tile, 3, &layer);
+        tile
+    }
+
+    fn intern_key(&mut self, key: &str) -> u32 {
+        if let Some(&id) = self.keys.get(key) {
+            return id;
+        }
+        let id = self.keys.len() as u32;
+        self.keys.insert(key.to_string(), id);
+        write_bytes_field(&mut self.key_table, 3, key.as_bytes());
+        id
+    }
+
+    fn intern_value(&mut self, value: &PropertyValue) -> u32 {
+        let mut encoded = Vec::new();
+        match value {
+            PropertyValue::Text(text) => write_bytes_field(&mut encoded, 1, text.as_bytes()),
+            // Whole numbers as integers, which decoders hand back as such
+            PropertyValue::Number(number)
+                if number.fract() == 0.0 && number.abs() < i64::MAX as f64 =>
+            {
+                match *number as i64 {
+                    whole if whole >= 0 => write_varint_field(&mut encoded, 5, whole as u64),
+                    whole => write_varint_field(&mut encoded, 6, zigzag64(whole)),
+                }
+            }
+            PropertyValue::Number(number) => {
+                write_varint(&mut encoded, (3 << 3) | 1);
+                encoded.extend(number.to_le_bytes());
+            }
+            PropertyValue::Bool(flag) => write_varint_field(&mut encoded, 7, *flag as u64),
+        }
+        if let Some(&id) = self.values.get(&encoded) {
+            return id;
+        }
+        let id = self.values.len() as u32;
+        write_bytes_field(&mut self.value_table, 4, &encoded);
+        self.values.insert(encoded, id);
+        id
+    }
+}
+
+fn command(id: u32, count: u32) -> u32 {
+    id | (count << 3)
+}
+
+fn zigzag(value: i32) -> u32 {
+    ((value << 1) ^ (value >> 31)) as u32
+}
+
+fn zigzag64(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn write_varint_field(out: &mut Vec<u8>, field: u64, value: u64) {
+    write_varint(out, field << 3);
+    write_varint(out, value);
+}
+
+fn write_bytes_field(out: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    write_varint(out, (field << 3) | 2);
+    write_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+fn write_packed(out: &mut Vec<u8>, field: u64, values: &[u32]) {
+    let mut packed = Vec::with_capacity(values.len());
+    for &value in values {
+        write_varint(&mut packed, value as u64);
+    }
+    write_bytes_field(out, field, &packed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    enum Field<'a> {
+        Varint(u64),
+        Fixed64(u64),
+        Bytes(&'a [u8]),
+    }
+
+    fn read_varint(bytes: &[u8], at: &mut usize) -> u64 {
+        let mut value = 0;
+        for shift in (0..).step_by(7) {
+            let byte = bytes[*at];
+            *at += 1;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte < 0x80 {
+                break;
+            }
+        }
+        value
+    }
+
+    fn fields(bytes: &[u8]) -> Vec<(u64, Field<'_>)> {
+        let mut fields = Vec::new();
+        let mut at = 0;
+        while at < bytes.len() {
+            let key = read_varint(bytes, &mut at);
+            let field = match key & 7 {
+                0 => Field::Varint(read_varint(bytes, &mut at)),
+                1 => {
+                    at += 8;
+                    Field::Fixed64(u64::from_le_bytes(bytes[at - 8..at].try_into().unwrap()))
+                }
+                2 => {
+                    let len = read_varint(bytes, &mut at) as usize;
+                    at += len;
+                    Field::Bytes(&bytes[at - len..at])
+                }
+                wire => panic!("unexpected wire type {wire}"),
+            };
+            fields.push((key >> 3, field));
+        }
+        fields
+    }
+
+    fn packed(field: &Field) -> Vec<u64> {
+        let Field::Bytes(bytes) = field else {
+            panic!("not packed: {field:?}");
+        };
+        let mut at = 0;
+        let mut values = Vec::new();
+        while at < bytes.len() {
+            values.push(read_varint(bytes, &mut at));
+        }
+        values
+    }
+
+    #[test]
+    fn test_layer_decodes_to_its_features() {
+        let mut encoder = LayerEncoder::new("places");
+        let park = [
+            ("name".to_string(), "park".into()),
+            ("rank".to_string(), 3.0.into()),
+        ];
+        let stop = [
+            ("name".to_string(), "stop".into()),
+            ("rank".to_string(), 3.0.into()),
+        ];
+        let kiosk = [
+            ("score".to_string(), (-1.5).into()),
+            ("open".to_string(), true.into()),
+            ("floor".to_string(), (-4i64).into()),
+        ];
+        encoder.add(&BoundingBox::new(10.0, 20.0, 110.0, 220.0), &park);
+        // Far outside the tile: clipped to the buffer
+        encoder.add(&BoundingBox::new(-500.0, 5000.0, -500.0, 5000.0), &stop);
+        // Less than a unit wide: a point
+        encoder.add(&BoundingBox::new(7.2, 7.4, 7.3, 7.6), &kiosk);
+        let tile = encoder.finish();
+
+        let tile = fields(&tile);
+        assert_eq!(tile.len(), 1);
+        let (3, Field::Bytes(layer)) = tile[0] else {
+            panic!("expected a layer, got {:?}", tile[0]);
+        };
+        let layer = fields(layer);
+        let of = |number: u64| -> Vec<&Field> {
+            layer
+                .iter()
+                .filter(|(field, _)| *field == number)
+                .map(|(_, value)| value)
+                .collect()
+        };
+        assert_eq!(of(15), [&Field::Varint(2)]);
+        assert_eq!(of(1), [&Field::Bytes(b"places")]);
+        assert_eq!(of(5), [&Field::Varint(EXTENT as u64)]);
+        let keys: Vec<Field> = ["name", "rank", "score", "open", "floor"]
+            .iter()
+            .map(|key| Field::Bytes(key.as_bytes()))
+            .collect();
+        assert_eq!(of(3), keys.iter().collect::<Vec<_>>());
+        let values: Vec<Vec<(u64, Field)>> = of(4)
+            .into_iter()
+            .map(|value| match value {
+                Field::Bytes(bytes) => fields(bytes),
+                other => panic!("unexpected value {other:?}"),
+            })
+            .collect();
+        assert_eq!(
+            values,
+            [
+                vec![(1, Field::Bytes(b"park"))],
+                vec![(5, Field::Varint(3))],
+                vec![(1, Field::Bytes(b"stop"))],
+                vec![(3, Field::Fixed64((-1.5f64).to_bits()))],
+                vec![(7, Field::Varint(1))],
+                vec![(6, Field::Varint(7))],
+            ]
+        );
+
+        let features: Vec<Vec<(u64, Field)>> = of(2)
+            .into_iter()
+            .map(|feature| match feature {
+                Field::Bytes(bytes) => fields(bytes),
+                other => panic!("unexpected feature {other:?}"),
+            })
+            .collect();
+        let decoded: Vec<(Vec<u64>, &Field, Vec<u64>)> = features
+            .iter()
+            .map(|feature| (packed(&feature[0].1), &feature[1].1, packed(&feature[2].1)))
+            .collect();
+        let z = |value: i32| zigzag(value) as u64;
+        assert_eq!(
+            decoded,
+            [
+                (
+                    vec![0, 0, 1, 1],
+                    &Field::Varint(POLYGON),
+                    vec![9, z(10), z(20), 26, z(100), 0, 0, z(200), z(-100), 0, 15],
+                ),
+                (
+                    vec![0, 2, 1, 1],
+                    &Field::Varint(POINT),
+                    vec![9, z(-64), z(4160)]
+                ),
+                (
+                    vec![2, 3, 3, 4, 4, 5],
+                    &Field::Varint(POINT),
+                    vec![9, z(7), z(7)],
+                ),
+            ]
+        );
+    }
+}
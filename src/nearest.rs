@@ -0,0 +1,742 @@
+//! Nearest-neighbor and radius search under weighted Minkowski metrics.
+//!
+//! Entries are measured as points by default. `nearest_boxes` and `nearest_box_iter`
+//! measure them as boxes instead, by the distance from the target to the nearest point of
+//! the box, which is what "the closest rectangle" means.
+
+use crate::query::SpatialQuery;
+use crate::search::Match;
+use crate::spatial::Point;
+use crate::storage::NodeLinker;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::marker::PhantomData;
+
+/// Weighted Minkowski distance over the dimensions of a point.
+///
+/// The distance between `a` and `b` is `(Σ (w_i · |a_i − b_i|)^p)^(1/p)`, or the largest
+/// weighted difference for `p = ∞`. Weights put heterogeneous dimensions on a common scale
+/// (e.g. a weight of 1/60 turns seconds into minutes before they are compared to meters);
+/// a weight of zero ignores a dimension. Dimensions without an explicit weight use 1.
+///
+/// Only `p >= 1` is accepted: below that the triangle inequality fails, and with it the
+/// pruning rule that no entry beyond a split plane can be closer than the plane itself.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metric {
+    p: f64,
+    weights: Vec<f64>,
+}
+
+impl Metric {
+    /// Minkowski distance of order `p`.
+    ///
+    /// # Panics
+    /// Panics if `p` is below 1 or NaN.
+    pub fn minkowski(p: f64) -> Self {
+        assert!(p >= 1.0, "Minkowski order must be at least 1, got {}", p);
+        Metric {
+            p,
+            weights: Vec::new(),
+        }
+    }
+
+    /// Straight-line distance (`p = 2`).
+    pub fn euclidean() -> Self {
+        Self::minkowski(2.0)
+    }
+
+    /// Sum of per-dimension differences (`p = 1`).
+    pub fn manhattan() -> Self {
+        Self::minkowski(1.0)
+    }
+
+    /// Largest per-dimension difference (`p = ∞`).
+    pub fn chebyshev() -> Self {
+        Self::minkowski(f64::INFINITY)
+    }
+
+    /// Scale dimension `i` by `weights[i]`.
+    ///
+    /// # Panics
+    /// Panics if a weight is negative or not finite.
+    pub fn with_weights(mut self, weights: Vec<f64>) -> Self {
+        assert!(
+            weights.iter().all(|w| w.is_finite() && *w >= 0.0),
+            "metric weights must be finite and non-negative"
+        );
+        self.weights = weights;
+        self
+    }
+
+    /// Order of the metric.
+    pub fn p(&self) -> f64 {
+        self.p
+    }
+
+    /// Weight of `dim`.
+    pub fn weight(&self, dim: usize) -> f64 {
+        self.weights.get(dim).copied().unwrap_or(1.0)
+    }
+
+    /// Distance between a point and target coordinates. Dimensions missing from either side
+    /// are ignored.
+    pub fn distance<P: Point>(&self, point: &P, target: &[f64]) -> f64 {
+        let dims = point.dimensions().min(target.len());
+        self.combine(
+            (0..dims).map(|dim| self.axis_distance(dim, point.get_dimension(dim) - target[dim])),
+        )
+    }
+
+    /// Smallest distance from `target` to any point in the cell bounded by `lower` and
+    /// `upper` (inclusive, per dimension); zero if the target lies inside the cell.
+    pub fn distance_to_cell(&self, target: &[f64], lower: &[f64], upper: &[f64]) -> f64 {
+        let dims = target.len().min(lower.len());
+        self.combine((0..dims).map(|dim| {
+            let outside = (lower[dim] - target[dim]).max(target[dim] - upper[dim]);
+            self.axis_distance(dim, outside.max(0.0))
+        }))
+    }
+
+    /// Smallest distance from `target` to the box `point` describes, its first half of
+    /// dimensions being the lower corner and its second half the upper corner, as in
+    /// `BoundingBox`; zero if the target lies inside. `target` and the weights have one
+    /// coordinate per axis of the box.
+    pub fn distance_to_box<P: Point>(&self, point: &P, target: &[f64]) -> f64 {
+        let axes = point.dimensions() / 2;
+        self.combine((0..axes.min(target.len())).map(|dim| {
+            let lower = point.get_dimension(dim);
+            let upper = point.get_dimension(axes + dim);
+            let outside = (lower - target[dim]).max(target[dim] - upper);
+            self.axis_distance(dim, outside.max(0.0))
+        }))
+    }
+
+    /// Smallest `distance_to_box` from `target` to any box in the cell bounded by `lower`
+    /// and `upper`, in the box's dimensions. A box in the cell starts no lower than the
+    /// cell's lower bound on its lower corner and ends no higher than the cell's upper
+    /// bound on its upper corner.
+    pub fn distance_to_box_cell(&self, target: &[f64], lower: &[f64], upper: &[f64]) -> f64 {
+        let axes = lower.len() / 2;
+        self.combine((0..axes.min(target.len())).map(|dim| {
+            let outside = (lower[dim] - target[dim]).max(target[dim] - upper[axes + dim]);
+            self.axis_distance(dim, outside.max(0.0))
+        }))
+    }
+
+    /// Weighted distance along a single dimension; a lower bound of `distance` for any pair
+    /// of points that far apart in `dim`.
+    pub fn axis_distance(&self, dim: usize, difference: f64) -> f64 {
+        let weight = self.weight(dim);
+        if weight == 0.0 {
+            0.0
+        } else {
+            weight * difference.abs()
+        }
+    }
+
+    /// Combine weighted per-dimension distances into the metric's distance.
+    fn combine(&self, axes: impl Iterator<Item = f64>) -> f64 {
+        if self.p == f64::INFINITY {
+            axes.fold(0.0, f64::max)
+        } else if self.p == 1.0 {
+            axes.sum()
+        } else if self.p == 2.0 {
+            axes.map(|axis| axis * axis).sum::<f64>().sqrt()
+        } else {
+            axes.map(|axis| axis.powf(self.p))
+                .sum::<f64>()
+                .powf(1.0 / self.p)
+        }
+    }
+}
+
+impl Default for Metric {
+    fn default() -> Self {
+        Self::euclidean()
+    }
+}
+
+/// How nearest-neighbor searches measure entries against the target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EntryShape {
+    /// Every dimension is a coordinate: `Metric::distance`.
+    #[default]
+    Point,
+    /// Lower corner then upper corner of a box, with a target of half as many
+    /// coordinates: `Metric::distance_to_box`.
+    Box,
+}
+
+impl EntryShape {
+    /// Distance from `target` to an entry.
+    pub fn distance<P: Point>(self, metric: &Metric, point: &P, target: &[f64]) -> f64 {
+        match self {
+            EntryShape::Point => metric.distance(point, target),
+            EntryShape::Box => metric.distance_to_box(point, target),
+        }
+    }
+
+    /// Smallest distance from `target` to any entry in a cell.
+    pub fn distance_to_cell(
+        self,
+        metric: &Metric,
+        target: &[f64],
+        lower: &[f64],
+        upper: &[f64],
+    ) -> f64 {
+        match self {
+            EntryShape::Point => metric.distance_to_cell(target, lower, upper),
+            EntryShape::Box => metric.distance_to_box_cell(target, lower, upper),
+        }
+    }
+}
+
+/// A node found by `nearest_neighbors`, with its distance to the target.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Neighbor<R> {
+    pub node: R,
+    pub distance: f64,
+}
+
+/// Heap entry for `NearestIter`: either a node whose distance is known or a subtree still
+/// to be expanded. `key` is the node's distance or the subtree's scaled lower bound.
+struct Frontier<R> {
+    key: f64,
+    seq: u64,
+    kind: FrontierKind<R>,
+}
+
+enum FrontierKind<R> {
+    Entry(R),
+    Subtree {
+        node: R,
+        depth: usize,
+        /// Per-dimension lower bounds followed by upper bounds of the subtree's cell.
+        cell: Vec<f64>,
+    },
+}
+
+impl<R> PartialEq for Frontier<R> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<R> Eq for Frontier<R> {}
+
+impl<R> PartialOrd for Frontier<R> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<R> Ord for Frontier<R> {
+    /// Reversed so `BinaryHeap` (a max-heap) pops the smallest key first.
+    /// `seq` breaks ties in insertion order, keeping the traversal deterministic.
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .key
+            .total_cmp(&self.key)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+/// Incremental nearest-neighbor search: yields nodes in ascending distance from a target.
+///
+/// # Architecture
+/// Best-bin-first traversal: subtrees wait in a min-heap keyed by the smallest distance any
+/// entry in their cell could have. Cells start unbounded and are narrowed by each split
+/// passed on the way down (left of a split => <= split, right => >= split), and the cell
+/// distance clamps the target into the cell per dimension. A node is yielded once nothing
+/// left in the heap can be closer, so promising branches are always expanded before
+/// unpromising ones, and consuming only the first few items touches only the nodes needed.
+///
+/// With `epsilon > 0` subtree keys are scaled by `1 + epsilon`, so a node is yielded as soon
+/// as nothing left could be closer by more than that factor: the `i`-th yielded node is at
+/// most `1 + epsilon` times farther away than the true `i`-th nearest.
+pub struct NearestIter<'a, P: Point, T, L: NodeLinker<P, T>> {
+    linker: &'a L,
+    target: &'a [f64],
+    metric: &'a Metric,
+    shape: EntryShape,
+    scale: f64,
+    heap: BinaryHeap<Frontier<L::NodeRef>>,
+    seq: u64,
+    _marker: PhantomData<(P, T)>,
+}
+
+/// Iterate over all nodes in ascending distance from `target` under `metric`.
+pub fn nearest_iter<'a, P: Point, T, L: NodeLinker<P, T>>(
+    linker: &'a L,
+    root: Option<L::NodeRef>,
+    target: &'a [f64],
+    metric: &'a Metric,
+    depth: usize,
+) -> NearestIter<'a, P, T, L> {
+    approximate_nearest_iter(linker, root, target, metric, 0.0, depth)
+}
+
+/// Like `nearest_iter`, but yields nodes up to `1 + epsilon` times out of order.
+///
+/// # Panics
+/// Panics if `epsilon` is negative or NaN.
+pub fn approximate_nearest_iter<'a, P: Point, T, L: NodeLinker<P, T>>(
+    linker: &'a L,
+    root: Option<L::NodeRef>,
+    target: &'a [f64],
+    metric: &'a Metric,
+    epsilon: f64,
+    depth: usize,
+) -> NearestIter<'a, P, T, L> {
+    assert!(
+        epsilon >= 0.0,
+        "epsilon must be non-negative, got {}",
+        epsilon
+    );
+    start_iter(
+        linker,
+        root,
+        target,
+        metric,
+        EntryShape::Point,
+        epsilon,
+        depth,
+    )
+}
+
+/// Iterate over all nodes in ascending distance from `target` to their boxes, as
+/// `EntryShape::Box` measures them.
+pub fn nearest_box_iter<'a, P: Point, T, L: NodeLinker<P, T>>(
+    linker: &'a L,
+    root: Option<L::NodeRef>,
+    target: &'a [f64],
+    metric: &'a Metric,
+    depth: usize,
+) -> NearestIter<'a, P, T, L> {
+    start_iter(linker, root, target, metric, EntryShape::Box, 0.0, depth)
+}
+
+fn start_iter<'a, P: Point, T, L: NodeLinker<P, T>>(
+    linker: &'a L,
+    root: Option<L::NodeRef>,
+    target: &'a [f64],
+    metric: &'a Metric,
+    shape: EntryShape,
+    epsilon: f64,
+    depth: usize,
+) -> NearestIter<'a, P, T, L> {
+    let mut iter = NearestIter {
+        linker,
+        target,
+        metric,
+        shape,
+        scale: 1.0 + epsilon,
+        heap: BinaryHeap::new(),
+        seq: 0,
+        _marker: PhantomData,
+    };
+    if let Some(node) = root {
+        let dimensions = linker.get_point(node).dimensions();
+        let mut cell = vec![f64::NEG_INFINITY; dimensions];
+        cell.resize(2 * dimensions, f64::INFINITY);
+        iter.push(0.0, FrontierKind::Subtree { node, depth, cell });
+    }
+    iter
+}
+
+impl<'a, P: Point, T, L: NodeLinker<P, T>> NearestIter<'a, P, T, L> {
+    fn push(&mut self, key: f64, kind: FrontierKind<L::NodeRef>) {
+        self.heap.push(Frontier {
+            key,
+            seq: self.seq,
+            kind,
+        });
+        self.seq += 1;
+    }
+
+    /// Queue a child subtree keyed by the scaled distance from the target to its cell.
+    fn push_subtree(&mut self, node: L::NodeRef, depth: usize, cell: Vec<f64>) {
+        let (lower, upper) = cell.split_at(cell.len() / 2);
+        let key = self
+            .shape
+            .distance_to_cell(self.metric, self.target, lower, upper)
+            * self.scale;
+        self.push(key, FrontierKind::Subtree { node, depth, cell });
+    }
+}
+
+impl<'a, P: Point, T, L: NodeLinker<P, T>> Iterator for NearestIter<'a, P, T, L> {
+    type Item = Neighbor<L::NodeRef>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(item) = self.heap.pop() {
+            let (node, depth, cell) = match item.kind {
+                FrontierKind::Entry(node) => {
+                    return Some(Neighbor {
+                        node,
+                        distance: item.key,
+                    });
+                }
+                FrontierKind::Subtree { node, depth, cell } => (node, depth, cell),
+            };
+
+            let point = self.linker.get_point(node);
+            let distance = self.shape.distance(self.metric, point, self.target);
+            self.push(distance, FrontierKind::Entry(node));
+
+            let dimensions = cell.len() / 2;
+            let dimension = depth % point.dimensions();
+            let split = point.get_dimension(dimension);
+            if let Some(left_child) = self.linker.get_left(node) {
+                let mut left = cell.clone();
+                if dimension < dimensions {
+                    left[dimensions + dimension] = left[dimensions + dimension].min(split);
+                }
+                self.push_subtree(left_child, depth + 1, left);
+            }
+            if let Some(right_child) = self.linker.get_right(node) {
+                let mut right = cell;
+                if dimension < dimensions {
+                    right[dimension] = right[dimension].max(split);
+                }
+                self.push_subtree(right_child, depth + 1, right);
+            }
+        }
+        None
+    }
+}
+
+/// Find the `k` nodes closest to `target` under `metric`, nearest first.
+///
+/// Runs the best-first traversal of `nearest_iter` and stops after `k` nodes, so it only
+/// expands subtrees whose cells are closer than the `k`-th neighbor.
+///
+/// Distances treat every dimension of `P` as a coordinate; for `BoundingBox` entries that
+/// is the 4D point (xmin, ymin, xmax, ymax), so a box is as far as its corners are. Use
+/// `nearest_boxes` to measure to the nearest point of each box. Ties are broken
+/// deterministically, in favor of the node reached first.
+pub fn nearest_neighbors<P: Point, T, L: NodeLinker<P, T>>(
+    linker: &L,
+    root: Option<L::NodeRef>,
+    target: &[f64],
+    k: usize,
+    metric: &Metric,
+    depth: usize,
+) -> Vec<Neighbor<L::NodeRef>> {
+    nearest_iter(linker, root, target, metric, depth)
+        .take(k)
+        .collect()
+}
+
+/// Find the `k` nodes whose boxes are closest to `target` under `metric`, nearest first.
+///
+/// Entries are measured as `EntryShape::Box`: a `BoundingBox` containing the target is at
+/// distance zero however large it is, and `target` holds one coordinate per axis, such
+/// as `[x, y]`. Subtrees are pruned by the same measure, so the result is exact.
+pub fn nearest_boxes<P: Point, T, L: NodeLinker<P, T>>(
+    linker: &L,
+    root: Option<L::NodeRef>,
+    target: &[f64],
+    k: usize,
+    metric: &Metric,
+    depth: usize,
+) -> Vec<Neighbor<L::NodeRef>> {
+    nearest_box_iter(linker, root, target, metric, depth)
+        .take(k)
+        .collect()
+}
+
+/// `nearest_neighbors` returning resolved `Match`es with their distances, nearest first.
+pub fn nearest_neighbor_matches<'a, P: Point, T, L: NodeLinker<P, T>>(
+    linker: &'a L,
+    root: Option<L::NodeRef>,
+    target: &[f64],
+    k: usize,
+    metric: &Metric,
+    depth: usize,
+) -> Vec<Match<'a, P, T, L::NodeRef>> {
+    let neighbors = nearest_neighbors(linker, root, target, k, metric, depth);
+    let nodes: Vec<L::NodeRef> = neighbors.iter().map(|neighbor| neighbor.node).collect();
+    linker
+        .get_many(&nodes)
+        .zip(&neighbors)
+        .map(|((point, data), neighbor)| Match {
+            node: neighbor.node,
+            point,
+            data,
+            distance: Some(neighbor.distance),
+        })
+        .collect()
+}
+
+/// Approximate variant of `nearest_neighbors` that trades accuracy for speed.
+///
+/// Each returned neighbor is at most `1 + epsilon` times farther away than the true
+/// neighbor of the same rank; `epsilon = 0` is the exact search. Larger values stop the
+/// search before it has proven that no far cell holds a slightly closer entry, which is
+/// where exact searches in many dimensions spend most of their time.
+///
+/// # Panics
+/// Panics if `epsilon` is negative or NaN.
+pub fn approximate_nearest_neighbors<P: Point, T, L: NodeLinker<P, T>>(
+    linker: &L,
+    root: Option<L::NodeRef>,
+    target: &[f64],
+    k: usize,
+    metric: &Metric,
+    epsilon: f64,
+    depth: usize,
+) -> Vec<Neighbor<L::NodeRef>> {
+    let mut neighbors: Vec<Neighbor<L::NodeRef>> =
+        approximate_nearest_iter(linker, root, target, metric, epsilon, depth)
+            .take(k)
+            .collect();
+    // Approximate traversal yields slightly out of order; the rank bound survives sorting
+    neighbors.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+    neighbors
+}
+
+/// Radius query: matches every point within `radius` of `target` under `metric`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WithinDistance {
+    pub target: Vec<f64>,
+    pub radius: f64,
+    pub metric: Metric,
+}
+
+impl WithinDistance {
+    /// Create a radius query around `target`.
+    pub fn new(target: Vec<f64>, radius: f64, metric: Metric) -> Self {
+        WithinDistance {
+            target,
+            radius,
+            metric,
+        }
+    }
+}
+
+impl<P: Point> SpatialQuery<P> for WithinDistance {
+    /// A point within the radius is at most `radius / weight` away along each dimension;
+    /// dimensions with zero weight, or absent from the target, are unbounded.
+    fn dimension_range(&self, dim: usize) -> (f64, f64) {
+        let weight = self.metric.weight(dim);
+        match self.target.get(dim) {
+            Some(&center) if weight > 0.0 => {
+                let reach = self.radius / weight;
+                (center - reach, center + reach)
+            }
+            _ => (f64::NEG_INFINITY, f64::INFINITY),
+        }
+    }
+
+    fn matches(&self, point: &P) -> bool {
+        self.metric.distance(point, &self.target) <= self.radius
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::{BuildOptions, bulk_build};
+    use crate::search::spatial_search;
+    use crate::spatial::BoundingBox;
+    use crate::storage::{InMemoryLinker, NodeArena};
+
+    fn tree() -> (NodeArena<BoundingBox, usize>, Option<usize>) {
+        let mut arena = NodeArena::new();
+        let mut nodes: Vec<usize> = (0..400)
+            .map(|i| {
+                let x = ((i * 37) % 101) as f64;
+                let y = ((i * 53) % 97) as f64;
+                arena.allocate(BoundingBox::new(x, y, x + (i % 3) as f64, y + 1.0), i)
+            })
+            .collect();
+        let mut linker = InMemoryLinker::new(&mut arena);
+        let root = bulk_build(&mut linker, &mut nodes, 0, &BuildOptions::default()).unwrap();
+        (arena, root)
+    }
+
+    #[test]
+    fn test_nearest_neighbors_match_brute_force() {
+        let (mut arena, root) = tree();
+        let target = [40.0, 60.0, 41.0, 61.0];
+        let metrics = [
+            Metric::euclidean(),
+            Metric::manhattan(),
+            Metric::chebyshev(),
+            Metric::minkowski(3.0).with_weights(vec![1.0, 10.0, 0.5, 0.0]),
+        ];
+        for metric in &metrics {
+            let mut expected: Vec<f64> = (0..arena.len())
+                .map(|node| metric.distance(arena.get(node).get_point(), &target))
+                .collect();
+            expected.sort_by(f64::total_cmp);
+            expected.truncate(7);
+
+            let linker = InMemoryLinker::new(&mut arena);
+            let neighbors = nearest_neighbors(&linker, root, &target, 7, metric, 0);
+            let distances: Vec<f64> = neighbors.iter().map(|n| n.distance).collect();
+            assert_eq!(distances, expected);
+        }
+    }
+
+    #[test]
+    fn test_neighbor_matches_resolve_points_and_data() {
+        let (mut arena, root) = tree();
+        let target = [10.0, 10.0, 11.0, 11.0];
+        let linker = InMemoryLinker::new(&mut arena);
+        let neighbors = nearest_neighbors(&linker, root, &target, 5, &Metric::euclidean(), 0);
+        let matches = nearest_neighbor_matches(&linker, root, &target, 5, &Metric::euclidean(), 0);
+
+        assert_eq!(matches.len(), 5);
+        for (found, neighbor) in matches.iter().zip(&neighbors) {
+            assert_eq!(found.node, neighbor.node);
+            assert_eq!(found.distance, Some(neighbor.distance));
+            assert_eq!(*found.data, neighbor.node);
+            assert!(std::ptr::eq(found.point, linker.get_point(neighbor.node)));
+        }
+    }
+
+    #[test]
+    fn test_approximate_neighbors_within_epsilon() {
+        let (mut arena, root) = tree();
+        let target = [10.0, 80.0, 12.0, 81.0];
+        let metric = Metric::euclidean();
+        let mut exact: Vec<f64> = (0..arena.len())
+            .map(|node| metric.distance(arena.get(node).get_point(), &target))
+            .collect();
+        exact.sort_by(f64::total_cmp);
+
+        let linker = InMemoryLinker::new(&mut arena);
+        let same = approximate_nearest_neighbors(&linker, root, &target, 5, &metric, 0.0, 0);
+        assert_eq!(
+            same,
+            nearest_neighbors(&linker, root, &target, 5, &metric, 0)
+        );
+
+        for epsilon in [0.1, 0.5, 2.0] {
+            let neighbors =
+                approximate_nearest_neighbors(&linker, root, &target, 5, &metric, epsilon, 0);
+            assert_eq!(neighbors.len(), 5);
+            for (neighbor, true_distance) in neighbors.iter().zip(&exact) {
+                assert!(neighbor.distance <= true_distance * (1.0 + epsilon));
+            }
+        }
+    }
+
+    #[test]
+    fn test_nearest_iter_yields_every_node_in_distance_order() {
+        let (mut arena, root) = tree();
+        let target = [55.0, 5.0];
+        let metric = Metric::chebyshev();
+        let linker = InMemoryLinker::new(&mut arena);
+
+        let all: Vec<Neighbor<usize>> = nearest_iter(&linker, root, &target, &metric, 0).collect();
+        assert_eq!(all.len(), 400);
+        assert!(
+            all.windows(2)
+                .all(|pair| pair[0].distance <= pair[1].distance)
+        );
+        let mut nodes: Vec<usize> = all.iter().map(|neighbor| neighbor.node).collect();
+        nodes.sort();
+        nodes.dedup();
+        assert_eq!(nodes.len(), 400);
+
+        let first: Vec<Neighbor<usize>> = nearest_iter(&linker, root, &target, &metric, 0)
+            .take(3)
+            .collect();
+        assert_eq!(
+            first,
+            nearest_neighbors(&linker, root, &target, 3, &metric, 0)
+        );
+    }
+
+    #[test]
+    fn test_nearest_boxes_measure_to_the_rectangle() {
+        let (mut arena, root) = tree();
+        let metrics = [
+            Metric::euclidean(),
+            Metric::chebyshev(),
+            Metric::manhattan().with_weights(vec![1.0, 4.0]),
+        ];
+        for target in [[40.0, 60.0], [-20.0, 50.0], [100.5, 96.5]] {
+            for metric in &metrics {
+                let mut expected: Vec<f64> = (0..arena.len())
+                    .map(|node| metric.distance_to_box(arena.get(node).get_point(), &target))
+                    .collect();
+                expected.sort_by(f64::total_cmp);
+                expected.truncate(9);
+
+                let linker = InMemoryLinker::new(&mut arena);
+                let neighbors = nearest_boxes(&linker, root, &target, 9, metric, 0);
+                let distances: Vec<f64> = neighbors.iter().map(|n| n.distance).collect();
+                assert_eq!(distances, expected);
+            }
+        }
+
+        // A large box around the target beats a small one beside it, whose corner is closer
+        let mut arena = NodeArena::new();
+        let around = arena.allocate(BoundingBox::new(0.0, 0.0, 100.0, 100.0), "around");
+        let beside = arena.allocate(BoundingBox::new(51.0, 50.0, 52.0, 51.0), "beside");
+        let mut linker = InMemoryLinker::new(&mut arena);
+        let mut nodes = vec![around, beside];
+        let root = bulk_build(&mut linker, &mut nodes, 0, &BuildOptions::default()).unwrap();
+        let metric = Metric::euclidean();
+        let corners = nearest_neighbors(&linker, root, &[50.0, 50.0, 50.0, 50.0], 1, &metric, 0);
+        assert_eq!(corners[0].node, beside);
+        let boxes = nearest_boxes(&linker, root, &[50.0, 50.0], 2, &metric, 0);
+        assert_eq!((boxes[0].node, boxes[0].distance), (around, 0.0));
+        assert_eq!((boxes[1].node, boxes[1].distance), (beside, 1.0));
+    }
+
+    #[test]
+    fn test_cell_distance() {
+        let metric = Metric::euclidean();
+        let (lower, upper) = ([0.0, 0.0], [1.0, f64::INFINITY]);
+        assert_eq!(metric.distance_to_cell(&[0.5, 100.0], &lower, &upper), 0.0);
+        assert_eq!(metric.distance_to_cell(&[4.0, -4.0], &lower, &upper), 5.0);
+    }
+
+    #[test]
+    fn test_weights_change_the_nearest_neighbor() {
+        let mut arena = NodeArena::new();
+        let close_in_x = arena.allocate(BoundingBox::new(1.0, 30.0, 1.0, 30.0), "x");
+        let close_in_y = arena.allocate(BoundingBox::new(20.0, 0.0, 20.0, 0.0), "y");
+        let mut linker = InMemoryLinker::new(&mut arena);
+        let mut nodes = vec![close_in_x, close_in_y];
+        let root = bulk_build(&mut linker, &mut nodes, 0, &BuildOptions::default()).unwrap();
+        let target = [0.0, 0.0, 0.0, 0.0];
+
+        let plain = nearest_neighbors(&linker, root, &target, 1, &Metric::euclidean(), 0);
+        assert_eq!(plain[0].node, close_in_y);
+
+        // Count y (dims 1 and 3) at a tenth of x
+        let scaled = Metric::euclidean().with_weights(vec![1.0, 0.1, 1.0, 0.1]);
+        let weighted = nearest_neighbors(&linker, root, &target, 1, &scaled, 0);
+        assert_eq!(weighted[0].node, close_in_x);
+        assert!(nearest_neighbors(&linker, None, &target, 3, &scaled, 0).is_empty());
+    }
+
+    #[test]
+    fn test_within_distance_query() {
+        let (mut arena, root) = tree();
+        let metric = Metric::manhattan().with_weights(vec![2.0, 1.0, 0.0, 0.0]);
+        let query = WithinDistance::new(vec![50.0, 50.0], 12.0, metric);
+        let mut expected: Vec<usize> = (0..arena.len())
+            .filter(|&node| query.matches(arena.get(node).get_point()))
+            .collect();
+        assert!(!expected.is_empty());
+
+        let linker = InMemoryLinker::new(&mut arena);
+        let mut results = spatial_search(&linker, root, &query, 0);
+        results.sort();
+        expected.sort();
+        assert_eq!(results, expected);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least 1")]
+    fn test_minkowski_rejects_fractional_order() {
+        Metric::minkowski(0.5);
+    }
+}
@@ -0,0 +1,813 @@
+//! Persisted node file: a KD-tree stored as an array of fixed-width node records.
+//!
+//! # Layout
+//! ```text
+//! ┌──────────────────────────────┐  offset 0
+//! │ Header (64 bytes)            │  magic, version, dimensions, sizes, count, root, checksum,
+//! │                              │  features
+//! ├──────────────────────────────┤  offset 64
+//! │ Record 0                     │  left u64 | right u64 | point | data | padding
+//! │ Record 1                     │
+//! │ ...                          │  record i at 64 + i * record_size
+//! └──────────────────────────────┘
+//! ```
+//! All integers are little-endian. Child links are record indices, with `u64::MAX` meaning
+//! "no child". Records are padded to a multiple of 8 bytes so every record (and the point
+//! at offset 16 inside it) stays 8-byte aligned.
+//!
+//! The checksum is a 64-bit FNV-1a of all record bytes, written by `NodeFileWriter`, so
+//! `verify` can detect corrupted records. Zero means the file has none: files from before
+//! checksums existed, and memory-mapped arenas, whose records change in place.
+//!
+//! The features are a `FormatFeatures` bitset of optional encodings the records use. Bits
+//! are only ever added, and a reader refuses any file setting a bit it does not know,
+//! rather than decoding the records as something they are not. Files written before the
+//! bitset existed have zeros there, which reads as no features.
+
+use crate::codec::FixedCodec;
+use crate::digest::Fnv1a;
+use crate::metrics::Metrics;
+use crate::query::{Relation, SpatialQuery};
+use crate::search::children_to_visit;
+use crate::spatial::Point;
+use crate::storage::NodeArena;
+use crate::summary::SubtreeBounds;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Magic bytes identifying a node file.
+pub const MAGIC: [u8; 8] = *b"BKDNODES";
+
+/// Current format version.
+pub const VERSION: u32 = 1;
+
+/// Size of the file header in bytes.
+pub const HEADER_SIZE: usize = 64;
+
+/// Encoded value of an absent child link or root.
+pub const NO_NODE: u64 = u64::MAX;
+
+/// Decoded file header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeFileHeader {
+    pub version: u32,
+    pub dimensions: u32,
+    pub point_size: u32,
+    pub data_size: u32,
+    pub node_count: u64,
+    pub root: Option<u64>,
+    /// Checksum of the records, if the file carries one.
+    pub checksum: Option<u64>,
+    /// Optional encodings the records use.
+    pub features: FormatFeatures,
+}
+
+/// Bitset of optional format features recorded in a node file header.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct FormatFeatures(u64);
+
+impl FormatFeatures {
+    /// No optional features: plain points and payloads.
+    pub const NONE: FormatFeatures = FormatFeatures(0);
+    /// Records are block-compressed. Reserved; no reader supports it yet.
+    pub const COMPRESSED: FormatFeatures = FormatFeatures(1 << 0);
+    /// Points are `QuantizedPoint`s, to be decoded with the `Quantizer` that wrote them.
+    pub const QUANTIZED: FormatFeatures = FormatFeatures(1 << 1);
+    /// Payloads hold compactly encoded doc ids. Reserved; no reader supports it yet.
+    pub const DOC_ID_ENCODED: FormatFeatures = FormatFeatures(1 << 2);
+    /// Every feature this version of the crate reads.
+    pub const SUPPORTED: FormatFeatures = FormatFeatures::QUANTIZED;
+
+    const NAMES: [(FormatFeatures, &'static str); 3] = [
+        (FormatFeatures::COMPRESSED, "compressed"),
+        (FormatFeatures::QUANTIZED, "quantized"),
+        (FormatFeatures::DOC_ID_ENCODED, "doc-id-encoded"),
+    ];
+
+    /// Features from their raw bits, known or not.
+    pub const fn from_bits(bits: u64) -> Self {
+        FormatFeatures(bits)
+    }
+
+    /// The raw bits.
+    pub const fn bits(self) -> u64 {
+        self.0
+    }
+
+    /// Check if every feature in `other` is set.
+    pub const fn contains(self, other: FormatFeatures) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Features set in either.
+    pub const fn union(self, other: FormatFeatures) -> Self {
+        FormatFeatures(self.0 | other.0)
+    }
+
+    /// Features set here that this version of the crate cannot read.
+    pub const fn unsupported(self) -> Self {
+        FormatFeatures(self.0 & !FormatFeatures::SUPPORTED.0)
+    }
+}
+
+/// Names the features, e.g. `quantized, 0x100`, with unnamed bits in hex.
+impl fmt::Display for FormatFeatures {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0 == 0 {
+            return f.write_str("none");
+        }
+        let mut rest = self.0;
+        let mut names = Vec::new();
+        for (feature, name) in FormatFeatures::NAMES {
+            if self.contains(feature) {
+                names.push(name.to_string());
+                rest &= !feature.0;
+            }
+        }
+        if rest != 0 {
+            names.push(format!("{rest:#x}"));
+        }
+        f.write_str(&names.join(", "))
+    }
+}
+
+impl NodeFileHeader {
+    /// Size of one node record for the given point and payload sizes.
+    pub fn record_size(&self) -> usize {
+        record_size(self.point_size as usize, self.data_size as usize)
+    }
+
+    /// Byte offset of record `index`, or an error if the index is out of range.
+    pub(crate) fn record_offset(&self, index: u64) -> io::Result<u64> {
+        if index >= self.node_count {
+            return Err(invalid_data("node index out of range"));
+        }
+        Ok(HEADER_SIZE as u64 + index * self.record_size() as u64)
+    }
+
+    /// Check that the records hold points of type `P` and payloads of type `T`.
+    pub(crate) fn check_layout<P: FixedCodec, T: FixedCodec>(&self) -> io::Result<()> {
+        if self.point_size as usize != P::SIZE || self.data_size as usize != T::SIZE {
+            return Err(invalid_data(
+                "node file record layout does not match the requested types",
+            ));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn encode(&self) -> [u8; HEADER_SIZE] {
+        let mut buf = [0u8; HEADER_SIZE];
+        buf[0..8].copy_from_slice(&MAGIC);
+        self.version.encode(&mut buf[8..12]);
+        self.dimensions.encode(&mut buf[12..16]);
+        self.point_size.encode(&mut buf[16..20]);
+        self.data_size.encode(&mut buf[20..24]);
+        self.node_count.encode(&mut buf[24..32]);
+        self.root.unwrap_or(NO_NODE).encode(&mut buf[32..40]);
+        self.checksum.unwrap_or(0).encode(&mut buf[40..48]);
+        self.features.bits().encode(&mut buf[48..56]);
+        buf
+    }
+
+    pub(crate) fn decode(buf: &[u8; HEADER_SIZE]) -> io::Result<Self> {
+        if buf[0..8] != MAGIC {
+            return Err(invalid_data("not a BKD node file (bad magic)"));
+        }
+        let header = NodeFileHeader {
+            version: u32::decode(&buf[8..12]),
+            dimensions: u32::decode(&buf[12..16]),
+            point_size: u32::decode(&buf[16..20]),
+            data_size: u32::decode(&buf[20..24]),
+            node_count: u64::decode(&buf[24..32]),
+            root: decode_link(&buf[32..40]),
+            checksum: match u64::decode(&buf[40..48]) {
+                0 => None,
+                checksum => Some(checksum),
+            },
+            features: FormatFeatures::from_bits(u64::decode(&buf[48..56])),
+        };
+        if header.version != VERSION {
+            return Err(invalid_data(&format!(
+                "unsupported node file version {}",
+                header.version
+            )));
+        }
+        let unsupported = header.features.unsupported();
+        if unsupported != FormatFeatures::NONE {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("node file uses unsupported format features: {unsupported}"),
+            ));
+        }
+        Ok(header)
+    }
+}
+
+/// One decoded node record.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeRecord<P, T> {
+    pub point: P,
+    pub data: T,
+    pub left: Option<u64>,
+    pub right: Option<u64>,
+}
+
+impl<P: FixedCodec, T: FixedCodec> NodeRecord<P, T> {
+    /// Decode a record from its on-disk bytes.
+    pub(crate) fn decode(buf: &[u8]) -> Self {
+        NodeRecord {
+            left: decode_link(&buf[0..8]),
+            right: decode_link(&buf[8..16]),
+            point: P::decode(&buf[16..16 + P::SIZE]),
+            data: T::decode(&buf[16 + P::SIZE..16 + P::SIZE + T::SIZE]),
+        }
+    }
+}
+
+/// Size of one record: two links, the point and the payload, padded to 8 bytes.
+pub fn record_size(point_size: usize, data_size: usize) -> usize {
+    (16 + point_size + data_size).div_ceil(8) * 8
+}
+
+fn decode_link(buf: &[u8]) -> Option<u64> {
+    match u64::decode(buf) {
+        NO_NODE => None,
+        index => Some(index),
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Sequential writer for node files.
+///
+/// # Usage pattern:
+/// Records are appended in index order; links may point forward to records that have not
+/// been written yet (e.g. a pre-order layout, where children follow their parent).
+/// `finish` writes the header with the final node count and root.
+///
+/// Writes go to a file by default; `new` takes any seekable sink instead, such as an
+/// `EncryptedFile` (feature `encryption`), with the node file starting at its offset 0.
+pub struct NodeFileWriter<P, T, W: Write = File> {
+    file: BufWriter<W>,
+    dimensions: Option<u32>,
+    node_count: u64,
+    record: Vec<u8>,
+    checksum: Fnv1a,
+    features: FormatFeatures,
+    // Encodes borrowed values and never holds one, so it is Send and Sync whatever P and T
+    _marker: PhantomData<fn() -> (P, T)>,
+}
+
+impl<P: Point + FixedCodec, T: FixedCodec> NodeFileWriter<P, T> {
+    /// Create (or truncate) a node file at `path`.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Self::new(File::create(path)?)
+    }
+
+    /// Write the header and flush everything to disk.
+    pub fn finish(self, root: Option<u64>) -> io::Result<()> {
+        self.finish_into(root)?.sync_all()
+    }
+}
+
+impl<P: Point + FixedCodec, T: FixedCodec, W: Write + Seek> NodeFileWriter<P, T, W> {
+    /// Write a node file into `sink`, which should be empty.
+    pub fn new(sink: W) -> io::Result<Self> {
+        let mut file = BufWriter::new(sink);
+        // Placeholder header, rewritten by `finish`
+        file.write_all(&[0u8; HEADER_SIZE])?;
+        Ok(NodeFileWriter {
+            file,
+            dimensions: None,
+            node_count: 0,
+            record: vec![0u8; record_size(P::SIZE, T::SIZE)],
+            checksum: Fnv1a(Fnv1a::OFFSET_BASIS),
+            features: FormatFeatures::NONE,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Record `features` in the header, e.g. `QUANTIZED` when `P` is a `QuantizedPoint`.
+    ///
+    /// # Panics
+    /// If `features` holds one this crate cannot read back.
+    pub fn with_features(mut self, features: FormatFeatures) -> Self {
+        assert_eq!(
+            features.unsupported(),
+            FormatFeatures::NONE,
+            "cannot write unsupported format features"
+        );
+        self.features = features;
+        self
+    }
+
+    /// Append a record and return its index.
+    pub fn push(
+        &mut self,
+        point: &P,
+        data: &T,
+        left: Option<u64>,
+        right: Option<u64>,
+    ) -> io::Result<u64> {
+        self.dimensions.get_or_insert(point.dimensions() as u32);
+        left.unwrap_or(NO_NODE).encode(&mut self.record[0..8]);
+        right.unwrap_or(NO_NODE).encode(&mut self.record[8..16]);
+        point.encode(&mut self.record[16..16 + P::SIZE]);
+        data.encode(&mut self.record[16 + P::SIZE..16 + P::SIZE + T::SIZE]);
+        self.file.write_all(&self.record)?;
+        self.checksum.write(&self.record);
+
+        let index = self.node_count;
+        self.node_count += 1;
+        Ok(index)
+    }
+
+    /// Number of records written so far.
+    pub fn len(&self) -> u64 {
+        self.node_count
+    }
+
+    /// Check if no records have been written.
+    pub fn is_empty(&self) -> bool {
+        self.node_count == 0
+    }
+
+    /// Write the header and flush, returning the sink for the caller to make durable.
+    pub fn finish_into(mut self, root: Option<u64>) -> io::Result<W> {
+        let header = NodeFileHeader {
+            version: VERSION,
+            dimensions: self.dimensions.unwrap_or(0),
+            point_size: P::SIZE as u32,
+            data_size: T::SIZE as u32,
+            node_count: self.node_count,
+            root,
+            checksum: Some(self.checksum.0),
+            features: self.features,
+        };
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&header.encode())?;
+        self.file.into_inner().map_err(|e| e.into_error())
+    }
+}
+
+/// Write an in-memory arena to a node file, preserving node indices.
+/// Arena index `i` becomes record `i`, so the file can be opened by any node-file backend
+/// and searched from `root` without relinking.
+pub fn write_arena<P: Point + FixedCodec, T: FixedCodec>(
+    path: &Path,
+    arena: &NodeArena<P, T>,
+    root: Option<usize>,
+) -> io::Result<()> {
+    let mut writer = NodeFileWriter::<P, T>::create(path)?;
+    for index in 0..arena.len() {
+        let node = arena.get(index);
+        writer.push(
+            &node.point,
+            &node.data,
+            node.left.map(|child| child as u64),
+            node.right.map(|child| child as u64),
+        )?;
+    }
+    writer.finish(root.map(|root| root as u64))
+}
+
+/// Random-access reader for node files using plain file reads.
+pub struct NodeFileReader<P, T> {
+    file: File,
+    header: NodeFileHeader,
+    record: Vec<u8>,
+    metrics: Option<Arc<Metrics>>,
+    // Decodes owned values and never holds one, so it is Send and Sync whatever P and T
+    _marker: PhantomData<fn() -> (P, T)>,
+}
+
+impl<P: Point + FixedCodec, T: FixedCodec> NodeFileReader<P, T> {
+    /// Open a node file and validate its header against `P` and `T`.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let mut buf = [0u8; HEADER_SIZE];
+        file.read_exact(&mut buf)?;
+        let header = NodeFileHeader::decode(&buf)?;
+        header.check_layout::<P, T>()?;
+        Ok(NodeFileReader {
+            file,
+            record: vec![0u8; header.record_size()],
+            header,
+            metrics: None,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Count this reader's disk reads in `metrics`.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// The decoded file header.
+    pub fn header(&self) -> &NodeFileHeader {
+        &self.header
+    }
+
+    /// Index of the root record, if the tree is not empty.
+    pub fn root(&self) -> Option<u64> {
+        self.header.root
+    }
+
+    /// Number of records in the file.
+    pub fn len(&self) -> u64 {
+        self.header.node_count
+    }
+
+    /// Check if the file holds no records.
+    pub fn is_empty(&self) -> bool {
+        self.header.node_count == 0
+    }
+
+    /// Read and decode the record at `index`.
+    pub fn read_node(&mut self, index: u64) -> io::Result<NodeRecord<P, T>> {
+        let offset = self.header.record_offset(index)?;
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(&mut self.record)?;
+        if let Some(metrics) = &self.metrics {
+            metrics.record_read(self.record.len() as u64);
+        }
+        Ok(NodeRecord::decode(&self.record))
+    }
+
+    /// Read the records of the top `levels` levels of the tree, breadth first, so they are in
+    /// the OS page cache before the first query arrives. Returns the number of records read.
+    ///
+    /// After a restart every query starts at the root, so the upper levels are the hottest
+    /// pages of the file; a handful of levels is usually enough to avoid first-query
+    /// latency spikes without reading the whole file.
+    pub fn warm_up(&mut self, levels: usize) -> io::Result<u64> {
+        let mut level: Vec<u64> = self.header.root.into_iter().collect();
+        let mut read = 0;
+        for _ in 0..levels {
+            let mut next = Vec::with_capacity(level.len() * 2);
+            for index in level {
+                let record = self.read_node(index)?;
+                next.extend(record.left);
+                next.extend(record.right);
+                read += 1;
+            }
+            if next.is_empty() {
+                break;
+            }
+            level = next;
+        }
+        Ok(read)
+    }
+
+    /// Read every record a search for `query` would visit, warming one region of the tree
+    /// (e.g. the area a service expects most traffic for). Returns the number of records read.
+    pub fn warm_up_region<Q: SpatialQuery<P>>(&mut self, query: &Q) -> io::Result<u64> {
+        let mut stack: Vec<(u64, usize)> =
+            self.header.root.map(|root| (root, 0)).into_iter().collect();
+        let mut read = 0;
+        while let Some((node, depth)) = stack.pop() {
+            let record = self.read_node(node)?;
+            read += 1;
+            let (visit_left, visit_right) = children_to_visit(&record.point, query, depth);
+            if visit_right {
+                stack.extend(record.right.map(|child| (child, depth + 1)));
+            }
+            if visit_left {
+                stack.extend(record.left.map(|child| (child, depth + 1)));
+            }
+        }
+        Ok(read)
+    }
+
+    /// Read only the child links of the record at `index`, without decoding its point or data.
+    pub fn read_links(&mut self, index: u64) -> io::Result<(Option<u64>, Option<u64>)> {
+        let offset = self.header.record_offset(index)?;
+        let mut links = [0u8; 16];
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(&mut links)?;
+        if let Some(metrics) = &self.metrics {
+            metrics.record_read(links.len() as u64);
+        }
+        Ok((decode_link(&links[0..8]), decode_link(&links[8..16])))
+    }
+
+    /// Compute the bounds of every subtree in one sequential pass over the records.
+    pub fn subtree_bounds(&mut self) -> io::Result<SubtreeBounds<u64>> {
+        let mut records = Vec::with_capacity(self.header.node_count as usize);
+        for index in 0..self.header.node_count {
+            let record = self.read_node(index)?;
+            records.push((record.point, record.left, record.right));
+        }
+
+        let mut summary = SubtreeBounds::empty(self.header.dimensions as usize);
+        let mut stack: Vec<(u64, bool)> = self
+            .header
+            .root
+            .map(|root| (root, false))
+            .into_iter()
+            .collect();
+        while let Some((node, children_done)) = stack.pop() {
+            let (point, left, right) = records
+                .get(node as usize)
+                .ok_or_else(|| invalid_data("child link out of range"))?;
+            if children_done {
+                summary.insert(node, point, left.iter().chain(right).copied());
+            } else {
+                stack.push((node, true));
+                stack.extend(right.map(|child| (child, false)));
+                stack.extend(left.map(|child| (child, false)));
+            }
+        }
+        Ok(summary)
+    }
+
+    /// Spatial search over the file, pruned and shortcut by subtree summaries.
+    ///
+    /// Returns the same record indices as `spatial_search` over the loaded tree, in the same
+    /// order. Subtrees inside the query are collected by following links alone: only the
+    /// 16 link bytes of each of their records are read, and no point is decoded.
+    pub fn search_summarized<Q: SpatialQuery<P>>(
+        &mut self,
+        query: &Q,
+        summary: &SubtreeBounds<u64>,
+    ) -> io::Result<Vec<u64>> {
+        let mut results = Vec::new();
+        let mut stack: Vec<(u64, usize)> =
+            self.header.root.map(|root| (root, 0)).into_iter().collect();
+        while let Some((node, depth)) = stack.pop() {
+            let relation = summary
+                .get(node)
+                .map_or(Relation::CellCrossesQuery, |(min, max)| {
+                    query.relate(min, max)
+                });
+            match relation {
+                Relation::CellOutsideQuery => continue,
+                Relation::CellInsideQuery => {
+                    self.collect_subtree(node, &mut results)?;
+                    continue;
+                }
+                Relation::CellCrossesQuery => {}
+            }
+
+            let record = self.read_node(node)?;
+            if query.matches(&record.point) {
+                results.push(node);
+            }
+            let (visit_left, visit_right) = children_to_visit(&record.point, query, depth);
+            if visit_right {
+                stack.extend(record.right.map(|child| (child, depth + 1)));
+            }
+            if visit_left {
+                stack.extend(record.left.map(|child| (child, depth + 1)));
+            }
+        }
+        Ok(results)
+    }
+
+    /// Append every record of the subtree rooted at `node` in pre-order, reading links only.
+    fn collect_subtree(&mut self, node: u64, results: &mut Vec<u64>) -> io::Result<()> {
+        let mut stack = vec![node];
+        while let Some(node) = stack.pop() {
+            results.push(node);
+            let (left, right) = self.read_links(node)?;
+            stack.extend(right);
+            stack.extend(left);
+        }
+        Ok(())
+    }
+
+    /// Load the whole tree into a `NodeArena`, returning the arena and its root index.
+    /// Record `i` becomes arena index `i`, so links carry over unchanged.
+    pub fn load_arena(&mut self) -> io::Result<(NodeArena<P, T>, Option<usize>)> {
+        let mut arena = NodeArena::with_capacity(self.header.node_count as usize);
+        for index in 0..self.header.node_count {
+            let record = self.read_node(index)?;
+            let node = arena.allocate(record.point, record.data);
+            let node = arena.get_mut(node);
+            node.left = record.left.map(|child| child as usize);
+            node.right = record.right.map(|child| child as usize);
+        }
+        Ok((arena, self.header.root.map(|root| root as usize)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::{BuildOptions, bulk_build};
+    use crate::quantize::Quantizer;
+    use crate::search::spatial_search;
+    use crate::spatial::BoundingBox;
+    use crate::storage::{ArenaView, InMemoryLinker};
+
+    #[test]
+    fn test_node_file_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.bkd");
+
+        let mut writer = NodeFileWriter::<BoundingBox, u32>::create(&path).unwrap();
+        writer
+            .push(&BoundingBox::new(5.0, 5.0, 6.0, 6.0), &1, Some(1), None)
+            .unwrap();
+        writer
+            .push(&BoundingBox::new(1.0, 1.0, 2.0, 2.0), &2, None, None)
+            .unwrap();
+        writer.finish(Some(0)).unwrap();
+
+        let mut reader = NodeFileReader::<BoundingBox, u32>::open(&path).unwrap();
+        assert_eq!(reader.len(), 2);
+        assert_eq!(reader.root(), Some(0));
+        assert_eq!(reader.header().dimensions, 4);
+
+        let record = reader.read_node(1).unwrap();
+        assert_eq!(record.point, BoundingBox::new(1.0, 1.0, 2.0, 2.0));
+        assert_eq!(record.data, 2);
+
+        let metrics = Arc::new(Metrics::new());
+        let mut reader = reader.with_metrics(metrics.clone());
+        let (arena, root) = reader.load_arena().unwrap();
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.disk_reads, 2);
+        assert_eq!(
+            snapshot.bytes_read,
+            2 * reader.header().record_size() as u64
+        );
+        assert_eq!(snapshot.cache_hit_rate(), None);
+        assert_eq!(root, Some(0));
+        assert_eq!(arena.get(0).left, Some(1));
+        assert_eq!(arena.get(0).right, None);
+
+        assert!(NodeFileReader::<BoundingBox, u64>::open(&path).is_err());
+
+        let copy = dir.path().join("copy.bkd");
+        write_arena(&copy, &arena, root).unwrap();
+        assert_eq!(std::fs::read(&copy).unwrap(), std::fs::read(&path).unwrap());
+    }
+
+    #[test]
+    fn test_summarized_file_search_reads_links_only_inside_query() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.bkd");
+        let mut arena = NodeArena::new();
+        let mut nodes: Vec<usize> = (0..200)
+            .map(|i| {
+                let x = ((i * 37) % 101) as f64;
+                let y = ((i * 53) % 97) as f64;
+                arena.allocate(BoundingBox::new(x, y, x + 2.0, y + 1.0), i as u32)
+            })
+            .collect();
+        let mut linker = InMemoryLinker::new(&mut arena);
+        let root = bulk_build(&mut linker, &mut nodes, 0, &BuildOptions::default()).unwrap();
+        write_arena(&path, &arena, root).unwrap();
+        let linker = ArenaView::new(&arena);
+
+        let metrics = Arc::new(Metrics::new());
+        let mut reader = NodeFileReader::<BoundingBox, u32>::open(&path)
+            .unwrap()
+            .with_metrics(metrics.clone());
+        let summary = reader.subtree_bounds().unwrap();
+        for query in [
+            BoundingBox::new(10.0, 10.0, 40.0, 30.0),
+            BoundingBox::new(200.0, 200.0, 300.0, 300.0),
+        ] {
+            let expected: Vec<u64> = spatial_search(&linker, root, &query, 0)
+                .into_iter()
+                .map(|node| node as u64)
+                .collect();
+            assert_eq!(
+                reader.search_summarized(&query, &summary).unwrap(),
+                expected
+            );
+        }
+
+        // A query covering the whole tree reads 16 link bytes per record and no points
+        metrics.reset();
+        let everything = BoundingBox::new(-1.0, -1.0, 1000.0, 1000.0);
+        let results = reader.search_summarized(&everything, &summary).unwrap();
+        assert_eq!(results.len(), 200);
+        assert_eq!(metrics.snapshot().bytes_read, 200 * 16);
+    }
+
+    #[test]
+    fn test_warm_up_reads_upper_levels() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.bkd");
+        let mut arena = NodeArena::new();
+        let mut nodes: Vec<usize> = (0..100)
+            .map(|i| arena.allocate(BoundingBox::new(i as f64, 0.0, i as f64 + 1.0, 1.0), i))
+            .collect();
+        let mut linker = InMemoryLinker::new(&mut arena);
+        let root = bulk_build(&mut linker, &mut nodes, 0, &BuildOptions::default()).unwrap();
+        write_arena(&path, &arena, root).unwrap();
+
+        let metrics = Arc::new(Metrics::new());
+        let mut reader = NodeFileReader::<BoundingBox, u32>::open(&path)
+            .unwrap()
+            .with_metrics(metrics.clone());
+        assert_eq!(reader.warm_up(0).unwrap(), 0);
+        assert_eq!(reader.warm_up(3).unwrap(), 7);
+        assert_eq!(reader.warm_up(64).unwrap(), 100);
+        assert_eq!(metrics.snapshot().disk_reads, 107);
+
+        let visited = reader
+            .warm_up_region(&BoundingBox::new(10.0, 0.0, 12.0, 1.0))
+            .unwrap();
+        assert!(visited > 0 && visited < 100);
+    }
+
+    #[test]
+    fn test_readers_reject_unknown_format_features() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.bkd");
+        let point = BoundingBox::new(0.0, 0.0, 1.0, 1.0);
+        let mut writer = NodeFileWriter::<BoundingBox, u32>::create(&path)
+            .unwrap()
+            .with_features(FormatFeatures::QUANTIZED);
+        writer.push(&point, &1, None, None).unwrap();
+        writer.finish(Some(0)).unwrap();
+        let reader = NodeFileReader::<BoundingBox, u32>::open(&path).unwrap();
+        assert_eq!(reader.header().features, FormatFeatures::QUANTIZED);
+
+        // A feature from a newer writer, say compression, plus a bit nobody has named yet
+        let mut bytes = std::fs::read(&path).unwrap();
+        let features = FormatFeatures::COMPRESSED
+            .union(FormatFeatures::QUANTIZED)
+            .union(FormatFeatures::from_bits(1 << 40));
+        features.bits().encode(&mut bytes[48..56]);
+        std::fs::write(&path, bytes).unwrap();
+        let error = NodeFileReader::<BoundingBox, u32>::open(&path)
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+        assert!(
+            error.to_string().ends_with("compressed, 0x10000000000"),
+            "{error}"
+        );
+        assert_eq!(FormatFeatures::NONE.to_string(), "none");
+    }
+
+    #[test]
+    fn test_file_bytes_are_platform_independent() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.bkd");
+        let mut writer = NodeFileWriter::<(f64, f64), u16>::create(&path).unwrap();
+        writer.push(&(1.0, -2.0), &0x0102, None, None).unwrap();
+        writer.finish(Some(0)).unwrap();
+
+        let mut record = vec![0xff; 16]; // no children
+        record.extend([0, 0, 0, 0, 0, 0, 0xf0, 0x3f]); // 1.0
+        record.extend([0, 0, 0, 0, 0, 0, 0, 0xc0]); // -2.0
+        record.extend([0x02, 0x01, 0, 0, 0, 0, 0, 0]); // payload, padding
+        let mut checksum = Fnv1a(Fnv1a::OFFSET_BASIS);
+        checksum.write(&record);
+        let mut expected = b"BKDNODES".to_vec();
+        expected.extend([1, 0, 0, 0, 2, 0, 0, 0, 16, 0, 0, 0, 2, 0, 0, 0]); // version, sizes
+        expected.extend([1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]); // count, root
+        expected.extend(checksum.0.to_le_bytes());
+        expected.extend([0; 16]); // features, reserved
+        expected.extend(&record);
+        assert_eq!(std::fs::read(&path).unwrap(), expected);
+
+        // Bytes spelled out by hand read back the same on any target
+        std::fs::write(&path, &expected).unwrap();
+        let mut reader = NodeFileReader::<(f64, f64), u16>::open(&path).unwrap();
+        let node = reader.read_node(0).unwrap();
+        assert_eq!((node.point, node.data), ((1.0, -2.0), 0x0102));
+    }
+
+    #[test]
+    fn test_records_round_trip_across_layouts() {
+        fn round_trip<P, T>(point: P, data: T, record_size: usize)
+        where
+            P: Point + FixedCodec + Clone + PartialEq + std::fmt::Debug,
+            T: FixedCodec + Clone + PartialEq + std::fmt::Debug,
+        {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("tree.bkd");
+            let mut writer = NodeFileWriter::<P, T>::create(&path).unwrap();
+            writer.push(&point, &data, Some(1), None).unwrap();
+            writer.push(&point, &data, None, None).unwrap();
+            writer.finish(Some(0)).unwrap();
+
+            let mut reader = NodeFileReader::<P, T>::open(&path).unwrap();
+            assert_eq!(reader.header().record_size(), record_size);
+            let node = reader.read_node(1).unwrap();
+            assert_eq!((node.point, node.data), (point, data));
+            assert_eq!(reader.read_links(0).unwrap(), (Some(1), None));
+        }
+
+        round_trip(BoundingBox::new(-1.0, 2.0, 3.0, 4.0), u64::MAX, 56);
+        round_trip(BoundingBox::new(0.5, 0.5, 1.5, 1.5), -7i8, 56);
+        round_trip([1.0, -0.0, f64::MIN_POSITIVE], (3u32, -4i16), 48);
+        round_trip((f64::MAX, f64::NEG_INFINITY), 1.25f32, 40);
+        let quantizer = Quantizer::<u16>::new(&[(0.0, 10.0), (0.0, 10.0)]);
+        round_trip(quantizer.point::<2, _>(&[2.5, 7.5]), [9u8; 3], 24);
+    }
+}
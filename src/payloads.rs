@@ -0,0 +1,208 @@
+//! Nodes carrying several payloads, for entries that share one point.
+//!
+//! Deduplicated geometries often back many documents: one bounding box, thousands of doc
+//! ids. Indexing each pair as its own node repeats the point and deepens the tree with
+//! entries no split can separate. With `Payloads<T>` as the node data, `insert_or_append`
+//! keeps one node per distinct point and appends further payloads to it.
+
+use crate::search::insert_node;
+use crate::spatial::Point;
+use crate::storage::{InMemoryLinker, NodeArena};
+use std::ops::Deref;
+
+/// One or more payloads, held inline while there is only one.
+///
+/// Dereferences to a slice in insertion order; it is never empty.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Payloads<T>(Repr<T>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Repr<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+impl<T> Payloads<T> {
+    /// Create a list holding one payload.
+    pub fn new(first: T) -> Self {
+        Payloads(Repr::One(first))
+    }
+
+    /// Append a payload, moving the list to the heap if it held only one.
+    pub fn push(&mut self, data: T) {
+        match &mut self.0 {
+            Repr::Many(payloads) => payloads.push(data),
+            Repr::One(_) => {
+                let Repr::One(first) = std::mem::replace(&mut self.0, Repr::Many(Vec::new()))
+                else {
+                    unreachable!("checked by the match");
+                };
+                self.0 = Repr::Many(vec![first, data]);
+            }
+        }
+    }
+
+    /// The payloads as a vector, in insertion order.
+    pub fn into_vec(self) -> Vec<T> {
+        match self.0 {
+            Repr::One(first) => vec![first],
+            Repr::Many(payloads) => payloads,
+        }
+    }
+}
+
+impl<T> Deref for Payloads<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        match &self.0 {
+            Repr::One(first) => std::slice::from_ref(first),
+            Repr::Many(payloads) => payloads,
+        }
+    }
+}
+
+impl<T> From<T> for Payloads<T> {
+    fn from(first: T) -> Self {
+        Payloads::new(first)
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Payloads<T> {
+    type Item = &'a T;
+    type IntoIter = std::slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Append `data` to the node whose point has exactly the coordinates of `point`, or insert
+/// a new node for it as `insert_node` would. Returns the root.
+///
+/// Coordinates compare with `==`, so `0.0` and `-0.0` are one point and a point with a NaN
+/// coordinate always gets its own node. The lookup follows the path `insert_node` takes,
+/// and where the point equals a split value it searches both children, as searches do: a
+/// bulk-built tree may place equal values on either side of a split, so duplicates in
+/// bulk-loaded trees are found too.
+pub fn insert_or_append<P: Point, T>(
+    arena: &mut NodeArena<P, Payloads<T>>,
+    root: Option<usize>,
+    point: P,
+    data: T,
+    depth: usize,
+) -> usize {
+    let mut stack: Vec<(usize, usize)> = root.map(|root| (root, depth)).into_iter().collect();
+    while let Some((node, level)) = stack.pop() {
+        let existing = arena.get(node);
+        if same_coordinates(&existing.point, &point) {
+            arena.get_mut(node).data.push(data);
+            return root.expect("a node was found, so the tree has a root");
+        }
+        let dimension = level % point.dimensions();
+        let (value, split) = (
+            point.get_dimension(dimension),
+            existing.point.get_dimension(dimension),
+        );
+        if value >= split {
+            stack.extend(existing.right.map(|right| (right, level + 1)));
+        }
+        if value <= split {
+            stack.extend(existing.left.map(|left| (left, level + 1)));
+        }
+    }
+
+    let node = arena.allocate(point, Payloads::new(data));
+    let mut linker = InMemoryLinker::new(arena);
+    insert_node(&mut linker, root, node, depth)
+}
+
+fn same_coordinates<P: Point>(a: &P, b: &P) -> bool {
+    a.dimensions() == b.dimensions()
+        && (0..a.dimensions()).all(|dim| a.get_dimension(dim) == b.get_dimension(dim))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::{BuildOptions, bulk_build};
+    use crate::search::spatial_search;
+    use crate::spatial::BoundingBox;
+    use crate::storage::NodeLinker;
+
+    #[test]
+    fn test_payloads_grow_in_order() {
+        let mut payloads = Payloads::new(1);
+        assert_eq!(&*payloads, &[1]);
+        payloads.push(2);
+        payloads.push(3);
+        assert_eq!(payloads.len(), 3);
+        assert_eq!(payloads.iter().sum::<i32>(), 6);
+        assert_eq!(payloads.into_vec(), [1, 2, 3]);
+        assert_eq!(Payloads::from("a").first(), Some(&"a"));
+    }
+
+    #[test]
+    fn test_insert_or_append_keeps_one_node_per_point() {
+        let mut arena = NodeArena::new();
+        let mut root = None;
+        let shapes = [
+            BoundingBox::new(0.0, 0.0, 1.0, 1.0),
+            BoundingBox::new(5.0, 5.0, 6.0, 6.0),
+            BoundingBox::new(2.0, 2.0, 3.0, 3.0),
+            BoundingBox::new(0.0, 0.0, 1.0, 2.0),
+        ];
+        for doc in 0..40u32 {
+            let shape = shapes[(doc % 4) as usize].clone();
+            root = Some(insert_or_append(&mut arena, root, shape, doc, 0));
+        }
+        assert_eq!(arena.len(), 4);
+        assert_eq!(root, Some(0));
+
+        let linker = InMemoryLinker::new(&mut arena);
+        let query = BoundingBox::new(-1.0, -1.0, 0.5, 0.5);
+        let mut docs: Vec<u32> = spatial_search(&linker, root, &query, 0)
+            .into_iter()
+            .flat_map(|node| linker.get_data(node).iter().copied())
+            .collect();
+        docs.sort_unstable();
+        let expected: Vec<u32> = (0..40).filter(|doc| doc % 4 == 0 || doc % 4 == 3).collect();
+        assert_eq!(docs, expected);
+        assert_eq!(linker.get_data(1).len(), 10);
+        assert!(linker.get_data(1).iter().all(|doc| doc % 4 == 1));
+    }
+
+    #[test]
+    fn test_insert_or_append_finds_duplicates_in_bulk_built_trees() {
+        // Most entries share xmin, the first split dimension, so the median split leaves
+        // entries equal to it on both sides
+        let points: Vec<BoundingBox> = (0..60)
+            .map(|i| {
+                let x = [0.0, 1.0, 1.0, 1.0, 2.0][i % 5];
+                let y = (i * 7 % 60) as f64;
+                BoundingBox::new(x, y, x + 1.0, y + 1.0)
+            })
+            .collect();
+        let mut arena = NodeArena::new();
+        let mut nodes: Vec<usize> = points
+            .iter()
+            .enumerate()
+            .map(|(i, point)| arena.allocate(point.clone(), Payloads::new(i)))
+            .collect();
+        let mut linker = InMemoryLinker::new(&mut arena);
+        let mut root = bulk_build(&mut linker, &mut nodes, 0, &BuildOptions::default()).unwrap();
+        for (i, point) in points.iter().enumerate() {
+            root = Some(insert_or_append(
+                &mut arena,
+                root,
+                point.clone(),
+                100 + i,
+                0,
+            ));
+        }
+        assert_eq!(arena.len(), 60);
+        for node in 0..60 {
+            assert_eq!(arena.get(node).data.as_ref(), [node, 100 + node]);
+        }
+    }
+}
@@ -0,0 +1,317 @@
+//! Coordinate reference system (CRS) transforms applied at index and query time.
+
+use crate::index::SpatialIndex;
+use crate::query::{Relation, SpatialQuery};
+use crate::spatial::BoundingBox;
+
+/// Transform between a source CRS (what callers hold) and the index CRS (what is stored).
+///
+/// # Design Principle: Transform at the boundary, search in one CRS
+/// The tree only ever sees index-CRS coordinates. `ProjectedIndex` passes entries through
+/// `forward_box` on insert and maps them back with `inverse_box`, and `Projected` does the
+/// same for queries, so callers work in the source CRS and never transform by hand.
+///
+/// The default box transforms project the four corners and take their envelope, which is
+/// exact for projections that are monotonic per axis (Web Mercator, equirectangular, ...).
+/// Projections that bend straight lines should override them with a denser sampling.
+pub trait Projection {
+    /// Map a coordinate from the source CRS into the index CRS.
+    fn forward(&self, x: f64, y: f64) -> (f64, f64);
+
+    /// Map a coordinate from the index CRS back into the source CRS.
+    fn inverse(&self, x: f64, y: f64) -> (f64, f64);
+
+    /// Map a source-CRS box into the index CRS.
+    fn forward_box(&self, bbox: &BoundingBox) -> BoundingBox {
+        envelope([
+            self.forward(bbox.xmin, bbox.ymin),
+            self.forward(bbox.xmin, bbox.ymax),
+            self.forward(bbox.xmax, bbox.ymin),
+            self.forward(bbox.xmax, bbox.ymax),
+        ])
+    }
+
+    /// Map an index-CRS box back into the source CRS.
+    fn inverse_box(&self, bbox: &BoundingBox) -> BoundingBox {
+        envelope([
+            self.inverse(bbox.xmin, bbox.ymin),
+            self.inverse(bbox.xmin, bbox.ymax),
+            self.inverse(bbox.xmax, bbox.ymin),
+            self.inverse(bbox.xmax, bbox.ymax),
+        ])
+    }
+}
+
+/// Smallest box containing all given corners.
+fn envelope(corners: [(f64, f64); 4]) -> BoundingBox {
+    let mut bbox = BoundingBox::new(corners[0].0, corners[0].1, corners[0].0, corners[0].1);
+    for &(x, y) in &corners[1..] {
+        bbox = bbox.union(&BoundingBox::new(x, y, x, y));
+    }
+    bbox
+}
+
+/// Source-CRS box query run against an index in another CRS.
+///
+/// The box is projected once, on construction; the search then runs on index-CRS cells
+/// and entries exactly as the projected box would.
+///
+/// # Usage pattern:
+/// ```rust
+/// use bkd::BoundingBox;
+/// use bkd::projection::{Projected, Projection, WebMercator};
+///
+/// let europe = Projected::new(&WebMercator, BoundingBox::new(-10.0, 35.0, 30.0, 60.0));
+/// assert_eq!(*europe.source(), BoundingBox::new(-10.0, 35.0, 30.0, 60.0));
+/// assert_eq!(*europe.projected(), WebMercator.forward_box(europe.source()));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Projected {
+    source: BoundingBox,
+    projected: BoundingBox,
+}
+
+impl Projected {
+    /// Project `source` into the index CRS of `projection`.
+    pub fn new(projection: &(impl Projection + ?Sized), source: BoundingBox) -> Self {
+        let projected = projection.forward_box(&source);
+        Projected { source, projected }
+    }
+
+    /// The query as given, in the source CRS.
+    pub fn source(&self) -> &BoundingBox {
+        &self.source
+    }
+
+    /// The query in the index CRS.
+    pub fn projected(&self) -> &BoundingBox {
+        &self.projected
+    }
+}
+
+impl SpatialQuery<BoundingBox> for Projected {
+    fn dimension_range(&self, dim: usize) -> (f64, f64) {
+        self.projected.dimension_range(dim)
+    }
+
+    fn matches(&self, point: &BoundingBox) -> bool {
+        self.projected.matches(point)
+    }
+
+    fn relate(&self, min: &[f64], max: &[f64]) -> Relation {
+        self.projected.relate(min, max)
+    }
+}
+
+/// A `SpatialIndex` of boxes stored in the index CRS of a projection, inserted and searched
+/// in its source CRS.
+///
+/// # Usage pattern:
+/// ```rust
+/// use bkd::BoundingBox;
+/// use bkd::projection::{ProjectedIndex, WebMercator};
+///
+/// // Longitude/latitude in, Web Mercator meters stored
+/// let mut index = ProjectedIndex::new(WebMercator);
+/// let paris = index.insert(BoundingBox::new(2.25, 48.81, 2.42, 48.90), "paris");
+/// index.insert(BoundingBox::new(139.56, 35.53, 139.92, 35.82), "tokyo");
+///
+/// let found = index.search(&BoundingBox::new(-10.0, 35.0, 30.0, 60.0));
+/// assert_eq!(found, [paris]);
+/// assert_eq!(*index.get_data(paris), "paris");
+/// assert!((index.bounds(paris).xmin - 2.25).abs() < 1e-9);
+/// ```
+pub struct ProjectedIndex<Pr, T> {
+    projection: Pr,
+    index: SpatialIndex<BoundingBox, T>,
+}
+
+impl<Pr: Projection, T> ProjectedIndex<Pr, T> {
+    /// Create an empty index storing entries in the index CRS of `projection`.
+    pub fn new(projection: Pr) -> Self {
+        ProjectedIndex {
+            projection,
+            index: SpatialIndex::new(),
+        }
+    }
+
+    /// Insert a source-CRS box and return its handle.
+    pub fn insert(&mut self, bbox: BoundingBox, data: T) -> usize {
+        let projected = self.projection.forward_box(&bbox);
+        self.index.insert(projected, data)
+    }
+
+    /// Handles of all entries overlapping the source-CRS box `query`.
+    pub fn search(&self, query: &BoundingBox) -> Vec<usize> {
+        self.index
+            .search(&Projected::new(&self.projection, query.clone()))
+    }
+
+    /// Bounds of an entry, mapped back into the source CRS.
+    pub fn bounds(&self, node: usize) -> BoundingBox {
+        self.projection
+            .inverse_box(self.index.arena().get(node).get_point())
+    }
+
+    /// Payload of an entry.
+    pub fn get_data(&self, node: usize) -> &T {
+        self.index.arena().get(node).get_data()
+    }
+
+    /// Number of entries.
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Check if the index holds no entries.
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// The projection entries and queries go through.
+    pub fn projection(&self) -> &Pr {
+        &self.projection
+    }
+
+    /// The underlying index, in index-CRS coordinates, for the crate's other algorithms.
+    pub fn index(&self) -> &SpatialIndex<BoundingBox, T> {
+        &self.index
+    }
+}
+
+/// No-op projection: source and index CRS are the same.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Identity;
+
+impl Projection for Identity {
+    fn forward(&self, x: f64, y: f64) -> (f64, f64) {
+        (x, y)
+    }
+
+    fn inverse(&self, x: f64, y: f64) -> (f64, f64) {
+        (x, y)
+    }
+
+    fn forward_box(&self, bbox: &BoundingBox) -> BoundingBox {
+        bbox.clone()
+    }
+
+    fn inverse_box(&self, bbox: &BoundingBox) -> BoundingBox {
+        bbox.clone()
+    }
+}
+
+/// Spherical Web Mercator: WGS84 longitude/latitude degrees (EPSG:4326) to meters (EPSG:3857).
+///
+/// Latitudes are clamped to `±MAX_LATITUDE`, where the projection becomes square; beyond it
+/// y diverges to infinity.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WebMercator;
+
+impl WebMercator {
+    /// Radius of the spherical earth model used by EPSG:3857, in meters.
+    pub const EARTH_RADIUS: f64 = 6_378_137.0;
+
+    /// Latitude limit of the projection, in degrees.
+    pub const MAX_LATITUDE: f64 = 85.051_128_779_806_59;
+}
+
+impl Projection for WebMercator {
+    fn forward(&self, lon: f64, lat: f64) -> (f64, f64) {
+        let lat = lat.clamp(-Self::MAX_LATITUDE, Self::MAX_LATITUDE);
+        let x = Self::EARTH_RADIUS * lon.to_radians();
+        let y = Self::EARTH_RADIUS
+            * (std::f64::consts::FRAC_PI_4 + lat.to_radians() / 2.0)
+                .tan()
+                .ln();
+        (x, y)
+    }
+
+    fn inverse(&self, x: f64, y: f64) -> (f64, f64) {
+        let lon = (x / Self::EARTH_RADIUS).to_degrees();
+        let lat = (2.0 * (y / Self::EARTH_RADIUS).exp().atan() - std::f64::consts::FRAC_PI_2)
+            .to_degrees();
+        (lon, lat)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::{insert_node, spatial_search};
+    use crate::storage::{InMemoryLinker, NodeArena, NodeLinker};
+
+    #[test]
+    fn test_web_mercator_known_values() {
+        let (x, y) = WebMercator.forward(180.0, 0.0);
+        assert!((x - 20_037_508.342_789_244).abs() < 1e-6);
+        assert!(y.abs() < 1e-6);
+
+        let (_, y) = WebMercator.forward(0.0, WebMercator::MAX_LATITUDE);
+        assert!((y - 20_037_508.342_789_244).abs() < 1e-3);
+
+        let (x, y) = WebMercator.forward(-122.4, 37.8);
+        let (lon, lat) = WebMercator.inverse(x, y);
+        assert!((lon + 122.4).abs() < 1e-9);
+        assert!((lat - 37.8).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_index_and_query_through_projection() {
+        let projection = WebMercator;
+        let paris = BoundingBox::new(2.25, 48.81, 2.42, 48.90);
+        let tokyo = BoundingBox::new(139.56, 35.53, 139.92, 35.82);
+
+        let mut arena = NodeArena::new();
+        let paris_ref = arena.allocate(projection.forward_box(&paris), "paris");
+        let tokyo_ref = arena.allocate(projection.forward_box(&tokyo), "tokyo");
+
+        let mut linker = InMemoryLinker::new(&mut arena);
+        let root = insert_node(&mut linker, None, paris_ref, 0);
+        insert_node(&mut linker, Some(root), tokyo_ref, 0);
+
+        let europe = projection.forward_box(&BoundingBox::new(-10.0, 35.0, 30.0, 60.0));
+        assert_eq!(
+            spatial_search(&linker, Some(root), &europe, 0),
+            vec![paris_ref]
+        );
+
+        let restored = projection.inverse_box(linker.get_point(tokyo_ref));
+        assert!((restored.xmin - tokyo.xmin).abs() < 1e-9);
+        assert!((restored.ymax - tokyo.ymax).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_projected_index_takes_source_coordinates() {
+        let mut index = ProjectedIndex::new(WebMercator);
+        let mut source = Vec::new();
+        for i in 0..200u32 {
+            let lon = f64::from(i * 37 % 360) - 180.0;
+            let lat = f64::from(i * 53 % 160) - 80.0;
+            let bbox = BoundingBox::new(lon, lat, lon + 0.5, lat + 0.5);
+            assert_eq!(index.insert(bbox.clone(), i), i as usize);
+            source.push(bbox);
+        }
+        assert_eq!(index.len(), 200);
+
+        for query in [
+            BoundingBox::new(-10.0, 35.0, 30.0, 60.0),
+            BoundingBox::new(100.0, -70.0, 170.0, 10.0),
+            BoundingBox::new(-180.0, -85.0, 180.0, 85.0),
+        ] {
+            let mut found = index.search(&query);
+            found.sort_unstable();
+            let expected: Vec<usize> = (0..200).filter(|&i| query.matches(&source[i])).collect();
+            assert!(!expected.is_empty());
+            assert_eq!(found, expected);
+        }
+
+        // Stored in meters, handed back in degrees
+        let stored = index.index().arena().get(5).get_point();
+        assert!(stored.xmax > 1000.0);
+        let bounds = index.bounds(5);
+        assert!((bounds.xmin - source[5].xmin).abs() < 1e-9);
+        assert!((bounds.ymax - source[5].ymax).abs() < 1e-9);
+        assert_eq!(*index.get_data(5), 5);
+    }
+}
@@ -0,0 +1,291 @@
+//! Fixed-point quantization of coordinates for compact persisted trees.
+//!
+//! A `Quantizer` maps each dimension's `[min, max]` range onto the `u16` or `u32` codes, in
+//! equal steps. Codes are unsigned and sort like the values they stand for, so a tree over
+//! `QuantizedPoint`s splits and prunes in code space exactly as it would over the original
+//! coordinates. A `QuantizedPoint<u32, N>` takes half the bytes of `N` `f64`s and a
+//! `QuantizedPoint<u16, N>` a quarter, in every format written through `FixedCodec`: node
+//! files, block trees and memory-mapped arenas.
+//!
+//! # Conservative rounding
+//! Values and query bounds are both rounded down to the step containing them, with the
+//! same arithmetic, and values outside the range are clamped to the first or last code.
+//! The mapping never reverses the order of two values, so an entry inside a query range is
+//! inside the quantized range too: pruning never loses a match. The quantized query may
+//! also match entries up to one step outside the original range; refine with the original
+//! coordinates when that matters.
+//!
+//! ```rust
+//! use bkd::RangeQuery;
+//! use bkd::quantize::{QuantizedPoint, Quantizer};
+//!
+//! let quantizer = Quantizer::<u16>::new(&[(-180.0, 180.0), (-90.0, 90.0)]);
+//! let paris: QuantizedPoint<u16, 2> = quantizer.point(&[2.3522, 48.8566]);
+//! let france = RangeQuery::new()
+//!     .with_range(0, -5.0, 10.0)
+//!     .with_range(1, 41.0, 51.5);
+//! let query = quantizer.query(&france);
+//! assert!(bkd::SpatialQuery::matches(&query, &paris));
+//! ```
+
+use crate::codec::{FixedCodec, ZeroCopy};
+use crate::query::RangeQuery;
+use crate::spatial::Point;
+use std::fmt::Debug;
+use std::hash::Hash;
+
+/// Unsigned integer type holding one quantized coordinate, `u16` or `u32`.
+pub trait Code: ZeroCopy + Copy + Ord + Hash + Debug {
+    /// Number of distinct codes, as the number of steps a range is divided into.
+    const STEPS: f64;
+
+    /// Code for a step number, which the caller keeps below `STEPS`.
+    fn from_step(step: f64) -> Self;
+
+    /// Step number of the code.
+    fn step(self) -> f64;
+}
+
+impl Code for u16 {
+    const STEPS: f64 = 65_536.0;
+
+    fn from_step(step: f64) -> Self {
+        step as u16
+    }
+
+    fn step(self) -> f64 {
+        f64::from(self)
+    }
+}
+
+impl Code for u32 {
+    const STEPS: f64 = 4_294_967_296.0;
+
+    fn from_step(step: f64) -> Self {
+        step as u32
+    }
+
+    fn step(self) -> f64 {
+        f64::from(self)
+    }
+}
+
+/// Per-dimension mapping between `f64` coordinates and codes of type `C`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quantizer<C> {
+    min: Vec<f64>,
+    /// Width of one step in each dimension.
+    step: Vec<f64>,
+    _code: std::marker::PhantomData<C>,
+}
+
+impl<C: Code> Quantizer<C> {
+    /// Create a quantizer for the given `(min, max)` range of each dimension.
+    ///
+    /// # Panics
+    /// Panics if a bound is not finite or a range is empty.
+    pub fn new(ranges: &[(f64, f64)]) -> Self {
+        assert!(
+            ranges
+                .iter()
+                .all(|&(min, max)| min.is_finite() && max.is_finite() && min < max),
+            "quantization ranges must be finite and non-empty"
+        );
+        Quantizer {
+            min: ranges.iter().map(|&(min, _)| min).collect(),
+            step: ranges
+                .iter()
+                .map(|&(min, max)| (max - min) / C::STEPS)
+                .collect(),
+            _code: std::marker::PhantomData,
+        }
+    }
+
+    /// Number of dimensions.
+    pub fn dimensions(&self) -> usize {
+        self.min.len()
+    }
+
+    /// Width of one step in `dim`: the precision of quantized coordinates.
+    pub fn step(&self, dim: usize) -> f64 {
+        self.step[dim]
+    }
+
+    /// Code of the step containing `value`, clamped to the range of `dim`.
+    pub fn code(&self, dim: usize, value: f64) -> C {
+        let step = ((value - self.min[dim]) / self.step[dim]).floor();
+        // NaN passes through the clamp and casts to the first code
+        C::from_step(step.clamp(0.0, C::STEPS - 1.0))
+    }
+
+    /// Inclusive lower and exclusive upper bound of the values with code `code` in `dim`.
+    pub fn bounds(&self, dim: usize, code: C) -> (f64, f64) {
+        let lower = self.min[dim] + code.step() * self.step[dim];
+        (lower, lower + self.step[dim])
+    }
+
+    /// Quantize the first `N` dimensions of a point.
+    ///
+    /// # Panics
+    /// Panics if the quantizer does not have exactly `N` dimensions.
+    pub fn point<const N: usize, P: Point>(&self, point: &P) -> QuantizedPoint<C, N> {
+        assert_eq!(
+            self.dimensions(),
+            N,
+            "quantizer dimensions do not match the point"
+        );
+        QuantizedPoint(std::array::from_fn(|dim| {
+            self.code(dim, point.get_dimension(dim))
+        }))
+    }
+
+    /// Map a range query onto codes, rounding both ends of every range down like the
+    /// values they are compared with. Unconstrained dimensions stay unconstrained.
+    pub fn query(&self, query: &RangeQuery) -> RangeQuery {
+        (0..self.dimensions()).fold(RangeQuery::new(), |quantized, dim| match query.range(dim) {
+            Some((min, max)) => {
+                quantized.with_range(dim, self.code(dim, min).step(), self.code(dim, max).step())
+            }
+            None => quantized,
+        })
+    }
+}
+
+/// A point stored as one code per dimension; `get_dimension` returns the code.
+///
+/// Search it with queries mapped by `Quantizer::query`, and read coordinates back with
+/// `Quantizer::bounds`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct QuantizedPoint<C, const N: usize>(pub [C; N]);
+
+impl<C: Code, const N: usize> Point for QuantizedPoint<C, N> {
+    fn get_dimension(&self, dim: usize) -> f64 {
+        self.0[dim].step()
+    }
+
+    fn dimensions(&self) -> usize {
+        N
+    }
+}
+
+/// Encoded as the codes in dimension order, `C::SIZE` bytes each.
+impl<C: Code, const N: usize> FixedCodec for QuantizedPoint<C, N> {
+    const SIZE: usize = N * C::SIZE;
+
+    fn encode(&self, buf: &mut [u8]) {
+        for (code, chunk) in self.0.iter().zip(buf.chunks_exact_mut(C::SIZE)) {
+            code.encode(chunk);
+        }
+    }
+
+    fn decode(buf: &[u8]) -> Self {
+        QuantizedPoint(std::array::from_fn(|dim| {
+            C::decode(&buf[dim * C::SIZE..(dim + 1) * C::SIZE])
+        }))
+    }
+}
+
+// SAFETY: `QuantizedPoint` is `repr(transparent)` over an array of codes, which are
+// zero-copy primitives with no padding between them, laid out in encoding order.
+unsafe impl<C: Code, const N: usize> ZeroCopy for QuantizedPoint<C, N> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_tree::{BkdReader, BkdWriter, BkdWriterOptions};
+    use crate::query::SpatialQuery;
+
+    #[test]
+    fn test_codes_are_monotonic_and_clamped() {
+        let quantizer = Quantizer::<u16>::new(&[(0.0, 1.0)]);
+        assert_eq!(quantizer.code(0, 0.0), 0);
+        assert_eq!(quantizer.code(0, 1.0), u16::MAX);
+        assert_eq!(quantizer.code(0, -5.0), 0);
+        assert_eq!(quantizer.code(0, 5.0), u16::MAX);
+        assert_eq!(quantizer.code(0, f64::NAN), 0);
+
+        let mut previous = 0;
+        for i in 0..10_000 {
+            let value = i as f64 / 10_000.0;
+            let code = quantizer.code(0, value);
+            assert!(code >= previous);
+            let (lower, upper) = quantizer.bounds(0, code);
+            assert!(lower <= value && value < upper);
+            previous = code;
+        }
+        assert_eq!(quantizer.step(0), 1.0 / 65_536.0);
+    }
+
+    #[test]
+    fn test_encoding_is_compact() {
+        assert_eq!(QuantizedPoint::<u32, 2>::SIZE, 8);
+        assert_eq!(QuantizedPoint::<u16, 4>::SIZE, 8);
+
+        let quantizer = Quantizer::<u32>::new(&[(-180.0, 180.0), (-90.0, 90.0)]);
+        let point: QuantizedPoint<u32, 2> = quantizer.point(&[151.2093, -33.8688]);
+        let mut buf = [0u8; 8];
+        point.encode(&mut buf);
+        assert_eq!(QuantizedPoint::decode(&buf), point);
+        let (lower, upper) = quantizer.bounds(0, point.0[0]);
+        assert!(lower <= 151.2093 && 151.2093 < upper && upper - lower < 1e-7);
+    }
+
+    #[test]
+    #[should_panic(expected = "ranges must be finite and non-empty")]
+    fn test_rejects_empty_range() {
+        Quantizer::<u16>::new(&[(1.0, 1.0)]);
+    }
+
+    #[test]
+    fn test_quantized_block_tree_never_misses_a_match() {
+        let dir = tempfile::tempdir().unwrap();
+        let quantizer = Quantizer::<u16>::new(&[(0.0, 100.0), (0.0, 100.0)]);
+        let points: Vec<[f64; 2]> = (0..5000u32)
+            .map(|i| {
+                [
+                    ((i * 7919) % 10007) as f64 / 100.07,
+                    ((i * 104_729) % 9973) as f64 / 99.73,
+                ]
+            })
+            .collect();
+
+        let path = dir.path().join("quantized.bkd");
+        let mut writer = BkdWriter::new(BkdWriterOptions {
+            max_points_in_leaf: 64,
+            ..BkdWriterOptions::default()
+        });
+        for (i, point) in points.iter().enumerate() {
+            writer
+                .add(quantizer.point::<2, _>(point), i as u32)
+                .unwrap();
+        }
+        writer.finish(&path).unwrap();
+        let mut reader = BkdReader::<QuantizedPoint<u16, 2>, u32>::open(&path).unwrap();
+        assert_eq!(reader.header().point_size, 4);
+
+        for &(x0, x1, y0, y1) in &[
+            (10.0, 20.0, 30.0, 45.5),
+            (0.0, 100.0, 99.0, 100.0),
+            (33.3333, 33.3334, 0.0, 100.0),
+            (50.0, 50.0, 50.0, 50.0),
+        ] {
+            let query = RangeQuery::new()
+                .with_range(0, x0, x1)
+                .with_range(1, y0, y1);
+            let mut found = reader.search(&quantizer.query(&query)).unwrap();
+            found.sort_unstable();
+            for (i, point) in points.iter().enumerate() {
+                let i = i as u32;
+                if query.matches(point) {
+                    assert!(found.binary_search(&i).is_ok());
+                } else if found.binary_search(&i).is_ok() {
+                    // Extra matches lie within one step of the query
+                    let step = quantizer.step(0);
+                    assert!(point[0] >= x0 - step && point[0] <= x1 + step);
+                    assert!(point[1] >= y0 - step && point[1] <= y1 + step);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,673 @@
+//! Query shapes for spatial search.
+
+use crate::spatial::{BoundingBox, Point, SpatialPoint};
+
+/// Generic query interface used by the search algorithms.
+///
+/// # Design Principle: Pruning and matching are separate questions
+/// - `dimension_range` answers "which coordinate values could a matching entry have?"
+///   It is consulted at every split to decide which subtrees can be skipped.
+/// - `matches` is the exact per-entry test applied to every visited node.
+///
+/// Any query shape that can answer both questions works with every search function,
+/// regardless of how complex its exact geometry is (boxes, circles, polygons, ...).
+pub trait SpatialQuery<P: Point> {
+    /// Inclusive `(min, max)` range of values an entry may hold in `dim` and still match.
+    /// Returning a wider range than necessary is always safe; it only reduces pruning.
+    fn dimension_range(&self, dim: usize) -> (f64, f64);
+
+    /// Check if an indexed entry matches this query exactly.
+    fn matches(&self, point: &P) -> bool;
+
+    /// Relate a cell of point space, given by inclusive per-dimension `min`/`max` bounds,
+    /// to the query.
+    ///
+    /// The default derives the answer from `dimension_range`: a cell disjoint from the range
+    /// in any dimension is outside, anything else crosses. Queries whose ranges describe
+    /// `matches` exactly may also report `CellInsideQuery`, which lets searches collect a
+    /// whole cell without testing its entries.
+    fn relate(&self, min: &[f64], max: &[f64]) -> Relation {
+        for dim in 0..min.len() {
+            let (query_min, query_max) = self.dimension_range(dim);
+            if max[dim] < query_min || min[dim] > query_max {
+                return Relation::CellOutsideQuery;
+            }
+        }
+        Relation::CellCrossesQuery
+    }
+}
+
+/// How a cell of point space relates to a query (after Lucene's `PointValues.Relation`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Relation {
+    /// Every entry in the cell matches.
+    CellInsideQuery,
+    /// No entry in the cell matches.
+    CellOutsideQuery,
+    /// Some entries may match; each must be tested.
+    CellCrossesQuery,
+}
+
+/// Relation implied by per-dimension ranges that exactly describe a query.
+fn relate_exact_ranges(min: &[f64], max: &[f64], range: impl Fn(usize) -> (f64, f64)) -> Relation {
+    let mut inside = true;
+    for dim in 0..min.len() {
+        let (query_min, query_max) = range(dim);
+        if max[dim] < query_min || min[dim] > query_max {
+            return Relation::CellOutsideQuery;
+        }
+        inside &= min[dim] >= query_min && max[dim] <= query_max;
+    }
+    if inside {
+        Relation::CellInsideQuery
+    } else {
+        Relation::CellCrossesQuery
+    }
+}
+
+/// Bounding box query: matches every indexed box that is within or overlaps it.
+impl SpatialQuery<BoundingBox> for BoundingBox {
+    /// For an indexed box to overlap the query it must start before the query ends and
+    /// end after the query starts:
+    /// - xmin/ymin (dims 0, 1) are only bounded above, by the query's xmax/ymax
+    /// - xmax/ymax (dims 2, 3) are only bounded below, by the query's xmin/ymin
+    fn dimension_range(&self, dim: usize) -> (f64, f64) {
+        match dim {
+            0 => (f64::NEG_INFINITY, self.xmax),
+            1 => (f64::NEG_INFINITY, self.ymax),
+            2 => (self.xmin, f64::INFINITY),
+            3 => (self.ymin, f64::INFINITY),
+            _ => panic!("Invalid dimension: {}", dim),
+        }
+    }
+
+    fn matches(&self, point: &BoundingBox) -> bool {
+        point.is_within(self) || point.overlaps(self)
+    }
+
+    /// The four ranges are exactly the overlap test, so whole cells can be inside.
+    fn relate(&self, min: &[f64], max: &[f64]) -> Relation {
+        relate_exact_ranges(min, max, |dim| self.dimension_range(dim))
+    }
+}
+
+/// Query constraining only some dimensions of a point, each to an inclusive range.
+///
+/// Dimensions without a range are unbounded: they never prune a subtree and are never
+/// compared, so callers need not fabricate infinite bounds (whose arithmetic, such as
+/// `inf - inf`, yields NaN). An empty query matches everything.
+///
+/// # Usage pattern:
+/// ```rust
+/// use bkd::{BoundingBox, RangeQuery, SpatialQuery};
+///
+/// // Boxes whose xmin (dimension 0) lies in 2..=5, any other coordinate
+/// let query = RangeQuery::new().with_range(0, 2.0, 5.0);
+/// assert!(query.matches(&BoundingBox::new(3.0, -1e300, 4.0, 1e300)));
+/// assert!(!query.matches(&BoundingBox::new(6.0, 0.0, 7.0, 1.0)));
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RangeQuery {
+    ranges: Vec<Option<(f64, f64)>>,
+}
+
+impl RangeQuery {
+    /// Create a query that constrains no dimension.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Constrain `dim` to `min..=max`, replacing any earlier range for it.
+    pub fn with_range(mut self, dim: usize, min: f64, max: f64) -> Self {
+        if self.ranges.len() <= dim {
+            self.ranges.resize(dim + 1, None);
+        }
+        self.ranges[dim] = Some((min, max));
+        self
+    }
+
+    /// Range of `dim`, or `None` if it is unconstrained.
+    pub fn range(&self, dim: usize) -> Option<(f64, f64)> {
+        self.ranges.get(dim).copied().flatten()
+    }
+
+    /// Constrained dimensions and their ranges.
+    fn constrained(&self) -> impl Iterator<Item = (usize, f64, f64)> + '_ {
+        self.ranges
+            .iter()
+            .enumerate()
+            .filter_map(|(dim, range)| range.map(|(min, max)| (dim, min, max)))
+    }
+}
+
+impl<P: Point> SpatialQuery<P> for RangeQuery {
+    fn dimension_range(&self, dim: usize) -> (f64, f64) {
+        self.range(dim)
+            .unwrap_or((f64::NEG_INFINITY, f64::INFINITY))
+    }
+
+    fn matches(&self, point: &P) -> bool {
+        self.constrained().all(|(dim, min, max)| {
+            dim >= point.dimensions() || (min..=max).contains(&point.get_dimension(dim))
+        })
+    }
+
+    /// Unconstrained dimensions never make a cell outside or crossing.
+    fn relate(&self, cell_min: &[f64], cell_max: &[f64]) -> Relation {
+        let mut inside = true;
+        for (dim, min, max) in self.constrained() {
+            if dim >= cell_min.len() {
+                continue;
+            }
+            if cell_max[dim] < min || cell_min[dim] > max {
+                return Relation::CellOutsideQuery;
+            }
+            inside &= cell_min[dim] >= min && cell_max[dim] <= max;
+        }
+        if inside {
+            Relation::CellInsideQuery
+        } else {
+            Relation::CellCrossesQuery
+        }
+    }
+}
+
+/// Bounding box query constraining only the x extent, only the y extent, or both.
+///
+/// Matches every indexed box overlapping the given ranges; an axis without a range accepts
+/// any box, including ones with infinite extent along it.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PartialBox {
+    pub x: Option<(f64, f64)>,
+    pub y: Option<(f64, f64)>,
+}
+
+impl PartialBox {
+    /// Boxes overlapping `min..=max` on the x axis, at any y.
+    pub fn x_range(min: f64, max: f64) -> Self {
+        PartialBox {
+            x: Some((min, max)),
+            y: None,
+        }
+    }
+
+    /// Boxes overlapping `min..=max` on the y axis, at any x.
+    pub fn y_range(min: f64, max: f64) -> Self {
+        PartialBox {
+            x: None,
+            y: Some((min, max)),
+        }
+    }
+
+    /// Overlap ranges over (xmin, ymin, xmax, ymax), `None` for unconstrained dimensions.
+    fn range(&self, dim: usize) -> Option<(f64, f64)> {
+        match dim {
+            0 => self.x.map(|(_, max)| (f64::NEG_INFINITY, max)),
+            1 => self.y.map(|(_, max)| (f64::NEG_INFINITY, max)),
+            2 => self.x.map(|(min, _)| (min, f64::INFINITY)),
+            3 => self.y.map(|(min, _)| (min, f64::INFINITY)),
+            _ => panic!("Invalid dimension: {}", dim),
+        }
+    }
+}
+
+impl SpatialQuery<BoundingBox> for PartialBox {
+    fn dimension_range(&self, dim: usize) -> (f64, f64) {
+        self.range(dim)
+            .unwrap_or((f64::NEG_INFINITY, f64::INFINITY))
+    }
+
+    fn matches(&self, point: &BoundingBox) -> bool {
+        let x = self
+            .x
+            .is_none_or(|(min, max)| point.xmin <= max && point.xmax >= min);
+        let y = self
+            .y
+            .is_none_or(|(min, max)| point.ymin <= max && point.ymax >= min);
+        x && y
+    }
+
+    fn relate(&self, min: &[f64], max: &[f64]) -> Relation {
+        relate_exact_ranges(min, max, |dim| self.dimension_range(dim))
+    }
+}
+
+/// Bounding box query with a floating-point tolerance.
+///
+/// Matches entries that overlap the box or lie within `epsilon` of it, using
+/// `BoundingBox::overlaps_eps` for the exact test and widening the pruning ranges by the
+/// same amount, so pruning and matching agree on every touching or near-touching entry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TolerantBox {
+    pub bbox: BoundingBox,
+    pub epsilon: f64,
+}
+
+impl TolerantBox {
+    /// Create a tolerant query around a box.
+    pub fn new(bbox: BoundingBox, epsilon: f64) -> Self {
+        TolerantBox { bbox, epsilon }
+    }
+}
+
+impl SpatialQuery<BoundingBox> for TolerantBox {
+    fn dimension_range(&self, dim: usize) -> (f64, f64) {
+        let (min, max) = self.bbox.dimension_range(dim);
+        (min - self.epsilon, max + self.epsilon)
+    }
+
+    fn matches(&self, point: &BoundingBox) -> bool {
+        point.overlaps_eps(&self.bbox, self.epsilon)
+    }
+}
+
+/// Circle (disc) query: matches every indexed box that intersects the disc.
+///
+/// Distances are planar (Euclidean) in the same units as the indexed coordinates.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Circle {
+    pub center: (f64, f64),
+    pub radius: f64,
+}
+
+impl Circle {
+    /// Create a new circle from its center coordinates and radius.
+    pub fn new(center_x: f64, center_y: f64, radius: f64) -> Self {
+        Circle {
+            center: (center_x, center_y),
+            radius,
+        }
+    }
+
+    /// Smallest bounding box enclosing the circle.
+    pub fn bounding_box(&self) -> BoundingBox {
+        let (cx, cy) = self.center;
+        BoundingBox::new(
+            cx - self.radius,
+            cy - self.radius,
+            cx + self.radius,
+            cy + self.radius,
+        )
+    }
+
+    /// Minimum distance from the circle's center to a box (zero if the center is inside).
+    pub fn distance_to_box(&self, bbox: &BoundingBox) -> f64 {
+        let (cx, cy) = self.center;
+        let dx = (bbox.xmin - cx).max(0.0).max(cx - bbox.xmax);
+        let dy = (bbox.ymin - cy).max(0.0).max(cy - bbox.ymax);
+        dx.hypot(dy)
+    }
+}
+
+impl SpatialQuery<BoundingBox> for Circle {
+    /// Pruning uses the circle's bounding square: no box outside it can touch the disc.
+    /// Boxes that pass pruning but only reach the square's corners are rejected by `matches`.
+    fn dimension_range(&self, dim: usize) -> (f64, f64) {
+        self.bounding_box().dimension_range(dim)
+    }
+
+    fn matches(&self, point: &BoundingBox) -> bool {
+        self.distance_to_box(point) <= self.radius
+    }
+}
+
+/// Line segment query: matches every indexed box the segment from `start` to `end`
+/// touches, endpoints included. Picking and sight-line checks are segment queries.
+///
+/// # Usage pattern:
+/// ```rust
+/// use bkd::{BoundingBox, LineSegment, SpatialIndex};
+///
+/// let mut index = SpatialIndex::new();
+/// let wall = index.insert(BoundingBox::new(4.0, -1.0, 5.0, 1.0), "wall");
+/// index.insert(BoundingBox::new(4.0, 3.0, 5.0, 4.0), "post");
+///
+/// // Is the line of sight from (0, 0) to (10, 1) blocked?
+/// assert_eq!(index.search(&LineSegment::new((0.0, 0.0), (10.0, 1.0))), [wall]);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineSegment {
+    pub start: (f64, f64),
+    pub end: (f64, f64),
+}
+
+impl LineSegment {
+    /// Create a segment between two points.
+    pub fn new(start: (f64, f64), end: (f64, f64)) -> Self {
+        LineSegment { start, end }
+    }
+
+    fn line(&self) -> Line {
+        Line {
+            origin: self.start,
+            direction: (self.end.0 - self.start.0, self.end.1 - self.start.1),
+            reach: 1.0,
+        }
+    }
+}
+
+/// Ray query: matches every indexed box touched by the half-line from `origin` in
+/// `direction`, as routing and ray picking need. `direction` need not be normalized.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ray {
+    pub origin: (f64, f64),
+    pub direction: (f64, f64),
+}
+
+impl Ray {
+    /// Create a ray from `origin` towards `direction`.
+    pub fn new(origin: (f64, f64), direction: (f64, f64)) -> Self {
+        Ray { origin, direction }
+    }
+
+    fn line(&self) -> Line {
+        Line {
+            origin: self.origin,
+            direction: self.direction,
+            reach: f64::INFINITY,
+        }
+    }
+}
+
+/// The points `origin + t * direction` for `t` in `0..=reach`.
+struct Line {
+    origin: (f64, f64),
+    direction: (f64, f64),
+    reach: f64,
+}
+
+impl Line {
+    /// Slab test: clip the parameter range to each axis's slab of the box in turn; the
+    /// line touches the box if anything is left.
+    fn hits(&self, xmin: f64, ymin: f64, xmax: f64, ymax: f64) -> bool {
+        let (mut enter, mut exit) = (0.0f64, self.reach);
+        for (origin, direction, min, max) in [
+            (self.origin.0, self.direction.0, xmin, xmax),
+            (self.origin.1, self.direction.1, ymin, ymax),
+        ] {
+            if direction == 0.0 {
+                if origin < min || origin > max {
+                    return false;
+                }
+                continue;
+            }
+            let (near, far) = {
+                let (a, b) = ((min - origin) / direction, (max - origin) / direction);
+                (a.min(b), a.max(b))
+            };
+            enter = enter.max(near);
+            exit = exit.min(far);
+            if enter > exit {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Smallest and largest coordinate the line reaches along an axis.
+    fn extent(origin: f64, direction: f64, reach: f64) -> (f64, f64) {
+        let end = if direction == 0.0 {
+            origin
+        } else {
+            origin + direction * reach
+        };
+        (origin.min(end), origin.max(end))
+    }
+
+    /// Overlap ranges over (xmin, ymin, xmax, ymax) of the line's bounding box.
+    fn dimension_range(&self, dim: usize) -> (f64, f64) {
+        let (xmin, xmax) = Line::extent(self.origin.0, self.direction.0, self.reach);
+        let (ymin, ymax) = Line::extent(self.origin.1, self.direction.1, self.reach);
+        BoundingBox::new(xmin, ymin, xmax, ymax).dimension_range(dim)
+    }
+
+    /// Every box of a cell lies within the area from its lowest possible lower corner to
+    /// its highest possible upper corner, and contains the core from its highest possible
+    /// lower corner to its lowest possible upper corner, when that is not empty.
+    fn relate(&self, min: &[f64], max: &[f64]) -> Relation {
+        if !self.hits(min[0], min[1], max[2], max[3]) {
+            return Relation::CellOutsideQuery;
+        }
+        if max[0] <= min[2] && max[1] <= min[3] && self.hits(max[0], max[1], min[2], min[3]) {
+            return Relation::CellInsideQuery;
+        }
+        Relation::CellCrossesQuery
+    }
+}
+
+impl SpatialQuery<BoundingBox> for LineSegment {
+    fn dimension_range(&self, dim: usize) -> (f64, f64) {
+        self.line().dimension_range(dim)
+    }
+
+    fn matches(&self, point: &BoundingBox) -> bool {
+        self.line()
+            .hits(point.xmin, point.ymin, point.xmax, point.ymax)
+    }
+
+    fn relate(&self, min: &[f64], max: &[f64]) -> Relation {
+        self.line().relate(min, max)
+    }
+}
+
+impl SpatialQuery<BoundingBox> for Ray {
+    fn dimension_range(&self, dim: usize) -> (f64, f64) {
+        self.line().dimension_range(dim)
+    }
+
+    fn matches(&self, point: &BoundingBox) -> bool {
+        self.line()
+            .hits(point.xmin, point.ymin, point.xmax, point.ymax)
+    }
+
+    fn relate(&self, min: &[f64], max: &[f64]) -> Relation {
+        self.line().relate(min, max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_tree::{BkdReader, BkdWriter, BkdWriterOptions};
+    use crate::build::{BuildOptions, bulk_build};
+    use crate::search::{insert_node, spatial_search};
+    use crate::storage::{InMemoryLinker, NodeArena};
+
+    /// A query behind a reference, so one check runs over several query types.
+    struct DynRef<'q>(&'q dyn SpatialQuery<BoundingBox>);
+
+    impl SpatialQuery<BoundingBox> for DynRef<'_> {
+        fn dimension_range(&self, dim: usize) -> (f64, f64) {
+            self.0.dimension_range(dim)
+        }
+
+        fn matches(&self, point: &BoundingBox) -> bool {
+            self.0.matches(point)
+        }
+
+        fn relate(&self, min: &[f64], max: &[f64]) -> Relation {
+            self.0.relate(min, max)
+        }
+    }
+
+    #[test]
+    fn test_circle_matches_boxes() {
+        let circle = Circle::new(0.0, 0.0, 5.0);
+
+        assert!(circle.matches(&BoundingBox::new(-1.0, -1.0, 1.0, 1.0))); // contains center
+        assert!(circle.matches(&BoundingBox::new(4.0, -1.0, 6.0, 1.0))); // crosses the edge
+        assert!(circle.matches(&BoundingBox::new(3.0, 4.0, 6.0, 6.0))); // touches at (3, 4)
+        assert!(!circle.matches(&BoundingBox::new(4.0, 4.0, 6.0, 6.0))); // in the square's corner
+        assert!(!circle.matches(&BoundingBox::new(10.0, 10.0, 11.0, 11.0)));
+    }
+
+    #[test]
+    fn test_tolerant_box_search() {
+        let mut arena = NodeArena::new();
+        let a = arena.allocate(BoundingBox::new(0.0, 0.0, 1.0, 1.0), "a");
+        let b = arena.allocate(BoundingBox::new(2.0 + 1e-10, 0.0, 3.0, 1.0), "b");
+
+        let mut linker = InMemoryLinker::new(&mut arena);
+        let root = insert_node(&mut linker, None, a, 0);
+        insert_node(&mut linker, Some(root), b, 0);
+
+        let query = BoundingBox::new(1.5, 0.0, 2.0, 1.0);
+        assert!(spatial_search(&linker, Some(root), &query, 0).is_empty());
+
+        let tolerant = TolerantBox::new(query, 1e-9);
+        assert_eq!(spatial_search(&linker, Some(root), &tolerant, 0), vec![b]);
+    }
+
+    #[test]
+    fn test_circle_search() {
+        let mut arena = NodeArena::new();
+        let near = arena.allocate(BoundingBox::new(1.0, 1.0, 2.0, 2.0), "near");
+        let corner = arena.allocate(BoundingBox::new(4.0, 4.0, 5.0, 5.0), "corner");
+        let far = arena.allocate(BoundingBox::new(20.0, 20.0, 21.0, 21.0), "far");
+        let wide = arena.allocate(BoundingBox::new(-50.0, -0.5, 50.0, 0.5), "wide");
+
+        let mut linker = InMemoryLinker::new(&mut arena);
+        let root = insert_node(&mut linker, None, near, 0);
+        for node in [corner, far, wide] {
+            insert_node(&mut linker, Some(root), node, 0);
+        }
+
+        let mut results = spatial_search(&linker, Some(root), &Circle::new(0.0, 0.0, 5.0), 0);
+        results.sort();
+        assert_eq!(results, vec![near, wide]);
+    }
+
+    #[test]
+    fn test_segments_and_rays_match_a_scan() {
+        let boxes: Vec<BoundingBox> = (0..500)
+            .map(|i| {
+                let x = ((i * 37) % 101) as f64;
+                let y = ((i * 53) % 97) as f64;
+                BoundingBox::new(x, y, x + (i % 4) as f64, y + (i % 3) as f64)
+            })
+            .collect();
+        let mut arena = NodeArena::new();
+        let mut nodes: Vec<usize> = boxes
+            .iter()
+            .enumerate()
+            .map(|(i, bbox)| arena.allocate(bbox.clone(), i))
+            .collect();
+        let mut linker = InMemoryLinker::new(&mut arena);
+        let root = bulk_build(&mut linker, &mut nodes, 0, &BuildOptions::default()).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("boxes.bkd");
+        let mut writer = BkdWriter::new(BkdWriterOptions {
+            max_points_in_leaf: 8,
+            ..BkdWriterOptions::default()
+        });
+        for (i, bbox) in boxes.iter().enumerate() {
+            writer.add(bbox.clone(), i as u32).unwrap();
+        }
+        writer.finish(&path).unwrap();
+        let mut reader = BkdReader::<BoundingBox, u32>::open(&path).unwrap();
+
+        let check = |query: &dyn SpatialQuery<BoundingBox>, reader: &mut BkdReader<_, u32>| {
+            let expected: Vec<usize> = (0..boxes.len())
+                .filter(|&i| query.matches(&boxes[i]))
+                .collect();
+            assert!(!expected.is_empty());
+            let mut found = spatial_search(&linker, root, &DynRef(query), 0);
+            found.sort();
+            assert_eq!(found, expected);
+            let mut read: Vec<usize> = reader
+                .search(&DynRef(query))
+                .unwrap()
+                .into_iter()
+                .map(|i| i as usize)
+                .collect();
+            read.sort();
+            assert_eq!(read, expected);
+        };
+        check(&LineSegment::new((-5.0, 3.0), (110.0, 90.0)), &mut reader);
+        check(&LineSegment::new((50.5, -10.0), (50.5, 40.0)), &mut reader);
+        check(&LineSegment::new((84.5, 72.0), (84.5, 72.0)), &mut reader);
+        check(&Ray::new((10.0, 90.0), (1.0, -0.25)), &mut reader);
+        check(&Ray::new((200.0, 30.0), (-1.0, 0.0)), &mut reader);
+
+        // The slab test on its own, edges included
+        let segment = LineSegment::new((0.0, 0.0), (10.0, 10.0));
+        assert!(segment.matches(&BoundingBox::new(9.0, 0.0, 10.0, 9.0)));
+        assert!(!segment.matches(&BoundingBox::new(9.5, 0.0, 10.0, 9.0)));
+        assert!(segment.matches(&BoundingBox::new(10.0, 10.0, 11.0, 11.0)));
+        assert!(!segment.matches(&BoundingBox::new(10.5, 10.5, 11.0, 11.0)));
+        assert!(Ray::new((0.0, 0.0), (1.0, 1.0)).matches(&BoundingBox::new(1e6, 1e6, 2e6, 2e6)));
+        assert!(
+            !Ray::new((0.0, 0.0), (1.0, 1.0)).matches(&BoundingBox::new(-2.0, -2.0, -1.0, -1.0))
+        );
+
+        // Cells over (xmin, ymin, xmax, ymax): every box of this one contains (4..6, 4..6)
+        let cell = segment.relate(&[0.0, 0.0, 6.0, 6.0], &[4.0, 4.0, 8.0, 8.0]);
+        assert_eq!(cell, Relation::CellInsideQuery);
+        let cell = segment.relate(&[0.0, 5.0, 1.0, 6.0], &[1.0, 6.0, 2.0, 7.0]);
+        assert_eq!(cell, Relation::CellOutsideQuery);
+        let cell = segment.relate(&[0.0, 0.0, 1.0, 1.0], &[9.0, 9.0, 10.0, 10.0]);
+        assert_eq!(cell, Relation::CellCrossesQuery);
+    }
+
+    #[test]
+    fn test_relate_cells() {
+        let query = BoundingBox::new(0.0, 0.0, 10.0, 10.0);
+        // Cells over (xmin, ymin, xmax, ymax) space
+        let inside = query.relate(&[1.0, 1.0, 2.0, 2.0], &[5.0, 5.0, 6.0, 6.0]);
+        let crosses = query.relate(&[1.0, 1.0, 2.0, 2.0], &[12.0, 5.0, 13.0, 6.0]);
+        let outside = query.relate(&[11.0, 1.0, 12.0, 2.0], &[12.0, 5.0, 13.0, 6.0]);
+        assert_eq!(inside, Relation::CellInsideQuery);
+        assert_eq!(crosses, Relation::CellCrossesQuery);
+        assert_eq!(outside, Relation::CellOutsideQuery);
+
+        // Circles only know their bounding ranges, so cells are never reported inside
+        let circle = Circle::new(0.0, 0.0, 100.0);
+        let cell = circle.relate(&[0.0, 0.0, 0.0, 0.0], &[1.0, 1.0, 1.0, 1.0]);
+        assert_eq!(cell, Relation::CellCrossesQuery);
+    }
+
+    #[test]
+    fn test_partial_dimension_queries() {
+        let mut arena = NodeArena::new();
+        let boxes = [
+            BoundingBox::new(0.0, 0.0, 1.0, 1.0),
+            BoundingBox::new(2.0, 100.0, 3.0, 200.0),
+            BoundingBox::new(4.0, -5.0, 5.0, f64::INFINITY),
+            BoundingBox::new(2.5, 0.0, 9.0, 0.5),
+        ];
+        let nodes: Vec<usize> = boxes
+            .iter()
+            .enumerate()
+            .map(|(i, bbox)| arena.allocate(bbox.clone(), i))
+            .collect();
+        let mut linker = InMemoryLinker::new(&mut arena);
+        let root = insert_node(&mut linker, None, nodes[0], 0);
+        for &node in &nodes[1..] {
+            insert_node(&mut linker, Some(root), node, 0);
+        }
+
+        let mut results = spatial_search(&linker, Some(root), &PartialBox::x_range(2.0, 4.0), 0);
+        results.sort();
+        assert_eq!(results, vec![nodes[1], nodes[2], nodes[3]]);
+
+        let mut results = spatial_search(&linker, Some(root), &PartialBox::y_range(150.0, 1e6), 0);
+        results.sort();
+        assert_eq!(results, vec![nodes[1], nodes[2]]);
+
+        // Generic ranges over raw dimensions: xmin in [2, 3], anything else
+        let query = RangeQuery::new().with_range(0, 2.0, 3.0);
+        let mut results = spatial_search(&linker, Some(root), &query, 0);
+        results.sort();
+        assert_eq!(results, vec![nodes[1], nodes[3]]);
+
+        let cell = SpatialQuery::<BoundingBox>::relate(
+            &query,
+            &[2.0, -1e9, 0.0, 0.0],
+            &[2.5, 1e9, 1e9, f64::INFINITY],
+        );
+        assert_eq!(cell, Relation::CellInsideQuery);
+        let everything = SpatialQuery::<BoundingBox>::relate(&RangeQuery::new(), &[0.0], &[1.0]);
+        assert_eq!(everything, Relation::CellInsideQuery);
+    }
+}
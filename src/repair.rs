@@ -0,0 +1,238 @@
+//! Salvage of damaged node files.
+
+use crate::codec::FixedCodec;
+use crate::external::{ExternalBuildOptions, external_bulk_build};
+use crate::node_file::{FormatFeatures, HEADER_SIZE, NodeFileHeader, record_size};
+use crate::spatial::Point;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::marker::PhantomData;
+use std::path::Path;
+
+/// Outcome of `repair`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepairReport {
+    /// Records copied into the rebuilt index.
+    pub salvaged: u64,
+    /// Records dropped because a coordinate decoded as NaN.
+    pub discarded: u64,
+    /// Whether the header was unreadable, so the record count came from the file size.
+    pub header_damaged: bool,
+}
+
+/// Rebuild the index in the damaged node file at `path` into a fresh file at `output`.
+///
+/// # Architecture
+/// Links are the fragile part of a node file: one bad offset hides a whole subtree from
+/// searches. Points and payloads sit in fixed-size records that stay readable on their
+/// own, so `repair` ignores the tree structure altogether:
+/// - Every record present in the file is decoded in storage order, reachable or not
+/// - Records with a NaN coordinate, which no search matches, are dropped as garbage
+/// - The rest are bulk-built anew with `external_bulk_build`, so memory stays bounded by
+///   `options` however large the file is
+///
+/// A header that no longer decodes is not fatal: the records are assumed to hold `P` and
+/// `T`, and their number follows from the file size. A header that decodes but describes
+/// other record types, or format features this crate cannot read, is an error, since
+/// reading on would only produce garbage. The rebuilt file keeps the features. A record
+/// whose bytes were damaged but still decode is salvaged as it is; `verify` reports such
+/// damage through the checksum beforehand.
+///
+/// The damaged file is left untouched, and `output` must be another file.
+pub fn repair<P: Point + FixedCodec, T: FixedCodec>(
+    path: &Path,
+    output: &Path,
+    options: &ExternalBuildOptions,
+) -> io::Result<RepairReport> {
+    let mut file = BufReader::new(File::open(path)?);
+    let file_size = file.get_ref().metadata()?.len();
+    let record_size = record_size(P::SIZE, T::SIZE) as u64;
+    let present = file_size.saturating_sub(HEADER_SIZE as u64) / record_size;
+
+    let mut buf = [0u8; HEADER_SIZE];
+    let header = if file_size >= HEADER_SIZE as u64 {
+        file.read_exact(&mut buf)?;
+        match NodeFileHeader::decode(&buf) {
+            Ok(header) => Some(header),
+            Err(error) if error.kind() == io::ErrorKind::Unsupported => return Err(error),
+            Err(_) => None,
+        }
+    } else {
+        None
+    };
+    let records = match &header {
+        Some(header) => {
+            header.check_layout::<P, T>()?;
+            // Writable memory maps leave spare capacity past the last record
+            header.node_count.min(present)
+        }
+        None => present,
+    };
+
+    let mut options = options.clone();
+    options.features = header
+        .as_ref()
+        .map_or(FormatFeatures::NONE, |header| header.features);
+    let mut salvage = Salvage::<P, T> {
+        file,
+        remaining: records,
+        record: vec![0u8; record_size as usize],
+        discarded: 0,
+        error: None,
+        _marker: PhantomData,
+    };
+    let salvaged = external_bulk_build(&mut salvage, output, &options)?;
+    if let Some(error) = salvage.error {
+        return Err(error);
+    }
+    Ok(RepairReport {
+        salvaged,
+        discarded: salvage.discarded,
+        header_damaged: header.is_none(),
+    })
+}
+
+/// Reads records in storage order, keeping the first read error for the caller since
+/// `external_bulk_build` consumes plain entries.
+struct Salvage<P, T> {
+    file: BufReader<File>,
+    remaining: u64,
+    record: Vec<u8>,
+    discarded: u64,
+    error: Option<io::Error>,
+    _marker: PhantomData<fn() -> (P, T)>,
+}
+
+impl<P: Point + FixedCodec, T: FixedCodec> Iterator for Salvage<P, T> {
+    type Item = (P, T);
+
+    fn next(&mut self) -> Option<(P, T)> {
+        while self.remaining > 0 {
+            self.remaining -= 1;
+            if let Err(error) = self.file.read_exact(&mut self.record) {
+                self.error = Some(error);
+                return None;
+            }
+            let point = P::decode(&self.record[16..16 + P::SIZE]);
+            if (0..point.dimensions()).any(|dim| point.get_dimension(dim).is_nan()) {
+                self.discarded += 1;
+                continue;
+            }
+            let data = T::decode(&self.record[16 + P::SIZE..16 + P::SIZE + T::SIZE]);
+            return Some((point, data));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node_file::{NodeFileReader, NodeFileWriter};
+    use crate::search::{export_all, spatial_search};
+    use crate::spatial::BoundingBox;
+    use crate::storage::ArenaView;
+    use crate::verify::{Problem, verify};
+    use std::fs::OpenOptions;
+    use std::io::{Seek, SeekFrom, Write};
+
+    fn overwrite(path: &Path, offset: u64, bytes: &[u8]) {
+        let mut file = OpenOptions::new().write(true).open(path).unwrap();
+        file.seek(SeekFrom::Start(offset)).unwrap();
+        file.write_all(bytes).unwrap();
+    }
+
+    fn salvaged_data(path: &Path) -> Vec<u64> {
+        let mut reader = NodeFileReader::<BoundingBox, u64>::open(path).unwrap();
+        let (arena, root) = reader.load_arena().unwrap();
+        let linker = ArenaView::new(&arena);
+        let mut data: Vec<u64> = export_all(&linker, root).map(|(_, data)| data).collect();
+        data.sort_unstable();
+        data
+    }
+
+    #[test]
+    fn test_repair_relinks_every_readable_record() {
+        let dir = tempfile::tempdir().unwrap();
+        let damaged = dir.path().join("damaged.bkd");
+        let repaired = dir.path().join("repaired.bkd");
+        let at = |x: f64| BoundingBox::new(x, 0.0, x + 1.0, 1.0);
+
+        // Node 0 lost its link to node 2, node 1 links out of range, node 3 is garbage
+        let mut writer = NodeFileWriter::<BoundingBox, u64>::create(&damaged).unwrap();
+        writer.push(&at(5.0), &0, Some(1), None).unwrap();
+        writer.push(&at(2.0), &1, None, Some(40)).unwrap();
+        writer.push(&at(8.0), &2, None, None).unwrap();
+        writer.push(&at(f64::NAN), &3, None, None).unwrap();
+        writer.push(&at(9.0), &4, None, None).unwrap();
+        writer.finish(Some(0)).unwrap();
+        assert!(!verify::<BoundingBox, u64>(&damaged).unwrap().is_ok());
+
+        let options = ExternalBuildOptions {
+            max_entries_in_memory: 2,
+            ..Default::default()
+        };
+        let report = repair::<BoundingBox, u64>(&damaged, &repaired, &options).unwrap();
+        assert_eq!(
+            report,
+            RepairReport {
+                salvaged: 4,
+                discarded: 1,
+                header_damaged: false,
+            }
+        );
+        assert!(verify::<BoundingBox, u64>(&repaired).unwrap().is_ok());
+        assert_eq!(salvaged_data(&repaired), [0, 1, 2, 4]);
+
+        let mut reader = NodeFileReader::<BoundingBox, u64>::open(&repaired).unwrap();
+        let (arena, root) = reader.load_arena().unwrap();
+        let linker = ArenaView::new(&arena);
+        let found = spatial_search(&linker, root, &BoundingBox::new(7.5, 0.0, 8.5, 1.0), 0);
+        assert_eq!(found.len(), 1);
+    }
+
+    #[test]
+    fn test_repair_survives_header_damage_and_truncation() {
+        let dir = tempfile::tempdir().unwrap();
+        let damaged = dir.path().join("damaged.bkd");
+        let repaired = dir.path().join("repaired.bkd");
+        let mut writer = NodeFileWriter::<BoundingBox, u64>::create(&damaged).unwrap();
+        for i in 0..30u64 {
+            let x = (i * 7 % 30) as f64;
+            let left = (i + 1 < 30).then_some(i + 1);
+            writer
+                .push(&BoundingBox::new(x, x, x, x), &i, left, None)
+                .unwrap();
+        }
+        writer.finish(Some(0)).unwrap();
+
+        // Smash the magic and cut the last record in half
+        overwrite(&damaged, 0, b"XXXX");
+        let size = std::fs::metadata(&damaged).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&damaged)
+            .unwrap()
+            .set_len(size - 20)
+            .unwrap();
+        assert!(matches!(
+            verify::<BoundingBox, u64>(&damaged).unwrap().problems[..],
+            [Problem::BadHeader(_)]
+        ));
+
+        let report =
+            repair::<BoundingBox, u64>(&damaged, &repaired, &ExternalBuildOptions::default())
+                .unwrap();
+        assert_eq!((report.salvaged, report.header_damaged), (29, true));
+        assert_eq!(salvaged_data(&repaired), (0..29).collect::<Vec<_>>());
+
+        let mut bytes = std::fs::read(&repaired).unwrap();
+        bytes[48] = FormatFeatures::COMPRESSED.bits() as u8;
+        std::fs::write(&damaged, bytes).unwrap();
+        let unsupported = repair::<BoundingBox, u64>(&damaged, &repaired, &Default::default());
+        assert_eq!(unsupported.unwrap_err().kind(), io::ErrorKind::Unsupported);
+
+        let wrong_types = repair::<BoundingBox, u32>(&repaired, &damaged, &Default::default());
+        assert_eq!(wrong_types.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,190 @@
+//! Lookups from payloads to the nodes carrying them, for deletes and updates by an
+//! external id.
+
+use crate::spatial::Point;
+use crate::storage::NodeLinker;
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Find the nodes reachable from `root` whose payload satisfies `predicate`, in pre-order.
+///
+/// Points do not constrain the search, so every node is visited. Callers looking up
+/// payloads repeatedly should build a `ReverseIndex` once instead.
+pub fn find_by_data<P: Point, T, L: NodeLinker<P, T>>(
+    linker: &L,
+    root: Option<L::NodeRef>,
+    mut predicate: impl FnMut(&T) -> bool,
+) -> Vec<L::NodeRef> {
+    let mut results = Vec::new();
+    let mut stack: Vec<L::NodeRef> = root.into_iter().collect();
+    while let Some(node) = stack.pop() {
+        if predicate(linker.get_data(node)) {
+            results.push(node);
+        }
+        stack.extend(linker.get_right(node));
+        stack.extend(linker.get_left(node));
+    }
+    results
+}
+
+/// Map from a key derived from each payload, such as a document id, to the nodes carrying
+/// it.
+///
+/// # Usage pattern:
+/// - `build` once over an existing tree, with a function extracting the key of a payload
+/// - `insert` each node added to the tree afterwards, and `remove` each node deleted from
+///   it; the map does not watch the tree
+/// - `get` the nodes of a key, then delete or update them in the tree
+///
+/// Several nodes may share a key, as when a document has several shapes. Nodes are kept
+/// in the order they were added.
+///
+/// ```rust
+/// use bkd::{BoundingBox, ReverseIndex, SpatialIndex};
+///
+/// let mut index = SpatialIndex::new();
+/// index.insert(BoundingBox::new(0.0, 0.0, 1.0, 1.0), ("doc-1", 0));
+/// let second = index.insert(BoundingBox::new(5.0, 5.0, 6.0, 6.0), ("doc-2", 0));
+///
+/// let by_doc = ReverseIndex::build(&index, index.root(), |&(doc, _)| doc.to_string());
+/// assert_eq!(by_doc.get("doc-2"), [second]);
+/// assert!(by_doc.get("doc-3").is_empty());
+/// ```
+#[derive(Debug, Clone)]
+pub struct ReverseIndex<K, R = usize> {
+    nodes: HashMap<K, Vec<R>>,
+    len: usize,
+}
+
+impl<K: Eq + Hash, R: Copy + PartialEq> ReverseIndex<K, R> {
+    /// Create an empty map.
+    pub fn new() -> Self {
+        ReverseIndex {
+            nodes: HashMap::new(),
+            len: 0,
+        }
+    }
+
+    /// Map every node reachable from `root` by the key `key` extracts from its payload.
+    pub fn build<P: Point, T, L: NodeLinker<P, T, NodeRef = R>>(
+        linker: &L,
+        root: Option<R>,
+        mut key: impl FnMut(&T) -> K,
+    ) -> Self {
+        let mut index = ReverseIndex::new();
+        let mut stack: Vec<R> = root.into_iter().collect();
+        while let Some(node) = stack.pop() {
+            index.insert(key(linker.get_data(node)), node);
+            stack.extend(linker.get_right(node));
+            stack.extend(linker.get_left(node));
+        }
+        index
+    }
+
+    /// Record that `node` carries `key`.
+    pub fn insert(&mut self, key: K, node: R) {
+        self.nodes.entry(key).or_default().push(node);
+        self.len += 1;
+    }
+
+    /// Forget that `node` carries `key`. Returns `false` if it was not recorded.
+    pub fn remove<Q>(&mut self, key: &Q, node: R) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let Some(nodes) = self.nodes.get_mut(key) else {
+            return false;
+        };
+        let Some(position) = nodes.iter().position(|&other| other == node) else {
+            return false;
+        };
+        nodes.remove(position);
+        if nodes.is_empty() {
+            self.nodes.remove(key);
+        }
+        self.len -= 1;
+        true
+    }
+
+    /// Nodes carrying `key`, in the order they were added; empty for unknown keys.
+    pub fn get<Q>(&self, key: &Q) -> &[R]
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.nodes.get(key).map_or(&[], Vec::as_slice)
+    }
+
+    /// Number of nodes mapped.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Check if no node is mapped.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of distinct keys.
+    pub fn keys(&self) -> usize {
+        self.nodes.len()
+    }
+}
+
+impl<K: Eq + Hash, R: Copy + PartialEq> Default for ReverseIndex<K, R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deletes::{DeletionBitmap, spatial_search_live};
+    use crate::index::SpatialIndex;
+    use crate::spatial::BoundingBox;
+
+    #[test]
+    fn test_lookups_match_a_full_scan() {
+        let mut index = SpatialIndex::new();
+        for i in 0..300u32 {
+            let x = ((i * 37) % 101) as f64;
+            // Every document has three shapes
+            index.insert(BoundingBox::new(x, x, x + 1.0, x + 1.0), (i % 100, i));
+        }
+        let mut by_doc = ReverseIndex::build(&index, index.root(), |&(doc, _)| doc);
+        assert_eq!((by_doc.len(), by_doc.keys()), (300, 100));
+        for doc in [0, 42, 99] {
+            let mut nodes = by_doc.get(&doc).to_vec();
+            nodes.sort_unstable();
+            let mut scanned = find_by_data(&index, index.root(), |&(other, _)| other == doc);
+            scanned.sort_unstable();
+            assert_eq!(nodes, scanned);
+            assert_eq!(nodes.len(), 3);
+        }
+        assert!(by_doc.get(&100).is_empty());
+
+        // Delete a document by id: mark its nodes, then forget them
+        let mut deleted = DeletionBitmap::new();
+        for node in by_doc.get(&42).to_vec() {
+            deleted.delete(node);
+            assert!(by_doc.remove(&42, node));
+            assert!(!by_doc.remove(&42, node));
+        }
+        assert!(by_doc.get(&42).is_empty());
+        assert_eq!((by_doc.len(), by_doc.keys()), (297, 99));
+        let everything = BoundingBox::new(-1.0, -1.0, 200.0, 200.0);
+        let live = spatial_search_live(&index, index.root(), &everything, 0, &deleted);
+        assert_eq!(live.len(), 297);
+        assert!(
+            live.iter()
+                .all(|&node| index.arena().get(node).data.0 != 42)
+        );
+
+        let added = index.insert(BoundingBox::new(7.0, 7.0, 8.0, 8.0), (42, 300));
+        by_doc.insert(42, added);
+        assert_eq!(by_doc.get(&42), [added]);
+    }
+}
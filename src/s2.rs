@@ -0,0 +1,297 @@
+//! S2 cell ids, enough to query the index by S2 cells and coverings.
+//!
+//! S2 projects the sphere onto the six faces of a cube and orders each face's cells along a
+//! Hilbert curve. A cell id packs the face into its top three bits, then two bits per level
+//! of the curve position, then a single marker bit; the marker's position gives the level.
+//! This module reads and writes those ids and turns cells into `GridCell`s, so
+//! `CellQuery::new(covering)` searches an index of `(lon, lat)` boxes by S2 cells.
+
+use crate::cells::{GridCell, to_lon_lat, to_xyz};
+use std::fmt;
+
+/// Deepest S2 level; cells at level 30 are about a centimeter across.
+pub const MAX_LEVEL: u8 = 30;
+
+/// Curve position of each `(i, j)` quadrant (`2 * i + j`) for each orientation.
+const IJ_TO_POS: [[u8; 4]; 4] = [[0, 1, 3, 2], [0, 3, 1, 2], [2, 3, 1, 0], [2, 1, 3, 0]];
+
+/// Inverse of `IJ_TO_POS`.
+const POS_TO_IJ: [[u8; 4]; 4] = [[0, 1, 3, 2], [0, 2, 3, 1], [3, 2, 0, 1], [3, 1, 0, 2]];
+
+/// Change of orientation when descending into the quadrant at each curve position.
+const POS_TO_ORIENTATION: [u8; 4] = [1, 0, 0, 3];
+
+/// A cell of the S2 hierarchy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct S2CellId(u64);
+
+impl S2CellId {
+    /// Wrap a raw id, or `None` if it does not name a cell.
+    pub fn new(id: u64) -> Option<Self> {
+        let valid = id != 0 && id >> 61 <= 5 && id.trailing_zeros() % 2 == 0;
+        valid.then_some(S2CellId(id))
+    }
+
+    /// Parse the hexadecimal token form, which drops the id's trailing zero digits.
+    pub fn from_token(token: &str) -> Option<Self> {
+        if token.is_empty() || token.len() > 16 || !token.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        let id = u64::from_str_radix(token, 16).ok()?;
+        Self::new(id << (4 * (16 - token.len())))
+    }
+
+    /// Cell at `level` containing a position.
+    ///
+    /// # Panics
+    /// Panics if `level` is greater than `MAX_LEVEL`.
+    pub fn from_lon_lat(lon: f64, lat: f64, level: u8) -> Self {
+        assert!(level <= MAX_LEVEL, "S2 level must be at most {MAX_LEVEL}");
+        let [x, y, z] = to_xyz(lon, lat.clamp(-90.0, 90.0));
+        let (ax, ay, az) = (x.abs(), y.abs(), z.abs());
+        let axis = if ax >= ay && ax >= az {
+            0
+        } else if ay >= az {
+            1
+        } else {
+            2
+        };
+        let face = axis + if [x, y, z][axis] < 0.0 { 3 } else { 0 };
+        let (u, v) = match face {
+            0 => (y / x, z / x),
+            1 => (-x / y, z / y),
+            2 => (-x / z, -y / z),
+            3 => (z / x, y / x),
+            4 => (z / y, -x / y),
+            _ => (-y / z, -x / z),
+        };
+        let i = st_to_ij(uv_to_st(u));
+        let j = st_to_ij(uv_to_st(v));
+
+        let mut orientation = (face & 1) as u8;
+        let mut pos = 0u64;
+        for bit in (0..MAX_LEVEL).rev() {
+            let ij = ((i >> bit & 1) << 1 | (j >> bit & 1)) as usize;
+            let quadrant = IJ_TO_POS[orientation as usize][ij];
+            pos = pos << 2 | quadrant as u64;
+            orientation ^= POS_TO_ORIENTATION[quadrant as usize];
+        }
+        S2CellId((face as u64) << 61 | pos << 1 | 1).parent(level)
+    }
+
+    /// The raw 64-bit id.
+    pub fn id(self) -> u64 {
+        self.0
+    }
+
+    /// Hexadecimal token form of the id.
+    pub fn to_token(self) -> String {
+        let digits = format!("{:016x}", self.0);
+        digits.trim_end_matches('0').to_string()
+    }
+
+    /// Cube face of the cell, from 0 to 5.
+    pub fn face(self) -> u8 {
+        (self.0 >> 61) as u8
+    }
+
+    /// Level of the cell, from 0 (a whole face) to `MAX_LEVEL`.
+    pub fn level(self) -> u8 {
+        MAX_LEVEL - (self.0.trailing_zeros() / 2) as u8
+    }
+
+    /// Ancestor of the cell at `level`.
+    ///
+    /// # Panics
+    /// Panics if `level` is deeper than the cell's own level.
+    pub fn parent(self, level: u8) -> Self {
+        assert!(
+            level <= self.level(),
+            "parent level must not be deeper than the cell"
+        );
+        let lsb = 1u64 << (2 * (MAX_LEVEL - level));
+        S2CellId(self.0 & lsb.wrapping_neg() | lsb)
+    }
+
+    /// Check if `other` is this cell or one of its descendants.
+    pub fn contains(self, other: S2CellId) -> bool {
+        other.level() >= self.level() && other.parent(self.level()) == self
+    }
+
+    /// Face coordinates of the cell's lower-left corner, and its size, in level-30 cells.
+    fn face_ij(self) -> (u32, u32, u32) {
+        let level = self.level();
+        let mut orientation = self.face() & 1;
+        let (mut i, mut j) = (0u32, 0u32);
+        for step in 0..level {
+            let quadrant = (self.0 >> (59 - 2 * step as u32) & 3) as usize;
+            let ij = POS_TO_IJ[orientation as usize][quadrant];
+            i = i << 1 | (ij >> 1) as u32;
+            j = j << 1 | (ij & 1) as u32;
+            orientation ^= POS_TO_ORIENTATION[quadrant];
+        }
+        let shift = MAX_LEVEL - level;
+        (i << shift, j << shift, 1 << shift)
+    }
+}
+
+impl fmt::Display for S2CellId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_token())
+    }
+}
+
+impl GridCell for S2CellId {
+    /// Corners counterclockwise from the lowest `(i, j)`. Cell edges are lines of constant
+    /// `u` or `v`, which the face projection maps onto great circles.
+    fn vertices(&self) -> Vec<(f64, f64)> {
+        let (i, j, size) = self.face_ij();
+        let face = self.face();
+        [(i, j), (i + size, j), (i + size, j + size), (i, j + size)]
+            .into_iter()
+            .map(|(i, j)| {
+                let u = st_to_uv(ij_to_st(i));
+                let v = st_to_uv(ij_to_st(j));
+                let xyz = match face {
+                    0 => [1.0, u, v],
+                    1 => [-u, 1.0, v],
+                    2 => [-u, -v, 1.0],
+                    3 => [-1.0, -v, -u],
+                    4 => [v, -1.0, -u],
+                    _ => [v, u, -1.0],
+                };
+                to_lon_lat(xyz)
+            })
+            .collect()
+    }
+
+    fn contains_point(&self, lon: f64, lat: f64) -> bool {
+        S2CellId::from_lon_lat(lon, lat, self.level()) == *self
+    }
+}
+
+/// S2's quadratic transform from face coordinates to evenly sized cell coordinates.
+fn uv_to_st(u: f64) -> f64 {
+    if u >= 0.0 {
+        0.5 * (1.0 + 3.0 * u).sqrt()
+    } else {
+        1.0 - 0.5 * (1.0 - 3.0 * u).sqrt()
+    }
+}
+
+fn st_to_uv(s: f64) -> f64 {
+    if s >= 0.5 {
+        (4.0 * s * s - 1.0) / 3.0
+    } else {
+        (1.0 - 4.0 * (1.0 - s) * (1.0 - s)) / 3.0
+    }
+}
+
+fn st_to_ij(s: f64) -> u32 {
+    let max = (1u32 << MAX_LEVEL) - 1;
+    ((s * (1u32 << MAX_LEVEL) as f64).floor().max(0.0) as u32).min(max)
+}
+
+fn ij_to_st(i: u32) -> f64 {
+    i as f64 / (1u32 << MAX_LEVEL) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::{BuildOptions, bulk_build};
+    use crate::cells::CellQuery;
+    use crate::search::spatial_search;
+    use crate::spatial::BoundingBox;
+    use crate::storage::{InMemoryLinker, NodeArena, NodeLinker};
+
+    #[test]
+    fn test_known_cell_ids() {
+        assert_eq!(
+            S2CellId::from_lon_lat(0.0, 0.0, 0).id(),
+            0x1000_0000_0000_0000
+        );
+        assert_eq!(
+            S2CellId::from_lon_lat(90.0, 0.0, 0).id(),
+            0x3000_0000_0000_0000
+        );
+        assert_eq!(
+            S2CellId::from_lon_lat(0.0, 90.0, 0).id(),
+            0x5000_0000_0000_0000
+        );
+        assert_eq!(S2CellId::from_lon_lat(0.0, -90.0, 0).face(), 5);
+
+        let nyc = S2CellId::from_lon_lat(-74.0060, 40.7128, 12);
+        assert_eq!(nyc.level(), 12);
+        assert!(nyc.to_token().starts_with("89c2"));
+        assert_eq!(S2CellId::from_token(&nyc.to_token()), Some(nyc));
+        assert_eq!(nyc.to_string(), nyc.to_token());
+    }
+
+    #[test]
+    fn test_invalid_ids() {
+        assert_eq!(S2CellId::new(0), None);
+        assert_eq!(S2CellId::new(0xe000_0000_0000_0000), None);
+        assert_eq!(S2CellId::new(0x1000_0000_0000_0002), None);
+        assert_eq!(S2CellId::from_token(""), None);
+        assert_eq!(S2CellId::from_token("89c2g"), None);
+        assert_eq!(S2CellId::from_token("00000000000000001"), None);
+    }
+
+    #[test]
+    fn test_hierarchy() {
+        let leaf = S2CellId::from_lon_lat(151.2093, -33.8688, MAX_LEVEL);
+        for level in 0..MAX_LEVEL {
+            let parent = leaf.parent(level);
+            assert_eq!(parent.level(), level);
+            assert!(parent.contains(leaf));
+            assert_eq!(S2CellId::from_lon_lat(151.2093, -33.8688, level), parent);
+        }
+        assert!(!leaf.contains(leaf.parent(10)));
+        let other = S2CellId::from_lon_lat(-151.2093, 33.8688, 10);
+        assert!(!other.contains(leaf));
+    }
+
+    #[test]
+    fn test_vertices_surround_point() {
+        for &(lon, lat) in &[(2.3522, 48.8566), (-74.0060, 40.7128), (179.99, -0.01)] {
+            let cell = S2CellId::from_lon_lat(lon, lat, 10);
+            let vertices = cell.vertices();
+            assert_eq!(vertices.len(), 4);
+            // A level-10 cell is about 10 km across
+            for (vlon, vlat) in vertices {
+                let dlon = (vlon - lon + 540.0) % 360.0 - 180.0;
+                assert!(dlon.abs() < 0.2 && (vlat - lat).abs() < 0.2);
+            }
+            assert!(cell.contains_point(lon, lat));
+        }
+    }
+
+    #[test]
+    fn test_cell_query_search() {
+        let mut arena = NodeArena::new();
+        let mut nodes = Vec::new();
+        for i in 0..900 {
+            let lon = -74.2 + (i % 30) as f64 * 0.015;
+            let lat = 40.5 + (i / 30) as f64 * 0.015;
+            nodes.push(arena.allocate(BoundingBox::new(lon, lat, lon, lat), i));
+        }
+        let mut linker = InMemoryLinker::new(&mut arena);
+        let root = bulk_build(&mut linker, &mut nodes, 0, &BuildOptions::default()).unwrap();
+
+        let center = S2CellId::from_lon_lat(-74.0060, 40.7128, 11);
+        let covering = [center, S2CellId::from_lon_lat(-73.9, 40.8, 12)];
+        let query = CellQuery::new(covering);
+        let mut found = spatial_search(&linker, root, &query, 0);
+        found.sort_unstable();
+        let expected: Vec<usize> = (0..900)
+            .filter(|&node| {
+                let point = linker.get_point(node);
+                let leaf = S2CellId::from_lon_lat(point.xmin, point.ymin, MAX_LEVEL);
+                covering.iter().any(|cell| cell.contains(leaf))
+            })
+            .collect();
+        assert!(!expected.is_empty());
+        assert_eq!(found, expected);
+    }
+}
@@ -1,7 +1,14 @@
 //! Spatial search algorithms and tree construction.
 
-use crate::spatial::{BoundingBox, Point, SpatialPoint};
+use crate::cancel::{self, CancellationToken, Cancelled};
+use crate::codec::FixedCodec;
+use crate::query::SpatialQuery;
+use crate::spatial::{BoundingBox, Point};
 use crate::storage::NodeLinker;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::io;
+use std::marker::PhantomData;
 
 /// Simple KD-tree insertion function demonstrating "tree tools" approach.
 /// Takes a linker and inserts a node into the tree using alternating dimensions.
@@ -45,7 +52,8 @@ pub fn insert_node<P: Point, T, L: NodeLinker<P, T>>(
 }
 
 /// Generic spatial search function for KD-tree using NodeLinker abstraction.
-/// Returns all nodes whose spatial data overlaps with or is within the query.
+/// Returns all nodes matching the query; for a `BoundingBox` query that means every node whose
+/// spatial data overlaps with or is within it. Any `SpatialQuery` shape (e.g. `Circle`) works.
 ///
 /// # Architecture
 /// This implements the same spatial pruning logic as bbox.rs but generically:
@@ -53,10 +61,17 @@ pub fn insert_node<P: Point, T, L: NodeLinker<P, T>>(
 /// - Employs dimensional pruning: only visits subtrees that could contain overlapping results
 /// - Alternates dimensions by depth: root splits on dim 0, children on dim 1, etc.
 /// - For 4D bounding boxes: [xmin, ymin, xmax, ymax] cycle through dimensions 0,1,2,3
-pub fn spatial_search<P: SpatialPoint, T, L: NodeLinker<P, T>>(
+/// - The query decides, per dimension, which value range can still match (`dimension_range`)
+///
+/// # Result Order
+/// Results are reported in pre-order: a node, then its left subtree, then its right subtree.
+/// This is deterministic for a given tree, but the same set of entries inserted in a different
+/// order produces a different tree shape and therefore a different result order. Use
+/// `spatial_search_ordered` when the order must not depend on tree shape.
+pub fn spatial_search<P: Point, T, L: NodeLinker<P, T>, Q: SpatialQuery<P>>(
     linker: &L,
     root: Option<L::NodeRef>,
-    query: &P,
+    query: &Q,
     depth: usize,
 ) -> Vec<L::NodeRef> {
     let mut results = Vec::new();
@@ -68,50 +83,591 @@ pub fn spatial_search<P: SpatialPoint, T, L: NodeLinker<P, T>>(
     results
 }
 
-fn spatial_search_recursive<P: SpatialPoint, T, L: NodeLinker<P, T>>(
+/// A search result with its point and payload resolved, borrowed from the linker.
+///
+/// Search functions return bare node references so they work with any caller-side
+/// bookkeeping; the `_matches` variants resolve each result once instead, so callers need
+/// no further linker calls. `distance` is set by nearest-neighbor searches.
+#[derive(Debug, PartialEq)]
+pub struct Match<'a, P, T, R = usize> {
+    pub node: R,
+    pub point: &'a P,
+    pub data: &'a T,
+    /// Distance to the search target, for searches that compute one.
+    pub distance: Option<f64>,
+}
+
+impl<'a, P: Point, T, R: Copy> Match<'a, P, T, R> {
+    /// Resolve a node's point and payload.
+    pub fn resolve<L: NodeLinker<P, T, NodeRef = R>>(linker: &'a L, node: R) -> Self {
+        Match {
+            node,
+            point: linker.get_point(node),
+            data: linker.get_data(node),
+            distance: None,
+        }
+    }
+}
+
+// Manual impls: the fields are references, so copying needs no bounds on P and T
+impl<P, T, R: Copy> Clone for Match<'_, P, T, R> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<P, T, R: Copy> Copy for Match<'_, P, T, R> {}
+
+/// `spatial_search` returning resolved `Match`es, in the same order.
+pub fn spatial_search_matches<'a, P: Point, T, L: NodeLinker<P, T>, Q: SpatialQuery<P>>(
+    linker: &'a L,
+    root: Option<L::NodeRef>,
+    query: &Q,
+    depth: usize,
+) -> Vec<Match<'a, P, T, L::NodeRef>> {
+    let nodes = spatial_search(linker, root, query, depth);
+    linker
+        .get_many(&nodes)
+        .zip(&nodes)
+        .map(|((point, data), &node)| Match {
+            node,
+            point,
+            data,
+            distance: None,
+        })
+        .collect()
+}
+
+fn spatial_search_recursive<P: Point, T, L: NodeLinker<P, T>, Q: SpatialQuery<P>>(
     linker: &L,
     node: L::NodeRef,
-    query: &P,
+    query: &Q,
     depth: usize,
     results: &mut Vec<L::NodeRef>,
 ) {
     let node_point = linker.get_point(node);
 
     // Check if this node should be included in results
-    // BEHAVIOR: Matches bbox.rs - collect nodes that are fully within OR partially overlap query
-    if node_point.is_within(query) || node_point.overlaps(query) {
+    if query.matches(node_point) {
         results.push(node);
     }
 
+    let (visit_left, visit_right) = children_to_visit(node_point, query, depth);
+
+    if let Some(left_child) = linker.get_left(node) {
+        if visit_left {
+            spatial_search_recursive(linker, left_child, query, depth + 1, results);
+        }
+    }
+
+    if let Some(right_child) = linker.get_right(node) {
+        if visit_right {
+            spatial_search_recursive(linker, right_child, query, depth + 1, results);
+        }
+    }
+}
+
+/// Decide which subtrees of a node could contain results for the query.
+/// Returns `(visit_left, visit_right)`; shared by every traversal so they prune identically.
+pub(crate) fn children_to_visit<P: Point, Q: SpatialQuery<P>>(
+    node_point: &P,
+    query: &Q,
+    depth: usize,
+) -> (bool, bool) {
     // DIMENSIONAL PRUNING: Determine which children to visit based on current dimension split
-    // This is the core optimization - only visit subtrees that could contain overlapping results
-    let dimension = depth % query.dimensions();
+    // This is the core optimization - only visit subtrees that could contain matching results
+    let dimension = depth % node_point.dimensions();
     let split_value = node_point.get_dimension(dimension);
 
-    // Get query bounds for this dimension - handles 4D bounding box logic
-    let query_min = query.get_dimension(dimension);
-    let query_max = if dimension < 2 {
-        // For min dimensions (xmin=0, ymin=1), check if query extends into this subspace
-        // Query [1,2,5,6] on xmin split: need to check if query.xmax >= split_value
-        query.get_dimension(dimension + 2) // xmax or ymax
+    // Range of values a matching entry could hold in this dimension, e.g. for a bounding box
+    // query [1,2,5,6] an overlapping entry must have xmin <= 5 but may have any xmin below it
+    let (query_min, query_max) = query.dimension_range(dimension);
+
+    // PRUNING LOGIC: Only recurse if the range reaches into that subspace
+    // Left subtree: contains values <= split_value
+    // Right subtree: contains values >= split_value
+    (query_min <= split_value, query_max >= split_value)
+}
+
+/// Result ordering applied by `spatial_search_ordered`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResultOrder {
+    /// Tree pre-order, exactly as `spatial_search` reports it. Depends on tree shape.
+    Traversal,
+    /// Ascending by node reference. For arena-backed trees this is allocation order,
+    /// which is independent of tree shape.
+    NodeRef,
+    /// Ascending by the value of a single dimension, ties broken by node reference.
+    Dimension(usize),
+}
+
+/// Spatial search with a guaranteed, documented result order.
+/// Finds the same nodes as `spatial_search`, then sorts them according to `order`, so snapshot
+/// tests and callers that paginate over a materialized result list see a stable sequence.
+pub fn spatial_search_ordered<P: Point, T, L: NodeLinker<P, T>, Q: SpatialQuery<P>>(
+    linker: &L,
+    root: Option<L::NodeRef>,
+    query: &Q,
+    depth: usize,
+    order: ResultOrder,
+) -> Vec<L::NodeRef>
+where
+    L::NodeRef: Ord,
+{
+    let mut results = spatial_search(linker, root, query, depth);
+    sort_results(linker, &mut results, order);
+    results
+}
+
+/// Sort a list of node references according to `order`.
+/// Coordinates are compared with `f64::total_cmp`, so NaN values sort deterministically too.
+pub fn sort_results<P: Point, T, L: NodeLinker<P, T>>(
+    linker: &L,
+    results: &mut [L::NodeRef],
+    order: ResultOrder,
+) where
+    L::NodeRef: Ord,
+{
+    match order {
+        ResultOrder::Traversal => {}
+        ResultOrder::NodeRef => results.sort_unstable(),
+        ResultOrder::Dimension(dim) => results.sort_unstable_by(|&a, &b| {
+            let a_value = linker.get_point(a).get_dimension(dim);
+            let b_value = linker.get_point(b).get_dimension(dim);
+            a_value.total_cmp(&b_value).then(a.cmp(&b))
+        }),
+    }
+}
+
+/// Opaque traversal position for paginated spatial search.
+///
+/// # Architecture
+/// Follows the `search_after` pattern: instead of an offset (which forces the tree to be
+/// re-walked from the root on every page), the cursor holds the pending DFS stack of
+/// `(node, depth)` frames. Resuming pops the next frame, so each page costs only the nodes
+/// it actually visits. A cursor is tied to the tree it was produced from; inserting into
+/// the tree between pages may cause new nodes to be missed.
+///
+/// A cursor can also be encoded with `to_bytes` and resumed later, in another process, by
+/// `from_bytes`, so a stateless server can hand it to its client as a page token. The
+/// encoding is bound to an identifier of the tree, such as a `tree_digest`, and is
+/// rejected for any other tree.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchCursor<R> {
+    pub(crate) stack: Vec<(R, usize)>,
+}
+
+/// Magic bytes identifying an encoded `SearchCursor`.
+const CURSOR_MAGIC: [u8; 4] = *b"BKDC";
+
+/// Size of the encoded cursor header, little-endian: magic at bytes 0..4, frame count
+/// (`u32`) at 4..8 and tree identifier (`u64`) at 8..16.
+const CURSOR_HEADER_SIZE: usize = 16;
+
+impl<R: Copy> SearchCursor<R> {
+    /// Create a cursor positioned before the first result of a search starting at `root`.
+    pub fn new(root: Option<R>, depth: usize) -> Self {
+        SearchCursor {
+            stack: root.map(|node| (node, depth)).into_iter().collect(),
+        }
+    }
+
+    /// Check if the traversal has no remaining nodes to visit.
+    pub fn is_exhausted(&self) -> bool {
+        self.stack.is_empty()
+    }
+}
+
+impl<R: Copy + FixedCodec> SearchCursor<R> {
+    /// Encode the cursor for the tree identified by `tree`.
+    ///
+    /// The encoding holds the pending `(node, depth)` frames, `R::SIZE + 8` bytes each,
+    /// after a 16-byte header. It is not authenticated: a server resuming cursors from
+    /// untrusted clients should check the node references against its tree.
+    pub fn to_bytes(&self, tree: u64) -> Vec<u8> {
+        let frame_size = R::SIZE + 8;
+        let mut bytes = vec![0u8; CURSOR_HEADER_SIZE + self.stack.len() * frame_size];
+        bytes[0..4].copy_from_slice(&CURSOR_MAGIC);
+        (self.stack.len() as u32).encode(&mut bytes[4..8]);
+        tree.encode(&mut bytes[8..16]);
+        for (&(node, depth), frame) in self
+            .stack
+            .iter()
+            .zip(bytes[CURSOR_HEADER_SIZE..].chunks_exact_mut(frame_size))
+        {
+            node.encode(&mut frame[..R::SIZE]);
+            (depth as u64).encode(&mut frame[R::SIZE..]);
+        }
+        bytes
+    }
+
+    /// Decode a cursor encoded by `to_bytes` for the same `tree`.
+    ///
+    /// Fails with `InvalidData` if the bytes are not an encoded cursor, or were encoded for
+    /// another tree.
+    pub fn from_bytes(bytes: &[u8], tree: u64) -> io::Result<Self> {
+        let invalid =
+            |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+        if bytes.len() < CURSOR_HEADER_SIZE || bytes[0..4] != CURSOR_MAGIC {
+            return Err(invalid("not an encoded search cursor"));
+        }
+        if u64::decode(&bytes[8..16]) != tree {
+            return Err(invalid("search cursor was encoded for another tree"));
+        }
+        let frame_size = R::SIZE + 8;
+        let frames = u32::decode(&bytes[4..8]) as usize;
+        let body = &bytes[CURSOR_HEADER_SIZE..];
+        if body.len() != frames * frame_size {
+            return Err(invalid(
+                "search cursor length does not match its frame count",
+            ));
+        }
+        let stack = body
+            .chunks_exact(frame_size)
+            .map(|frame| {
+                let depth = u64::decode(&frame[R::SIZE..]);
+                let depth = usize::try_from(depth)
+                    .map_err(|_| invalid("search cursor depth out of range"))?;
+                Ok((R::decode(&frame[..R::SIZE]), depth))
+            })
+            .collect::<io::Result<_>>()?;
+        Ok(SearchCursor { stack })
+    }
+}
+
+/// One page of spatial search results.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchPage<R> {
+    /// Matching nodes, in the same order `spatial_search` would report them.
+    pub results: Vec<R>,
+    /// Cursor for the following page, or `None` when the search is complete.
+    pub next: Option<SearchCursor<R>>,
+}
+
+/// Paginated variant of `spatial_search`.
+/// Collects up to `limit` matches starting at `cursor` and returns them with a cursor for the
+/// next page. Concatenating all pages yields exactly the results of `spatial_search`.
+///
+/// Every page but the last holds exactly `limit` results, so paging always advances.
+///
+/// # Panics
+/// Panics if `limit` is 0: such a page would return its own cursor as `next`, and a loop
+/// over the pages would never end.
+///
+/// # Usage pattern:
+/// ```rust
+/// use bkd::{BoundingBox, InMemoryLinker, NodeArena, SearchCursor, insert_node, spatial_search_page};
+///
+/// let mut arena = NodeArena::new();
+/// let a = arena.allocate(BoundingBox::new(1.0, 1.0, 2.0, 2.0), 1);
+/// let b = arena.allocate(BoundingBox::new(3.0, 3.0, 4.0, 4.0), 2);
+/// let mut linker = InMemoryLinker::new(&mut arena);
+/// let root = insert_node(&mut linker, None, a, 0);
+/// insert_node(&mut linker, Some(root), b, 0);
+///
+/// let query = BoundingBox::new(0.0, 0.0, 5.0, 5.0);
+/// let mut cursor = Some(SearchCursor::new(Some(root), 0));
+/// while let Some(position) = cursor {
+///     let page = spatial_search_page(&linker, &query, position, 1);
+///     // ... serve page.results ...
+///     cursor = page.next;
+/// }
+/// ```
+pub fn spatial_search_page<P: Point, T, L: NodeLinker<P, T>, Q: SpatialQuery<P>>(
+    linker: &L,
+    query: &Q,
+    cursor: SearchCursor<L::NodeRef>,
+    limit: usize,
+) -> SearchPage<L::NodeRef> {
+    assert!(limit > 0, "search pages must hold at least one result");
+    let mut stack = cursor.stack;
+    let mut results = Vec::new();
+    drain_search_stack(linker, query, &mut stack, &mut results, limit, None)
+        .expect("search without a cancellation token cannot be cancelled");
+
+    let next = if stack.is_empty() {
+        None
     } else {
-        // For max dimensions (xmax=2, ymax=3), query bound is the same coordinate
-        // Query [1,2,5,6] on xmax split: check if query.xmax >= split_value
-        query_min
+        Some(SearchCursor { stack })
     };
 
-    // PRUNING LOGIC: Only recurse if query could overlap that subspace
-    // Left subtree: contains values <= split_value
-    if let Some(left_child) = linker.get_left(node) {
-        if query_min <= split_value {
-            spatial_search_recursive(linker, left_child, query, depth + 1, results);
+    SearchPage { results, next }
+}
+
+/// Spatial search that can be aborted through a cancellation token.
+/// The token is checked every `CANCEL_CHECK_INTERVAL` visited nodes, so even queries that
+/// visit many nodes but match few of them stop promptly. Results are in the same order as
+/// `spatial_search`.
+pub fn spatial_search_cancellable<P: Point, T, L: NodeLinker<P, T>, Q: SpatialQuery<P>>(
+    linker: &L,
+    root: Option<L::NodeRef>,
+    query: &Q,
+    depth: usize,
+    cancel: &CancellationToken,
+) -> Result<Vec<L::NodeRef>, Cancelled> {
+    let mut stack = SearchCursor::new(root, depth).stack;
+    let mut results = Vec::new();
+    drain_search_stack(
+        linker,
+        query,
+        &mut stack,
+        &mut results,
+        usize::MAX,
+        Some(cancel),
+    )?;
+    Ok(results)
+}
+
+/// Number of visited nodes between cancellation checks in iterative searches.
+const CANCEL_CHECK_INTERVAL: usize = 1024;
+
+/// Iterative pre-order search driven by an explicit stack of `(node, depth)` frames.
+/// Stops once `results` holds `limit` entries, leaving the remaining frames on the stack.
+pub(crate) fn drain_search_stack<P: Point, T, L: NodeLinker<P, T>, Q: SpatialQuery<P>>(
+    linker: &L,
+    query: &Q,
+    stack: &mut Vec<(L::NodeRef, usize)>,
+    results: &mut Vec<L::NodeRef>,
+    limit: usize,
+    cancel: Option<&CancellationToken>,
+) -> Result<(), Cancelled> {
+    let mut visited = 0;
+    while results.len() < limit {
+        if visited % CANCEL_CHECK_INTERVAL == 0 {
+            cancel::check(cancel)?;
+        }
+        visited += 1;
+
+        let Some((node, depth)) = stack.pop() else {
+            break;
+        };
+        let node_point = linker.get_point(node);
+
+        if query.matches(node_point) {
+            results.push(node);
+        }
+
+        // Push right before left so the left subtree is visited first, matching the
+        // pre-order of the recursive search
+        let (visit_left, visit_right) = children_to_visit(node_point, query, depth);
+        if let Some(right_child) = linker.get_right(node) {
+            if visit_right {
+                stack.push((right_child, depth + 1));
+            }
+        }
+        if let Some(left_child) = linker.get_left(node) {
+            if visit_left {
+                stack.push((left_child, depth + 1));
+            }
         }
     }
+    Ok(())
+}
 
-    // Right subtree: contains values >= split_value
-    if let Some(right_child) = linker.get_right(node) {
-        if query_max >= split_value {
-            spatial_search_recursive(linker, right_child, query, depth + 1, results);
+/// Heap entry for `DimensionScan`: either a node ready to be yielded or a subtree still to
+/// be expanded. `key` is the node's value or the smallest value the subtree can contain.
+struct ScanItem<R> {
+    key: f64,
+    seq: u64,
+    kind: ScanKind<R>,
+}
+
+enum ScanKind<R> {
+    Entry(R),
+    Subtree { node: R, depth: usize, upper: f64 },
+}
+
+impl<R> PartialEq for ScanItem<R> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<R> Eq for ScanItem<R> {}
+
+impl<R> PartialOrd for ScanItem<R> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<R> Ord for ScanItem<R> {
+    /// Reversed so `BinaryHeap` (a max-heap) pops the smallest key first.
+    /// `seq` breaks ties in insertion order, keeping the scan deterministic.
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .key
+            .total_cmp(&self.key)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+/// Iterator over nodes in ascending order of one dimension, restricted to a value range.
+///
+/// # Architecture
+/// A 1D range scan that reuses the KD structure instead of sorting the whole tree:
+/// - Subtrees wait in a min-heap keyed by the smallest value they could contain, which is
+///   known from the splits on `dim` passed on the way down (right of a split => >= split)
+/// - Splits on `dim` also bound subtrees from above (left of a split => <= split), so whole
+///   subtrees outside `[min, max]` are never expanded
+/// - A node is yielded only once nothing left in the heap can hold a smaller value
+///
+/// Memory use is proportional to the frontier of the scan, and consuming only the first few
+/// items (e.g. "the 10 smallest xmin values above 3.0") only touches the nodes needed.
+pub struct DimensionScan<'a, P: Point, T, L: NodeLinker<P, T>> {
+    linker: &'a L,
+    dim: usize,
+    min: f64,
+    max: f64,
+    heap: BinaryHeap<ScanItem<L::NodeRef>>,
+    seq: u64,
+    _marker: PhantomData<(P, T)>,
+}
+
+/// Scan all nodes whose value in `dim` lies in `[min, max]`, in ascending order of that value.
+/// Nodes with equal values are yielded in a deterministic, tree-dependent order.
+pub fn dimension_scan<'a, P: Point, T, L: NodeLinker<P, T>>(
+    linker: &'a L,
+    root: Option<L::NodeRef>,
+    depth: usize,
+    dim: usize,
+    min: f64,
+    max: f64,
+) -> DimensionScan<'a, P, T, L> {
+    let mut scan = DimensionScan {
+        linker,
+        dim,
+        min,
+        max,
+        heap: BinaryHeap::new(),
+        seq: 0,
+        _marker: PhantomData,
+    };
+    if let Some(node) = root {
+        scan.push(
+            f64::NEG_INFINITY,
+            ScanKind::Subtree {
+                node,
+                depth,
+                upper: f64::INFINITY,
+            },
+        );
+    }
+    scan
+}
+
+impl<'a, P: Point, T, L: NodeLinker<P, T>> DimensionScan<'a, P, T, L> {
+    fn push(&mut self, key: f64, kind: ScanKind<L::NodeRef>) {
+        self.heap.push(ScanItem {
+            key,
+            seq: self.seq,
+            kind,
+        });
+        self.seq += 1;
+    }
+
+    /// Queue a child subtree if its value bounds can still intersect `[min, max]`.
+    fn push_subtree(&mut self, node: L::NodeRef, depth: usize, lower: f64, upper: f64) {
+        if lower <= self.max && upper >= self.min {
+            self.push(lower, ScanKind::Subtree { node, depth, upper });
+        }
+    }
+}
+
+impl<'a, P: Point, T, L: NodeLinker<P, T>> Iterator for DimensionScan<'a, P, T, L> {
+    type Item = L::NodeRef;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(item) = self.heap.pop() {
+            let (node, depth, upper) = match item.kind {
+                ScanKind::Entry(node) => return Some(node),
+                ScanKind::Subtree { node, depth, upper } => (node, depth, upper),
+            };
+            let lower = item.key;
+
+            let point = self.linker.get_point(node);
+            let value = point.get_dimension(self.dim);
+            if value >= self.min && value <= self.max {
+                self.push(value, ScanKind::Entry(node));
+            }
+
+            // Only splits on the scanned dimension tighten the children's bounds
+            let splits_on_dim = depth % point.dimensions() == self.dim;
+            let (left_upper, right_lower) = if splits_on_dim {
+                (upper.min(value), lower.max(value))
+            } else {
+                (upper, lower)
+            };
+
+            if let Some(left_child) = self.linker.get_left(node) {
+                self.push_subtree(left_child, depth + 1, lower, left_upper);
+            }
+            if let Some(right_child) = self.linker.get_right(node) {
+                self.push_subtree(right_child, depth + 1, right_lower, upper);
+            }
+        }
+        None
+    }
+}
+
+/// Iterator over every entry of a tree, as owned `(point, data)` pairs.
+///
+/// The tree is walked in pre-order with an explicit stack. Only the right sibling of each
+/// node on the current path waits on it, so memory stays proportional to the depth of the
+/// tree rather than its size, and a degenerate tree cannot overflow the call stack.
+pub struct ExportAll<'a, P: Point, T, L: NodeLinker<P, T>> {
+    linker: &'a L,
+    stack: Vec<L::NodeRef>,
+    _marker: PhantomData<(P, T)>,
+}
+
+/// Stream every entry reachable from `root`, e.g. to back a tree up, migrate it to a new
+/// format or rebuild it with `bulk_build`. Entries come in pre-order: each node before its
+/// left subtree, and the left subtree before the right one.
+pub fn export_all<'a, P: Point + Clone, T: Clone, L: NodeLinker<P, T>>(
+    linker: &'a L,
+    root: Option<L::NodeRef>,
+) -> ExportAll<'a, P, T, L> {
+    ExportAll {
+        linker,
+        stack: root.into_iter().collect(),
+        _marker: PhantomData,
+    }
+}
+
+impl<'a, P: Point + Clone, T: Clone, L: NodeLinker<P, T>> Iterator for ExportAll<'a, P, T, L> {
+    type Item = (P, T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        self.stack.extend(self.linker.get_right(node));
+        self.stack.extend(self.linker.get_left(node));
+        Some((
+            self.linker.get_point(node).clone(),
+            self.linker.get_data(node).clone(),
+        ))
+    }
+}
+
+/// Limits on what `tree_to_svg_with_options` draws.
+///
+/// Rendering walks the tree with an explicit stack, so depth alone cannot overflow it,
+/// but a degenerate or huge tree still produces an unreadable, unbounded document. Nodes
+/// deeper than `max_depth` are skipped with their subtrees, and drawing stops after
+/// `max_nodes`; a truncated image says so in its bottom-left corner.
+#[derive(Debug, Clone)]
+pub struct SvgOptions {
+    /// Deepest level drawn; the root is depth 0.
+    pub max_depth: usize,
+    /// Most nodes drawn.
+    pub max_nodes: usize,
+}
+
+impl Default for SvgOptions {
+    fn default() -> Self {
+        SvgOptions {
+            max_depth: 64,
+            max_nodes: 100_000,
         }
     }
 }
@@ -125,12 +681,28 @@ fn spatial_search_recursive<P: SpatialPoint, T, L: NodeLinker<P, T>>(
 /// - Colors nodes by depth to show KD-tree splitting pattern
 /// - Shows spatial relationships between bounding boxes
 /// - Displays data IDs for each node
+///
+/// Draws within the default `SvgOptions` limits.
 pub fn tree_to_svg<T, L: NodeLinker<BoundingBox, T>>(
     linker: &L,
     root: Option<L::NodeRef>,
     width: u32,
     height: u32,
 ) -> String
+where
+    T: std::fmt::Display,
+{
+    tree_to_svg_with_options(linker, root, width, height, &SvgOptions::default())
+}
+
+/// `tree_to_svg` with explicit depth and size limits.
+pub fn tree_to_svg_with_options<T, L: NodeLinker<BoundingBox, T>>(
+    linker: &L,
+    root: Option<L::NodeRef>,
+    width: u32,
+    height: u32,
+    options: &SvgOptions,
+) -> String
 where
     T: std::fmt::Display,
 {
@@ -172,13 +744,32 @@ where
     ));
 
     if let Some(root_ref) = root {
-        render_tree_node_svg(linker, root_ref, 0, &bounds, width, height, &mut svg);
+        let drawn = render_tree_svg(linker, root_ref, &bounds, width, height, options, &mut svg);
+        if drawn.truncated {
+            svg.push_str(&format!(
+                r#"<text x="4" y="{}" class="data-text">Truncated: {} nodes drawn (max depth {}, max nodes {})</text>
+"#,
+                height.saturating_sub(4),
+                drawn.nodes,
+                options.max_depth,
+                options.max_nodes
+            ));
+        }
     }
 
     svg.push_str("</svg>");
     svg
 }
 
+/// World-space bounds `tree_to_svg` maps onto the image, or `None` for an empty tree.
+/// Pass these to `add_query_to_svg` so overlays line up with the rendered nodes.
+pub fn tree_svg_bounds<T, L: NodeLinker<BoundingBox, T>>(
+    linker: &L,
+    root: Option<L::NodeRef>,
+) -> Option<BoundingBox> {
+    root.map(|root| calculate_tree_bounds(linker, root))
+}
+
 /// Calculate the bounding box that contains all nodes in the tree
 fn calculate_tree_bounds<T, L: NodeLinker<BoundingBox, T>>(
     linker: &L,
@@ -188,7 +779,11 @@ fn calculate_tree_bounds<T, L: NodeLinker<BoundingBox, T>>(
     let mut bounds = root_point.clone();
 
     expand_tree_bounds(linker, root, &mut bounds);
+    pad_svg_bounds(&bounds)
+}
 
+/// Pad world-space bounds so shapes on the edge stay inside the image
+pub(crate) fn pad_svg_bounds(bounds: &BoundingBox) -> BoundingBox {
     // Add padding - expand bounds by 10%
     let mut padded_bounds = bounds.clone();
     for dim in 0..bounds.dimensions() {
@@ -208,28 +803,98 @@ fn calculate_tree_bounds<T, L: NodeLinker<BoundingBox, T>>(
     padded_bounds
 }
 
+/// Map a world-space box onto the image as `(x, y, width, height)`
+pub(crate) fn svg_rect(
+    bbox: &BoundingBox,
+    bounds: &BoundingBox,
+    svg_width: u32,
+    svg_height: u32,
+) -> (f64, f64, f64, f64) {
+    // 4D bounding box format: [xmin, ymin, xmax, ymax]
+    let xmin = bbox.get_dimension(0);
+    let ymin = bbox.get_dimension(1);
+    let xmax = bbox.get_dimension(2);
+    let ymax = bbox.get_dimension(3);
+
+    let bounds_xmin = bounds.get_dimension(0);
+    let bounds_ymin = bounds.get_dimension(1);
+    let bounds_xmax = bounds.get_dimension(2);
+    let bounds_ymax = bounds.get_dimension(3);
+
+    // Transform coordinates from world space to SVG space
+    let x1 = ((xmin - bounds_xmin) / (bounds_xmax - bounds_xmin)) * svg_width as f64;
+    let y1 = ((bounds_ymax - ymax) / (bounds_ymax - bounds_ymin)) * svg_height as f64; // Flip Y
+    let x2 = ((xmax - bounds_xmin) / (bounds_xmax - bounds_xmin)) * svg_width as f64;
+    let y2 = ((bounds_ymax - ymin) / (bounds_ymax - bounds_ymin)) * svg_height as f64; // Flip Y
+
+    (x1, y1, x2 - x1, y2 - y1)
+}
+
 /// Expand bounds to include all nodes in the subtree
+///
+/// Covers the whole tree whatever `SvgOptions` cut from the drawing, so the bounds do not
+/// depend on the limits and query overlays line up either way.
 fn expand_tree_bounds<T, L: NodeLinker<BoundingBox, T>>(
     linker: &L,
     node: L::NodeRef,
     bounds: &mut BoundingBox,
 ) {
-    let node_point = linker.get_point(node);
+    let mut stack = vec![node];
+    while let Some(node) = stack.pop() {
+        // Use the union method to expand bounds
+        *bounds = bounds.union(linker.get_point(node));
+        stack.extend(linker.get_left(node));
+        stack.extend(linker.get_right(node));
+    }
+}
 
-    // Use the union method to expand bounds
-    *bounds = bounds.union(&node_point);
+/// What `render_tree_svg` drew.
+struct RenderedNodes {
+    nodes: usize,
+    truncated: bool,
+}
 
-    // Recursively expand for children
-    if let Some(left_child) = linker.get_left(node) {
-        expand_tree_bounds(linker, left_child, bounds);
-    }
-    if let Some(right_child) = linker.get_right(node) {
-        expand_tree_bounds(linker, right_child, bounds);
+/// Render the tree in pre-order, as the recursive renderer did, within `options`
+fn render_tree_svg<T, L: NodeLinker<BoundingBox, T>>(
+    linker: &L,
+    root: L::NodeRef,
+    bounds: &BoundingBox,
+    svg_width: u32,
+    svg_height: u32,
+    options: &SvgOptions,
+    svg: &mut String,
+) -> RenderedNodes
+where
+    T: std::fmt::Display,
+{
+    let mut drawn = RenderedNodes {
+        nodes: 0,
+        truncated: false,
+    };
+    let mut stack = vec![(root, 0)];
+    while let Some((node, depth)) = stack.pop() {
+        if drawn.nodes == options.max_nodes {
+            drawn.truncated = true;
+            break;
+        }
+        render_node_svg(linker, node, depth, bounds, svg_width, svg_height, svg);
+        drawn.nodes += 1;
+
+        // Push right first so the left subtree is drawn first
+        let children = [linker.get_right(node), linker.get_left(node)];
+        for child in children.into_iter().flatten() {
+            if depth < options.max_depth {
+                stack.push((child, depth + 1));
+            } else {
+                drawn.truncated = true;
+            }
+        }
     }
+    drawn
 }
 
-/// Render a single node and its children recursively
-fn render_tree_node_svg<T, L: NodeLinker<BoundingBox, T>>(
+/// Render a single node's rectangle and data label
+fn render_node_svg<T, L: NodeLinker<BoundingBox, T>>(
     linker: &L,
     node: L::NodeRef,
     depth: usize,
@@ -240,27 +905,7 @@ fn render_tree_node_svg<T, L: NodeLinker<BoundingBox, T>>(
 ) where
     T: std::fmt::Display,
 {
-    let node_point = linker.get_point(node);
-
-    // 4D bounding box format: [xmin, ymin, xmax, ymax]
-    let xmin = node_point.get_dimension(0);
-    let ymin = node_point.get_dimension(1);
-    let xmax = node_point.get_dimension(2);
-    let ymax = node_point.get_dimension(3);
-
-    let bounds_xmin = bounds.get_dimension(0);
-    let bounds_ymin = bounds.get_dimension(1);
-    let bounds_xmax = bounds.get_dimension(2);
-    let bounds_ymax = bounds.get_dimension(3);
-
-    // Transform coordinates from world space to SVG space
-    let x1 = ((xmin - bounds_xmin) / (bounds_xmax - bounds_xmin)) * svg_width as f64;
-    let y1 = ((bounds_ymax - ymax) / (bounds_ymax - bounds_ymin)) * svg_height as f64; // Flip Y
-    let x2 = ((xmax - bounds_xmin) / (bounds_xmax - bounds_xmin)) * svg_width as f64;
-    let y2 = ((bounds_ymax - ymin) / (bounds_ymax - bounds_ymin)) * svg_height as f64; // Flip Y
-
-    let width = x2 - x1;
-    let height = y2 - y1;
+    let (x1, y1, width, height) = svg_rect(linker.get_point(node), bounds, svg_width, svg_height);
 
     // Draw rectangle
     svg.push_str(&format!(
@@ -282,30 +927,6 @@ fn render_tree_node_svg<T, L: NodeLinker<BoundingBox, T>>(
 "#,
         text_x, text_y, data_ref
     ));
-
-    // Recursively render children
-    if let Some(left_child) = linker.get_left(node) {
-        render_tree_node_svg(
-            linker,
-            left_child,
-            depth + 1,
-            bounds,
-            svg_width,
-            svg_height,
-            svg,
-        );
-    }
-    if let Some(right_child) = linker.get_right(node) {
-        render_tree_node_svg(
-            linker,
-            right_child,
-            depth + 1,
-            bounds,
-            svg_width,
-            svg_height,
-            svg,
-        );
-    }
 }
 
 /// Add a query box overlay to existing SVG
@@ -353,3 +974,320 @@ pub fn add_query_to_svg(
 
     svg.insert_str(closing_tag_pos, &query_rect);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{InMemoryLinker, NodeArena, NodeLinker};
+
+    #[test]
+    fn test_paginated_search_matches_full_search() {
+        let mut arena = NodeArena::new();
+        let refs: Vec<usize> = (0..20)
+            .map(|i| {
+                let offset = (i * 7 % 20) as f64;
+                arena.allocate(
+                    BoundingBox::new(offset, offset, offset + 1.0, offset + 1.0),
+                    i,
+                )
+            })
+            .collect();
+
+        let mut linker = InMemoryLinker::new(&mut arena);
+        let root = insert_node(&mut linker, None, refs[0], 0);
+        for &node in &refs[1..] {
+            insert_node(&mut linker, Some(root), node, 0);
+        }
+
+        let query = BoundingBox::new(2.5, 2.5, 15.5, 15.5);
+        let expected = spatial_search(&linker, Some(root), &query, 0);
+
+        let mut paged = Vec::new();
+        let mut cursor = Some(SearchCursor::new(Some(root), 0));
+        while let Some(position) = cursor {
+            let page = spatial_search_page(&linker, &query, position, 3);
+            assert!(page.results.len() <= 3);
+            paged.extend(page.results);
+            cursor = page.next;
+        }
+
+        assert_eq!(paged, expected);
+    }
+
+    #[test]
+    fn test_search_matches_resolve_results() {
+        let mut arena = NodeArena::new();
+        let refs: Vec<usize> = (0..10)
+            .map(|i| {
+                let x = (i * 3 % 10) as f64;
+                arena.allocate(
+                    BoundingBox::new(x, x, x + 1.0, x + 1.0),
+                    format!("entry {i}"),
+                )
+            })
+            .collect();
+        let mut linker = InMemoryLinker::new(&mut arena);
+        let root = insert_node(&mut linker, None, refs[0], 0);
+        for &node in &refs[1..] {
+            insert_node(&mut linker, Some(root), node, 0);
+        }
+
+        let query = BoundingBox::new(2.5, 2.5, 5.5, 5.5);
+        let nodes = spatial_search(&linker, Some(root), &query, 0);
+        let matches = spatial_search_matches(&linker, Some(root), &query, 0);
+        assert_eq!(matches.len(), nodes.len());
+        for (found, &node) in matches.iter().zip(&nodes) {
+            assert_eq!(found.node, node);
+            assert_eq!(found.data, &format!("entry {node}"));
+            assert_eq!(found.point, linker.get_point(node));
+            assert_eq!(found.distance, None);
+        }
+        // Copyable even though the payload is not
+        let first = matches[0];
+        assert_eq!(first, matches[0]);
+    }
+
+    #[test]
+    fn test_ordered_search_independent_of_insertion_order() {
+        let boxes = [
+            BoundingBox::new(5.0, 5.0, 6.0, 6.0),
+            BoundingBox::new(1.0, 1.0, 2.0, 2.0),
+            BoundingBox::new(3.0, 3.0, 4.0, 4.0),
+            BoundingBox::new(7.0, 7.0, 8.0, 8.0),
+        ];
+        let query = BoundingBox::new(0.0, 0.0, 10.0, 10.0);
+
+        let mut orderings = Vec::new();
+        for insertion in [[0, 1, 2, 3], [3, 2, 1, 0]] {
+            let mut arena = NodeArena::new();
+            for (id, bbox) in boxes.iter().enumerate() {
+                arena.allocate(bbox.clone(), id);
+            }
+            let mut linker = InMemoryLinker::new(&mut arena);
+            let root = insert_node(&mut linker, None, insertion[0], 0);
+            for &node in &insertion[1..] {
+                insert_node(&mut linker, Some(root), node, 0);
+            }
+
+            let by_ref =
+                spatial_search_ordered(&linker, Some(root), &query, 0, ResultOrder::NodeRef);
+            assert_eq!(by_ref, vec![0, 1, 2, 3]);
+
+            let by_xmin =
+                spatial_search_ordered(&linker, Some(root), &query, 0, ResultOrder::Dimension(0));
+            orderings.push(by_xmin);
+        }
+
+        assert_eq!(orderings[0], vec![1, 2, 0, 3]);
+        assert_eq!(orderings[0], orderings[1]);
+    }
+
+    #[test]
+    fn test_search_finds_wide_box_left_of_split() {
+        // The wide box has a smaller xmin than the root, so it lands in the left subtree even
+        // though it extends far to the right of the split value
+        let mut arena = NodeArena::new();
+        let root_ref = arena.allocate(BoundingBox::new(5.0, 5.0, 7.0, 7.0), 1);
+        let wide_ref = arena.allocate(BoundingBox::new(0.0, 0.0, 100.0, 100.0), 2);
+
+        let mut linker = InMemoryLinker::new(&mut arena);
+        let root = insert_node(&mut linker, None, root_ref, 0);
+        insert_node(&mut linker, Some(root), wide_ref, 0);
+
+        let query = BoundingBox::new(50.0, 50.0, 60.0, 60.0);
+        assert_eq!(
+            spatial_search(&linker, Some(root), &query, 0),
+            vec![wide_ref]
+        );
+    }
+
+    #[test]
+    fn test_dimension_scan_sorted_range() {
+        let mut arena = NodeArena::new();
+        let xmins = [5.0, 2.0, 8.0, 1.0, 9.0, 3.0, 7.0, 4.0, 6.0, 0.0];
+        let refs: Vec<usize> = xmins
+            .iter()
+            .map(|&x| arena.allocate(BoundingBox::new(x, 10.0 - x, x + 1.0, 11.0 - x), x))
+            .collect();
+
+        let mut linker = InMemoryLinker::new(&mut arena);
+        let root = insert_node(&mut linker, None, refs[0], 0);
+        for &node in &refs[1..] {
+            insert_node(&mut linker, Some(root), node, 0);
+        }
+
+        let scanned: Vec<f64> = dimension_scan(&linker, Some(root), 0, 0, 2.5, 7.0)
+            .map(|node| linker.get_point(node).xmin)
+            .collect();
+        assert_eq!(scanned, vec![3.0, 4.0, 5.0, 6.0, 7.0]);
+
+        // Scanning a dimension the root does not split on still yields sorted values
+        let ymins: Vec<f64> = dimension_scan(&linker, Some(root), 0, 1, f64::MIN, f64::MAX)
+            .map(|node| linker.get_point(node).ymin)
+            .collect();
+        assert_eq!(ymins, (1..=10).map(f64::from).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_cancellable_search() {
+        let mut arena = NodeArena::new();
+        let refs: Vec<usize> = (0..50)
+            .map(|i| arena.allocate(BoundingBox::new(i as f64, 0.0, i as f64 + 1.0, 1.0), i))
+            .collect();
+        let mut linker = InMemoryLinker::new(&mut arena);
+        let root = insert_node(&mut linker, None, refs[0], 0);
+        for &node in &refs[1..] {
+            insert_node(&mut linker, Some(root), node, 0);
+        }
+
+        let query = BoundingBox::new(10.0, 0.0, 20.0, 1.0);
+        let token = CancellationToken::new();
+        assert_eq!(
+            spatial_search_cancellable(&linker, Some(root), &query, 0, &token),
+            Ok(spatial_search(&linker, Some(root), &query, 0))
+        );
+
+        token.cancel();
+        assert_eq!(
+            spatial_search_cancellable(&linker, Some(root), &query, 0, &token),
+            Err(Cancelled)
+        );
+    }
+
+    #[test]
+    fn test_paginated_search_empty_tree() {
+        let mut arena: NodeArena<BoundingBox, u32> = NodeArena::new();
+        let linker = InMemoryLinker::new(&mut arena);
+        let cursor = SearchCursor::new(None, 0);
+        assert!(cursor.is_exhausted());
+
+        let page = spatial_search_page(&linker, &BoundingBox::new(0.0, 0.0, 1.0, 1.0), cursor, 10);
+        assert!(page.results.is_empty());
+        assert!(page.next.is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "search pages must hold at least one result")]
+    fn test_paginated_search_rejects_empty_pages() {
+        let mut arena = NodeArena::new();
+        let root = arena.allocate(BoundingBox::new(0.0, 0.0, 1.0, 1.0), 0);
+        let linker = InMemoryLinker::new(&mut arena);
+        let query = BoundingBox::new(0.0, 0.0, 1.0, 1.0);
+        spatial_search_page(&linker, &query, SearchCursor::new(Some(root), 0), 0);
+    }
+
+    #[test]
+    fn test_svg_renders_degenerate_tree_within_limits() {
+        // A right-leaning chain deep enough to overflow a recursive walk
+        let mut arena = NodeArena::new();
+        let refs: Vec<usize> = (0..200_000)
+            .map(|i| {
+                let x = i as f64;
+                arena.allocate(BoundingBox::new(x, 0.0, x + 1.0, 1.0), i)
+            })
+            .collect();
+        let mut linker = InMemoryLinker::new(&mut arena);
+        for pair in refs.windows(2) {
+            linker.link_right(pair[0], pair[1]);
+        }
+        let root = Some(refs[0]);
+
+        let bounds = tree_svg_bounds(&linker, root).unwrap();
+        assert!(bounds.get_dimension(2) > 200_000.0);
+
+        let svg = tree_to_svg(&linker, root, 800, 600);
+        assert_eq!(svg.matches("class=\"bbox").count(), 65);
+        assert!(svg.contains("Truncated: 65 nodes drawn"));
+        assert!(svg.ends_with("</svg>"));
+
+        let options = SvgOptions {
+            max_depth: usize::MAX,
+            max_nodes: 1000,
+        };
+        let svg = tree_to_svg_with_options(&linker, root, 800, 600, &options);
+        assert_eq!(svg.matches("class=\"bbox").count(), 1000);
+        assert!(svg.contains("Truncated: 1000 nodes drawn"));
+
+        let options = SvgOptions {
+            max_depth: usize::MAX,
+            max_nodes: usize::MAX,
+        };
+        let svg = tree_to_svg_with_options(&linker, root, 800, 600, &options);
+        assert_eq!(svg.matches("class=\"bbox").count(), refs.len());
+        assert!(!svg.contains("Truncated"));
+    }
+
+    #[test]
+    fn test_svg_draws_in_pre_order() {
+        let mut arena = NodeArena::new();
+        let refs: Vec<usize> = [5.0, 2.0, 8.0, 1.0]
+            .into_iter()
+            .enumerate()
+            .map(|(i, x)| arena.allocate(BoundingBox::new(x, x, x + 1.0, x + 1.0), i))
+            .collect();
+        let mut linker = InMemoryLinker::new(&mut arena);
+        let root = insert_node(&mut linker, None, refs[0], 0);
+        for &node in &refs[1..] {
+            insert_node(&mut linker, Some(root), node, 0);
+        }
+
+        let svg = tree_to_svg(&linker, Some(root), 800, 600);
+        let labels: Vec<&str> = svg
+            .split("class=\"data-text\">")
+            .skip(1)
+            .map(|rest| &rest[..1])
+            .collect();
+        assert_eq!(labels, ["0", "1", "3", "2"]);
+    }
+
+    #[test]
+    fn test_export_all_streams_every_entry() {
+        let mut arena = NodeArena::new();
+        let refs: Vec<usize> = (0..5)
+            .map(|i| arena.allocate(BoundingBox::new(i as f64, 0.0, i as f64 + 1.0, 1.0), i))
+            .collect();
+        let mut linker = InMemoryLinker::new(&mut arena);
+        assert_eq!(export_all(&linker, None).count(), 0);
+
+        // Pre-order: a node, then its left subtree, then its right one
+        linker.link_left(refs[2], refs[1]);
+        linker.link_right(refs[2], refs[3]);
+        linker.link_left(refs[1], refs[0]);
+        linker.link_right(refs[3], refs[4]);
+        let exported: Vec<(BoundingBox, i32)> = export_all(&linker, Some(refs[2])).collect();
+        assert_eq!(exported[3], (BoundingBox::new(3.0, 0.0, 4.0, 1.0), 3));
+        let order: Vec<i32> = exported.into_iter().map(|(_, data)| data).collect();
+        assert_eq!(order, [2, 1, 0, 3, 4]);
+
+        // A 10,000-deep chain, as sorted insertion would grow, keeps the stack at one node
+        let mut arena = NodeArena::new();
+        let refs: Vec<usize> = (0..10_000)
+            .map(|i| arena.allocate(BoundingBox::new(i as f64, 0.0, i as f64 + 1.0, 1.0), i))
+            .collect();
+        let mut linker = InMemoryLinker::new(&mut arena);
+        for pair in refs.windows(2) {
+            linker.link_right(pair[0], pair[1]);
+        }
+        let mut exported = export_all(&linker, Some(refs[0]));
+        let mut count = 0;
+        while exported.next().is_some() {
+            assert!(exported.stack.len() <= 1);
+            count += 1;
+        }
+        assert_eq!(count, 10_000);
+    }
+
+    #[test]
+    fn test_cursor_bytes_round_trip() {
+        let cursor = SearchCursor {
+            stack: vec![(3u64, 1), (17, 4)],
+        };
+        let bytes = cursor.to_bytes(42);
+        assert_eq!(bytes.len(), 16 + 2 * 16);
+        assert_eq!(SearchCursor::<u64>::from_bytes(&bytes, 42).unwrap(), cursor);
+        assert!(SearchCursor::<u64>::from_bytes(&bytes, 43).is_err());
+        assert!(SearchCursor::<u64>::from_bytes(&bytes[..20], 42).is_err());
+        assert!(SearchCursor::<u64>::from_bytes(b"not a cursor at all", 42).is_err());
+        assert!(SearchCursor::<u32>::from_bytes(&bytes, 42).is_err());
+    }
+}
@@ -0,0 +1,741 @@
+//! Segmented index: flushed, immutable trees merged according to a policy.
+//!
+//! # Architecture
+//! - Inserts are buffered in memory; `flush` bulk-builds the buffer into an immutable
+//!   `Segment`, as Lucene flushes a segment per indexing buffer
+//! - Searches visit every segment and the unflushed buffer
+//! - After each flush the `MergePolicy` picks groups of segments worth merging; each group
+//!   becomes a `MergeTask` handed to the `MergeScheduler`, which runs it inline, on a
+//!   thread, or wherever the application schedules background work
+//! - A merge reads only immutable segments and never blocks the index. Finished merges are
+//!   swapped in by the next `flush` or `apply_merges`; until then searches use the inputs
+//! - Deletes mark entries in a `DeletionBitmap` per segment, which searches consult; merges
+//!   leave marked entries out, and marks made while a merge ran carry over to its result
+
+use crate::build::{BuildOptions, bulk_build};
+use crate::deletes::{DeletionBitmap, spatial_search_live};
+use crate::query::SpatialQuery;
+use crate::search::spatial_search;
+use crate::spatial::Point;
+use crate::storage::{ArenaView, InMemoryLinker, NodeArena, NodeLinker};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+/// An immutable, balanced tree produced by a flush or a merge.
+pub struct Segment<P: Point, T> {
+    id: u64,
+    arena: NodeArena<P, T>,
+    root: Option<usize>,
+}
+
+impl<P: Point, T> Segment<P, T> {
+    fn build(id: u64, entries: impl IntoIterator<Item = (P, T)>) -> Self {
+        let mut arena = NodeArena::new();
+        let mut nodes: Vec<usize> = entries
+            .into_iter()
+            .map(|(point, data)| arena.allocate(point, data))
+            .collect();
+        let root = bulk_build(
+            &mut InMemoryLinker::new(&mut arena),
+            &mut nodes,
+            0,
+            &BuildOptions::default(),
+        )
+        .expect("builds without a cancellation token are never cancelled");
+        Segment { id, arena, root }
+    }
+
+    /// Segment id, unique within its index and increasing with creation order.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Number of entries.
+    pub fn len(&self) -> usize {
+        self.arena.len()
+    }
+
+    /// Check if the segment has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.arena.is_empty()
+    }
+
+    /// Read-only linker over the segment's tree.
+    pub fn linker(&self) -> ArenaView<'_, P, T> {
+        ArenaView::new(&self.arena)
+    }
+
+    /// Root of the segment's tree.
+    pub fn root(&self) -> Option<usize> {
+        self.root
+    }
+}
+
+/// What a `MergePolicy` knows about a segment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentInfo {
+    pub id: u64,
+    pub len: usize,
+}
+
+/// Decides which segments to merge.
+///
+/// Called after every flush with the segments not already being merged, oldest first. Each
+/// returned group of segment ids is merged into one segment; groups must not overlap.
+pub trait MergePolicy: Send {
+    fn find_merges(&self, segments: &[SegmentInfo]) -> Vec<Vec<u64>>;
+}
+
+/// Size-tiered policy: merge segments of similar size once enough of them pile up.
+///
+/// Segments are bucketed into tiers that grow by a factor of `segments_per_tier`, starting
+/// at `min_segment_len` entries. When a tier holds `segments_per_tier` segments they are
+/// merged into one segment of the next tier. Each entry is rewritten about once per tier,
+/// which keeps write amplification low; searches may visit up to `segments_per_tier - 1`
+/// segments per tier.
+#[derive(Debug, Clone)]
+pub struct TieredMergePolicy {
+    pub segments_per_tier: usize,
+    /// Segments smaller than this all share the lowest tier.
+    pub min_segment_len: usize,
+}
+
+impl Default for TieredMergePolicy {
+    fn default() -> Self {
+        TieredMergePolicy {
+            segments_per_tier: 10,
+            min_segment_len: 1000,
+        }
+    }
+}
+
+impl MergePolicy for TieredMergePolicy {
+    fn find_merges(&self, segments: &[SegmentInfo]) -> Vec<Vec<u64>> {
+        let width = self.segments_per_tier.max(2);
+        let tier = |len: usize| size_class(len, self.min_segment_len, width);
+        let mut tiers: Vec<Vec<u64>> = Vec::new();
+        for segment in segments {
+            let tier = tier(segment.len);
+            if tiers.len() <= tier {
+                tiers.resize(tier + 1, Vec::new());
+            }
+            tiers[tier].push(segment.id);
+        }
+        tiers
+            .iter()
+            .flat_map(|ids| ids.chunks_exact(width).map(<[u64]>::to_vec))
+            .collect()
+    }
+}
+
+/// Leveled policy: keep at most one segment per level, merging eagerly.
+///
+/// Level `n` holds segments of up to `base_len * size_ratio^n` entries. As soon as two
+/// segments share a level they are merged, and a result that climbs into an occupied
+/// level merges again at once. Searches visit about one segment per level, at the cost of
+/// rewriting entries more often than `TieredMergePolicy`.
+#[derive(Debug, Clone)]
+pub struct LeveledMergePolicy {
+    pub size_ratio: usize,
+    /// Capacity of level 0.
+    pub base_len: usize,
+}
+
+impl Default for LeveledMergePolicy {
+    fn default() -> Self {
+        LeveledMergePolicy {
+            size_ratio: 10,
+            base_len: 1000,
+        }
+    }
+}
+
+impl MergePolicy for LeveledMergePolicy {
+    fn find_merges(&self, segments: &[SegmentInfo]) -> Vec<Vec<u64>> {
+        let mut levels: Vec<Vec<u64>> = Vec::new();
+        for segment in segments {
+            let level = size_class(segment.len, self.base_len, self.size_ratio.max(2));
+            if levels.len() <= level {
+                levels.resize(level + 1, Vec::new());
+            }
+            levels[level].push(segment.id);
+        }
+        levels.into_iter().filter(|ids| ids.len() > 1).collect()
+    }
+}
+
+/// Smallest `n` with `len <= base * ratio^n`.
+fn size_class(len: usize, base: usize, ratio: usize) -> usize {
+    let mut class = 0;
+    let mut capacity = base.max(1);
+    while len > capacity {
+        capacity = capacity.saturating_mul(ratio);
+        class += 1;
+    }
+    class
+}
+
+/// Runs merges chosen by the policy; the hook for background scheduling.
+///
+/// Implementations call `MergeTask::run` whenever and wherever suits them: inline, on a
+/// dedicated thread, or on an application thread pool.
+pub trait MergeScheduler<P: Point, T>: Send {
+    fn schedule(&self, task: MergeTask<P, T>);
+}
+
+impl<P: Point, T, S: MergeScheduler<P, T> + Sync> MergeScheduler<P, T> for Arc<S> {
+    fn schedule(&self, task: MergeTask<P, T>) {
+        (**self).schedule(task);
+    }
+}
+
+/// Runs each merge immediately on the flushing thread.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SerialMergeScheduler;
+
+impl<P: Point + Clone, T: Clone> MergeScheduler<P, T> for SerialMergeScheduler {
+    fn schedule(&self, task: MergeTask<P, T>) {
+        task.run();
+    }
+}
+
+/// Runs each merge on a thread of its own.
+#[derive(Debug, Default)]
+pub struct ThreadMergeScheduler {
+    running: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl ThreadMergeScheduler {
+    /// Create a scheduler with no merges running.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Block until every merge scheduled so far has finished.
+    pub fn wait(&self) {
+        let running = std::mem::take(&mut *self.running.lock().unwrap());
+        for handle in running {
+            if let Err(panic) = handle.join() {
+                std::panic::resume_unwind(panic);
+            }
+        }
+    }
+}
+
+impl<P, T> MergeScheduler<P, T> for ThreadMergeScheduler
+where
+    P: Point + Clone + Send + Sync + 'static,
+    T: Clone + Send + Sync + 'static,
+{
+    fn schedule(&self, task: MergeTask<P, T>) {
+        let handle = std::thread::spawn(move || task.run());
+        let mut running = self.running.lock().unwrap();
+        running.retain(|handle| !handle.is_finished());
+        running.push(handle);
+    }
+}
+
+/// A merge of several segments into one, ready to run on any thread.
+pub struct MergeTask<P: Point, T> {
+    id: u64,
+    inputs: Vec<Arc<Segment<P, T>>>,
+    /// Deletions of each input when the merge was scheduled.
+    deleted: Vec<DeletionBitmap>,
+    finished: Arc<Mutex<Vec<Arc<Segment<P, T>>>>>,
+    input_ids: Vec<u64>,
+}
+
+impl<P: Point, T> MergeTask<P, T> {
+    /// Ids of the segments being merged.
+    pub fn input_ids(&self) -> &[u64] {
+        &self.input_ids
+    }
+
+    /// Number of entries the merged segment will hold: those of the inputs not deleted
+    /// when the merge was scheduled.
+    pub fn len(&self) -> usize {
+        self.inputs
+            .iter()
+            .zip(&self.deleted)
+            .map(|(segment, deleted)| segment.len() - deleted.len())
+            .sum()
+    }
+
+    /// Check if the merged segment will be empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<P: Point + Clone, T: Clone> MergeTask<P, T> {
+    /// Build the merged segment and hand it back to the index.
+    pub fn run(self) {
+        let entries = self
+            .inputs
+            .iter()
+            .zip(&self.deleted)
+            .flat_map(|(segment, deleted)| {
+                let linker = segment.linker();
+                (0..segment.len())
+                    .filter(|&node| !deleted.is_deleted(node))
+                    .map(move |node| {
+                        (
+                            linker.get_point(node).clone(),
+                            linker.get_data(node).clone(),
+                        )
+                    })
+            });
+        let merged = Segment::build(self.id, entries);
+        self.finished.lock().unwrap().push(Arc::new(merged));
+    }
+}
+
+/// A merge handed to the scheduler, by merged segment id.
+struct PendingMerge {
+    id: u64,
+    inputs: Vec<u64>,
+    /// Deletions of each input when the merge was scheduled, which the merge left out.
+    deleted: Vec<DeletionBitmap>,
+}
+
+/// Index of immutable segments, flushed from an insert buffer and merged by a policy.
+///
+/// # Example
+/// ```rust
+/// use bkd::{BoundingBox, SegmentedIndex, SerialMergeScheduler, TieredMergePolicy};
+///
+/// let policy = TieredMergePolicy { segments_per_tier: 4, min_segment_len: 10 };
+/// let mut index = SegmentedIndex::new(policy, SerialMergeScheduler);
+/// for i in 0..40 {
+///     let x = i as f64;
+///     index.insert(BoundingBox::new(x, x, x + 1.0, x + 1.0), i);
+///     if i % 10 == 9 {
+///         index.flush();
+///     }
+/// }
+/// // The fourth flush filled the lowest tier, so its segments were merged
+/// assert_eq!(index.segments().len(), 1);
+/// assert_eq!(index.search(&BoundingBox::new(0.0, 0.0, 5.0, 5.0)).len(), 6);
+/// ```
+pub struct SegmentedIndex<P: Point, T> {
+    buffer: Vec<(P, T)>,
+    segments: Vec<Arc<Segment<P, T>>>,
+    next_id: u64,
+    policy: Box<dyn MergePolicy>,
+    scheduler: Box<dyn MergeScheduler<P, T>>,
+    /// Segments handed to a merge that has not been applied yet.
+    merging: HashSet<u64>,
+    /// Scheduled merges that have not been applied yet.
+    pending: Vec<PendingMerge>,
+    /// Deleted entries by segment id; segments without deletions have no entry.
+    deletes: HashMap<u64, DeletionBitmap>,
+    finished: Arc<Mutex<Vec<Arc<Segment<P, T>>>>>,
+}
+
+impl<P: Point + Clone, T: Clone> SegmentedIndex<P, T> {
+    /// Create an empty index merging with `policy` and running merges on `scheduler`.
+    pub fn new(
+        policy: impl MergePolicy + 'static,
+        scheduler: impl MergeScheduler<P, T> + 'static,
+    ) -> Self {
+        SegmentedIndex {
+            buffer: Vec::new(),
+            segments: Vec::new(),
+            next_id: 0,
+            policy: Box::new(policy),
+            scheduler: Box::new(scheduler),
+            merging: HashSet::new(),
+            pending: Vec::new(),
+            deletes: HashMap::new(),
+            finished: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Buffer an entry; it is searchable at once and indexed by the next `flush`.
+    pub fn insert(&mut self, point: P, data: T) {
+        self.buffer.push((point, data));
+    }
+
+    /// Number of buffered, unflushed entries.
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Total number of live entries, flushed or not.
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+            + self
+                .segments
+                .iter()
+                .map(|segment| segment.len())
+                .sum::<usize>()
+            - self.deleted()
+    }
+
+    /// Number of deleted entries still held by segments, until merges purge them.
+    pub fn deleted(&self) -> usize {
+        self.deletes.values().map(DeletionBitmap::len).sum()
+    }
+
+    /// Delete the entries matching `query` for which `predicate` holds. Returns the
+    /// number deleted.
+    ///
+    /// Buffered entries are dropped at once. Flushed ones are only marked deleted: searches
+    /// skip them, and the merge that next rewrites their segment leaves them out.
+    pub fn delete_matching<Q: SpatialQuery<P>>(
+        &mut self,
+        query: &Q,
+        mut predicate: impl FnMut(&P, &T) -> bool,
+    ) -> usize {
+        let mut count = 0;
+        for segment in &self.segments {
+            let linker = segment.linker();
+            let deleted = self.deletes.entry(segment.id()).or_default();
+            for node in spatial_search(&linker, segment.root(), query, 0) {
+                if predicate(linker.get_point(node), linker.get_data(node)) && deleted.delete(node)
+                {
+                    count += 1;
+                }
+            }
+            if deleted.is_empty() {
+                self.deletes.remove(&segment.id());
+            }
+        }
+        let buffered = self.buffer.len();
+        self.buffer
+            .retain(|(point, data)| !(query.matches(point) && predicate(point, data)));
+        count + buffered - self.buffer.len()
+    }
+
+    /// Check if the index has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Current segments, oldest first.
+    pub fn segments(&self) -> Vec<SegmentInfo> {
+        self.segments
+            .iter()
+            .map(|segment| SegmentInfo {
+                id: segment.id(),
+                len: segment.len(),
+            })
+            .collect()
+    }
+
+    /// Build the buffer into a new segment, then apply finished merges and schedule new
+    /// ones. Returns the new segment's id, or `None` if the buffer was empty.
+    pub fn flush(&mut self) -> Option<u64> {
+        let flushed = (!self.buffer.is_empty()).then(|| {
+            let id = self.allocate_id();
+            let segment = Segment::build(id, std::mem::take(&mut self.buffer));
+            self.segments.push(Arc::new(segment));
+            id
+        });
+        self.apply_merges();
+        self.schedule_merges();
+        flushed
+    }
+
+    /// Swap finished merges in for their inputs. Returns the number applied.
+    pub fn apply_merges(&mut self) -> usize {
+        let finished = std::mem::take(&mut *self.finished.lock().unwrap());
+        for merged in &finished {
+            let position = self
+                .pending
+                .iter()
+                .position(|pending| pending.id == merged.id())
+                .expect("finished merges were scheduled by this index");
+            let PendingMerge {
+                inputs, deleted, ..
+            } = self.pending.swap_remove(position);
+            self.carry_over_deletes(merged.id(), &inputs, &deleted);
+            // The merged segment takes the place of its oldest input
+            let slot = self
+                .segments
+                .iter()
+                .position(|segment| inputs.contains(&segment.id()))
+                .expect("merge inputs stay in the index until the merge is applied");
+            self.segments[slot] = Arc::clone(merged);
+            self.segments
+                .retain(|segment| !inputs.contains(&segment.id()));
+            for id in inputs {
+                self.merging.remove(&id);
+            }
+        }
+        finished.len()
+    }
+
+    /// Find the entries of every segment and of the buffer matching `query`.
+    pub fn search<Q: SpatialQuery<P>>(&self, query: &Q) -> Vec<(&P, &T)> {
+        let mut results = Vec::new();
+        let no_deletes = DeletionBitmap::new();
+        for segment in &self.segments {
+            let linker = segment.linker();
+            let deleted = self.deletes.get(&segment.id()).unwrap_or(&no_deletes);
+            results.extend(
+                spatial_search_live(&linker, segment.root(), query, 0, deleted)
+                    .into_iter()
+                    .map(|node| {
+                        let node = segment.arena.get(node);
+                        (&node.point, &node.data)
+                    }),
+            );
+        }
+        results.extend(
+            self.buffer
+                .iter()
+                .filter(|(point, _)| query.matches(point))
+                .map(|(point, data)| (point, data)),
+        );
+        results
+    }
+
+    /// Mark the entries of a merged segment that were deleted from its inputs after the
+    /// merge was scheduled, since the merge copied them.
+    fn carry_over_deletes(&mut self, merged: u64, inputs: &[u64], scheduled: &[DeletionBitmap]) {
+        let mut carried = DeletionBitmap::new();
+        // Position in the merged segment of the first entry copied from each input
+        let mut offset = 0;
+        for (&id, scheduled) in inputs.iter().zip(scheduled) {
+            let len = self
+                .segments
+                .iter()
+                .find(|segment| segment.id() == id)
+                .expect("merge inputs stay in the index until the merge is applied")
+                .len();
+            let current = self.deletes.remove(&id).unwrap_or_default();
+            if current.len() > scheduled.len() {
+                let copied = (0..len).filter(|&node| !scheduled.is_deleted(node));
+                for (position, node) in copied.enumerate() {
+                    if current.is_deleted(node) {
+                        carried.delete(offset + position);
+                    }
+                }
+            }
+            offset += len - scheduled.len();
+        }
+        if !carried.is_empty() {
+            self.deletes.insert(merged, carried);
+        }
+    }
+
+    fn allocate_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    /// Schedule the merges the policy asks for, repeating while merges finish inline so a
+    /// merge that fills the next tier or level cascades at once.
+    fn schedule_merges(&mut self) {
+        loop {
+            self.schedule_policy_merges();
+            if self.apply_merges() == 0 {
+                break;
+            }
+        }
+    }
+
+    fn schedule_policy_merges(&mut self) {
+        let candidates: Vec<SegmentInfo> = self
+            .segments()
+            .into_iter()
+            .filter(|segment| !self.merging.contains(&segment.id))
+            .collect();
+        for group in self.policy.find_merges(&candidates) {
+            let inputs: Vec<Arc<Segment<P, T>>> = self
+                .segments
+                .iter()
+                .filter(|segment| group.contains(&segment.id()))
+                .cloned()
+                .collect();
+            if inputs.len() < 2 || inputs.iter().any(|s| self.merging.contains(&s.id())) {
+                continue;
+            }
+            let id = self.allocate_id();
+            let input_ids: Vec<u64> = inputs.iter().map(|segment| segment.id()).collect();
+            let deleted: Vec<DeletionBitmap> = input_ids
+                .iter()
+                .map(|id| self.deletes.get(id).cloned().unwrap_or_default())
+                .collect();
+            self.merging.extend(&input_ids);
+            self.pending.push(PendingMerge {
+                id,
+                inputs: input_ids.clone(),
+                deleted: deleted.clone(),
+            });
+            self.scheduler.schedule(MergeTask {
+                id,
+                inputs,
+                deleted,
+                finished: Arc::clone(&self.finished),
+                input_ids,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spatial::BoundingBox;
+
+    fn fill(index: &mut SegmentedIndex<BoundingBox, u32>, flushes: u32, per_flush: u32) {
+        for i in 0..flushes * per_flush {
+            let x = ((i * 37) % 1009) as f64;
+            index.insert(BoundingBox::new(x, x, x + 1.0, x + 1.0), i);
+            if i % per_flush == per_flush - 1 {
+                index.flush();
+            }
+        }
+    }
+
+    #[test]
+    fn test_size_classes() {
+        assert_eq!(size_class(0, 10, 4), 0);
+        assert_eq!(size_class(10, 10, 4), 0);
+        assert_eq!(size_class(11, 10, 4), 1);
+        assert_eq!(size_class(160, 10, 4), 2);
+        assert_eq!(size_class(usize::MAX, 10, 4), 31);
+    }
+
+    #[test]
+    fn test_tiered_policy_merges_full_tiers() {
+        let policy = TieredMergePolicy {
+            segments_per_tier: 3,
+            min_segment_len: 10,
+        };
+        let infos = |lens: &[usize]| -> Vec<SegmentInfo> {
+            lens.iter()
+                .enumerate()
+                .map(|(id, &len)| SegmentInfo { id: id as u64, len })
+                .collect()
+        };
+        assert!(policy.find_merges(&infos(&[10, 10, 30])).is_empty());
+        assert_eq!(
+            policy.find_merges(&infos(&[10, 25, 10, 30, 5])),
+            [vec![0, 2, 4]]
+        );
+        assert_eq!(policy.find_merges(&infos(&[25, 30, 29])), [vec![0, 1, 2]]);
+    }
+
+    #[test]
+    fn test_tiered_index_keeps_entries_searchable() {
+        let policy = TieredMergePolicy {
+            segments_per_tier: 4,
+            min_segment_len: 50,
+        };
+        let mut index = SegmentedIndex::new(policy, SerialMergeScheduler);
+        fill(&mut index, 17, 50);
+        // 16 flushes merged twice over into one segment of 800, plus one fresh flush
+        let lens: Vec<usize> = index.segments().iter().map(|s| s.len).collect();
+        assert_eq!(lens, [800, 50]);
+
+        index.insert(BoundingBox::new(5000.0, 5000.0, 5001.0, 5001.0), 9999);
+        assert_eq!(index.len(), 851);
+        let everything = BoundingBox::new(f64::MIN, f64::MIN, f64::MAX, f64::MAX);
+        let mut ids: Vec<u32> = index
+            .search(&everything)
+            .into_iter()
+            .map(|(_, &id)| id)
+            .collect();
+        ids.sort_unstable();
+        let mut expected: Vec<u32> = (0..850).collect();
+        expected.push(9999);
+        assert_eq!(ids, expected);
+    }
+
+    #[test]
+    fn test_leveled_index_keeps_one_segment_per_level() {
+        let policy = LeveledMergePolicy {
+            size_ratio: 4,
+            base_len: 50,
+        };
+        let mut index = SegmentedIndex::new(policy, SerialMergeScheduler);
+        fill(&mut index, 7, 50);
+        // Two flushes climb to level 1, where 200 and 100 cascade into level 2
+        let lens: Vec<usize> = index.segments().iter().map(|s| s.len).collect();
+        assert_eq!(lens, [300, 50]);
+        let mut levels: Vec<usize> = lens.iter().map(|&len| size_class(len, 50, 4)).collect();
+        levels.sort_unstable();
+        levels.dedup();
+        assert_eq!(levels.len(), lens.len());
+    }
+
+    /// Holds merges until the test runs them.
+    struct Deferred(Mutex<Vec<MergeTask<BoundingBox, u32>>>);
+
+    impl MergeScheduler<BoundingBox, u32> for Deferred {
+        fn schedule(&self, task: MergeTask<BoundingBox, u32>) {
+            self.0.lock().unwrap().push(task);
+        }
+    }
+
+    #[test]
+    fn test_deletes_are_skipped_and_purged_by_merges() {
+        let policy = TieredMergePolicy {
+            segments_per_tier: 2,
+            min_segment_len: 100,
+        };
+        let scheduler = Arc::new(Deferred(Mutex::new(Vec::new())));
+        let mut index = SegmentedIndex::new(policy, Arc::clone(&scheduler));
+        let everything = BoundingBox::new(f64::MIN, f64::MIN, f64::MAX, f64::MAX);
+        let ids = |index: &SegmentedIndex<BoundingBox, u32>| -> Vec<u32> {
+            let mut ids: Vec<u32> = index
+                .search(&everything)
+                .into_iter()
+                .map(|(_, &id)| id)
+                .collect();
+            ids.sort_unstable();
+            ids
+        };
+
+        // Deletes before the merge is scheduled are left out of it
+        fill(&mut index, 1, 100);
+        assert_eq!(index.delete_matching(&everything, |_, &id| id < 10), 10);
+        index.insert(BoundingBox::new(0.0, 0.0, 1.0, 1.0), 500);
+        assert_eq!(index.delete_matching(&everything, |_, &id| id == 500), 1);
+        for i in 100..200 {
+            let x = ((i * 37) % 1009) as f64;
+            index.insert(BoundingBox::new(x, x, x + 1.0, x + 1.0), i);
+        }
+        index.flush();
+        assert_eq!((index.len(), index.deleted()), (190, 10));
+        let task = scheduler.0.lock().unwrap().pop().unwrap();
+        assert_eq!(task.len(), 190);
+
+        // Deletes while it runs carry over to the merged segment
+        assert_eq!(index.delete_matching(&everything, |_, &id| id % 50 == 0), 3);
+        assert_eq!(index.delete_matching(&everything, |_, &id| id < 10), 0);
+        task.run();
+        index.apply_merges();
+        let lens: Vec<usize> = index.segments().iter().map(|s| s.len).collect();
+        assert_eq!(lens, [190]);
+        assert_eq!((index.len(), index.deleted()), (187, 3));
+        let expected: Vec<u32> = (10..200).filter(|id| id % 50 != 0).collect();
+        assert_eq!(ids(&index), expected);
+    }
+
+    #[test]
+    fn test_thread_scheduler_merges_in_background() {
+        let policy = TieredMergePolicy {
+            segments_per_tier: 2,
+            min_segment_len: 100,
+        };
+        let scheduler = Arc::new(ThreadMergeScheduler::new());
+        let mut index = SegmentedIndex::new(policy, Arc::clone(&scheduler));
+        fill(&mut index, 2, 100);
+
+        // Searches see every entry whether or not the merge has been applied
+        let everything = BoundingBox::new(f64::MIN, f64::MIN, f64::MAX, f64::MAX);
+        assert_eq!(index.search(&everything).len(), 200);
+
+        scheduler.wait();
+        index.apply_merges();
+        let lens: Vec<usize> = index.segments().iter().map(|s| s.len).collect();
+        assert_eq!(lens, [200]);
+        assert_eq!(index.search(&everything).len(), 200);
+    }
+}
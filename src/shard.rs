@@ -0,0 +1,404 @@
+//! Splitting an index into shards, and querying several trees as one.
+
+use crate::nearest::{Metric, Neighbor, nearest_iter};
+use crate::query::{Relation, SpatialQuery};
+use crate::search::spatial_search;
+use crate::spatial::Point;
+use crate::storage::NodeLinker;
+use std::marker::PhantomData;
+
+/// Split the entries of a tree into `n` spatially coherent partitions of near-equal size.
+///
+/// Partitions are cut the way a KD-tree cuts space: the entries are divided in proportion
+/// to the partitions each side receives, at the matching order statistic of the dimension
+/// the depth selects (`depth % dimensions`), and each side is divided again until every
+/// partition stands alone. Sizes differ by at most one, and each partition occupies its
+/// own cell of space, so it can be bulk-built into a shard of its own and queries touch
+/// only the shards whose cells they reach.
+///
+/// Partitions are returned in cell order, left before right; with fewer entries than
+/// partitions the trailing ones may be empty.
+///
+/// # Panics
+/// Panics if `n` is zero.
+pub fn partition<P: Point, T, L: NodeLinker<P, T>>(
+    linker: &L,
+    root: Option<L::NodeRef>,
+    n: usize,
+) -> Vec<Vec<L::NodeRef>> {
+    assert!(n > 0, "cannot split an index into zero partitions");
+
+    let mut entries = Vec::new();
+    let mut stack: Vec<L::NodeRef> = root.into_iter().collect();
+    while let Some(node) = stack.pop() {
+        entries.push(node);
+        stack.extend(linker.get_right(node));
+        stack.extend(linker.get_left(node));
+    }
+
+    let mut partitions = Vec::with_capacity(n);
+    // (start, end, partitions, depth); right pushed first so cells come out left to right
+    let mut tasks = vec![(0, entries.len(), n, 0)];
+    while let Some((start, end, parts, depth)) = tasks.pop() {
+        let slice = &mut entries[start..end];
+        if parts == 1 {
+            partitions.push(slice.to_vec());
+            continue;
+        }
+
+        let left_parts = parts / 2;
+        let cut = slice.len() * left_parts / parts;
+        if cut > 0 && cut < slice.len() {
+            let dimension = depth % linker.get_point(slice[0]).dimensions();
+            slice.select_nth_unstable_by(cut, |&a, &b| {
+                let a_value = linker.get_point(a).get_dimension(dimension);
+                let b_value = linker.get_point(b).get_dimension(dimension);
+                a_value.total_cmp(&b_value)
+            });
+        }
+        tasks.push((start + cut, end, parts - left_parts, depth + 1));
+        tasks.push((start, start + cut, left_parts, depth + 1));
+    }
+    partitions
+}
+
+/// An entry found by a `ShardedIndex`, copied out of the shard that holds it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShardHit<P, T> {
+    /// Index of the shard, in the order shards were added.
+    pub shard: usize,
+    pub point: P,
+    pub data: T,
+}
+
+/// Several trees queried as one index, like the per-segment BKD trees of a Lucene index.
+///
+/// Each shard keeps its own linker, so in-memory arenas, snapshots and disk-backed trees
+/// can be mixed. Queries run against every shard and the hits are merged; because node
+/// references of different backends cannot be compared, hits carry a copy of the entry's
+/// point and payload along with the shard it came from.
+///
+/// # Example
+/// ```rust
+/// use bkd::{BoundingBox, ShardedIndex, SharedTree};
+///
+/// let mut east = SharedTree::new();
+/// east.insert(BoundingBox::new(10.0, 0.0, 11.0, 1.0), "depot");
+/// let mut west = SharedTree::new();
+/// west.insert(BoundingBox::new(-10.0, 0.0, -9.0, 1.0), "yard");
+///
+/// let mut index = ShardedIndex::new();
+/// let (east_root, west_root) = (east.root(), west.root());
+/// index.add_shard(east, east_root);
+/// index.add_shard(west, west_root);
+///
+/// let hits = index.search(&BoundingBox::new(-20.0, -1.0, 20.0, 2.0));
+/// assert_eq!(hits.len(), 2);
+/// ```
+pub struct ShardedIndex<'a, P: Point, T> {
+    shards: Vec<Box<dyn Shard<P, T> + 'a>>,
+}
+
+impl<'a, P: Point + Clone + 'a, T: Clone + 'a> ShardedIndex<'a, P, T> {
+    /// Create an index with no shards.
+    pub fn new() -> Self {
+        ShardedIndex { shards: Vec::new() }
+    }
+
+    /// Add a tree as the next shard and return its shard index.
+    pub fn add_shard<L: NodeLinker<P, T> + 'a>(
+        &mut self,
+        linker: L,
+        root: Option<L::NodeRef>,
+    ) -> usize {
+        self.shards.push(Box::new(TreeShard {
+            linker,
+            root,
+            _marker: PhantomData,
+        }));
+        self.shards.len() - 1
+    }
+
+    /// Number of shards.
+    pub fn len(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Check if the index has no shards.
+    pub fn is_empty(&self) -> bool {
+        self.shards.is_empty()
+    }
+
+    /// Find the entries of every shard matching `query`, shard by shard in the order the
+    /// shards were added and in `spatial_search` order within each.
+    pub fn search<Q: SpatialQuery<P>>(&self, query: &Q) -> Vec<ShardHit<P, T>> {
+        let mut hits = Vec::new();
+        for (shard, tree) in self.shards.iter().enumerate() {
+            tree.search(query, &mut |point, data| {
+                hits.push(ShardHit {
+                    shard,
+                    point: point.clone(),
+                    data: data.clone(),
+                });
+            });
+        }
+        hits
+    }
+
+    /// `search`, with the hits sorted by distance to `target` under `metric`, nearest first.
+    pub fn search_by_distance<Q: SpatialQuery<P>>(
+        &self,
+        query: &Q,
+        target: &[f64],
+        metric: &Metric,
+    ) -> Vec<Neighbor<ShardHit<P, T>>> {
+        let mut hits: Vec<_> = self
+            .search(query)
+            .into_iter()
+            .map(|hit| Neighbor {
+                distance: metric.distance(&hit.point, target),
+                node: hit,
+            })
+            .collect();
+        // Stable, so equidistant hits keep shard order
+        hits.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        hits
+    }
+
+    /// Find the `k` entries closest to `target` across all shards, nearest first.
+    ///
+    /// Takes the `k` nearest of each shard and keeps the best `k` overall; ties go to the
+    /// shard added first.
+    pub fn nearest_neighbors(
+        &self,
+        target: &[f64],
+        k: usize,
+        metric: &Metric,
+    ) -> Vec<Neighbor<ShardHit<P, T>>> {
+        let mut neighbors = Vec::new();
+        for (shard, tree) in self.shards.iter().enumerate() {
+            tree.nearest(target, k, metric, &mut |distance, point, data| {
+                neighbors.push(Neighbor {
+                    node: ShardHit {
+                        shard,
+                        point: point.clone(),
+                        data: data.clone(),
+                    },
+                    distance,
+                });
+            });
+        }
+        neighbors.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        neighbors.truncate(k);
+        neighbors
+    }
+}
+
+impl<'a, P: Point + Clone + 'a, T: Clone + 'a> Default for ShardedIndex<'a, P, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Object-safe view of a tree, so shards with different linkers share one `Vec`.
+trait Shard<P: Point, T> {
+    fn search(&self, query: &dyn SpatialQuery<P>, visit: &mut dyn FnMut(&P, &T));
+
+    fn nearest(
+        &self,
+        target: &[f64],
+        k: usize,
+        metric: &Metric,
+        visit: &mut dyn FnMut(f64, &P, &T),
+    );
+}
+
+struct TreeShard<P: Point, T, L: NodeLinker<P, T>> {
+    linker: L,
+    root: Option<L::NodeRef>,
+    _marker: PhantomData<fn() -> (P, T)>,
+}
+
+impl<P: Point, T, L: NodeLinker<P, T>> Shard<P, T> for TreeShard<P, T, L> {
+    fn search(&self, query: &dyn SpatialQuery<P>, visit: &mut dyn FnMut(&P, &T)) {
+        for node in spatial_search(&self.linker, self.root, &DynQuery(query), 0) {
+            visit(self.linker.get_point(node), self.linker.get_data(node));
+        }
+    }
+
+    fn nearest(
+        &self,
+        target: &[f64],
+        k: usize,
+        metric: &Metric,
+        visit: &mut dyn FnMut(f64, &P, &T),
+    ) {
+        for neighbor in nearest_iter(&self.linker, self.root, target, metric, 0).take(k) {
+            let node = neighbor.node;
+            visit(
+                neighbor.distance,
+                self.linker.get_point(node),
+                self.linker.get_data(node),
+            );
+        }
+    }
+}
+
+/// Sized wrapper that lets a query trait object drive the generic search algorithms.
+struct DynQuery<'q, P>(&'q dyn SpatialQuery<P>);
+
+impl<P: Point> SpatialQuery<P> for DynQuery<'_, P> {
+    fn dimension_range(&self, dim: usize) -> (f64, f64) {
+        self.0.dimension_range(dim)
+    }
+
+    fn matches(&self, point: &P) -> bool {
+        self.0.matches(point)
+    }
+
+    fn relate(&self, min: &[f64], max: &[f64]) -> Relation {
+        self.0.relate(min, max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::{BuildOptions, bulk_build};
+    use crate::snapshot::SharedTree;
+    use crate::spatial::BoundingBox;
+    use crate::storage::{InMemoryLinker, NodeArena};
+
+    #[test]
+    fn test_partition_sizes_and_coverage() {
+        let mut arena = NodeArena::new();
+        let mut nodes: Vec<usize> = (0..1000)
+            .map(|i| {
+                let x = ((i * 37) % 1009) as f64;
+                let y = ((i * 53) % 997) as f64;
+                arena.allocate(BoundingBox::new(x, y, x + 1.0, y + 1.0), i)
+            })
+            .collect();
+        let mut linker = InMemoryLinker::new(&mut arena);
+        let root = bulk_build(&mut linker, &mut nodes, 0, &BuildOptions::default()).unwrap();
+
+        for n in [1, 2, 3, 7, 16] {
+            let partitions = partition(&linker, root, n);
+            assert_eq!(partitions.len(), n);
+            let sizes: Vec<usize> = partitions.iter().map(Vec::len).collect();
+            let (min, max) = (sizes.iter().min().unwrap(), sizes.iter().max().unwrap());
+            assert!(max - min <= 1, "unbalanced partitions {sizes:?}");
+
+            let mut all: Vec<usize> = partitions.concat();
+            all.sort_unstable();
+            assert_eq!(all, (0..1000).collect::<Vec<_>>());
+        }
+
+        // The first cut is on x: every entry on the left lies at or before every entry on the right
+        let halves = partition(&linker, root, 2);
+        let x = |node: &usize| linker.get_point(*node).xmin;
+        let left_max = halves[0].iter().map(x).fold(f64::MIN, f64::max);
+        let right_min = halves[1].iter().map(x).fold(f64::MAX, f64::min);
+        assert!(left_max <= right_min);
+    }
+
+    #[test]
+    fn test_partition_small_trees() {
+        let mut arena = NodeArena::new();
+        let node = arena.allocate(BoundingBox::new(0.0, 0.0, 1.0, 1.0), 0u32);
+        let linker = InMemoryLinker::new(&mut arena);
+
+        let partitions = partition(&linker, Some(node), 3);
+        assert_eq!(partitions.iter().map(Vec::len).sum::<usize>(), 1);
+        assert_eq!(partitions.len(), 3);
+        assert!(partition(&linker, None, 2).iter().all(Vec::is_empty));
+    }
+
+    #[test]
+    fn test_sharded_index_matches_single_tree() {
+        let entries: Vec<(BoundingBox, u32)> = (0..600)
+            .map(|i| {
+                let x = ((i * 37) % 211) as f64;
+                let y = ((i * 53) % 199) as f64;
+                (BoundingBox::new(x, y, x + 1.5, y + 0.5), i)
+            })
+            .collect();
+
+        let mut whole = NodeArena::new();
+        let mut nodes: Vec<usize> = entries
+            .iter()
+            .map(|(bbox, id)| whole.allocate(bbox.clone(), *id))
+            .collect();
+        let mut whole_linker = InMemoryLinker::new(&mut whole);
+        let whole_root =
+            bulk_build(&mut whole_linker, &mut nodes, 0, &BuildOptions::default()).unwrap();
+
+        // Two shards cut from the tree into arenas, one in a snapshot-capable tree
+        let parts = partition(&whole_linker, whole_root, 3);
+        let mut arenas: Vec<(NodeArena<BoundingBox, u32>, Vec<usize>)> = parts[..2]
+            .iter()
+            .map(|part| {
+                let mut arena = NodeArena::new();
+                let nodes = part
+                    .iter()
+                    .map(|&node| {
+                        let point = whole_linker.get_point(node).clone();
+                        arena.allocate(point, *whole_linker.get_data(node))
+                    })
+                    .collect();
+                (arena, nodes)
+            })
+            .collect();
+        let mut shared = SharedTree::new();
+        for &node in &parts[2] {
+            let point = whole_linker.get_point(node).clone();
+            shared.insert(point, *whole_linker.get_data(node));
+        }
+
+        let mut index = ShardedIndex::new();
+        for (arena, nodes) in &mut arenas {
+            let mut linker = InMemoryLinker::new(arena);
+            let root = bulk_build(&mut linker, nodes, 0, &BuildOptions::default()).unwrap();
+            index.add_shard(linker, root);
+        }
+        let shared_root = shared.root();
+        assert_eq!(index.add_shard(shared, shared_root), 2);
+        assert_eq!(index.len(), 3);
+
+        let query = BoundingBox::new(20.0, 20.0, 90.0, 70.0);
+        let mut expected: Vec<u32> = spatial_search(&whole_linker, whole_root, &query, 0)
+            .into_iter()
+            .map(|node| *whole_linker.get_data(node))
+            .collect();
+        let mut found: Vec<u32> = index
+            .search(&query)
+            .into_iter()
+            .map(|hit| hit.data)
+            .collect();
+        expected.sort_unstable();
+        found.sort_unstable();
+        assert_eq!(found, expected);
+
+        let target = [50.0, 50.0, 51.5, 50.5];
+        let metric = Metric::euclidean();
+        let sorted = index.search_by_distance(&query, &target, &metric);
+        assert_eq!(sorted.len(), expected.len());
+        assert!(
+            sorted
+                .windows(2)
+                .all(|pair| pair[0].distance <= pair[1].distance)
+        );
+
+        let expected: Vec<f64> =
+            crate::nearest::nearest_neighbors(&whole_linker, whole_root, &target, 10, &metric, 0)
+                .into_iter()
+                .map(|neighbor| neighbor.distance)
+                .collect();
+        let found: Vec<f64> = index
+            .nearest_neighbors(&target, 10, &metric)
+            .into_iter()
+            .map(|neighbor| neighbor.distance)
+            .collect();
+        assert_eq!(found, expected);
+    }
+}
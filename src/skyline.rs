@@ -0,0 +1,205 @@
+//! Skyline (Pareto-optimal) queries over two criteria.
+
+use crate::nearest::Metric;
+use crate::spatial::Point;
+use crate::storage::NodeLinker;
+use crate::summary::SubtreeBounds;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::hash::Hash;
+
+/// One criterion of a skyline query, turned into a cost that is better when smaller.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SkylineCriterion {
+    /// Prefer small values of a dimension, such as a price indexed as one.
+    Minimize(usize),
+    /// Prefer large values of a dimension, such as a rating.
+    Maximize(usize),
+    /// Prefer entries close to `target` under `metric`.
+    Distance { target: Vec<f64>, metric: Metric },
+}
+
+impl SkylineCriterion {
+    /// Cost of an entry.
+    pub fn cost<P: Point>(&self, point: &P) -> f64 {
+        match self {
+            SkylineCriterion::Minimize(dim) => point.get_dimension(*dim),
+            SkylineCriterion::Maximize(dim) => -point.get_dimension(*dim),
+            SkylineCriterion::Distance { target, metric } => metric.distance(point, target),
+        }
+    }
+
+    /// Smallest cost of any entry in the cell bounded by `min` and `max`.
+    pub fn lower_bound(&self, min: &[f64], max: &[f64]) -> f64 {
+        match self {
+            SkylineCriterion::Minimize(dim) => min[*dim],
+            SkylineCriterion::Maximize(dim) => -max[*dim],
+            SkylineCriterion::Distance { target, metric } => {
+                metric.distance_to_cell(target, min, max)
+            }
+        }
+    }
+}
+
+/// Heap entry: a node to report or a subtree to expand, keyed by the sum of its costs or
+/// of its subtree's lower bounds.
+struct Pending<R> {
+    key: f64,
+    costs: [f64; 2],
+    node: R,
+    subtree: bool,
+}
+
+impl<R> PartialEq for Pending<R> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<R> Eq for Pending<R> {}
+
+impl<R> PartialOrd for Pending<R> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<R> Ord for Pending<R> {
+    /// Reversed so `BinaryHeap` pops the smallest key first; subtrees before nodes of the
+    /// same key, so a subtree is expanded before anything it could dominate is reported.
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .key
+            .total_cmp(&self.key)
+            .then_with(|| self.subtree.cmp(&other.subtree))
+    }
+}
+
+/// Find the nodes reachable from `root` that no other node beats on both `criteria`: the
+/// skyline, or Pareto front, such as the hotels for which every closer one costs more.
+/// Returns them by increasing cost on the first criterion.
+///
+/// A node dominates another when it is at least as good on both criteria and better on
+/// one. Nodes with identical costs do not dominate each other, so all of them are kept.
+///
+/// # Architecture Decision: branch and bound over subtree bounds
+/// Branch-and-bound skyline (BBS, Papadias et al.): nodes and subtrees wait in a heap
+/// ordered by the sum of their costs, for a subtree the sum of the lower bounds its
+/// `SubtreeBounds` give. A node is taken before every node it dominates, since those sum
+/// to more, so each node popped is on the skyline unless something already found beats
+/// it. A subtree whose best possible corner is already beaten is dropped whole, so only
+/// the subtrees along the front are expanded. Subtrees without bounds are never pruned.
+pub fn skyline<P: Point, T, L: NodeLinker<P, T>>(
+    linker: &L,
+    root: Option<L::NodeRef>,
+    bounds: &SubtreeBounds<L::NodeRef>,
+    criteria: &[SkylineCriterion; 2],
+) -> Vec<L::NodeRef>
+where
+    L::NodeRef: Eq + Hash,
+{
+    let subtree = |node: L::NodeRef| {
+        let costs = bounds
+            .get(node)
+            .map_or([f64::NEG_INFINITY; 2], |(min, max)| {
+                [
+                    criteria[0].lower_bound(min, max),
+                    criteria[1].lower_bound(min, max),
+                ]
+            });
+        Pending {
+            key: costs[0] + costs[1],
+            costs,
+            node,
+            subtree: true,
+        }
+    };
+
+    let mut heap: BinaryHeap<Pending<L::NodeRef>> = root.map(subtree).into_iter().collect();
+    // Sorted by the first cost, so by decreasing second cost
+    let mut front: Vec<([f64; 2], L::NodeRef)> = Vec::new();
+    while let Some(pending) = heap.pop() {
+        if dominated(&front, pending.costs) {
+            continue;
+        }
+        if !pending.subtree {
+            let at = front.partition_point(|(costs, _)| costs[0] <= pending.costs[0]);
+            front.insert(at, (pending.costs, pending.node));
+            continue;
+        }
+
+        let point = linker.get_point(pending.node);
+        let costs = [criteria[0].cost(point), criteria[1].cost(point)];
+        heap.push(Pending {
+            key: costs[0] + costs[1],
+            costs,
+            node: pending.node,
+            subtree: false,
+        });
+        heap.extend(linker.get_left(pending.node).map(subtree));
+        heap.extend(linker.get_right(pending.node).map(subtree));
+    }
+    front.into_iter().map(|(_, node)| node).collect()
+}
+
+/// Check if some node of `front` beats `costs`. Among the nodes no worse on the first
+/// cost, the last has the best second cost.
+fn dominated<R>(front: &[([f64; 2], R)], costs: [f64; 2]) -> bool {
+    let at = front.partition_point(|(other, _)| other[0] <= costs[0]);
+    match at.checked_sub(1).map(|last| front[last].0) {
+        Some(best) => best[1] < costs[1] || (best[1] == costs[1] && best[0] < costs[0]),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::{BuildOptions, bulk_build};
+    use crate::storage::{ArenaView, InMemoryLinker, NodeArena};
+
+    #[test]
+    fn test_skyline_matches_a_pairwise_scan() {
+        // Hotels at (x, y) with a price as a third dimension
+        let mut arena = NodeArena::new();
+        let mut nodes: Vec<usize> = (0..2000)
+            .map(|i| {
+                let x = ((i * 37) % 211) as f64;
+                let y = ((i * 53) % 197) as f64;
+                let price = ((i * 71) % 89) as f64;
+                arena.allocate([x, y, price], i)
+            })
+            .collect();
+        let mut linker = InMemoryLinker::new(&mut arena);
+        let root = bulk_build(&mut linker, &mut nodes, 0, &BuildOptions::default()).unwrap();
+        let linker = ArenaView::new(&arena);
+        let bounds = SubtreeBounds::build(&linker, root);
+
+        let near_and_cheap = [
+            SkylineCriterion::Distance {
+                target: vec![100.0, 100.0],
+                metric: Metric::euclidean(),
+            },
+            SkylineCriterion::Minimize(2),
+        ];
+        let west_and_north = [SkylineCriterion::Minimize(0), SkylineCriterion::Maximize(1)];
+        for criteria in [&near_and_cheap, &west_and_north] {
+            let costs = |node: usize| {
+                let point = arena.get(node).get_point();
+                [criteria[0].cost(point), criteria[1].cost(point)]
+            };
+            let beats = |a: [f64; 2], b: [f64; 2]| a[0] <= b[0] && a[1] <= b[1] && a != b;
+            let mut expected: Vec<usize> = (0..arena.len())
+                .filter(|&node| !(0..arena.len()).any(|other| beats(costs(other), costs(node))))
+                .collect();
+            expected.sort_by(|&a, &b| costs(a)[0].total_cmp(&costs(b)[0]).then(a.cmp(&b)));
+
+            let mut found = skyline(&linker, root, &bounds, criteria);
+            found.sort_by(|&a, &b| costs(a)[0].total_cmp(&costs(b)[0]).then(a.cmp(&b)));
+            assert_eq!(found, expected);
+            assert!(expected.len() > 1);
+        }
+
+        assert!(skyline(&linker, None, &bounds, &west_and_north).is_empty());
+    }
+}
@@ -0,0 +1,365 @@
+//! Append-only tree with snapshot-consistent concurrent readers.
+//!
+//! # Architecture
+//! Inserting into a KD-tree never moves or rewrites nodes: it appends one node and sets a
+//! single empty child link of an existing node. `SharedTree` relies on that:
+//! - Nodes live in fixed-size chunks that are never reallocated; slots are write-once
+//! - Child links are atomics, so a reader never observes a torn link
+//! - A snapshot records how many nodes existed when it was taken and ignores links to
+//!   any node at or beyond that count
+//!
+//! A `TreeSnapshot` therefore sees exactly the tree as of `snapshot()`, no matter how many
+//! inserts happen afterwards, without locks, copying, or `&mut` access to shared storage.
+
+use crate::search::insert_node;
+use crate::spatial::Point;
+use crate::storage::{CHUNK_SIZE, NodeLinker};
+use crate::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+
+/// Encoded value of an absent child link.
+const NO_CHILD: usize = usize::MAX;
+
+/// Expiration timestamp of entries that never expire.
+pub const NEVER_EXPIRES: u64 = u64::MAX;
+
+struct SharedNode<P, T> {
+    point: P,
+    data: T,
+    expires_at: u64,
+    left: AtomicUsize,
+    right: AtomicUsize,
+}
+
+/// Fixed-size block of write-once node slots.
+struct Chunk<P, T> {
+    slots: Box<[OnceLock<SharedNode<P, T>>]>,
+}
+
+impl<P, T> Chunk<P, T> {
+    fn new() -> Self {
+        Chunk {
+            slots: (0..CHUNK_SIZE).map(|_| OnceLock::new()).collect(),
+        }
+    }
+}
+
+/// Look up an initialized node; callers only pass indices below a published length.
+fn node<P, T>(chunks: &[Arc<Chunk<P, T>>], index: usize) -> &SharedNode<P, T> {
+    chunks[index / CHUNK_SIZE].slots[index % CHUNK_SIZE]
+        .get()
+        .expect("node index out of range")
+}
+
+fn decode_child(link: &AtomicUsize, visible: usize) -> Option<usize> {
+    match link.load(Ordering::Acquire) {
+        NO_CHILD => None,
+        child if child < visible => Some(child),
+        // Linked after the snapshot was taken
+        _ => None,
+    }
+}
+
+/// Insert-only KD-tree whose readers work on consistent snapshots.
+///
+/// # Usage pattern:
+/// The writer owns the `SharedTree` and inserts through `&mut self`; readers get a
+/// `TreeSnapshot` (cheap to create, `Send + Sync`) and search it with the regular
+/// algorithms, concurrently with further inserts.
+pub struct SharedTree<P, T> {
+    chunks: Vec<Arc<Chunk<P, T>>>,
+    len: usize,
+    root: Option<usize>,
+}
+
+impl<P: Point, T> SharedTree<P, T> {
+    /// Create an empty tree.
+    pub fn new() -> Self {
+        SharedTree {
+            chunks: Vec::new(),
+            len: 0,
+            root: None,
+        }
+    }
+
+    /// Insert a node and return its index.
+    pub fn insert(&mut self, point: P, data: T) -> usize {
+        self.insert_with_expiry(point, data, NEVER_EXPIRES)
+    }
+
+    /// Insert a node that expires at `expires_at` and return its index.
+    /// Timestamps are opaque `u64`s (e.g. Unix milliseconds); an entry is expired at time
+    /// `now` once `expires_at <= now`.
+    pub fn insert_with_expiry(&mut self, point: P, data: T, expires_at: u64) -> usize {
+        let index = self.len;
+        if index / CHUNK_SIZE == self.chunks.len() {
+            self.chunks.push(Arc::new(Chunk::new()));
+        }
+        let slot = &self.chunks[index / CHUNK_SIZE].slots[index % CHUNK_SIZE];
+        if slot
+            .set(SharedNode {
+                point,
+                data,
+                expires_at,
+                left: AtomicUsize::new(NO_CHILD),
+                right: AtomicUsize::new(NO_CHILD),
+            })
+            .is_err()
+        {
+            unreachable!("node slots are written exactly once");
+        }
+        self.len += 1;
+
+        let root = self.root;
+        self.root = Some(insert_node(self, root, index, 0));
+        index
+    }
+
+    /// Number of nodes in the tree.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Check if the tree is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Root node index, if the tree is not empty.
+    pub fn root(&self) -> Option<usize> {
+        self.root
+    }
+
+    /// Capture the current tree. Later inserts are invisible to the snapshot.
+    pub fn snapshot(&self) -> TreeSnapshot<P, T> {
+        TreeSnapshot {
+            chunks: self.chunks.clone().into(),
+            len: self.len,
+            root: self.root,
+        }
+    }
+}
+
+impl<P: Point, T> Default for SharedTree<P, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The writer's own view: every node is visible and links are set in place.
+impl<P: Point, T> NodeLinker<P, T> for SharedTree<P, T> {
+    type NodeRef = usize;
+
+    fn link_left(&mut self, parent: Self::NodeRef, child: Self::NodeRef) {
+        node(&self.chunks, parent)
+            .left
+            .store(child, Ordering::Release);
+    }
+
+    fn link_right(&mut self, parent: Self::NodeRef, child: Self::NodeRef) {
+        node(&self.chunks, parent)
+            .right
+            .store(child, Ordering::Release);
+    }
+
+    fn get_left(&self, node_ref: Self::NodeRef) -> Option<Self::NodeRef> {
+        decode_child(&node(&self.chunks, node_ref).left, self.len)
+    }
+
+    fn get_right(&self, node_ref: Self::NodeRef) -> Option<Self::NodeRef> {
+        decode_child(&node(&self.chunks, node_ref).right, self.len)
+    }
+
+    fn get_point(&self, node_ref: Self::NodeRef) -> &P {
+        &node(&self.chunks, node_ref).point
+    }
+
+    fn get_data(&self, node_ref: Self::NodeRef) -> &T {
+        &node(&self.chunks, node_ref).data
+    }
+}
+
+/// Immutable view of a `SharedTree` at one point in time.
+///
+/// Cloning is cheap (the chunk list is shared), and the snapshot keeps the chunks it
+/// references alive even if the tree itself is dropped.
+pub struct TreeSnapshot<P, T> {
+    chunks: Arc<[Arc<Chunk<P, T>>]>,
+    len: usize,
+    root: Option<usize>,
+}
+
+impl<P, T> Clone for TreeSnapshot<P, T> {
+    fn clone(&self) -> Self {
+        TreeSnapshot {
+            chunks: self.chunks.clone(),
+            len: self.len,
+            root: self.root,
+        }
+    }
+}
+
+impl<P: Point, T> TreeSnapshot<P, T> {
+    /// Number of nodes visible in the snapshot.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Check if the snapshot is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Root node index, if the snapshot is not empty.
+    pub fn root(&self) -> Option<usize> {
+        self.root
+    }
+
+    /// Expiration timestamp of a node (`NEVER_EXPIRES` if it has none).
+    pub fn expires_at(&self, node_ref: usize) -> u64 {
+        assert!(node_ref < self.len, "node is not part of this snapshot");
+        node(&self.chunks, node_ref).expires_at
+    }
+
+    /// Check if a node is expired at time `now`.
+    pub fn is_expired(&self, node_ref: usize, now: u64) -> bool {
+        self.expires_at(node_ref) <= now
+    }
+}
+
+/// Snapshots are read-only; linking through one panics.
+impl<P: Point, T> NodeLinker<P, T> for TreeSnapshot<P, T> {
+    type NodeRef = usize;
+
+    fn link_left(&mut self, _parent: Self::NodeRef, _child: Self::NodeRef) {
+        panic!("cannot link nodes through a read-only TreeSnapshot");
+    }
+
+    fn link_right(&mut self, _parent: Self::NodeRef, _child: Self::NodeRef) {
+        panic!("cannot link nodes through a read-only TreeSnapshot");
+    }
+
+    fn get_left(&self, node_ref: Self::NodeRef) -> Option<Self::NodeRef> {
+        decode_child(&node(&self.chunks, node_ref).left, self.len)
+    }
+
+    fn get_right(&self, node_ref: Self::NodeRef) -> Option<Self::NodeRef> {
+        decode_child(&node(&self.chunks, node_ref).right, self.len)
+    }
+
+    fn get_point(&self, node_ref: Self::NodeRef) -> &P {
+        assert!(node_ref < self.len, "node is not part of this snapshot");
+        &node(&self.chunks, node_ref).point
+    }
+
+    fn get_data(&self, node_ref: Self::NodeRef) -> &T {
+        assert!(node_ref < self.len, "node is not part of this snapshot");
+        &node(&self.chunks, node_ref).data
+    }
+}
+
+#[cfg(all(test, not(bkd_loom)))]
+mod tests {
+    use super::*;
+    use crate::query::SpatialQuery;
+    use crate::search::spatial_search;
+    use crate::spatial::BoundingBox;
+    use std::sync::mpsc;
+
+    fn point(i: usize) -> BoundingBox {
+        let x = (i * 37 % 1000) as f64;
+        let y = (i * 53 % 1000) as f64;
+        BoundingBox::new(x, y, x + 5.0, y + 5.0)
+    }
+
+    #[test]
+    fn test_snapshots_ignore_later_inserts() {
+        let query = BoundingBox::new(200.0, 200.0, 600.0, 500.0);
+        let (sender, receiver) = mpsc::channel::<TreeSnapshot<BoundingBox, usize>>();
+
+        let readers: Vec<_> = (0..3)
+            .map(|_| {
+                let (tx, rx) = mpsc::channel::<TreeSnapshot<BoundingBox, usize>>();
+                let query = query.clone();
+                let handle = std::thread::spawn(move || {
+                    let mut checked = 0;
+                    for snapshot in rx {
+                        // Search repeatedly while the writer keeps inserting
+                        for _ in 0..3 {
+                            let mut results = spatial_search(&snapshot, snapshot.root(), &query, 0);
+                            results.sort();
+                            let expected: Vec<usize> = (0..snapshot.len())
+                                .filter(|&i| query.matches(&point(i)))
+                                .collect();
+                            assert_eq!(results, expected);
+                        }
+                        checked += 1;
+                    }
+                    checked
+                });
+                (tx, handle)
+            })
+            .collect();
+
+        let writer = std::thread::spawn(move || {
+            let mut tree = SharedTree::new();
+            for i in 0..5 * CHUNK_SIZE {
+                tree.insert(point(i), i);
+                if i % 500 == 0 {
+                    sender.send(tree.snapshot()).unwrap();
+                }
+            }
+            tree.len()
+        });
+
+        for snapshot in receiver {
+            for (tx, _) in &readers {
+                tx.send(snapshot.clone()).unwrap();
+            }
+        }
+        assert_eq!(writer.join().unwrap(), 5 * CHUNK_SIZE);
+        for (tx, handle) in readers {
+            drop(tx);
+            assert_eq!(handle.join().unwrap(), 5 * CHUNK_SIZE / 500 + 1);
+        }
+    }
+}
+
+#[cfg(all(test, bkd_loom))]
+mod loom_tests {
+    use super::*;
+    use crate::search::spatial_search;
+    use crate::spatial::BoundingBox;
+    use loom::thread;
+
+    fn point(x: f64) -> BoundingBox {
+        BoundingBox::new(x, x, x + 1.0, x + 1.0)
+    }
+
+    #[test]
+    fn test_snapshot_readers_never_see_torn_or_future_links() {
+        loom::model(|| {
+            let mut tree = SharedTree::new();
+            tree.insert(point(5.0), 0u32);
+            tree.insert(point(2.0), 1);
+            let before = tree.snapshot();
+            let query = BoundingBox::new(0.0, 0.0, 10.0, 10.0);
+
+            let early_query = query.clone();
+            let reader = thread::spawn(move || {
+                let mut found = spatial_search(&before, before.root(), &early_query, 0);
+                found.sort();
+                found
+            });
+
+            // Links a new child below node 1 while the reader may be walking past it
+            tree.insert(point(1.0), 2);
+            let after = tree.snapshot();
+            let late_reader =
+                thread::spawn(move || spatial_search(&after, after.root(), &query, 0).len());
+
+            assert_eq!(reader.join().unwrap(), vec![0, 1]);
+            assert_eq!(late_reader.join().unwrap(), 3);
+        });
+    }
+}
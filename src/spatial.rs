@@ -24,6 +24,7 @@ pub trait SpatialPoint: Point {
 /// Represents a rectangular region in 2D space with min/max coordinates.
 #[derive(Debug, Clone, PartialEq)]
 #[cfg_attr(feature = "tantivy", derive(serde::Serialize, serde::Deserialize))]
+#[repr(C)]
 pub struct BoundingBox {
     pub xmin: f64,
     pub ymin: f64,
@@ -42,6 +43,41 @@ impl BoundingBox {
         }
     }
 
+    /// Create a bounding box centered on a point with the given half-width and half-height.
+    /// Convenient for "around this point" queries without manual min/max arithmetic.
+    pub fn from_center(cx: f64, cy: f64, half_w: f64, half_h: f64) -> Self {
+        BoundingBox::new(cx - half_w, cy - half_h, cx + half_w, cy + half_h)
+    }
+
+    /// Return a new bounding box grown by `distance` on every side.
+    /// A negative distance shrinks the box; an axis shrunk past zero width collapses to
+    /// its center line rather than producing an inverted box.
+    pub fn buffer(&self, distance: f64) -> Self {
+        let (xmin, xmax) = buffer_axis(self.xmin, self.xmax, distance);
+        let (ymin, ymax) = buffer_axis(self.ymin, self.ymax, distance);
+        BoundingBox::new(xmin, ymin, xmax, ymax)
+    }
+
+    /// Check if this box is within the query box, allowing each edge to stick out by up to
+    /// `epsilon`. With `epsilon = 0.0` this is exactly `is_within`.
+    pub fn is_within_eps(&self, query: &BoundingBox, epsilon: f64) -> bool {
+        self.xmin >= query.xmin - epsilon
+            && self.xmax <= query.xmax + epsilon
+            && self.ymin >= query.ymin - epsilon
+            && self.ymax <= query.ymax + epsilon
+    }
+
+    /// Check if this box overlaps the query box, treating boxes separated by a gap of at
+    /// most `epsilon` as overlapping. Edge contact always counts as overlap, so with
+    /// `epsilon = 0.0` this is exactly `overlaps`; a negative `epsilon` requires the boxes
+    /// to overlap by more than `-epsilon` on each axis.
+    pub fn overlaps_eps(&self, query: &BoundingBox, epsilon: f64) -> bool {
+        !(self.xmax < query.xmin - epsilon
+            || self.xmin > query.xmax + epsilon
+            || self.ymax < query.ymin - epsilon
+            || self.ymin > query.ymax + epsilon)
+    }
+
     /// Return a new bounding box with the specified dimension set to a new value.
     /// Used for bounds calculation in SVG rendering.
     pub fn with_dimension(&self, dim: usize, value: f64) -> Self {
@@ -66,6 +102,17 @@ impl BoundingBox {
     }
 }
 
+/// Grow one axis by `distance` on both ends, collapsing to the midpoint if it would invert.
+fn buffer_axis(min: f64, max: f64, distance: f64) -> (f64, f64) {
+    let (new_min, new_max) = (min - distance, max + distance);
+    if new_min > new_max {
+        let mid = (min + max) / 2.0;
+        (mid, mid)
+    } else {
+        (new_min, new_max)
+    }
+}
+
 impl Point for BoundingBox {
     /// Get value for dimension (0=xmin, 1=ymin, 2=xmax, 3=ymax)
     fn get_dimension(&self, dim: usize) -> f64 {
@@ -101,6 +148,55 @@ impl SpatialPoint for BoundingBox {
     }
 }
 
+/// A plain coordinate array is a point of `N` dimensions.
+impl<const N: usize> Point for [f64; N] {
+    fn get_dimension(&self, dim: usize) -> f64 {
+        self[dim]
+    }
+
+    fn dimensions(&self) -> usize {
+        N
+    }
+}
+
+/// Points have no extent, so containment and overlap both mean the points coincide; use
+/// `RangeQuery` to find the points inside a region.
+impl<const N: usize> SpatialPoint for [f64; N] {
+    fn is_within(&self, query: &Self) -> bool {
+        self == query
+    }
+
+    fn overlaps(&self, query: &Self) -> bool {
+        self == query
+    }
+}
+
+/// A pair is a 2D point: 0 is the first element and 1 the second.
+impl Point for (f64, f64) {
+    fn get_dimension(&self, dim: usize) -> f64 {
+        match dim {
+            0 => self.0,
+            1 => self.1,
+            _ => panic!("Invalid dimension: {}", dim),
+        }
+    }
+
+    fn dimensions(&self) -> usize {
+        2
+    }
+}
+
+/// Same semantics as for arrays: a pair is within or overlaps only an equal pair.
+impl SpatialPoint for (f64, f64) {
+    fn is_within(&self, query: &Self) -> bool {
+        self == query
+    }
+
+    fn overlaps(&self, query: &Self) -> bool {
+        self == query
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -133,4 +229,81 @@ mod tests {
         assert!(!bbox1.is_within(&bbox2));
         assert!(!bbox1.is_within(&bbox4));
     }
+
+    #[test]
+    fn test_bounding_box_from_center_and_buffer() {
+        let bbox = BoundingBox::from_center(10.0, 20.0, 2.0, 1.0);
+        assert_eq!(bbox, BoundingBox::new(8.0, 19.0, 12.0, 21.0));
+
+        assert_eq!(bbox.buffer(1.0), BoundingBox::new(7.0, 18.0, 13.0, 22.0));
+        assert_eq!(bbox.buffer(-0.5), BoundingBox::new(8.5, 19.5, 11.5, 20.5));
+
+        // Shrinking past the height collapses y to the center line, x stays valid
+        assert_eq!(bbox.buffer(-1.5), BoundingBox::new(9.5, 20.0, 10.5, 20.0));
+    }
+
+    #[test]
+    fn test_bounding_box_epsilon_predicates() {
+        let bbox = BoundingBox::new(0.0, 0.0, 1.0, 1.0);
+        let touching = BoundingBox::new(1.0, 0.0, 2.0, 1.0);
+        let near = BoundingBox::new(1.0 + 1e-10, 0.0, 2.0, 1.0);
+
+        assert!(bbox.overlaps_eps(&touching, 0.0));
+        assert!(!bbox.overlaps_eps(&near, 0.0));
+        assert!(bbox.overlaps_eps(&near, 1e-9));
+        assert!(!bbox.overlaps_eps(&touching, -1e-9));
+
+        let query = BoundingBox::new(0.0, 0.0, 1.0 - 1e-12, 1.0);
+        assert!(!bbox.is_within_eps(&query, 0.0));
+        assert!(bbox.is_within_eps(&query, 1e-9));
+    }
+
+    #[test]
+    fn test_array_and_tuple_points() {
+        let point = [1.0, 2.0, 3.0];
+        assert_eq!(point.dimensions(), 3);
+        assert_eq!(point.get_dimension(2), 3.0);
+        assert!(point.is_within(&[1.0, 2.0, 3.0]));
+        assert!(!point.overlaps(&[1.0, 2.0, 3.5]));
+
+        let pair = (4.0, 5.0);
+        assert_eq!(pair.dimensions(), 2);
+        assert_eq!((pair.get_dimension(0), pair.get_dimension(1)), (4.0, 5.0));
+        assert!(pair.overlaps(&(4.0, 5.0)));
+        assert!(!pair.is_within(&(4.0, 6.0)));
+    }
+
+    #[test]
+    fn test_index_arrays_without_newtype() {
+        use crate::build::{BuildOptions, bulk_build};
+        use crate::query::RangeQuery;
+        use crate::search::spatial_search;
+        use crate::storage::{InMemoryLinker, NodeArena, NodeLinker};
+
+        let mut arena = NodeArena::new();
+        let mut nodes: Vec<usize> = (0..100)
+            .map(|i| arena.allocate([(i % 10) as f64, (i / 10) as f64, i as f64], i))
+            .collect();
+        let mut linker = InMemoryLinker::new(&mut arena);
+        let root = bulk_build(&mut linker, &mut nodes, 0, &BuildOptions::default()).unwrap();
+
+        let query = RangeQuery::new()
+            .with_range(0, 2.0, 3.0)
+            .with_range(1, 5.0, 5.0);
+        let mut found: Vec<i32> = spatial_search(&linker, root, &query, 0)
+            .into_iter()
+            .map(|node| *linker.get_data(node))
+            .collect();
+        found.sort_unstable();
+        assert_eq!(found, [52, 53]);
+
+        let mut pairs = NodeArena::new();
+        let mut nodes: Vec<usize> = (0..20)
+            .map(|i| pairs.allocate((i as f64, -(i as f64)), i))
+            .collect();
+        let mut linker = InMemoryLinker::new(&mut pairs);
+        let root = bulk_build(&mut linker, &mut nodes, 0, &BuildOptions::default()).unwrap();
+        let query = RangeQuery::new().with_range(1, -4.0, -3.0);
+        assert_eq!(spatial_search(&linker, root, &query, 0).len(), 2);
+    }
 }
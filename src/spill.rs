@@ -0,0 +1,233 @@
+//! Spatial search whose results spill to a temporary file instead of growing in memory.
+
+use crate::external::TempFile;
+use crate::query::SpatialQuery;
+use crate::search::drain_search_stack;
+use crate::spatial::Point;
+use crate::storage::NodeLinker;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::PathBuf;
+
+/// Node reference that can be written to a spill file, or used as a position in a
+/// `DeletionBitmap`.
+///
+/// Spill files live only as long as the `SpilledResults` that owns them, so references are
+/// stored as plain `u64` values rather than in a portable `FixedCodec` layout.
+pub trait SpillRef: Copy {
+    fn to_u64(self) -> u64;
+    fn from_u64(value: u64) -> Self;
+}
+
+impl SpillRef for usize {
+    fn to_u64(self) -> u64 {
+        self as u64
+    }
+
+    fn from_u64(value: u64) -> Self {
+        value as usize
+    }
+}
+
+impl SpillRef for u64 {
+    fn to_u64(self) -> u64 {
+        self
+    }
+
+    fn from_u64(value: u64) -> Self {
+        value
+    }
+}
+
+impl SpillRef for u32 {
+    fn to_u64(self) -> u64 {
+        self as u64
+    }
+
+    fn from_u64(value: u64) -> Self {
+        value as u32
+    }
+}
+
+/// Configuration for `spatial_search_spilled`.
+#[derive(Debug, Clone)]
+pub struct SpillOptions {
+    /// Results held in memory before they are appended to the spill file.
+    pub max_in_memory: usize,
+    /// Directory for the spill file; defaults to the system temporary directory.
+    pub temp_dir: Option<PathBuf>,
+}
+
+impl Default for SpillOptions {
+    fn default() -> Self {
+        SpillOptions {
+            max_in_memory: 1 << 20,
+            temp_dir: None,
+        }
+    }
+}
+
+/// Results of a spilled search: a prefix on disk, if the results outgrew memory, followed
+/// by the most recent matches in memory. The spill file is deleted on drop.
+pub struct SpilledResults<R> {
+    file: Option<TempFile>,
+    spilled: u64,
+    memory: Vec<R>,
+}
+
+impl<R: SpillRef> SpilledResults<R> {
+    /// Total number of results.
+    pub fn len(&self) -> u64 {
+        self.spilled + self.memory.len() as u64
+    }
+
+    /// Check if the search matched nothing.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Check if part of the results were written to disk.
+    pub fn is_spilled(&self) -> bool {
+        self.spilled > 0
+    }
+
+    /// Iterate over the results in search order, reading the spilled prefix back from disk.
+    pub fn iter(&self) -> io::Result<SpilledIter<'_, R>> {
+        let reader = match &self.file {
+            Some(file) if self.spilled > 0 => Some(BufReader::new(File::open(&file.path)?)),
+            _ => None,
+        };
+        Ok(SpilledIter {
+            reader,
+            remaining: self.spilled,
+            memory: self.memory.iter(),
+        })
+    }
+}
+
+/// Iterator over `SpilledResults`; yields an error if reading the spill file fails.
+pub struct SpilledIter<'a, R> {
+    reader: Option<BufReader<File>>,
+    remaining: u64,
+    memory: std::slice::Iter<'a, R>,
+}
+
+impl<'a, R: SpillRef> Iterator for SpilledIter<'a, R> {
+    type Item = io::Result<R>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining > 0 {
+            if let Some(reader) = &mut self.reader {
+                self.remaining -= 1;
+                let mut buf = [0u8; 8];
+                return Some(
+                    reader
+                        .read_exact(&mut buf)
+                        .map(|()| R::from_u64(u64::from_le_bytes(buf))),
+                );
+            }
+        }
+        self.memory.next().copied().map(Ok)
+    }
+}
+
+/// Spatial search for result sets too large to hold in memory.
+///
+/// Finds the same nodes as `spatial_search`, in the same order, but holds at most
+/// `max_in_memory` of them at a time: whenever the buffer fills while the traversal still
+/// has nodes to visit, it is appended to a temporary spill file. Queries with small results
+/// never touch the disk.
+pub fn spatial_search_spilled<P: Point, T, L: NodeLinker<P, T>, Q: SpatialQuery<P>>(
+    linker: &L,
+    root: Option<L::NodeRef>,
+    query: &Q,
+    depth: usize,
+    options: &SpillOptions,
+) -> io::Result<SpilledResults<L::NodeRef>>
+where
+    L::NodeRef: SpillRef,
+{
+    let limit = options.max_in_memory.max(1);
+    let temp_dir = options.temp_dir.clone().unwrap_or_else(std::env::temp_dir);
+    let mut stack: Vec<(L::NodeRef, usize)> = root.map(|node| (node, depth)).into_iter().collect();
+    let mut spill: Option<(TempFile, BufWriter<File>)> = None;
+    let mut results = SpilledResults {
+        file: None,
+        spilled: 0,
+        memory: Vec::new(),
+    };
+
+    loop {
+        drain_search_stack(linker, query, &mut stack, &mut results.memory, limit, None)
+            .expect("search without a cancellation token cannot be cancelled");
+        if stack.is_empty() {
+            break;
+        }
+
+        let (_, writer) = match &mut spill {
+            Some(spill) => spill,
+            None => {
+                let file = TempFile::new(&temp_dir);
+                let writer = BufWriter::new(File::create(&file.path)?);
+                spill.insert((file, writer))
+            }
+        };
+        results.spilled += results.memory.len() as u64;
+        for node in results.memory.drain(..) {
+            writer.write_all(&node.to_u64().to_le_bytes())?;
+        }
+    }
+
+    if let Some((file, mut writer)) = spill {
+        writer.flush()?;
+        results.file = Some(file);
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::{BuildOptions, bulk_build};
+    use crate::search::spatial_search;
+    use crate::spatial::BoundingBox;
+    use crate::storage::{InMemoryLinker, NodeArena};
+
+    #[test]
+    fn test_spilled_search_matches_spatial_search() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut arena = NodeArena::new();
+        let mut nodes: Vec<usize> = (0..1000)
+            .map(|i| {
+                let x = ((i * 37) % 101) as f64;
+                let y = ((i * 53) % 97) as f64;
+                arena.allocate(BoundingBox::new(x, y, x + 1.0, y + 1.0), i)
+            })
+            .collect();
+        let mut linker = InMemoryLinker::new(&mut arena);
+        let root = bulk_build(&mut linker, &mut nodes, 0, &BuildOptions::default()).unwrap();
+        let query = BoundingBox::new(10.0, 10.0, 80.0, 70.0);
+        let expected = spatial_search(&linker, root, &query, 0);
+
+        let options = SpillOptions {
+            max_in_memory: 64,
+            temp_dir: Some(dir.path().to_path_buf()),
+        };
+        let results = spatial_search_spilled(&linker, root, &query, 0, &options).unwrap();
+        assert!(results.is_spilled());
+        assert_eq!(results.len(), expected.len() as u64);
+        let collected: Vec<usize> = results.iter().unwrap().map(Result::unwrap).collect();
+        assert_eq!(collected, expected);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+        drop(results);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        // Small result sets stay in memory
+        let small = BoundingBox::new(0.0, 0.0, 3.0, 3.0);
+        let results = spatial_search_spilled(&linker, root, &small, 0, &options).unwrap();
+        assert!(!results.is_spilled());
+        let collected: Vec<usize> = results.iter().unwrap().map(Result::unwrap).collect();
+        assert_eq!(collected, spatial_search(&linker, root, &small, 0));
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
@@ -1,11 +1,13 @@
 //! Storage abstractions and memory management for spatial indexes.
 
 use crate::spatial::Point;
+use std::marker::PhantomData;
 
 /// KD-tree node with generic point type and associated data.
 /// Uses indices for arena allocation pattern.
 /// P: Point type (coordinates, bounding box, etc.)
 /// T: Associated data type (page_id, user data, etc.)
+#[derive(Clone)]
 pub struct Node<P: Point, T> {
     pub point: P,             // Spatial data for tree algorithms
     pub data: T,              // Associated payload (page_id, etc.)
@@ -60,57 +62,246 @@ pub trait NodeLinker<P: Point, T> {
 
     /// Get a reference to the associated data of a node.
     fn get_data(&self, node: Self::NodeRef) -> &T;
+
+    /// Resolve the points and payloads of many nodes, in the order of `refs`.
+    ///
+    /// The default resolves one node at a time. Disk-backed linkers override it to batch
+    /// or prefetch the reads, so resolving a whole result list costs fewer random reads.
+    fn get_many<'a>(&'a self, refs: &[Self::NodeRef]) -> impl Iterator<Item = (&'a P, &'a T)>
+    where
+        P: 'a,
+        T: 'a,
+    {
+        refs.iter()
+            .map(move |&node| (self.get_point(node), self.get_data(node)))
+    }
+}
+
+/// Linker whose backend records the root of its tree.
+///
+/// Persistent backends store the root with the nodes, so a reopened tree knows where to
+/// start without the application keeping the root out-of-band. Searches still take the
+/// root as an argument; pass `get_root()`.
+pub trait RootedLinker<P: Point, T>: NodeLinker<P, T> {
+    /// The recorded root, or `None` for an empty tree.
+    fn get_root(&self) -> Option<Self::NodeRef>;
+
+    /// Record a new root, e.g. the one returned by `insert_node` or `bulk_build`.
+    fn set_root(&mut self, root: Option<Self::NodeRef>);
+}
+
+/// A linker that can also create nodes in its backend.
+///
+/// Allocation is otherwise backend-specific (`NodeArena::allocate`, `MmapArena::allocate`,
+/// ...), so code that fills an arbitrary backend, such as `copy_tree`, goes through this.
+/// New nodes have no children and are not linked into any tree.
+pub trait AllocatingLinker<P: Point, T>: NodeLinker<P, T> {
+    /// Create an unlinked node and return its reference.
+    fn allocate(&mut self, point: P, data: T) -> Self::NodeRef;
+}
+
+/// Indexed node storage that `InMemoryLinker` can link over.
+///
+/// Implemented by `NodeArena` and by alternative allocators such as the bump arena
+/// (feature `bumpalo`), so the same linker and tree algorithms run on any of them.
+pub trait NodeStore<P: Point, T> {
+    /// Get a reference to a node by index.
+    fn node(&self, index: usize) -> &Node<P, T>;
+
+    /// Get a mutable reference to a node by index.
+    fn node_mut(&mut self, index: usize) -> &mut Node<P, T>;
+
+    /// Allocate a new node and return its index.
+    fn allocate(&mut self, point: P, data: T) -> usize;
 }
 
+/// Number of nodes per arena chunk.
+pub(crate) const CHUNK_SIZE: usize = 1024;
+
 /// Arena-based allocator for in-memory nodes.
 /// Manages node allocation and provides stable references.
+///
+/// # Architecture Decision: Chunked slabs instead of one `Vec`
+/// Nodes live in fixed-size chunks that are allocated once and never resized, so growing
+/// the arena only allocates a new chunk: nothing is copied, and nodes never move in memory.
+/// A single `Vec` would reallocate and copy the whole arena on every doubling, which stalls
+/// large ingests for hundreds of milliseconds at a time. Index `i` maps to chunk
+/// `i / CHUNK_SIZE`, slot `i % CHUNK_SIZE`, so lookups stay O(1).
 pub struct NodeArena<P: Point, T> {
-    nodes: Vec<Node<P, T>>,
+    chunks: Vec<Vec<Node<P, T>>>,
+    len: usize,
 }
 
 impl<P: Point, T> NodeArena<P, T> {
     /// Create a new empty arena.
     pub fn new() -> Self {
-        NodeArena { nodes: Vec::new() }
+        NodeArena {
+            chunks: Vec::new(),
+            len: 0,
+        }
     }
 
     /// Create a new arena with pre-allocated capacity.
     pub fn with_capacity(capacity: usize) -> Self {
-        NodeArena {
-            nodes: Vec::with_capacity(capacity),
-        }
+        let chunks = (0..capacity.div_ceil(CHUNK_SIZE))
+            .map(|_| Vec::with_capacity(CHUNK_SIZE))
+            .collect();
+        NodeArena { chunks, len: 0 }
     }
 
     /// Allocate a new node and return its index.
     pub fn allocate(&mut self, point: P, data: T) -> usize {
-        let index = self.nodes.len();
-        self.nodes.push(Node {
+        let index = self.len;
+        let chunk = index / CHUNK_SIZE;
+        if chunk == self.chunks.len() {
+            self.chunks.push(Vec::with_capacity(CHUNK_SIZE));
+        }
+        let slots = &mut self.chunks[chunk];
+        if slots.len() == slots.capacity() {
+            // Only a chunk trimmed by `shrink_to_fit` can fill up before CHUNK_SIZE nodes
+            slots.reserve_exact(CHUNK_SIZE - slots.len());
+        }
+        slots.push(Node {
             point,
             data,
             left: None,
             right: None,
         });
+        self.len += 1;
         index
     }
 
     /// Get a reference to a node by index.
     pub fn get(&self, index: usize) -> &Node<P, T> {
-        &self.nodes[index]
+        assert!(index < self.len, "node index out of range");
+        &self.chunks[index / CHUNK_SIZE][index % CHUNK_SIZE]
     }
 
     /// Get a mutable reference to a node by index.
     pub fn get_mut(&mut self, index: usize) -> &mut Node<P, T> {
-        &mut self.nodes[index]
+        assert!(index < self.len, "node index out of range");
+        &mut self.chunks[index / CHUNK_SIZE][index % CHUNK_SIZE]
     }
 
     /// Get the number of allocated nodes.
     pub fn len(&self) -> usize {
-        self.nodes.len()
+        self.len
     }
 
     /// Check if the arena is empty.
     pub fn is_empty(&self) -> bool {
-        self.nodes.is_empty()
+        self.len == 0
+    }
+
+    /// Number of nodes the arena holds without allocating.
+    pub fn capacity(&self) -> usize {
+        self.chunks.iter().map(Vec::capacity).sum()
+    }
+
+    /// Reserve room for at least `additional` more nodes, so that many allocations need no
+    /// further memory. Room is reserved in whole chunks.
+    pub fn reserve(&mut self, additional: usize) {
+        let needed = (self.len + additional).div_ceil(CHUNK_SIZE);
+        if let Some(last) = self.chunks.last_mut() {
+            last.reserve_exact(CHUNK_SIZE - last.len());
+        }
+        self.chunks
+            .reserve_exact(needed.saturating_sub(self.chunks.len()));
+        while self.chunks.len() < needed {
+            self.chunks.push(Vec::with_capacity(CHUNK_SIZE));
+        }
+    }
+
+    /// Release reserved room that no node occupies: unused chunks are freed and the last
+    /// chunk is trimmed to its nodes.
+    ///
+    /// Trimming reallocates the last chunk, so unlike allocation this may move up to
+    /// `CHUNK_SIZE - 1` nodes in memory; indices are unaffected.
+    pub fn shrink_to_fit(&mut self) {
+        self.chunks.truncate(self.len.div_ceil(CHUNK_SIZE));
+        if let Some(last) = self.chunks.last_mut() {
+            last.shrink_to_fit();
+        }
+        self.chunks.shrink_to_fit();
+    }
+
+    /// Estimate of the bytes the arena has allocated, including reserved room.
+    ///
+    /// Counts the node slots and chunk table, not memory owned by the points or payloads
+    /// themselves (such as the contents of a `String` payload).
+    pub fn memory_bytes(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.chunks.capacity() * std::mem::size_of::<Vec<Node<P, T>>>()
+            + self.capacity() * std::mem::size_of::<Node<P, T>>()
+    }
+
+    /// Take the nodes out of the arena, in index order, so node `i` is at position `i`.
+    ///
+    /// Together with `from_parts` this moves an index's contents without copying them, for
+    /// example to hand a tree to a thread that rebuilds it in the background.
+    pub fn into_parts(self) -> Vec<Node<P, T>> {
+        let mut nodes = Vec::with_capacity(self.len);
+        for chunk in self.chunks {
+            nodes.extend(chunk);
+        }
+        nodes
+    }
+
+    /// Rebuild an arena from nodes in index order, as returned by `into_parts`.
+    ///
+    /// # Panics
+    /// Panics if a node links to an index outside `nodes`.
+    pub fn from_parts(nodes: Vec<Node<P, T>>) -> Self {
+        let len = nodes.len();
+        assert!(
+            nodes
+                .iter()
+                .flat_map(|node| node.left.into_iter().chain(node.right))
+                .all(|child| child < len),
+            "node links to an index outside the arena"
+        );
+        let mut chunks = Vec::with_capacity(len.div_ceil(CHUNK_SIZE));
+        let mut nodes = nodes.into_iter();
+        while chunks.len() < len.div_ceil(CHUNK_SIZE) {
+            let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+            chunk.extend(nodes.by_ref().take(CHUNK_SIZE));
+            chunks.push(chunk);
+        }
+        NodeArena { chunks, len }
+    }
+}
+
+/// Cloning copies every node, keeping each chunk's reserved room so the copy grows like
+/// the original.
+impl<P: Point + Clone, T: Clone> Clone for NodeArena<P, T> {
+    fn clone(&self) -> Self {
+        let chunks = self
+            .chunks
+            .iter()
+            .map(|chunk| {
+                let mut copy = Vec::with_capacity(chunk.capacity());
+                copy.extend(chunk.iter().cloned());
+                copy
+            })
+            .collect();
+        NodeArena {
+            chunks,
+            len: self.len,
+        }
+    }
+}
+
+impl<P: Point, T> NodeStore<P, T> for NodeArena<P, T> {
+    fn node(&self, index: usize) -> &Node<P, T> {
+        self.get(index)
+    }
+
+    fn node_mut(&mut self, index: usize) -> &mut Node<P, T> {
+        self.get_mut(index)
+    }
+
+    fn allocate(&mut self, point: P, data: T) -> usize {
+        NodeArena::allocate(self, point, data)
     }
 }
 
@@ -131,46 +322,286 @@ impl<P: Point, T> Default for NodeArena<P, T> {
 ///
 /// # Usage pattern:
 /// ```rust
+/// # use bkd::{BoundingBox, InMemoryLinker, NodeArena};
+/// # let (point, data) = (BoundingBox::new(0.0, 0.0, 1.0, 1.0), 1);
 /// let mut arena = NodeArena::new();
 /// let node1 = arena.allocate(point, data);  // User allocates
 /// let mut linker = InMemoryLinker::new(&mut arena);  // Linker borrows arena
 /// // Tree algorithms use linker, don't see arena directly
 /// ```
-pub struct InMemoryLinker<'a, P: Point, T> {
-    arena: &'a mut NodeArena<P, T>,
+///
+/// Any `NodeStore` can back the linker; `NodeArena` is the default.
+pub struct InMemoryLinker<'a, P: Point, T, A: NodeStore<P, T> = NodeArena<P, T>> {
+    arena: &'a mut A,
+    _marker: PhantomData<fn() -> (P, T)>,
 }
 
-impl<'a, P: Point, T> InMemoryLinker<'a, P, T> {
+impl<'a, P: Point, T, A: NodeStore<P, T>> InMemoryLinker<'a, P, T, A> {
     /// Create a new linker that operates on the given arena.
-    pub fn new(arena: &'a mut NodeArena<P, T>) -> Self {
-        InMemoryLinker { arena }
+    pub fn new(arena: &'a mut A) -> Self {
+        InMemoryLinker {
+            arena,
+            _marker: PhantomData,
+        }
     }
 }
 
-impl<'a, P: Point, T> NodeLinker<P, T> for InMemoryLinker<'a, P, T> {
+impl<'a, P: Point, T, A: NodeStore<P, T>> NodeLinker<P, T> for InMemoryLinker<'a, P, T, A> {
     type NodeRef = usize; // Use index instead of raw pointer
 
     fn link_left(&mut self, parent: Self::NodeRef, child: Self::NodeRef) {
-        self.arena.get_mut(parent).left = Some(child);
+        self.arena.node_mut(parent).left = Some(child);
     }
 
     fn link_right(&mut self, parent: Self::NodeRef, child: Self::NodeRef) {
-        self.arena.get_mut(parent).right = Some(child);
+        self.arena.node_mut(parent).right = Some(child);
     }
 
     fn get_left(&self, node: Self::NodeRef) -> Option<Self::NodeRef> {
-        self.arena.get(node).left
+        self.arena.node(node).left
     }
 
     fn get_right(&self, node: Self::NodeRef) -> Option<Self::NodeRef> {
-        self.arena.get(node).right
+        self.arena.node(node).right
     }
 
     fn get_point(&self, node: Self::NodeRef) -> &P {
-        self.arena.get(node).get_point()
+        self.arena.node(node).get_point()
     }
 
     fn get_data(&self, node: Self::NodeRef) -> &T {
-        self.arena.get(node).get_data()
+        self.arena.node(node).get_data()
+    }
+}
+
+impl<'a, P: Point, T, A: NodeStore<P, T>> AllocatingLinker<P, T> for InMemoryLinker<'a, P, T, A> {
+    fn allocate(&mut self, point: P, data: T) -> usize {
+        self.arena.allocate(point, data)
+    }
+}
+
+/// Read-only linker over a shared arena.
+///
+/// # Concurrency
+/// `InMemoryLinker` needs `&mut` access to the arena even for searches, which forces
+/// readers to serialize. `ArenaView` borrows the arena immutably, so any number of threads
+/// can search the same arena at once (e.g. behind an `Arc<NodeArena>` in a server).
+/// Linking through a view panics: build the tree first, then share it.
+pub struct ArenaView<'a, P: Point, T, A: NodeStore<P, T> = NodeArena<P, T>> {
+    arena: &'a A,
+    _marker: PhantomData<fn() -> (P, T)>,
+}
+
+impl<'a, P: Point, T, A: NodeStore<P, T>> ArenaView<'a, P, T, A> {
+    /// Create a read-only linker over the given arena.
+    pub fn new(arena: &'a A) -> Self {
+        ArenaView {
+            arena,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, P: Point, T, A: NodeStore<P, T>> Clone for ArenaView<'a, P, T, A> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, P: Point, T, A: NodeStore<P, T>> Copy for ArenaView<'a, P, T, A> {}
+
+impl<'a, P: Point, T, A: NodeStore<P, T>> NodeLinker<P, T> for ArenaView<'a, P, T, A> {
+    type NodeRef = usize;
+
+    fn link_left(&mut self, _parent: Self::NodeRef, _child: Self::NodeRef) {
+        panic!("cannot link nodes through a read-only ArenaView");
+    }
+
+    fn link_right(&mut self, _parent: Self::NodeRef, _child: Self::NodeRef) {
+        panic!("cannot link nodes through a read-only ArenaView");
+    }
+
+    fn get_left(&self, node: Self::NodeRef) -> Option<Self::NodeRef> {
+        self.arena.node(node).left
+    }
+
+    fn get_right(&self, node: Self::NodeRef) -> Option<Self::NodeRef> {
+        self.arena.node(node).right
+    }
+
+    fn get_point(&self, node: Self::NodeRef) -> &P {
+        self.arena.node(node).get_point()
+    }
+
+    fn get_data(&self, node: Self::NodeRef) -> &T {
+        self.arena.node(node).get_data()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spatial::BoundingBox;
+
+    #[test]
+    fn test_arena_growth_keeps_nodes_in_place() {
+        let mut arena = NodeArena::new();
+        let first = arena.allocate(BoundingBox::new(0.0, 0.0, 1.0, 1.0), 0usize);
+        let address = arena.get(first) as *const Node<BoundingBox, usize>;
+
+        for i in 1..3 * CHUNK_SIZE + 7 {
+            let x = i as f64;
+            assert_eq!(arena.allocate(BoundingBox::new(x, x, x, x), i), i);
+        }
+        assert_eq!(arena.len(), 3 * CHUNK_SIZE + 7);
+        assert_eq!(arena.chunks.len(), 4);
+        assert!(std::ptr::eq(arena.get(first), address));
+
+        for i in [0, CHUNK_SIZE - 1, CHUNK_SIZE, 3 * CHUNK_SIZE + 6] {
+            assert_eq!(arena.get(i).data, i);
+            assert_eq!(arena.get(i).point.xmin, i as f64);
+        }
+        arena.get_mut(CHUNK_SIZE).left = Some(0);
+        assert_eq!(arena.get(CHUNK_SIZE).left, Some(0));
+    }
+
+    #[test]
+    fn test_arena_view_concurrent_searches() {
+        use crate::search::{insert_node, spatial_search};
+        use std::sync::Arc;
+
+        let mut arena = NodeArena::new();
+        let nodes: Vec<usize> = (0..500)
+            .map(|i| {
+                let x = (i * 13 % 100) as f64;
+                let y = (i * 29 % 100) as f64;
+                arena.allocate(BoundingBox::new(x, y, x + 1.0, y + 1.0), i)
+            })
+            .collect();
+        let mut linker = InMemoryLinker::new(&mut arena);
+        let root = insert_node(&mut linker, None, nodes[0], 0);
+        for &node in &nodes[1..] {
+            insert_node(&mut linker, Some(root), node, 0);
+        }
+        let query = BoundingBox::new(20.0, 20.0, 60.0, 40.0);
+        let expected = spatial_search(&linker, Some(root), &query, 0);
+
+        let arena = Arc::new(arena);
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let arena = arena.clone();
+                let query = query.clone();
+                std::thread::spawn(move || {
+                    let view = ArenaView::new(&*arena);
+                    spatial_search(&view, Some(root), &query, 0)
+                })
+            })
+            .collect();
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_get_many_resolves_in_order() {
+        let mut arena = NodeArena::new();
+        for i in 0..5 {
+            let x = i as f64;
+            arena.allocate(BoundingBox::new(x, x, x, x), i * 10);
+        }
+        let view = ArenaView::new(&arena);
+        let refs = vec![4, 0, 2];
+        let resolved: Vec<(f64, i32)> = view
+            .get_many(&refs)
+            .map(|(point, data)| (point.xmin, *data))
+            .collect();
+        drop(refs);
+        assert_eq!(resolved, [(4.0, 40), (0.0, 0), (2.0, 20)]);
+    }
+
+    #[test]
+    fn test_arena_reserve_and_shrink() {
+        let mut arena: NodeArena<BoundingBox, u64> = NodeArena::new();
+        let empty = arena.memory_bytes();
+        arena.reserve(2 * CHUNK_SIZE + 1);
+        assert_eq!(arena.capacity(), 3 * CHUNK_SIZE);
+        assert!(arena.memory_bytes() > empty + 3 * CHUNK_SIZE * 48);
+
+        for i in 0..CHUNK_SIZE + 10 {
+            arena.allocate(BoundingBox::new(0.0, 0.0, 1.0, 1.0), i as u64);
+        }
+        let reserved = arena.memory_bytes();
+        arena.shrink_to_fit();
+        assert_eq!(arena.capacity(), CHUNK_SIZE + 10);
+        assert!(arena.memory_bytes() < reserved);
+        assert_eq!(arena.get(CHUNK_SIZE + 9).data, (CHUNK_SIZE + 9) as u64);
+
+        // Growing after a shrink refills the trimmed chunk to full size
+        let first = arena.get(0) as *const Node<BoundingBox, u64>;
+        arena.allocate(BoundingBox::new(2.0, 2.0, 3.0, 3.0), 0);
+        assert_eq!(arena.capacity(), 2 * CHUNK_SIZE);
+        assert!(std::ptr::eq(arena.get(0), first));
+        arena.reserve(CHUNK_SIZE);
+        assert_eq!(arena.capacity(), 3 * CHUNK_SIZE);
+
+        let mut cleared: NodeArena<BoundingBox, u64> = NodeArena::with_capacity(10);
+        cleared.shrink_to_fit();
+        assert_eq!(cleared.capacity(), 0);
+    }
+
+    #[test]
+    fn test_arena_clone_and_parts() {
+        use crate::search::spatial_search;
+
+        let mut arena = NodeArena::new();
+        let mut nodes: Vec<usize> = (0..CHUNK_SIZE + 100)
+            .map(|i| {
+                let x = (i * 7 % 500) as f64;
+                arena.allocate(BoundingBox::new(x, x, x + 1.0, x + 1.0), i)
+            })
+            .collect();
+        let mut linker = InMemoryLinker::new(&mut arena);
+        let root = crate::build::bulk_build(
+            &mut linker,
+            &mut nodes,
+            0,
+            &crate::build::BuildOptions::default(),
+        )
+        .unwrap();
+        let query = BoundingBox::new(100.0, 100.0, 200.0, 200.0);
+        let expected = spatial_search(&ArenaView::new(&arena), root, &query, 0);
+
+        let mut copy = arena.clone();
+        assert_eq!(copy.capacity(), arena.capacity());
+        copy.get_mut(0).data = usize::MAX;
+        assert_eq!(arena.get(0).data, 0);
+        assert_eq!(
+            spatial_search(&ArenaView::new(&copy), root, &query, 0),
+            expected
+        );
+
+        // Parts cross a thread boundary and come back as the same tree
+        let parts = std::thread::spawn(move || arena.into_parts())
+            .join()
+            .unwrap();
+        assert_eq!(parts.len(), CHUNK_SIZE + 100);
+        assert_eq!(parts[CHUNK_SIZE + 5].data, CHUNK_SIZE + 5);
+        let rebuilt = NodeArena::from_parts(parts);
+        assert_eq!(rebuilt.len(), CHUNK_SIZE + 100);
+        assert_eq!(
+            spatial_search(&ArenaView::new(&rebuilt), root, &query, 0),
+            expected
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "node links to an index outside the arena")]
+    fn test_from_parts_rejects_dangling_links() {
+        NodeArena::from_parts(vec![Node {
+            point: BoundingBox::new(0.0, 0.0, 1.0, 1.0),
+            data: (),
+            left: Some(1),
+            right: None,
+        }]);
     }
 }
@@ -0,0 +1,222 @@
+//! Per-subtree bounding summaries for node trees.
+
+use crate::query::{Relation, SpatialQuery};
+use crate::search::children_to_visit;
+use crate::spatial::Point;
+use crate::storage::NodeLinker;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Per-dimension min/max of every subtree of a node tree.
+///
+/// A node's split value only bounds its subtrees in one dimension; the summary bounds
+/// them in all of them. `spatial_search_summarized` relates each subtree's bounds to the
+/// query, as Lucene's `relate()` does for BKD cells, to prune subtrees that only the
+/// summary can rule out and to collect subtrees inside the query without testing their
+/// nodes.
+///
+/// Summaries are optional and kept beside the tree, so a tree that never needs them pays
+/// nothing. They cost `16 * dimensions` bytes per node plus a map entry, and describe the
+/// tree as it was when built: rebuild them after inserting.
+#[derive(Debug, Clone)]
+pub struct SubtreeBounds<R> {
+    dimensions: usize,
+    slots: HashMap<R, usize>,
+    /// `dimensions` minimums followed by `dimensions` maximums per slot.
+    bounds: Vec<f64>,
+}
+
+impl<R: Copy + Eq + Hash> SubtreeBounds<R> {
+    /// Compute the bounds of every subtree reachable from `root`.
+    pub fn build<P: Point, T, L: NodeLinker<P, T, NodeRef = R>>(
+        linker: &L,
+        root: Option<R>,
+    ) -> Self {
+        let dimensions = root.map_or(0, |node| linker.get_point(node).dimensions());
+        let mut summary = SubtreeBounds::empty(dimensions);
+
+        // Post-order: a node is summarized once both children are
+        let mut stack: Vec<(R, bool)> = root.map(|node| (node, false)).into_iter().collect();
+        while let Some((node, children_done)) = stack.pop() {
+            if !children_done {
+                stack.push((node, true));
+                stack.extend(linker.get_right(node).map(|child| (child, false)));
+                stack.extend(linker.get_left(node).map(|child| (child, false)));
+                continue;
+            }
+
+            let children = [linker.get_left(node), linker.get_right(node)];
+            summary.insert(node, linker.get_point(node), children.into_iter().flatten());
+        }
+        summary
+    }
+
+    pub(crate) fn empty(dimensions: usize) -> Self {
+        SubtreeBounds {
+            dimensions,
+            slots: HashMap::new(),
+            bounds: Vec::new(),
+        }
+    }
+
+    /// Summarize `node` from its point and its already summarized children.
+    pub(crate) fn insert<P: Point>(
+        &mut self,
+        node: R,
+        point: &P,
+        children: impl IntoIterator<Item = R>,
+    ) {
+        let dimensions = self.dimensions;
+        let mut cell: Vec<f64> = (0..dimensions)
+            .map(|dim| point.get_dimension(dim))
+            .collect();
+        cell.extend_from_within(..);
+        for child in children {
+            let (min, max) = self.get(child).expect("children are summarized first");
+            for dim in 0..dimensions {
+                cell[dim] = cell[dim].min(min[dim]);
+                cell[dimensions + dim] = cell[dimensions + dim].max(max[dim]);
+            }
+        }
+        self.slots
+            .insert(node, self.bounds.len() / (2 * dimensions));
+        self.bounds.extend_from_slice(&cell);
+    }
+
+    /// Bounds of the subtree rooted at `node` as `(min, max)`, if it was summarized.
+    pub fn get(&self, node: R) -> Option<(&[f64], &[f64])> {
+        let slot = *self.slots.get(&node)?;
+        let cell = &self.bounds[slot * 2 * self.dimensions..(slot + 1) * 2 * self.dimensions];
+        Some(cell.split_at(self.dimensions))
+    }
+
+    /// Number of summarized subtrees.
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Check if no subtree is summarized.
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+}
+
+/// Spatial search that uses subtree summaries to prune and to skip per-node tests.
+///
+/// Finds the same nodes as `spatial_search`, in the same order. Each subtree is first related
+/// to the query through its summary: subtrees outside the query are skipped, subtrees inside
+/// it are collected without a single `matches` call, and only crossing subtrees fall back to
+/// split-value pruning. Nodes without a summary are treated as crossing.
+pub fn spatial_search_summarized<P: Point, T, L: NodeLinker<P, T>, Q: SpatialQuery<P>>(
+    linker: &L,
+    root: Option<L::NodeRef>,
+    query: &Q,
+    depth: usize,
+    summary: &SubtreeBounds<L::NodeRef>,
+) -> Vec<L::NodeRef>
+where
+    L::NodeRef: Eq + Hash,
+{
+    let mut results = Vec::new();
+    let mut stack: Vec<(L::NodeRef, usize)> = root.map(|node| (node, depth)).into_iter().collect();
+    while let Some((node, depth)) = stack.pop() {
+        let relation = summary
+            .get(node)
+            .map_or(Relation::CellCrossesQuery, |(min, max)| {
+                query.relate(min, max)
+            });
+        match relation {
+            Relation::CellOutsideQuery => continue,
+            Relation::CellInsideQuery => {
+                collect_subtree(linker, node, &mut results);
+                continue;
+            }
+            Relation::CellCrossesQuery => {}
+        }
+
+        let point = linker.get_point(node);
+        if query.matches(point) {
+            results.push(node);
+        }
+        // Push right first so the left subtree is reported first, as in pre-order
+        let (visit_left, visit_right) = children_to_visit(point, query, depth);
+        if visit_right {
+            stack.extend(linker.get_right(node).map(|child| (child, depth + 1)));
+        }
+        if visit_left {
+            stack.extend(linker.get_left(node).map(|child| (child, depth + 1)));
+        }
+    }
+    results
+}
+
+/// Append every node of the subtree rooted at `node` in pre-order.
+fn collect_subtree<P: Point, T, L: NodeLinker<P, T>>(
+    linker: &L,
+    node: L::NodeRef,
+    results: &mut Vec<L::NodeRef>,
+) {
+    let mut stack = vec![node];
+    while let Some(node) = stack.pop() {
+        results.push(node);
+        stack.extend(linker.get_right(node));
+        stack.extend(linker.get_left(node));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::{BuildOptions, bulk_build};
+    use crate::query::Circle;
+    use crate::search::spatial_search;
+    use crate::spatial::BoundingBox;
+    use crate::storage::{InMemoryLinker, NodeArena};
+
+    #[test]
+    fn test_summarized_search_matches_spatial_search() {
+        let mut arena = NodeArena::new();
+        let mut nodes: Vec<usize> = (0..500)
+            .map(|i| {
+                let x = ((i * 37) % 101) as f64;
+                let y = ((i * 53) % 97) as f64;
+                arena.allocate(BoundingBox::new(x, y, x + 2.0, y + 1.0), i)
+            })
+            .collect();
+        let mut linker = InMemoryLinker::new(&mut arena);
+        let root = bulk_build(&mut linker, &mut nodes, 0, &BuildOptions::default()).unwrap();
+        let summary = SubtreeBounds::build(&linker, root);
+        assert_eq!(summary.len(), 500);
+
+        let (min, max) = summary.get(root.unwrap()).unwrap();
+        assert_eq!(min, &[0.0, 0.0, 2.0, 1.0]);
+        assert_eq!(max, &[100.0, 96.0, 102.0, 97.0]);
+
+        for query in [
+            BoundingBox::new(10.0, 10.0, 40.0, 30.0),
+            BoundingBox::new(-5.0, -5.0, 200.0, 200.0),
+            BoundingBox::new(150.0, 150.0, 160.0, 160.0),
+        ] {
+            let expected = spatial_search(&linker, root, &query, 0);
+            let results = spatial_search_summarized(&linker, root, &query, 0, &summary);
+            assert_eq!(results, expected);
+        }
+
+        let circle = Circle::new(50.0, 50.0, 20.0);
+        let expected = spatial_search(&linker, root, &circle, 0);
+        assert_eq!(
+            spatial_search_summarized(&linker, root, &circle, 0, &summary),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_summarized_search_empty_tree() {
+        let mut arena = NodeArena::<BoundingBox, u32>::new();
+        let linker = InMemoryLinker::new(&mut arena);
+        let summary = SubtreeBounds::build(&linker, None);
+        assert!(summary.is_empty());
+        let query = BoundingBox::new(0.0, 0.0, 1.0, 1.0);
+        assert!(spatial_search_summarized(&linker, None, &query, 0, &summary).is_empty());
+    }
+}
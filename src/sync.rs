@@ -0,0 +1,17 @@
+//! Synchronization primitives shared by the concurrent structures.
+//!
+//! Under `--cfg bkd_loom` these are loom's model-checked versions, so the `loom_tests`
+//! modules can explore every interleaving of the link updates in `SharedTree` and the frame
+//! bookkeeping in `BufferPool`. Run them with:
+//! ```text
+//! RUSTFLAGS="--cfg bkd_loom" cargo test --release --lib loom_tests
+//! ```
+//! The cfg is not the conventional `loom` because Tokio (a dev-dependency) reacts to that
+//! one itself. Run only `loom_tests` in that configuration: other tests use the primitives
+//! outside a loom model.
+
+#[cfg(bkd_loom)]
+pub(crate) use loom::sync::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard, atomic};
+
+#[cfg(not(bkd_loom))]
+pub(crate) use std::sync::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard, atomic};
@@ -10,14 +10,22 @@ This bridges your BKD spatial indexing algorithms with Tantivy's storage system.
 */
 
 use crate::BoundingBox;
-use crate::spatial::{Point, SpatialPoint};
-use crate::storage::NodeLinker;
+use crate::codec::FixedCodec;
+use crate::node_file::NO_NODE;
+use crate::spatial::Point;
+use crate::storage::{AllocatingLinker, NodeLinker, RootedLinker};
+use bincode::Options;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tantivy::TantivyError;
+use tantivy::error::DataCorruption;
+use tantivy::directory::error::{DeleteError, OpenReadError};
 use tantivy::directory::{Directory, MmapDirectory};
 
 /// Node reference for TantivyLinker - uses u64 as file offset
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct TantivyNodeRef(pub u64);
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -29,19 +37,86 @@ pub struct Node<P, T> {
 }
 
 /// TantivyLinker implements NodeLinker using Tantivy's storage system
-pub struct TantivyLinker<T> {
+///
+/// Points are any `Point` serde can encode: `BoundingBox` by default, or 3D boxes, lat/lon
+/// pairs and custom types, named as `TantivyLinker<T, P>`.
+///
+/// # File layout
+/// Every file of an index is namespaced by its prefix, so several indexes can share one
+/// Directory:
+/// - `{prefix}_node_{id}.bkd`: one node per file, in the layout below
+/// - `{prefix}.files`: manifest naming every node file ever written under the prefix
+/// - `{prefix}.root`: the root `set_root` recorded, as a `u64` like the links below
+///
+/// Tantivy's `Directory` cannot list its files, so the manifest is what makes cleanup
+/// possible. It is written before the node files it names, so a crash mid-`persist` leaves
+/// files that are tracked, never leaked. The root is written last, once the nodes it
+/// reaches are in place; indexes persisted before the root file existed open without a
+/// root.
+///
+/// A node file holds, little-endian and fixed-width like every other format of the crate,
+/// so it opens on any architecture:
+/// ```text
+/// "BKDT" | version u32 | left u64 | right u64 | point (bincode) | payload (bincode)
+/// ```
+/// The bincode encoding of a `BoundingBox` is its four coordinates as `f64`, so files of
+/// the default point type read the same as before points were generic.
+/// Absent links are `u64::MAX`. Files from before the magic existed are plain bincode of
+/// `Node`, and still open.
+///
+/// # Read path
+/// `open` reads only the manifest and the root. Each node gets a slot that is filled from its file the
+/// first time a search reaches it, and kept. `get_point` and `get_data` borrow from the
+/// slot, so payloads are never cloned, and `T` need not be `Clone` at all. A filled slot
+/// never moves or changes while the linker is shared, which is what lets a `&self` read
+/// load a node and hand out a reference to it; links and new nodes take `&mut self`.
+pub struct TantivyLinker<T, P = BoundingBox> {
     directory: Box<dyn Directory>,
-    nodes: HashMap<TantivyNodeRef, Node<BoundingBox, T>>,
+    /// Every node of the index, loaded or not; a node whose file is missing or cannot be
+    /// decoded loads as `None`.
+    nodes: HashMap<TantivyNodeRef, OnceLock<Option<Node<P, T>>>>,
     file_prefix: String,
+    next_id: u64,
+    root: Option<TantivyNodeRef>,
 }
 
-impl<T: Clone> TantivyLinker<T> {
+/// Magic bytes opening a node file.
+const NODE_MAGIC: [u8; 4] = *b"BKDT";
+
+/// Current node file version.
+const NODE_VERSION: u32 = 1;
+
+/// Bytes before the point: magic, version and both links.
+const NODE_HEADER_SIZE: usize = 24;
+
+/// Bincode settings for points and payloads, spelled out rather than left to the crate's defaults:
+/// little-endian, fixed-width integers, `usize` widened to 64 bits.
+fn payload_options() -> impl Options + Copy {
+    bincode::DefaultOptions::new()
+        .with_little_endian()
+        .with_fixint_encoding()
+}
+
+fn encode_link(link: Option<TantivyNodeRef>, buf: &mut [u8]) {
+    link.map_or(NO_NODE, |node_ref| node_ref.0).encode(buf);
+}
+
+fn decode_link(buf: &[u8]) -> Option<TantivyNodeRef> {
+    match u64::decode(buf) {
+        NO_NODE => None,
+        id => Some(TantivyNodeRef(id)),
+    }
+}
+
+impl<T, P> TantivyLinker<T, P> {
     /// Create a new TantivyLinker with file-based storage
     pub fn new_with_directory(directory: Box<dyn Directory>, file_prefix: String) -> Self {
         Self {
             directory,
             nodes: HashMap::new(),
             file_prefix,
+            next_id: 0,
+            root: None,
         }
     }
 
@@ -51,22 +126,49 @@ impl<T: Clone> TantivyLinker<T> {
         Ok(Self::new_with_directory(Box::new(directory), file_prefix))
     }
 
-    /// Serialize a node to bytes for storage
-    fn serialize_node(&self, node: &Node<BoundingBox, T>) -> Vec<u8>
+    /// Serialize a node to bytes for storage. Fails if serde cannot encode the point or
+    /// payload, rather than writing a file that would load as a missing node.
+    fn serialize_node(&self, node: &Node<P, T>) -> tantivy::Result<Vec<u8>>
     where
+        P: serde::Serialize,
         T: serde::Serialize,
     {
-        // Simple binary format for now
-        // TODO: Use more efficient serialization (bincode, postcard, etc.)
-        bincode::serialize(node).unwrap_or_else(|_| Vec::new())
+        let mut bytes = vec![0u8; NODE_HEADER_SIZE];
+        bytes[0..4].copy_from_slice(&NODE_MAGIC);
+        NODE_VERSION.encode(&mut bytes[4..8]);
+        encode_link(node.left, &mut bytes[8..16]);
+        encode_link(node.right, &mut bytes[16..24]);
+        let options = payload_options();
+        options
+            .serialize_into(&mut bytes, &node.point)
+            .and_then(|()| options.serialize_into(&mut bytes, &node.data))
+            .map_err(|error| {
+                TantivyError::InvalidArgument(format!("cannot encode node: {error}"))
+            })?;
+        Ok(bytes)
     }
 
-    /// Deserialize a node from bytes
-    fn deserialize_node(&self, bytes: &[u8]) -> Option<Node<BoundingBox, T>>
+    /// Deserialize a node from bytes, in the current layout or the bincode one of files
+    /// written before it
+    fn deserialize_node(&self, bytes: &[u8]) -> Option<Node<P, T>>
     where
+        P: serde::de::DeserializeOwned,
         T: serde::de::DeserializeOwned,
     {
-        bincode::deserialize(bytes).ok()
+        if bytes.len() < NODE_HEADER_SIZE || bytes[0..4] != NODE_MAGIC {
+            return bincode::deserialize(bytes).ok();
+        }
+        if u32::decode(&bytes[4..8]) != NODE_VERSION {
+            return None;
+        }
+        let mut rest = &bytes[NODE_HEADER_SIZE..];
+        let options = payload_options();
+        Some(Node {
+            left: decode_link(&bytes[8..16]),
+            right: decode_link(&bytes[16..24]),
+            point: options.deserialize_from(&mut rest).ok()?,
+            data: options.deserialize_from(&mut rest).ok()?,
+        })
     }
 
     /// Get filename for a node
@@ -75,44 +177,283 @@ impl<T: Clone> TantivyLinker<T> {
     }
 }
 
-impl<T: Clone + serde::Serialize + serde::de::DeserializeOwned> NodeLinker<BoundingBox, T>
-    for TantivyLinker<T>
+impl<T, P> TantivyLinker<T, P>
+where
+    T: serde::Serialize + serde::de::DeserializeOwned,
+    P: Point + serde::Serialize + serde::de::DeserializeOwned,
+{
+    /// Open the index stored under `file_prefix`, with a slot for every node listed in its
+    /// manifest. Nodes are read when first reached; those whose files are missing or
+    /// cannot be decoded (e.g. left half-written by a crash), or fail to read, behave as
+    /// absent, and `collect_garbage` deletes their files.
+    pub fn open(directory: Box<dyn Directory>, file_prefix: String) -> tantivy::Result<Self> {
+        let mut linker = Self::new_with_directory(directory, file_prefix);
+        linker.root = match linker.directory.atomic_read(&linker.root_path()) {
+            Ok(bytes) if bytes.len() == 8 => decode_link(&bytes),
+            Ok(_) => {
+                return Err(TantivyError::DataCorruption(DataCorruption::comment_only(
+                    format!("{} is not a root record", linker.root_path().display()),
+                )));
+            }
+            Err(OpenReadError::FileDoesNotExist(_)) => None,
+            Err(error) => return Err(error.into()),
+        };
+        for file in linker.read_manifest()? {
+            let Some(node_ref) = linker.parse_node_filename(&file) else {
+                continue;
+            };
+            // Never hand out the id of a listed file, even one that fails to load
+            linker.next_id = linker.next_id.max(node_ref.0 + 1);
+            linker.nodes.insert(node_ref, OnceLock::new());
+        }
+        Ok(linker)
+    }
+
+    /// Number of nodes read from their files or added since `open`.
+    pub fn loaded(&self) -> usize {
+        self.nodes
+            .values()
+            .filter(|slot| slot.get().is_some())
+            .count()
+    }
+
+    /// The node behind `node_ref`, read from its file on first access.
+    fn node(&self, node_ref: TantivyNodeRef) -> Option<&Node<P, T>> {
+        self.nodes
+            .get(&node_ref)?
+            .get_or_init(|| {
+                let path = PathBuf::from(self.get_node_filename(node_ref));
+                let bytes = self.directory.atomic_read(&path).ok()?;
+                self.deserialize_node(&bytes)
+            })
+            .as_ref()
+    }
+
+    fn node_mut(&mut self, node_ref: TantivyNodeRef) -> Option<&mut Node<P, T>> {
+        self.node(node_ref)?;
+        self.nodes.get_mut(&node_ref)?.get_mut()?.as_mut()
+    }
+
+    /// Add an unlinked node and return its reference.
+    pub fn add_node(&mut self, point: P, data: T) -> TantivyNodeRef {
+        let node_ref = TantivyNodeRef(self.next_id);
+        self.next_id += 1;
+        let node = Node {
+            point,
+            data,
+            left: None,
+            right: None,
+        };
+        self.nodes.insert(node_ref, OnceLock::from(Some(node)));
+        node_ref
+    }
+
+    /// Write every loaded node to its file, after recording the files in the manifest, and
+    /// then the root. Nodes never loaded are unchanged since they were last written.
+    pub fn persist(&mut self) -> tantivy::Result<()> {
+        let mut files = self.read_manifest()?;
+        files.extend(
+            self.nodes
+                .keys()
+                .map(|&node_ref| PathBuf::from(self.get_node_filename(node_ref))),
+        );
+        self.write_manifest(&files)?;
+        for (&node_ref, slot) in &self.nodes {
+            let Some(Some(node)) = slot.get() else {
+                continue;
+            };
+            let path = PathBuf::from(self.get_node_filename(node_ref));
+            self.directory
+                .atomic_write(&path, &self.serialize_node(node)?)?;
+        }
+        let mut root = [0u8; 8];
+        encode_link(self.root, &mut root);
+        self.directory.atomic_write(&self.root_path(), &root)?;
+        Ok(())
+    }
+
+    /// Files of this index present in the Directory, manifest and root included, sorted by
+    /// name.
+    pub fn list_files(&self) -> tantivy::Result<Vec<PathBuf>> {
+        let mut files = BTreeSet::new();
+        for file in self.read_manifest()? {
+            if self.directory.exists(&file)? {
+                files.insert(file);
+            }
+        }
+        for file in [self.manifest_path(), self.root_path()] {
+            if self.directory.exists(&file)? {
+                files.insert(file);
+            }
+        }
+        Ok(files.into_iter().collect())
+    }
+
+    /// Delete every node file not reachable from the recorded root, dropping the
+    /// unreachable nodes from memory too, and return the deleted files.
+    ///
+    /// Reachable nodes that were never persisted have no file and are kept. Files the
+    /// manifest names for nodes that failed to load, such as half-written ones, count as
+    /// unreachable.
+    pub fn collect_garbage(&mut self) -> tantivy::Result<Vec<PathBuf>> {
+        let mut reachable = HashSet::new();
+        let mut stack: Vec<TantivyNodeRef> = self.root.into_iter().collect();
+        while let Some(node_ref) = stack.pop() {
+            if let Some(node) = self.node(node_ref) {
+                if reachable.insert(node_ref) {
+                    stack.extend(node.left);
+                    stack.extend(node.right);
+                }
+            }
+        }
+        self.nodes
+            .retain(|node_ref, _| reachable.contains(node_ref));
+
+        let mut kept = BTreeSet::new();
+        let mut deleted = Vec::new();
+        for file in self.read_manifest()? {
+            let live = self
+                .parse_node_filename(&file)
+                .is_some_and(|node_ref| reachable.contains(&node_ref));
+            if live {
+                kept.insert(file);
+            } else if self.delete_file(&file)? {
+                deleted.push(file);
+            }
+        }
+        self.write_manifest(&kept)?;
+        Ok(deleted)
+    }
+
+    /// Delete every file of this index, manifest last, so an interrupted deletion can be
+    /// resumed by opening the index and deleting it again.
+    pub fn delete_index(self) -> tantivy::Result<()> {
+        for file in self.read_manifest()? {
+            self.delete_file(&file)?;
+        }
+        self.delete_file(&self.root_path())?;
+        self.delete_file(&self.manifest_path())?;
+        Ok(())
+    }
+
+    fn manifest_path(&self) -> PathBuf {
+        PathBuf::from(format!("{}.files", self.file_prefix))
+    }
+
+    fn root_path(&self) -> PathBuf {
+        PathBuf::from(format!("{}.root", self.file_prefix))
+    }
+
+    fn read_manifest(&self) -> tantivy::Result<BTreeSet<PathBuf>> {
+        match self.directory.atomic_read(&self.manifest_path()) {
+            Ok(bytes) => Ok(String::from_utf8_lossy(&bytes)
+                .lines()
+                .map(PathBuf::from)
+                .collect()),
+            Err(OpenReadError::FileDoesNotExist(_)) => Ok(BTreeSet::new()),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    fn write_manifest(&self, files: &BTreeSet<PathBuf>) -> tantivy::Result<()> {
+        let mut contents = String::new();
+        for file in files {
+            contents.push_str(&file.to_string_lossy());
+            contents.push('\n');
+        }
+        self.directory
+            .atomic_write(&self.manifest_path(), contents.as_bytes())?;
+        Ok(())
+    }
+
+    /// Node reference named by a node file of this index.
+    fn parse_node_filename(&self, file: &Path) -> Option<TantivyNodeRef> {
+        let name = file.to_str()?;
+        let id = name
+            .strip_prefix(&self.file_prefix)?
+            .strip_prefix("_node_")?
+            .strip_suffix(".bkd")?;
+        id.parse().ok().map(TantivyNodeRef)
+    }
+
+    /// Delete a file, returning whether it existed.
+    fn delete_file(&self, file: &Path) -> tantivy::Result<bool> {
+        match self.directory.delete(file) {
+            Ok(()) => Ok(true),
+            Err(DeleteError::FileDoesNotExist(_)) => Ok(false),
+            Err(DeleteError::IoError { io_error, .. }) => Err(TantivyError::IoError(io_error)),
+        }
+    }
+}
+
+impl<T, P> NodeLinker<P, T> for TantivyLinker<T, P>
+where
+    T: serde::Serialize + serde::de::DeserializeOwned,
+    P: Point + serde::Serialize + serde::de::DeserializeOwned,
 {
     type NodeRef = TantivyNodeRef;
 
-    fn get_point(&self, node_ref: Self::NodeRef) -> &BoundingBox {
-        &self.nodes.get(&node_ref).unwrap().point
+    fn get_point(&self, node_ref: Self::NodeRef) -> &P {
+        &self.node(node_ref).unwrap().point
     }
 
     fn get_data(&self, node_ref: Self::NodeRef) -> &T {
-        &self.nodes.get(&node_ref).unwrap().data
+        &self.node(node_ref).unwrap().data
     }
 
     fn get_left(&self, node_ref: Self::NodeRef) -> Option<Self::NodeRef> {
-        self.nodes.get(&node_ref)?.left
+        self.node(node_ref)?.left
     }
 
     fn get_right(&self, node_ref: Self::NodeRef) -> Option<Self::NodeRef> {
-        self.nodes.get(&node_ref)?.right
+        self.node(node_ref)?.right
     }
 
     fn link_left(&mut self, parent_ref: Self::NodeRef, child_ref: Self::NodeRef) {
-        if let Some(parent) = self.nodes.get_mut(&parent_ref) {
+        if let Some(parent) = self.node_mut(parent_ref) {
             parent.left = Some(child_ref);
         }
     }
 
     fn link_right(&mut self, parent_ref: Self::NodeRef, child_ref: Self::NodeRef) {
-        if let Some(parent) = self.nodes.get_mut(&parent_ref) {
+        if let Some(parent) = self.node_mut(parent_ref) {
             parent.right = Some(child_ref);
         }
     }
 }
 
+impl<T, P> AllocatingLinker<P, T> for TantivyLinker<T, P>
+where
+    T: serde::Serialize + serde::de::DeserializeOwned,
+    P: Point + serde::Serialize + serde::de::DeserializeOwned,
+{
+    fn allocate(&mut self, point: P, data: T) -> TantivyNodeRef {
+        self.add_node(point, data)
+    }
+}
+
+/// The root is stored beside the manifest by `persist` and restored by `open`.
+impl<T, P> RootedLinker<P, T> for TantivyLinker<T, P>
+where
+    T: serde::Serialize + serde::de::DeserializeOwned,
+    P: Point + serde::Serialize + serde::de::DeserializeOwned,
+{
+    fn get_root(&self) -> Option<TantivyNodeRef> {
+        self.root
+    }
+
+    fn set_root(&mut self, root: Option<TantivyNodeRef>) {
+        self.root = root;
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::query::RangeQuery;
+    use crate::search::{insert_node, spatial_search};
     use crate::{InMemoryLinker, NodeArena};
+    use tantivy::directory::RamDirectory;
 
     #[test]
     fn test_tantivy_linker_creation() {
@@ -140,4 +481,285 @@ mod tests {
         // let tantivy_linker = TantivyLinker::new_temp("test".to_string()).unwrap();
         // ... same operations should work
     }
+
+    #[test]
+    fn test_node_files_are_platform_independent() {
+        let linker = TantivyLinker::<(u16, u64)>::new_with_directory(
+            Box::new(RamDirectory::create()),
+            "a".to_string(),
+        );
+        let node = Node {
+            point: BoundingBox::new(1.0, 0.0, 0.0, -2.0),
+            data: (0x0102, 3),
+            left: Some(TantivyNodeRef(0x0a0b)),
+            right: None,
+        };
+        let bytes = linker.serialize_node(&node).unwrap();
+
+        let mut expected = b"BKDT".to_vec();
+        expected.extend([1, 0, 0, 0]);
+        expected.extend([0x0b, 0x0a, 0, 0, 0, 0, 0, 0]);
+        expected.extend([0xff; 8]);
+        expected.extend([0, 0, 0, 0, 0, 0, 0xf0, 0x3f]);
+        expected.extend([0; 16]);
+        expected.extend([0, 0, 0, 0, 0, 0, 0, 0xc0]);
+        expected.extend([0x02, 0x01, 3, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(bytes, expected);
+
+        let decoded = linker.deserialize_node(&bytes).unwrap();
+        assert_eq!(
+            (decoded.point, decoded.data),
+            (node.point.clone(), node.data)
+        );
+        assert_eq!((decoded.left, decoded.right), (node.left, node.right));
+
+        // Files written before the explicit layout still open
+        let legacy = linker
+            .deserialize_node(&bincode::serialize(&node).unwrap())
+            .unwrap();
+        assert_eq!(legacy.left, node.left);
+        assert_eq!(legacy.data, node.data);
+    }
+
+    #[test]
+    fn test_persist_list_and_delete() {
+        let directory = RamDirectory::create();
+        let mut linker =
+            TantivyLinker::<u32>::new_with_directory(Box::new(directory.clone()), "a".to_string());
+        let refs: Vec<TantivyNodeRef> = (0..3)
+            .map(|i| {
+                let x = f64::from(i);
+                linker.add_node(BoundingBox::new(x, x, x + 1.0, x + 1.0), i)
+            })
+            .collect();
+        let root = insert_node(&mut linker, None, refs[0], 0);
+        insert_node(&mut linker, Some(root), refs[1], 0);
+        insert_node(&mut linker, Some(root), refs[2], 0);
+        linker.persist().unwrap();
+
+        // A second index in the same directory is left alone
+        let mut other =
+            TantivyLinker::<u32>::new_with_directory(Box::new(directory.clone()), "b".to_string());
+        other.add_node(BoundingBox::new(0.0, 0.0, 1.0, 1.0), 7);
+        other.persist().unwrap();
+
+        let files: Vec<String> = linker
+            .list_files()
+            .unwrap()
+            .iter()
+            .map(|file| file.to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(
+            files,
+            [
+                "a.files",
+                "a.root",
+                "a_node_0.bkd",
+                "a_node_1.bkd",
+                "a_node_2.bkd"
+            ]
+        );
+
+        let reopened =
+            TantivyLinker::<u32>::open(Box::new(directory.clone()), "a".to_string()).unwrap();
+        let query = BoundingBox::new(0.5, 0.5, 1.5, 1.5);
+        let mut found: Vec<u32> = spatial_search(&reopened, Some(root), &query, 0)
+            .into_iter()
+            .map(|node| *reopened.get_data(node))
+            .collect();
+        found.sort_unstable();
+        assert_eq!(found, [0, 1]);
+
+        reopened.delete_index().unwrap();
+        assert!(!directory.exists(Path::new("a_node_0.bkd")).unwrap());
+        assert!(!directory.exists(Path::new("a.files")).unwrap());
+        assert!(!directory.exists(Path::new("a.root")).unwrap());
+        assert_eq!(other.list_files().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_nodes_load_on_first_access_without_cloning() {
+        // A payload that cannot be cloned
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Document(String);
+
+        let directory = RamDirectory::create();
+        let mut linker = TantivyLinker::<Document>::new_with_directory(
+            Box::new(directory.clone()),
+            "docs".to_string(),
+        );
+        let mut root = None;
+        for i in 0..50 {
+            let x = f64::from(i);
+            let node = linker.add_node(
+                BoundingBox::new(x, x, x + 1.0, x + 1.0),
+                Document(format!("doc-{i}")),
+            );
+            root = Some(insert_node(&mut linker, root, node, 0));
+        }
+        linker.persist().unwrap();
+
+        let reopened =
+            TantivyLinker::<Document>::open(Box::new(directory.clone()), "docs".to_string())
+                .unwrap();
+        assert_eq!(reopened.loaded(), 0);
+        let found = spatial_search(&reopened, root, &BoundingBox::new(0.2, 0.2, 0.8, 0.8), 0);
+        assert_eq!(found.len(), 1);
+        let data = reopened.get_data(found[0]);
+        assert_eq!(*data, Document("doc-0".to_string()));
+        assert!(std::ptr::eq(data, reopened.get_data(found[0])));
+        let loaded = reopened.loaded();
+        assert!(loaded > 0 && loaded < 50);
+    }
+
+    #[test]
+    fn test_other_point_types_round_trip() {
+        let directory = RamDirectory::create();
+        // 3D boxes as [xmin, ymin, zmin, xmax, ymax, zmax]
+        let mut boxes = TantivyLinker::<u32, [f64; 6]>::new_with_directory(
+            Box::new(directory.clone()),
+            "boxes".to_string(),
+        );
+        let mut lat_lon = TantivyLinker::<u32, (f64, f64)>::new_with_directory(
+            Box::new(directory.clone()),
+            "geo".to_string(),
+        );
+        let (mut box_root, mut geo_root) = (None, None);
+        for i in 0..40u32 {
+            let z = f64::from(i);
+            let node = boxes.add_node([0.0, 0.0, z, 1.0, 1.0, z + 0.5], i);
+            box_root = Some(insert_node(&mut boxes, box_root, node, 0));
+            let node = lat_lon.add_node((48.0 + z / 100.0, 2.0 - z / 100.0), i);
+            geo_root = Some(insert_node(&mut lat_lon, geo_root, node, 0));
+        }
+        boxes.persist().unwrap();
+        lat_lon.persist().unwrap();
+
+        let boxes =
+            TantivyLinker::<u32, [f64; 6]>::open(Box::new(directory.clone()), "boxes".to_string())
+                .unwrap();
+        let slab = RangeQuery::new().with_range(2, 10.0, 12.0);
+        let mut found: Vec<u32> = spatial_search(&boxes, box_root, &slab, 0)
+            .into_iter()
+            .map(|node| *boxes.get_data(node))
+            .collect();
+        found.sort_unstable();
+        assert_eq!(found, [10, 11, 12]);
+
+        let lat_lon =
+            TantivyLinker::<u32, (f64, f64)>::open(Box::new(directory.clone()), "geo".to_string())
+                .unwrap();
+        let north = RangeQuery::new().with_range(0, 48.355, 90.0);
+        let found = spatial_search(&lat_lon, geo_root, &north, 0);
+        assert_eq!(found.len(), 4);
+        assert!(found.iter().all(|&node| lat_lon.get_point(node).0 > 48.355));
+    }
+
+    #[test]
+    fn test_collect_garbage_removes_orphans() {
+        let directory = RamDirectory::create();
+        let mut linker = TantivyLinker::<u32>::new_with_directory(
+            Box::new(directory.clone()),
+            "idx".to_string(),
+        );
+        let root = linker.add_node(BoundingBox::new(0.0, 0.0, 1.0, 1.0), 0);
+        let child = linker.add_node(BoundingBox::new(2.0, 2.0, 3.0, 3.0), 1);
+        let orphan = linker.add_node(BoundingBox::new(4.0, 4.0, 5.0, 5.0), 2);
+        insert_node(&mut linker, Some(root), child, 0);
+        linker.set_root(Some(root));
+        linker.persist().unwrap();
+
+        // A node file half-written before a crash: listed, but undecodable
+        let mut files = linker.read_manifest().unwrap();
+        files.insert(PathBuf::from("idx_node_9.bkd"));
+        linker.write_manifest(&files).unwrap();
+        directory
+            .atomic_write(Path::new("idx_node_9.bkd"), &[1, 2])
+            .unwrap();
+
+        let mut reopened =
+            TantivyLinker::<u32>::open(Box::new(directory.clone()), "idx".to_string()).unwrap();
+        let deleted = reopened.collect_garbage().unwrap();
+        assert_eq!(
+            deleted,
+            [
+                linker.get_node_filename(orphan),
+                "idx_node_9.bkd".to_string()
+            ]
+            .map(PathBuf::from)
+        );
+        assert_eq!(reopened.list_files().unwrap().len(), 4);
+        assert_eq!(
+            reopened.add_node(BoundingBox::new(0.0, 0.0, 0.0, 0.0), 3),
+            TantivyNodeRef(10)
+        );
+    }
+
+    #[test]
+    fn test_root_is_persisted_with_the_nodes() {
+        let directory = RamDirectory::create();
+        let mut linker = TantivyLinker::<u32>::new_with_directory(
+            Box::new(directory.clone()),
+            "rooted".to_string(),
+        );
+        linker.persist().unwrap();
+        let empty =
+            TantivyLinker::<u32>::open(Box::new(directory.clone()), "rooted".to_string()).unwrap();
+        assert_eq!(empty.get_root(), None);
+
+        for i in 0..30u32 {
+            let x = f64::from((i * 7) % 30);
+            let node = linker.add_node(BoundingBox::new(x, x, x + 1.0, x + 1.0), i);
+            let root = linker.get_root();
+            let root = insert_node(&mut linker, root, node, 0);
+            linker.set_root(Some(root));
+        }
+        linker.persist().unwrap();
+
+        let reopened =
+            TantivyLinker::<u32>::open(Box::new(directory.clone()), "rooted".to_string()).unwrap();
+        assert_eq!(reopened.get_root(), linker.get_root());
+        let query = BoundingBox::new(10.5, 10.5, 12.5, 12.5);
+        let mut found: Vec<u32> = spatial_search(&reopened, reopened.get_root(), &query, 0)
+            .into_iter()
+            .map(|node| *reopened.get_data(node))
+            .collect();
+        found.sort_unstable();
+        // Entries at x = 10, 11 and 12
+        assert_eq!(found, [6, 10, 23]);
+
+        // A root file that is not a root record
+        directory
+            .atomic_write(Path::new("rooted.root"), &[1, 2, 3])
+            .unwrap();
+        assert!(
+            TantivyLinker::<u32>::open(Box::new(directory.clone()), "rooted".to_string()).is_err()
+        );
+    }
+
+    #[test]
+    fn test_persist_fails_on_payloads_serde_cannot_encode() {
+        // A payload whose Serialize impl fails, as any serde type may
+        struct Unencodable;
+        impl Serialize for Unencodable {
+            fn serialize<S: serde::Serializer>(&self, _: S) -> Result<S::Ok, S::Error> {
+                Err(serde::ser::Error::custom("unencodable payload"))
+            }
+        }
+        impl<'de> Deserialize<'de> for Unencodable {
+            fn deserialize<D: serde::Deserializer<'de>>(_: D) -> Result<Self, D::Error> {
+                Ok(Unencodable)
+            }
+        }
+
+        let directory = RamDirectory::create();
+        let mut linker = TantivyLinker::<Unencodable>::new_with_directory(
+            Box::new(directory.clone()),
+            "bad".to_string(),
+        );
+        linker.add_node(BoundingBox::new(0.0, 0.0, 1.0, 1.0), Unencodable);
+        let error = linker.persist().unwrap_err();
+        assert!(error.to_string().contains("unencodable payload"));
+        assert!(!directory.exists(Path::new("bad_node_0.bkd")).unwrap());
+    }
 }
@@ -0,0 +1,290 @@
+//! A Tantivy query matching documents by their fast-field coordinates (feature `tantivy`).
+//!
+//! `FastFieldSpatialQuery` filters a segment's documents with any `SpatialQuery` over
+//! `FastFieldPoint`s. By default every match scores the query's boost, like a filter; with
+//! `with_distance_scoring` a match scores higher the closer it lies to a reference point, so
+//! combined with a text query in a `BooleanQuery` nearby documents rank first.
+
+use crate::nearest::Metric;
+use crate::query::SpatialQuery;
+use crate::tantivy_points::{FastFieldCoordinates, FastFieldPoint};
+use std::fmt;
+use std::sync::Arc;
+use tantivy::query::{EnableScoring, Explanation, Query, Scorer, Weight};
+use tantivy::{DocId, DocSet, Score, SegmentReader, TERMINATED, TantivyError};
+
+/// A spatial filter usable for the points of any segment.
+type SharedFilter = Arc<dyn for<'a> SpatialQuery<FastFieldPoint<'a>> + Send + Sync>;
+
+/// How matching documents are scored.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SpatialScoring {
+    /// Every match scores the query's boost.
+    Constant,
+    /// A match at `distance` from `reference` scores `boost * pivot / (pivot + distance)`:
+    /// the full boost at the reference, half of it at the pivot distance.
+    Distance {
+        reference: Vec<f64>,
+        metric: Metric,
+        pivot: f64,
+    },
+}
+
+impl SpatialScoring {
+    fn score(&self, point: &FastFieldPoint<'_>) -> Score {
+        match self {
+            SpatialScoring::Constant => 1.0,
+            SpatialScoring::Distance {
+                reference,
+                metric,
+                pivot,
+            } => (pivot / (pivot + metric.distance(point, reference))) as Score,
+        }
+    }
+}
+
+/// Tantivy query for the documents whose coordinates match a spatial query.
+#[derive(Clone)]
+pub struct FastFieldSpatialQuery {
+    fields: Vec<String>,
+    filter: SharedFilter,
+    scoring: SpatialScoring,
+}
+
+impl FastFieldSpatialQuery {
+    /// Match the documents whose point, read from the `f64` fast fields named by `fields` in
+    /// dimension order, matches `filter`. Documents lacking a coordinate never match.
+    pub fn new<Q>(fields: &[&str], filter: Q) -> Self
+    where
+        Q: for<'a> SpatialQuery<FastFieldPoint<'a>> + Send + Sync + 'static,
+    {
+        FastFieldSpatialQuery {
+            fields: fields.iter().map(|field| field.to_string()).collect(),
+            filter: Arc::new(filter),
+            scoring: SpatialScoring::Constant,
+        }
+    }
+
+    /// Score matches by their distance to `reference` under `metric`, halving the score at
+    /// `pivot`.
+    ///
+    /// # Panics
+    /// Panics if `pivot` is not finite and positive.
+    pub fn with_distance_scoring(
+        mut self,
+        reference: Vec<f64>,
+        metric: Metric,
+        pivot: f64,
+    ) -> Self {
+        assert!(
+            pivot.is_finite() && pivot > 0.0,
+            "distance scoring pivot must be finite and positive"
+        );
+        self.scoring = SpatialScoring::Distance {
+            reference,
+            metric,
+            pivot,
+        };
+        self
+    }
+
+    /// How matches are scored.
+    pub fn scoring(&self) -> &SpatialScoring {
+        &self.scoring
+    }
+}
+
+impl fmt::Debug for FastFieldSpatialQuery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FastFieldSpatialQuery")
+            .field("fields", &self.fields)
+            .field("scoring", &self.scoring)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Query for FastFieldSpatialQuery {
+    fn weight(&self, enable_scoring: EnableScoring<'_>) -> tantivy::Result<Box<dyn Weight>> {
+        let scoring = if enable_scoring.is_scoring_enabled() {
+            self.scoring.clone()
+        } else {
+            SpatialScoring::Constant
+        };
+        Ok(Box::new(SpatialWeight {
+            fields: self.fields.clone(),
+            filter: Arc::clone(&self.filter),
+            scoring,
+        }))
+    }
+}
+
+struct SpatialWeight {
+    fields: Vec<String>,
+    filter: SharedFilter,
+    scoring: SpatialScoring,
+}
+
+impl SpatialWeight {
+    fn coordinates(&self, reader: &SegmentReader) -> tantivy::Result<FastFieldCoordinates> {
+        let fields: Vec<&str> = self.fields.iter().map(String::as_str).collect();
+        FastFieldCoordinates::open(reader, &fields)
+    }
+}
+
+impl Weight for SpatialWeight {
+    fn scorer(&self, reader: &SegmentReader, boost: Score) -> tantivy::Result<Box<dyn Scorer>> {
+        let coordinates = self.coordinates(reader)?;
+        let hits = (0..reader.max_doc())
+            .filter(|&doc| !reader.is_deleted(doc))
+            .filter_map(|doc| {
+                let point = coordinates.point(doc)?;
+                self.filter
+                    .matches(&point)
+                    .then(|| (doc, boost * self.scoring.score(&point)))
+            })
+            .collect();
+        Ok(Box::new(SpatialScorer { hits, cursor: 0 }))
+    }
+
+    fn explain(&self, reader: &SegmentReader, doc: DocId) -> tantivy::Result<Explanation> {
+        let coordinates = self.coordinates(reader)?;
+        let point = coordinates
+            .point(doc)
+            .filter(|point| self.filter.matches(point))
+            .ok_or_else(|| {
+                TantivyError::InvalidArgument(format!("Document #({doc}) does not match"))
+            })?;
+        let score = self.scoring.score(&point);
+        match &self.scoring {
+            SpatialScoring::Constant => Ok(Explanation::new("SpatialQuery", score)),
+            SpatialScoring::Distance {
+                reference, metric, ..
+            } => {
+                let mut explanation =
+                    Explanation::new("SpatialQuery, pivot / (pivot + distance)", score);
+                explanation.add_const("distance", metric.distance(&point, reference) as Score);
+                Ok(explanation)
+            }
+        }
+    }
+}
+
+/// Matches of one segment, in doc id order, with their scores.
+struct SpatialScorer {
+    hits: Vec<(DocId, Score)>,
+    cursor: usize,
+}
+
+impl DocSet for SpatialScorer {
+    fn advance(&mut self) -> DocId {
+        self.cursor = (self.cursor + 1).min(self.hits.len());
+        self.doc()
+    }
+
+    fn doc(&self) -> DocId {
+        self.hits
+            .get(self.cursor)
+            .map_or(TERMINATED, |&(doc, _)| doc)
+    }
+
+    fn size_hint(&self) -> u32 {
+        self.hits.len() as u32
+    }
+}
+
+impl Scorer for SpatialScorer {
+    fn score(&mut self) -> Score {
+        self.hits.get(self.cursor).map_or(0.0, |&(_, score)| score)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::RangeQuery;
+    use tantivy::collector::TopDocs;
+    use tantivy::query::{BooleanQuery, ConstScoreQuery, Occur, TermQuery};
+    use tantivy::schema::{FAST, IndexRecordOption, STRING, Schema};
+    use tantivy::{DocAddress, Index, IndexWriter, Term, doc};
+
+    #[test]
+    fn test_distance_scores_rank_nearer_documents_first() {
+        let mut schema = Schema::builder();
+        let x = schema.add_f64_field("x", FAST);
+        let y = schema.add_f64_field("y", FAST);
+        let kind = schema.add_text_field("kind", STRING);
+        let index = Index::create_in_ram(schema.build());
+        let mut writer: IndexWriter = index.writer_with_num_threads(1, 15_000_000).unwrap();
+        for i in 0..100 {
+            let label = if i % 2 == 0 { "cafe" } else { "bar" };
+            writer
+                .add_document(doc!(x => (i % 10) as f64, y => (i / 10) as f64, kind => label))
+                .unwrap();
+        }
+        writer.add_document(doc!(x => 5.0)).unwrap();
+        writer.commit().unwrap();
+        let searcher = index.reader().unwrap().searcher();
+
+        let box_query = RangeQuery::new()
+            .with_range(0, 2.0, 7.0)
+            .with_range(1, 2.0, 7.0);
+        let constant = FastFieldSpatialQuery::new(&["x", "y"], box_query.clone());
+        let hits = searcher
+            .search(&constant, &TopDocs::with_limit(100))
+            .unwrap();
+        assert_eq!(hits.len(), 36);
+        assert!(hits.iter().all(|&(score, _)| score == 1.0));
+
+        let scored = FastFieldSpatialQuery::new(&["x", "y"], box_query).with_distance_scoring(
+            vec![5.0, 5.0],
+            Metric::euclidean(),
+            1.0,
+        );
+        let hits = searcher.search(&scored, &TopDocs::with_limit(3)).unwrap();
+        assert_eq!(hits[0], (1.0, DocAddress::new(0, 55)));
+        assert_eq!(hits[1].0, 0.5);
+        assert!(hits[2].0 == 0.5);
+        let explanation = scored.explain(&searcher, DocAddress::new(0, 44)).unwrap();
+        assert!((explanation.value() - 1.0 / (1.0 + 2f32.sqrt())).abs() < 1e-6);
+        assert!(scored.explain(&searcher, DocAddress::new(0, 0)).is_err());
+        assert!(scored.explain(&searcher, DocAddress::new(0, 100)).is_err());
+
+        // Spatial relevance combined with a text filter: the nearest cafes, one unit away
+        let around = RangeQuery::new()
+            .with_range(0, 2.0, 8.0)
+            .with_range(1, 2.0, 8.0);
+        let nearby = FastFieldSpatialQuery::new(&["x", "y"], around).with_distance_scoring(
+            vec![5.0, 5.0],
+            Metric::euclidean(),
+            1.0,
+        );
+        let cafes = TermQuery::new(
+            Term::from_field_text(kind, "cafe"),
+            IndexRecordOption::Basic,
+        );
+        let combined = BooleanQuery::new(vec![
+            (Occur::Must, Box::new(nearby) as Box<dyn Query>),
+            (
+                Occur::Must,
+                Box::new(ConstScoreQuery::new(Box::new(cafes), 0.0)),
+            ),
+        ]);
+        let hits = searcher
+            .search(&combined, &TopDocs::with_limit(100))
+            .unwrap();
+        assert_eq!(hits.len(), 28);
+        assert!(hits.iter().all(|&(_, address)| address.doc_id % 2 == 0));
+        assert_eq!(hits[0].0, 0.5);
+        assert!(hits.windows(2).all(|pair| pair[0].0 >= pair[1].0));
+    }
+
+    #[test]
+    #[should_panic(expected = "pivot must be finite and positive")]
+    fn test_rejects_zero_pivot() {
+        let _ = FastFieldSpatialQuery::new(&["x"], RangeQuery::new()).with_distance_scoring(
+            vec![0.0],
+            Metric::euclidean(),
+            0.0,
+        );
+    }
+}
@@ -0,0 +1,507 @@
+//! Export of an index as a pyramid of web-map tiles, one GeoJSON file per tile.
+//!
+//! Tiles follow the slippy-map convention: at zoom `z` the Web Mercator square is cut into
+//! `2^z × 2^z` tiles, `x` counting east from the antimeridian and `y` south from the top
+//! (XYZ) or north from the bottom (TMS).
+
+use crate::projection::{Identity, Projection, WebMercator};
+use crate::search::spatial_search;
+use crate::spatial::BoundingBox;
+use crate::storage::NodeLinker;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+/// A tile of the pyramid, addressed XYZ-style.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TileId {
+    pub z: u8,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl TileId {
+    /// Create a tile id; `x` and `y` must be below `2^z`.
+    pub fn new(z: u8, x: u32, y: u32) -> Self {
+        debug_assert!(z < 32 && x < 1 << z && y < 1 << z);
+        TileId { z, x, y }
+    }
+
+    /// Extent of the tile in longitude/latitude degrees.
+    pub fn bounds(&self) -> BoundingBox {
+        let n = (1u64 << self.z) as f64;
+        let lon = |x: u32| x as f64 / n * 360.0 - 180.0;
+        let lat = |y: u32| {
+            let (_, lat) = WebMercator.inverse(
+                0.0,
+                WebMercator::EARTH_RADIUS * std::f64::consts::PI * (1.0 - 2.0 * y as f64 / n),
+            );
+            lat
+        };
+        BoundingBox::new(lon(self.x), lat(self.y + 1), lon(self.x + 1), lat(self.y))
+    }
+
+    /// Row of the tile counted from the bottom, as TMS numbers it.
+    pub fn tms_y(&self) -> u32 {
+        (1 << self.z) - 1 - self.y
+    }
+
+    /// The four tiles of the next zoom covering this one.
+    pub fn children(&self) -> [TileId; 4] {
+        let (z, x, y) = (self.z + 1, self.x * 2, self.y * 2);
+        [
+            TileId::new(z, x, y),
+            TileId::new(z, x + 1, y),
+            TileId::new(z, x, y + 1),
+            TileId::new(z, x + 1, y + 1),
+        ]
+    }
+}
+
+/// How tile rows are numbered in exported paths.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TileScheme {
+    /// Rows counted from the top, as most web maps expect: `z/x/y`.
+    #[default]
+    Xyz,
+    /// Rows counted from the bottom: `z/x/tms_y`.
+    Tms,
+}
+
+/// A feature property value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PropertyValue {
+    Number(f64),
+    Text(String),
+    Bool(bool),
+}
+
+impl From<f64> for PropertyValue {
+    fn from(value: f64) -> Self {
+        PropertyValue::Number(value)
+    }
+}
+
+impl From<u64> for PropertyValue {
+    fn from(value: u64) -> Self {
+        PropertyValue::Number(value as f64)
+    }
+}
+
+impl From<i64> for PropertyValue {
+    fn from(value: i64) -> Self {
+        PropertyValue::Number(value as f64)
+    }
+}
+
+impl From<bool> for PropertyValue {
+    fn from(value: bool) -> Self {
+        PropertyValue::Bool(value)
+    }
+}
+
+impl From<&str> for PropertyValue {
+    fn from(value: &str) -> Self {
+        PropertyValue::Text(value.to_string())
+    }
+}
+
+impl From<String> for PropertyValue {
+    fn from(value: String) -> Self {
+        PropertyValue::Text(value)
+    }
+}
+
+/// Properties of the feature exported for a payload.
+type PropertiesFn<'a, T> = Box<dyn Fn(&T) -> Vec<(String, PropertyValue)> + 'a>;
+
+/// Writes the entries of a `BoundingBox` tree as per-tile GeoJSON `FeatureCollection`s,
+/// laid out as `dir/z/x/y.geojson` for web-map renderers and tile servers.
+///
+/// # Architecture Decision: descend the pyramid, skip empty tiles
+/// The export starts at the single tile of zoom 0 and searches the tree for each tile's
+/// extent; only tiles that hold entries are split into their four children. Sparse data,
+/// a city at zoom 18 say, thus costs searches along its tiles, not across the billions
+/// of empty ones. Features are written whole into every tile they overlap, unclipped, so
+/// renderers see complete geometries; entries whose box is a point become `Point`s and
+/// the rest `Polygon`s.
+///
+/// # Usage pattern:
+/// ```rust
+/// use bkd::{BoundingBox, SpatialIndex, TileExport};
+///
+/// let mut index = SpatialIndex::new();
+/// index.insert(BoundingBox::new(2.25, 48.81, 2.42, 48.90), ("paris", 2_100_000u64));
+///
+/// let dir = std::env::temp_dir().join("bkd-tiles-doc");
+/// let written = TileExport::new(0..=2)
+///     .with_properties(|&(name, population): &(&str, u64)| {
+///         vec![("name".into(), name.into()), ("population".into(), population.into())]
+///     })
+///     .write_geojson(&index, index.root(), &dir)
+///     .unwrap();
+/// assert_eq!(written, 3);
+/// assert!(dir.join("2/2/1.geojson").exists());
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+pub struct TileExport<'a, T> {
+    zooms: RangeInclusive<u8>,
+    scheme: TileScheme,
+    projection: Box<dyn Projection + 'a>,
+    properties: PropertiesFn<'a, T>,
+}
+
+impl<'a, T> TileExport<'a, T> {
+    /// Export the tiles of `zooms`, for an index of longitude/latitude boxes, with empty
+    /// properties.
+    pub fn new(zooms: RangeInclusive<u8>) -> Self {
+        TileExport {
+            zooms,
+            scheme: TileScheme::default(),
+            projection: Box::new(Identity),
+            properties: Box::new(|_| Vec::new()),
+        }
+    }
+
+    /// Number the rows of exported paths by `scheme`.
+    pub fn with_scheme(mut self, scheme: TileScheme) -> Self {
+        self.scheme = scheme;
+        self
+    }
+
+    /// Map index coordinates to and from longitude/latitude through `projection`, for
+    /// indexes holding projected boxes such as Web Mercator meters.
+    pub fn with_projection(mut self, projection: impl Projection + 'a) -> Self {
+        self.projection = Box::new(projection);
+        self
+    }
+
+    /// Give each feature the properties `properties` derives from its payload.
+    pub fn with_properties(
+        mut self,
+        properties: impl Fn(&T) -> Vec<(String, PropertyValue)> + 'a,
+    ) -> Self {
+        self.properties = Box::new(properties);
+        self
+    }
+
+    /// Write every non-empty tile of the zoom range under `dir`. Returns the number of
+    /// tiles written.
+    pub fn write_geojson<L: NodeLinker<BoundingBox, T>>(
+        &self,
+        linker: &L,
+        root: Option<L::NodeRef>,
+        dir: &Path,
+    ) -> io::Result<usize> {
+        self.write_tiles(linker, root, dir, "geojson", |nodes, _| {
+            self.feature_collection(linker, nodes)
+        })
+    }
+
+    /// GeoJSON of a single tile, for serving tiles on demand; an empty collection if the
+    /// tile holds no entries.
+    pub fn tile_geojson<L: NodeLinker<BoundingBox, T>>(
+        &self,
+        linker: &L,
+        root: Option<L::NodeRef>,
+        tile: TileId,
+    ) -> String {
+        let nodes = self.search(linker, root, tile);
+        self.feature_collection(linker, &nodes)
+    }
+
+    /// Write every non-empty tile of the zoom range under `dir` as a Mapbox Vector Tile
+    /// holding one layer named `layer` (feature `mvt`). Returns the number of tiles written.
+    #[cfg(feature = "mvt")]
+    pub fn write_mvt<L: NodeLinker<BoundingBox, T>>(
+        &self,
+        linker: &L,
+        root: Option<L::NodeRef>,
+        dir: &Path,
+        layer: &str,
+    ) -> io::Result<usize> {
+        self.write_tiles(linker, root, dir, "mvt", |nodes, tile| {
+            self.vector_tile(linker, nodes, tile, layer)
+        })
+    }
+
+    /// Mapbox Vector Tile of a single tile, holding one layer named `layer` (feature
+    /// `mvt`): the encoded body of a `z/x/y.mvt` response.
+    ///
+    /// Geometries are in the tile's 4096-unit grid, clipped a little beyond its edges.
+    /// A box that rounds to less than a unit in either direction becomes a point.
+    #[cfg(feature = "mvt")]
+    pub fn tile_mvt<L: NodeLinker<BoundingBox, T>>(
+        &self,
+        linker: &L,
+        root: Option<L::NodeRef>,
+        tile: TileId,
+        layer: &str,
+    ) -> Vec<u8> {
+        let nodes = self.search(linker, root, tile);
+        self.vector_tile(linker, &nodes, tile, layer)
+    }
+
+    /// Descend the pyramid and write the non-empty tiles of the zoom range as `encode`
+    /// renders them.
+    fn write_tiles<L: NodeLinker<BoundingBox, T>, C: AsRef<[u8]>>(
+        &self,
+        linker: &L,
+        root: Option<L::NodeRef>,
+        dir: &Path,
+        extension: &str,
+        encode: impl Fn(&[L::NodeRef], TileId) -> C,
+    ) -> io::Result<usize> {
+        let mut written = 0;
+        let mut stack = vec![TileId::new(0, 0, 0)];
+        while let Some(tile) = stack.pop() {
+            let nodes = self.search(linker, root, tile);
+            if nodes.is_empty() {
+                continue;
+            }
+            if tile.z >= *self.zooms.start() {
+                let row = match self.scheme {
+                    TileScheme::Xyz => tile.y,
+                    TileScheme::Tms => tile.tms_y(),
+                };
+                let column = dir.join(tile.z.to_string()).join(tile.x.to_string());
+                fs::create_dir_all(&column)?;
+                fs::write(
+                    column.join(format!("{row}.{extension}")),
+                    encode(&nodes, tile),
+                )?;
+                written += 1;
+            }
+            if tile.z < *self.zooms.end() {
+                stack.extend(tile.children());
+            }
+        }
+        Ok(written)
+    }
+
+    fn search<L: NodeLinker<BoundingBox, T>>(
+        &self,
+        linker: &L,
+        root: Option<L::NodeRef>,
+        tile: TileId,
+    ) -> Vec<L::NodeRef> {
+        let extent = self.projection.forward_box(&tile.bounds());
+        spatial_search(linker, root, &extent, 0)
+    }
+
+    #[cfg(feature = "mvt")]
+    fn vector_tile<L: NodeLinker<BoundingBox, T>>(
+        &self,
+        linker: &L,
+        nodes: &[L::NodeRef],
+        tile: TileId,
+        layer: &str,
+    ) -> Vec<u8> {
+        let extent = WebMercator.forward_box(&tile.bounds());
+        let scale = crate::mvt::EXTENT as f64 / (extent.xmax - extent.xmin);
+        let mut encoder = crate::mvt::LayerEncoder::new(layer);
+        for &node in nodes {
+            let lon_lat = self.projection.inverse_box(linker.get_point(node));
+            let meters = WebMercator.forward_box(&lon_lat);
+            // Tile-local, with y growing down from the tile's north edge
+            let local = BoundingBox::new(
+                (meters.xmin - extent.xmin) * scale,
+                (extent.ymax - meters.ymax) * scale,
+                (meters.xmax - extent.xmin) * scale,
+                (extent.ymax - meters.ymin) * scale,
+            );
+            encoder.add(&local, &(self.properties)(linker.get_data(node)));
+        }
+        encoder.finish()
+    }
+
+    fn feature_collection<L: NodeLinker<BoundingBox, T>>(
+        &self,
+        linker: &L,
+        nodes: &[L::NodeRef],
+    ) -> String {
+        let mut json = String::from(r#"{"type":"FeatureCollection","features":["#);
+        for (i, &node) in nodes.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            let bbox = self.projection.inverse_box(linker.get_point(node));
+            json.push_str(r#"{"type":"Feature","geometry":"#);
+            write_geometry(&mut json, &bbox);
+            json.push_str(r#","properties":{"#);
+            for (j, (key, value)) in (self.properties)(linker.get_data(node)).iter().enumerate() {
+                if j > 0 {
+                    json.push(',');
+                }
+                write_string(&mut json, key);
+                json.push(':');
+                match value {
+                    PropertyValue::Number(number) => write_number(&mut json, *number),
+                    PropertyValue::Text(text) => write_string(&mut json, text),
+                    PropertyValue::Bool(flag) => {
+                        json.push_str(if *flag { "true" } else { "false" })
+                    }
+                }
+            }
+            json.push_str("}}");
+        }
+        json.push_str("]}");
+        json
+    }
+}
+
+fn write_geometry(json: &mut String, bbox: &BoundingBox) {
+    let position = |json: &mut String, x: f64, y: f64| {
+        json.push('[');
+        write_number(json, x);
+        json.push(',');
+        write_number(json, y);
+        json.push(']');
+    };
+    if bbox.xmin == bbox.xmax && bbox.ymin == bbox.ymax {
+        json.push_str(r#"{"type":"Point","coordinates":"#);
+        position(json, bbox.xmin, bbox.ymin);
+        json.push('}');
+        return;
+    }
+    json.push_str(r#"{"type":"Polygon","coordinates":[["#);
+    let ring = [
+        (bbox.xmin, bbox.ymin),
+        (bbox.xmax, bbox.ymin),
+        (bbox.xmax, bbox.ymax),
+        (bbox.xmin, bbox.ymax),
+        (bbox.xmin, bbox.ymin),
+    ];
+    for (i, (x, y)) in ring.into_iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        position(json, x, y);
+    }
+    json.push_str("]]}");
+}
+
+/// JSON has no infinities or NaN; they are written as `null`.
+fn write_number(json: &mut String, number: f64) {
+    if number.is_finite() {
+        write!(json, "{number}").expect("writing to a String cannot fail");
+    } else {
+        json.push_str("null");
+    }
+}
+
+fn write_string(json: &mut String, text: &str) {
+    json.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                write!(json, "\\u{:04x}", c as u32).expect("writing to a String cannot fail")
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::SpatialIndex;
+    use crate::query::SpatialQuery;
+
+    #[test]
+    fn test_export_writes_each_non_empty_tile() {
+        let mut index = SpatialIndex::new();
+        for i in 0..300u32 {
+            let lon = ((i * 37) % 340) as f64 - 170.0;
+            let lat = ((i * 53) % 140) as f64 - 70.0;
+            let size = if i % 3 == 0 { 0.0 } else { 4.0 };
+            index.insert(BoundingBox::new(lon, lat, lon + size, lat + size), i);
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let export = TileExport::new(1..=3)
+            .with_scheme(TileScheme::Tms)
+            .with_properties(|&i| vec![("id".to_string(), (i as u64).into())]);
+        let written = export
+            .write_geojson(&index, index.root(), dir.path())
+            .unwrap();
+
+        let mut expected = 0;
+        for z in 0..=4u8 {
+            for x in 0..1u32 << z {
+                for y in 0..1u32 << z {
+                    let tile = TileId::new(z, x, y);
+                    let extent = tile.bounds();
+                    let count = (0..index.len())
+                        .filter(|&node| extent.matches(index.get_point(node)))
+                        .count();
+                    let path = dir.path().join(format!("{z}/{x}/{}.geojson", tile.tms_y()));
+                    assert_eq!(path.exists(), (1..=3).contains(&z) && count > 0);
+                    if !path.exists() {
+                        continue;
+                    }
+                    expected += 1;
+                    let json = fs::read_to_string(&path).unwrap();
+                    assert_eq!(json, export.tile_geojson(&index, index.root(), tile));
+                    assert_eq!(json.matches(r#""type":"Feature""#).count(), count);
+                }
+            }
+        }
+        assert_eq!(written, expected);
+        assert!(written > 20);
+
+        let paris = TileId::new(2, 2, 1);
+        assert_eq!(paris.tms_y(), 2);
+        let bounds = paris.bounds();
+        assert_eq!((bounds.xmin, bounds.xmax), (0.0, 90.0));
+        assert!(bounds.ymin.abs() < 1e-9);
+        assert!((bounds.ymax - 66.513_260_443_111_86).abs() < 1e-9);
+        assert_eq!(TileId::new(0, 0, 0).children()[3], TileId::new(1, 1, 1));
+
+        let mut json = String::new();
+        write_string(&mut json, "a \"b\"\n\u{1}");
+        assert_eq!(json, r#""a \"b\"\n\u0001""#);
+        let empty = TileExport::<u32>::new(0..=0).tile_geojson(&index, None, paris);
+        assert_eq!(empty, r#"{"type":"FeatureCollection","features":[]}"#);
+    }
+
+    #[cfg(feature = "mvt")]
+    #[test]
+    fn test_mvt_export_matches_geojson_tiles() {
+        let mut index = SpatialIndex::new();
+        for i in 0..100u32 {
+            let lon = ((i * 37) % 340) as f64 - 170.0;
+            let lat = ((i * 53) % 140) as f64 - 70.0;
+            index.insert(BoundingBox::new(lon, lat, lon + 2.0, lat + 2.0), i);
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let export =
+            TileExport::new(0..=2).with_properties(|&i| vec![("id".into(), (i as u64).into())]);
+        let geojson = export
+            .write_geojson(&index, index.root(), dir.path())
+            .unwrap();
+        let mvt = export
+            .write_mvt(&index, index.root(), dir.path(), "entries")
+            .unwrap();
+        assert_eq!(mvt, geojson);
+
+        let tile = TileId::new(2, 1, 1);
+        let path = dir.path().join("2/1/1");
+        assert!(path.with_extension("geojson").exists());
+        let bytes = fs::read(path.with_extension("mvt")).unwrap();
+        assert_eq!(
+            bytes,
+            export.tile_mvt(&index, index.root(), tile, "entries")
+        );
+        assert!(bytes.windows(7).any(|window| window == b"entries"));
+        let empty = export.tile_mvt(&index, None, tile, "entries");
+        assert!(empty.len() < 20);
+    }
+}
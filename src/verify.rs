@@ -0,0 +1,375 @@
+//! Integrity checks for persisted node files.
+
+use crate::codec::FixedCodec;
+use crate::digest::Fnv1a;
+use crate::node_file::{HEADER_SIZE, NO_NODE, NodeFileHeader, NodeFileReader};
+use crate::spatial::Point;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+
+/// One problem found by `verify`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+    /// The header cannot be decoded, or describes records other than `P` and `T`. Nothing
+    /// else is checked.
+    BadHeader(String),
+    /// The file ends before the last record the header counts.
+    Truncated { expected: u64, present: u64 },
+    /// The records do not hash to the checksum in the header.
+    ChecksumMismatch { expected: u64, actual: u64 },
+    /// The root is not a record of the file.
+    RootOutOfRange { root: u64 },
+    /// A child link is not a record of the file.
+    ChildOutOfRange { node: u64, child: u64 },
+    /// A record is reached a second time, through a shared child or a cycle.
+    MultipleParents { node: u64 },
+    /// A point has another number of dimensions than the header records.
+    DimensionMismatch { node: u64, dimensions: usize },
+    /// A node lies on the wrong side of an ancestor's split, so searches can miss it.
+    SplitViolation {
+        node: u64,
+        ancestor: u64,
+        dimension: usize,
+    },
+    /// Records the root does not reach; `first` is the lowest of them.
+    Unreachable { count: u64, first: u64 },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::BadHeader(reason) => write!(f, "bad header: {reason}"),
+            Problem::Truncated { expected, present } => {
+                write!(f, "truncated: {present} of {expected} records present")
+            }
+            Problem::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "checksum mismatch: header has {expected:#018x}, records hash to {actual:#018x}"
+            ),
+            Problem::RootOutOfRange { root } => write!(f, "root {root} is out of range"),
+            Problem::ChildOutOfRange { node, child } => {
+                write!(
+                    f,
+                    "node {node} links to child {child}, which is out of range"
+                )
+            }
+            Problem::MultipleParents { node } => write!(f, "node {node} is reached twice"),
+            Problem::DimensionMismatch { node, dimensions } => {
+                write!(f, "node {node} has {dimensions} dimensions")
+            }
+            Problem::SplitViolation {
+                node,
+                ancestor,
+                dimension,
+            } => write!(
+                f,
+                "node {node} is on the wrong side of node {ancestor} in dimension {dimension}"
+            ),
+            Problem::Unreachable { count, first } => {
+                write!(
+                    f,
+                    "{count} records are unreachable, the first being {first}"
+                )
+            }
+        }
+    }
+}
+
+/// Outcome of `verify`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifyReport {
+    /// Records the header counts.
+    pub records: u64,
+    /// Records reached from the root.
+    pub reachable: u64,
+    /// Whether the file carries a checksum, which was then compared.
+    pub checksummed: bool,
+    /// Problems found, in the order they were found.
+    pub problems: Vec<Problem>,
+}
+
+impl VerifyReport {
+    /// Check if no problem was found.
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Check a node file of `(P, T)` records before trusting it, e.g. a restored backup.
+///
+/// # Architecture
+/// The file is read twice, so memory stays at 16 bytes per record:
+/// - A sequential pass hashes the records against the header checksum, when there is
+///   one, and keeps every record's child links
+/// - A walk from the root checks that links stay in range, that each record is reached
+///   once, and that every node lies within the splits of its ancestors, the invariant
+///   searches prune by: left of a split means at most its value in the split dimension,
+///   right of it at least. The root splits on dimension 0, as in all trees of this crate
+/// - Records never reached are reported last
+///
+/// Damage is reported in the returned `VerifyReport` rather than as an error; `Err` means
+/// the file could not be read at all.
+pub fn verify<P: Point + FixedCodec, T: FixedCodec>(path: &Path) -> io::Result<VerifyReport> {
+    let mut file = BufReader::new(File::open(path)?);
+    let file_size = file.get_ref().metadata()?.len();
+    let mut report = VerifyReport {
+        records: 0,
+        reachable: 0,
+        checksummed: false,
+        problems: Vec::new(),
+    };
+
+    let mut buf = [0u8; HEADER_SIZE];
+    if file_size < HEADER_SIZE as u64 {
+        report.problems.push(Problem::BadHeader(
+            "file is shorter than a header".to_string(),
+        ));
+        return Ok(report);
+    }
+    file.read_exact(&mut buf)?;
+    let header = match NodeFileHeader::decode(&buf).and_then(|header| {
+        header.check_layout::<P, T>()?;
+        Ok(header)
+    }) {
+        Ok(header) => header,
+        Err(error) => {
+            report.problems.push(Problem::BadHeader(error.to_string()));
+            return Ok(report);
+        }
+    };
+    report.records = header.node_count;
+
+    let record_size = header.record_size() as u64;
+    let present = ((file_size - HEADER_SIZE as u64) / record_size).min(header.node_count);
+    if present < header.node_count {
+        report.problems.push(Problem::Truncated {
+            expected: header.node_count,
+            present,
+        });
+    }
+
+    // Pass 1: checksum and links
+    let mut hasher = Fnv1a(Fnv1a::OFFSET_BASIS);
+    let mut links = Vec::with_capacity(present as usize);
+    let mut record = vec![0u8; record_size as usize];
+    for _ in 0..present {
+        file.read_exact(&mut record)?;
+        hasher.write(&record);
+        links.push((u64::decode(&record[0..8]), u64::decode(&record[8..16])));
+    }
+    if let Some(expected) = header.checksum {
+        if present == header.node_count {
+            report.checksummed = true;
+            if hasher.0 != expected {
+                report.problems.push(Problem::ChecksumMismatch {
+                    expected,
+                    actual: hasher.0,
+                });
+            }
+        }
+    }
+
+    // Pass 2: walk from the root, carrying the tightest split bounds of each dimension
+    let mut reader = NodeFileReader::<P, T>::open(path)?;
+    let mut reached = vec![false; present as usize];
+    let mut reported_dimensions = false;
+    let dimensions = header.dimensions as usize;
+    let unbounded = vec![None; dimensions];
+    let mut stack = Vec::new();
+    match header.root {
+        Some(root) if root < present => stack.push(Walk {
+            node: root,
+            depth: 0,
+            lower: unbounded.clone(),
+            upper: unbounded,
+        }),
+        Some(root) => report.problems.push(Problem::RootOutOfRange { root }),
+        None => {}
+    }
+    while let Some(walk) = stack.pop() {
+        let node = walk.node;
+        if std::mem::replace(&mut reached[node as usize], true) {
+            report.problems.push(Problem::MultipleParents { node });
+            continue;
+        }
+        report.reachable += 1;
+
+        let point = reader.read_node(node)?.point;
+        if point.dimensions() != dimensions {
+            if !reported_dimensions {
+                report.problems.push(Problem::DimensionMismatch {
+                    node,
+                    dimensions: point.dimensions(),
+                });
+                reported_dimensions = true;
+            }
+            continue;
+        }
+        for dimension in 0..dimensions {
+            let value = point.get_dimension(dimension);
+            let below = walk.lower[dimension].filter(|&(bound, _)| value < bound);
+            let above = walk.upper[dimension].filter(|&(bound, _)| value > bound);
+            if let Some((_, ancestor)) = below.or(above) {
+                report.problems.push(Problem::SplitViolation {
+                    node,
+                    ancestor,
+                    dimension,
+                });
+            }
+        }
+
+        let split = walk.depth % dimensions.max(1);
+        let value = (dimensions > 0).then(|| point.get_dimension(split));
+        let (left, right) = links[node as usize];
+        for (child, is_left) in [(right, false), (left, true)] {
+            if child == NO_NODE {
+                continue;
+            }
+            if child >= present {
+                report
+                    .problems
+                    .push(Problem::ChildOutOfRange { node, child });
+                continue;
+            }
+            let mut lower = walk.lower.clone();
+            let mut upper = walk.upper.clone();
+            if let Some(value) = value {
+                if is_left {
+                    upper[split] = Some((value, node));
+                } else {
+                    lower[split] = Some((value, node));
+                }
+            }
+            stack.push(Walk {
+                node: child,
+                depth: walk.depth + 1,
+                lower,
+                upper,
+            });
+        }
+    }
+
+    let mut unreachable = (0..present).filter(|&node| !reached[node as usize]);
+    if let Some(first) = unreachable.next() {
+        report.problems.push(Problem::Unreachable {
+            count: 1 + unreachable.count() as u64,
+            first,
+        });
+    }
+    Ok(report)
+}
+
+/// A node to check, with the split bounds its ancestors impose and the ancestor setting
+/// each of them.
+struct Walk {
+    node: u64,
+    depth: usize,
+    lower: Vec<Option<(f64, u64)>>,
+    upper: Vec<Option<(f64, u64)>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::{BuildOptions, bulk_build};
+    use crate::node_file::{NodeFileWriter, record_size, write_arena};
+    use crate::spatial::BoundingBox;
+    use crate::storage::{InMemoryLinker, NodeArena};
+    use std::fs::OpenOptions;
+    use std::io::{Seek, SeekFrom, Write};
+
+    fn write_tree(path: &Path) {
+        let mut arena = NodeArena::new();
+        let mut nodes: Vec<usize> = (0..200u64)
+            .map(|i| {
+                let x = ((i * 37) % 101) as f64;
+                let y = ((i * 53) % 97) as f64;
+                arena.allocate(BoundingBox::new(x, y, x + 1.0, y + 1.0), i)
+            })
+            .collect();
+        let mut linker = InMemoryLinker::new(&mut arena);
+        let root = bulk_build(&mut linker, &mut nodes, 0, &BuildOptions::default()).unwrap();
+        write_arena(path, &arena, root).unwrap();
+    }
+
+    fn overwrite(path: &Path, offset: u64, bytes: &[u8]) {
+        let mut file = OpenOptions::new().write(true).open(path).unwrap();
+        file.seek(SeekFrom::Start(offset)).unwrap();
+        file.write_all(bytes).unwrap();
+    }
+
+    #[test]
+    fn test_verify_accepts_written_trees() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.bkd");
+        write_tree(&path);
+        let report = verify::<BoundingBox, u64>(&path).unwrap();
+        assert!(report.is_ok(), "{:?}", report.problems);
+        assert!(report.checksummed);
+        assert_eq!((report.records, report.reachable), (200, 200));
+
+        let wrong_types = verify::<BoundingBox, u32>(&path).unwrap();
+        assert!(matches!(wrong_types.problems[..], [Problem::BadHeader(_)]));
+    }
+
+    #[test]
+    fn test_verify_reports_damage() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.bkd");
+        let record = record_size(BoundingBox::SIZE, u64::SIZE) as u64;
+
+        // A flipped payload byte only shows in the checksum
+        write_tree(&path);
+        overwrite(&path, HEADER_SIZE as u64 + 3 * record + 48, &[0xff]);
+        let report = verify::<BoundingBox, u64>(&path).unwrap();
+        assert!(matches!(
+            report.problems[..],
+            [Problem::ChecksumMismatch { .. }]
+        ));
+
+        // Hand-written records: an out-of-range link, a misplaced node and an orphan
+        let mut writer = NodeFileWriter::<BoundingBox, u64>::create(&path).unwrap();
+        let at = |x: f64| BoundingBox::new(x, x, x + 1.0, x + 1.0);
+        writer.push(&at(5.0), &0, Some(1), Some(9)).unwrap();
+        writer.push(&at(7.0), &1, None, None).unwrap();
+        writer.push(&at(1.0), &2, None, None).unwrap();
+        writer.finish(Some(0)).unwrap();
+        let report = verify::<BoundingBox, u64>(&path).unwrap();
+        assert_eq!(
+            report.problems,
+            [
+                Problem::ChildOutOfRange { node: 0, child: 9 },
+                Problem::SplitViolation {
+                    node: 1,
+                    ancestor: 0,
+                    dimension: 0
+                },
+                Problem::Unreachable { count: 1, first: 2 },
+            ]
+        );
+        assert_eq!(report.reachable, 2);
+
+        // A cycle, and a file cut short
+        overwrite(&path, HEADER_SIZE as u64 + 8, &0u64.to_le_bytes());
+        let problems = verify::<BoundingBox, u64>(&path).unwrap().problems;
+        assert!(problems.contains(&Problem::MultipleParents { node: 0 }));
+        let size = std::fs::metadata(&path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(size - record)
+            .unwrap();
+        let report = verify::<BoundingBox, u64>(&path).unwrap();
+        assert_eq!(
+            report.problems[0],
+            Problem::Truncated {
+                expected: 3,
+                present: 2
+            }
+        );
+        assert!(!report.checksummed);
+    }
+}
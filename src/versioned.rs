@@ -0,0 +1,442 @@
+//! MVCC-style versioned index built on `SharedTree` snapshots.
+//!
+//! # Architecture
+//! - The writer inserts into a `SharedTree`; inserts stay invisible until `commit`
+//! - `commit` captures a snapshot, wraps it in an immutable `Version` with the next
+//!   version number, and publishes it as current
+//! - Readers `pin` the current version (or a specific older one that is still alive) and
+//!   query it for as long as they hold it, unaffected by later commits
+//! - Versions are reference counted: once no reader pins a version and it is no longer
+//!   current, it is dropped and pruned from the version registry on the next commit
+//! - Deletes are tombstones: each version carries the set of deleted nodes, shared between
+//!   versions until a commit changes it, and `Version::search` filters them out
+//! - A `Transaction` buffers inserts and deletes and applies them in one commit, so readers
+//!   see all of a batch or none of it
+//! - Entries may carry an expiration timestamp: `Version::search_at` hides expired entries
+//!   immediately, and `expire_before` sweeps them into tombstones so they stop costing
+//!   filter work
+
+use crate::query::SpatialQuery;
+use crate::search::spatial_search;
+use crate::snapshot::{NEVER_EXPIRES, SharedTree, TreeSnapshot};
+use crate::spatial::Point;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+
+/// Immutable, committed state of a `VersionedIndex`.
+pub struct Version<P, T> {
+    number: u64,
+    snapshot: TreeSnapshot<P, T>,
+    deleted: Arc<HashSet<usize>>,
+}
+
+impl<P, T> Version<P, T> {
+    /// Version number; the empty initial version is 0 and each commit adds one.
+    pub fn number(&self) -> u64 {
+        self.number
+    }
+
+    /// The tree as of this version, searchable with the regular algorithms.
+    pub fn snapshot(&self) -> &TreeSnapshot<P, T> {
+        &self.snapshot
+    }
+
+    /// Check if `node` was deleted as of this version.
+    pub fn is_deleted(&self, node: usize) -> bool {
+        self.deleted.contains(&node)
+    }
+}
+
+impl<P: Point, T> Version<P, T> {
+    /// Number of live (inserted and not deleted) nodes.
+    pub fn live_count(&self) -> usize {
+        self.snapshot.len() - self.deleted.len()
+    }
+
+    /// Search this version, skipping deleted nodes.
+    /// Deleted nodes still route the traversal; they are only dropped from the results.
+    pub fn search<Q: SpatialQuery<P>>(&self, query: &Q) -> Vec<usize> {
+        let mut results = spatial_search(&self.snapshot, self.snapshot.root(), query, 0);
+        if !self.deleted.is_empty() {
+            results.retain(|node| !self.deleted.contains(node));
+        }
+        results
+    }
+
+    /// Search this version as of time `now`, skipping deleted and expired nodes.
+    pub fn search_at<Q: SpatialQuery<P>>(&self, query: &Q, now: u64) -> Vec<usize> {
+        let mut results = self.search(query);
+        results.retain(|&node| !self.snapshot.is_expired(node, now));
+        results
+    }
+}
+
+/// State shared between the writer and its readers.
+struct Published<P, T> {
+    current: RwLock<Arc<Version<P, T>>>,
+    /// Every version that may still be pinned, oldest first.
+    history: Mutex<Vec<Weak<Version<P, T>>>>,
+}
+
+impl<P, T> Published<P, T> {
+    fn pin(&self) -> Arc<Version<P, T>> {
+        self.current
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    fn pin_version(&self, number: u64) -> Option<Arc<Version<P, T>>> {
+        let history = self.history.lock().unwrap_or_else(|e| e.into_inner());
+        history
+            .iter()
+            .filter_map(Weak::upgrade)
+            .find(|version| version.number == number)
+    }
+
+    fn live_versions(&self) -> Vec<u64> {
+        let history = self.history.lock().unwrap_or_else(|e| e.into_inner());
+        history
+            .iter()
+            .filter_map(Weak::upgrade)
+            .map(|version| version.number)
+            .collect()
+    }
+}
+
+/// Insert-only index with pinnable, immutable versions.
+///
+/// # Usage pattern:
+/// ```rust
+/// # use bkd::BoundingBox;
+/// # use bkd::versioned::VersionedIndex;
+/// let mut index = VersionedIndex::new();
+/// let reader = index.reader();
+/// index.insert(BoundingBox::new(0.0, 0.0, 1.0, 1.0), 1u32);
+/// assert_eq!(reader.pin().snapshot().len(), 0); // not committed yet
+/// let version = index.commit();
+/// assert_eq!(reader.pin().number(), version);
+/// assert_eq!(reader.pin().snapshot().len(), 1);
+/// ```
+pub struct VersionedIndex<P, T> {
+    tree: SharedTree<P, T>,
+    deleted: Arc<HashSet<usize>>,
+    pending_deletes: Vec<usize>,
+    /// Nodes with an expiration, keyed by timestamp, not yet swept by `expire_before`.
+    expirations: BTreeMap<u64, Vec<usize>>,
+    published: Arc<Published<P, T>>,
+    next_version: u64,
+}
+
+impl<P: Point, T> VersionedIndex<P, T> {
+    /// Create an empty index whose current version is the empty version 0.
+    pub fn new() -> Self {
+        let tree = SharedTree::new();
+        let deleted = Arc::new(HashSet::new());
+        let initial = Arc::new(Version {
+            number: 0,
+            snapshot: tree.snapshot(),
+            deleted: deleted.clone(),
+        });
+        VersionedIndex {
+            tree,
+            deleted,
+            pending_deletes: Vec::new(),
+            expirations: BTreeMap::new(),
+            published: Arc::new(Published {
+                history: Mutex::new(vec![Arc::downgrade(&initial)]),
+                current: RwLock::new(initial),
+            }),
+            next_version: 1,
+        }
+    }
+
+    /// Stage an insert; it becomes visible to readers at the next `commit`.
+    pub fn insert(&mut self, point: P, data: T) -> usize {
+        self.tree.insert(point, data)
+    }
+
+    /// Stage an insert of an entry that expires at `expires_at`.
+    pub fn insert_with_expiry(&mut self, point: P, data: T, expires_at: u64) -> usize {
+        let node = self.tree.insert_with_expiry(point, data, expires_at);
+        if expires_at != NEVER_EXPIRES {
+            self.expirations.entry(expires_at).or_default().push(node);
+        }
+        node
+    }
+
+    /// Stage deletes for every entry that expires before `timestamp` and return how many
+    /// were staged. Like other deletes, they take effect at the next `commit`.
+    pub fn expire_before(&mut self, timestamp: u64) -> usize {
+        let remaining = self.expirations.split_off(&timestamp);
+        let expired = std::mem::replace(&mut self.expirations, remaining);
+        let before = self.pending_deletes.len();
+        self.pending_deletes.extend(
+            expired
+                .into_values()
+                .flatten()
+                .filter(|node| !self.deleted.contains(node)),
+        );
+        self.pending_deletes.len() - before
+    }
+
+    /// Stage a delete; it becomes visible to readers at the next `commit`.
+    /// Returns false (and stages nothing) if `node` was never inserted.
+    pub fn delete(&mut self, node: usize) -> bool {
+        if node >= self.tree.len() {
+            return false;
+        }
+        self.pending_deletes.push(node);
+        true
+    }
+
+    /// Start a transaction; its changes are applied and published together by
+    /// `Transaction::commit`, or discarded by `rollback` or by dropping it.
+    pub fn transaction(&mut self) -> Transaction<'_, P, T> {
+        Transaction {
+            index: self,
+            inserts: Vec::new(),
+            deletes: Vec::new(),
+        }
+    }
+
+    /// Publish everything inserted and deleted so far as a new version and return its number.
+    pub fn commit(&mut self) -> u64 {
+        if !self.pending_deletes.is_empty() {
+            // Copy-on-write: versions already published keep the old set
+            let deleted = Arc::make_mut(&mut self.deleted);
+            deleted.extend(self.pending_deletes.drain(..));
+        }
+
+        let number = self.next_version;
+        self.next_version += 1;
+        let version = Arc::new(Version {
+            number,
+            snapshot: self.tree.snapshot(),
+            deleted: self.deleted.clone(),
+        });
+
+        let mut history = self
+            .published
+            .history
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        history.retain(|version| version.strong_count() > 0);
+        history.push(Arc::downgrade(&version));
+        drop(history);
+
+        *self
+            .published
+            .current
+            .write()
+            .unwrap_or_else(|e| e.into_inner()) = version;
+        number
+    }
+
+    /// Handle for readers; cheap to clone and `Send + Sync`.
+    pub fn reader(&self) -> IndexReader<P, T> {
+        IndexReader {
+            published: self.published.clone(),
+        }
+    }
+
+    /// Pin the current version.
+    pub fn pin(&self) -> Arc<Version<P, T>> {
+        self.published.pin()
+    }
+}
+
+impl<P: Point, T> Default for VersionedIndex<P, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Batch of inserts and deletes that becomes visible atomically.
+///
+/// Nothing touches the index until `commit`, so a rolled-back (or dropped) transaction
+/// leaves no trace, not even unreachable nodes.
+pub struct Transaction<'a, P: Point, T> {
+    index: &'a mut VersionedIndex<P, T>,
+    inserts: Vec<(P, T, u64)>,
+    deletes: Vec<usize>,
+}
+
+impl<'a, P: Point, T> Transaction<'a, P, T> {
+    /// Stage an insert and return the node index it will have once committed.
+    pub fn insert(&mut self, point: P, data: T) -> usize {
+        self.insert_with_expiry(point, data, NEVER_EXPIRES)
+    }
+
+    /// Stage an insert of an entry that expires at `expires_at`.
+    pub fn insert_with_expiry(&mut self, point: P, data: T, expires_at: u64) -> usize {
+        self.inserts.push((point, data, expires_at));
+        self.index.tree.len() + self.inserts.len() - 1
+    }
+
+    /// Stage a delete of an existing node or of a node staged in this transaction.
+    /// Returns false (and stages nothing) for unknown nodes.
+    pub fn delete(&mut self, node: usize) -> bool {
+        if node >= self.index.tree.len() + self.inserts.len() {
+            return false;
+        }
+        self.deletes.push(node);
+        true
+    }
+
+    /// Apply all staged changes and publish them as one new version.
+    pub fn commit(self) -> u64 {
+        let Transaction {
+            index,
+            inserts,
+            deletes,
+        } = self;
+        for (point, data, expires_at) in inserts {
+            index.insert_with_expiry(point, data, expires_at);
+        }
+        index.pending_deletes.extend(deletes);
+        index.commit()
+    }
+
+    /// Discard all staged changes.
+    pub fn rollback(self) {}
+}
+
+/// Reader handle of a `VersionedIndex`.
+pub struct IndexReader<P, T> {
+    published: Arc<Published<P, T>>,
+}
+
+impl<P, T> Clone for IndexReader<P, T> {
+    fn clone(&self) -> Self {
+        IndexReader {
+            published: self.published.clone(),
+        }
+    }
+}
+
+impl<P, T> IndexReader<P, T> {
+    /// Pin the latest committed version.
+    pub fn pin(&self) -> Arc<Version<P, T>> {
+        self.published.pin()
+    }
+
+    /// Pin a specific version, if it is still alive (current or pinned by someone).
+    pub fn pin_version(&self, number: u64) -> Option<Arc<Version<P, T>>> {
+        self.published.pin_version(number)
+    }
+
+    /// Numbers of all versions that are still alive, oldest first.
+    pub fn live_versions(&self) -> Vec<u64> {
+        self.published.live_versions()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::spatial_search;
+    use crate::spatial::BoundingBox;
+
+    #[test]
+    fn test_pinned_versions_and_garbage_collection() {
+        let mut index = VersionedIndex::new();
+        let reader = index.reader();
+        let query = BoundingBox::new(0.0, 0.0, 100.0, 100.0);
+        let count = |version: &Version<BoundingBox, u32>| {
+            let snapshot = version.snapshot();
+            spatial_search(snapshot, snapshot.root(), &query, 0).len()
+        };
+
+        for i in 0..10 {
+            index.insert(BoundingBox::new(i as f64, 0.0, i as f64 + 1.0, 1.0), i);
+        }
+        assert_eq!(index.commit(), 1);
+        let pinned = reader.pin();
+
+        for i in 10..20 {
+            index.insert(BoundingBox::new(i as f64, 0.0, i as f64 + 1.0, 1.0), i);
+        }
+        assert_eq!(count(&reader.pin()), 10);
+        assert_eq!(index.commit(), 2);
+
+        assert_eq!(count(&pinned), 10);
+        assert_eq!(count(&reader.pin()), 20);
+        assert_eq!(reader.pin_version(1).unwrap().number(), 1);
+
+        // Version 0 was never pinned and is gone; version 1 lives while `pinned` does
+        index.commit();
+        assert_eq!(reader.live_versions(), vec![1, 3]);
+        assert!(reader.pin_version(0).is_none());
+        drop(pinned);
+        index.commit();
+        assert_eq!(reader.live_versions(), vec![4]);
+    }
+
+    #[test]
+    fn test_transactions_apply_atomically() {
+        let mut index = VersionedIndex::new();
+        let reader = index.reader();
+        let query = BoundingBox::new(0.0, 0.0, 100.0, 100.0);
+        let point = |i: usize| BoundingBox::new(i as f64, 0.0, i as f64 + 1.0, 1.0);
+
+        let mut transaction = index.transaction();
+        let nodes: Vec<usize> = (0..5).map(|i| transaction.insert(point(i), i)).collect();
+        assert_eq!(nodes, vec![0, 1, 2, 3, 4]);
+        assert!(transaction.delete(4));
+        assert!(!transaction.delete(5));
+        assert_eq!(reader.pin().live_count(), 0);
+        transaction.commit();
+
+        let before = reader.pin();
+        assert_eq!(before.search(&query), vec![0, 1, 2, 3]);
+
+        let mut transaction = index.transaction();
+        transaction.insert(point(50), 50);
+        transaction.delete(0);
+        transaction.rollback();
+        assert_eq!(index.pin().number(), before.number());
+        assert_eq!(index.pin().snapshot().len(), 5);
+
+        let mut transaction = index.transaction();
+        let added = transaction.insert(point(60), 60);
+        transaction.delete(0);
+        transaction.commit();
+        let after = reader.pin();
+        let mut results = after.search(&query);
+        results.sort();
+        assert_eq!(results, vec![1, 2, 3, added]);
+        assert!(after.is_deleted(0));
+        assert_eq!(after.live_count(), 4);
+
+        // The earlier version is unaffected by the later deletes
+        assert!(!before.is_deleted(0));
+        assert_eq!(before.search(&query), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_expiration() {
+        let mut index = VersionedIndex::new();
+        let query = BoundingBox::new(0.0, 0.0, 100.0, 100.0);
+        let point = |i: usize| BoundingBox::new(i as f64, 0.0, i as f64 + 1.0, 1.0);
+
+        let forever = index.insert(point(0), 0);
+        let short = index.insert_with_expiry(point(1), 1, 100);
+        let mut transaction = index.transaction();
+        let long = transaction.insert_with_expiry(point(2), 2, 200);
+        transaction.commit();
+
+        let version = index.pin();
+        assert_eq!(version.search_at(&query, 50), vec![forever, short, long]);
+        assert_eq!(version.search_at(&query, 100), vec![forever, long]);
+        assert_eq!(version.search(&query).len(), 3);
+
+        assert_eq!(index.expire_before(150), 1);
+        assert_eq!(index.expire_before(150), 0);
+        index.commit();
+        let swept = index.pin();
+        assert!(swept.is_deleted(short));
+        assert_eq!(swept.search(&query), vec![forever, long]);
+        assert_eq!(swept.search_at(&query, u64::MAX - 1), vec![forever]);
+    }
+}